@@ -0,0 +1,36 @@
+// Time of the conversions behind the night cards: decoding a card image, converting it to
+// terminal lines, and resizing it for a frame of the flip animation.
+// Run with `cargo bench --bench image_to_lines`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use stonks::utils::{img_to_lines, read_image, resize_image};
+
+const CARD_PATHS: [&str; 4] = [
+    "images/stonks.png",
+    "images/doge.png",
+    "images/elon.png",
+    "images/card_front.png",
+];
+
+fn bench_image_to_lines(c: &mut Criterion) {
+    for path in CARD_PATHS {
+        let image = read_image(path).expect("Cannot load image from file");
+        let (width, height) = image.dimensions();
+
+        c.bench_function(&format!("read_image {}", path), |b| {
+            b.iter(|| read_image(black_box(path)))
+        });
+        c.bench_function(&format!("img_to_lines {}", path), |b| {
+            b.iter(|| img_to_lines(black_box(&image)))
+        });
+        c.bench_function(&format!("resize and img_to_lines {}", path), |b| {
+            b.iter(|| {
+                let resized = resize_image(black_box(&image), width / 2, height)?;
+                img_to_lines(&resized)
+            })
+        });
+    }
+}
+
+criterion_group!(benches, bench_image_to_lines);
+criterion_main!(benches);
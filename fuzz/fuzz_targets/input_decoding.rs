@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use stonks::input::InputDecoder;
+
+// Client input is untrusted: decoding arbitrary bytes must never panic. The data is fed in two
+// packets to also exercise sequences split across packets.
+fuzz_target!(|data: &[u8]| {
+    let mut decoder = InputDecoder::default();
+    let (first, second) = data.split_at(data.len() / 2);
+    let _ = decoder.feed(first);
+    let _ = decoder.feed(second);
+});
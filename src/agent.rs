@@ -1,92 +1,858 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Instant, SystemTime},
+};
 
 use crate::{
-    events::NightEvent, market::NUMBER_OF_STONKS, ssh_client::SessionAuth, stonk::StonkClass,
+    api::{ApiToken, MAX_API_TOKENS},
+    board::BoardDecision,
+    conditions::{Condition, ConditionSet, Stacking},
+    events::NightEvent,
+    market::NUMBER_OF_STONKS,
+    money::Money,
+    passport::Achievement,
+    ssh_client::{Password, SessionAuth},
+    stonk::StonkClass,
+    tournament::PaperAccount,
     utils::AppResult,
 };
 use serde::{Deserialize, Serialize};
 use strum::Display;
 use tracing::info;
 
-pub const INITIAL_USER_CASH_CENTS: u32 = 10_000 * 100;
+pub const INITIAL_USER_CASH: Money = Money::from_cents(10_000 * 100);
+// Agents with reputation at or below this value are considered shady.
+pub const LOW_REPUTATION_THRESHOLD: i32 = 0;
+// Increasing counter stamped on the first action selected by an agent,
+// used to apply the actions of different agents in the order they were selected.
+static ACTION_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+pub const USERNAME_CHANGE_COOLDOWN_SECONDS: u64 = 60 * 60 * 24 * 7;
+// Old usernames cannot be taken by other players for this long after a change.
+pub const USERNAME_RESERVATION_SECONDS: u64 = 60 * 60 * 24 * 30;
 
 #[derive(Debug, Display, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum AgentAction {
-    Buy { stonk_id: usize, amount: u32 },
-    Sell { stonk_id: usize, amount: u32 },
-    BumpStonkClass { class: StonkClass },
+    Buy {
+        stonk_id: usize,
+        amount: u32,
+    },
+    Sell {
+        stonk_id: usize,
+        amount: u32,
+    },
+    DarkPoolBuy {
+        stonk_id: usize,
+        amount: u32,
+    },
+    DarkPoolSell {
+        stonk_id: usize,
+        amount: u32,
+    },
+    OffshoreBuy {
+        stonk_id: usize,
+        amount: u32,
+    },
+    OffshoreSell {
+        stonk_id: usize,
+        amount: u32,
+    },
+    // Converts the amount, in the currency sold, between dollars and the offshore currency.
+    ExchangeCurrency {
+        to_offshore: bool,
+        amount: Money,
+    },
+    BumpStonkClass {
+        class: StonkClass,
+    },
     CrashAll,
     OneDayUltraVision,
-    CrashAgentStonks { username: String },
-    AddCash { amount: u32 },
+    CrashAgentStonks {
+        username: String,
+    },
+    AddCash {
+        amount: Money,
+    },
     AcceptBribe,
     AssassinationVictim, // This action is actually used to signal that the user got CharacterAssassinated
-    GetDividends { stonk_id: usize },
+    GetDividends {
+        stonk_id: usize,
+    },
+    // Paid out of the market treasury.
+    ReceiveStimulus,
+    ReceiveBailout,
+    TakeShadyLoan,
+    // Defensive choices against a pending character assassination.
+    PayProtection,
+    DumpHoldings,
+    CallBluff,
+    StartVacation {
+        days: usize,
+    },
+    JoinTournament,
+    // Trades on the paper account of the current tournament round.
+    TournamentBuy {
+        stonk_id: usize,
+        amount: u32,
+    },
+    TournamentSell {
+        stonk_id: usize,
+        amount: u32,
+    },
+    // Orders of the opening auction, executed at dawn at the clearing price.
+    AuctionBuy {
+        stonk_id: usize,
+        amount: u32,
+    },
+    AuctionSell {
+        stonk_id: usize,
+        amount: u32,
+    },
+    // Taken by the player controlling the board of the stonk.
+    BoardDecision {
+        stonk_id: usize,
+        decision: BoardDecision,
+    },
+    // Futures on the price of a Commodity stonk at the end of the season.
+    OpenFutures {
+        stonk_id: usize,
+        amount: u32,
+        is_long: bool,
+    },
+}
+
+impl AgentAction {
+    /// Trades of real shares, counting toward the trading limits.
+    pub fn is_trade(&self) -> bool {
+        matches!(
+            self,
+            Self::Buy { .. }
+                | Self::Sell { .. }
+                | Self::DarkPoolBuy { .. }
+                | Self::DarkPoolSell { .. }
+                | Self::OffshoreBuy { .. }
+                | Self::OffshoreSell { .. }
+        )
+    }
+
+    pub fn is_auction_order(&self) -> bool {
+        matches!(self, Self::AuctionBuy { .. } | Self::AuctionSell { .. })
+    }
+
+    pub fn is_futures_order(&self) -> bool {
+        matches!(self, Self::OpenFutures { .. })
+    }
+
+    /// The stonk whose shares, real or not, the action trades or orders.
+    pub fn traded_stonk_id(&self) -> Option<usize> {
+        match self {
+            Self::Buy { stonk_id, .. }
+            | Self::Sell { stonk_id, .. }
+            | Self::DarkPoolBuy { stonk_id, .. }
+            | Self::DarkPoolSell { stonk_id, .. }
+            | Self::OffshoreBuy { stonk_id, .. }
+            | Self::OffshoreSell { stonk_id, .. }
+            | Self::TournamentBuy { stonk_id, .. }
+            | Self::TournamentSell { stonk_id, .. }
+            | Self::AuctionBuy { stonk_id, .. }
+            | Self::AuctionSell { stonk_id, .. }
+            | Self::OpenFutures { stonk_id, .. } => Some(*stonk_id),
+            _ => None,
+        }
+    }
+
+    /// Adds the amount of another application of the same action, on the same target, to this
+    /// one. Returns false if the actions differ by more than their amount.
+    fn accumulate(&mut self, other: &AgentAction) -> bool {
+        match (self, other) {
+            (
+                Self::Buy { stonk_id, amount },
+                Self::Buy {
+                    stonk_id: other_id,
+                    amount: other_amount,
+                },
+            )
+            | (
+                Self::Sell { stonk_id, amount },
+                Self::Sell {
+                    stonk_id: other_id,
+                    amount: other_amount,
+                },
+            )
+            | (
+                Self::DarkPoolBuy { stonk_id, amount },
+                Self::DarkPoolBuy {
+                    stonk_id: other_id,
+                    amount: other_amount,
+                },
+            )
+            | (
+                Self::DarkPoolSell { stonk_id, amount },
+                Self::DarkPoolSell {
+                    stonk_id: other_id,
+                    amount: other_amount,
+                },
+            )
+            | (
+                Self::OffshoreBuy { stonk_id, amount },
+                Self::OffshoreBuy {
+                    stonk_id: other_id,
+                    amount: other_amount,
+                },
+            )
+            | (
+                Self::OffshoreSell { stonk_id, amount },
+                Self::OffshoreSell {
+                    stonk_id: other_id,
+                    amount: other_amount,
+                },
+            )
+            | (
+                Self::TournamentBuy { stonk_id, amount },
+                Self::TournamentBuy {
+                    stonk_id: other_id,
+                    amount: other_amount,
+                },
+            )
+            | (
+                Self::TournamentSell { stonk_id, amount },
+                Self::TournamentSell {
+                    stonk_id: other_id,
+                    amount: other_amount,
+                },
+            )
+            | (
+                Self::AuctionBuy { stonk_id, amount },
+                Self::AuctionBuy {
+                    stonk_id: other_id,
+                    amount: other_amount,
+                },
+            )
+            | (
+                Self::AuctionSell { stonk_id, amount },
+                Self::AuctionSell {
+                    stonk_id: other_id,
+                    amount: other_amount,
+                },
+            ) if stonk_id == other_id => {
+                *amount = amount.saturating_add(*other_amount);
+                true
+            }
+            (
+                Self::AddCash { amount },
+                Self::AddCash {
+                    amount: other_amount,
+                },
+            ) => {
+                *amount = amount.saturating_add(*other_amount);
+                true
+            }
+            (action, other) => action == other,
+        }
+    }
+
+    // Looked up by variant name, so that it also applies to the counts migrated from older
+    // saves, which only kept the name.
+    fn reputation_modifier(action: &str) -> i32 {
+        match action {
+            "AcceptBribe" => -3,
+            "CrashAgentStonks" => -4,
+            "CrashAll" => -5,
+            "GetDividends" => 1,
+            _ => 0,
+        }
+    }
+}
+
+// An action applied in the past, with the number of times it was and the last tick it was.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PastAction {
+    // Amounts of shares and cash are summed over the applications on the same target.
+    pub action: AgentAction,
+    pub count: usize,
+    pub last_tick: usize,
+}
+
+// The actions applied by the agent, keeping their payloads: which stonk was traded, who was
+// targeted. Applications of the same action on the same target are merged, so that the store
+// does not grow with every trade.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(from = "StoredPastActions")]
+pub struct PastActions {
+    actions: Vec<PastAction>,
+    // Number of times and last tick by action name, migrated from older saves.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    legacy: HashMap<String, (usize, usize)>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredPastActions {
+    Current {
+        actions: Vec<PastAction>,
+        #[serde(default)]
+        legacy: HashMap<String, (usize, usize)>,
+    },
+    // Older saves stored the actions by name only.
+    Legacy(HashMap<String, (usize, usize)>),
+}
+
+impl From<StoredPastActions> for PastActions {
+    fn from(stored: StoredPastActions) -> Self {
+        match stored {
+            StoredPastActions::Current { actions, legacy } => Self { actions, legacy },
+            StoredPastActions::Legacy(legacy) => Self {
+                actions: vec![],
+                legacy,
+            },
+        }
+    }
+}
+
+impl PastActions {
+    pub fn record(&mut self, action: AgentAction, tick: usize) {
+        for past in self.actions.iter_mut() {
+            if past.action.accumulate(&action) {
+                past.count += 1;
+                past.last_tick = tick;
+                return;
+            }
+        }
+        self.actions.push(PastAction {
+            action,
+            count: 1,
+            last_tick: tick,
+        });
+    }
+
+    /// Number of times an action of the same kind was applied, whatever its payload.
+    pub fn count(&self, kind: &AgentAction) -> usize {
+        let name = kind.to_string();
+        self.actions
+            .iter()
+            .filter(|past| past.action.to_string() == name)
+            .map(|past| past.count)
+            .sum::<usize>()
+            + self.legacy.get(&name).map_or(0, |(count, _)| *count)
+    }
+
+    pub fn contains(&self, kind: &AgentAction) -> bool {
+        self.count(kind) > 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PastAction> {
+        self.actions.iter()
+    }
+
+    /// Number of times each kind of action was applied, by name.
+    fn counts_by_name(&self) -> HashMap<String, usize> {
+        let mut counts = self
+            .legacy
+            .iter()
+            .map(|(name, (count, _))| (name.clone(), *count))
+            .collect::<HashMap<String, usize>>();
+        for past in self.actions.iter() {
+            *counts.entry(past.action.to_string()).or_default() += past.count;
+        }
+        counts
+    }
+}
+
+pub const MAX_VACATION_DAYS: usize = 7;
+// Days after the end of a vacation before a new one can start.
+pub const VACATION_COOLDOWN_DAYS: usize = 7;
+// Days of audit after accepting a bribe, during which every trade pays an extra fee.
+pub const AUDIT_DAYS: usize = 3;
+// Days in prison for the attackers whose bluff was called.
+pub const PRISON_DAYS: usize = 1;
+// Number of richest players that become famous at the close.
+pub const FAMOUS_PLAYERS: usize = 3;
+
+// Net worth drop, in percent, that triggers a SellAllOnDrawdown order.
+pub const SCHEDULED_DRAWDOWN_PERCENT: u64 = 20;
+
+// Trades made in the last tick and day with trades, to enforce the trading limits.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TradeCounter {
+    tick: usize,
+    trades_this_tick: u32,
+    day: usize,
+    trades_today: u32,
+}
+
+impl TradeCounter {
+    pub fn trades_this_tick(&self, tick: usize) -> u32 {
+        if self.tick == tick {
+            self.trades_this_tick
+        } else {
+            0
+        }
+    }
+
+    pub fn trades_today(&self, day: usize) -> u32 {
+        if self.day == day {
+            self.trades_today
+        } else {
+            0
+        }
+    }
+
+    pub fn record_trade(&mut self, tick: usize, day: usize) {
+        self.trades_this_tick = self.trades_this_tick(tick) + 1;
+        self.trades_today = self.trades_today(day) + 1;
+        self.tick = tick;
+        self.day = day;
+    }
+}
+
+// A conditional instruction evaluated by the server, even while the agent is offline.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ScheduledOrder {
+    SellAllOnDrawdown { reference_value: Money },
+    BuyAtOpen { stonk_id: usize, amount: u32 },
+}
+
+// A character assassination queued against the agent, which hits when until_tick is reached
+// unless the agent defends against it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AssassinationThreat {
+    pub attacker: String,
+    pub until_tick: usize,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum AgentCondition {
+    // Trading is disabled.
     Prison,
+    // The projection of the stonk prices is shown.
     UltraVision,
+    // Trading is disabled and the agent cannot be targeted.
+    Vacation,
+    VacationCooldown,
+    // Trades pay an extra fee.
+    Audited,
+    // Protection money is cheaper.
+    Famous,
+}
+
+impl AgentCondition {
+    pub fn icon(&self) -> &'static str {
+        match self {
+            Self::Prison => "▦",
+            Self::UltraVision => "◉",
+            Self::Vacation => "☼",
+            Self::VacationCooldown => "◌",
+            Self::Audited => "§",
+            Self::Famous => "★",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Prison => "In prison",
+            Self::UltraVision => "UltraVision",
+            Self::Vacation => "On vacation",
+            Self::VacationCooldown => "Vacation cooldown",
+            Self::Audited => "Audited",
+            Self::Famous => "Famous",
+        }
+    }
+}
+
+impl Condition for AgentCondition {
+    fn stacking(&self) -> Stacking {
+        Stacking::Extend
+    }
 }
 
 pub trait DecisionAgent {
     fn username(&self) -> &str;
 
-    fn cash(&self) -> u32;
-    fn add_cash(&mut self, amount: u32) -> AppResult<u32>;
-    fn sub_cash(&mut self, amount: u32) -> AppResult<u32>;
+    fn cash(&self) -> Money;
+    fn add_cash(&mut self, amount: Money) -> AppResult<Money>;
+    fn sub_cash(&mut self, amount: Money) -> AppResult<Money>;
+    /// Cash held in the currency of the offshore exchange.
+    fn offshore_cash(&self) -> Money;
+    fn add_offshore_cash(&mut self, amount: Money) -> AppResult<Money>;
+    fn sub_offshore_cash(&mut self, amount: Money) -> AppResult<Money>;
     fn owned_stonks(&self) -> &[u32; NUMBER_OF_STONKS];
     fn add_stonk(&mut self, stonk_id: usize, amount: u32) -> AppResult<&[u32; NUMBER_OF_STONKS]>;
     fn sub_stonk(&mut self, stonk_id: usize, amount: u32) -> AppResult<&[u32; NUMBER_OF_STONKS]>;
 
     fn select_action(&mut self, action: AgentAction);
-    fn selected_action(&self) -> Option<&AgentAction>;
-    fn clear_action(&mut self);
+    /// Drops a single selected action, keeping the others in their order.
+    fn unselect_action(&mut self, action: &AgentAction);
+    fn selected_actions(&self) -> &Vec<AgentAction>;
+    fn clear_actions(&mut self);
+
+    fn night_action_points(&self) -> usize;
+    fn set_night_action_points(&mut self, points: usize);
 
     fn set_available_night_events(&mut self, actions: Vec<NightEvent>);
     fn available_night_events(&self) -> &Vec<NightEvent>;
 
-    fn insert_past_selected_actions(&mut self, action: AgentAction, tick: usize);
-    fn past_selected_actions(&self) -> &HashMap<String, (usize, usize)>;
+    fn record_past_action(&mut self, action: AgentAction, tick: usize);
+    fn past_actions(&self) -> &PastActions;
 
     fn apply_conditions(&mut self, current_tick: usize);
     fn add_condition(&mut self, condition: AgentCondition, until_tick: usize);
     fn has_condition(&self, condition: AgentCondition) -> bool;
+
+    fn trade_counter(&self) -> &TradeCounter;
+    fn trade_counter_mut(&mut self) -> &mut TradeCounter;
+
+    fn tournament_account(&self) -> Option<&PaperAccount>;
+    fn tournament_account_mut(&mut self) -> Option<&mut PaperAccount>;
+    fn set_tournament_account(&mut self, account: Option<PaperAccount>);
+
+    fn assassination_threats(&self) -> &Vec<AssassinationThreat>;
+    fn add_assassination_threat(&mut self, threat: AssassinationThreat);
+    /// Removes and returns the threats expiring at or before the given tick.
+    fn remove_assassination_threats(&mut self, until_tick: usize) -> Vec<AssassinationThreat>;
+
+    fn spent_night_action_points(&self) -> usize {
+        self.available_night_events()
+            .iter()
+            .filter(|event| self.selected_actions().contains(&event.action()))
+            .map(|event| event.action_points())
+            .sum()
+    }
+
+    /// The first event can always be selected, further ones only if there are enough action points left.
+    fn can_select_night_event(&self, event: &NightEvent) -> bool {
+        if self.selected_actions().contains(&event.action()) {
+            return false;
+        }
+        self.selected_actions().is_empty()
+            || self.spent_night_action_points() + event.action_points()
+                <= self.night_action_points()
+    }
+
+    fn has_completed_night_selection(&self) -> bool {
+        !self
+            .available_night_events()
+            .iter()
+            .any(|event| self.can_select_night_event(event))
+    }
+
+    /// Public reputation derived from the actions selected in the past.
+    fn reputation(&self) -> i32 {
+        self.past_actions()
+            .counts_by_name()
+            .iter()
+            .map(|(action, count)| AgentAction::reputation_modifier(action) * *count as i32)
+            .sum()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserAgent {
     pub session_auth: SessionAuth,
-    cash: u32, //in usd cents
+    cash: Money,
+    // Cash in the currency of the offshore exchange.
+    #[serde(default)]
+    offshore_cash: Money,
     owned_stonks: [u32; NUMBER_OF_STONKS],
-    pending_action: Option<AgentAction>,
+    #[serde(default)]
+    pending_actions: Vec<AgentAction>,
     available_night_events: Vec<NightEvent>,
-    // A map of actions selected in the past to (number of times it was selected, last tick it was selected).
-    // We use the action string as key to be able to serialize, but lose the enum nested properties.
-    past_selected_actions: HashMap<String, (usize, usize)>,
-    conditions: Vec<(usize, AgentCondition)>,
+    #[serde(default)]
+    night_action_points: usize,
+    #[serde(default, alias = "past_selected_actions")]
+    past_actions: PastActions,
+    conditions: ConditionSet<AgentCondition>,
+    // Number of nights each event (by name) has been offered to the agent.
+    #[serde(default)]
+    seen_night_events: HashMap<String, usize>,
+    #[serde(default)]
+    assassination_threats: Vec<AssassinationThreat>,
+    #[serde(default)]
+    scheduled_order: Option<ScheduledOrder>,
+    // Accessibility setting to disable animations and transitions.
+    #[serde(default)]
+    reduced_motion: bool,
+    // Accessibility setting to use the plain text display, friendly to screen readers.
+    #[serde(default)]
+    text_mode: bool,
+    // Guests play under a generated username until they choose their own.
+    #[serde(default)]
+    guest: bool,
+    // Usernames used before, with the time they were changed.
+    #[serde(default)]
+    previous_usernames: Vec<(String, SystemTime)>,
+    #[serde(default)]
+    api_tokens: Vec<ApiToken>,
+    #[serde(default)]
+    trade_counter: TradeCounter,
+    // Set while playing a tournament round.
+    #[serde(default)]
+    tournament_account: Option<PaperAccount>,
+    // Number of market days closed since the agent joined.
+    #[serde(default)]
+    days_played: usize,
+    // Version of the game the agent last read the changes of, None before it was tracked.
+    #[serde(default)]
+    last_seen_version: Option<String>,
+    // Cosmetic progress, earned here or brought with a passport.
+    #[serde(default)]
+    achievements: Vec<Achievement>,
+    #[serde(default)]
+    titles: Vec<String>,
+    // The passport imported into the account, if any.
+    #[serde(default)]
+    imported_passport: Option<String>,
+    // Secret of the one-time codes asked at login, when two-factor authentication is on.
+    #[serde(default)]
+    totp_secret: Option<Vec<u8>>,
+    // Sequence number of the first pending action.
+    #[serde(skip)]
+    action_sequence: Option<u64>,
 }
 
 impl UserAgent {
     pub fn new(session_auth: SessionAuth) -> Self {
         Self {
             session_auth,
-            cash: INITIAL_USER_CASH_CENTS, // in cents
+            cash: INITIAL_USER_CASH,
+            offshore_cash: Money::ZERO,
             owned_stonks: [0; NUMBER_OF_STONKS],
-            pending_action: None,
+            pending_actions: vec![],
             available_night_events: vec![],
-            past_selected_actions: HashMap::default(),
-            conditions: vec![],
+            night_action_points: 0,
+            past_actions: PastActions::default(),
+            conditions: ConditionSet::default(),
+            seen_night_events: HashMap::default(),
+            assassination_threats: vec![],
+            scheduled_order: None,
+            reduced_motion: false,
+            text_mode: false,
+            guest: false,
+            previous_usernames: vec![],
+            api_tokens: vec![],
+            trade_counter: TradeCounter::default(),
+            tournament_account: None,
+            days_played: 0,
+            // New players have nothing to catch up on.
+            last_seen_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            achievements: vec![],
+            titles: vec![],
+            imported_passport: None,
+            totp_secret: None,
+            action_sequence: None,
         }
     }
 
-    pub fn cash_dollars(&self) -> f64 {
-        self.cash as f64 / 100.0
+    pub fn conditions(&self) -> &ConditionSet<AgentCondition> {
+        &self.conditions
     }
 
-    pub fn conditions(&self) -> &Vec<(usize, AgentCondition)> {
-        &self.conditions
+    pub fn seen_night_events(&self) -> &HashMap<String, usize> {
+        &self.seen_night_events
+    }
+
+    pub fn days_played(&self) -> usize {
+        self.days_played
+    }
+
+    pub fn add_day_played(&mut self) {
+        self.days_played += 1;
+    }
+
+    pub fn reduced_motion(&self) -> bool {
+        self.reduced_motion
+    }
+
+    pub fn toggle_reduced_motion(&mut self) {
+        self.reduced_motion = !self.reduced_motion;
+    }
+
+    pub fn text_mode(&self) -> bool {
+        self.text_mode
+    }
+
+    pub fn toggle_text_mode(&mut self) {
+        self.text_mode = !self.text_mode;
+    }
+
+    /// Removes the selected trades and auction orders from the pending actions, keeping the
+    /// other ones selected.
+    pub fn take_selected_orders(&mut self) -> Vec<AgentAction> {
+        let (orders, others) = std::mem::take(&mut self.pending_actions)
+            .into_iter()
+            .partition(|action: &AgentAction| action.is_trade() || action.is_auction_order());
+        self.pending_actions = others;
+        orders
+    }
+
+    pub fn is_guest(&self) -> bool {
+        self.guest
+    }
+
+    pub fn set_guest(&mut self, guest: bool) {
+        self.guest = guest;
+    }
+
+    /// Turns a guest into a regular account with the given credentials.
+    pub fn upgrade_account(&mut self, username: String, hashed_password: Password) {
+        info!(
+            "Guest {} upgraded to account {}",
+            self.session_auth.username, username
+        );
+        self.session_auth.username = username;
+        self.session_auth.hashed_password = hashed_password;
+        self.guest = false;
+    }
+
+    pub fn last_seen_version(&self) -> Option<&str> {
+        self.last_seen_version.as_deref()
+    }
+
+    pub fn set_last_seen_version(&mut self, version: &str) {
+        self.last_seen_version = Some(version.to_string());
+    }
+
+    pub fn achievements(&self) -> &[Achievement] {
+        &self.achievements
+    }
+
+    pub fn titles(&self) -> &[String] {
+        &self.titles
+    }
+
+    pub fn imported_passport(&self) -> Option<&str> {
+        self.imported_passport.as_deref()
+    }
+
+    pub fn set_imported_passport(
+        &mut self,
+        passport: String,
+        achievements: &[Achievement],
+        titles: &[String],
+    ) {
+        self.imported_passport = Some(passport);
+        for achievement in achievements {
+            if !self.achievements.contains(achievement) {
+                self.achievements.push(*achievement);
+            }
+        }
+        for title in titles {
+            if !self.titles.contains(title) {
+                self.titles.push(title.clone());
+            }
+        }
+    }
+
+    pub fn totp_secret(&self) -> Option<&[u8]> {
+        self.totp_secret.as_deref()
+    }
+
+    pub fn set_totp_secret(&mut self, secret: Option<Vec<u8>>) {
+        info!(
+            "Agent {} turned two-factor authentication {}",
+            self.username(),
+            if secret.is_some() { "on" } else { "off" }
+        );
+        self.totp_secret = secret;
+    }
+
+    pub fn previous_usernames(&self) -> &Vec<(String, SystemTime)> {
+        &self.previous_usernames
+    }
+
+    /// Changes the username, at most once every USERNAME_CHANGE_COOLDOWN_SECONDS.
+    pub fn change_username(&mut self, username: String) -> AppResult<()> {
+        let now = SystemTime::now();
+        if let Some((_, changed_at)) = self.previous_usernames.last() {
+            let elapsed = now
+                .duration_since(*changed_at)
+                .unwrap_or_default()
+                .as_secs();
+            if elapsed < USERNAME_CHANGE_COOLDOWN_SECONDS {
+                let days_left = (USERNAME_CHANGE_COOLDOWN_SECONDS - elapsed).div_ceil(60 * 60 * 24);
+                return Err(format!(
+                    "You can change your username again in {} day{}.",
+                    days_left,
+                    if days_left > 1 { "s" } else { "" }
+                )
+                .into());
+            }
+        }
+
+        info!(
+            "Agent {} changed username to {}",
+            self.session_auth.username, username
+        );
+        let old_username = std::mem::replace(&mut self.session_auth.username, username);
+        self.previous_usernames.push((old_username, now));
+        Ok(())
+    }
+
+    /// Whether the agent used this username recently enough to keep it reserved.
+    pub fn has_reserved_username(&self, username: &str) -> bool {
+        self.previous_usernames
+            .iter()
+            .any(|(previous, changed_at)| {
+                previous == username
+                    && changed_at
+                        .elapsed()
+                        .map(|elapsed| elapsed.as_secs() < USERNAME_RESERVATION_SECONDS)
+                        .unwrap_or(true)
+            })
+    }
+
+    /// Position of the pending actions in the order of selection among all agents.
+    pub fn action_sequence(&self) -> Option<u64> {
+        self.action_sequence
+    }
+
+    pub fn api_tokens(&self) -> &Vec<ApiToken> {
+        &self.api_tokens
+    }
+
+    /// Creates a new API token and returns its value, which cannot be retrieved later.
+    pub fn generate_api_token(&mut self) -> AppResult<String> {
+        if self.api_tokens.len() >= MAX_API_TOKENS {
+            return Err(format!("Cannot have more than {} API tokens", MAX_API_TOKENS).into());
+        }
+        let (token, value) = ApiToken::generate();
+        info!("Agent {} created API token {}", self.username(), token.id);
+        self.api_tokens.push(token);
+        Ok(value)
+    }
+
+    pub fn revoke_api_token(&mut self, id: &str) {
+        info!("Agent {} revoked API token {}", self.username(), id);
+        self.api_tokens.retain(|token| token.id != id);
+    }
+
+    pub fn has_api_token(&self, value: &str) -> bool {
+        ApiToken::parse(value).is_some_and(|(id, secret)| {
+            self.api_tokens
+                .iter()
+                .any(|token| token.id == id && token.check_secret(secret))
+        })
+    }
+
+    /// Checks the token value and counts the request against the token rate limit.
+    pub fn use_api_token(&mut self, value: &str, now: Instant) -> AppResult<()> {
+        let token = ApiToken::parse(value)
+            .and_then(|(id, secret)| {
+                self.api_tokens
+                    .iter_mut()
+                    .find(|token| token.id == id && token.check_secret(secret))
+            })
+            .ok_or("Invalid API token")?;
+        token.use_request(now)
+    }
+
+    pub fn scheduled_order(&self) -> Option<&ScheduledOrder> {
+        self.scheduled_order.as_ref()
+    }
+
+    pub fn set_scheduled_order(&mut self, order: Option<ScheduledOrder>) {
+        info!("Agent scheduled order: {:?}", order);
+        self.scheduled_order = order;
     }
 }
 
@@ -95,22 +861,33 @@ impl DecisionAgent for UserAgent {
         &self.session_auth.username
     }
 
-    fn cash(&self) -> u32 {
+    fn cash(&self) -> Money {
         self.cash
     }
-    fn add_cash(&mut self, amount: u32) -> AppResult<u32> {
-        self.cash += amount;
+    fn add_cash(&mut self, amount: Money) -> AppResult<Money> {
+        self.cash = self.cash.checked_add(amount).ok_or("Overflow")?;
         Ok(self.cash)
     }
 
-    fn sub_cash(&mut self, amount: u32) -> AppResult<u32> {
-        if self.cash < amount {
-            return Err("Underflow".into());
-        }
-        self.cash -= amount;
+    fn sub_cash(&mut self, amount: Money) -> AppResult<Money> {
+        self.cash = self.cash.checked_sub(amount).ok_or("Underflow")?;
         Ok(self.cash)
     }
 
+    fn offshore_cash(&self) -> Money {
+        self.offshore_cash
+    }
+
+    fn add_offshore_cash(&mut self, amount: Money) -> AppResult<Money> {
+        self.offshore_cash = self.offshore_cash.checked_add(amount).ok_or("Overflow")?;
+        Ok(self.offshore_cash)
+    }
+
+    fn sub_offshore_cash(&mut self, amount: Money) -> AppResult<Money> {
+        self.offshore_cash = self.offshore_cash.checked_sub(amount).ok_or("Underflow")?;
+        Ok(self.offshore_cash)
+    }
+
     fn owned_stonks(&self) -> &[u32; NUMBER_OF_STONKS] {
         &self.owned_stonks
     }
@@ -138,20 +915,42 @@ impl DecisionAgent for UserAgent {
 
     fn select_action(&mut self, action: AgentAction) {
         info!("Agent selected action: {:#?}", action);
-        if self.pending_action.is_none() {
-            self.pending_action = Some(action);
+        if self.action_sequence.is_none() {
+            self.action_sequence = Some(ACTION_SEQUENCE.fetch_add(1, Ordering::Relaxed));
+        }
+        self.pending_actions.push(action);
+    }
+
+    fn unselect_action(&mut self, action: &AgentAction) {
+        if let Some(index) = self.pending_actions.iter().position(|a| a == action) {
+            self.pending_actions.remove(index);
         }
+        if self.pending_actions.is_empty() {
+            self.action_sequence = None;
+        }
+    }
+
+    fn selected_actions(&self) -> &Vec<AgentAction> {
+        &self.pending_actions
     }
 
-    fn selected_action(&self) -> Option<&AgentAction> {
-        self.pending_action.as_ref()
+    fn clear_actions(&mut self) {
+        self.pending_actions.clear();
+        self.action_sequence = None;
     }
 
-    fn clear_action(&mut self) {
-        self.pending_action = None;
+    fn night_action_points(&self) -> usize {
+        self.night_action_points
+    }
+
+    fn set_night_action_points(&mut self, points: usize) {
+        self.night_action_points = points;
     }
 
     fn set_available_night_events(&mut self, events: Vec<NightEvent>) {
+        for event in events.iter() {
+            *self.seen_night_events.entry(event.to_string()).or_default() += 1;
+        }
         self.available_night_events = events;
     }
 
@@ -159,41 +958,143 @@ impl DecisionAgent for UserAgent {
         &self.available_night_events
     }
 
-    fn insert_past_selected_actions(&mut self, action: AgentAction, tick: usize) {
-        if let Some((amount, _)) = self.past_selected_actions.get(&action.to_string()) {
-            self.past_selected_actions
-                .insert(action.to_string(), (amount + 1, tick));
-        } else {
-            self.past_selected_actions
-                .insert(action.to_string(), (1, tick));
-        }
+    fn record_past_action(&mut self, action: AgentAction, tick: usize) {
+        self.past_actions.record(action, tick);
     }
 
-    fn past_selected_actions(&self) -> &HashMap<String, (usize, usize)> {
-        &self.past_selected_actions
+    fn past_actions(&self) -> &PastActions {
+        &self.past_actions
     }
 
     fn apply_conditions(&mut self, current_tick: usize) {
-        for (_, condition) in self.conditions.iter() {
-            match condition {
-                AgentCondition::Prison => {}
-                AgentCondition::UltraVision => {}
-            }
-        }
-
-        self.conditions
-            .retain(|(until_tick, _)| *until_tick > current_tick);
+        self.conditions.expire(current_tick);
     }
 
     fn add_condition(&mut self, condition: AgentCondition, until_tick: usize) {
-        self.conditions.push((until_tick, condition));
+        self.conditions.add(condition, until_tick);
     }
 
     fn has_condition(&self, condition: AgentCondition) -> bool {
-        self.conditions
-            .iter()
-            .map(|(_, condition)| *condition)
-            .collect::<Vec<AgentCondition>>()
-            .contains(&condition)
+        self.conditions.has(&condition)
+    }
+
+    fn trade_counter(&self) -> &TradeCounter {
+        &self.trade_counter
+    }
+
+    fn trade_counter_mut(&mut self) -> &mut TradeCounter {
+        &mut self.trade_counter
+    }
+
+    fn tournament_account(&self) -> Option<&PaperAccount> {
+        self.tournament_account.as_ref()
+    }
+
+    fn tournament_account_mut(&mut self) -> Option<&mut PaperAccount> {
+        self.tournament_account.as_mut()
+    }
+
+    fn set_tournament_account(&mut self, account: Option<PaperAccount>) {
+        self.tournament_account = account;
+    }
+
+    fn assassination_threats(&self) -> &Vec<AssassinationThreat> {
+        &self.assassination_threats
+    }
+
+    fn add_assassination_threat(&mut self, threat: AssassinationThreat) {
+        self.assassination_threats.push(threat);
+    }
+
+    fn remove_assassination_threats(&mut self, until_tick: usize) -> Vec<AssassinationThreat> {
+        let (expired, pending) = self
+            .assassination_threats
+            .drain(..)
+            .partition(|threat| threat.until_tick <= until_tick);
+        self.assassination_threats = pending;
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AgentAction, PastActions};
+    use crate::money::Money;
+
+    #[test]
+    fn test_past_actions() {
+        let mut past_actions = PastActions::default();
+        past_actions.record(
+            AgentAction::Buy {
+                stonk_id: 1,
+                amount: 10,
+            },
+            3,
+        );
+        past_actions.record(
+            AgentAction::Buy {
+                stonk_id: 1,
+                amount: 5,
+            },
+            4,
+        );
+        past_actions.record(
+            AgentAction::Buy {
+                stonk_id: 2,
+                amount: 1,
+            },
+            4,
+        );
+        past_actions.record(
+            AgentAction::AddCash {
+                amount: Money::from_dollars(1),
+            },
+            5,
+        );
+        past_actions.record(
+            AgentAction::AddCash {
+                amount: Money::from_dollars(2),
+            },
+            6,
+        );
+        let stored = past_actions.iter().collect::<Vec<_>>();
+        assert_eq!(stored.len(), 3);
+        assert_eq!(
+            (&stored[0].action, stored[0].count, stored[0].last_tick),
+            (
+                &AgentAction::Buy {
+                    stonk_id: 1,
+                    amount: 15
+                },
+                2,
+                4
+            )
+        );
+        assert_eq!(
+            stored[2].action,
+            AgentAction::AddCash {
+                amount: Money::from_dollars(3)
+            }
+        );
+        assert_eq!(
+            past_actions.count(&AgentAction::Buy {
+                stonk_id: 0,
+                amount: 0
+            }),
+            3
+        );
+
+        // Older saves only kept the count by name, which still counts.
+        let legacy: PastActions =
+            serde_json::from_str(r#"{"AcceptBribe": [2, 10], "CrashAll": [1, 12]}"#)
+                .expect("Legacy past actions should load");
+        assert_eq!(legacy.count(&AgentAction::AcceptBribe), 2);
+        let mut migrated: PastActions =
+            serde_json::from_str(&serde_json::to_string(&legacy).expect("Should serialize"))
+                .expect("Past actions should load");
+        migrated.record(AgentAction::AcceptBribe, 20);
+        assert_eq!(migrated.count(&AgentAction::AcceptBribe), 3);
+        assert!(migrated.contains(&AgentAction::CrashAll));
+        assert!(!migrated.contains(&AgentAction::CallBluff));
     }
 }
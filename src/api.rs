@@ -0,0 +1,337 @@
+// API tokens and the line based protocol used by bots over SSH exec channels,
+// e.g. `ssh -p 3333 username@host "buy 0 10"` using a token as password.
+
+use crate::{
+    agent::{AgentAction, DecisionAgent, UserAgent},
+    clock::GameClock,
+    config::PassportsConfig,
+    logging,
+    market::{GamePhase, Market, NUMBER_OF_STONKS},
+    market_event::MarketEvent,
+    passport::{self, Passport},
+    ssh_client::Password,
+    utils::AppResult,
+};
+use ed25519_dalek::SigningKey;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant, SystemTime},
+};
+
+pub const ADMIN_COMMANDS: [&str; 9] = [
+    "clock",
+    "pause",
+    "resume",
+    "speed",
+    "fast-forward",
+    "log-level",
+    "maintenance",
+    "suspend",
+    "restore",
+];
+
+pub const PASSPORT_COMMANDS: [&str; 2] = ["passport", "import-passport"];
+
+const API_TOKEN_PREFIX: &str = "stk";
+const API_TOKEN_ID_LENGTH: usize = 8;
+const API_TOKEN_SECRET_LENGTH: usize = 32;
+pub const MAX_API_TOKENS: usize = 5;
+pub const DEFAULT_API_RATE_LIMIT_PER_MINUTE: u32 = 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    // Public part of the token, shown in the account settings.
+    pub id: String,
+    hashed_secret: Password,
+    pub created_at: SystemTime,
+    pub rate_limit_per_minute: u32,
+    // Start of the current rate limit window and number of requests in it.
+    #[serde(skip)]
+    window: Option<(Instant, u32)>,
+}
+
+impl ApiToken {
+    /// Creates a new token, returned together with its full value, which is not stored.
+    pub fn generate() -> (Self, String) {
+        let random_string = |length| {
+            rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(length)
+                .map(char::from)
+                .collect::<String>()
+        };
+        let id = random_string(API_TOKEN_ID_LENGTH);
+        let secret = random_string(API_TOKEN_SECRET_LENGTH);
+        let token = Self {
+            hashed_secret: Self::hash_secret(&secret),
+            id: id.clone(),
+            created_at: SystemTime::now(),
+            rate_limit_per_minute: DEFAULT_API_RATE_LIMIT_PER_MINUTE,
+            window: None,
+        };
+        (token, format!("{}_{}_{}", API_TOKEN_PREFIX, id, secret))
+    }
+
+    fn hash_secret(secret: &str) -> Password {
+        Sha256::digest(secret.as_bytes()).into()
+    }
+
+    /// Splits a full token value into (id, secret), if it has the token format.
+    pub fn parse(value: &str) -> Option<(&str, &str)> {
+        let mut parts = value.splitn(3, '_');
+        if parts.next() != Some(API_TOKEN_PREFIX) {
+            return None;
+        }
+        let id = parts.next()?;
+        let secret = parts.next()?;
+        if id.len() != API_TOKEN_ID_LENGTH || secret.len() != API_TOKEN_SECRET_LENGTH {
+            return None;
+        }
+        Some((id, secret))
+    }
+
+    pub fn check_secret(&self, secret: &str) -> bool {
+        self.hashed_secret == Self::hash_secret(secret)
+    }
+
+    /// Counts a request against the rate limit, failing if the limit is reached.
+    pub fn use_request(&mut self, now: Instant) -> AppResult<()> {
+        let (window_start, requests) = match self.window {
+            Some((start, requests)) if now.duration_since(start) < Duration::from_secs(60) => {
+                (start, requests)
+            }
+            _ => (now, 0),
+        };
+        if requests >= self.rate_limit_per_minute {
+            return Err(format!(
+                "Rate limit of {} requests per minute reached",
+                self.rate_limit_per_minute
+            )
+            .into());
+        }
+        self.window = Some((window_start, requests + 1));
+        Ok(())
+    }
+}
+
+fn parse_trade(args: &[&str]) -> AppResult<(usize, u32)> {
+    let [stonk_id, amount] = args else {
+        return Err("Expected a stonk id and an amount".into());
+    };
+    let stonk_id = stonk_id.parse::<usize>()?;
+    if stonk_id >= NUMBER_OF_STONKS {
+        return Err(format!("Invalid stonk id {}", stonk_id).into());
+    }
+    Ok((stonk_id, amount.parse::<u32>()?))
+}
+
+/// Runs a bot command for the agent, returning the JSON response.
+/// Trades are queued like the ones selected in game and applied at the next tick.
+pub fn execute_command(command: &str, market: &Market, agent: &mut UserAgent) -> AppResult<String> {
+    let mut words = command.split_whitespace();
+    let name = words.next().unwrap_or_default();
+    let args = words.collect::<Vec<&str>>();
+
+    let response = match name {
+        "market" => json!({
+            "phase": market.phase,
+            "stonks": market
+                .stonks
+                .iter()
+                .map(|stonk| json!({
+                    "id": stonk.id,
+                    "name": stonk.name,
+                    "price_cents": stonk.current_unit_price_cents(),
+                    "available_amount": stonk.available_amount(),
+                    "suspended": stonk.is_suspended(),
+                }))
+                .collect::<Vec<_>>(),
+        }),
+        "portfolio" => json!({
+            "username": agent.username(),
+            "cash_cents": agent.cash(),
+            "owned_stonks": agent.owned_stonks(),
+            "net_worth_cents": market.net_worth(agent),
+        }),
+        "buy" | "sell" => {
+            if !matches!(market.phase, GamePhase::Day { .. }) {
+                return Err("Trades are only possible during the day".into());
+            }
+            let (stonk_id, amount) = parse_trade(&args)?;
+            let action = if name == "buy" {
+                AgentAction::Buy { stonk_id, amount }
+            } else {
+                AgentAction::Sell { stonk_id, amount }
+            };
+            agent.select_action(action.clone());
+            json!({ "queued": action })
+        }
+        _ => {
+            return Err(format!(
+                "Unknown command '{}', expected one of: market, portfolio, buy <stonk id> <amount>, sell <stonk id> <amount>",
+                name
+            )
+            .into())
+        }
+    };
+
+    Ok(response.to_string())
+}
+
+/// Runs a passport command for the agent, returning the JSON response. Importing needs every
+/// agent, so that the same passport is never imported twice.
+pub fn execute_passport_command(
+    command: &str,
+    market: &Market,
+    agents: &mut HashMap<String, UserAgent>,
+    username: &str,
+    signing_key: &SigningKey,
+    config: &PassportsConfig,
+) -> AppResult<String> {
+    let mut words = command.split_whitespace();
+    let name = words.next().unwrap_or_default();
+    let args = words.collect::<Vec<&str>>();
+
+    let response = match (name, args.as_slice()) {
+        ("passport", []) => {
+            let agent = agents.get(username).ok_or("Agent not found")?;
+            let passport = Passport::issue(market, agent, &signing_key.verifying_key());
+            json!({
+                "passport": passport.sign(signing_key)?,
+                "issuer": passport.issuer,
+                "cash_multiplier": passport.cash_multiplier,
+                "achievements": passport.achievements,
+                "titles": passport.titles,
+            })
+        }
+        ("import-passport", [encoded]) => {
+            let mut trusted_issuers = config.trusted_keys()?;
+            trusted_issuers.push(signing_key.verifying_key());
+            let passport = Passport::verify(encoded, &trusted_issuers)?;
+            if agents
+                .values()
+                .any(|agent| agent.imported_passport() == Some(*encoded))
+            {
+                return Err("This passport was already imported".into());
+            }
+            let agent = agents.get_mut(username).ok_or("Agent not found")?;
+            let cash = passport::import(&passport, encoded, agent)?;
+            json!({
+                "cash_cents": cash,
+                "achievements": agent.achievements(),
+                "titles": agent.titles(),
+            })
+        }
+        _ => {
+            return Err(format!(
+            "Invalid passport command '{}', expected one of: passport, import-passport <passport>",
+            command
+        )
+            .into())
+        }
+    };
+
+    Ok(response.to_string())
+}
+
+/// Runs a clock or stonk command for an admin, returning the JSON response.
+pub fn execute_admin_command(
+    command: &str,
+    clock: &mut GameClock,
+    market: &mut Market,
+    agents: &mut HashMap<String, UserAgent>,
+) -> AppResult<String> {
+    let mut words = command.split_whitespace();
+    let name = words.next().unwrap_or_default();
+    let args = words.collect::<Vec<&str>>();
+    let now = Instant::now();
+
+    match (name, args.as_slice()) {
+        ("log-level", []) => return Ok(json!({ "log_levels": logging::log_levels()? }).to_string()),
+        ("log-level", [directive]) => {
+            return Ok(json!({ "log_levels": logging::set_log_level(directive)? }).to_string())
+        }
+        ("suspend" | "restore", [stonk_id]) => {
+            let event = MarketEvent::SetStonkSuspended {
+                stonk_id: stonk_id.parse::<usize>()?,
+                suspended: name == "suspend",
+            };
+            market.apply_event(&event, agents)?;
+            return Ok(json!({
+                "suspended": market
+                    .stonks
+                    .iter()
+                    .filter(|stonk| stonk.is_suspended())
+                    .map(|stonk| stonk.id)
+                    .collect::<Vec<usize>>(),
+            })
+            .to_string());
+        }
+        ("clock", []) => {}
+        ("pause", []) => clock.pause(),
+        ("resume", []) => clock.resume(now),
+        ("speed", [speed]) => clock.set_speed(speed.parse::<f64>()?, now)?,
+        ("fast-forward", [ticks]) => clock.fast_forward(ticks.parse::<usize>()?)?,
+        ("maintenance", ["off"]) => clock.end_maintenance(now),
+        ("maintenance", message) => {
+            // The expected duration in minutes can come before the message.
+            let (eta, message) = match message
+                .split_first()
+                .map(|(first, rest)| (first.parse::<u64>(), rest))
+            {
+                Some((Ok(minutes), rest)) => (Some(Duration::from_secs(minutes * 60)), rest),
+                _ => (None, message),
+            };
+            clock.start_maintenance(message.join(" "), eta, now);
+        }
+        _ => {
+            return Err(format!(
+                "Invalid admin command '{}', expected one of: clock, pause, resume, speed <multiplier>, fast-forward <ticks>, log-level [<module>=]<level>, maintenance [<minutes>] [<message>] | off, suspend <stonk id>, restore <stonk id>",
+                command
+            )
+            .into())
+        }
+    }
+
+    Ok(json!({
+        "paused": clock.is_paused(),
+        "maintenance": clock.maintenance().map(|maintenance| json!({
+            "message": maintenance.message,
+            "back_in_seconds": maintenance
+                .back_at
+                .map(|back_at| back_at.saturating_duration_since(now).as_secs()),
+        })),
+        "speed": clock.speed(),
+        "tick_interval_millis": clock.tick_interval().as_millis() as u64,
+    })
+    .to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ApiToken, DEFAULT_API_RATE_LIMIT_PER_MINUTE};
+    use crate::utils::AppResult;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_api_token() -> AppResult<()> {
+        let (mut token, value) = ApiToken::generate();
+        let (id, secret) = ApiToken::parse(&value).expect("Token should be valid");
+        assert_eq!(id, token.id);
+        assert!(token.check_secret(secret));
+        assert!(!token.check_secret("wrong"));
+        assert!(ApiToken::parse("password").is_none());
+
+        let now = Instant::now();
+        for _ in 0..DEFAULT_API_RATE_LIMIT_PER_MINUTE {
+            token.use_request(now)?;
+        }
+        assert!(token.use_request(now).is_err());
+        assert!(token.use_request(now + Duration::from_secs(61)).is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,285 @@
+// The opening auction. During the last ticks of the night players submit orders with a limit
+// price; at dawn a single clearing price is computed for every stonk, the one matching the
+// most shares, and all the matched orders execute at it, between the players.
+
+use serde::{Deserialize, Serialize};
+
+// Number of night ticks before dawn during which auction orders are accepted.
+pub const AUCTION_TICKS: usize = 4;
+// Auction orders placed from the game are limited to this far from the last price.
+pub const AUCTION_PRICE_BAND: f64 = 0.05;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuctionOrder {
+    pub username: String,
+    pub stonk_id: usize,
+    pub amount: u32,
+    // Highest price paid by a buy order, lowest price accepted by a sell order, in cents.
+    pub limit_price: u32,
+    pub is_buy: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AuctionResult {
+    pub stonk_id: usize,
+    pub price: u32,
+    pub volume: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OpeningAuction {
+    // Orders in submission order, which gives time priority at the same limit price.
+    #[serde(default)]
+    orders: Vec<AuctionOrder>,
+    // Results of the last auction, with the cycle it ran at.
+    #[serde(default)]
+    results: Option<(usize, Vec<AuctionResult>)>,
+}
+
+impl OpeningAuction {
+    /// Adds an order, merging it with an order of the same player at the same limit.
+    pub fn submit(&mut self, order: AuctionOrder) {
+        match self.orders.iter_mut().find(|existing| {
+            existing.username == order.username
+                && existing.stonk_id == order.stonk_id
+                && existing.is_buy == order.is_buy
+                && existing.limit_price == order.limit_price
+        }) {
+            Some(existing) => existing.amount = existing.amount.saturating_add(order.amount),
+            None => self.orders.push(order),
+        }
+    }
+
+    pub fn orders(&self) -> &[AuctionOrder] {
+        &self.orders
+    }
+
+    pub fn take_orders(&mut self) -> Vec<AuctionOrder> {
+        std::mem::take(&mut self.orders)
+    }
+
+    /// Shares of a stonk the player has ordered to buy and to sell.
+    pub fn ordered_amounts(&self, username: &str, stonk_id: usize) -> (u32, u32) {
+        self.orders
+            .iter()
+            .filter(|order| order.username == username && order.stonk_id == stonk_id)
+            .fold((0, 0), |(bought, sold), order| {
+                if order.is_buy {
+                    (bought.saturating_add(order.amount), sold)
+                } else {
+                    (bought, sold.saturating_add(order.amount))
+                }
+            })
+    }
+
+    /// Price and volume the auction of a stonk would clear at with the current orders.
+    pub fn indicative_price(&self, stonk_id: usize, reference_price: u32) -> Option<(u32, u32)> {
+        let orders = self
+            .orders
+            .iter()
+            .filter(|order| order.stonk_id == stonk_id)
+            .cloned()
+            .collect::<Vec<AuctionOrder>>();
+        clearing_price(&orders, reference_price)
+    }
+
+    pub fn rename(&mut self, old_username: &str, new_username: &str) {
+        for order in self.orders.iter_mut() {
+            if order.username == old_username {
+                order.username = new_username.to_string();
+            }
+        }
+    }
+
+    pub fn set_results(&mut self, cycle: usize, results: Vec<AuctionResult>) {
+        self.results = Some((cycle, results));
+    }
+
+    /// Results of the auction run in the given cycle.
+    pub fn results(&self, cycle: usize) -> Option<&[AuctionResult]> {
+        self.results
+            .as_ref()
+            .filter(|(results_cycle, _)| *results_cycle == cycle)
+            .map(|(_, results)| results.as_slice())
+    }
+}
+
+/// The price matching the most shares, and the matched volume, or None if no order crosses.
+/// Ties go to the smallest imbalance, then to the side with the surplus: the highest price
+/// when buyers are left over, the lowest when sellers are, otherwise the closest to the
+/// reference price.
+pub fn clearing_price(orders: &[AuctionOrder], reference_price: u32) -> Option<(u32, u32)> {
+    let demand = |price: u32| -> u64 {
+        orders
+            .iter()
+            .filter(|order| order.is_buy && order.limit_price >= price)
+            .map(|order| order.amount as u64)
+            .sum()
+    };
+    let supply = |price: u32| -> u64 {
+        orders
+            .iter()
+            .filter(|order| !order.is_buy && order.limit_price <= price)
+            .map(|order| order.amount as u64)
+            .sum()
+    };
+
+    let mut prices = orders
+        .iter()
+        .map(|order| order.limit_price)
+        .collect::<Vec<u32>>();
+    prices.sort_unstable();
+    prices.dedup();
+    let candidates = prices
+        .into_iter()
+        .map(|price| (price, demand(price), supply(price)))
+        .collect::<Vec<(u32, u64, u64)>>();
+
+    let volume = candidates
+        .iter()
+        .map(|(_, demand, supply)| *demand.min(supply))
+        .max()
+        .filter(|volume| *volume > 0)?;
+    let best = candidates
+        .iter()
+        .filter(|(_, demand, supply)| *demand.min(supply) == volume)
+        .collect::<Vec<_>>();
+    let imbalance = best
+        .iter()
+        .map(|(_, demand, supply)| demand.abs_diff(*supply))
+        .min()?;
+    let best = best
+        .into_iter()
+        .filter(|(_, demand, supply)| demand.abs_diff(*supply) == imbalance)
+        .collect::<Vec<_>>();
+
+    let lowest = best.first()?.0;
+    let highest = best.last()?.0;
+    let price = if best.iter().all(|(_, demand, supply)| demand > supply) {
+        highest
+    } else if best.iter().all(|(_, demand, supply)| demand < supply) {
+        lowest
+    } else {
+        reference_price.clamp(lowest, highest)
+    };
+    Some((price, volume.min(u32::MAX as u64) as u32))
+}
+
+/// Clears the auction of a stonk, returning its result and the filled orders, with the filled
+/// amounts. Orders are filled by best limit first, then by submission order.
+pub fn clear_orders(
+    stonk_id: usize,
+    orders: &[AuctionOrder],
+    reference_price: u32,
+) -> Option<(AuctionResult, Vec<AuctionOrder>)> {
+    let orders = orders
+        .iter()
+        .filter(|order| order.stonk_id == stonk_id)
+        .cloned()
+        .collect::<Vec<AuctionOrder>>();
+    let (price, volume) = clearing_price(&orders, reference_price)?;
+
+    let mut buys = orders
+        .iter()
+        .filter(|order| order.is_buy && order.limit_price >= price)
+        .collect::<Vec<_>>();
+    buys.sort_by_key(|order| std::cmp::Reverse(order.limit_price));
+    let mut sells = orders
+        .iter()
+        .filter(|order| !order.is_buy && order.limit_price <= price)
+        .collect::<Vec<_>>();
+    sells.sort_by_key(|order| order.limit_price);
+
+    let mut fills = vec![];
+    for side in [buys, sells] {
+        let mut remaining = volume;
+        for order in side {
+            if remaining == 0 {
+                break;
+            }
+            let amount = order.amount.min(remaining);
+            remaining -= amount;
+            fills.push(AuctionOrder {
+                amount,
+                ..order.clone()
+            });
+        }
+    }
+    Some((
+        AuctionResult {
+            stonk_id,
+            price,
+            volume,
+        },
+        fills,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clear_orders, clearing_price, AuctionOrder, OpeningAuction};
+
+    fn order(username: &str, amount: u32, limit_price: u32, is_buy: bool) -> AuctionOrder {
+        AuctionOrder {
+            username: username.to_string(),
+            stonk_id: 0,
+            amount,
+            limit_price,
+            is_buy,
+        }
+    }
+
+    #[test]
+    fn test_clearing_price() {
+        assert_eq!(clearing_price(&[], 100), None);
+        // Orders that do not cross leave the stonk out of the auction.
+        assert_eq!(
+            clearing_price(&[order("a", 10, 95, true), order("b", 10, 105, false)], 100),
+            None
+        );
+
+        let orders = vec![
+            order("a", 10, 105, true),
+            order("b", 10, 102, true),
+            order("c", 15, 95, false),
+            order("d", 10, 104, false),
+        ];
+        // 20 shares are bid above 102, and 15 offered below it.
+        assert_eq!(clearing_price(&orders, 100), Some((102, 15)));
+
+        // A balanced book clears at the reference price, within the crossing range.
+        let balanced = vec![order("a", 10, 105, true), order("b", 10, 95, false)];
+        assert_eq!(clearing_price(&balanced, 100), Some((100, 10)));
+        assert_eq!(clearing_price(&balanced, 200), Some((105, 10)));
+        // Left over buyers push the price up, left over sellers down.
+        let buyers = vec![order("a", 20, 105, true), order("b", 10, 95, false)];
+        assert_eq!(clearing_price(&buyers, 100), Some((105, 10)));
+        let sellers = vec![order("a", 10, 105, true), order("b", 20, 95, false)];
+        assert_eq!(clearing_price(&sellers, 100), Some((95, 10)));
+
+        let (result, fills) = clear_orders(0, &orders, 100).expect("Orders should cross");
+        assert_eq!((result.price, result.volume), (102, 15));
+        let filled = fills
+            .iter()
+            .map(|fill| (fill.username.as_str(), fill.amount))
+            .collect::<Vec<_>>();
+        assert_eq!(filled, vec![("a", 10), ("b", 5), ("c", 15)]);
+        assert!(clear_orders(1, &orders, 100).is_none());
+
+        let mut auction = OpeningAuction::default();
+        for order in orders {
+            auction.submit(order);
+        }
+        auction.submit(order("a", 5, 105, true));
+        assert_eq!(auction.orders().len(), 4);
+        assert_eq!(auction.ordered_amounts("a", 0), (15, 0));
+        assert_eq!(auction.indicative_price(0, 100), Some((100, 15)));
+        auction.rename("a", "z");
+        assert_eq!(auction.ordered_amounts("a", 0), (0, 0));
+        assert_eq!(auction.ordered_amounts("z", 0), (15, 0));
+        assert_eq!(auction.indicative_price(0, 100), Some((100, 15)));
+        auction.set_results(3, vec![]);
+        assert!(auction.results(3).is_some());
+        assert!(auction.results(4).is_none());
+    }
+}
@@ -0,0 +1,392 @@
+use crate::{
+    agent::AgentAction, events::NightEvent, market::NUMBER_OF_STONKS, money::Money,
+    utils::AppResult,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Mutex,
+    },
+    time::SystemTime,
+};
+use tracing::error;
+
+// The log is rotated when it grows over this size, keeping at most AUDIT_LOG_MAX_FILES old files.
+const AUDIT_LOG_MAX_BYTES: u64 = 10 * 1024 * 1024;
+const AUDIT_LOG_MAX_FILES: usize = 5;
+// After a failed rotation the log keeps growing, and rotating is retried after this many bytes.
+const AUDIT_LOG_ROTATION_RETRY_BYTES: u64 = 1024 * 1024;
+// Records queued over this are dropped rather than held in memory while the disk is stuck.
+const AUDIT_QUEUE_CAPACITY: usize = 4096;
+
+// Records are handed over to a writer thread, so that the game loop never waits for the disk.
+static AUDIT_WRITER: Lazy<Mutex<Option<mpsc::SyncSender<AuditMessage>>>> =
+    Lazy::new(|| Mutex::new(None));
+// Records dropped because the queue was full, reported by the writer once it catches up.
+static DROPPED_RECORDS: AtomicUsize = AtomicUsize::new(0);
+
+enum AuditMessage {
+    Record { timestamp: u64, event: AuditEvent },
+    // Answered once every record sent before it is on disk.
+    Flush(mpsc::Sender<()>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum AuditEvent {
+    Action {
+        username: String,
+        tick: usize,
+        action: AgentAction,
+        error: Option<String>,
+    },
+    NightEventsOffered {
+        username: String,
+        events: Vec<NightEvent>,
+    },
+    AssassinationResolved {
+        attacker: String,
+        target: String,
+    },
+    CallBluffResolved {
+        username: String,
+        success: bool,
+    },
+    Login {
+        username: String,
+        new_agent: bool,
+    },
+    LoginRejected {
+        username: String,
+        reason: String,
+    },
+    Logout {
+        username: String,
+    },
+    UsernameChanged {
+        old_username: String,
+        username: String,
+    },
+    AgentArchived {
+        username: String,
+    },
+    AgentRestored {
+        username: String,
+    },
+    AgentLiquidated {
+        username: String,
+        shares: [u32; NUMBER_OF_STONKS],
+        proceeds_cents: Money,
+    },
+    // Cash given to a player out of nothing, such as dividends and event income.
+    CashPrinted {
+        username: String,
+        amount_cents: Money,
+    },
+    DayClosed {
+        cycle: usize,
+        // Relative price change of each stonk over the day.
+        stonk_returns: Vec<(String, f64)>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct AuditRecord<'a> {
+    // Seconds since the unix epoch.
+    timestamp: u64,
+    #[serde(flatten)]
+    event: &'a AuditEvent,
+}
+
+#[derive(Debug, Deserialize)]
+struct SavedAuditRecord {
+    timestamp: u64,
+    #[serde(flatten)]
+    event: AuditEvent,
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut path = path.to_path_buf().into_os_string();
+    path.push(format!(".{}", index));
+    path.into()
+}
+
+/// Reads the records logged from the given timestamp until, excluding, the other one, from
+/// the oldest rotated file to the current one. Unreadable lines are skipped.
+pub fn read_records(path: &Path, from: u64, until: u64) -> AppResult<Vec<AuditEvent>> {
+    let mut events = vec![];
+    let paths = (1..=AUDIT_LOG_MAX_FILES)
+        .rev()
+        .map(|index| rotated_path(path, index))
+        .chain(std::iter::once(path.to_path_buf()));
+    for path in paths.filter(|path| path.is_file()) {
+        for line in BufReader::new(File::open(path)?).lines() {
+            let Ok(record) = serde_json::from_str::<SavedAuditRecord>(&line?) else {
+                continue;
+            };
+            if (from..until).contains(&record.timestamp) {
+                events.push(record.event);
+            }
+        }
+    }
+    Ok(events)
+}
+
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    size: u64,
+    rotation_size: u64,
+}
+
+impl AuditLog {
+    pub fn open(path: PathBuf) -> AppResult<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            writer: BufWriter::new(file),
+            size,
+            rotation_size: AUDIT_LOG_MAX_BYTES,
+        })
+    }
+
+    fn rotate(&mut self) -> AppResult<()> {
+        self.writer.flush()?;
+        for index in (1..AUDIT_LOG_MAX_FILES).rev() {
+            let from = rotated_path(&self.path, index);
+            if from.exists() {
+                std::fs::rename(from, rotated_path(&self.path, index + 1))?;
+            }
+        }
+        std::fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        *self = Self::open(self.path.clone())?;
+        Ok(())
+    }
+
+    /// Buffers the record, which is only on disk after the next flush.
+    pub fn write(&mut self, timestamp: u64, event: &AuditEvent) -> AppResult<()> {
+        if self.size >= self.rotation_size {
+            // Keep appending to the current file rather than losing the record.
+            if let Err(e) = self.rotate() {
+                error!("Could not rotate the audit log: {}", e);
+                self.rotation_size = self.size + AUDIT_LOG_ROTATION_RETRY_BYTES;
+            }
+        }
+
+        let record = AuditRecord { timestamp, event };
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        self.writer.write_all(&line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> AppResult<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn handle(&mut self, message: AuditMessage) {
+        match message {
+            AuditMessage::Record { timestamp, event } => {
+                if let Err(e) = self.write(timestamp, &event) {
+                    error!("Could not write audit record: {}", e);
+                }
+            }
+            AuditMessage::Flush(done) => {
+                if let Err(e) = self.flush() {
+                    error!("Could not flush the audit log: {}", e);
+                }
+                let _ = done.send(());
+            }
+        }
+    }
+
+    // Writes the records as they arrive, flushing once the queue is empty, so that a burst of
+    // records costs a single flush and the log is only behind while records are queued.
+    fn run(mut self, receiver: mpsc::Receiver<AuditMessage>) {
+        while let Ok(message) = receiver.recv() {
+            self.handle(message);
+            while let Ok(message) = receiver.try_recv() {
+                self.handle(message);
+            }
+            if let Err(e) = self.flush() {
+                error!("Could not flush the audit log: {}", e);
+            }
+            let dropped = DROPPED_RECORDS.swap(0, Ordering::Relaxed);
+            if dropped > 0 {
+                error!("Dropped {} audit records: the queue was full", dropped);
+            }
+        }
+    }
+}
+
+/// Starts writing audit records to the given file. Until then, records are discarded.
+pub fn init(path: PathBuf) -> AppResult<()> {
+    let log = AuditLog::open(path)?;
+    let (sender, receiver) = mpsc::sync_channel(AUDIT_QUEUE_CAPACITY);
+    std::thread::Builder::new()
+        .name("audit-log".to_string())
+        .spawn(move || log.run(receiver))?;
+    *AUDIT_WRITER.lock().map_err(|e| e.to_string())? = Some(sender);
+    Ok(())
+}
+
+/// Queues the record for the writer thread. The record is timestamped here, so that it
+/// keeps the time it happened at however long it waits.
+pub fn record(event: AuditEvent) {
+    let Ok(writer) = AUDIT_WRITER.lock() else {
+        return;
+    };
+    let Some(writer) = writer.as_ref() else {
+        return;
+    };
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    match writer.try_send(AuditMessage::Record { timestamp, event }) {
+        Ok(()) => {}
+        Err(mpsc::TrySendError::Full(_)) => {
+            DROPPED_RECORDS.fetch_add(1, Ordering::Relaxed);
+        }
+        Err(mpsc::TrySendError::Disconnected(_)) => {
+            error!("Could not write audit record: the audit log writer stopped");
+        }
+    }
+}
+
+/// Blocks until every record queued so far is on disk. Call it before reading the log.
+pub fn flush() {
+    let Some(writer) = AUDIT_WRITER.lock().ok().and_then(|writer| writer.clone()) else {
+        return;
+    };
+    let (done, flushed) = mpsc::channel();
+    if writer.send(AuditMessage::Flush(done)).is_ok() {
+        let _ = flushed.recv();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        read_records, rotated_path, AuditEvent, AuditLog, AuditMessage, AUDIT_LOG_MAX_BYTES,
+        AUDIT_LOG_MAX_FILES, AUDIT_LOG_ROTATION_RETRY_BYTES, AUDIT_QUEUE_CAPACITY,
+    };
+    use crate::{agent::AgentAction, utils::AppResult};
+    use std::{io::BufRead, sync::mpsc};
+
+    #[test]
+    fn test_audit_log() -> AppResult<()> {
+        let dir = std::env::temp_dir().join(format!("stonks-audit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("audit.log");
+
+        let mut log = AuditLog::open(path.clone())?;
+        log.write(
+            42,
+            &AuditEvent::Action {
+                username: "auditor".to_string(),
+                tick: 42,
+                action: AgentAction::Buy {
+                    stonk_id: 1,
+                    amount: 10,
+                },
+                error: None,
+            },
+        )?;
+        log.flush()?;
+
+        let file = std::fs::File::open(&path)?;
+        let line = std::io::BufReader::new(file)
+            .lines()
+            .next()
+            .expect("Missing audit record")?;
+        let record: serde_json::Value = serde_json::from_str(&line)?;
+        assert_eq!(record["kind"], "Action");
+        assert_eq!(record["username"], "auditor");
+        assert_eq!(record["action"]["Buy"]["amount"], 10);
+
+        // Force a rotation.
+        log.size = AUDIT_LOG_MAX_BYTES;
+        log.write(
+            43,
+            &AuditEvent::Logout {
+                username: "auditor".to_string(),
+            },
+        )?;
+        log.flush()?;
+        assert!(rotated_path(&path, 1).is_file());
+        assert!(std::fs::read_to_string(&path)?.contains("Logout"));
+
+        // Records are read back across the rotated files, oldest first.
+        let events = read_records(&path, 0, u64::MAX)?;
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], AuditEvent::Action { tick: 42, .. }));
+        assert!(matches!(events[1], AuditEvent::Logout { .. }));
+        assert!(read_records(&path, 0, 42)?.is_empty());
+        assert_eq!(read_records(&path, 43, 44)?.len(), 1);
+
+        // A failed rotation keeps appending, and is only retried once the file grew some more.
+        std::fs::write(rotated_path(&path, AUDIT_LOG_MAX_FILES - 1), "")?;
+        std::fs::create_dir_all(rotated_path(&path, AUDIT_LOG_MAX_FILES).join("blocked"))?;
+        log.size = AUDIT_LOG_MAX_BYTES;
+        log.write(
+            44,
+            &AuditEvent::Logout {
+                username: "auditor".to_string(),
+            },
+        )?;
+        log.flush()?;
+        assert_eq!(read_records(&path, 44, 45)?.len(), 1);
+        assert!(log.rotation_size > log.size);
+        assert!(log.rotation_size <= log.size + AUDIT_LOG_ROTATION_RETRY_BYTES);
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_audit_writer() -> AppResult<()> {
+        let dir = std::env::temp_dir().join(format!("stonks-audit-writer-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("audit.log");
+
+        let log = AuditLog::open(path.clone())?;
+        let (sender, receiver) = mpsc::sync_channel(AUDIT_QUEUE_CAPACITY);
+        let writer = std::thread::spawn(move || log.run(receiver));
+        for timestamp in 0..100 {
+            sender
+                .send(AuditMessage::Record {
+                    timestamp,
+                    event: AuditEvent::Logout {
+                        username: "auditor".to_string(),
+                    },
+                })
+                .map_err(|e| e.to_string())?;
+        }
+
+        // Once the flush is answered, every record sent before it can be read back.
+        let (done, flushed) = mpsc::channel();
+        sender
+            .send(AuditMessage::Flush(done))
+            .map_err(|e| e.to_string())?;
+        flushed.recv().map_err(|e| e.to_string())?;
+        assert_eq!(read_records(&path, 0, u64::MAX)?.len(), 100);
+        assert_eq!(read_records(&path, 99, 100)?.len(), 1);
+
+        // The writer stops when the server drops its sender.
+        drop(sender);
+        writer.join().map_err(|_| "Audit writer panicked")?;
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
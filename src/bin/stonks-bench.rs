@@ -0,0 +1,227 @@
+// Load test of the market engine, without the SSH server: thousands of simulated players trade
+// at every tick, and the time spent trading, updating the portfolios, ticking, copying the state
+// and persisting it is reported per phase. Interrupting the run with ctrl-c still prints the report of the
+// ticks completed so far. The state is persisted to a temporary directory, never to the store.
+
+use clap::{ArgAction, Parser};
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use stonks::{
+    agent::{AgentAction, DecisionAgent, UserAgent},
+    market::{Market, DAY_LENGTH, NIGHT_LENGTH, NUMBER_OF_STONKS},
+    market_event::MarketEvent,
+    ssh_client::SessionAuth,
+    ssh_server::AgentsDatabase,
+    utils::AppResult,
+};
+
+// Largest amount of shares of a simulated trade.
+const MAX_TRADE_AMOUNT: u32 = 20;
+
+#[derive(Parser, Debug)]
+#[clap(name = "stonks-bench", about = "Load test of the Stonks market engine", long_about = None)]
+struct Args {
+    #[clap(long, short = 'a', action=ArgAction::Set, default_value_t = 5_000, help = "Number of simulated players")]
+    agents: usize,
+    #[clap(long, short = 't', action=ArgAction::Set, default_value_t = DAY_LENGTH + NIGHT_LENGTH, help = "Number of market ticks")]
+    ticks: usize,
+    #[clap(long, action=ArgAction::Set, default_value_t = 0.2, help = "Probability of a player trading at each tick")]
+    trade_probability: f64,
+    #[clap(long, action=ArgAction::Set, default_value_t = 16, help = "Ticks between two saves of the state")]
+    persist_interval: usize,
+    #[clap(long, short = 's', action=ArgAction::Set, default_value_t = 0, help = "Set random seed")]
+    seed: u64,
+}
+
+#[derive(Debug, Default)]
+struct PhaseTimings {
+    samples: Vec<Duration>,
+}
+
+impl PhaseTimings {
+    fn time<T>(&mut self, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.samples.push(start.elapsed());
+        result
+    }
+
+    fn report(&mut self, name: &str) -> String {
+        if self.samples.is_empty() {
+            return format!("{:<12} not run", name);
+        }
+        self.samples.sort();
+        let total = self.samples.iter().sum::<Duration>();
+        let percentile = |p: f64| {
+            self.samples[((self.samples.len() - 1) as f64 * p).round() as usize].as_secs_f64()
+                * 1_000.0
+        };
+        format!(
+            "{:<12} {:>6} runs  total {:>9.1} ms  mean {:>8.3} ms  p50 {:>8.3} ms  p99 {:>8.3} ms  max {:>8.3} ms",
+            name,
+            self.samples.len(),
+            total.as_secs_f64() * 1_000.0,
+            total.as_secs_f64() * 1_000.0 / self.samples.len() as f64,
+            percentile(0.5),
+            percentile(0.99),
+            percentile(1.0),
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+struct Report {
+    ticks: usize,
+    trades: usize,
+    rejected_trades: usize,
+    trading: PhaseTimings,
+    portfolios: PhaseTimings,
+    tick: PhaseTimings,
+    // Copy of the state handed to the persistence task, paid by the game loop.
+    snapshot: PhaseTimings,
+    persistence: PhaseTimings,
+}
+
+impl Report {
+    fn print(&mut self, args: &Args, interrupted: bool, elapsed: Duration) {
+        println!(
+            "{} ticks of {} with {} players in {:.2} s",
+            self.ticks,
+            args.ticks,
+            args.agents,
+            elapsed.as_secs_f64()
+        );
+        if interrupted {
+            println!("Interrupted, the report covers the completed ticks only.");
+        }
+        println!(
+            "{} trades, {} of which rejected",
+            self.trades, self.rejected_trades
+        );
+        println!("{}", self.trading.report("trading"));
+        println!("{}", self.portfolios.report("portfolios"));
+        println!("{}", self.tick.report("tick"));
+        println!("{}", self.snapshot.report("snapshot"));
+        println!("{}", self.persistence.report("persistence"));
+    }
+}
+
+fn random_action<R: Rng>(rng: &mut R, agent: &UserAgent) -> AgentAction {
+    let stonk_id = rng.gen_range(0..NUMBER_OF_STONKS);
+    let amount = rng.gen_range(1..=MAX_TRADE_AMOUNT);
+    let owned = agent.owned_stonks()[stonk_id];
+    if owned > 0 && rng.gen_bool(0.5) {
+        AgentAction::Sell {
+            stonk_id,
+            amount: amount.min(owned),
+        }
+    } else {
+        AgentAction::Buy { stonk_id, amount }
+    }
+}
+
+fn save_json<T: serde::Serialize>(path: &Path, data: &T) -> AppResult<()> {
+    serde_json::to_writer(BufWriter::new(File::create(path)?), data)?;
+    Ok(())
+}
+
+fn run(args: &Args, stop: &AtomicBool, directory: &Path) -> AppResult<(Report, bool)> {
+    let rng = &mut ChaCha8Rng::seed_from_u64(args.seed);
+    let mut market = Market::new();
+    let mut agents = AgentsDatabase::default();
+    for index in 0..args.agents {
+        let username = format!("bench{}", index);
+        agents.insert(
+            username.clone(),
+            UserAgent::new(SessionAuth::new(username, [0; 32])),
+        );
+    }
+    let mut usernames = agents.keys().cloned().collect::<Vec<String>>();
+    usernames.sort();
+
+    let mut report = Report::default();
+    for tick in 0..args.ticks {
+        if stop.load(Ordering::Relaxed) {
+            return Ok((report, true));
+        }
+
+        usernames.shuffle(rng);
+        let mut events = vec![];
+        for username in usernames.iter() {
+            if rng.gen_bool(args.trade_probability) {
+                events.push(MarketEvent::AgentActions {
+                    username: username.clone(),
+                    actions: vec![random_action(rng, &agents[username])],
+                    seed: rng.gen(),
+                });
+            }
+        }
+        report.trades += events.len();
+        report.rejected_trades += report
+            .trading
+            .time(|| market.replay(&events, &mut agents))
+            .len();
+
+        report
+            .portfolios
+            .time(|| market.update_portfolios(&agents).len());
+        report
+            .tick
+            .time(|| market.apply_event(&MarketEvent::Tick { seed: rng.gen() }, &mut agents))?;
+        if (tick + 1) % args.persist_interval.max(1) == 0 {
+            let (market, agents) = report.snapshot.time(|| (market.clone(), agents.clone()));
+            report.persistence.time(|| -> AppResult<()> {
+                save_json(&directory.join("market.json"), &market)?;
+                save_json(&directory.join("agents.json"), &agents)
+            })?;
+        }
+        report.ticks += 1;
+    }
+    Ok((report, false))
+}
+
+#[tokio::main]
+async fn main() -> AppResult<()> {
+    let args = Args::parse();
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let interrupt = stop.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            eprintln!("Stopping after the current tick, press ctrl-c again to abort.");
+            interrupt.store(true, Ordering::Relaxed);
+        }
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(130);
+        }
+    });
+
+    let directory = std::env::temp_dir().join(format!("stonks-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&directory)?;
+    let start = Instant::now();
+    let (result, args) = tokio::task::spawn_blocking(move || {
+        let result = run(&args, &stop, &directory).map_err(|e| e.to_string());
+        std::fs::remove_dir_all(&directory).unwrap_or_else(|e| {
+            eprintln!("Could not remove {}: {}", directory.display(), e);
+        });
+        (result, args)
+    })
+    .await?;
+
+    let (mut report, interrupted) = result?;
+    report.print(&args, interrupted, start.elapsed());
+    if interrupted {
+        std::process::exit(130);
+    }
+    Ok(())
+}
@@ -0,0 +1,148 @@
+// Plays back a session recorded by a player for a bug report: the keys are fed to a headless
+// client, in order, and every screen reached is compared with the recorded one, so that the
+// first key where the playback diverges points at the bug. The market is a fresh one, or the
+// one passed with --market, for example a copy of the server store taken around the report.
+
+use clap::{ArgAction, Parser};
+use crossterm::event::KeyCode;
+use ratatui::{backend::TestBackend, Terminal};
+use std::path::PathBuf;
+use stonks::{
+    agent::UserAgent,
+    market::Market,
+    recording::{load_recording, RecordedEvent},
+    ssh_client::{Client, SessionAuth},
+    ui::{render, RenderCache},
+    utils::AppResult,
+};
+
+#[derive(Parser, Debug)]
+#[clap(name = "stonks-replay", about = "Plays back a recorded Stonks session", long_about = None)]
+struct Args {
+    #[clap(help = "Session recording, as downloaded by the player")]
+    recording: PathBuf,
+    #[clap(long, short = 'm', action=ArgAction::Set, help = "Market to play the session on")]
+    market: Option<PathBuf>,
+    #[clap(long, short = 'f', action=ArgAction::SetTrue, help = "Print the screen after every key")]
+    frames: bool,
+}
+
+fn print_frame(
+    client: &Client,
+    market: &Market,
+    agent: &UserAgent,
+    (width, height): (u16, u16),
+) -> AppResult<()> {
+    let mut terminal = Terminal::new(TestBackend::new(width, height))?;
+    let mut cache = RenderCache::default();
+    let mut result = Ok(vec![]);
+    terminal.draw(|frame| {
+        result = render(frame, market, agent, client.ui_options(), &mut cache, 1);
+    })?;
+    result?;
+
+    let buffer = terminal.backend().buffer();
+    for y in 0..buffer.area.height {
+        let line = (0..buffer.area.width)
+            .map(|x| buffer.get(x, y).symbol())
+            .collect::<String>();
+        println!("{}", line.trim_end());
+    }
+    Ok(())
+}
+
+fn main() -> AppResult<()> {
+    let args = Args::parse();
+    let events = load_recording(&args.recording)?;
+    let Some(RecordedEvent::Start {
+        username,
+        version,
+        width,
+        height,
+        text_mode,
+        tick,
+        screen,
+    }) = events.first().cloned()
+    else {
+        return Err("The recording does not begin with its start".into());
+    };
+    println!(
+        "Session of {} on version {} ({}x{}), recorded at tick {}",
+        username, version, width, height, tick
+    );
+    if version != env!("CARGO_PKG_VERSION") {
+        println!(
+            "Warning: playing back on version {}",
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+
+    let market = match args.market.as_ref() {
+        Some(path) => serde_json::from_reader(std::fs::File::open(path)?)?,
+        None => Market::new(),
+    };
+    let mut agent = UserAgent::new(SessionAuth::new(username.clone(), [0; 32]));
+    if agent.text_mode() != text_mode {
+        agent.toggle_text_mode();
+    }
+    let mut client = Client::headless(username)?;
+    let mut size = (width, height);
+    client.tui.resize(width, height, 0, 0)?;
+    if !client.ui_options_mut().restore_screen_state(&screen) {
+        println!(
+            "Warning: the session starts in the {} dialog, which cannot be restored",
+            screen.modal.as_deref().unwrap_or_default()
+        );
+    }
+
+    let mut keys = 0;
+    let mut divergences = 0;
+    for event in events.iter().skip(1) {
+        match event {
+            RecordedEvent::Key { millis, key } => {
+                keys += 1;
+                if key.modifiers.is_empty() {
+                    println!("{:>8} ms  {:?}", millis, key.code);
+                } else {
+                    println!("{:>8} ms  {:?} with {:?}", millis, key.code, key.modifiers);
+                }
+                if key.code == KeyCode::Esc || client.is_quit_key(key) {
+                    println!("The player quit");
+                    break;
+                }
+                client.handle_key_events(*key, &market, &mut agent)?;
+                if args.frames {
+                    print_frame(&client, &market, &agent, size)?;
+                }
+            }
+            RecordedEvent::Resize {
+                millis,
+                width,
+                height,
+            } => {
+                println!("{:>8} ms  resized to {}x{}", millis, width, height);
+                size = (*width, *height);
+                client.tui.resize(*width, *height, 0, 0)?;
+            }
+            RecordedEvent::Screen { millis, screen } => {
+                let replayed = client.ui_options().screen_state();
+                if replayed == *screen {
+                    println!("{:>8} ms  screen {:?}", millis, screen);
+                } else {
+                    divergences += 1;
+                    println!(
+                        "{:>8} ms  diverged: recorded {:?}, played back {:?}",
+                        millis, screen, replayed
+                    );
+                }
+            }
+            RecordedEvent::Start { .. } => return Err("The recording starts twice".into()),
+        }
+    }
+
+    println!("{} keys played back, {} divergences", keys, divergences);
+    if !args.frames {
+        print_frame(&client, &market, &agent, size)?;
+    }
+    Ok(())
+}
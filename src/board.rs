@@ -0,0 +1,133 @@
+// Board control. The largest shareholder of a stonk, holding more than a quarter of its shares,
+// chairs the board and can take a decision on the company once per day. Decisions are public:
+// they are reported on the news ticker for a while after being taken.
+
+use crate::market::{DAY_LENGTH, TICKS_PER_HOUR};
+use crate::stonk::Stonk;
+use serde::{Deserialize, Serialize};
+use strum::EnumIter;
+
+// Stake of the shares needed to control the board, as a fraction of all the shares.
+pub const BOARD_CONTROL_STAKE: f64 = 0.25;
+// Decisions on the same stonk are at least this many ticks apart, whoever takes them.
+pub const BOARD_DECISION_COOLDOWN_TICKS: usize = DAY_LENGTH;
+// Drift bump of a guidance change, lasting one day. A fourth of a class bump.
+pub const BOARD_GUIDANCE_BUMP: f64 = 1.0;
+// Fraction of the value of their shares paid to every shareholder by a dividend.
+pub const BOARD_DIVIDEND_RATE: f64 = 0.02;
+// Fraction of all the shares retired by a buyback, out of the shares nobody holds.
+pub const BOARD_BUYBACK_STAKE: f64 = 0.02;
+// Decisions stay on the news ticker for this many ticks.
+pub const BOARD_NEWS_TICKS: usize = 2 * TICKS_PER_HOUR;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EnumIter, Serialize, Deserialize)]
+pub enum BoardDecision {
+    RaiseGuidance,
+    LowerGuidance,
+    DeclareDividend,
+    Buyback,
+}
+
+impl BoardDecision {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::RaiseGuidance => "Raise guidance",
+            Self::LowerGuidance => "Lower guidance",
+            Self::DeclareDividend => "Declare dividend",
+            Self::Buyback => "Buyback",
+        }
+    }
+
+    pub fn description(&self) -> String {
+        match self {
+            Self::RaiseGuidance => format!(
+                "Bump the drift by {:+.0} for a day.",
+                BOARD_GUIDANCE_BUMP
+            ),
+            Self::LowerGuidance => format!(
+                "Bump the drift by {:+.0} for a day.",
+                -BOARD_GUIDANCE_BUMP
+            ),
+            Self::DeclareDividend => format!(
+                "Pay {:.0}% of the value of their shares to every shareholder, the price drops as much.",
+                BOARD_DIVIDEND_RATE * 100.0
+            ),
+            Self::Buyback => format!(
+                "Retire up to {:.0}% of the shares out of the unheld ones, the price rises to keep the market cap.",
+                BOARD_BUYBACK_STAKE * 100.0
+            ),
+        }
+    }
+
+    /// The news headline of the decision.
+    pub fn headline(&self) -> &'static str {
+        match self {
+            Self::RaiseGuidance => "raises guidance",
+            Self::LowerGuidance => "cuts guidance",
+            Self::DeclareDividend => "declares a dividend",
+            Self::Buyback => "announces a buyback",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BoardMinutes {
+    pub stonk_id: usize,
+    pub username: String,
+    pub decision: BoardDecision,
+    pub tick: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Boards {
+    // Last decision taken on each stonk.
+    #[serde(default)]
+    minutes: Vec<BoardMinutes>,
+}
+
+impl Boards {
+    /// The player chairing the board of the stonk, if any.
+    pub fn controller(stonk: &Stonk) -> Option<&str> {
+        stonk
+            .shareholders
+            .first()
+            .filter(|(_, amount)| stonk.to_stake(*amount) > BOARD_CONTROL_STAKE)
+            .map(|(username, _)| username.as_str())
+    }
+
+    pub fn last_decision(&self, stonk_id: usize) -> Option<&BoardMinutes> {
+        self.minutes
+            .iter()
+            .find(|minutes| minutes.stonk_id == stonk_id)
+    }
+
+    /// The tick the board of the stonk can take its next decision at.
+    pub fn next_decision_tick(&self, stonk_id: usize) -> usize {
+        self.last_decision(stonk_id)
+            .map_or(0, |minutes| minutes.tick + BOARD_DECISION_COOLDOWN_TICKS)
+    }
+
+    pub fn record(&mut self, minutes: BoardMinutes) {
+        self.minutes.retain(|old| old.stonk_id != minutes.stonk_id);
+        self.minutes.push(minutes);
+    }
+
+    /// The decisions still in the news at the given tick, most recent first.
+    pub fn news(&self, current_tick: usize) -> Vec<&BoardMinutes> {
+        let mut news = self
+            .minutes
+            .iter()
+            .filter(|minutes| current_tick < minutes.tick + BOARD_NEWS_TICKS)
+            .collect::<Vec<_>>();
+        news.sort_by_key(|minutes| std::cmp::Reverse(minutes.tick));
+        news
+    }
+
+    pub fn rename(&mut self, old_username: &str, new_username: &str) {
+        for minutes in self.minutes.iter_mut() {
+            if minutes.username == old_username {
+                minutes.username = new_username.to_string();
+            }
+        }
+    }
+}
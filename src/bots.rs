@@ -0,0 +1,187 @@
+// NPC bots keep the market moving when few players are online. Bots trade from paper
+// accounts, following the strategies of the registry, so they never hold real shares, but
+// their order flow bumps the prices like the one of the players. The server sizes the
+// population from the number of players online, aiming at a target number of trades per tick.
+
+use crate::{
+    agent::{AgentAction, INITIAL_USER_CASH},
+    config::BotsConfig,
+    money::Money,
+    strategy::{build_strategy, strategy_names, AgentView, MarketView, Strategy},
+    tournament::PaperAccount,
+};
+use serde::{Deserialize, Serialize};
+
+pub const BOT_STARTING_CASH: Money = INITIAL_USER_CASH;
+// Least probability that a bot trades at a tick, when there are bots at all.
+pub const MIN_BOT_AGGRESSIVENESS: f64 = 0.1;
+
+#[derive(Serialize, Deserialize)]
+pub struct Bot {
+    pub name: String,
+    pub account: PaperAccount,
+    // Name of the strategy in the registry.
+    #[serde(default = "default_strategy_name")]
+    pub strategy_name: String,
+    // Built from the registry on the first decision, so its state is not stored.
+    #[serde(skip)]
+    strategy: Option<Box<dyn Strategy>>,
+}
+
+fn default_strategy_name() -> String {
+    "noise".to_string()
+}
+
+impl Clone for Bot {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            account: self.account.clone(),
+            strategy_name: self.strategy_name.clone(),
+            strategy: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for Bot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Bot")
+            .field("name", &self.name)
+            .field("account", &self.account)
+            .field("strategy_name", &self.strategy_name)
+            .finish()
+    }
+}
+
+impl Bot {
+    // Bots cycle through the registered strategies.
+    fn new(idx: usize) -> Self {
+        let names = strategy_names();
+        let strategy_name = names
+            .get(idx % names.len().max(1))
+            .map_or_else(default_strategy_name, |name| name.to_string());
+        Self {
+            name: format!("bot-{}", idx + 1),
+            account: PaperAccount::new(BOT_STARTING_CASH),
+            strategy_name,
+            strategy: None,
+        }
+    }
+
+    /// Asks the strategy of the bot for a trade. Bots with an unknown strategy do not trade.
+    pub fn decide(
+        &mut self,
+        view: &MarketView,
+        aggressiveness: f64,
+        seed: u64,
+    ) -> Option<AgentAction> {
+        if self.strategy.is_none() {
+            self.strategy = build_strategy(&self.strategy_name);
+        }
+        let me = AgentView {
+            name: &self.name,
+            cash: self.account.cash,
+            owned_stonks: &self.account.owned_stonks,
+            aggressiveness,
+            seed,
+        };
+        self.strategy.as_mut()?.decide(view, &me)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BotPopulation {
+    bots: Vec<Bot>,
+    // Probability that each bot trades at a tick of the day.
+    aggressiveness: f64,
+}
+
+impl BotPopulation {
+    pub fn len(&self) -> usize {
+        self.bots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bots.is_empty()
+    }
+
+    pub fn aggressiveness(&self) -> f64 {
+        self.aggressiveness
+    }
+
+    pub fn bots(&self) -> &[Bot] {
+        &self.bots
+    }
+
+    pub fn bots_mut(&mut self) -> &mut [Bot] {
+        &mut self.bots
+    }
+
+    /// Adds or drops bots, the most recent first. Bots kept keep their accounts.
+    pub fn resize(&mut self, size: usize, aggressiveness: f64) {
+        self.bots.truncate(size);
+        for idx in self.bots.len()..size {
+            self.bots.push(Bot::new(idx));
+        }
+        self.aggressiveness = aggressiveness.clamp(0.0, 1.0);
+    }
+}
+
+/// Bot population size and aggressiveness making up for the trades the players online are
+/// expected to miss to reach the target. Each bot trades at most once per tick.
+pub fn bot_scaling(config: &BotsConfig, players_online: usize) -> (usize, f64) {
+    let missing = (config.target_trades_per_tick
+        - players_online as f64 * config.trades_per_player_per_tick)
+        .max(0.0);
+    if missing == 0.0 || config.max_bots == 0 {
+        return (0, 0.0);
+    }
+    let size = (missing.ceil() as usize).clamp(1, config.max_bots);
+    let aggressiveness = (missing / size as f64).clamp(MIN_BOT_AGGRESSIVENESS, 1.0);
+    (size, aggressiveness)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bot_scaling, BotPopulation, MIN_BOT_AGGRESSIVENESS};
+    use crate::config::BotsConfig;
+
+    #[test]
+    fn test_bot_scaling() {
+        let config = BotsConfig {
+            target_trades_per_tick: 2.5,
+            trades_per_player_per_tick: 0.5,
+            max_bots: 2,
+        };
+        // Alone, the target is capped by the largest population.
+        assert_eq!(bot_scaling(&config, 0), (2, 1.0));
+        assert_eq!(bot_scaling(&config, 2), (2, 0.75));
+        assert_eq!(bot_scaling(&config, 4), (1, 0.5));
+        assert_eq!(bot_scaling(&config, 5), (0, 0.0));
+        assert_eq!(bot_scaling(&config, 50), (0, 0.0));
+
+        let (size, aggressiveness) = bot_scaling(
+            &BotsConfig {
+                target_trades_per_tick: 0.01,
+                ..config.clone()
+            },
+            0,
+        );
+        assert_eq!((size, aggressiveness), (1, MIN_BOT_AGGRESSIVENESS));
+        let disabled = BotsConfig {
+            max_bots: 0,
+            ..config
+        };
+        assert_eq!(bot_scaling(&disabled, 0), (0, 0.0));
+
+        let mut population = BotPopulation::default();
+        population.resize(3, 0.5);
+        population.bots_mut()[0].account.cash = Default::default();
+        population.resize(1, 2.0);
+        assert_eq!(population.len(), 1);
+        assert_eq!(population.aggressiveness(), 1.0);
+        population.resize(2, 1.0);
+        assert_eq!(population.bots()[1].name, "bot-2");
+        assert!(population.bots()[0].account.cash.cents() == 0);
+    }
+}
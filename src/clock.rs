@@ -0,0 +1,242 @@
+// The game clock decides when the market ticks. It can be paused, sped up or
+// fast-forwarded by admins, so all tick timing goes through it. During maintenance it is
+// frozen altogether, fast-forwards included, so that the state can be saved or migrated.
+
+use crate::utils::AppResult;
+use std::time::{Duration, Instant};
+
+pub const MARKET_TICK_INTERVAL_MILLIS: u64 = 1000;
+pub const MIN_CLOCK_SPEED: f64 = 0.1;
+pub const MAX_CLOCK_SPEED: f64 = 100.0;
+// Upper bound on a single fast-forward, so that the market is not locked for too long.
+pub const MAX_FAST_FORWARD_TICKS: usize = 10_000;
+
+// Announced to the players in place of the game while the server is under maintenance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Maintenance {
+    pub message: String,
+    // When the game is expected to be back, if announced.
+    pub back_at: Option<Instant>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GameClock {
+    speed: f64,
+    paused: bool,
+    // Ticks requested by a fast-forward and not applied yet.
+    pending_ticks: usize,
+    next_tick_at: Instant,
+    maintenance: Option<Maintenance>,
+}
+
+impl GameClock {
+    pub fn new(speed: f64, now: Instant) -> AppResult<Self> {
+        let mut clock = Self {
+            speed: 1.0,
+            paused: false,
+            pending_ticks: 0,
+            next_tick_at: now,
+            maintenance: None,
+        };
+        clock.set_speed(speed, now)?;
+        Ok(clock)
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Whether the market is halted, by a pause or by a maintenance.
+    pub fn is_paused(&self) -> bool {
+        self.paused || self.maintenance.is_some()
+    }
+
+    pub fn maintenance(&self) -> Option<&Maintenance> {
+        self.maintenance.as_ref()
+    }
+
+    /// Freezes the clock until the maintenance ends, `eta` being the expected duration.
+    pub fn start_maintenance(&mut self, message: String, eta: Option<Duration>, now: Instant) {
+        self.maintenance = Some(Maintenance {
+            message,
+            back_at: eta.map(|eta| now + eta),
+        });
+    }
+
+    /// Restarts the clock in the state it had before the maintenance.
+    pub fn end_maintenance(&mut self, now: Instant) {
+        if self.maintenance.take().is_some() {
+            self.next_tick_at = now + self.tick_interval();
+        }
+    }
+
+    /// Real time between two ticks at the current speed.
+    pub fn tick_interval(&self) -> Duration {
+        Duration::from_millis(MARKET_TICK_INTERVAL_MILLIS).div_f64(self.speed)
+    }
+
+    pub fn set_speed(&mut self, speed: f64, now: Instant) -> AppResult<()> {
+        if !(MIN_CLOCK_SPEED..=MAX_CLOCK_SPEED).contains(&speed) {
+            return Err(format!(
+                "Invalid clock speed {}, must be between {} and {}",
+                speed, MIN_CLOCK_SPEED, MAX_CLOCK_SPEED
+            )
+            .into());
+        }
+        self.speed = speed;
+        self.next_tick_at = now + self.tick_interval();
+        Ok(())
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self, now: Instant) {
+        if self.paused {
+            self.paused = false;
+            self.next_tick_at = now + self.tick_interval();
+        }
+    }
+
+    /// Schedules ticks to be applied right away, even while paused.
+    pub fn fast_forward(&mut self, ticks: usize) -> AppResult<()> {
+        if ticks == 0 || ticks > MAX_FAST_FORWARD_TICKS {
+            return Err(format!(
+                "Can fast-forward between 1 and {} ticks at a time",
+                MAX_FAST_FORWARD_TICKS
+            )
+            .into());
+        }
+        // Fast-forwards not applied yet add up to the same bound.
+        self.pending_ticks = self
+            .pending_ticks
+            .saturating_add(ticks)
+            .min(MAX_FAST_FORWARD_TICKS);
+        Ok(())
+    }
+
+    /// When the next tick is due, or None if the clock is paused with nothing to apply.
+    pub fn next_tick_at(&self) -> Option<Instant> {
+        if self.maintenance.is_some() {
+            None
+        } else if self.pending_ticks > 0 {
+            Some(Instant::now().min(self.next_tick_at))
+        } else if self.paused {
+            None
+        } else {
+            Some(self.next_tick_at)
+        }
+    }
+
+    /// Returns the number of ticks to apply now and schedules the next one.
+    /// A late tick shifts the following ones rather than bursting to catch up.
+    pub fn take_due_ticks(&mut self, now: Instant) -> usize {
+        if self.maintenance.is_some() {
+            return 0;
+        }
+        let mut ticks = std::mem::take(&mut self.pending_ticks);
+        if !self.paused && now >= self.next_tick_at {
+            ticks += 1;
+            self.next_tick_at = now + self.tick_interval();
+        }
+        ticks
+    }
+
+    pub fn status(&self) -> String {
+        format!(
+            "{} at speed {}x, one tick every {} ms",
+            if self.maintenance.is_some() {
+                "Under maintenance"
+            } else if self.paused {
+                "Paused"
+            } else {
+                "Running"
+            },
+            self.speed,
+            self.tick_interval().as_millis()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GameClock, MAX_FAST_FORWARD_TICKS};
+    use crate::utils::AppResult;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_game_clock() -> AppResult<()> {
+        let start = Instant::now();
+        let mut clock = GameClock::new(2.0, start)?;
+        assert_eq!(clock.tick_interval(), Duration::from_millis(500));
+        assert_eq!(clock.take_due_ticks(start), 0);
+        assert_eq!(clock.take_due_ticks(start + Duration::from_millis(500)), 1);
+        // A late tick does not make the clock burst.
+        let late = start + Duration::from_millis(3_000);
+        assert_eq!(clock.take_due_ticks(late), 1);
+        assert_eq!(clock.take_due_ticks(late), 0);
+
+        clock.pause();
+        assert!(clock.next_tick_at().is_none());
+        assert_eq!(clock.take_due_ticks(late + Duration::from_secs(60)), 0);
+        clock.fast_forward(5)?;
+        assert!(clock.next_tick_at().is_some());
+        assert_eq!(clock.take_due_ticks(late + Duration::from_secs(60)), 5);
+        assert!(clock.fast_forward(0).is_err());
+        assert!(clock.fast_forward(MAX_FAST_FORWARD_TICKS + 1).is_err());
+        assert!(clock.fast_forward(usize::MAX).is_err());
+        clock.fast_forward(MAX_FAST_FORWARD_TICKS)?;
+        clock.fast_forward(MAX_FAST_FORWARD_TICKS)?;
+        assert_eq!(
+            clock.take_due_ticks(late + Duration::from_secs(60)),
+            MAX_FAST_FORWARD_TICKS
+        );
+
+        let now = late + Duration::from_secs(120);
+        clock.resume(now);
+        clock.set_speed(0.5, now)?;
+        assert!(clock.set_speed(0.0, now).is_err());
+        assert_eq!(clock.take_due_ticks(now + Duration::from_millis(1_999)), 0);
+        assert_eq!(clock.take_due_ticks(now + Duration::from_millis(2_000)), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_maintenance() -> AppResult<()> {
+        let start = Instant::now();
+        let mut clock = GameClock::new(1.0, start)?;
+        clock.pause();
+        clock.fast_forward(3)?;
+        clock.start_maintenance(
+            "Migrating".to_string(),
+            Some(Duration::from_secs(600)),
+            start,
+        );
+        assert!(clock.is_paused());
+        assert_eq!(
+            clock
+                .maintenance()
+                .and_then(|maintenance| maintenance.back_at),
+            Some(start + Duration::from_secs(600))
+        );
+        // Not even the fast-forwarded ticks are applied during the maintenance.
+        assert!(clock.next_tick_at().is_none());
+        assert_eq!(clock.take_due_ticks(start + Duration::from_secs(60)), 0);
+
+        // Afterwards the clock is still paused, with the fast-forward pending.
+        let now = start + Duration::from_secs(120);
+        clock.end_maintenance(now);
+        assert!(clock.maintenance().is_none());
+        assert!(clock.is_paused());
+        assert_eq!(clock.take_due_ticks(now), 3);
+
+        clock.start_maintenance(String::new(), None, now);
+        clock.resume(now);
+        assert_eq!(clock.take_due_ticks(now + Duration::from_secs(60)), 0);
+        clock.end_maintenance(now + Duration::from_secs(60));
+        assert!(!clock.is_paused());
+        assert_eq!(clock.take_due_ticks(now + Duration::from_secs(61)), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+// Buffs and debuffs of the stonks and of the agents. Every condition lasts until a tick, and
+// its stacking rule says what happens when it is added while another one of its kind is active.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stacking {
+    // Every application is kept and counts on its own, like drift bumps.
+    Stack,
+    // A single instance is kept, lasting until the latest end.
+    Extend,
+    // A single instance is kept, and the new one replaces it, value and end.
+    Refresh,
+}
+
+pub trait Condition: Clone {
+    fn stacking(&self) -> Stacking;
+
+    /// Whether two conditions are of the same kind, whatever their values.
+    fn same_kind(&self, other: &Self) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+// Stored as the list of (until tick, condition) it replaced, so older saves still load.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ConditionSet<T> {
+    conditions: Vec<(usize, T)>,
+}
+
+impl<T> Default for ConditionSet<T> {
+    fn default() -> Self {
+        Self { conditions: vec![] }
+    }
+}
+
+impl<T: Condition> ConditionSet<T> {
+    pub fn add(&mut self, condition: T, until_tick: usize) {
+        let existing = self
+            .conditions
+            .iter_mut()
+            .find(|(_, existing)| existing.same_kind(&condition));
+        match (condition.stacking(), existing) {
+            (Stacking::Extend, Some((existing_until_tick, _))) => {
+                *existing_until_tick = (*existing_until_tick).max(until_tick)
+            }
+            (Stacking::Refresh, Some(existing)) => *existing = (until_tick, condition),
+            _ => self.conditions.push((until_tick, condition)),
+        }
+    }
+
+    /// Drops the conditions ending by the given tick.
+    pub fn expire(&mut self, current_tick: usize) {
+        self.conditions
+            .retain(|(until_tick, _)| *until_tick > current_tick);
+    }
+
+    pub fn remove(&mut self, kind: &T) {
+        self.conditions
+            .retain(|(_, condition)| !condition.same_kind(kind));
+    }
+
+    pub fn has(&self, kind: &T) -> bool {
+        self.conditions
+            .iter()
+            .any(|(_, condition)| condition.same_kind(kind))
+    }
+
+    /// The latest tick a condition of this kind ends at.
+    pub fn until_tick(&self, kind: &T) -> Option<usize> {
+        self.conditions
+            .iter()
+            .filter(|(_, condition)| condition.same_kind(kind))
+            .map(|(until_tick, _)| *until_tick)
+            .max()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(usize, T)> {
+        self.conditions.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.conditions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.conditions.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Condition, ConditionSet, Stacking};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    enum TestCondition {
+        Stacked(u32),
+        Extended,
+        Refreshed(u32),
+    }
+
+    impl Condition for TestCondition {
+        fn stacking(&self) -> Stacking {
+            match self {
+                Self::Stacked(_) => Stacking::Stack,
+                Self::Extended => Stacking::Extend,
+                Self::Refreshed(_) => Stacking::Refresh,
+            }
+        }
+    }
+
+    #[test]
+    fn test_condition_set() {
+        let mut set = ConditionSet::default();
+        set.add(TestCondition::Stacked(1), 5);
+        set.add(TestCondition::Stacked(2), 3);
+        set.add(TestCondition::Extended, 10);
+        set.add(TestCondition::Extended, 4);
+        set.add(TestCondition::Refreshed(1), 10);
+        set.add(TestCondition::Refreshed(2), 4);
+        assert_eq!(set.len(), 4);
+        assert_eq!(set.until_tick(&TestCondition::Stacked(0)), Some(5));
+        assert_eq!(set.until_tick(&TestCondition::Extended), Some(10));
+        assert_eq!(set.iter().last(), Some(&(4, TestCondition::Refreshed(2))));
+
+        set.expire(4);
+        assert_eq!(set.len(), 2);
+        assert!(set.has(&TestCondition::Stacked(7)));
+        assert!(!set.has(&TestCondition::Refreshed(2)));
+        set.remove(&TestCondition::Extended);
+        assert!(!set.has(&TestCondition::Extended));
+
+        // The set is stored like the list of conditions it replaced.
+        let json = serde_json::to_string(&set).expect("Set should serialize");
+        assert_eq!(json, r#"[[5,{"Stacked":1}]]"#);
+        let loaded: ConditionSet<TestCondition> =
+            serde_json::from_str(&json).expect("Set should deserialize");
+        assert_eq!(loaded, set);
+    }
+}
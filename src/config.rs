@@ -0,0 +1,575 @@
+use crate::{
+    agent::INITIAL_USER_CASH,
+    events::EventRarity,
+    formatting::NumberFormat,
+    market::{DAY_LENGTH, NIGHT_LENGTH, TICKS_PER_HOUR},
+    money::Money,
+    utils::AppResult,
+};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EventsConfig {
+    // Probability that an event unlocks once its unlock condition is satisfied.
+    pub unlock_probabilities: HashMap<EventRarity, f64>,
+    // Relative weight used to pick the events shown at night among the unlocked ones.
+    pub rarity_weights: HashMap<EventRarity, f64>,
+    // Ticks before dawn from which the selected night events can no longer be changed.
+    pub selection_lock_ticks: usize,
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        Self {
+            unlock_probabilities: HashMap::from([
+                (EventRarity::Common, 0.75),
+                (EventRarity::Uncommon, 0.5),
+                (EventRarity::Rare, 0.25),
+            ]),
+            rarity_weights: HashMap::from([
+                (EventRarity::Common, 1.0),
+                (EventRarity::Uncommon, 1.0),
+                (EventRarity::Rare, 1.0),
+            ]),
+            selection_lock_ticks: TICKS_PER_HOUR,
+        }
+    }
+}
+
+impl EventsConfig {
+    pub fn unlock_probability(&self, rarity: &EventRarity) -> f64 {
+        self.unlock_probabilities
+            .get(rarity)
+            .copied()
+            .unwrap_or_else(|| Self::default().unlock_probabilities[rarity])
+    }
+
+    pub fn rarity_weight(&self, rarity: &EventRarity) -> f64 {
+        self.rarity_weights
+            .get(rarity)
+            .copied()
+            .unwrap_or_else(|| Self::default().rarity_weights[rarity])
+    }
+
+    fn validate(&self) -> AppResult<()> {
+        for (rarity, probability) in self.unlock_probabilities.iter() {
+            if !(0.0..=1.0).contains(probability) {
+                return Err(format!(
+                    "Invalid unlock probability {} for {} events, must be between 0 and 1",
+                    probability, rarity
+                )
+                .into());
+            }
+        }
+
+        for (rarity, weight) in self.rarity_weights.iter() {
+            if !weight.is_finite() || *weight < 0.0 {
+                return Err(format!(
+                    "Invalid rarity weight {} for {} events, must be a non-negative number",
+                    weight, rarity
+                )
+                .into());
+            }
+        }
+
+        if self.selection_lock_ticks > NIGHT_LENGTH {
+            return Err(format!(
+                "Invalid selection lock of {} ticks, must be at most the night length of {}",
+                self.selection_lock_ticks, NIGHT_LENGTH
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    // Seconds without key presses before a client is disconnected.
+    pub idle_timeout_seconds: u64,
+    // Seconds before the idle timeout at which the client is warned.
+    pub idle_warning_seconds: u64,
+    // Maximum number of players connected at the same time.
+    pub max_players: usize,
+    // Message of the day, shown in the login banner.
+    pub motd: String,
+    // Seconds without data from a client before a keepalive is sent.
+    pub keepalive_interval_seconds: u64,
+    // Unanswered keepalives after which the connection is dropped.
+    pub keepalive_max_missed: usize,
+    // Bytes sent to each client per second, frames over this are dropped.
+    pub max_bytes_per_second: u64,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout_seconds: 60 * 10,
+            idle_warning_seconds: 60,
+            max_players: 100,
+            motd: String::new(),
+            keepalive_interval_seconds: 15,
+            keepalive_max_missed: 3,
+            max_bytes_per_second: 256 * 1024,
+        }
+    }
+}
+
+impl SessionConfig {
+    fn validate(&self) -> AppResult<()> {
+        if self.idle_timeout_seconds == 0 {
+            return Err("Idle timeout must be positive".into());
+        }
+        if self.max_players == 0 {
+            return Err("Max players must be positive".into());
+        }
+        if self.keepalive_interval_seconds == 0 || self.keepalive_max_missed == 0 {
+            return Err("Keepalive interval and max missed keepalives must be positive".into());
+        }
+        if self.max_bytes_per_second == 0 {
+            return Err("Max bytes per second must be positive".into());
+        }
+        if self.idle_warning_seconds >= self.idle_timeout_seconds {
+            return Err(format!(
+                "Idle warning of {} seconds must be shorter than the idle timeout of {} seconds",
+                self.idle_warning_seconds, self.idle_timeout_seconds
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TradingConfig {
+    // Maximum number of buy and sell actions applied for an agent in a single tick.
+    pub max_trades_per_tick: u32,
+    // Maximum number of buy and sell actions applied for an agent in a single day.
+    pub max_trades_per_day: u32,
+    // Share of the player order flow taken by the market maker, 0 to disable it.
+    pub market_maker_share: f64,
+}
+
+impl Default for TradingConfig {
+    fn default() -> Self {
+        Self {
+            max_trades_per_tick: 10,
+            max_trades_per_day: 1000,
+            market_maker_share: 0.5,
+        }
+    }
+}
+
+impl TradingConfig {
+    fn validate(&self) -> AppResult<()> {
+        if self.max_trades_per_tick == 0 || self.max_trades_per_day == 0 {
+            return Err("Trade limits must be positive".into());
+        }
+        if !(0.0..=1.0).contains(&self.market_maker_share) {
+            return Err("Market maker share must be between 0 and 1".into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TournamentConfig {
+    // Duration of a round, by default a whole trading day.
+    pub round_ticks: usize,
+    // Cash every remaining player starts each round with, in cents.
+    pub starting_cash_cents: Money,
+    // Registered players needed to start a tournament.
+    pub min_players: usize,
+}
+
+impl Default for TournamentConfig {
+    fn default() -> Self {
+        Self {
+            round_ticks: DAY_LENGTH,
+            starting_cash_cents: INITIAL_USER_CASH,
+            min_players: 4,
+        }
+    }
+}
+
+impl TournamentConfig {
+    fn validate(&self) -> AppResult<()> {
+        if self.round_ticks == 0 || self.starting_cash_cents == Money::ZERO {
+            return Err("Tournament rounds and starting cash must be positive".into());
+        }
+        if self.min_players < 2 {
+            return Err("Tournaments need at least 2 players".into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DormancyConfig {
+    // Days without logging in after which the holdings of a registered player are sold.
+    pub liquidate_after_days: u64,
+    // Days without logging in after which a registered player is moved to the archive,
+    // from which it is restored on the next login.
+    pub archive_after_days: u64,
+}
+
+impl Default for DormancyConfig {
+    fn default() -> Self {
+        Self {
+            liquidate_after_days: 30,
+            archive_after_days: 90,
+        }
+    }
+}
+
+impl DormancyConfig {
+    fn validate(&self) -> AppResult<()> {
+        if self.liquidate_after_days == 0 {
+            return Err("Liquidation delay must be positive".into());
+        }
+        if self.archive_after_days < self.liquidate_after_days {
+            return Err(format!(
+                "Archive delay of {} days must not be shorter than the liquidation delay of {} days",
+                self.archive_after_days, self.liquidate_after_days
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BotsConfig {
+    // Trades per tick the market should see, from the players and the bots together.
+    pub target_trades_per_tick: f64,
+    // Trades per tick expected from every player online.
+    pub trades_per_player_per_tick: f64,
+    // Largest bot population, 0 disables the bots.
+    pub max_bots: usize,
+}
+
+impl Default for BotsConfig {
+    fn default() -> Self {
+        Self {
+            target_trades_per_tick: 2.0,
+            trades_per_player_per_tick: 0.25,
+            max_bots: 10,
+        }
+    }
+}
+
+impl BotsConfig {
+    fn validate(&self) -> AppResult<()> {
+        for (name, value) in [
+            ("target trades per tick", self.target_trades_per_tick),
+            (
+                "trades per player per tick",
+                self.trades_per_player_per_tick,
+            ),
+        ] {
+            if !value.is_finite() || value < 0.0 {
+                return Err(format!("Invalid bot {} {}, must be non-negative", name, value).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoadSheddingConfig {
+    // Players online above which the ticks are reduced to the essential work, 0 never reduces them.
+    pub max_players_full_detail: usize,
+    // Ticks between two updates of the portfolio rankings while the ticks are reduced.
+    pub portfolio_update_interval: usize,
+}
+
+impl Default for LoadSheddingConfig {
+    fn default() -> Self {
+        Self {
+            max_players_full_detail: 200,
+            portfolio_update_interval: 4,
+        }
+    }
+}
+
+impl LoadSheddingConfig {
+    fn validate(&self) -> AppResult<()> {
+        if self.portfolio_update_interval == 0 {
+            return Err("Invalid portfolio update interval 0, must be positive".into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PassportsConfig {
+    // Public keys of the other servers whose passports are accepted, as printed in their
+    // passports. This server always trusts its own.
+    pub trusted_issuers: Vec<String>,
+}
+
+impl PassportsConfig {
+    /// The keys of the trusted issuers.
+    pub fn trusted_keys(&self) -> AppResult<Vec<VerifyingKey>> {
+        self.trusted_issuers
+            .iter()
+            .map(|issuer| {
+                Base64UrlUnpadded::decode_vec(issuer)
+                    .ok()
+                    .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                    .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+                    .ok_or_else(|| format!("Invalid trusted passport issuer {}", issuer).into())
+            })
+            .collect()
+    }
+
+    fn validate(&self) -> AppResult<()> {
+        self.trusted_keys().map(|_| ())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    // Writes a game balance report at the end of every day, to help tuning the game.
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UsernamesConfig {
+    // Usernames that cannot be registered, compared ignoring case.
+    pub reserved: Vec<String>,
+    // Patterns of usernames that cannot be registered, compared ignoring case.
+    // `*` matches any sequence of characters and `?` any single character.
+    pub blocked_patterns: Vec<String>,
+}
+
+impl Default for UsernamesConfig {
+    fn default() -> Self {
+        Self {
+            reserved: vec!["admin".into(), "guest".into(), "system".into()],
+            blocked_patterns: vec![],
+        }
+    }
+}
+
+impl UsernamesConfig {
+    /// Checks a new username against the reserved names, the blocklist and
+    /// lookalikes of the protected usernames.
+    pub fn check<'a>(
+        &self,
+        username: &str,
+        protected: impl IntoIterator<Item = &'a str>,
+    ) -> AppResult<()> {
+        if self
+            .reserved
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(username))
+        {
+            return Err("This username is reserved.".into());
+        }
+        if self
+            .blocked_patterns
+            .iter()
+            .any(|pattern| matches_pattern(pattern, username))
+        {
+            return Err("This username is not allowed.".into());
+        }
+        let skeleton = confusable_skeleton(username);
+        if protected
+            .into_iter()
+            .any(|name| name != username && confusable_skeleton(name) == skeleton)
+        {
+            return Err("This username is too similar to the one of a top player.".into());
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> AppResult<()> {
+        if self
+            .blocked_patterns
+            .iter()
+            .any(|pattern| pattern.is_empty())
+        {
+            return Err("Blocked username patterns must not be empty".into());
+        }
+        Ok(())
+    }
+}
+
+/// Case insensitive glob matching, supporting `*` and `?` wildcards.
+fn matches_pattern(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.to_lowercase().chars().collect::<Vec<char>>();
+    let text = text.to_lowercase().chars().collect::<Vec<char>>();
+
+    // Position of the last `*` in the pattern and of the text when it was reached.
+    let mut backtrack: Option<(usize, usize)> = None;
+    let (mut p, mut t) = (0, 0);
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, star_t)) = backtrack {
+            // Let the last `*` swallow one more character.
+            p = star + 1;
+            t = star_t + 1;
+            backtrack = Some((star, t));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Maps a username to a canonical form shared by names that look alike.
+fn confusable_skeleton(username: &str) -> String {
+    let skeleton = username
+        .to_lowercase()
+        .chars()
+        .filter(|c| !matches!(c, '_' | '-' | '.'))
+        .map(|c| match c {
+            '0' => 'o',
+            '1' | 'i' | '|' | '!' => 'l',
+            '3' => 'e',
+            '4' | '@' => 'a',
+            '5' | '$' => 's',
+            '7' => 't',
+            '8' => 'b',
+            c => c,
+        })
+        .collect::<String>();
+    skeleton.replace("rn", "m").replace("vv", "w")
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub events: EventsConfig,
+    pub session: SessionConfig,
+    pub usernames: UsernamesConfig,
+    pub trading: TradingConfig,
+    pub dormancy: DormancyConfig,
+    pub tournament: TournamentConfig,
+    pub telemetry: TelemetryConfig,
+    pub bots: BotsConfig,
+    pub passports: PassportsConfig,
+    pub load_shedding: LoadSheddingConfig,
+    // Locale and precision of the amounts of money shown to the players.
+    pub formatting: NumberFormat,
+    // Usernames allowed to control the game clock over SSH exec.
+    pub admins: Vec<String>,
+}
+
+impl ServerConfig {
+    pub fn validate(&self) -> AppResult<()> {
+        self.events.validate()?;
+        self.session.validate()?;
+        self.usernames.validate()?;
+        self.trading.validate()?;
+        self.dormancy.validate()?;
+        self.tournament.validate()?;
+        self.bots.validate()?;
+        self.passports.validate()?;
+        self.load_shedding.validate()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{matches_pattern, ServerConfig, UsernamesConfig};
+    use crate::{events::EventRarity, market::NIGHT_LENGTH};
+
+    #[test]
+    fn test_validate_config() {
+        let mut config = ServerConfig::default();
+        assert!(config.validate().is_ok());
+
+        config
+            .events
+            .unlock_probabilities
+            .insert(EventRarity::Rare, 1.5);
+        assert!(config.validate().is_err());
+
+        config
+            .events
+            .unlock_probabilities
+            .remove(&EventRarity::Rare);
+        assert_eq!(config.events.unlock_probability(&EventRarity::Rare), 0.25);
+
+        config
+            .events
+            .rarity_weights
+            .insert(EventRarity::Common, -1.0);
+        assert!(config.validate().is_err());
+
+        let mut config = ServerConfig::default();
+        config.events.selection_lock_ticks = NIGHT_LENGTH + 1;
+        assert!(config.validate().is_err());
+
+        let mut config = ServerConfig::default();
+        config.session.idle_warning_seconds = config.session.idle_timeout_seconds;
+        assert!(config.validate().is_err());
+
+        let mut config = ServerConfig::default();
+        config.session.keepalive_max_missed = 0;
+        assert!(config.validate().is_err());
+
+        let mut config = ServerConfig::default();
+        config.trading.max_trades_per_tick = 0;
+        assert!(config.validate().is_err());
+
+        let mut config = ServerConfig::default();
+        config.passports.trusted_issuers = vec!["not a key".to_string()];
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_usernames_config() {
+        let config = UsernamesConfig {
+            reserved: vec!["admin".into()],
+            blocked_patterns: vec!["*badword*".into(), "mod?".into()],
+        };
+        assert!(config.check("player", []).is_ok());
+        assert!(config.check("Admin", []).is_err());
+        assert!(config.check("xBadWordx", []).is_err());
+        assert!(config.check("badword", []).is_err());
+        assert!(config.check("mods", []).is_err());
+        assert!(config.check("modest", []).is_ok());
+
+        assert!(config.check("r1chie", ["richie"]).is_err());
+        assert!(config.check("W0RM_", ["worm"]).is_err());
+        assert!(config.check("richie", ["richie"]).is_ok());
+        assert!(config.check("richard", ["richie"]).is_ok());
+
+        assert!(matches_pattern("a*b*c", "aXXbYYc"));
+        assert!(!matches_pattern("a*b*c", "aXXbYY"));
+        assert!(matches_pattern("*", ""));
+    }
+
+    #[test]
+    fn test_partial_config() {
+        let config: ServerConfig =
+            serde_json::from_str(r#"{"events": {"rarity_weights": {"Rare": 5.0}}}"#)
+                .expect("Failed to parse config");
+        assert!(config.validate().is_ok());
+        assert_eq!(config.events.rarity_weight(&EventRarity::Rare), 5.0);
+        assert_eq!(config.events.rarity_weight(&EventRarity::Common), 1.0);
+    }
+}
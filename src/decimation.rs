@@ -0,0 +1,67 @@
+// Reduces the points of a chart to what the terminal can show, with the largest-triangle-three-
+// buckets algorithm (Steinarsson, 2013): the points are split in buckets, and the point of each
+// bucket forming the largest triangle with the point kept before it and the average of the next
+// bucket is kept. Unlike averaging, this keeps the peaks and crashes of the price visible.
+
+/// Keeps at most `threshold` points of the data, always including the first and the last one.
+pub fn lttb(data: &[(f64, f64)], threshold: usize) -> Vec<(f64, f64)> {
+    if threshold >= data.len() || threshold < 3 {
+        return data.to_vec();
+    }
+
+    let mut sampled = Vec::with_capacity(threshold);
+    // The first and last points are kept, the others are split in threshold - 2 buckets.
+    let bucket_size = (data.len() - 2) as f64 / (threshold - 2) as f64;
+    let bucket_start = |bucket: usize| (bucket as f64 * bucket_size) as usize + 1;
+
+    let mut kept = 0;
+    sampled.push(data[kept]);
+    for bucket in 0..threshold - 2 {
+        let next_bucket = &data[bucket_start(bucket + 1)..bucket_start(bucket + 2).min(data.len())];
+        let (average_x, average_y) = next_bucket
+            .iter()
+            .fold((0.0, 0.0), |(sum_x, sum_y), (x, y)| (sum_x + x, sum_y + y));
+        let (average_x, average_y) = (
+            average_x / next_bucket.len() as f64,
+            average_y / next_bucket.len() as f64,
+        );
+
+        let (kept_x, kept_y) = data[kept];
+        kept = (bucket_start(bucket)..bucket_start(bucket + 1))
+            .max_by(|&a, &b| {
+                let area = |index: usize| {
+                    let (x, y) = data[index];
+                    ((kept_x - average_x) * (y - kept_y) - (kept_x - x) * (average_y - kept_y))
+                        .abs()
+                };
+                area(a).total_cmp(&area(b))
+            })
+            .unwrap_or(kept);
+        sampled.push(data[kept]);
+    }
+    sampled.push(data[data.len() - 1]);
+    sampled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::lttb;
+
+    #[test]
+    fn test_lttb() {
+        let data = (0..1_000)
+            .map(|x| (x as f64, (x % 100) as f64))
+            .collect::<Vec<(f64, f64)>>();
+
+        let sampled = lttb(&data, 50);
+        assert_eq!(sampled.len(), 50);
+        assert_eq!(sampled.first(), data.first());
+        assert_eq!(sampled.last(), data.last());
+        assert!(sampled.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        // The peaks of the saw survive, while averaging would flatten them around 50.
+        assert!(sampled.iter().filter(|(_, y)| *y >= 90.0).count() >= 5);
+
+        assert_eq!(lttb(&data[..10], 50), data[..10].to_vec());
+        assert_eq!(lttb(&data, 2), data);
+    }
+}
@@ -0,0 +1,173 @@
+// Composable effects of the night events. An event lists its effects as plain data, and they
+// are applied together: either the agent and the treasury can afford all of them, or none is.
+
+use crate::{
+    agent::{AgentCondition, DecisionAgent},
+    audit::{self, AuditEvent},
+    market::Market,
+    money::Money,
+    stonk::{StonkClass, StonkCondition},
+    utils::AppResult,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Effect {
+    AddCash(Money),
+    // Paid into the market treasury.
+    PayCash(Money),
+    // Paid to the agent out of the market treasury.
+    TreasuryPayout(Money),
+    // Conditions last for the given number of ticks.
+    AgentCondition {
+        condition: AgentCondition,
+        ticks: usize,
+    },
+    ClassCondition {
+        class: StonkClass,
+        condition: StonkCondition,
+        ticks: usize,
+    },
+    AllStonksCondition {
+        condition: StonkCondition,
+        ticks: usize,
+    },
+    // Only the stonks the agent owns shares of.
+    OwnedStonksCondition {
+        condition: StonkCondition,
+        ticks: usize,
+    },
+}
+
+impl Market {
+    /// Applies all the effects, or none of them if the agent or the treasury cannot afford them.
+    pub fn apply_effects<A: DecisionAgent>(
+        &mut self,
+        agent: &mut A,
+        effects: &[Effect],
+    ) -> AppResult<()> {
+        let mut cost = Money::ZERO;
+        let mut income = Money::ZERO;
+        let mut payout = Money::ZERO;
+        for effect in effects.iter() {
+            match effect {
+                Effect::AddCash(amount) => income = income.saturating_add(*amount),
+                Effect::PayCash(amount) => {
+                    cost = cost.saturating_add(self.event_cost(agent.username(), *amount))
+                }
+                Effect::TreasuryPayout(amount) => payout = payout.saturating_add(*amount),
+                _ => {}
+            }
+        }
+        if agent.cash() < cost {
+            return Err("Not enough cash".into());
+        }
+        if self.treasury.balance() < payout {
+            return Err("The treasury cannot afford it".into());
+        }
+        agent
+            .cash()
+            .checked_add(income)
+            .and_then(|cash| cash.checked_add(payout))
+            .ok_or("Overflow")?;
+
+        for effect in effects.iter() {
+            match effect {
+                Effect::AddCash(amount) => {
+                    agent.add_cash(*amount)?;
+                    audit::record(AuditEvent::CashPrinted {
+                        username: agent.username().to_string(),
+                        amount_cents: *amount,
+                    });
+                }
+                Effect::PayCash(amount) => {
+                    let amount = self.event_cost(agent.username(), *amount);
+                    agent.sub_cash(amount)?;
+                    self.treasury.deposit(amount);
+                }
+                Effect::TreasuryPayout(amount) => {
+                    self.treasury.withdraw(*amount)?;
+                    agent.add_cash(*amount)?;
+                }
+                Effect::AgentCondition { condition, ticks } => {
+                    agent.add_condition(*condition, self.last_tick + ticks)
+                }
+                Effect::ClassCondition {
+                    class,
+                    condition,
+                    ticks,
+                } => {
+                    for stonk in self.stonks.iter_mut().filter(|s| s.class == *class) {
+                        stonk.add_condition(*condition, self.last_tick + ticks);
+                    }
+                }
+                Effect::AllStonksCondition { condition, ticks } => {
+                    for stonk in self.stonks.iter_mut() {
+                        stonk.add_condition(*condition, self.last_tick + ticks);
+                    }
+                }
+                Effect::OwnedStonksCondition { condition, ticks } => {
+                    for stonk in self
+                        .stonks
+                        .iter_mut()
+                        .filter(|s| agent.owned_stonks()[s.id] > 0)
+                    {
+                        stonk.add_condition(*condition, self.last_tick + ticks);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Effect;
+    use crate::{
+        agent::{AgentCondition, DecisionAgent, UserAgent},
+        market::{Market, DAY_LENGTH},
+        money::Money,
+        ssh_client::SessionAuth,
+        stonk::StonkCondition,
+        utils::AppResult,
+    };
+
+    #[test]
+    fn test_effects_are_applied_atomically() -> AppResult<()> {
+        let mut market = Market::new();
+        let mut agent = UserAgent::new(SessionAuth::new("agent".to_string(), [0; 32]));
+        agent.add_stonk(0, 10)?;
+        let cash = agent.cash();
+
+        // The payout cannot be afforded, so the cash is not added either.
+        let effects = vec![
+            Effect::AddCash(Money::from_dollars(100)),
+            Effect::TreasuryPayout(Money::from_dollars(100)),
+        ];
+        assert!(market.apply_effects(&mut agent, &effects).is_err());
+        assert_eq!(agent.cash(), cash);
+        assert!(market.treasury.balance() == Money::ZERO);
+
+        let effects = vec![
+            Effect::AddCash(Money::from_dollars(5_000)),
+            Effect::PayCash(cash),
+            Effect::AgentCondition {
+                condition: AgentCondition::Audited,
+                ticks: DAY_LENGTH,
+            },
+            Effect::OwnedStonksCondition {
+                condition: StonkCondition::IncreasedShockProbability,
+                ticks: DAY_LENGTH,
+            },
+        ];
+        market.apply_effects(&mut agent, &effects)?;
+        assert_eq!(agent.cash(), Money::from_dollars(5_000));
+        assert_eq!(market.treasury.balance(), cash);
+        assert!(agent.has_condition(AgentCondition::Audited));
+        assert!(market.stonks[0].has_condition(StonkCondition::IncreasedShockProbability));
+        assert!(!market.stonks[1].has_condition(StonkCondition::IncreasedShockProbability));
+
+        Ok(())
+    }
+}
@@ -1,18 +1,126 @@
 use crate::{
-    agent::{AgentAction, DecisionAgent},
+    agent::{
+        AgentAction, AgentCondition, DecisionAgent, AUDIT_DAYS, INITIAL_USER_CASH,
+        LOW_REPUTATION_THRESHOLD,
+    },
+    config::EventsConfig,
+    effects::Effect,
     market::{Market, DAY_LENGTH},
-    stonk::{DollarValue, Stonk, StonkClass},
+    money::Money,
+    stonk::{DollarValue, StonkClass, StonkCondition},
+    unlock::UnlockCondition,
 };
-use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 use strum::Display;
 use strum_macros::EnumIter;
 
-pub const CHARACTER_ASSASSINATION_COST: u32 = 5_000 * 100;
-pub const MARKET_CRASH_COST: u32 = 50_000 * 100;
-const MARKET_CRASH_PREREQUISITE: u32 = 100_000 * 100;
+pub const CHARACTER_ASSASSINATION_COST: Money = Money::from_dollars(5_000);
+// Ticks the target of a character assassination has to react before the crash hits.
+pub const ASSASSINATION_WARNING_TICKS: usize = DAY_LENGTH / 2;
+pub const PROTECTION_COST: Money = Money::from_dollars(2_500);
+// Famous agents get the story killed for a fraction of the protection money.
+pub const FAMOUS_PROTECTION_DISCOUNT: f64 = 0.5;
+// Fraction of the sell value lost when dumping all holdings to dodge an assassination.
+pub const DUMP_HOLDINGS_PENALTY: f64 = 0.2;
+pub const CALL_BLUFF_SUCCESS_PROBABILITY: f64 = 0.5;
+// Crash multiplier applied when calling the bluff fails.
+pub const CALL_BLUFF_FAILURE_MULTIPLIER: f64 = 2.0;
+// Choices offered to the target of a character assassination, in display order.
+pub const ASSASSINATION_DEFENSES: [AgentAction; 3] = [
+    AgentAction::PayProtection,
+    AgentAction::DumpHoldings,
+    AgentAction::CallBluff,
+];
+
+/// Protection money paid by the agent for each threat.
+pub fn protection_cost<A: DecisionAgent>(agent: &A) -> Money {
+    if agent.has_condition(AgentCondition::Famous) {
+        PROTECTION_COST.scale(1.0 - FAMOUS_PROTECTION_DISCOUNT)
+    } else {
+        PROTECTION_COST
+    }
+}
+
+pub fn assassination_defense_description<A: DecisionAgent>(
+    action: &AgentAction,
+    agent: &A,
+) -> String {
+    match action {
+        AgentAction::PayProtection => format!(
+            "Pay {} of protection money and the story never runs.",
+            protection_cost(agent).format_dollars()
+        ),
+        AgentAction::DumpHoldings => format!(
+            "Dump all your holdings now, losing {}% of their value.",
+            (DUMP_HOLDINGS_PENALTY * 100.0) as u32
+        ),
+        AgentAction::CallBluff => format!(
+            "Call the bluff: {}% chance nothing happens, otherwise the crash hits {}x harder.",
+            (CALL_BLUFF_SUCCESS_PROBABILITY * 100.0) as u32,
+            CALL_BLUFF_FAILURE_MULTIPLIER
+        ),
+        _ => String::new(),
+    }
+}
+// Average stake in Media stonks, in percent, giving early warning of the hostile events
+// selected during the night.
+pub const INTEL_MEDIA_STAKE_PERCENT: f64 = 2.0;
+
+/// A hostile event selected for the next morning. The attacker stays anonymous.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HostileIntel {
+    MarketCrash,
+    CharacterAssassination { target: String },
+}
+
+impl HostileIntel {
+    pub fn from_action(action: &AgentAction) -> Option<Self> {
+        match action {
+            AgentAction::CrashAll => Some(Self::MarketCrash),
+            AgentAction::CrashAgentStonks { username } => Some(Self::CharacterAssassination {
+                target: username.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn description(&self) -> String {
+        match self {
+            Self::MarketCrash => "a market crash".to_string(),
+            Self::CharacterAssassination { target } => {
+                format!("a character assassination of {}", target)
+            }
+        }
+    }
+}
+
+pub const MARKET_CRASH_COST: Money = Money::from_dollars(50_000);
+const MARKET_CRASH_PREREQUISITE: Money = Money::from_dollars(100_000);
 pub const DIVIDEND_PAYOUT: f64 = 0.1;
+pub const STIMULUS_AMOUNT: Money = Money::from_dollars(500);
+// Stimulus checks are only sent when the treasury holds this many of them.
+const STIMULUS_TREASURY_MULTIPLE: u64 = 20;
+pub const BAILOUT_AMOUNT: Money = Money::from_dollars(5_000);
+const LUCKY_NIGHT_AMOUNT: Money = Money::from_dollars(100);
+pub const BRIBE_AMOUNT: Money = Money::from_dollars(10_000);
+pub const SHADY_LOAN_AMOUNT: Money = Money::from_dollars(5_000);
+// Drift bump of the first class bump of the day. Each further bump of the same class is
+// worth CLASS_BUMP_DIMINISHING_FACTOR times the previous one, up to MAX_CLASS_BUMP_PER_DAY.
+pub const CLASS_BUMP: f64 = 4.0;
+pub const CLASS_BUMP_DIMINISHING_FACTOR: f64 = 0.6;
+pub const MAX_CLASS_BUMP_PER_DAY: f64 = 8.0;
+
+// Net worth needed to get each action point after the first one.
+pub const NIGHT_ACTION_POINT_THRESHOLDS: [Money; 2] =
+    [Money::from_dollars(100_000), Money::from_dollars(1_000_000)];
+
+pub fn night_action_points(net_worth: Money) -> usize {
+    1 + NIGHT_ACTION_POINT_THRESHOLDS
+        .iter()
+        .filter(|threshold| net_worth >= **threshold)
+        .count()
+}
 
 #[derive(Debug, Clone, EnumIter, Display, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum EventRarity {
@@ -33,6 +141,9 @@ pub enum NightEvent {
     AGoodOffer,
     LuckyNight,
     ReceiveDividends { stonk_id: usize },
+    Stimulus,
+    Bailout,
+    ShadyLoan,
 }
 
 impl Display for NightEvent {
@@ -48,16 +159,19 @@ impl Display for NightEvent {
             Self::AGoodOffer => write!(f, "A good offer"),
             Self::LuckyNight => write!(f, "Lucky night"),
             Self::ReceiveDividends { .. } => write!(f, "Receive dividends"),
+            Self::Stimulus => write!(f, "Stimulus"),
+            Self::Bailout => write!(f, "Bailout"),
+            Self::ShadyLoan => write!(f, "Shady loan"),
         }
     }
 }
 
 impl NightEvent {
-    fn unlock_probability(&self) -> f64 {
+    pub fn action_points(&self) -> usize {
         match self.rarity() {
-            EventRarity::Common => 0.75,
-            EventRarity::Uncommon => 0.5,
-            EventRarity::Rare => 0.25,
+            EventRarity::Common => 1,
+            EventRarity::Uncommon => 1,
+            EventRarity::Rare => 2,
         }
     }
 
@@ -73,6 +187,9 @@ impl NightEvent {
             Self::AGoodOffer => EventRarity::Common,
             Self::LuckyNight => EventRarity::Common,
             Self::ReceiveDividends { .. } => EventRarity::Common,
+            Self::Stimulus => EventRarity::Common,
+            Self::Bailout => EventRarity::Uncommon,
+            Self::ShadyLoan => EventRarity::Uncommon,
         }
     }
 
@@ -124,45 +241,52 @@ impl NightEvent {
             }
             Self::AGoodOffer => vec![
                 "An offer you can't refuse".to_string(),
-                "they say. Get $10000,".to_string(),
+                format!("they say. Get {},", BRIBE_AMOUNT.format_dollars()),
                 "pay later (maybe).".to_string(),
             ],
             Self::LuckyNight => vec![
-                "You've found $100 ".to_string(),
+                format!("You've found {}", LUCKY_NIGHT_AMOUNT.format_dollars()),
                 "on the ground.".to_string(),
                 "Che culo!".to_string(),
             ],
             Self::ReceiveDividends { stonk_id } => {
                 let stonk = &market.stonks[*stonk_id];
 
-                let yesterday_opening_price =
-                    stonk.historical_prices[stonk.historical_prices.len() - DAY_LENGTH];
-                let yesterday_closing_price =
-                    stonk.historical_prices[stonk.historical_prices.len() - 1];
-
-                if yesterday_opening_price >= yesterday_closing_price
-                    || yesterday_opening_price == 0
-                {
-                    return vec!["No divindend, this shouldn't happen".to_string()];
-                }
-
-                let yesterday_gain = (yesterday_closing_price - yesterday_opening_price) as f64
-                    / yesterday_opening_price as f64;
-
-                let dividend = ((agent.owned_stonks()[*stonk_id] * stonk.current_unit_price_cents())
-                    as f64
-                    * DIVIDEND_PAYOUT
-                    * yesterday_gain) as u32;
+                let Some(dividend) = market
+                    .last_closed_day_start_tick()
+                    .and_then(|tick| stonk.dividend(agent.owned_stonks()[*stonk_id], tick))
+                else {
+                    return vec![format!("{} has no dividend to pay.", stonk.name)];
+                };
                 vec![
                     format!("{} is paying", stonk.name),
                     format!("dividends, you will get",),
-                    format!("${}.", dividend.format()),
+                    format!("{}.", dividend.format_dollars()),
                 ]
             }
+            Self::Stimulus => vec![
+                "The treasury is overflowing".to_string(),
+                "and it's election year.".to_string(),
+                format!("Here's {}, go spend it.", STIMULUS_AMOUNT.format_dollars()),
+            ],
+            Self::Bailout => vec![
+                "Too big to fail, or at".to_string(),
+                "least too loud. The".to_string(),
+                format!("treasury offers {}.", BAILOUT_AMOUNT.format_dollars()),
+            ],
+            Self::ShadyLoan => vec![
+                "A friend of a friend".to_string(),
+                format!(
+                    "lends you {}. Word gets",
+                    SHADY_LOAN_AMOUNT.format_dollars()
+                ),
+                "around, and your stonks".to_string(),
+                "get nervous.".to_string(),
+            ],
         };
 
         let unlock_description = self.unlock_condition_description();
-        if unlock_description.len() > 0 {
+        if !unlock_description.is_empty() {
             description.push("".to_string());
             description.push("Unlock Condition:".to_string());
             for l in unlock_description.iter() {
@@ -170,183 +294,97 @@ impl NightEvent {
             }
         }
 
-        let cost_description = self.cost_description();
-        if cost_description.len() > 0 {
+        if let Some(cost) = self.cost() {
             description.push("".to_string());
             description.push("Cost:".to_string());
-            for l in cost_description.iter() {
-                description.push(l.clone());
+            let agent_cost = market.event_cost(agent.username(), cost);
+            description.push(agent_cost.format_dollars());
+            if agent_cost > cost {
+                description.push(format!(
+                    "Wealth surcharge: +{:.0}%",
+                    (agent_cost.as_f64_cents() / cost.as_f64_cents() - 1.0) * 100.0
+                ));
             }
         }
 
+        description.push("".to_string());
+        description.push(format!("Action points: {}", self.action_points()));
+
         description
     }
 
-    pub fn unlock_condition(&self) -> Box<dyn Fn(&dyn DecisionAgent, &Market) -> bool> {
-        let unlock_probability = self.unlock_probability();
+    pub fn unlock_condition(&self, config: &EventsConfig) -> UnlockCondition {
+        let condition = match self {
+            Self::War => UnlockCondition::StakeInClass {
+                class: StonkClass::War,
+                percent: 1.0,
+            },
+            Self::ColdWinter => UnlockCondition::StakeInClass {
+                class: StonkClass::Commodity,
+                percent: 1.0,
+            },
+            Self::RoyalScandal => UnlockCondition::StakeInClass {
+                class: StonkClass::Media,
+                percent: 1.0,
+            },
+            Self::PurpleBlockchain => UnlockCondition::StakeInClass {
+                class: StonkClass::Technology,
+                percent: 1.0,
+            },
+            Self::MarketCrash => UnlockCondition::CashAtLeast(MARKET_CRASH_PREREQUISITE),
+            // Riccardino.
+            Self::UltraVision => UnlockCondition::StakeInStonk {
+                stonk_id: 3,
+                percent: 10.0,
+            },
+            Self::CharacterAssassination { username, .. } => UnlockCondition::And(vec![
+                UnlockCondition::Not(Box::new(UnlockCondition::IsAgent(username.clone()))),
+                UnlockCondition::CashAtLeast(CHARACTER_ASSASSINATION_COST),
+            ]),
+            Self::AGoodOffer => UnlockCondition::And(vec![
+                UnlockCondition::Not(Box::new(UnlockCondition::PastAction(
+                    AgentAction::AcceptBribe,
+                ))),
+                UnlockCondition::ReputationAtMost(LOW_REPUTATION_THRESHOLD),
+                UnlockCondition::CashBelow(Money::from_dollars(1_000)),
+            ]),
+            Self::LuckyNight => UnlockCondition::CashBelow(Money::from_dollars(2_000)),
+            Self::Stimulus => UnlockCondition::TreasuryAtLeast(
+                STIMULUS_AMOUNT.saturating_mul(STIMULUS_TREASURY_MULTIPLE),
+            ),
+            Self::Bailout => UnlockCondition::And(vec![
+                UnlockCondition::TreasuryAtLeast(BAILOUT_AMOUNT),
+                UnlockCondition::CashBelow(BAILOUT_AMOUNT),
+                UnlockCondition::NetWorthBelow(INITIAL_USER_CASH.percent(50)),
+            ]),
+            Self::ShadyLoan => UnlockCondition::CashBelow(SHADY_LOAN_AMOUNT),
+            Self::ReceiveDividends { stonk_id } => UnlockCondition::StonkGained {
+                stonk_id: *stonk_id,
+            },
+        };
+        // The draw comes last, so that it only happens when the rest holds.
+        UnlockCondition::And(vec![
+            condition,
+            UnlockCondition::Probability(config.unlock_probability(&self.rarity())),
+        ])
+    }
 
+    /// Base cost of the event, before the wealth surcharge.
+    pub fn cost(&self) -> Option<Money> {
         match self {
-            Self::War => Box::new(move |agent, market| {
-                let war_stonks = market
-                    .stonks
-                    .iter()
-                    .filter(|s| s.class == StonkClass::War)
-                    .collect::<Vec<&Stonk>>();
-
-                war_stonks
-                    .iter()
-                    .map(|s| 100.0 * s.to_stake(agent.owned_stonks()[s.id]))
-                    .sum::<f64>()
-                    / war_stonks.len() as f64
-                    >= 1.0
-                    && {
-                        let rng = &mut rand::thread_rng();
-                        rng.gen_bool(unlock_probability)
-                    }
-            }),
-            Self::ColdWinter => Box::new(move |agent, market| {
-                let commodity_stonks = market
-                    .stonks
-                    .iter()
-                    .filter(|s| s.class == StonkClass::Commodity)
-                    .collect::<Vec<&Stonk>>();
-
-                commodity_stonks
-                    .iter()
-                    .map(|s| 100.0 * s.to_stake(agent.owned_stonks()[s.id]))
-                    .sum::<f64>()
-                    / commodity_stonks.len() as f64
-                    >= 1.0
-                    && {
-                        let rng = &mut rand::thread_rng();
-                        rng.gen_bool(unlock_probability)
-                    }
-            }),
-            Self::RoyalScandal => Box::new(move |agent, market| {
-                let media_stonks = market
-                    .stonks
-                    .iter()
-                    .filter(|s| s.class == StonkClass::Media)
-                    .collect::<Vec<&Stonk>>();
-
-                media_stonks
-                    .iter()
-                    .map(|s| 100.0 * s.to_stake(agent.owned_stonks()[s.id]))
-                    .sum::<f64>()
-                    / media_stonks.len() as f64
-                    >= 1.0
-                    && {
-                        let rng = &mut rand::thread_rng();
-                        rng.gen_bool(unlock_probability)
-                    }
-            }),
-            Self::PurpleBlockchain => Box::new(move |agent, market| {
-                let tech_stonks = market
-                    .stonks
-                    .iter()
-                    .filter(|s| s.class == StonkClass::Technology)
-                    .collect::<Vec<&Stonk>>();
-
-                tech_stonks
-                    .iter()
-                    .map(|s| 100.0 * s.to_stake(agent.owned_stonks()[s.id]))
-                    .sum::<f64>()
-                    / tech_stonks.len() as f64
-                    >= 1.0
-                    && {
-                        let rng = &mut rand::thread_rng();
-                        rng.gen_bool(unlock_probability)
-                    }
-            }),
-            Self::MarketCrash => Box::new(move |agent, _| {
-                agent.cash() >= MARKET_CRASH_PREREQUISITE && {
-                    let rng = &mut rand::thread_rng();
-                    rng.gen_bool(unlock_probability)
-                }
-            }),
-            Self::UltraVision => Box::new(move |agent, market| {
-                let riccardino_id = 3;
-                let riccardino = &market.stonks[riccardino_id];
-                100.0 * riccardino.to_stake(agent.owned_stonks()[riccardino_id]) >= 10.0 && {
-                    let rng = &mut rand::thread_rng();
-                    rng.gen_bool(unlock_probability)
-                }
-            }),
-            Self::CharacterAssassination { username, .. } => {
-                let username = username.clone();
-                Box::new(move |agent, _| {
-                    // let has_any_large_stake = agent_stonks
-                    //     .iter()
-                    //     .enumerate()
-                    //     .map(|(stonk_id, &amount)| 100.0 * market.stonks[stonk_id].to_stake(amount))
-                    //     .any(|s| s > 5.0);
-                    username != agent.username() && agent.cash() > CHARACTER_ASSASSINATION_COST && {
-                        let rng = &mut rand::thread_rng();
-                        rng.gen_bool(unlock_probability)
-                    }
-                    // && has_any_large_stake
-                })
-            }
-            Self::AGoodOffer => Box::new(move |agent, _| {
-                agent
-                    .past_selected_actions()
-                    .get(&AgentAction::AcceptBribe.to_string())
-                    .is_none()
-                    && agent.cash() < 1_000 * 100
-                    && {
-                        let rng = &mut rand::thread_rng();
-                        rng.gen_bool(unlock_probability)
-                    }
-            }),
-            Self::LuckyNight => Box::new(move |agent, _| {
-                agent.cash() < 2_000 * 100 && {
-                    let rng = &mut rand::thread_rng();
-                    rng.gen_bool(unlock_probability)
-                }
-            }),
-            Self::ReceiveDividends { stonk_id } => {
-                let stonk_id = stonk_id.clone();
-                Box::new(move |agent, market| {
-                    if agent.owned_stonks()[stonk_id] == 0 {
-                        return false;
-                    }
-                    let stonk = &market.stonks[stonk_id];
-
-                    if stonk.current_unit_price_cents() == 0 {
-                        return false;
-                    }
-
-                    let yesterday_opening_price =
-                        stonk.historical_prices[stonk.historical_prices.len() - DAY_LENGTH];
-                    let yesterday_closing_price =
-                        stonk.historical_prices[stonk.historical_prices.len() - 1];
-
-                    if yesterday_opening_price >= yesterday_closing_price
-                        || yesterday_opening_price == 0
-                    {
-                        return false;
-                    }
-
-                    let rng = &mut rand::thread_rng();
-                    rng.gen_bool(unlock_probability)
-                })
-            }
+            Self::MarketCrash => Some(MARKET_CRASH_COST),
+            Self::CharacterAssassination { .. } => Some(CHARACTER_ASSASSINATION_COST),
+            _ => None,
         }
     }
 
-    fn cost_description(&self) -> Vec<String> {
-        match self {
-            Self::MarketCrash => {
-                vec![format!("${}", MARKET_CRASH_COST / 100)]
-            }
-            Self::CharacterAssassination { .. } => {
-                vec![format!("${}", CHARACTER_ASSASSINATION_COST / 100)]
-            }
-            _ => vec![],
-        }
+    pub fn cost_description(&self) -> Vec<String> {
+        self.cost()
+            .map(|cost| vec![cost.format_dollars()])
+            .unwrap_or_default()
     }
 
-    fn unlock_condition_description(&self) -> Vec<String> {
+    pub fn unlock_condition_description(&self) -> Vec<String> {
         match self {
             Self::War => vec![
                 "Average share in".to_string(),
@@ -364,17 +402,38 @@ impl NightEvent {
                 "Average share in".to_string(),
                 "Technology stonks >= 1%".to_string(),
             ],
-            Self::MarketCrash => vec![format!("Cash >= ${MARKET_CRASH_PREREQUISITE}")],
+            Self::MarketCrash => vec![format!(
+                "Cash >= {}",
+                MARKET_CRASH_PREREQUISITE.format_dollars()
+            )],
             Self::UltraVision => vec!["Riccardino share >= 10%".to_string()],
             Self::CharacterAssassination { username, .. } => vec![
                 format!("{username} took a special offer"),
                 "in the past and got too".to_string(),
                 "greedy now;".to_string(),
-                format!("Cash >= ${}", CHARACTER_ASSASSINATION_COST / 100),
+                format!("Cash >= {}", CHARACTER_ASSASSINATION_COST.format_dollars()),
+            ],
+            Self::AGoodOffer => vec![
+                "Happens only once".to_string(),
+                format!("Reputation <= {LOW_REPUTATION_THRESHOLD}"),
             ],
-            Self::AGoodOffer => vec!["Happens only once".to_string()],
             Self::LuckyNight => vec!["Got lucky ;)".to_string()],
             Self::ReceiveDividends { .. } => vec!["Stonk price increased.".to_string()],
+            Self::Stimulus => vec![format!(
+                "Treasury >= {}",
+                STIMULUS_AMOUNT
+                    .saturating_mul(STIMULUS_TREASURY_MULTIPLE)
+                    .format_dollars()
+            )],
+            Self::Bailout => vec![
+                format!("Cash < {}", BAILOUT_AMOUNT.format_dollars()),
+                format!(
+                    "Net worth < {}",
+                    INITIAL_USER_CASH.percent(50).format_dollars()
+                ),
+                format!("Treasury >= {}", BAILOUT_AMOUNT.format_dollars()),
+            ],
+            Self::ShadyLoan => vec![format!("Cash < {}", SHADY_LOAN_AMOUNT.format_dollars())],
         }
     }
 
@@ -398,10 +457,71 @@ impl NightEvent {
                 username: username.to_string(),
             },
             Self::AGoodOffer => AgentAction::AcceptBribe,
-            Self::LuckyNight => AgentAction::AddCash { amount: 100 * 100 },
+            Self::LuckyNight => AgentAction::AddCash {
+                amount: LUCKY_NIGHT_AMOUNT,
+            },
             Self::ReceiveDividends { stonk_id } => AgentAction::GetDividends {
                 stonk_id: *stonk_id,
             },
+            Self::Stimulus => AgentAction::ReceiveStimulus,
+            Self::Bailout => AgentAction::ReceiveBailout,
+            Self::ShadyLoan => AgentAction::TakeShadyLoan,
         }
     }
+
+    pub fn effects(&self) -> Vec<Effect> {
+        action_effects(&self.action()).unwrap_or_default()
+    }
+}
+
+/// The effects of a class bump of the given amount, which shrinks as the class is bumped
+/// again during the same day.
+pub fn class_bump_effects(class: StonkClass, amount: f64) -> Vec<Effect> {
+    vec![Effect::ClassCondition {
+        class,
+        condition: StonkCondition::Bump { amount },
+        ticks: DAY_LENGTH,
+    }]
+}
+
+/// The effects of the actions picked from the night event cards, applied all together.
+/// Actions acting on other agents or on market state computed at apply time have none.
+pub fn action_effects(action: &AgentAction) -> Option<Vec<Effect>> {
+    let effects = match action {
+        AgentAction::BumpStonkClass { class } => class_bump_effects(*class, CLASS_BUMP),
+        AgentAction::CrashAll => vec![
+            Effect::PayCash(MARKET_CRASH_COST),
+            Effect::AllStonksCondition {
+                condition: StonkCondition::Bump { amount: -4.0 },
+                ticks: DAY_LENGTH,
+            },
+            Effect::AllStonksCondition {
+                condition: StonkCondition::IncreasedShockProbability,
+                ticks: DAY_LENGTH,
+            },
+        ],
+        AgentAction::OneDayUltraVision => vec![Effect::AgentCondition {
+            condition: AgentCondition::UltraVision,
+            ticks: DAY_LENGTH,
+        }],
+        AgentAction::AddCash { amount } => vec![Effect::AddCash(*amount)],
+        AgentAction::AcceptBribe => vec![
+            Effect::AddCash(BRIBE_AMOUNT),
+            Effect::AgentCondition {
+                condition: AgentCondition::Audited,
+                ticks: AUDIT_DAYS * DAY_LENGTH,
+            },
+        ],
+        AgentAction::ReceiveStimulus => vec![Effect::TreasuryPayout(STIMULUS_AMOUNT)],
+        AgentAction::ReceiveBailout => vec![Effect::TreasuryPayout(BAILOUT_AMOUNT)],
+        AgentAction::TakeShadyLoan => vec![
+            Effect::AddCash(SHADY_LOAN_AMOUNT),
+            Effect::OwnedStonksCondition {
+                condition: StonkCondition::IncreasedShockProbability,
+                ticks: DAY_LENGTH,
+            },
+        ],
+        _ => return None,
+    };
+    Some(effects)
 }
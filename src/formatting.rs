@@ -0,0 +1,210 @@
+// Formatting of amounts of money for display. The server formats every amount with the number
+// format of its config: the separators of a locale, the symbol of the currency, before or after
+// the amount, and either an abbreviation like 1.235k or the full amount like 1,234.56.
+// Amounts are formatted through DollarValue, so that tables, footers and events all agree.
+
+use crate::fx::OFFSHORE_CURRENCY_SYMBOL;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+
+pub const DOLLAR_SYMBOL: &str = "$";
+
+static NUMBER_FORMAT: Lazy<RwLock<NumberFormat>> =
+    Lazy::new(|| RwLock::new(NumberFormat::default()));
+
+#[cfg(test)]
+thread_local! {
+    // Overrides the number format on the thread of a test, so that a test can format amounts
+    // in another locale without changing them for the tests running in parallel.
+    static TEST_NUMBER_FORMAT: std::cell::Cell<Option<NumberFormat>> =
+        const { std::cell::Cell::new(None) };
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Locale {
+    #[default]
+    #[serde(rename = "en-US")]
+    EnUs,
+    #[serde(rename = "de-DE")]
+    DeDe,
+    #[serde(rename = "fr-FR")]
+    FrFr,
+    #[serde(rename = "de-CH")]
+    DeCh,
+}
+
+impl Locale {
+    fn thousands_separator(&self) -> char {
+        match self {
+            Self::EnUs => ',',
+            Self::DeDe => '.',
+            Self::FrFr => ' ',
+            Self::DeCh => '\'',
+        }
+    }
+
+    fn decimal_separator(&self) -> char {
+        match self {
+            Self::EnUs | Self::DeCh => '.',
+            Self::DeDe | Self::FrFr => ',',
+        }
+    }
+
+    // Whether the currency symbol follows the amount, separated by a space.
+    fn symbol_after_amount(&self) -> bool {
+        matches!(self, Self::DeDe | Self::FrFr)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Currency {
+    #[default]
+    Dollar,
+    // The currency of the offshore exchange.
+    Offshore,
+}
+
+impl Currency {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Dollar => DOLLAR_SYMBOL,
+            Self::Offshore => OFFSHORE_CURRENCY_SYMBOL,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NumberFormat {
+    pub locale: Locale,
+    // Shows every amount to the cent instead of abbreviating the large ones.
+    pub full_precision: bool,
+}
+
+impl NumberFormat {
+    /// Formats the number with the given decimals and the separators of the locale.
+    pub fn decimal(&self, value: f64, decimals: usize) -> String {
+        let formatted = format!("{:.*}", decimals, value.abs());
+        let (integer, fraction) = formatted
+            .split_once('.')
+            .unwrap_or((formatted.as_str(), ""));
+
+        let mut text = String::new();
+        if value < 0.0 {
+            text.push('-');
+        }
+        for (index, digit) in integer.chars().enumerate() {
+            if index > 0 && (integer.len() - index) % 3 == 0 {
+                text.push(self.locale.thousands_separator());
+            }
+            text.push(digit);
+        }
+        if !fraction.is_empty() {
+            text.push(self.locale.decimal_separator());
+            text.push_str(fraction);
+        }
+        text
+    }
+
+    /// Formats an amount of dollars, or of another currency, without its symbol.
+    pub fn amount(&self, value: f64) -> String {
+        if self.full_precision {
+            return self.decimal(value, 2);
+        }
+        if value > 1_000_000_000_000.0 {
+            format!("{}T", self.decimal(value / 1_000_000_000_000.0, 3))
+        } else if value > 1_000_000_000.0 {
+            format!("{}B", self.decimal(value / 1_000_000_000.0, 3))
+        } else if value > 1_000_000.0 {
+            format!("{}M", self.decimal(value / 1_000_000.0, 3))
+        } else if value > 1_000.0 {
+            format!("{}k", self.decimal(value / 1_000.0, 3))
+        } else {
+            self.decimal(value, 2)
+        }
+    }
+
+    /// Places the currency symbol next to a formatted amount.
+    pub fn with_symbol(&self, amount: &str, currency: Currency) -> String {
+        if self.locale.symbol_after_amount() {
+            format!("{} {}", amount, currency.symbol())
+        } else {
+            format!("{}{}", currency.symbol(), amount)
+        }
+    }
+
+    pub fn money(&self, value: f64, currency: Currency) -> String {
+        self.with_symbol(&self.amount(value), currency)
+    }
+}
+
+/// The number format of the server.
+pub fn number_format() -> NumberFormat {
+    #[cfg(test)]
+    if let Some(format) = TEST_NUMBER_FORMAT.with(|format| format.get()) {
+        return format;
+    }
+    NUMBER_FORMAT
+        .read()
+        .map(|format| *format)
+        .unwrap_or_default()
+}
+
+/// Sets the number format of the server, from its config.
+pub fn set_number_format(format: NumberFormat) {
+    if let Ok(mut number_format) = NUMBER_FORMAT.write() {
+        *number_format = format;
+    }
+}
+
+/// Sets the number format of the current test thread, or goes back to the server one with None.
+#[cfg(test)]
+pub fn set_test_number_format(format: Option<NumberFormat>) {
+    TEST_NUMBER_FORMAT.with(|test_format| test_format.set(format));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Currency, Locale, NumberFormat};
+
+    #[test]
+    fn test_number_format() {
+        let format = NumberFormat::default();
+        assert_eq!(format.money(999.5, Currency::Dollar), "$999.50");
+        assert_eq!(format.money(1_234.56, Currency::Dollar), "$1.235k");
+        assert_eq!(format.money(1_234.56, Currency::Offshore), "Ð1.235k");
+        assert_eq!(format.amount(1_234_567_890_123_456.0), "1,234.568T");
+        assert_eq!(format.decimal(-1_234_567.0, 0), "-1,234,567");
+
+        let format = NumberFormat {
+            locale: Locale::DeDe,
+            full_precision: true,
+        };
+        assert_eq!(
+            format.money(1_234_567.891, Currency::Dollar),
+            "1.234.567,89 $"
+        );
+        assert_eq!(format.money(0.5, Currency::Offshore), "0,50 Ð");
+
+        let format = NumberFormat {
+            locale: Locale::FrFr,
+            full_precision: false,
+        };
+        assert_eq!(format.money(12_345.0, Currency::Dollar), "12,345k $");
+
+        let format = NumberFormat {
+            locale: Locale::DeCh,
+            full_precision: true,
+        };
+        assert_eq!(format.money(12_345.0, Currency::Dollar), "$12'345.00");
+
+        assert_eq!(
+            serde_json::from_str::<NumberFormat>(r#"{"locale":"fr-FR"}"#).ok(),
+            Some(NumberFormat {
+                locale: Locale::FrFr,
+                full_precision: false
+            })
+        );
+    }
+}
@@ -0,0 +1,180 @@
+// Futures on Commodity stonks. A contract bets on the price of the stonk when the season ends:
+// the holder pays a margin on the value of the shares, and at the first dawn of the next season
+// gets it back together with the change of the price over the amount, long or short. Gains and
+// losses are capped at the margin, the market is on the other side of every contract.
+
+use crate::{
+    leaderboard::DAYS_PER_SEASON,
+    market::GamePhase,
+    money::Money,
+    stonk::{Stonk, StonkClass, Stonks},
+};
+use serde::{Deserialize, Serialize};
+
+// Margin paid to open a contract, as a fraction of the value of its shares.
+pub const FUTURES_MARGIN: f64 = 0.2;
+// Fee on the value of the shares of a contract, paid when opening it.
+pub const FUTURES_FEE: f64 = 0.005;
+// Open contracts of a player on a stonk are limited to this stake of its shares.
+pub const FUTURES_MAX_STAKE: f64 = 0.05;
+// No contracts can be opened in the last days of the season.
+pub const FUTURES_CUTOFF_DAYS: usize = 7;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FuturesContract {
+    pub username: String,
+    pub stonk_id: usize,
+    pub amount: u32,
+    // Long contracts gain when the price rises, short ones when it falls.
+    pub is_long: bool,
+    pub entry_price_cents: u32,
+    pub margin: Money,
+    // Day cycle of the settlement, the first one of the next season.
+    pub settlement_cycle: usize,
+}
+
+impl FuturesContract {
+    /// The margin plus the profit at the given price, between nothing and twice the margin.
+    pub fn value(&self, price_cents: u32) -> Money {
+        let change = (price_cents as f64 - self.entry_price_cents as f64) * self.amount as f64;
+        let profit = if self.is_long { change } else { -change };
+        let margin = self.margin.as_f64_cents();
+        Money::saturating_from_f64_cents((margin + profit).clamp(0.0, 2.0 * margin))
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FuturesBook {
+    #[serde(default)]
+    contracts: Vec<FuturesContract>,
+}
+
+impl FuturesBook {
+    /// The day cycle contracts opened at the given one settle at.
+    pub fn settlement_cycle(cycle: usize) -> usize {
+        (cycle / DAYS_PER_SEASON + 1) * DAYS_PER_SEASON
+    }
+
+    /// Returns an error if no contract on the stonk can be opened now.
+    pub fn check_open(phase: GamePhase, stonk: &Stonk) -> Result<(), String> {
+        if stonk.class != StonkClass::Commodity {
+            return Err("Futures are listed on Commodity stonks only".to_string());
+        }
+        let GamePhase::Day { cycle, .. } = phase else {
+            return Err("Futures cannot be opened at night".to_string());
+        };
+        if Self::settlement_cycle(cycle) - cycle <= FUTURES_CUTOFF_DAYS {
+            return Err(format!(
+                "Futures cannot be opened in the last {} days of the season",
+                FUTURES_CUTOFF_DAYS
+            ));
+        }
+        Ok(())
+    }
+
+    /// The margin of a contract on the amount at the given price, or None if it cannot be
+    /// represented.
+    pub fn margin(price_cents: u32, amount: u32) -> Option<Money> {
+        Some(
+            Money::from(price_cents)
+                .checked_mul(amount as u64)?
+                .scale(FUTURES_MARGIN),
+        )
+    }
+
+    /// The largest amount the player can still open contracts on for the stonk.
+    pub fn max_open_amount(&self, username: &str, stonk: &Stonk) -> u32 {
+        let max_amount = (stonk.number_of_shares as f64 * FUTURES_MAX_STAKE) as u32;
+        let open_amount = self
+            .contracts_of(username)
+            .filter(|contract| contract.stonk_id == stonk.id)
+            .map(|contract| contract.amount)
+            .sum::<u32>();
+        max_amount.saturating_sub(open_amount)
+    }
+
+    pub fn contracts_of<'a>(
+        &'a self,
+        username: &'a str,
+    ) -> impl Iterator<Item = &'a FuturesContract> + 'a {
+        self.contracts
+            .iter()
+            .filter(move |contract| contract.username == username)
+    }
+
+    pub fn open(&mut self, contract: FuturesContract) {
+        self.contracts.push(contract);
+    }
+
+    /// Current value of the contracts of the player, at the current prices.
+    pub fn value(&self, username: &str, stonks: &Stonks) -> Money {
+        self.contracts_of(username)
+            .map(|contract| contract.value(stonks[contract.stonk_id].current_unit_price_cents()))
+            .sum()
+    }
+
+    /// Removes and returns the contracts due at the given day cycle.
+    pub fn take_due(&mut self, cycle: usize) -> Vec<FuturesContract> {
+        let (due, open) = std::mem::take(&mut self.contracts)
+            .into_iter()
+            .partition(|contract| contract.settlement_cycle <= cycle);
+        self.contracts = open;
+        due
+    }
+
+    /// Removes and returns the open contracts of the player.
+    pub fn take_of(&mut self, username: &str) -> Vec<FuturesContract> {
+        let (taken, open) = std::mem::take(&mut self.contracts)
+            .into_iter()
+            .partition(|contract| contract.username == username);
+        self.contracts = open;
+        taken
+    }
+
+    pub fn rename(&mut self, old_username: &str, new_username: &str) {
+        for contract in self.contracts.iter_mut() {
+            if contract.username == old_username {
+                contract.username = new_username.to_string();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FuturesBook, FuturesContract};
+    use crate::{leaderboard::DAYS_PER_SEASON, money::Money};
+
+    #[test]
+    fn test_futures_contract_value() {
+        let mut contract = FuturesContract {
+            username: "farmer".to_string(),
+            stonk_id: 0,
+            amount: 100,
+            is_long: true,
+            entry_price_cents: 1_000,
+            margin: FuturesBook::margin(1_000, 100).unwrap(),
+            settlement_cycle: DAYS_PER_SEASON,
+        };
+        assert_eq!(contract.margin, Money::from_dollars(200));
+        assert_eq!(contract.value(1_000), Money::from_dollars(200));
+        assert_eq!(contract.value(1_100), Money::from_dollars(300));
+        // Gains and losses are capped at the margin.
+        assert_eq!(contract.value(5_000), Money::from_dollars(400));
+        assert_eq!(contract.value(100), Money::ZERO);
+        contract.is_long = false;
+        assert_eq!(contract.value(900), Money::from_dollars(300));
+
+        let mut book = FuturesBook::default();
+        book.open(contract);
+        assert!(book.take_due(DAYS_PER_SEASON - 1).is_empty());
+        assert_eq!(book.take_due(DAYS_PER_SEASON).len(), 1);
+        assert_eq!(book.contracts_of("farmer").count(), 0);
+
+        assert_eq!(FuturesBook::settlement_cycle(0), DAYS_PER_SEASON);
+        assert_eq!(
+            FuturesBook::settlement_cycle(DAYS_PER_SEASON),
+            2 * DAYS_PER_SEASON
+        );
+    }
+}
@@ -0,0 +1,111 @@
+// The currency of the offshore exchange. Its exchange rate against the dollar random-walks at
+// every tick, day and night, within a band. Players keep a separate balance in it, to trade
+// offshore, and convert between the two currencies paying a small fee.
+
+use crate::money::Money;
+use rand::Rng;
+use rand_distr::{Distribution, Normal};
+use serde::{Deserialize, Serialize};
+
+pub const OFFSHORE_CURRENCY_SYMBOL: &str = "Ð";
+// Standard deviation of the change of the logarithm of the rate at every tick.
+pub const FX_VOLATILITY: f64 = 0.002;
+pub const MIN_EXCHANGE_RATE: f64 = 0.5;
+pub const MAX_EXCHANGE_RATE: f64 = 2.0;
+// Fee on currency conversions, taken from the converted amount.
+pub const FX_FEE: f64 = 0.005;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExchangeRate {
+    // Dollars per unit of the offshore currency.
+    rate: f64,
+    // Rate at the start of the day.
+    day_open: f64,
+}
+
+impl Default for ExchangeRate {
+    fn default() -> Self {
+        Self {
+            rate: 1.0,
+            day_open: 1.0,
+        }
+    }
+}
+
+impl ExchangeRate {
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// Change of the rate since the day opened, in percentage.
+    pub fn day_change(&self) -> f64 {
+        (self.rate / self.day_open - 1.0) * 100.0
+    }
+
+    pub fn to_dollars(&self, amount: Money) -> Money {
+        amount.scale(self.rate)
+    }
+
+    pub fn to_offshore(&self, amount: Money) -> Money {
+        amount.scale(1.0 / self.rate)
+    }
+
+    /// Converts the amount, in the currency sold, returning the amount received after the fee
+    /// and the fee in dollars.
+    pub fn convert(&self, amount: Money, to_offshore: bool) -> (Money, Money) {
+        let converted = if to_offshore {
+            self.to_offshore(amount)
+        } else {
+            self.to_dollars(amount)
+        };
+        let received = converted.scale(1.0 - FX_FEE);
+        let fee = converted.saturating_sub(received);
+        let fee_dollars = if to_offshore {
+            self.to_dollars(fee)
+        } else {
+            fee
+        };
+        (received, fee_dollars)
+    }
+
+    pub fn open_day(&mut self) {
+        self.day_open = self.rate;
+    }
+
+    pub fn tick<R: Rng>(&mut self, rng: &mut R) {
+        let step = Normal::new(0.0, FX_VOLATILITY)
+            .expect("Failed to sample exchange rate distribution")
+            .sample(rng);
+        self.rate = (self.rate * step.exp()).clamp(MIN_EXCHANGE_RATE, MAX_EXCHANGE_RATE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExchangeRate, FX_FEE, MAX_EXCHANGE_RATE, MIN_EXCHANGE_RATE};
+    use crate::money::Money;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    #[test]
+    fn test_exchange_rate() {
+        let mut exchange_rate = ExchangeRate::default();
+        let (received, fee) = exchange_rate.convert(Money::from_dollars(1_000), true);
+        assert_eq!(received, Money::from_dollars(1_000).scale(1.0 - FX_FEE));
+        assert_eq!(fee, Money::from_dollars(5));
+
+        let rng = &mut ChaCha8Rng::seed_from_u64(0);
+        for _ in 0..10_000 {
+            exchange_rate.tick(rng);
+            assert!((MIN_EXCHANGE_RATE..=MAX_EXCHANGE_RATE).contains(&exchange_rate.rate()));
+        }
+        assert_ne!(exchange_rate.day_change(), 0.0);
+        exchange_rate.open_day();
+        assert_eq!(exchange_rate.day_change(), 0.0);
+
+        // Going through the other currency and back loses the fees, whatever the rate.
+        let (offshore, _) = exchange_rate.convert(Money::from_dollars(1_000), true);
+        let (dollars, _) = exchange_rate.convert(offshore, false);
+        assert!(dollars < Money::from_dollars(1_000));
+    }
+}
@@ -0,0 +1,104 @@
+// Minimal HTTP healthcheck on its own port, so that systemd or k8s can supervise the server
+// without an SSH client: `/live` answers as long as the process runs, `/ready` only when
+// the server can take players. Plain TCP probes can just connect to the port.
+
+use crate::clock::GameClock;
+use crate::server_status::ServerStatus;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tracing::debug;
+
+// Only the request line is read, anything longer is not a probe.
+const MAX_REQUEST_BYTES: usize = 1024;
+
+/// Answers the probes on the listener until the server stops.
+pub async fn serve(
+    listener: TcpListener,
+    status: Arc<std::sync::Mutex<ServerStatus>>,
+    clock: Arc<Mutex<GameClock>>,
+) {
+    loop {
+        let socket = match listener.accept().await {
+            Ok((socket, _)) => socket,
+            Err(e) => {
+                debug!("Healthcheck connection failed: {}", e);
+                continue;
+            }
+        };
+        let status = status.clone();
+        let clock = clock.clone();
+        tokio::spawn(async move {
+            if let Err(e) = answer_probe(socket, &status, &clock).await {
+                debug!("Healthcheck probe failed: {}", e);
+            }
+        });
+    }
+}
+
+async fn answer_probe(
+    mut socket: TcpStream,
+    status: &std::sync::Mutex<ServerStatus>,
+    clock: &Mutex<GameClock>,
+) -> std::io::Result<()> {
+    let mut buffer = [0; MAX_REQUEST_BYTES];
+    let length = socket.read(&mut buffer).await?;
+    let request = String::from_utf8_lossy(&buffer[..length]);
+
+    // The clock is read before taking the status lock, as in the status report.
+    let clock = clock.lock().await.clone();
+    let ready = status
+        .lock()
+        .map(|status| status.is_ready(&clock, Instant::now()))
+        .unwrap_or(false);
+
+    socket
+        .write_all(response(&request, ready).as_bytes())
+        .await?;
+    socket.shutdown().await
+}
+
+fn response(request: &str, ready: bool) -> String {
+    let path = request.lines().next().and_then(|line| {
+        match line.split_whitespace().collect::<Vec<_>>()[..] {
+            ["GET", path, ..] => Some(path),
+            _ => None,
+        }
+    });
+    let (code, body) = match path {
+        Some("/live") => ("200 OK", "alive"),
+        Some("/ready") if ready => ("200 OK", "ready"),
+        Some("/ready") => ("503 Service Unavailable", "not ready"),
+        Some(_) => ("404 Not Found", "expected /live or /ready"),
+        None => ("400 Bad Request", "expected a GET request"),
+    };
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}\n",
+        code,
+        body.len() + 1,
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::response;
+
+    #[test]
+    fn test_probe_response() {
+        let live = response("GET /live HTTP/1.1\r\nHost: localhost\r\n\r\n", false);
+        assert!(live.starts_with("HTTP/1.1 200 OK"));
+        assert!(live.ends_with("\r\n\r\nalive\n"));
+
+        let ready = response("GET /ready HTTP/1.1\r\n\r\n", true);
+        assert!(ready.starts_with("HTTP/1.1 200 OK"));
+        let not_ready = response("GET /ready HTTP/1.1\r\n\r\n", false);
+        assert!(not_ready.starts_with("HTTP/1.1 503 Service Unavailable"));
+        assert!(not_ready.contains("Content-Length: 10\r\n"));
+
+        assert!(response("GET /metrics HTTP/1.1\r\n\r\n", true).starts_with("HTTP/1.1 404"));
+        assert!(response("", true).starts_with("HTTP/1.1 400"));
+    }
+}
@@ -0,0 +1,502 @@
+use crossterm::event::{
+    Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+};
+use tracing::debug;
+
+// Pending bytes beyond this size are discarded: no terminal sequence comes close.
+pub const MAX_INPUT_LENGTH: usize = 4096;
+const BRACKETED_PASTE_START: &[u8] = b"\x1b[200~";
+const BRACKETED_PASTE_END: &[u8] = b"\x1b[201~";
+
+/// Result of decoding the beginning of the input buffer.
+enum Decoded {
+    // An event (if the sequence is understood) and the number of bytes consumed.
+    Event(Option<Event>, usize),
+    // The buffer ends in the middle of a sequence.
+    Incomplete,
+}
+
+/// Stateful decoder of the raw bytes sent by a client terminal.
+///
+/// A packet can contain several key presses and a sequence can be split over several packets,
+/// so incomplete sequences are kept until the next packet. The data comes straight from the
+/// network, so any input must be handled without panicking.
+#[derive(Debug, Default)]
+pub struct InputDecoder {
+    buffer: Vec<u8>,
+    // Set when an unterminated paste overflowed the buffer: the rest of it is dropped until its
+    // end arrives, so that the pasted text is never decoded as key presses.
+    discarding_paste: bool,
+}
+
+impl InputDecoder {
+    pub fn feed(&mut self, data: &[u8]) -> Vec<Event> {
+        self.buffer.extend_from_slice(data);
+        if self.discarding_paste && !self.skip_discarded_paste() {
+            return vec![];
+        }
+        let mut events = vec![];
+        let mut position = 0;
+
+        while position < self.buffer.len() {
+            match decode(&self.buffer[position..]) {
+                Decoded::Event(event, consumed) => {
+                    if let Some(event) = event {
+                        events.push(event);
+                    }
+                    position += consumed;
+                }
+                Decoded::Incomplete => break,
+            }
+        }
+        self.buffer.drain(..position);
+
+        if self.buffer.len() > MAX_INPUT_LENGTH {
+            debug!("Discarding {} bytes of pending input", self.buffer.len());
+            if self.buffer.starts_with(BRACKETED_PASTE_START) {
+                self.discarding_paste = true;
+                self.skip_discarded_paste();
+            } else {
+                self.buffer.clear();
+            }
+        }
+
+        events
+    }
+
+    /// Drops the buffered bytes of a discarded paste up to its end, returning whether the end
+    /// arrived.
+    fn skip_discarded_paste(&mut self) -> bool {
+        match self
+            .buffer
+            .windows(BRACKETED_PASTE_END.len())
+            .position(|window| window == BRACKETED_PASTE_END)
+        {
+            Some(end) => {
+                self.buffer.drain(..end + BRACKETED_PASTE_END.len());
+                self.discarding_paste = false;
+                true
+            }
+            None => {
+                // The end of the paste can be split over two packets.
+                let kept = self.buffer.len().min(BRACKETED_PASTE_END.len() - 1);
+                self.buffer.drain(..self.buffer.len() - kept);
+                false
+            }
+        }
+    }
+}
+
+fn key(code: KeyCode, modifiers: KeyModifiers) -> Option<Event> {
+    Some(Event::Key(KeyEvent::new(code, modifiers)))
+}
+
+fn decode(data: &[u8]) -> Decoded {
+    match data[0] {
+        27 => decode_escape(data),
+        x if x >= 0x80 => decode_utf8(data),
+        x => Decoded::Event(decode_ascii(x).map(Event::Key), 1),
+    }
+}
+
+fn decode_ascii(byte: u8) -> Option<KeyEvent> {
+    let (code, modifiers) = match byte {
+        0 => (KeyCode::Char(' '), KeyModifiers::CONTROL),
+        8 | 127 => (KeyCode::Backspace, KeyModifiers::empty()),
+        9 => (KeyCode::Tab, KeyModifiers::empty()),
+        10 | 13 => (KeyCode::Enter, KeyModifiers::empty()),
+        x if (1..=26).contains(&x) => {
+            (KeyCode::Char((x - 1 + b'a') as char), KeyModifiers::CONTROL)
+        }
+        x if x.is_ascii_uppercase() => (
+            KeyCode::Char((x as char).to_ascii_lowercase()),
+            KeyModifiers::SHIFT,
+        ),
+        x if (32..127).contains(&x) => (KeyCode::Char(x as char), KeyModifiers::empty()),
+        _ => return None,
+    };
+    Some(KeyEvent::new(code, modifiers))
+}
+
+fn decode_utf8(data: &[u8]) -> Decoded {
+    let length = match data[0] {
+        0xc2..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf4 => 4,
+        // Invalid leading byte.
+        _ => return Decoded::Event(None, 1),
+    };
+    if data.len() < length {
+        // Only wait for more data if what arrived so far is valid.
+        if data[1..].iter().all(|b| b & 0xc0 == 0x80) {
+            return Decoded::Incomplete;
+        }
+        return Decoded::Event(None, 1);
+    }
+
+    match std::str::from_utf8(&data[..length]) {
+        Ok(text) => {
+            let event = text
+                .chars()
+                .next()
+                .and_then(|c| key(KeyCode::Char(c), KeyModifiers::empty()));
+            Decoded::Event(event, length)
+        }
+        Err(_) => Decoded::Event(None, 1),
+    }
+}
+
+fn decode_escape(data: &[u8]) -> Decoded {
+    match data.get(1) {
+        // A lone escape at the end of a packet is the Esc key.
+        None => Decoded::Event(key(KeyCode::Esc, KeyModifiers::empty()), 1),
+        Some(b'[') => decode_csi(data),
+        Some(b'O') => match data.get(2) {
+            None => Decoded::Incomplete,
+            Some(&final_byte) => Decoded::Event(
+                decode_final_byte(final_byte).and_then(|code| key(code, KeyModifiers::empty())),
+                3,
+            ),
+        },
+        Some(27) => Decoded::Event(key(KeyCode::Esc, KeyModifiers::empty()), 1),
+        // Escape followed by a key is sent by terminals for Alt + key.
+        Some(_) => match decode(&data[1..]) {
+            Decoded::Event(Some(Event::Key(mut key_event)), consumed) => {
+                key_event.modifiers |= KeyModifiers::ALT;
+                Decoded::Event(Some(Event::Key(key_event)), consumed + 1)
+            }
+            Decoded::Event(_, consumed) => Decoded::Event(None, consumed + 1),
+            Decoded::Incomplete => Decoded::Incomplete,
+        },
+    }
+}
+
+// Key for the final byte of SS3 sequences and of CSI sequences without a number.
+fn decode_final_byte(final_byte: u8) -> Option<KeyCode> {
+    let code = match final_byte {
+        b'A' => KeyCode::Up,
+        b'B' => KeyCode::Down,
+        b'C' => KeyCode::Right,
+        b'D' => KeyCode::Left,
+        b'H' => KeyCode::Home,
+        b'F' => KeyCode::End,
+        b'P' => KeyCode::F(1),
+        b'Q' => KeyCode::F(2),
+        b'R' => KeyCode::F(3),
+        b'S' => KeyCode::F(4),
+        _ => return None,
+    };
+    Some(code)
+}
+
+// Key for CSI sequences of the form `ESC [ number ~`.
+fn decode_tilde_number(number: u16) -> Option<KeyCode> {
+    let code = match number {
+        1 | 7 => KeyCode::Home,
+        2 => KeyCode::Insert,
+        3 => KeyCode::Delete,
+        4 | 8 => KeyCode::End,
+        5 => KeyCode::PageUp,
+        6 => KeyCode::PageDown,
+        11..=15 => KeyCode::F((number - 10) as u8),
+        17..=21 => KeyCode::F((number - 11) as u8),
+        23..=26 => KeyCode::F((number - 12) as u8),
+        28 | 29 => KeyCode::F((number - 13) as u8),
+        31..=34 => KeyCode::F((number - 14) as u8),
+        _ => return None,
+    };
+    Some(code)
+}
+
+// xterm encodes modifiers as 1 + (shift | alt << 1 | ctrl << 2).
+fn decode_modifiers(parameter: u16) -> KeyModifiers {
+    let bits = parameter.saturating_sub(1);
+    let mut modifiers = KeyModifiers::empty();
+    if bits & 1 != 0 {
+        modifiers |= KeyModifiers::SHIFT;
+    }
+    if bits & 2 != 0 {
+        modifiers |= KeyModifiers::ALT;
+    }
+    if bits & 4 != 0 {
+        modifiers |= KeyModifiers::CONTROL;
+    }
+    modifiers
+}
+
+fn decode_csi(data: &[u8]) -> Decoded {
+    if data.starts_with(BRACKETED_PASTE_START) {
+        return decode_paste(data);
+    }
+
+    // Parameters and intermediate bytes are followed by a final byte in 0x40..=0x7e.
+    let Some(final_index) = data[2..]
+        .iter()
+        .position(|b| !(0x20..=0x3f).contains(b))
+        .map(|index| index + 2)
+    else {
+        return Decoded::Incomplete;
+    };
+    let final_byte = data[final_index];
+    let consumed = final_index + 1;
+    if !(0x40..=0x7e).contains(&final_byte) {
+        // Malformed sequence: drop the escape and decode the rest as keys.
+        return Decoded::Event(None, final_index);
+    }
+
+    let parameters = &data[2..final_index];
+    if parameters.first() == Some(&b'<') {
+        return Decoded::Event(
+            decode_sgr_mouse_input(&data[..consumed])
+                .ok()
+                .and_then(convert_sgr_to_mouse_event),
+            consumed,
+        );
+    }
+
+    let numbers = std::str::from_utf8(parameters)
+        .unwrap_or_default()
+        .split(';')
+        .map(|n| n.parse::<u16>().ok())
+        .collect::<Vec<Option<u16>>>();
+    let modifiers = numbers
+        .get(1)
+        .copied()
+        .flatten()
+        .map(decode_modifiers)
+        .unwrap_or(KeyModifiers::empty());
+
+    let code = match final_byte {
+        b'~' => numbers
+            .first()
+            .copied()
+            .flatten()
+            .and_then(decode_tilde_number),
+        b'Z' => Some(KeyCode::BackTab),
+        x => decode_final_byte(x),
+    };
+
+    Decoded::Event(code.and_then(|code| key(code, modifiers)), consumed)
+}
+
+fn decode_paste(data: &[u8]) -> Decoded {
+    let content = &data[BRACKETED_PASTE_START.len()..];
+    match content
+        .windows(BRACKETED_PASTE_END.len())
+        .position(|window| window == BRACKETED_PASTE_END)
+    {
+        Some(end) => Decoded::Event(
+            Some(Event::Paste(
+                String::from_utf8_lossy(&content[..end]).into_owned(),
+            )),
+            BRACKETED_PASTE_START.len() + end + BRACKETED_PASTE_END.len(),
+        ),
+        // Pastes too long to be buffered are discarded by the decoder.
+        None => Decoded::Incomplete,
+    }
+}
+
+fn decode_sgr_mouse_input(ansi_code: &[u8]) -> Result<(u8, u16, u16), &'static str> {
+    // Convert u8 vector to a String
+    let ansi_str = std::str::from_utf8(ansi_code).map_err(|_| "Invalid UTF-8 sequence")?;
+
+    // Check the prefix
+    if !ansi_str.starts_with("\x1b[<") {
+        return Err("Invalid SGR ANSI mouse code");
+    }
+
+    let cb_mod = if ansi_str.ends_with('M') {
+        0
+    } else if ansi_str.ends_with('m') {
+        3
+    } else {
+        return Err("Invalid SGR ANSI mouse code");
+    };
+
+    // Remove the prefix '\x1b[<' and trailing 'M'
+    let code_body = &ansi_str[3..ansi_str.len() - 1];
+
+    // Split the components
+    let components: Vec<&str> = code_body.split(';').collect();
+
+    if components.len() != 3 {
+        return Err("Invalid SGR ANSI mouse code format");
+    }
+
+    // Parse the components
+    let cb = components[0]
+        .parse::<u8>()
+        .map_err(|_| "Failed to parse Cb")?
+        .checked_add(cb_mod)
+        .ok_or("Cb out of range")?;
+    let cx = components[1]
+        .parse::<u16>()
+        .map_err(|_| "Failed to parse Cx")?;
+    let cy = components[2]
+        .parse::<u16>()
+        .map_err(|_| "Failed to parse Cy")?;
+
+    Ok((cb, cx, cy))
+}
+
+fn convert_sgr_to_mouse_event((cb, column, row): (u8, u16, u16)) -> Option<Event> {
+    let kind = match cb {
+        0 => MouseEventKind::Down(MouseButton::Left),
+        1 => MouseEventKind::Down(MouseButton::Middle),
+        2 => MouseEventKind::Down(MouseButton::Right),
+        3 => MouseEventKind::Up(MouseButton::Left),
+        32 => MouseEventKind::Drag(MouseButton::Left),
+        33 => MouseEventKind::Drag(MouseButton::Middle),
+        34 => MouseEventKind::Drag(MouseButton::Right),
+        35 => MouseEventKind::Moved,
+        64 => MouseEventKind::ScrollUp,
+        65 => MouseEventKind::ScrollDown,
+        96..=255 => {
+            debug!("cb {}", cb);
+            return None;
+        }
+        _ => return None,
+    };
+
+    Some(Event::Mouse(MouseEvent {
+        kind,
+        column,
+        row,
+        modifiers: KeyModifiers::empty(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InputDecoder, MAX_INPUT_LENGTH};
+    use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+
+    fn keys(decoder: &mut InputDecoder, data: &[u8]) -> Vec<(KeyCode, KeyModifiers)> {
+        decoder
+            .feed(data)
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Key(KeyEvent {
+                    code, modifiers, ..
+                }) => Some((code, modifiers)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_decode_keys() {
+        let mut decoder = InputDecoder::default();
+        let none = KeyModifiers::empty();
+
+        assert_eq!(
+            keys(&mut decoder, b"bB\r\x7f"),
+            vec![
+                (KeyCode::Char('b'), none),
+                (KeyCode::Char('b'), KeyModifiers::SHIFT),
+                (KeyCode::Enter, none),
+                (KeyCode::Backspace, none),
+            ]
+        );
+        assert_eq!(
+            keys(&mut decoder, "è€🚀".as_bytes()),
+            vec![
+                (KeyCode::Char('è'), none),
+                (KeyCode::Char('€'), none),
+                (KeyCode::Char('🚀'), none),
+            ]
+        );
+        assert_eq!(
+            keys(
+                &mut decoder,
+                b"\x1b[A\x1bOB\x1b[1;5C\x1b[H\x1b[4~\x1bOP\x1b[15~\x1b[24;2~\x1b[Z"
+            ),
+            vec![
+                (KeyCode::Up, none),
+                (KeyCode::Down, none),
+                (KeyCode::Right, KeyModifiers::CONTROL),
+                (KeyCode::Home, none),
+                (KeyCode::End, none),
+                (KeyCode::F(1), none),
+                (KeyCode::F(5), none),
+                (KeyCode::F(12), KeyModifiers::SHIFT),
+                (KeyCode::BackTab, none),
+            ]
+        );
+        assert_eq!(
+            keys(&mut decoder, b"\x1bb\x03\x1b"),
+            vec![
+                (KeyCode::Char('b'), KeyModifiers::ALT),
+                (KeyCode::Char('c'), KeyModifiers::CONTROL),
+                (KeyCode::Esc, none),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decode_split_sequences() {
+        let mut decoder = InputDecoder::default();
+        let emoji = "🚀".as_bytes();
+
+        assert!(decoder.feed(&emoji[..2]).is_empty());
+        assert_eq!(
+            keys(&mut decoder, &emoji[2..]),
+            vec![(KeyCode::Char('🚀'), KeyModifiers::empty())]
+        );
+
+        assert!(decoder.feed(b"\x1b[1;").is_empty());
+        assert_eq!(
+            keys(&mut decoder, b"3D"),
+            vec![(KeyCode::Left, KeyModifiers::ALT)]
+        );
+
+        assert!(decoder.feed(b"\x1b[200~hello ").is_empty());
+        assert!(matches!(
+            decoder.feed(b"world\x1b[201~").as_slice(),
+            [Event::Paste(text)] if text == "hello world"
+        ));
+    }
+
+    #[test]
+    fn test_decode_malformed_input() {
+        let mut decoder = InputDecoder::default();
+        let inputs: [&[u8]; 6] = [
+            b"\x1b[<255;1;1m",
+            b"\x1b[<0;99999;1M",
+            b"\x1b[<0;1;1;1M",
+            b"\x1b[99~",
+            &[0xff, 0xfe, 0xfd],
+            &[0xe2, b'a'],
+        ];
+        for input in inputs {
+            let events = decoder.feed(input);
+            assert!(
+                events
+                    .iter()
+                    .all(|e| matches!(e, Event::Key(k) if k.code == KeyCode::Char('a'))),
+                "Unexpected events {:?} for {:?}",
+                events,
+                input
+            );
+        }
+
+        // An unterminated paste cannot grow forever, and the rest of it is never decoded as
+        // key presses, up to its end, even when split over two packets.
+        assert!(decoder.feed(b"\x1b[200~").is_empty());
+        assert!(decoder.feed(&[b'b'; MAX_INPUT_LENGTH + 1]).is_empty());
+        assert!(decoder.feed(b"bsmB").is_empty());
+        assert!(decoder.feed(b"b\x1b[20").is_empty());
+        assert_eq!(
+            keys(&mut decoder, b"1~s"),
+            vec![(KeyCode::Char('s'), KeyModifiers::empty())]
+        );
+
+        // Other sequences are simply dropped.
+        decoder.feed(b"\x1b[");
+        decoder.feed(&[b'1'; MAX_INPUT_LENGTH + 1]);
+        assert_eq!(
+            keys(&mut decoder, b"b"),
+            vec![(KeyCode::Char('b'), KeyModifiers::empty())]
+        );
+    }
+}
@@ -0,0 +1,131 @@
+// Leaderboard snapshots taken at the end of every in-game day, kept in an append-only file.
+
+use crate::{market::GamePhase, money::Money};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Number of players recorded for each day.
+pub const LEADERBOARD_HISTORY_SIZE: usize = 10;
+pub const DAYS_PER_SEASON: usize = 90;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DailyLeaderboard {
+    pub cycle: usize,
+    pub date: String,
+    // Usernames and net worth, from the richest.
+    pub top: Vec<(String, Money)>,
+}
+
+impl DailyLeaderboard {
+    pub fn winner(&self) -> Option<&str> {
+        self.top.first().map(|(username, _)| username.as_str())
+    }
+
+    pub fn season(&self) -> usize {
+        self.cycle / DAYS_PER_SEASON
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LeaderboardHistory {
+    days: Vec<DailyLeaderboard>,
+    // Number of days already appended to the history file.
+    saved: usize,
+}
+
+impl LeaderboardHistory {
+    /// Builds the history from the days read back from the history file.
+    pub fn from_saved(days: Vec<DailyLeaderboard>) -> Self {
+        let saved = days.len();
+        Self { days, saved }
+    }
+
+    pub fn days(&self) -> &[DailyLeaderboard] {
+        &self.days
+    }
+
+    pub fn record(&mut self, phase: GamePhase, portfolios: &[(String, Money)]) {
+        self.days.push(DailyLeaderboard {
+            cycle: phase.cycle(),
+            date: phase.formatted_date(),
+            top: portfolios
+                .iter()
+                .take(LEADERBOARD_HISTORY_SIZE)
+                .cloned()
+                .collect(),
+        });
+    }
+
+    /// Returns the days not yet written to the history file, marking them as written.
+    pub fn take_unsaved(&mut self) -> &[DailyLeaderboard] {
+        let unsaved = &self.days[self.saved..];
+        self.saved = self.days.len();
+        unsaved
+    }
+
+    /// Number of consecutive days, up to the last recorded one, the player finished first.
+    pub fn streak(&self, username: &str) -> usize {
+        self.days
+            .iter()
+            .rev()
+            .take_while(|day| day.winner() == Some(username))
+            .count()
+    }
+
+    /// Number of days each player finished first in the season, from the most wins.
+    pub fn season_winners(&self, season: usize) -> Vec<(String, usize)> {
+        let mut wins = HashMap::new();
+        for day in self.days.iter().filter(|day| day.season() == season) {
+            if let Some(winner) = day.winner() {
+                *wins.entry(winner.to_string()).or_insert(0) += 1;
+            }
+        }
+        let mut wins = wins.into_iter().collect::<Vec<(String, usize)>>();
+        wins.sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then_with(|| a_name.cmp(b_name)));
+        wins
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LeaderboardHistory, DAYS_PER_SEASON};
+    use crate::{market::GamePhase, money::Money};
+
+    #[test]
+    fn test_leaderboard_history() {
+        let mut history = LeaderboardHistory::default();
+        let portfolios = |first: &str, second: &str| {
+            vec![
+                (first.to_string(), Money::from_cents(200)),
+                (second.to_string(), Money::from_cents(100)),
+            ]
+        };
+        for (cycle, winner) in ["alice", "bob", "bob", "alice", "alice"].iter().enumerate() {
+            let other = if *winner == "alice" { "bob" } else { "alice" };
+            history.record(
+                GamePhase::Day {
+                    cycle: cycle + DAYS_PER_SEASON - 2,
+                    counter: 0,
+                },
+                &portfolios(winner, other),
+            );
+        }
+
+        assert_eq!(history.streak("alice"), 2);
+        assert_eq!(history.streak("bob"), 0);
+        assert_eq!(
+            history.season_winners(0),
+            vec![("alice".to_string(), 1), ("bob".to_string(), 1)]
+        );
+        assert_eq!(
+            history.season_winners(1),
+            vec![("alice".to_string(), 2), ("bob".to_string(), 1)]
+        );
+
+        assert_eq!(history.take_unsaved().len(), 5);
+        assert!(history.take_unsaved().is_empty());
+        let restored = LeaderboardHistory::from_saved(history.days().to_vec());
+        assert_eq!(restored.days(), history.days());
+        assert_eq!(restored.streak("alice"), 2);
+    }
+}
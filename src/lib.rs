@@ -1,10 +1,52 @@
 pub mod agent;
+pub mod api;
+pub mod auction;
+pub mod audit;
+pub mod board;
+pub mod bots;
+pub mod clock;
+pub mod conditions;
+pub mod config;
+pub mod decimation;
+pub mod effects;
 pub mod events;
+pub mod formatting;
+pub mod futures;
+pub mod fx;
+pub mod healthcheck;
+pub mod input;
+pub mod leaderboard;
+pub mod load_shedding;
+pub mod logging;
+pub mod manual;
 pub mod market;
+pub mod market_event;
+pub mod market_maker;
+pub mod money;
+pub mod news;
+pub mod notifications;
+pub mod offshore;
+pub mod passport;
+pub mod persistence;
+pub mod price_series;
+pub mod recording;
+pub mod sandbox;
+pub mod scenario;
+pub mod server_status;
+pub mod sftp;
 pub mod ssh_backend;
 pub mod ssh_client;
 pub mod ssh_server;
+pub mod statistics;
 pub mod stonk;
+pub mod strategy;
+pub mod telemetry;
+#[cfg(test)]
+mod test_harness;
+pub mod totp;
+pub mod tournament;
+pub mod treasury;
 pub mod tui;
 pub mod ui;
+pub mod unlock;
 pub mod utils;
@@ -0,0 +1,87 @@
+// Under heavy load, like a traffic spike of new players, the market drops the per-tick work
+// nobody would miss: the verbose logs written at every trade and tick, and the portfolio
+// rankings, which are then recomputed every few ticks instead of at every tick. The rankings
+// are always fresh at the close, so that the leaderboard and the famous players are exact.
+
+use crate::{
+    config::LoadSheddingConfig,
+    market::{GamePhase, DAY_LENGTH},
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TickDetail {
+    #[default]
+    Full,
+    Reduced {
+        portfolio_update_interval: usize,
+    },
+}
+
+impl TickDetail {
+    pub fn is_full(&self) -> bool {
+        *self == Self::Full
+    }
+
+    /// Whether the portfolio rankings are recomputed before the given tick.
+    pub fn updates_portfolios(&self, phase: GamePhase, tick: usize) -> bool {
+        match self {
+            Self::Full => true,
+            Self::Reduced {
+                portfolio_update_interval,
+            } => {
+                matches!(phase, GamePhase::Day { counter, .. } if counter == DAY_LENGTH - 1)
+                    || tick.is_multiple_of((*portfolio_update_interval).max(1))
+            }
+        }
+    }
+}
+
+/// The detail of the ticks for the number of players online.
+pub fn tick_detail(config: &LoadSheddingConfig, players_online: usize) -> TickDetail {
+    if config.max_players_full_detail > 0 && players_online > config.max_players_full_detail {
+        TickDetail::Reduced {
+            portfolio_update_interval: config.portfolio_update_interval,
+        }
+    } else {
+        TickDetail::Full
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tick_detail, TickDetail};
+    use crate::{
+        config::LoadSheddingConfig,
+        market::{GamePhase, DAY_LENGTH},
+    };
+
+    #[test]
+    fn test_tick_detail() {
+        let config = LoadSheddingConfig {
+            max_players_full_detail: 100,
+            portfolio_update_interval: 4,
+        };
+        assert_eq!(tick_detail(&config, 100), TickDetail::Full);
+        let reduced = tick_detail(&config, 101);
+        assert_eq!(
+            reduced,
+            TickDetail::Reduced {
+                portfolio_update_interval: 4
+            }
+        );
+        // Zero disables the load shedding.
+        let disabled = LoadSheddingConfig {
+            max_players_full_detail: 0,
+            ..config
+        };
+        assert_eq!(tick_detail(&disabled, 10_000), TickDetail::Full);
+
+        let day = |counter| GamePhase::Day { cycle: 1, counter };
+        assert!(TickDetail::Full.updates_portfolios(day(1), 1));
+        assert!(!reduced.updates_portfolios(day(1), 1));
+        assert!(reduced.updates_portfolios(day(4), 4));
+        // The rankings of the close are always up to date.
+        assert!(reduced.updates_portfolios(day(DAY_LENGTH - 1), 1));
+    }
+}
@@ -0,0 +1,237 @@
+// Server logs go to stdout and, optionally, to a rotated file. The levels can be changed at
+// runtime by admins, per module, e.g. to debug the market without restarting the server.
+
+use crate::utils::AppResult;
+use once_cell::sync::Lazy;
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+use tracing::metadata::LevelFilter;
+use tracing_subscriber::{
+    fmt, layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Registry,
+};
+
+pub const LOG_FILE_NAME: &str = "stonks.log";
+// The log is rotated when it grows over this size, keeping at most LOG_MAX_FILES old files.
+const LOG_MAX_BYTES: u64 = 10 * 1024 * 1024;
+const LOG_MAX_FILES: usize = 5;
+// Modules given without a path are modules of the game.
+const CRATE_NAME: &str = "stonks";
+
+type FilterHandle = reload::Handle<EnvFilter, Registry>;
+
+static LOG_LEVELS: Lazy<Mutex<Option<(LogLevels, FilterHandle)>>> = Lazy::new(|| Mutex::new(None));
+// Cleared while the server sheds load, to skip the verbose logs of every trade and tick.
+static DETAILED_LOGS: AtomicBool = AtomicBool::new(true);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogLevels {
+    level: LevelFilter,
+    modules: BTreeMap<String, LevelFilter>,
+}
+
+impl LogLevels {
+    pub fn new(level: LevelFilter) -> Self {
+        Self {
+            level,
+            modules: BTreeMap::new(),
+        }
+    }
+
+    /// Applies a directive: either a level, for every module without its own, or
+    /// `module=level`. Modules without a path, like `market`, are modules of the game.
+    pub fn set(&mut self, directive: &str) -> AppResult<()> {
+        match directive.split_once('=') {
+            Some((module, level)) => {
+                if module.is_empty() {
+                    return Err(format!("Invalid log directive '{}'", directive).into());
+                }
+                let module = if module == CRATE_NAME || module.contains("::") {
+                    module.to_string()
+                } else {
+                    format!("{}::{}", CRATE_NAME, module)
+                };
+                self.modules.insert(module, level.parse()?);
+            }
+            None => self.level = directive.parse()?,
+        }
+        Ok(())
+    }
+
+    pub fn directives(&self) -> String {
+        std::iter::once(self.level.to_string().to_lowercase())
+            .chain(
+                self.modules.iter().map(|(module, level)| {
+                    format!("{}={}", module, level.to_string().to_lowercase())
+                }),
+            )
+            .collect::<Vec<String>>()
+            .join(",")
+    }
+
+    fn filter(&self) -> AppResult<EnvFilter> {
+        Ok(EnvFilter::try_new(self.directives())?)
+    }
+}
+
+#[derive(Debug)]
+pub struct RotatingLog {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl RotatingLog {
+    pub fn open(path: PathBuf) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { path, file, size })
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        for index in (1..LOG_MAX_FILES).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                std::fs::rename(from, self.rotated_path(index + 1))?;
+            }
+        }
+        std::fs::rename(&self.path, self.rotated_path(1))?;
+        *self = Self::open(self.path.clone())?;
+        Ok(())
+    }
+}
+
+impl Write for RotatingLog {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.size >= LOG_MAX_BYTES {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Turns the verbose logs of every trade and tick on or off.
+pub fn set_detailed_logs(detailed: bool) {
+    DETAILED_LOGS.store(detailed, Ordering::Relaxed);
+}
+
+/// Whether the verbose logs of every trade and tick are written.
+pub fn detailed_logs() -> bool {
+    DETAILED_LOGS.load(Ordering::Relaxed)
+}
+
+/// Installs the global subscriber, logging to stdout and to a rotated file in the log
+/// directory, if any.
+pub fn init(level: LevelFilter, log_dir: Option<&Path>) -> AppResult<()> {
+    let levels = LogLevels::new(level);
+    let (filter, handle) = reload::Layer::new(levels.filter()?);
+    let file_layer = match log_dir {
+        Some(dir) => {
+            std::fs::create_dir_all(dir)?;
+            let log = RotatingLog::open(dir.join(LOG_FILE_NAME))?;
+            Some(
+                fmt::layer()
+                    .with_ansi(false)
+                    .with_line_number(true)
+                    .with_file(true)
+                    .with_writer(Mutex::new(log)),
+            )
+        }
+        None => None,
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().with_line_number(true).with_file(true))
+        .with(file_layer)
+        .try_init()?;
+    *LOG_LEVELS.lock().map_err(|e| e.to_string())? = Some((levels, handle));
+    Ok(())
+}
+
+/// Applies a directive to the running logger, returning the resulting directives.
+pub fn set_log_level(directive: &str) -> AppResult<String> {
+    let mut log_levels = LOG_LEVELS.lock().map_err(|e| e.to_string())?;
+    let (levels, handle) = log_levels.as_mut().ok_or("Logging is not initialized")?;
+    let mut updated = levels.clone();
+    updated.set(directive)?;
+    handle.reload(updated.filter()?)?;
+    *levels = updated;
+    Ok(levels.directives())
+}
+
+pub fn log_levels() -> AppResult<String> {
+    let log_levels = LOG_LEVELS.lock().map_err(|e| e.to_string())?;
+    let (levels, _) = log_levels.as_ref().ok_or("Logging is not initialized")?;
+    Ok(levels.directives())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LogLevels, RotatingLog, LOG_MAX_BYTES};
+    use crate::utils::AppResult;
+    use std::io::Write;
+    use tracing::metadata::LevelFilter;
+
+    #[test]
+    fn test_log_levels() -> AppResult<()> {
+        let mut levels = LogLevels::new(LevelFilter::INFO);
+        assert_eq!(levels.directives(), "info");
+
+        levels.set("market=debug")?;
+        levels.set("russh::server=warn")?;
+        assert_eq!(
+            levels.directives(),
+            "info,russh::server=warn,stonks::market=debug"
+        );
+        levels.set("market=info")?;
+        levels.set("error")?;
+        assert_eq!(
+            levels.directives(),
+            "error,russh::server=warn,stonks::market=info"
+        );
+
+        assert!(levels.set("market=loud").is_err());
+        assert!(levels.set("=debug").is_err());
+        assert!(levels.set("loud").is_err());
+        assert!(levels.filter().is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotating_log() -> AppResult<()> {
+        let dir = std::env::temp_dir().join(format!("stonks-log-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("stonks.log");
+
+        let mut log = RotatingLog::open(path.clone())?;
+        log.write_all(b"first\n")?;
+        // Force a rotation.
+        log.size = LOG_MAX_BYTES;
+        log.write_all(b"second\n")?;
+        assert_eq!(std::fs::read_to_string(log.rotated_path(1))?, "first\n");
+        assert_eq!(std::fs::read_to_string(&path)?, "second\n");
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
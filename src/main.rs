@@ -1,7 +1,7 @@
 use clap::{ArgAction, Parser};
-use stonks::{ssh_server::AppServer, utils::AppResult};
+use std::path::PathBuf;
+use stonks::{logging, ssh_server::AppServer, utils::AppResult};
 use tracing::metadata::LevelFilter;
-use tracing_subscriber::EnvFilter;
 
 const DEFAULT_SERVER_SSH_PORT: u16 = 3333;
 
@@ -14,20 +14,36 @@ struct Args {
     port: Option<u16>,
     #[clap(long, short='r', action=ArgAction::SetTrue, help = "Reset storage")]
     reset: bool,
+    #[clap(long, action=ArgAction::Set, help = "Set game clock speed multiplier")]
+    speed: Option<f64>,
+    #[clap(long, action=ArgAction::Set, help = "Run the scripted events of a scenario file")]
+    scenario: Option<PathBuf>,
+    #[clap(long, action=ArgAction::Set, help = "Also write the logs to rotated files in this directory")]
+    log_dir: Option<PathBuf>,
+    #[clap(long, action=ArgAction::Set, help = "Set log level (error, warn, info, debug, trace)")]
+    log_level: Option<LevelFilter>,
+    #[clap(long, action=ArgAction::Set, help = "Serve HTTP healthcheck probes on this port")]
+    health_port: Option<u16>,
 }
 
 #[tokio::main]
 async fn main() -> AppResult<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::default().add_directive(LevelFilter::INFO.into()))
-        .with_line_number(true)
-        .with_file(true)
-        .init();
-
     let args = Args::parse();
 
+    logging::init(
+        args.log_level.unwrap_or(LevelFilter::INFO),
+        args.log_dir.as_deref(),
+    )?;
+
     let port = args.port.unwrap_or(DEFAULT_SERVER_SSH_PORT);
-    AppServer::new(args.reset, args.seed)?.run(port).await?;
+    AppServer::new(
+        args.reset,
+        args.seed,
+        args.speed.unwrap_or(1.0),
+        args.scenario.as_deref(),
+    )?
+    .run(port, args.health_port)
+    .await?;
 
     Ok(())
 }
@@ -0,0 +1,366 @@
+// The in-game manual. Every number is taken from the constants and config used by the engine,
+// so that the explanations cannot drift away from the actual rules.
+
+use crate::{
+    agent::{
+        AUDIT_DAYS, FAMOUS_PLAYERS, INITIAL_USER_CASH, MAX_VACATION_DAYS, PRISON_DAYS,
+        SCHEDULED_DRAWDOWN_PERCENT, VACATION_COOLDOWN_DAYS,
+    },
+    auction::{AUCTION_PRICE_BAND, AUCTION_TICKS},
+    board::{BoardDecision, BOARD_CONTROL_STAKE, BOARD_DECISION_COOLDOWN_TICKS, BOARD_NEWS_TICKS},
+    events::{
+        NightEvent, ASSASSINATION_WARNING_TICKS, CLASS_BUMP, CLASS_BUMP_DIMINISHING_FACTOR,
+        DIVIDEND_PAYOUT, DUMP_HOLDINGS_PENALTY, FAMOUS_PROTECTION_DISCOUNT,
+        INTEL_MEDIA_STAKE_PERCENT, MAX_CLASS_BUMP_PER_DAY, NIGHT_ACTION_POINT_THRESHOLDS,
+    },
+    futures::{FUTURES_CUTOFF_DAYS, FUTURES_FEE, FUTURES_MARGIN, FUTURES_MAX_STAKE},
+    fx::{FX_FEE, MAX_EXCHANGE_RATE, MIN_EXCHANGE_RATE, OFFSHORE_CURRENCY_SYMBOL},
+    leaderboard::DAYS_PER_SEASON,
+    market::{
+        Market, AUDIT_FEE, DARK_POOL_FEE, DAY_LENGTH, FLASH_SALE_MAX_MISPRICING,
+        FLASH_SALE_MIN_MISPRICING, FLASH_SALE_TICKS, GLOBAL_DRIFT_INTERVAL, LIQUIDATION_HAIRCUT,
+        MAX_EVENTS_PER_NIGHT, MAX_GLOBAL_DRIFT, MAX_WEALTH_FEE, NIGHT_LENGTH,
+        TRADE_BUMP_PER_LIQUIDITY, WEALTH_EVENT_COST_FACTOR, WEALTH_FEE_FACTOR,
+    },
+    market_maker::MAX_INVENTORY_STAKE,
+    offshore::{MAX_OFFSHORE_PREMIUM, OFFSHORE_FEE, OFFSHORE_MAX_STAKE},
+    stonk::{
+        DollarValue, AFTER_HOURS_MAX_STAKE, AFTER_HOURS_SPREAD_MULTIPLIER, MAX_PRICE_DRIFT,
+        MAX_SHOCK_PROBABILITY, MIN_PRICE_DIVISOR, PRICE_CONTROL_FACTOR,
+        SHOCK_PROBABILITY_MULTIPLIER,
+    },
+};
+use strum::IntoEnumIterator;
+
+// Number of shares used in the spread examples.
+const SPREAD_EXAMPLE_AMOUNT: u32 = 100;
+
+pub struct ManualPage {
+    pub title: &'static str,
+    pub lines: Vec<String>,
+}
+
+fn percent(value: f64) -> String {
+    format!("{}%", value * 100.0)
+}
+
+pub fn manual_pages(market: &Market) -> Vec<ManualPage> {
+    vec![
+        price_formation_page(market),
+        spread_page(market),
+        conditions_page(),
+        events_page(),
+        board_page(),
+        futures_page(),
+        fees_page(market),
+    ]
+}
+
+fn price_formation_page(market: &Market) -> ManualPage {
+    ManualPage {
+        title: "Price formation",
+        lines: vec![
+            format!(
+                "A day lasts {} ticks and a night {} ticks. Prices only move during the day, once per tick.",
+                DAY_LENGTH, NIGHT_LENGTH
+            ),
+            String::new(),
+            "At every tick the price of a stonk changes by a random percentage. Most of the time it is drawn from a normal distribution centered on the stonk drift, as wide as its volatility. With the stonk shock probability it is drawn from a Cauchy distribution instead, which gives rare but large jumps.".to_string(),
+            format!(
+                "A single tick can never move a price by more than {}.",
+                percent(MAX_PRICE_DRIFT)
+            ),
+            String::new(),
+            "The drift halves at every tick, and the last change is carried over to the next tick as a bump: prices have momentum.".to_string(),
+            format!(
+                "The trades of a tick bump the drift at the next tick by {} times the traded shares divided by the stonk liquidity, scaled by the stonk drift volatility: buying pushes the price up and selling pushes it down. Dark pool trades do not bump the drift.",
+                TRADE_BUMP_PER_LIQUIDITY
+            ),
+            format!(
+                "A market maker takes the other side of {} of the trades, more when they push the price away from its average of the last day, and up to {} of the shares of a stonk. Its part of a trade does not bump the drift, and the spread paid on it goes to the treasury.",
+                percent(market.trading.market_maker_share),
+                percent(MAX_INVENTORY_STAKE)
+            ),
+            format!(
+                "Every {} ticks the whole market gets a global drift of at most {} either way, pulling the total market cap toward its target. The target grows by {} for every player.",
+                GLOBAL_DRIFT_INTERVAL,
+                percent(MAX_GLOBAL_DRIFT),
+                INITIAL_USER_CASH.format_dollars()
+            ),
+            String::new(),
+            format!(
+                "A price cannot go below 1/{} of the starting price. When it gets {} times above or below the starting price, it is pushed back and shocks get more likely.",
+                MIN_PRICE_DIVISOR, PRICE_CONTROL_FACTOR
+            ),
+        ],
+    }
+}
+
+fn spread_page(market: &Market) -> ManualPage {
+    let mut lines = vec![
+        "Buying and selling move along the order book, so every share costs a bit more than the previous one. The liquidity of a stonk is the depth of its order book: buying that many shares doubles the price of the next one.".to_string(),
+        "Buying n shares costs price * n * (1 + (n + 1) / 2 / liquidity).".to_string(),
+        "Selling n shares pays price * n * (1 - (n + 1) / 2 / liquidity), with the liquidity at least the number of shares.".to_string(),
+        format!(
+            "Trading stays open after hours, during the night, with a spread {} times wider and at most {:.1}% of the shares of a stonk per trade.",
+            AFTER_HOURS_SPREAD_MULTIPLIER,
+            AFTER_HOURS_MAX_STAKE * 100.0
+        ),
+        format!(
+            "In the last {} ticks of the night, buying and selling place orders in the opening auction instead, bidding {:.0}% above or offering {:.0}% below the last price. At dawn every stonk opens at the price matching the most shares, and all the matched orders execute at it.",
+            AUCTION_TICKS,
+            AUCTION_PRICE_BAND * 100.0,
+            AUCTION_PRICE_BAND * 100.0
+        ),
+        String::new(),
+        format!(
+            "{:<24} {:>10} {:>12} {:>22} {:>22}",
+            "Stonk",
+            "Price",
+            "Liquidity",
+            format!("Buy x{}", SPREAD_EXAMPLE_AMOUNT),
+            format!("Sell x{}", SPREAD_EXAMPLE_AMOUNT)
+        ),
+    ];
+    for stonk in market.stonks.iter() {
+        let price = stonk.current_unit_price_cents();
+        let value = price as f64 * SPREAD_EXAMPLE_AMOUNT as f64;
+        let buy = stonk.buy_price(SPREAD_EXAMPLE_AMOUNT);
+        let sell = stonk.sell_price(SPREAD_EXAMPLE_AMOUNT);
+        lines.push(format!(
+            "{:<24} {:>10} {:>12} {:>22} {:>22}",
+            stonk.name,
+            price.format_dollars(),
+            format!("{:.0}", stonk.liquidity()),
+            format!(
+                "{} ({:+.2}%)",
+                buy.format_dollars(),
+                (buy.as_f64_cents() / value - 1.0) * 100.0
+            ),
+            format!(
+                "{} ({:+.2}%)",
+                sell.format_dollars(),
+                (sell.as_f64_cents() / value - 1.0) * 100.0
+            ),
+        ));
+    }
+    ManualPage {
+        title: "Spread",
+        lines,
+    }
+}
+
+fn conditions_page() -> ManualPage {
+    ManualPage {
+        title: "Conditions",
+        lines: vec![
+            "Conditions are temporary effects, on players or on stonks, that expire after some ticks.".to_string(),
+            String::new(),
+            format!(
+                "Ultra vision: for {} ticks you see the drift and volatility of every stonk, and a projection of the possible prices on the stonk chart.",
+                DAY_LENGTH
+            ),
+            format!(
+                "Vacation: from 1 to {} days without trading, during which nobody can target you. Another vacation can only start {} days after the end of the last one.",
+                MAX_VACATION_DAYS, VACATION_COOLDOWN_DAYS
+            ),
+            format!(
+                "Character assassination: when somebody targets you, you have {} ticks to choose a defense before the attack resolves.",
+                ASSASSINATION_WARNING_TICKS
+            ),
+            String::new(),
+            "Bump: shifts the drift of a stonk until it expires. Trades, the global drift and the last price change all apply bumps.".to_string(),
+            format!(
+                "Increased shock probability: the shock probability of a stonk is multiplied by {}, up to {}.",
+                SHOCK_PROBABILITY_MULTIPLIER,
+                percent(MAX_SHOCK_PROBABILITY)
+            ),
+            format!(
+                "Mispriced: during a flash sale a stonk trades at a discount or at a premium of {} to {} for {} ticks, announced in the ticker. The price itself does not move.",
+                percent(FLASH_SALE_MIN_MISPRICING),
+                percent(FLASH_SALE_MAX_MISPRICING),
+                FLASH_SALE_TICKS
+            ),
+        ],
+    }
+}
+
+fn events_page() -> ManualPage {
+    let mut lines = vec![
+        format!(
+            "At night up to {} events are offered to every player, depending on what they own.",
+            MAX_EVENTS_PER_NIGHT
+        ),
+        format!(
+            "Events cost action points, depending on their rarity, and the first one can always be selected. You have one action point, plus one for each of these net worth thresholds: {}.",
+            NIGHT_ACTION_POINT_THRESHOLDS
+                .iter()
+                .map(|threshold| threshold.format_dollars())
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+        "Press backspace to unselect the focused event, or all of them. The selection is locked in shortly before dawn, when the selected events are applied.".to_string(),
+        format!(
+            "Events bumping a class of stonks have diminishing returns: the first bump of the day adds {} to the drift of the class, and each following one {} of the previous, up to {} per day. The effective bumps are shown in the market summary.",
+            CLASS_BUMP,
+            percent(CLASS_BUMP_DIMINISHING_FACTOR),
+            MAX_CLASS_BUMP_PER_DAY
+        ),
+        format!(
+            "Media contacts: with an average share of at least {}% in Media stonks, you hear of the market crashes and character assassinations selected during the night before they hit at dawn. The attackers stay anonymous.",
+            INTEL_MEDIA_STAKE_PERCENT
+        ),
+        String::new(),
+    ];
+    for event in NightEvent::iter() {
+        lines.push(format!(
+            "{} ({}, {} action point{}). Unlocked by: {}. Cost: {}.",
+            event,
+            event.rarity(),
+            event.action_points(),
+            if event.action_points() == 1 { "" } else { "s" },
+            event.unlock_condition_description().join(" "),
+            event.cost_description().join(" ")
+        ));
+    }
+    ManualPage {
+        title: "Events",
+        lines,
+    }
+}
+
+fn board_page() -> ManualPage {
+    let mut lines = vec![
+        format!(
+            "The largest shareholder of a stonk chairs its board while holding more than {} of its shares. Press n on the stonk chart to take a decision.",
+            percent(BOARD_CONTROL_STAKE)
+        ),
+        format!(
+            "The board of a stonk meets at most once every {} ticks, whoever chairs it. Decisions are applied at the next tick and announced in the ticker for {} ticks.",
+            BOARD_DECISION_COOLDOWN_TICKS, BOARD_NEWS_TICKS
+        ),
+        String::new(),
+    ];
+    for decision in BoardDecision::iter() {
+        lines.push(format!("{}: {}", decision.name(), decision.description()));
+    }
+    ManualPage {
+        title: "Board control",
+        lines,
+    }
+}
+
+fn futures_page() -> ManualPage {
+    ManualPage {
+        title: "Futures",
+        lines: vec![
+            format!(
+                "Commodity stonks list futures on their price at the end of the season, every {} days. Press > on the stonk chart to open a contract, long to bet on a rise or short to bet on a fall.",
+                DAYS_PER_SEASON
+            ),
+            format!(
+                "Opening a contract pays a margin of {} of the value of its shares and a {} fee. Contracts are settled at the first dawn of the next season at the closing price: you get back the margin plus the price change over the shares, long or short.",
+                percent(FUTURES_MARGIN),
+                percent(FUTURES_FEE)
+            ),
+            format!(
+                "Gains and losses are capped at the margin. Your contracts on a stonk cover at most {} of its shares, and none can be opened in the last {} days of the season.",
+                percent(FUTURES_MAX_STAKE),
+                FUTURES_CUTOFF_DAYS
+            ),
+        ],
+    }
+}
+
+fn fees_page(market: &Market) -> ManualPage {
+    ManualPage {
+        title: "Fees and limits",
+        lines: vec![
+            format!(
+                "You can make at most {} trades per tick and {} trades per day.",
+                market.trading.max_trades_per_tick, market.trading.max_trades_per_day
+            ),
+            format!(
+                "Press h to switch the trading venue. Dark pool trades pay a {} fee on the trade value, but do not move the price.",
+                percent(DARK_POOL_FEE)
+            ),
+            format!(
+                "The offshore exchange lists the same shares during the day, at a premium or discount of up to {} that wanders on its own and fades over time. Offshore trades pay a {} fee, are limited to {} of the shares and move the premium instead of the price: buy where it is cheaper and sell where it is dearer when the gap beats the fees.",
+                percent(MAX_OFFSHORE_PREMIUM),
+                percent(OFFSHORE_FEE),
+                percent(OFFSHORE_MAX_STAKE)
+            ),
+            format!(
+                "Offshore prices are in doubloons ({}), whose dollar rate wanders day and night between ${:.2} and ${:.2}. Press $ to exchange dollars and doubloons for a {} fee. Doubloons count toward your net worth at the current rate, so holding them is a bet on the rate.",
+                OFFSHORE_CURRENCY_SYMBOL,
+                MIN_EXCHANGE_RATE,
+                MAX_EXCHANGE_RATE,
+                percent(FX_FEE)
+            ),
+            format!(
+                "Accepting a bribe gets you audited for {} days: every trade pays an extra {} fee.",
+                AUDIT_DAYS,
+                percent(AUDIT_FEE)
+            ),
+            format!(
+                "The richest players pay for their lead: for every 10% of the wealth of all players held above an even split, trades pay an extra {} fee, up to {}, and event costs are {} higher. The fee is shown next to the trade prices, the surcharge on the event cards.",
+                percent(WEALTH_FEE_FACTOR / 10.0),
+                percent(MAX_WEALTH_FEE),
+                percent(WEALTH_EVENT_COST_FACTOR / 10.0)
+            ),
+            format!(
+                "The {} richest players at the close are famous until the next one, and pay {} less protection money.",
+                FAMOUS_PLAYERS,
+                percent(FAMOUS_PROTECTION_DISCOUNT)
+            ),
+            format!(
+                "Calling a bluff that works sends the attackers to prison for {} day{}, where they cannot trade.",
+                PRISON_DAYS,
+                if PRISON_DAYS > 1 { "s" } else { "" }
+            ),
+            format!(
+                "The stop-loss order sells everything when your net worth drops {}% below its value when the order was set. While you are on vacation it waits for your return.",
+                SCHEDULED_DRAWDOWN_PERCENT
+            ),
+            "Fees, penalties, haircuts and the cost of events go to the market treasury. The Stimulus and Bailout events pay out of it, as long as it can afford them.".to_string(),
+            format!(
+                "Dividends pay {} of the value of your holdings in the stonk, times its gain of the previous day.",
+                percent(DIVIDEND_PAYOUT)
+            ),
+            format!(
+                "Dumping your holdings to dodge a character assassination sells them with a {} penalty.",
+                percent(DUMP_HOLDINGS_PENALTY)
+            ),
+            format!(
+                "The holdings of inactive accounts are liquidated with a {} haircut.",
+                percent(LIQUIDATION_HAIRCUT)
+            ),
+        ],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::manual_pages;
+    use crate::market::{Market, DARK_POOL_FEE};
+
+    #[test]
+    fn test_manual_uses_engine_values() {
+        let mut market = Market::new();
+        market.trading.max_trades_per_tick = 3;
+        let pages = manual_pages(&market);
+
+        let text = |title: &str| {
+            pages
+                .iter()
+                .find(|page| page.title == title)
+                .map(|page| page.lines.join("\n"))
+                .unwrap_or_default()
+        };
+        assert!(text("Fees and limits").contains("at most 3 trades per tick"));
+        assert!(text("Fees and limits").contains(&format!("{}%", DARK_POOL_FEE * 100.0)));
+        for stonk in market.stonks.iter() {
+            assert!(text("Spread").contains(&stonk.name));
+        }
+    }
+}
@@ -1,22 +1,55 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use crate::{
-    agent::{AgentAction, AgentCondition, DecisionAgent, UserAgent, INITIAL_USER_CASH_CENTS},
-    events::{CHARACTER_ASSASSINATION_COST, DIVIDEND_PAYOUT, MARKET_CRASH_COST},
-    stonk::{DollarValue, Stonk, StonkCondition},
+    agent::{
+        AgentAction, AgentCondition, AssassinationThreat, DecisionAgent, ScheduledOrder, UserAgent,
+        FAMOUS_PLAYERS, INITIAL_USER_CASH, MAX_VACATION_DAYS, PRISON_DAYS,
+        SCHEDULED_DRAWDOWN_PERCENT, VACATION_COOLDOWN_DAYS,
+    },
+    auction::{clear_orders, AuctionOrder, OpeningAuction, AUCTION_PRICE_BAND, AUCTION_TICKS},
+    audit::{self, AuditEvent},
+    board::{
+        BoardDecision, BoardMinutes, Boards, BOARD_BUYBACK_STAKE, BOARD_DIVIDEND_RATE,
+        BOARD_GUIDANCE_BUMP,
+    },
+    bots::BotPopulation,
+    config::{EventsConfig, TradingConfig},
+    events::{
+        action_effects, class_bump_effects, night_action_points, protection_cost, HostileIntel,
+        NightEvent, ASSASSINATION_WARNING_TICKS, CALL_BLUFF_FAILURE_MULTIPLIER,
+        CALL_BLUFF_SUCCESS_PROBABILITY, CHARACTER_ASSASSINATION_COST, CLASS_BUMP,
+        CLASS_BUMP_DIMINISHING_FACTOR, DUMP_HOLDINGS_PENALTY, INTEL_MEDIA_STAKE_PERCENT,
+        MAX_CLASS_BUMP_PER_DAY,
+    },
+    futures::{FuturesBook, FuturesContract, FUTURES_FEE},
+    leaderboard::LeaderboardHistory,
+    load_shedding::TickDetail,
+    logging,
+    market_maker::{trade_spread, MarketMaker},
+    money::Money,
+    offshore::{OffshoreExchange, OFFSHORE_FEE},
+    scenario::{ScenarioRunner, ScriptedEffect},
+    statistics::ServerStatistics,
+    stonk::{DollarValue, Stonk, StonkClass, StonkCondition, Stonks},
+    strategy::MarketView,
+    telemetry::BalanceTelemetry,
+    tournament::{PaperAccount, Tournament},
+    treasury::Treasury,
+    unlock::class_stake,
     utils::{load_stonks_data, AppResult},
 };
-use rand::{Rng, SeedableRng};
+use rand::{seq::SliceRandom, Rng, RngCore, SeedableRng};
 use rand_chacha::ChaCha8Rng;
 use serde::{Deserialize, Serialize};
 use strum::{Display, EnumIter, IntoEnumIterator};
-use tracing::{debug, info};
+use tracing::{debug, error, info};
 
 const DAY_STARTING_HOUR: usize = 6;
 const DAY_LENGTH_HOURS: usize = 18;
 const NIGHT_LENGTH_HOURS: usize = 24 - DAY_LENGTH_HOURS;
 
-const TICKS_PER_HOUR: usize = 4;
+pub const TICKS_PER_HOUR: usize = 4;
 pub const MAX_EVENTS_PER_NIGHT: usize = 3;
 
 // Each second represents 15 minutes => 1 hour = 4 ticks.
@@ -27,11 +60,63 @@ pub const NIGHT_LENGTH: usize = TICKS_PER_HOUR * NIGHT_LENGTH_HOURS; // NIGHT_LE
 pub const HISTORICAL_SIZE: usize = DAY_LENGTH * 30 * 12;
 pub const NUMBER_OF_STONKS: usize = 8;
 
-const BRIBE_AMOUNT: u32 = 10_000 * 100;
+// Dark pool trades do not bump the stonk drift, but pay an extra fee on the trade value.
+pub const DARK_POOL_FEE: f64 = 0.05;
 
-const MAX_GLOBAL_DRIFT: f64 = 0.25;
-const GLOBAL_DRIFT_VOLATILITY: f64 = 0.05;
-const GLOBAL_DRIFT_INTERVAL: usize = DAY_LENGTH;
+/// Where the trades of a player are sent.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Venue {
+    #[default]
+    Exchange,
+    DarkPool,
+    Offshore,
+}
+
+impl Venue {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Exchange => Self::DarkPool,
+            Self::DarkPool => Self::Offshore,
+            Self::Offshore => Self::Exchange,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Exchange => "exchange",
+            Self::DarkPool => "dark pool",
+            Self::Offshore => "offshore",
+        }
+    }
+}
+
+// Audited agents pay an extra fee on the value of every trade.
+pub const AUDIT_FEE: f64 = 0.01;
+// Players holding more than an even share of the wealth of all players pay this extra fee rate,
+// and this extra fraction of the event costs, for each unit of share above it, so that the
+// richest players pay more friction.
+pub const WEALTH_FEE_FACTOR: f64 = 0.1;
+pub const MAX_WEALTH_FEE: f64 = 0.05;
+pub const WEALTH_EVENT_COST_FACTOR: f64 = 1.0;
+// Fraction of the value of the holdings kept by the market when liquidating an agent.
+pub const LIQUIDATION_HAIRCUT: f64 = 0.2;
+
+// Bump of the drift of a stonk for each liquidity worth of shares traded, applied at the next tick.
+pub const TRADE_BUMP_PER_LIQUIDITY: f64 = 100.0;
+// Trades moving at least this stake of a stonk are shown on the tape with the trader name.
+const WHALE_TRADE_STAKE: f64 = 0.01;
+const MAX_TRADE_TAPE_LENGTH: usize = 100;
+
+// Probability that a flash sale starts at a tick of the day, about two per day.
+pub const FLASH_SALE_PROBABILITY: f64 = 2.0 / DAY_LENGTH as f64;
+pub const FLASH_SALE_TICKS: usize = 10;
+// Range of the discount or premium of a flash sale.
+pub const FLASH_SALE_MIN_MISPRICING: f64 = 0.03;
+pub const FLASH_SALE_MAX_MISPRICING: f64 = 0.1;
+
+pub const MAX_GLOBAL_DRIFT: f64 = 0.25;
+pub const GLOBAL_DRIFT_VOLATILITY: f64 = 0.05;
+pub const GLOBAL_DRIFT_INTERVAL: usize = DAY_LENGTH;
 
 #[derive(Debug, Clone, Copy, Display, EnumIter)]
 enum Season {
@@ -61,6 +146,33 @@ impl GamePhase {
         }
     }
 
+    /// Market ticks elapsed since the start of the current day, or of the last one during the
+    /// night. The market tick only advances during the day.
+    pub fn ticks_since_day_start(&self) -> usize {
+        match self {
+            Self::Day { counter, .. } => *counter,
+            Self::Night { .. } => DAY_LENGTH,
+        }
+    }
+
+    /// Ticks of the game clock since the first day. Unlike the market tick, it also advances
+    /// during the night.
+    pub fn clock_tick(&self) -> usize {
+        match self {
+            Self::Day { cycle, counter } => cycle * (DAY_LENGTH + NIGHT_LENGTH) + counter,
+            Self::Night { cycle, counter } => {
+                cycle * (DAY_LENGTH + NIGHT_LENGTH) + DAY_LENGTH + counter
+            }
+        }
+    }
+
+    pub fn cycle(&self) -> usize {
+        match self {
+            Self::Day { cycle, .. } => *cycle,
+            Self::Night { cycle, .. } => *cycle,
+        }
+    }
+
     fn day(&self) -> usize {
         match self {
             Self::Day { cycle, .. } => cycle % 365 + 1,
@@ -83,6 +195,14 @@ impl GamePhase {
         }
     }
 
+    pub fn formatted_date(&self) -> String {
+        format!("{} {} {}", self.day(), self.season(), self.year())
+    }
+
+    pub fn formatted_season(&self) -> String {
+        format!("{} {}", self.season(), self.year())
+    }
+
     pub fn formatted(&self) -> String {
         let time = self.time();
         format!(
@@ -96,16 +216,169 @@ impl GamePhase {
     }
 }
 
+/// Fraction of the trade value the agent pays in fees.
+pub fn trade_fee(value: Money, rate: f64) -> Money {
+    Money::saturating_from_f64_cents((value.as_f64_cents() * rate).ceil())
+}
+
+/// Shares of the stonk the cash can buy, fees included.
+pub fn max_buy_amount_with_fee(stonk: &Stonk, cash: Money, rate: f64) -> u32 {
+    let is_affordable = |amount: u32| {
+        stonk
+            .checked_buy_price(amount)
+            .and_then(|cost| cost.checked_add(trade_fee(cost, rate)))
+            .is_some_and(|cost| cost <= cash)
+    };
+    // The estimate is off by the rounding of the fee, which is rounded up when charged.
+    let mut max_amount = stonk.max_buy_amount(cash.scale(1.0 / (1.0 + rate)));
+    while max_amount > 0 && !is_affordable(max_amount) {
+        max_amount -= 1;
+    }
+    while max_amount < stonk.available_amount() && is_affordable(max_amount + 1) {
+        max_amount += 1;
+    }
+    max_amount
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Trade {
+    pub tick: usize,
+    pub stonk_id: usize,
+    pub amount: u32,
+    pub value: Money,
+    pub is_buy: bool,
+    pub username: Option<String>, // Only whale trades are named
+}
+
+// Class bumps applied during a day, as (class, number of bumps, total bump).
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ClassBumps {
+    cycle: usize,
+    bumps: Vec<(StonkClass, usize, f64)>,
+}
+
+impl ClassBumps {
+    pub fn of_day(&self, cycle: usize) -> &[(StonkClass, usize, f64)] {
+        if self.cycle == cycle {
+            &self.bumps
+        } else {
+            &[]
+        }
+    }
+
+    /// Effective amount of the next bump of the class during the day, with diminishing
+    /// returns and capped to MAX_CLASS_BUMP_PER_DAY.
+    pub fn next_bump(&self, class: StonkClass, cycle: usize) -> f64 {
+        let (count, total) = self
+            .of_day(cycle)
+            .iter()
+            .find(|(bumped, _, _)| *bumped == class)
+            .map(|(_, count, total)| (*count, *total))
+            .unwrap_or_default();
+        (CLASS_BUMP * CLASS_BUMP_DIMINISHING_FACTOR.powi(count as i32))
+            .min(MAX_CLASS_BUMP_PER_DAY - total)
+            .max(0.0)
+    }
+
+    pub fn record(&mut self, class: StonkClass, cycle: usize, amount: f64) {
+        if self.cycle != cycle {
+            self.cycle = cycle;
+            self.bumps.clear();
+        }
+        match self
+            .bumps
+            .iter_mut()
+            .find(|(bumped, _, _)| *bumped == class)
+        {
+            Some((_, count, total)) => {
+                *count += 1;
+                *total += amount;
+            }
+            None => self.bumps.push((class, 1, amount)),
+        }
+    }
+}
+
+// Lightweight view of the market published after every tick.
+#[derive(Debug, Clone, Copy)]
+pub struct MarketSnapshot {
+    pub last_tick: usize,
+    pub phase: GamePhase,
+    pub prices: [u32; NUMBER_OF_STONKS],
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Market {
-    pub stonks: [Stonk; NUMBER_OF_STONKS],
+    pub stonks: Stonks,
     pub last_tick: usize,
     pub phase: GamePhase,
-    initial_total_market_cap: u64,
+    initial_total_market_cap: Money,
+    #[serde(default)]
+    target_total_market_cap: Money,
+    // Shared with the copies of the market, so it is replaced rather than changed.
+    #[serde(default)]
+    pub portfolios: Arc<Vec<(String, Money)>>,
+    #[serde(default)]
+    pub trade_tape: Vec<Trade>, // Most recent trade last
+    #[serde(default)]
+    pub volume_today: Money, // Value of all trades since the day started
     #[serde(default)]
-    target_total_market_cap: u64,
+    pub global_drift: f64,
     #[serde(default)]
-    pub portfolios: Vec<(String, u64)>,
+    pub reputations: HashMap<String, i32>,
+    // Set by the server from its config.
+    #[serde(skip)]
+    pub trading: TradingConfig,
+    // Set by the server from its config.
+    #[serde(skip)]
+    pub events: EventsConfig,
+    // Net drift bump of the trades made since the last tick, applied once per stonk at the next tick.
+    #[serde(default)]
+    pending_bumps: [f64; NUMBER_OF_STONKS],
+    #[serde(default)]
+    pub tournament: Tournament,
+    #[serde(default)]
+    pub market_maker: MarketMaker,
+    #[serde(default)]
+    pub treasury: Treasury,
+    #[serde(default)]
+    pub telemetry: BalanceTelemetry,
+    #[serde(default)]
+    pub bots: BotPopulation,
+    #[serde(default)]
+    pub scenario: ScenarioRunner,
+    #[serde(default)]
+    pub auction: OpeningAuction,
+    #[serde(default)]
+    pub class_bumps: ClassBumps,
+    #[serde(default)]
+    pub boards: Boards,
+    #[serde(default)]
+    pub futures: FuturesBook,
+    #[serde(default)]
+    pub offshore: OffshoreExchange,
+    // Hostile events selected during the night, known to the players with media contacts.
+    #[serde(default)]
+    pub hostile_intel: Vec<HostileIntel>,
+    // Loaded by the server from the history file, then extended at the end of every day.
+    #[serde(skip)]
+    pub leaderboard_history: LeaderboardHistory,
+    // Cached by the server, which refreshes it from the audit log once per day.
+    #[serde(skip)]
+    pub statistics: ServerStatistics,
+    // Source of randomness of the market, seeded by each applied MarketEvent.
+    #[serde(skip, default = "ChaCha8Rng::from_entropy")]
+    pub(crate) rng: ChaCha8Rng,
+    // Seed of the last tick, shown in the debug overlay.
+    #[serde(skip)]
+    pub last_tick_seed: Option<u64>,
+    // Reduced by the server under heavy load, back to full detail when loaded.
+    #[serde(skip)]
+    pub tick_detail: TickDetail,
+    // Number of events applied since the market was loaded, so that clients are only redrawn
+    // when it changed.
+    #[serde(skip)]
+    pub revision: u64,
 }
 
 impl Default for Market {
@@ -119,15 +392,40 @@ impl Market {
         let stonks = load_stonks_data().expect("Failed to load stonks from data");
 
         let mut m = Market {
-            stonks,
+            stonks: stonks.into(),
             last_tick: 0,
             phase: GamePhase::Day {
                 cycle: 0,
                 counter: 0,
             },
-            initial_total_market_cap: 0,
-            target_total_market_cap: 0,
-            portfolios: vec![],
+            initial_total_market_cap: Money::ZERO,
+            target_total_market_cap: Money::ZERO,
+            portfolios: Arc::default(),
+            trade_tape: vec![],
+            volume_today: Money::ZERO,
+            global_drift: 0.0,
+            reputations: HashMap::default(),
+            trading: TradingConfig::default(),
+            events: EventsConfig::default(),
+            pending_bumps: [0.0; NUMBER_OF_STONKS],
+            tournament: Tournament::default(),
+            market_maker: MarketMaker::default(),
+            treasury: Treasury::default(),
+            telemetry: BalanceTelemetry::default(),
+            bots: BotPopulation::default(),
+            scenario: ScenarioRunner::default(),
+            auction: OpeningAuction::default(),
+            class_bumps: ClassBumps::default(),
+            boards: Boards::default(),
+            futures: FuturesBook::default(),
+            offshore: OffshoreExchange::default(),
+            hostile_intel: vec![],
+            leaderboard_history: LeaderboardHistory::default(),
+            statistics: ServerStatistics::default(),
+            rng: ChaCha8Rng::from_entropy(),
+            last_tick_seed: None,
+            tick_detail: TickDetail::Full,
+            revision: 0,
         };
 
         m.initial_total_market_cap = m.total_market_cap();
@@ -144,349 +442,2929 @@ impl Market {
         }
 
         info!(
-            "Current total market cap: ${}",
-            m.total_market_cap().format()
+            "Current total market cap: {}",
+            m.total_market_cap().format_dollars()
         );
 
         m
     }
 
-    pub fn total_market_cap(&self) -> u64 {
-        self.stonks
-            .iter()
-            .map(|stonk| stonk.market_cap_cents() as u64)
-            .sum::<u64>()
+    pub fn total_market_cap(&self) -> Money {
+        self.stonks.iter().map(|stonk| stonk.market_cap()).sum()
+    }
+
+    pub fn snapshot(&self) -> MarketSnapshot {
+        MarketSnapshot {
+            last_tick: self.last_tick,
+            phase: self.phase,
+            prices: std::array::from_fn(|id| self.stonks[id].current_unit_price_cents()),
+        }
     }
 
-    pub fn update_target_total_market_cap(&mut self, number_of_agents: usize) -> u64 {
-        self.target_total_market_cap = self.initial_total_market_cap
-            + number_of_agents as u64 * INITIAL_USER_CASH_CENTS as u64;
+    pub fn update_target_total_market_cap(&mut self, number_of_agents: usize) -> Money {
+        self.target_total_market_cap = self
+            .initial_total_market_cap
+            .saturating_add(INITIAL_USER_CASH.saturating_mul(number_of_agents as u64));
         self.target_total_market_cap
     }
 
     pub fn update_portfolios(
         &mut self,
         agents: &HashMap<String, UserAgent>,
-    ) -> &Vec<(String, u64)> {
+    ) -> &Vec<(String, Money)> {
         let mut portfolios = vec![];
         for (username, agent) in agents.iter() {
-            let agent_value = agent
-                .owned_stonks()
-                .iter()
-                .enumerate()
-                .map(|(stonk_id, amount)| {
-                    let stonk = &self.stonks[stonk_id];
-                    stonk.current_unit_price_cents() as u64 * *amount as u64
-                })
-                .sum::<u64>()
-                + agent.cash() as u64;
-            if agent_value > 0 {
+            let agent_value = self.net_worth(agent);
+            if agent_value > Money::ZERO {
                 portfolios.push((username.clone(), agent_value));
             }
         }
 
-        portfolios.sort_by(|(_, a), (_, b)| b.cmp(a));
+        portfolios.sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then_with(|| a_name.cmp(b_name)));
 
-        self.portfolios = portfolios;
+        self.portfolios = Arc::new(portfolios);
 
         &self.portfolios
     }
 
-    pub fn tick_day(&mut self, rng: &mut ChaCha8Rng) {
-        let global_drift = if self.last_tick % GLOBAL_DRIFT_INTERVAL == 0 {
-            let current_market_cap = self.total_market_cap() as f64;
-            let mean = (self.target_total_market_cap as f64 - current_market_cap)
-                / current_market_cap.min(self.target_total_market_cap as f64);
-            let drift = (mean + rng.gen_range(-GLOBAL_DRIFT_VOLATILITY..GLOBAL_DRIFT_VOLATILITY))
-                .min(MAX_GLOBAL_DRIFT)
-                .max(-MAX_GLOBAL_DRIFT);
-
-            info!(
-                "Global drift: current cap {}, target cap {}, global drift {}",
-                current_market_cap, self.target_total_market_cap, drift
-            );
-            Some(drift)
-        } else {
-            None
+    /// Share of the wealth of all players held by the agent above an even split, from the
+    /// cached portfolio rankings. Zero for the players at or below the average.
+    pub fn excess_wealth_share(&self, username: &str) -> f64 {
+        let total = self
+            .portfolios
+            .iter()
+            .map(|(_, value)| *value)
+            .sum::<Money>()
+            .as_f64_cents();
+        let Some((_, value)) = self
+            .portfolios
+            .iter()
+            .find(|(holder, _)| holder == username)
+        else {
+            return 0.0;
         };
+        if total <= 0.0 {
+            return 0.0;
+        }
+        (value.as_f64_cents() / total - 1.0 / self.portfolios.len() as f64).max(0.0)
+    }
 
-        for stonk in self.stonks.iter_mut() {
-            if let Some(drift) = global_drift {
-                stonk.add_condition(
-                    StonkCondition::Bump { amount: drift },
-                    self.last_tick + GLOBAL_DRIFT_INTERVAL,
-                );
+    pub fn wealth_fee_rate(&self, username: &str) -> f64 {
+        (WEALTH_FEE_FACTOR * self.excess_wealth_share(username)).min(MAX_WEALTH_FEE)
+    }
+
+    pub fn trade_fee_rate<A: DecisionAgent>(&self, agent: &A, venue: Venue) -> f64 {
+        let mut rate = self.wealth_fee_rate(agent.username());
+        match venue {
+            Venue::Exchange => {}
+            Venue::DarkPool => rate += DARK_POOL_FEE,
+            Venue::Offshore => rate += OFFSHORE_FEE,
+        }
+        if agent.has_condition(AgentCondition::Audited) {
+            rate += AUDIT_FEE;
+        }
+        rate
+    }
+
+    /// The cost of an event for the agent, raised with its share of the wealth.
+    pub fn event_cost(&self, username: &str, cost: Money) -> Money {
+        cost.scale(1.0 + WEALTH_EVENT_COST_FACTOR * self.excess_wealth_share(username))
+    }
+
+    pub fn net_worth(&self, agent: &UserAgent) -> Money {
+        agent
+            .owned_stonks()
+            .iter()
+            .enumerate()
+            .map(|(stonk_id, amount)| self.stonks[stonk_id].holdings_value(*amount))
+            .sum::<Money>()
+            .saturating_add(agent.cash())
+            .saturating_add(
+                self.offshore
+                    .exchange_rate
+                    .to_dollars(agent.offshore_cash()),
+            )
+            .saturating_add(self.futures.value(agent.username(), &self.stonks))
+    }
+
+    /// Returns the actions to take if the agent scheduled order is triggered.
+    pub fn scheduled_order_actions(&self, agent: &UserAgent) -> Option<Vec<AgentAction>> {
+        match agent.scheduled_order()? {
+            ScheduledOrder::SellAllOnDrawdown { reference_value } => {
+                if self.net_worth(agent) > reference_value.percent(100 - SCHEDULED_DRAWDOWN_PERCENT)
+                {
+                    return None;
+                }
+                Some(
+                    agent
+                        .owned_stonks()
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, &amount)| amount > 0)
+                        .map(|(stonk_id, &amount)| AgentAction::Sell { stonk_id, amount })
+                        .collect(),
+                )
             }
-            stonk.tick(self.last_tick);
-            while stonk.historical_prices.len() > HISTORICAL_SIZE {
-                stonk.historical_prices.remove(0);
+            ScheduledOrder::BuyAtOpen { stonk_id, amount } => {
+                if !matches!(self.phase, GamePhase::Day { counter: 0, .. }) {
+                    return None;
+                }
+                let amount = (*amount).min(max_buy_amount_with_fee(
+                    &self.stonks[*stonk_id],
+                    agent.cash(),
+                    self.trade_fee_rate(agent, Venue::Exchange),
+                ));
+                if amount == 0 {
+                    return Some(vec![]);
+                }
+                Some(vec![AgentAction::Buy {
+                    stonk_id: *stonk_id,
+                    amount,
+                }])
             }
         }
-        self.last_tick += 1;
     }
 
-    fn tick_night(&mut self, _rng: &mut ChaCha8Rng) {}
-
-    pub fn tick(&mut self) {
-        debug!("\nMarket tick {:?}", self.phase);
-        for stonk in self.stonks.iter() {
-            debug!(
-                "Stonk availability: {} out of {} ({} bought)",
-                stonk.available_amount(),
-                stonk.number_of_shares,
-                stonk.allocated_shares
-            );
+    /// Whether the night event selection is locked in until dawn.
+    pub fn night_selection_locked(&self) -> bool {
+        match self.phase {
+            GamePhase::Night { counter, .. } => {
+                counter + self.events.selection_lock_ticks >= NIGHT_LENGTH
+            }
+            GamePhase::Day { .. } => false,
         }
-        let rng = &mut ChaCha8Rng::from_entropy();
+    }
+
+    /// Rolls the night events of every agent when the night starts, and drops them when the
+    /// day starts. Rolling once, at the close, makes every session of a player see the same
+    /// cards, unlocked by the closing prices rather than by the moment the night was drawn.
+    pub fn update_night_events(&mut self, agents: &mut HashMap<String, UserAgent>) {
         match self.phase {
-            GamePhase::Day { cycle, counter } => {
-                self.tick_day(rng);
-                if counter < DAY_LENGTH - 1 {
-                    self.phase = GamePhase::Day {
-                        cycle,
-                        counter: counter + 1,
-                    }
-                } else {
-                    self.phase = GamePhase::Night { cycle, counter: 0 }
+            GamePhase::Day { counter: 0, .. } => {
+                for agent in agents.values_mut() {
+                    agent.set_available_night_events(vec![]);
                 }
             }
-            GamePhase::Night { cycle, counter } => {
-                self.tick_night(rng);
-                if counter < NIGHT_LENGTH - 1 {
-                    self.phase = GamePhase::Night {
-                        cycle,
-                        counter: counter + 1,
-                    };
-                } else {
-                    self.phase = GamePhase::Day {
-                        cycle: cycle + 1,
-                        counter: 0,
+            GamePhase::Night { counter: 0, .. } => {
+                // The richest players at the close are famous until the next one.
+                for (username, _) in self.portfolios.iter().take(FAMOUS_PLAYERS) {
+                    if let Some(agent) = agents.get_mut(username) {
+                        agent.add_condition(AgentCondition::Famous, self.last_tick + DAY_LENGTH);
+                    }
+                }
+                let character_assassination_events = self.character_assassination_events(agents);
+                let rng = &mut self.rng.clone();
+                // Sorted, so that the outcome does not depend on the iteration order of the map.
+                let mut usernames = agents.keys().cloned().collect::<Vec<String>>();
+                usernames.sort();
+                for username in usernames {
+                    let Some(agent) = agents.get_mut(&username) else {
+                        continue;
                     };
+                    let events =
+                        self.roll_agent_night_events(agent, &character_assassination_events, rng);
+                    agent.clear_actions();
+                    agent.add_day_played();
+                    for event in events.iter() {
+                        self.telemetry.record_offered(&event.to_string());
+                    }
+                    audit::record(AuditEvent::NightEventsOffered {
+                        username: username.clone(),
+                        events: events.clone(),
+                    });
+                    agent.set_available_night_events(events);
+                    agent.set_night_action_points(night_action_points(
+                        self.portfolio_value(&username),
+                    ));
                 }
+                self.rng = rng.clone();
             }
+            _ => {}
         }
     }
 
-    pub fn apply_agent_action<A: DecisionAgent>(
-        &mut self,
-        agent: &mut A,
-        agents: &mut HashMap<String, A>,
-    ) -> AppResult<()> {
-        if let Some(action) = agent.selected_action().cloned().as_ref() {
-            agent.clear_action();
-            info!("Applying action {:?}", action);
-
-            match action {
-                AgentAction::Buy { stonk_id, amount } => {
-                    let stonk = &mut self.stonks[*stonk_id];
-                    let max_amount = stonk.available_amount();
-                    if max_amount < *amount {
-                        return Err("Not enough shares available".into());
-                    }
+    /// Collects the hostile events selected for the next morning, while the night lasts.
+    pub fn update_hostile_intel(&mut self, agents: &HashMap<String, UserAgent>) {
+        self.hostile_intel.clear();
+        if !matches!(self.phase, GamePhase::Night { .. }) {
+            return;
+        }
+        let mut usernames = agents.keys().collect::<Vec<&String>>();
+        usernames.sort();
+        for username in usernames {
+            for action in agents[username].selected_actions() {
+                if let Some(intel) = HostileIntel::from_action(action) {
+                    self.hostile_intel.push(intel);
+                }
+            }
+        }
+    }
 
-                    let cost = stonk.buy_price_cents(*amount);
-                    agent.sub_cash(cost)?;
+    /// Settles the futures at the first dawn of a season, at the closing price of the last one.
+    /// The market keeps the margins of the players that are gone.
+    pub fn settle_futures(&mut self, agents: &mut HashMap<String, UserAgent>) {
+        let GamePhase::Day { cycle, .. } = self.phase else {
+            return;
+        };
+        for contract in self.futures.take_due(cycle) {
+            let Some(agent) = agents.get_mut(&contract.username) else {
+                self.treasury.deposit(contract.margin);
+                continue;
+            };
+            self.settle_futures_contract(&contract, agent);
+        }
+    }
 
-                    agent.add_stonk(*stonk_id, *amount)?;
-                    stonk.allocate_shares_to_agent(agent.username(), *amount)?;
+    /// Settles the contract at the current price. The market pays the profits out of the
+    /// treasury, printing the money it lacks, and keeps the margins lost.
+    fn settle_futures_contract(&mut self, contract: &FuturesContract, agent: &mut UserAgent) {
+        let value = contract.value(self.stonks[contract.stonk_id].current_unit_price_cents());
+        info!(
+            "Futures of {} on {} settled for {}",
+            contract.username,
+            self.stonks[contract.stonk_id].name,
+            value.format_dollars()
+        );
+        if value > contract.margin {
+            let profit = value.saturating_sub(contract.margin);
+            if self.treasury.withdraw(profit).is_err() {
+                audit::record(AuditEvent::CashPrinted {
+                    username: contract.username.clone(),
+                    amount_cents: profit,
+                });
+            }
+        } else {
+            self.treasury.deposit(contract.margin.saturating_sub(value));
+        }
+        if let Err(e) = agent.add_cash(value) {
+            error!("Could not settle futures of {}: {}", contract.username, e);
+        }
+    }
 
-                    info!(
-                        "{} stonks bought, there are now {} available ({} total bought)",
-                        amount,
-                        stonk.available_amount(),
-                        stonk.allocated_shares
-                    );
+    /// Whether the agent holds enough Media stonks to hear of the hostile events in advance.
+    pub fn has_media_contacts<A: DecisionAgent>(&self, agent: &A) -> bool {
+        class_stake(agent, self, StonkClass::Media)
+            .is_some_and(|stake| stake >= INTEL_MEDIA_STAKE_PERCENT)
+    }
 
-                    let bump_amount = stonk.to_stake(*amount) * 100.0;
-                    stonk.add_condition(
-                        StonkCondition::Bump {
-                            amount: bump_amount,
-                        },
-                        self.last_tick + 1,
-                    );
+    // CharacterAssassination events common to every agent, targeting the top shareholders
+    // that accepted a bribe.
+    fn character_assassination_events(
+        &self,
+        agents: &HashMap<String, UserAgent>,
+    ) -> Vec<NightEvent> {
+        let mut events = vec![];
+        let mut usernames = vec![];
+        for stonk in self.stonks.iter() {
+            for (username, _) in stonk.shareholders.iter().take(5) {
+                if usernames.contains(&username) {
+                    continue;
                 }
-                AgentAction::Sell { stonk_id, amount } => {
-                    let stonk = &mut self.stonks[*stonk_id];
-
-                    let cost = stonk.sell_price_cents(*amount);
-                    agent.add_cash(cost)?;
-                    agent.sub_stonk(*stonk_id, *amount)?;
-                    stonk.deallocate_shares_to_agent(agent.username(), *amount)?;
-
-                    info!(
-                        "{} stonks sold, there are now {} available ({} total bought)",
-                        amount,
-                        stonk.available_amount(),
-                        stonk.allocated_shares
-                    );
 
-                    let bump_amount = stonk.to_stake(*amount) * 100.0;
-                    stonk.add_condition(
-                        StonkCondition::Bump {
-                            amount: -bump_amount,
-                        },
-                        self.last_tick + 1,
-                    );
-                }
-                AgentAction::BumpStonkClass { class } => {
-                    for stonk in self.stonks.iter_mut().filter(|s| s.class == *class) {
-                        stonk.add_condition(
-                            StonkCondition::Bump { amount: 4.0 },
-                            self.last_tick + DAY_LENGTH,
-                        )
-                    }
-                }
-                AgentAction::CrashAll => {
-                    for stonk in self.stonks.iter_mut() {
-                        stonk.add_condition(
-                            StonkCondition::Bump { amount: -4.0 },
-                            self.last_tick + DAY_LENGTH,
-                        );
-                        stonk.add_condition(
-                            StonkCondition::IncreasedShockProbability,
-                            self.last_tick + DAY_LENGTH,
-                        )
+                if let Some(agent) = agents.get(username) {
+                    if agent.past_actions().contains(&AgentAction::AcceptBribe)
+                        && !agent
+                            .past_actions()
+                            .contains(&AgentAction::AssassinationVictim)
+                        && agent.assassination_threats().is_empty()
+                        && !agent.has_condition(AgentCondition::Vacation)
+                    {
+                        usernames.push(username);
+                        events.push(NightEvent::CharacterAssassination {
+                            username: username.clone(),
+                        })
                     }
-                    agent.sub_cash(MARKET_CRASH_COST)?;
-                }
-                AgentAction::AddCash { amount } => {
-                    agent.add_cash(*amount)?;
                 }
+            }
+        }
+        events
+    }
 
-                AgentAction::AcceptBribe => {
-                    agent.add_cash(BRIBE_AMOUNT)?;
-                }
+    fn roll_agent_night_events(
+        &self,
+        agent: &UserAgent,
+        character_assassination_events: &[NightEvent],
+        rng: &mut ChaCha8Rng,
+    ) -> Vec<NightEvent> {
+        let mut events = NightEvent::iter()
+            .filter(|e| {
+                !matches!(
+                    e,
+                    NightEvent::CharacterAssassination { .. } | NightEvent::ReceiveDividends { .. }
+                )
+            })
+            .chain(character_assassination_events.iter().cloned())
+            // Add ReceiveDividends events for each stonk
+            .chain(
+                self.stonks
+                    .iter()
+                    .map(|stonk| NightEvent::ReceiveDividends { stonk_id: stonk.id }),
+            )
+            .filter(|e| e.unlock_condition(&self.events).evaluate(agent, self, rng))
+            .collect::<Vec<NightEvent>>();
 
-                AgentAction::OneDayUltraVision => {
-                    agent.add_condition(AgentCondition::UltraVision, self.last_tick + DAY_LENGTH)
-                }
-                AgentAction::CrashAgentStonks { username } => {
-                    if let Some(target) = agents.get_mut(username) {
-                        target.insert_past_selected_actions(
-                            AgentAction::AssassinationVictim,
-                            self.last_tick,
-                        );
+        debug!("Got events {:#?}", events);
+        events.retain(|e| self.events.rarity_weight(&e.rarity()) > 0.0);
+        let mut events = events
+            .choose_multiple_weighted(rng, MAX_EVENTS_PER_NIGHT, |e| {
+                self.events.rarity_weight(&e.rarity())
+            })
+            .map(|selected| selected.cloned().collect::<Vec<NightEvent>>())
+            .unwrap_or_default();
+        events.shuffle(rng);
+        events
+    }
 
-                        for (stonk_id, &amount) in target.owned_stonks().iter().enumerate() {
-                            let stonk = &mut self.stonks[stonk_id];
-                            let stake = stonk.to_stake(amount);
-                            stonk.add_condition(
-                                StonkCondition::Bump {
-                                    amount: 10.0 * stake,
-                                },
-                                self.last_tick + DAY_LENGTH,
-                            );
-                            stonk.add_condition(
-                                StonkCondition::IncreasedShockProbability,
-                                self.last_tick + DAY_LENGTH,
-                            );
-                        }
-                        agent.sub_cash(CHARACTER_ASSASSINATION_COST)?;
-                    }
-                }
-                AgentAction::AssassinationVictim => {}
-                AgentAction::GetDividends { stonk_id } => {
-                    let stonk = &self.stonks[*stonk_id];
-                    let yesterday_opening_price =
-                        stonk.historical_prices[stonk.historical_prices.len() - DAY_LENGTH];
-                    let yesterday_closing_price =
-                        stonk.historical_prices[stonk.historical_prices.len() - 1];
+    /// Records the returns of the stonks over the closed day when the night starts.
+    pub fn audit_closed_day(&self) {
+        let GamePhase::Night { cycle, counter: 0 } = self.phase else {
+            return;
+        };
+        let Some(day_start_tick) = self.last_closed_day_start_tick() else {
+            return;
+        };
+        audit::record(AuditEvent::DayClosed {
+            cycle,
+            stonk_returns: self
+                .stonks
+                .iter()
+                .filter_map(|stonk| {
+                    stonk
+                        .day_return(day_start_tick)
+                        .map(|day_return| (stonk.name.clone(), day_return))
+                })
+                .collect(),
+        });
+    }
 
-                    if yesterday_opening_price >= yesterday_closing_price
-                        || yesterday_opening_price == 0
-                    {
-                        panic!("This should have been checked before")
-                    }
+    /// Closes the balance report of the day when the night starts, then scans the wealth
+    /// of a batch of players at every tick until the report is complete.
+    pub fn update_telemetry(&mut self, agents: &HashMap<String, UserAgent>) {
+        if !self.telemetry.enabled {
+            return;
+        }
 
-                    let yesterday_gain = (yesterday_closing_price - yesterday_opening_price) as f64
-                        / yesterday_opening_price as f64;
+        if let GamePhase::Night { counter: 0, .. } = self.phase {
+            let mut usernames = agents.keys().cloned().collect::<Vec<String>>();
+            usernames.sort();
+            let class_returns = self.class_returns();
+            self.telemetry
+                .close_day(self.phase, class_returns, usernames);
+        }
 
-                    let dividend = (agent.owned_stonks()[*stonk_id] as f64
-                        * stonk.current_unit_price_cents() as f64
-                        * DIVIDEND_PAYOUT
-                        * yesterday_gain) as u32;
+        if !self.telemetry.is_scanning() {
+            return;
+        }
+        for username in self.telemetry.next_scan_batch() {
+            // Players that left since the day closed are skipped.
+            if let Some(agent) = agents.get(&username) {
+                let net_worth = self.net_worth(agent);
+                self.telemetry.record_player(agent.days_played(), net_worth);
+            }
+        }
+        if self.telemetry.scan_is_done() {
+            self.telemetry.finish_scan();
+        }
+    }
 
-                    agent.add_cash(dividend)?;
+    // Average return of the stonks of each class over the last closed day.
+    fn class_returns(&self) -> Vec<(StonkClass, f64)> {
+        let Some(day_start_tick) = self.last_closed_day_start_tick() else {
+            return vec![];
+        };
+        let mut returns: Vec<(StonkClass, f64, usize)> = vec![];
+        for stonk in self.stonks.iter() {
+            let Some(day_return) = stonk.day_return(day_start_tick) else {
+                continue;
+            };
+            match returns
+                .iter_mut()
+                .find(|(class, _, _)| *class == stonk.class)
+            {
+                Some((_, total, count)) => {
+                    *total += day_return;
+                    *count += 1;
                 }
+                None => returns.push((stonk.class, day_return, 1)),
             }
-            agent.insert_past_selected_actions(action.clone(), self.last_tick);
         }
-        Ok(())
+        returns
+            .into_iter()
+            .map(|(class, total, count)| (class, total / count as f64))
+            .collect()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::{Market, HISTORICAL_SIZE};
-    use crate::{
-        agent::{DecisionAgent, UserAgent},
-        ssh_client::SessionAuth,
-        ui::{render_stonk, UiOptions, ZoomLevel},
-        utils::AppResult,
-    };
-    use rand::SeedableRng;
-    use rand_chacha::ChaCha8Rng;
-    use ratatui::{backend::CrosstermBackend, Terminal};
-    use std::{thread, time::Duration};
+    /// Executes the triggered scheduled orders, consuming them.
+    pub fn execute_scheduled_orders(&mut self, agents: &mut HashMap<String, UserAgent>) {
+        if !matches!(self.phase, GamePhase::Day { .. }) {
+            return;
+        }
 
-    #[test]
-    fn test_market() -> AppResult<()> {
-        let mut market = Market::new();
+        // Trading is disabled on vacation, so the orders of vacationing agents wait for
+        // their return instead of failing.
+        let mut triggered = agents
+            .values()
+            .filter(|agent| !agent.has_condition(AgentCondition::Vacation))
+            .filter_map(|agent| {
+                self.scheduled_order_actions(agent)
+                    .map(|actions| (agent.username().to_string(), actions))
+            })
+            .collect::<Vec<(String, Vec<AgentAction>)>>();
+        triggered.sort_by(|(a, _), (b, _)| a.cmp(b));
 
-        let price_per_share_in_cents = 50 * 100;
-        let number_of_shares = 10_000;
-        let drift = 0.0;
-        let drift_volatility = 0.0002;
-        let shock_probability = 0.05;
-        let volatility = 0.00005;
-
-        for stonk in market.stonks.iter_mut() {
-            stonk.set_test_values(
-                price_per_share_in_cents,
-                number_of_shares,
-                drift,
-                drift_volatility,
-                volatility,
-                shock_probability,
-            );
+        for (username, actions) in triggered {
+            let mut agent = agents[&username].clone();
+            agent.set_scheduled_order(None);
+            for action in actions {
+                agent.select_action(action);
+            }
+            self.apply_agent_actions(&mut agent, agents)
+                .unwrap_or_else(|e| {
+                    error!("Could not execute {} scheduled order: {}", username, e)
+                });
+            agents.insert(username, agent);
         }
+    }
+
+    /// Trades the agent can still make in this tick and in this day.
+    pub fn remaining_trades<A: DecisionAgent>(&self, agent: &A) -> (u32, u32) {
+        let counter = agent.trade_counter();
+        (
+            self.trading
+                .max_trades_per_tick
+                .saturating_sub(counter.trades_this_tick(self.phase.clock_tick())),
+            self.trading
+                .max_trades_per_day
+                .saturating_sub(counter.trades_today(self.phase.cycle())),
+        )
+    }
+
+    /// Auction orders are accepted during the last ticks of the night.
+    pub fn auction_is_open(&self) -> bool {
+        matches!(self.phase, GamePhase::Night { counter, .. } if counter + AUCTION_TICKS >= NIGHT_LENGTH)
+    }
+
+    /// Limit price of the auction orders placed from the game, at the edge of the band around
+    /// the last price.
+    pub fn auction_limit_price(&self, stonk_id: usize, is_buy: bool) -> u32 {
+        let price = self.stonks[stonk_id].current_unit_price_cents() as f64;
+        let band = if is_buy {
+            1.0 + AUCTION_PRICE_BAND
+        } else {
+            1.0 - AUCTION_PRICE_BAND
+        };
+        ((price * band).round() as u32).max(1)
+    }
+
+    /// Shares the agent can still order to buy in the auction, at the limit price of the game.
+    pub fn auction_max_buy_amount<A: DecisionAgent>(&self, stonk_id: usize, agent: &A) -> u32 {
+        let (bought, _) = self.auction.ordered_amounts(agent.username(), stonk_id);
+        let limit_price = self.auction_limit_price(stonk_id, true) as u64;
+        let affordable = (agent.cash().cents() / limit_price).min(u32::MAX as u64) as u32;
+        affordable.saturating_sub(bought)
+    }
 
-        let rng = &mut ChaCha8Rng::from_entropy();
-        while market.last_tick < HISTORICAL_SIZE {
-            market.tick_day(rng)
+    /// Shares the agent can still order to sell in the auction.
+    pub fn auction_max_sell_amount<A: DecisionAgent>(&self, stonk_id: usize, agent: &A) -> u32 {
+        let (_, sold) = self.auction.ordered_amounts(agent.username(), stonk_id);
+        agent.owned_stonks()[stonk_id].saturating_sub(sold)
+    }
+
+    /// Runs the opening auction at dawn: orders the agents cannot honour anymore are dropped,
+    /// then the matched orders of every stonk are executed at its clearing price.
+    pub fn run_opening_auction(&mut self, agents: &mut HashMap<String, UserAgent>) {
+        if !matches!(self.phase, GamePhase::Day { counter: 0, .. }) {
+            return;
+        }
+        let mut orders = self.auction.take_orders();
+        if orders.is_empty() {
+            return;
         }
 
-        let mut agent = UserAgent::new(SessionAuth::default());
-        agent.add_condition(
-            crate::agent::AgentCondition::UltraVision,
-            market.last_tick + 1,
-        );
-        let mut ui_options = UiOptions::new();
-        ui_options.focus_on_stonk = Some(0);
-        ui_options.zoom_level = ZoomLevel::Max;
-
-        // create crossterm terminal to stdout
-        let backend = CrosstermBackend::new(std::io::stdout());
-        let mut terminal = Terminal::new(backend).unwrap();
-
-        let mut idx = 0;
-        loop {
-            thread::sleep(Duration::from_millis(1000));
-            ui_options.focus_on_stonk = Some(idx % market.stonks.len());
-            terminal.draw(|frame| {
-                let area = frame.size();
-                render_stonk(frame, &market, &agent, &ui_options, area)
-                    .expect("Failed to render stonk");
-            })?;
-
-            // if idx > 1 && idx % market.stonks.len() == 0 {
-            //     ui_options.zoom_level = ui_options.zoom_level.next();
-            // }
-            if idx == market.stonks.len() * (ZoomLevel::Max as usize + 1) {
-                break;
-            }
-            idx += 1;
+        // The oldest orders are kept first, buys reserving the cash at their limit price.
+        let mut reserved_cash: HashMap<String, Money> = HashMap::new();
+        let mut reserved_shares: HashMap<(String, usize), u32> = HashMap::new();
+        // Orders on suspended stonks are dropped, since their price cannot be discovered.
+        orders.retain(|order| {
+            if self.stonks[order.stonk_id].is_suspended() {
+                return false;
+            }
+            let Some(agent) = agents.get(&order.username) else {
+                return false;
+            };
+            if order.is_buy {
+                let reserved = reserved_cash.entry(order.username.clone()).or_default();
+                let total = reserved.saturating_add(
+                    Money::from(order.limit_price).saturating_mul(order.amount as u64),
+                );
+                if total > agent.cash() {
+                    return false;
+                }
+                *reserved = total;
+            } else {
+                let reserved = reserved_shares
+                    .entry((order.username.clone(), order.stonk_id))
+                    .or_default();
+                let total = reserved.saturating_add(order.amount);
+                if total > agent.owned_stonks()[order.stonk_id] {
+                    return false;
+                }
+                *reserved = total;
+            }
+            true
+        });
+
+        let mut results = vec![];
+        for stonk_id in 0..NUMBER_OF_STONKS {
+            let reference_price = self.stonks[stonk_id].current_unit_price_cents();
+            let Some((result, mut fills)) = clear_orders(stonk_id, &orders, reference_price) else {
+                continue;
+            };
+            // Sellers first, so that the shares are free when the buyers get them.
+            fills.sort_by_key(|fill| fill.is_buy);
+            for fill in fills {
+                let Some(agent) = agents.get_mut(&fill.username) else {
+                    continue;
+                };
+                self.execute_auction_fill(agent, &fill, result.price)
+                    .unwrap_or_else(|e| {
+                        error!("Could not execute {} auction order: {}", fill.username, e)
+                    });
+            }
+            results.push(result);
+        }
+        self.auction.set_results(self.phase.cycle(), results);
+    }
+
+    fn execute_auction_fill(
+        &mut self,
+        agent: &mut UserAgent,
+        fill: &AuctionOrder,
+        price: u32,
+    ) -> AppResult<()> {
+        let value = Money::from(price).saturating_mul(fill.amount as u64);
+        let stonk = &mut self.stonks[fill.stonk_id];
+        // Each step is undone if the next one fails, so that a failed fill changes nothing.
+        if fill.is_buy {
+            stonk.allocate_shares_to_agent(agent.username(), fill.amount)?;
+            if let Err(e) = agent.sub_cash(value) {
+                stonk.deallocate_shares_to_agent(agent.username(), fill.amount)?;
+                return Err(e);
+            }
+            agent.add_stonk(fill.stonk_id, fill.amount)?;
+        } else {
+            agent.sub_stonk(fill.stonk_id, fill.amount)?;
+            if let Err(e) = stonk.deallocate_shares_to_agent(agent.username(), fill.amount) {
+                agent.add_stonk(fill.stonk_id, fill.amount)?;
+                return Err(e);
+            }
+            agent.add_cash(value)?;
+        }
+        self.volume_today = self.volume_today.saturating_add(value);
+        self.record_trade(
+            agent.username(),
+            fill.stonk_id,
+            fill.amount,
+            value,
+            fill.is_buy,
+        );
+        Ok(())
+    }
+
+    pub fn portfolio_value(&self, username: &str) -> Money {
+        self.portfolios
+            .iter()
+            .find(|(holder, _)| holder == username)
+            .map(|(_, value)| *value)
+            .unwrap_or_default()
+    }
+
+    pub fn update_reputations(
+        &mut self,
+        agents: &HashMap<String, UserAgent>,
+    ) -> &HashMap<String, i32> {
+        self.reputations = agents
+            .iter()
+            .map(|(username, agent)| (username.clone(), agent.reputation()))
+            .collect();
+        &self.reputations
+    }
+
+    pub fn reputation_of(&self, username: &str) -> i32 {
+        self.reputations.get(username).copied().unwrap_or_default()
+    }
+
+    /// Tick at which the current day started, or the last one during the night.
+    pub fn day_start_tick(&self) -> usize {
+        self.last_tick
+            .saturating_sub(self.phase.ticks_since_day_start())
+    }
+
+    /// Tick at which the last complete day started, if any.
+    pub fn last_closed_day_start_tick(&self) -> Option<usize> {
+        match self.phase {
+            GamePhase::Day { .. } => self.day_start_tick().checked_sub(DAY_LENGTH),
+            GamePhase::Night { .. } => Some(self.day_start_tick()),
+        }
+    }
+
+    /// Moves the price histories loaded from an older store to end at the current tick.
+    pub fn align_price_history(&mut self) {
+        for stonk in self.stonks.iter_mut() {
+            stonk.historical_prices.align_end(self.last_tick);
+        }
+    }
+
+    /// Average price over the last day of history, or the current price without history.
+    pub fn fair_value(&self, stonk_id: usize) -> u32 {
+        let stonk = &self.stonks[stonk_id];
+        let end_tick = stonk.historical_prices.end_tick();
+        let prices = stonk
+            .historical_prices
+            .window(end_tick.saturating_sub(DAY_LENGTH), end_tick);
+        if prices.is_empty() {
+            return stonk.current_unit_price_cents();
+        }
+        (prices.iter().map(|price| *price as u64).sum::<u64>() / prices.len() as u64) as u32
+    }
+
+    // Lets the market maker take its share of a trade, returning the drift bump of the rest.
+    fn absorb_trade(&mut self, stonk_id: usize, amount: u32, cost: Money, is_buy: bool) -> f64 {
+        let (bump, absorbed) = self.order_flow_bump(stonk_id, amount, is_buy);
+        self.treasury
+            .deposit(trade_spread(&self.stonks[stonk_id], amount, cost, is_buy).scale(absorbed));
+        bump
+    }
+
+    // Drift bump of a trade, once the market maker took its share. Returns the bump and the
+    // fraction absorbed.
+    fn order_flow_bump(&mut self, stonk_id: usize, amount: u32, is_buy: bool) -> (f64, f64) {
+        let fair_value = self.fair_value(stonk_id);
+        let stonk = &self.stonks[stonk_id];
+        let signed_amount = if is_buy {
+            amount as i64
+        } else {
+            -(amount as i64)
+        };
+        let absorbed = self.market_maker.absorb(
+            stonk,
+            fair_value,
+            signed_amount,
+            self.trading.market_maker_share,
+        );
+        let bump = signed_amount.signum() as f64
+            * stonk.trade_impact(amount)
+            * TRADE_BUMP_PER_LIQUIDITY
+            * (1.0 - absorbed);
+        (bump, absorbed)
+    }
+
+    // Lets every bot trade, with a probability given by the aggressiveness of the population.
+    // Bot trades only move their paper accounts and the drift, never the treasury.
+    fn trade_bots(&mut self, rng: &mut ChaCha8Rng) {
+        // The bots are taken out of the market while they look at it.
+        let mut bots = std::mem::take(&mut self.bots);
+        let aggressiveness = bots.aggressiveness();
+        for bot in bots.bots_mut() {
+            if !rng.gen_bool(aggressiveness) {
+                continue;
+            }
+            let action = bot.decide(&MarketView::new(self), aggressiveness, rng.next_u64());
+            let (stonk_id, amount, is_buy) = match action {
+                Some(AgentAction::Buy { stonk_id, amount }) => (stonk_id, amount, true),
+                Some(AgentAction::Sell { stonk_id, amount }) => (stonk_id, amount, false),
+                _ => continue,
+            };
+
+            let stonk = &self.stonks[stonk_id];
+            if stonk.is_suspended() {
+                continue;
+            }
+            let cash = bot.account.cash;
+            let result = if is_buy {
+                bot.account.buy(stonk, amount)
+            } else {
+                bot.account.sell(stonk, amount)
+            };
+            if let Err(e) = result {
+                debug!("Bot {} could not trade: {}", bot.name, e);
+                continue;
+            }
+            let value = if is_buy {
+                cash.saturating_sub(bot.account.cash)
+            } else {
+                bot.account.cash.saturating_sub(cash)
+            };
+
+            let (bump, _) = self.order_flow_bump(stonk_id, amount, is_buy);
+            self.pending_bumps[stonk_id] += bump;
+            self.record_trade(&bot.name, stonk_id, amount, value, is_buy);
+        }
+        self.bots = bots;
+    }
+
+    /// Price variation since the day opened, in percentage.
+    pub fn today_variation(&self, stonk_id: usize) -> f64 {
+        let stonk = &self.stonks[stonk_id];
+        let today_initial_price = stonk
+            .historical_prices
+            .open_of_day(self.day_start_tick())
+            .unwrap_or_default();
+
+        if today_initial_price > 0 {
+            (stonk.current_unit_price_cents() as f64 - today_initial_price as f64)
+                / (today_initial_price as f64)
+                * 100.0
+        } else {
+            0.0
+        }
+    }
+
+    /// Stonk ids sorted from the best to the worst performer of the day.
+    pub fn top_movers(&self) -> Vec<(usize, f64)> {
+        let mut movers = self
+            .stonks
+            .iter()
+            .map(|stonk| (stonk.id, self.today_variation(stonk.id)))
+            .collect::<Vec<(usize, f64)>>();
+        movers.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        movers
+    }
+
+    pub fn regime(&self) -> &'static str {
+        if self.global_drift > GLOBAL_DRIFT_VOLATILITY {
+            "Bull run"
+        } else if self.global_drift < -GLOBAL_DRIFT_VOLATILITY {
+            "Bear raid"
+        } else {
+            "Choppy waters"
+        }
+    }
+
+    fn record_trade(
+        &mut self,
+        username: &str,
+        stonk_id: usize,
+        amount: u32,
+        value: Money,
+        is_buy: bool,
+    ) {
+        let username = if self.stonks[stonk_id].to_stake(amount) >= WHALE_TRADE_STAKE {
+            Some(username.to_string())
+        } else {
+            None
+        };
+        self.trade_tape.push(Trade {
+            tick: self.last_tick,
+            stonk_id,
+            amount,
+            value,
+            is_buy,
+            username,
+        });
+        if self.trade_tape.len() > MAX_TRADE_TAPE_LENGTH {
+            self.trade_tape.remove(0);
+        }
+    }
+
+    pub fn tick_day(&mut self, rng: &mut ChaCha8Rng) {
+        let global_drift = if self.last_tick.is_multiple_of(GLOBAL_DRIFT_INTERVAL) {
+            let current_market_cap = self.total_market_cap().as_f64_cents();
+            let target_market_cap = self.target_total_market_cap.as_f64_cents();
+            let mean = (target_market_cap - current_market_cap)
+                / current_market_cap.min(target_market_cap);
+            let drift = (mean + rng.gen_range(-GLOBAL_DRIFT_VOLATILITY..GLOBAL_DRIFT_VOLATILITY))
+                .clamp(-MAX_GLOBAL_DRIFT, MAX_GLOBAL_DRIFT);
+
+            info!(
+                "Global drift: current cap {}, target cap {}, global drift {}",
+                current_market_cap, target_market_cap, drift
+            );
+            self.global_drift = drift;
+            Some(drift)
+        } else {
+            None
+        };
+
+        for stonk in self.stonks.iter_mut() {
+            if let Some(drift) = global_drift.filter(|_| !stonk.is_suspended()) {
+                stonk.add_condition(
+                    StonkCondition::Bump { amount: drift },
+                    self.last_tick + GLOBAL_DRIFT_INTERVAL,
+                );
+            }
+            let trades_bump = std::mem::take(&mut self.pending_bumps[stonk.id]);
+            if trades_bump != 0.0 {
+                stonk.add_condition(
+                    StonkCondition::Bump {
+                        amount: trades_bump,
+                    },
+                    self.last_tick + 1,
+                );
+            }
+            stonk.tick(self.last_tick, rng);
+            stonk.historical_prices.keep_last(HISTORICAL_SIZE);
+        }
+        self.market_maker.tick();
+        self.start_flash_sale(rng);
+        // Bot trades bump the prices at the next tick, like the ones of the players.
+        self.trade_bots(rng);
+        self.offshore.tick(&self.stonks, self.last_tick, rng);
+        self.last_tick += 1;
+    }
+
+    fn tick_night(&mut self, _rng: &mut ChaCha8Rng) {}
+
+    // Sometimes makes a stonk trade at a discount or at a premium for a few ticks.
+    fn start_flash_sale(&mut self, rng: &mut ChaCha8Rng) {
+        if !rng.gen_bool(FLASH_SALE_PROBABILITY) {
+            return;
+        }
+        let stonk = &mut self.stonks[rng.gen_range(0..NUMBER_OF_STONKS)];
+        if stonk.mispricing().is_some() || stonk.is_suspended() {
+            return;
+        }
+        let mispricing = rng.gen_range(FLASH_SALE_MIN_MISPRICING..FLASH_SALE_MAX_MISPRICING);
+        let factor = if rng.gen_bool(0.5) {
+            -mispricing
+        } else {
+            mispricing
+        };
+        info!(
+            "Flash sale: {} trades at {:+.1}%",
+            stonk.name,
+            factor * 100.0
+        );
+        stonk.add_condition(
+            StonkCondition::Mispriced { factor },
+            self.last_tick + FLASH_SALE_TICKS,
+        );
+    }
+
+    // Applies the effects of the scripted events due at this tick.
+    fn run_scenario(&mut self) {
+        for event in self.scenario.due_events(self.phase) {
+            info!("Scenario event: {}", event.headline);
+            for effect in event.effects.iter() {
+                let (stonk_ids, condition, days) = match effect {
+                    ScriptedEffect::ClassDrift {
+                        class,
+                        amount,
+                        days,
+                    } => (
+                        self.stonks
+                            .iter()
+                            .filter(|stonk| stonk.class == *class)
+                            .map(|stonk| stonk.id)
+                            .collect::<Vec<usize>>(),
+                        StonkCondition::Bump { amount: *amount },
+                        *days,
+                    ),
+                    ScriptedEffect::StonkDrift {
+                        stonk_id,
+                        amount,
+                        days,
+                    } => (
+                        vec![*stonk_id],
+                        StonkCondition::Bump { amount: *amount },
+                        *days,
+                    ),
+                    ScriptedEffect::Shocks { class, days } => (
+                        self.stonks
+                            .iter()
+                            .filter(|stonk| class.is_none_or(|class| stonk.class == class))
+                            .map(|stonk| stonk.id)
+                            .collect::<Vec<usize>>(),
+                        StonkCondition::IncreasedShockProbability,
+                        *days,
+                    ),
+                };
+                for stonk_id in stonk_ids {
+                    if let Some(stonk) = self.stonks.get_mut(stonk_id) {
+                        stonk.add_condition(condition, self.last_tick + days * DAY_LENGTH);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn tick(&mut self) {
+        debug!("\nMarket tick {:?}", self.phase);
+        if logging::detailed_logs() {
+            for stonk in self.stonks.iter() {
+                debug!(
+                    "Stonk availability: {} out of {} ({} bought)",
+                    stonk.available_amount(),
+                    stonk.number_of_shares,
+                    stonk.allocated_shares
+                );
+            }
+        }
+        let rng = &mut self.rng.clone();
+        match self.phase {
+            GamePhase::Day { cycle, counter } => {
+                self.run_scenario();
+                self.tick_day(rng);
+                if counter < DAY_LENGTH - 1 {
+                    self.phase = GamePhase::Day {
+                        cycle,
+                        counter: counter + 1,
+                    }
+                } else {
+                    self.leaderboard_history
+                        .record(self.phase, &self.portfolios);
+                    self.treasury.close_day();
+                    self.phase = GamePhase::Night { cycle, counter: 0 }
+                }
+            }
+            GamePhase::Night { cycle, counter } => {
+                self.tick_night(rng);
+                if counter < NIGHT_LENGTH - 1 {
+                    self.phase = GamePhase::Night {
+                        cycle,
+                        counter: counter + 1,
+                    };
+                } else {
+                    self.phase = GamePhase::Day {
+                        cycle: cycle + 1,
+                        counter: 0,
+                    };
+                    self.volume_today = Money::ZERO;
+                    self.offshore.exchange_rate.open_day();
+                }
+            }
+        }
+        // Currencies trade around the clock.
+        self.offshore.exchange_rate.tick(rng);
+        // Trades during the night pay the after hours spread.
+        let after_hours = matches!(self.phase, GamePhase::Night { .. });
+        for stonk in self.stonks.iter_mut() {
+            stonk.set_after_hours(after_hours);
+        }
+        self.rng = rng.clone();
+    }
+
+    /// Moves everything recorded under the old username to the new one.
+    pub fn rename_agent(&mut self, old_username: &str, new_username: &str) {
+        for stonk in self.stonks.iter_mut() {
+            for (holder, _) in stonk.shareholders.iter_mut() {
+                if holder == old_username {
+                    *holder = new_username.to_string();
+                }
+            }
+        }
+        for (holder, _) in Arc::make_mut(&mut self.portfolios).iter_mut() {
+            if holder == old_username {
+                *holder = new_username.to_string();
+            }
+        }
+        for trade in self.trade_tape.iter_mut() {
+            if trade.username.as_deref() == Some(old_username) {
+                trade.username = Some(new_username.to_string());
+            }
+        }
+        self.tournament.rename(old_username, new_username);
+        self.boards.rename(old_username, new_username);
+        self.futures.rename(old_username, new_username);
+        self.auction.rename(old_username, new_username);
+        if let Some(reputation) = self.reputations.remove(old_username) {
+            self.reputations
+                .insert(new_username.to_string(), reputation);
+        }
+    }
+
+    pub fn apply_agent_actions<A: DecisionAgent>(
+        &mut self,
+        agent: &mut A,
+        agents: &mut HashMap<String, A>,
+    ) -> AppResult<()> {
+        let actions = agent.selected_actions().clone();
+        agent.clear_actions();
+
+        // Apply all actions, even if some fail, and report the last error.
+        let mut result = Ok(());
+        for action in actions.iter() {
+            let action_result = self.apply_agent_action(agent, action, agents);
+            if action_result.is_ok() {
+                if let Some(event) = agent
+                    .available_night_events()
+                    .iter()
+                    .find(|event| event.action() == *action)
+                {
+                    self.telemetry.record_picked(&event.to_string());
+                }
+            }
+            audit::record(AuditEvent::Action {
+                username: agent.username().to_string(),
+                tick: self.last_tick,
+                action: action.clone(),
+                error: action_result.as_ref().err().map(|e| e.to_string()),
+            });
+            if let Err(e) = action_result {
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    /// Ends the current tournament round when its time is up, and starts the next one
+    /// at the beginning of the day, giving every remaining player a fresh paper account.
+    pub fn update_tournament(&mut self, agents: &mut HashMap<String, UserAgent>) {
+        if let Some(round) = self.tournament.current_round() {
+            if self.last_tick < round.ends_at_tick {
+                return;
+            }
+            let scores = self
+                .tournament
+                .alive()
+                .into_iter()
+                .map(|username| {
+                    let net_worth = agents
+                        .get(&username)
+                        .and_then(|agent| agent.tournament_account())
+                        .map(|account| account.net_worth(&self.stonks))
+                        .unwrap_or_default();
+                    (username, net_worth)
+                })
+                .collect();
+            self.tournament.end_round(scores);
+            // No carry-over between rounds.
+            for agent in agents.values_mut() {
+                agent.set_tournament_account(None);
+            }
+            info!("Tournament round {} ended", self.tournament.rounds.len());
+        }
+
+        if self.tournament.is_ready() && matches!(self.phase, GamePhase::Day { counter: 0, .. }) {
+            self.tournament.start_round(self.last_tick);
+            for username in self.tournament.alive() {
+                if let Some(agent) = agents.get_mut(&username) {
+                    agent.set_tournament_account(Some(PaperAccount::new(
+                        self.tournament.config.starting_cash_cents,
+                    )));
+                }
+            }
+            info!("Tournament round {} started", self.tournament.rounds.len());
+        }
+    }
+
+    /// Sells all the holdings of the agent back to the market at a haircut, returning the proceeds.
+    /// Shareholder entries are removed even if they do not match the agent holdings. Futures are
+    /// settled at the current price and offshore cash is exchanged back to dollars, so that
+    /// nothing is left for the market to keep if the agent is then archived.
+    pub fn liquidate_agent(&mut self, agent: &mut UserAgent) -> AppResult<Money> {
+        let mut shares = [0; NUMBER_OF_STONKS];
+        let mut proceeds = Money::ZERO;
+        for stonk in self.stonks.iter_mut() {
+            let owned = agent.owned_stonks()[stonk.id];
+            let held = stonk.remove_shareholder(agent.username());
+            if held != owned {
+                error!(
+                    "Agent {} owns {} shares of stonk {} but holds {}",
+                    agent.username(),
+                    owned,
+                    stonk.id,
+                    held
+                );
+            }
+            if owned > 0 {
+                let value = stonk.sell_price(owned);
+                let haircut = Money::saturating_from_f64_cents(
+                    (value.as_f64_cents() * LIQUIDATION_HAIRCUT).ceil(),
+                );
+                proceeds = proceeds.saturating_add(value.saturating_sub(haircut));
+                self.treasury.deposit(haircut);
+                agent.sub_stonk(stonk.id, owned)?;
+            }
+            shares[stonk.id] = owned;
+        }
+        agent.add_cash(proceeds)?;
+
+        for contract in self.futures.take_of(agent.username()) {
+            self.settle_futures_contract(&contract, agent);
+        }
+        let offshore_cash = agent.offshore_cash();
+        if offshore_cash > Money::ZERO {
+            let (received, fee) = self.offshore.exchange_rate.convert(offshore_cash, false);
+            agent.sub_offshore_cash(offshore_cash)?;
+            agent.add_cash(received)?;
+            self.treasury.deposit(fee);
+        }
+
+        audit::record(AuditEvent::AgentLiquidated {
+            username: agent.username().to_string(),
+            shares,
+            proceeds_cents: proceeds,
+        });
+        Ok(proceeds)
+    }
+
+    pub(crate) fn apply_agent_action<A: DecisionAgent>(
+        &mut self,
+        agent: &mut A,
+        action: &AgentAction,
+        agents: &mut HashMap<String, A>,
+    ) -> AppResult<()> {
+        info!("Applying action {:?}", action);
+
+        let is_trade = action.is_trade();
+
+        // Empty trades would still count toward the limits and bump the price.
+        if let AgentAction::Buy { amount: 0, .. }
+        | AgentAction::Sell { amount: 0, .. }
+        | AgentAction::DarkPoolBuy { amount: 0, .. }
+        | AgentAction::DarkPoolSell { amount: 0, .. }
+        | AgentAction::OffshoreBuy { amount: 0, .. }
+        | AgentAction::OffshoreSell { amount: 0, .. }
+        | AgentAction::TournamentBuy { amount: 0, .. }
+        | AgentAction::TournamentSell { amount: 0, .. }
+        | AgentAction::AuctionBuy { amount: 0, .. }
+        | AgentAction::AuctionSell { amount: 0, .. }
+        | AgentAction::OpenFutures { amount: 0, .. } = action
+        {
+            return Err("Cannot trade zero shares".into());
+        }
+
+        if agent.has_condition(AgentCondition::Vacation)
+            && (is_trade || action.is_auction_order() || action.is_futures_order())
+        {
+            return Err("Trading is disabled while on vacation".into());
+        }
+
+        if agent.has_condition(AgentCondition::Prison)
+            && (is_trade || action.is_auction_order() || action.is_futures_order())
+        {
+            return Err("Trading is disabled while in prison".into());
+        }
+
+        if let Some(stonk) = action
+            .traded_stonk_id()
+            .and_then(|stonk_id| self.stonks.get(stonk_id))
+        {
+            if stonk.is_suspended() {
+                return Err(format!("Trading of {} is suspended", stonk.name).into());
+            }
+        }
+
+        if is_trade {
+            match self.remaining_trades(agent) {
+                (0, _) => return Err("Too many trades in a single tick".into()),
+                (_, 0) => return Err("Daily trade limit reached".into()),
+                _ => {}
+            }
+        }
+
+        if let AgentAction::Buy { stonk_id, amount }
+        | AgentAction::Sell { stonk_id, amount }
+        | AgentAction::DarkPoolBuy { stonk_id, amount }
+        | AgentAction::DarkPoolSell { stonk_id, amount } = action
+        {
+            let max_amount = self.stonks[*stonk_id].max_trade_amount();
+            if *amount > max_amount {
+                return Err(
+                    format!("After hours trades are limited to {} shares", max_amount).into(),
+                );
+            }
+        }
+
+        if let AgentAction::OffshoreBuy { stonk_id, amount }
+        | AgentAction::OffshoreSell { stonk_id, amount } = action
+        {
+            if !matches!(self.phase, GamePhase::Day { .. }) {
+                return Err("The offshore exchange is closed at night".into());
+            }
+            let max_amount = self.offshore.max_trade_amount(&self.stonks[*stonk_id]);
+            if *amount > max_amount {
+                return Err(format!("Offshore trades are limited to {} shares", max_amount).into());
+            }
+        }
+
+        match action {
+            AgentAction::Buy { stonk_id, amount }
+            | AgentAction::DarkPoolBuy { stonk_id, amount } => {
+                let is_dark_pool = matches!(action, AgentAction::DarkPoolBuy { .. });
+                let venue = if is_dark_pool {
+                    Venue::DarkPool
+                } else {
+                    Venue::Exchange
+                };
+                let fee_rate = self.trade_fee_rate(agent, venue);
+                let stonk = &mut self.stonks[*stonk_id];
+                let max_amount = stonk.available_amount();
+                if max_amount < *amount {
+                    return Err("Not enough shares available".into());
+                }
+
+                let mut cost = stonk
+                    .checked_buy_price(*amount)
+                    .ok_or("Trade value is too large")?;
+                let fee = trade_fee(cost, fee_rate);
+                cost = cost.checked_add(fee).ok_or("Trade value is too large")?;
+                agent.sub_cash(cost)?;
+                self.treasury.deposit(fee);
+                self.volume_today = self.volume_today.saturating_add(cost);
+
+                agent.add_stonk(*stonk_id, *amount)?;
+                stonk.allocate_shares_to_agent(agent.username(), *amount)?;
+
+                info!(
+                    "{} stonks bought, there are now {} available ({} total bought)",
+                    amount,
+                    stonk.available_amount(),
+                    stonk.allocated_shares
+                );
+
+                if !is_dark_pool {
+                    self.pending_bumps[*stonk_id] +=
+                        self.absorb_trade(*stonk_id, *amount, cost, true);
+                    self.record_trade(agent.username(), *stonk_id, *amount, cost, true);
+                }
+            }
+            AgentAction::Sell { stonk_id, amount }
+            | AgentAction::DarkPoolSell { stonk_id, amount } => {
+                let is_dark_pool = matches!(action, AgentAction::DarkPoolSell { .. });
+                let venue = if is_dark_pool {
+                    Venue::DarkPool
+                } else {
+                    Venue::Exchange
+                };
+                let fee_rate = self.trade_fee_rate(agent, venue);
+                let stonk = &mut self.stonks[*stonk_id];
+
+                let mut cost = stonk.sell_price(*amount);
+                let fee = trade_fee(cost, fee_rate);
+                cost = cost.saturating_sub(fee);
+                // Shares are removed first, so that a failed sale never pays out.
+                agent.sub_stonk(*stonk_id, *amount)?;
+                stonk.deallocate_shares_to_agent(agent.username(), *amount)?;
+                agent.add_cash(cost)?;
+                self.treasury.deposit(fee);
+                self.volume_today = self.volume_today.saturating_add(cost);
+
+                info!(
+                    "{} stonks sold, there are now {} available ({} total bought)",
+                    amount,
+                    stonk.available_amount(),
+                    stonk.allocated_shares
+                );
+
+                if !is_dark_pool {
+                    self.pending_bumps[*stonk_id] +=
+                        self.absorb_trade(*stonk_id, *amount, cost, false);
+                    self.record_trade(agent.username(), *stonk_id, *amount, cost, false);
+                }
+            }
+            AgentAction::OffshoreBuy { stonk_id, amount } => {
+                let fee_rate = self.trade_fee_rate(agent, Venue::Offshore);
+                let stonk = &mut self.stonks[*stonk_id];
+                if stonk.available_amount() < *amount {
+                    return Err("Not enough shares available".into());
+                }
+
+                let mut cost = self
+                    .offshore
+                    .buy_price(stonk, *amount)
+                    .ok_or("Trade value is too large")?;
+                let fee = trade_fee(cost, fee_rate);
+                cost = cost.checked_add(fee).ok_or("Trade value is too large")?;
+                agent.sub_offshore_cash(cost)?;
+                let exchange_rate = &self.offshore.exchange_rate;
+                self.treasury.deposit(exchange_rate.to_dollars(fee));
+                self.volume_today = self
+                    .volume_today
+                    .saturating_add(exchange_rate.to_dollars(cost));
+
+                agent.add_stonk(*stonk_id, *amount)?;
+                stonk.allocate_shares_to_agent(agent.username(), *amount)?;
+                self.offshore.record_trade(stonk, *amount, true);
+            }
+            AgentAction::OffshoreSell { stonk_id, amount } => {
+                let fee_rate = self.trade_fee_rate(agent, Venue::Offshore);
+                let stonk = &mut self.stonks[*stonk_id];
+
+                let mut cost = self.offshore.sell_price(stonk, *amount);
+                let fee = trade_fee(cost, fee_rate);
+                cost = cost.saturating_sub(fee);
+                // Shares are removed first, so that a failed sale never pays out.
+                agent.sub_stonk(*stonk_id, *amount)?;
+                stonk.deallocate_shares_to_agent(agent.username(), *amount)?;
+                agent.add_offshore_cash(cost)?;
+                let exchange_rate = &self.offshore.exchange_rate;
+                self.treasury.deposit(exchange_rate.to_dollars(fee));
+                self.volume_today = self
+                    .volume_today
+                    .saturating_add(exchange_rate.to_dollars(cost));
+                self.offshore.record_trade(stonk, *amount, false);
+            }
+            AgentAction::ExchangeCurrency {
+                to_offshore,
+                amount,
+            } => {
+                if *amount == Money::ZERO {
+                    return Err("Cannot exchange nothing".into());
+                }
+                let (received, fee) = self.offshore.exchange_rate.convert(*amount, *to_offshore);
+                if *to_offshore {
+                    agent.sub_cash(*amount)?;
+                    agent.add_offshore_cash(received)?;
+                } else {
+                    agent.sub_offshore_cash(*amount)?;
+                    agent.add_cash(received)?;
+                }
+                self.treasury.deposit(fee);
+            }
+            AgentAction::BumpStonkClass { class } => {
+                // Night events are applied in the morning, so all the bumps of a night
+                // share the same day.
+                let cycle = self.phase.cycle();
+                let amount = self.class_bumps.next_bump(*class, cycle);
+                if amount <= 0.0 {
+                    return Err(format!("{:?} stonks cannot be bumped more today", class).into());
+                }
+                self.apply_effects(agent, &class_bump_effects(*class, amount))?;
+                self.class_bumps.record(*class, cycle, amount);
+            }
+            AgentAction::CrashAll
+            | AgentAction::AddCash { .. }
+            | AgentAction::AcceptBribe
+            | AgentAction::OneDayUltraVision
+            | AgentAction::ReceiveStimulus
+            | AgentAction::ReceiveBailout
+            | AgentAction::TakeShadyLoan => {
+                self.apply_effects(agent, &action_effects(action).unwrap_or_default())?
+            }
+            AgentAction::CrashAgentStonks { username } => {
+                if let Some(target) = agents.get_mut(username) {
+                    if target.has_condition(AgentCondition::Vacation) {
+                        return Err(format!("{} is on vacation", username).into());
+                    }
+                    // The crash is not immediate: the target gets a chance to react first.
+                    target.add_assassination_threat(AssassinationThreat {
+                        attacker: agent.username().to_string(),
+                        until_tick: self.last_tick + ASSASSINATION_WARNING_TICKS,
+                    });
+                    let cost = self.event_cost(agent.username(), CHARACTER_ASSASSINATION_COST);
+                    agent.sub_cash(cost)?;
+                    self.treasury.deposit(cost);
+                }
+            }
+            AgentAction::AssassinationVictim => {}
+            AgentAction::GetDividends { stonk_id } => {
+                let stonk = &self.stonks[*stonk_id];
+                // Prices can move between the unlock and now, so the gain is checked again.
+                let dividend = self
+                    .last_closed_day_start_tick()
+                    .and_then(|tick| stonk.dividend(agent.owned_stonks()[*stonk_id], tick))
+                    .ok_or_else(|| format!("{} has no dividend to pay", stonk.name))?;
+
+                agent.add_cash(dividend)?;
+                audit::record(AuditEvent::CashPrinted {
+                    username: agent.username().to_string(),
+                    amount_cents: dividend,
+                });
+            }
+            AgentAction::PayProtection => {
+                let number_of_threats = agent.assassination_threats().len() as u64;
+                if number_of_threats == 0 {
+                    return Err("No assassination to protect from".into());
+                }
+                let cost = protection_cost(agent).saturating_mul(number_of_threats);
+                agent.sub_cash(cost)?;
+                self.treasury.deposit(cost);
+                agent.remove_assassination_threats(usize::MAX);
+            }
+            AgentAction::DumpHoldings => {
+                if agent.assassination_threats().is_empty() {
+                    return Err("No assassination to dodge".into());
+                }
+                for stonk_id in 0..NUMBER_OF_STONKS {
+                    let amount = agent.owned_stonks()[stonk_id];
+                    if amount == 0 {
+                        continue;
+                    }
+                    let stonk = &mut self.stonks[stonk_id];
+                    let value = stonk.sell_price(amount);
+                    let proceeds = value.scale(1.0 - DUMP_HOLDINGS_PENALTY);
+                    agent.add_cash(proceeds)?;
+                    self.treasury.deposit(value.saturating_sub(proceeds));
+                    agent.sub_stonk(stonk_id, amount)?;
+                    self.volume_today = self.volume_today.saturating_add(proceeds);
+                    stonk.deallocate_shares_to_agent(agent.username(), amount)?;
+                }
+                agent.remove_assassination_threats(usize::MAX);
+            }
+            AgentAction::CallBluff => {
+                if agent.assassination_threats().is_empty() {
+                    return Err("No assassination to call the bluff on".into());
+                }
+                let threats = agent.remove_assassination_threats(usize::MAX);
+                let success = self.rng.gen_bool(CALL_BLUFF_SUCCESS_PROBABILITY);
+                audit::record(AuditEvent::CallBluffResolved {
+                    username: agent.username().to_string(),
+                    success,
+                });
+                if success {
+                    // The attackers are exposed, and end up in prison.
+                    for threat in threats.iter() {
+                        if let Some(attacker) = agents.get_mut(&threat.attacker) {
+                            attacker.add_condition(
+                                AgentCondition::Prison,
+                                self.last_tick + PRISON_DAYS * DAY_LENGTH,
+                            );
+                        }
+                    }
+                } else {
+                    for _ in threats.iter() {
+                        self.crash_agent_stonks(agent, CALL_BLUFF_FAILURE_MULTIPLIER);
+                    }
+                }
+            }
+            AgentAction::StartVacation { days } => {
+                if !(1..=MAX_VACATION_DAYS).contains(days) {
+                    return Err(
+                        format!("Vacation must last 1 to {} days", MAX_VACATION_DAYS).into(),
+                    );
+                }
+                if agent.has_condition(AgentCondition::Vacation)
+                    || agent.has_condition(AgentCondition::VacationCooldown)
+                {
+                    return Err("Cannot go on vacation again so soon".into());
+                }
+                if !agent.assassination_threats().is_empty() {
+                    return Err("Cannot go on vacation with a pending assassination".into());
+                }
+                let vacation_end = self.last_tick + days * DAY_LENGTH;
+                agent.add_condition(AgentCondition::Vacation, vacation_end);
+                agent.add_condition(
+                    AgentCondition::VacationCooldown,
+                    vacation_end + VACATION_COOLDOWN_DAYS * DAY_LENGTH,
+                );
+            }
+            AgentAction::JoinTournament => self.tournament.join(agent.username())?,
+            AgentAction::TournamentBuy { stonk_id, amount } => agent
+                .tournament_account_mut()
+                .ok_or("You are not playing a tournament round")?
+                .buy(&self.stonks[*stonk_id], *amount)?,
+            AgentAction::TournamentSell { stonk_id, amount } => agent
+                .tournament_account_mut()
+                .ok_or("You are not playing a tournament round")?
+                .sell(&self.stonks[*stonk_id], *amount)?,
+            AgentAction::AuctionBuy { stonk_id, amount }
+            | AgentAction::AuctionSell { stonk_id, amount } => {
+                if !self.auction_is_open() {
+                    return Err("The opening auction is closed".into());
+                }
+                let is_buy = matches!(action, AgentAction::AuctionBuy { .. });
+                let limit_price = self.auction_limit_price(*stonk_id, is_buy);
+                let (bought, sold) = self.auction.ordered_amounts(agent.username(), *stonk_id);
+                // Checked again at dawn, against all the orders of the agent.
+                if is_buy {
+                    let cost = Money::from(limit_price)
+                        .saturating_mul(bought.saturating_add(*amount) as u64);
+                    if cost > agent.cash() {
+                        return Err("Not enough cash for the auction order".into());
+                    }
+                } else if sold.saturating_add(*amount) > agent.owned_stonks()[*stonk_id] {
+                    return Err("Not enough shares for the auction order".into());
+                }
+                self.auction.submit(AuctionOrder {
+                    username: agent.username().to_string(),
+                    stonk_id: *stonk_id,
+                    amount: *amount,
+                    limit_price,
+                    is_buy,
+                });
+            }
+            AgentAction::BoardDecision { stonk_id, decision } => {
+                self.take_board_decision(agent, *stonk_id, *decision, agents)?
+            }
+            AgentAction::OpenFutures {
+                stonk_id,
+                amount,
+                is_long,
+            } => {
+                let stonk = &self.stonks[*stonk_id];
+                FuturesBook::check_open(self.phase, stonk)?;
+                let max_amount = self.futures.max_open_amount(agent.username(), stonk);
+                if *amount > max_amount {
+                    return Err(
+                        format!("You can open futures on {} more shares", max_amount).into(),
+                    );
+                }
+                let price = stonk.current_unit_price_cents();
+                let margin =
+                    FuturesBook::margin(price, *amount).ok_or("Trade value is too large")?;
+                let fee = trade_fee(
+                    Money::from(price).saturating_mul(*amount as u64),
+                    FUTURES_FEE,
+                );
+                agent.sub_cash(margin.checked_add(fee).ok_or("Trade value is too large")?)?;
+                self.treasury.deposit(fee);
+                self.futures.open(FuturesContract {
+                    username: agent.username().to_string(),
+                    stonk_id: *stonk_id,
+                    amount: *amount,
+                    is_long: *is_long,
+                    entry_price_cents: price,
+                    margin,
+                    settlement_cycle: FuturesBook::settlement_cycle(self.phase.cycle()),
+                });
+            }
+        }
+        if is_trade {
+            agent
+                .trade_counter_mut()
+                .record_trade(self.phase.clock_tick(), self.phase.cycle());
+        }
+        agent.record_past_action(action.clone(), self.last_tick);
+        Ok(())
+    }
+
+    fn take_board_decision<A: DecisionAgent>(
+        &mut self,
+        agent: &mut A,
+        stonk_id: usize,
+        decision: BoardDecision,
+        agents: &mut HashMap<String, A>,
+    ) -> AppResult<()> {
+        let stonk = &self.stonks[stonk_id];
+        if Boards::controller(stonk) != Some(agent.username()) {
+            return Err(format!("You do not control the board of {}", stonk.name).into());
+        }
+        if self.last_tick < self.boards.next_decision_tick(stonk_id) {
+            return Err(format!("The board of {} already met today", stonk.name).into());
+        }
+
+        match decision {
+            BoardDecision::RaiseGuidance | BoardDecision::LowerGuidance => {
+                let amount = if decision == BoardDecision::RaiseGuidance {
+                    BOARD_GUIDANCE_BUMP
+                } else {
+                    -BOARD_GUIDANCE_BUMP
+                };
+                self.stonks[stonk_id]
+                    .add_condition(StonkCondition::Bump { amount }, self.last_tick + DAY_LENGTH);
+            }
+            BoardDecision::DeclareDividend => {
+                let stonk = &mut self.stonks[stonk_id];
+                for (username, amount) in stonk.shareholders.iter() {
+                    let dividend = stonk.holdings_value(*amount).scale(BOARD_DIVIDEND_RATE);
+                    // The acting agent is not in the map while its actions are applied.
+                    let shareholder = if username == agent.username() {
+                        &mut *agent
+                    } else if let Some(shareholder) = agents.get_mut(username) {
+                        shareholder
+                    } else {
+                        continue;
+                    };
+                    shareholder.add_cash(dividend)?;
+                    audit::record(AuditEvent::CashPrinted {
+                        username: username.clone(),
+                        amount_cents: dividend,
+                    });
+                }
+                stonk.scale_price(1.0 - BOARD_DIVIDEND_RATE);
+            }
+            BoardDecision::Buyback => {
+                let stonk = &mut self.stonks[stonk_id];
+                let amount = ((stonk.number_of_shares as f64 * BOARD_BUYBACK_STAKE) as u32)
+                    .min(stonk.available_amount());
+                stonk.retire_shares(amount)?;
+            }
+        }
+
+        self.boards.record(BoardMinutes {
+            stonk_id,
+            username: agent.username().to_string(),
+            decision,
+            tick: self.last_tick,
+        });
+        Ok(())
+    }
+
+    fn crash_agent_stonks<A: DecisionAgent>(&mut self, target: &mut A, multiplier: f64) {
+        target.record_past_action(AgentAction::AssassinationVictim, self.last_tick);
+
+        for (stonk_id, &amount) in target.owned_stonks().iter().enumerate() {
+            let stonk = &mut self.stonks[stonk_id];
+            let stake = stonk.to_stake(amount);
+            stonk.add_condition(
+                StonkCondition::Bump {
+                    amount: 10.0 * stake * multiplier,
+                },
+                self.last_tick + DAY_LENGTH,
+            );
+            stonk.add_condition(
+                StonkCondition::IncreasedShockProbability,
+                self.last_tick + DAY_LENGTH,
+            );
+        }
+    }
+
+    /// Applies the character assassinations whose targets did not react in time.
+    pub fn resolve_assassination_threats<A: DecisionAgent>(
+        &mut self,
+        agents: &mut HashMap<String, A>,
+    ) {
+        // Sorted, so that the outcome does not depend on the iteration order of the map.
+        let mut targets = agents.values_mut().collect::<Vec<&mut A>>();
+        targets.sort_by(|a, b| a.username().cmp(b.username()));
+        for agent in targets {
+            for threat in agent.remove_assassination_threats(self.last_tick) {
+                info!(
+                    "Character assassination by {} hits {}",
+                    threat.attacker,
+                    agent.username()
+                );
+                audit::record(AuditEvent::AssassinationResolved {
+                    attacker: threat.attacker.clone(),
+                    target: agent.username().to_string(),
+                });
+                self.crash_agent_stonks(agent, 1.0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        max_buy_amount_with_fee, trade_fee, GamePhase, Market, Venue, AUDIT_FEE, DARK_POOL_FEE,
+        DAY_LENGTH, NIGHT_LENGTH, NUMBER_OF_STONKS, WEALTH_EVENT_COST_FACTOR, WEALTH_FEE_FACTOR,
+    };
+    use crate::{
+        agent::{
+            AgentAction, AgentCondition, DecisionAgent, ScheduledOrder, UserAgent,
+            INITIAL_USER_CASH,
+        },
+        auction::AuctionOrder,
+        board::{BoardDecision, BOARD_DECISION_COOLDOWN_TICKS},
+        events::{
+            protection_cost, HostileIntel, NightEvent, ASSASSINATION_WARNING_TICKS, BAILOUT_AMOUNT,
+            CLASS_BUMP, CLASS_BUMP_DIMINISHING_FACTOR, FAMOUS_PROTECTION_DISCOUNT,
+            INTEL_MEDIA_STAKE_PERCENT, MAX_CLASS_BUMP_PER_DAY, PROTECTION_COST,
+        },
+        futures::{FUTURES_CUTOFF_DAYS, FUTURES_FEE},
+        leaderboard::DAYS_PER_SEASON,
+        market_event::MarketEvent,
+        money::Money,
+        offshore::OFFSHORE_FEE,
+        price_series::PriceSeries,
+        ssh_client::SessionAuth,
+        stonk::StonkClass,
+        utils::AppResult,
+    };
+    use proptest::prelude::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_assassination_counterplay() -> AppResult<()> {
+        let mut market = Market::new();
+        let mut attacker = UserAgent::new(SessionAuth::new("attacker".to_string(), [0; 32]));
+        let mut agents = HashMap::from([
+            (
+                "victim".to_string(),
+                UserAgent::new(SessionAuth::new("victim".to_string(), [0; 32])),
+            ),
+            (
+                "bluffer".to_string(),
+                UserAgent::new(SessionAuth::new("bluffer".to_string(), [0; 32])),
+            ),
+        ]);
+
+        for username in ["victim", "bluffer"] {
+            attacker.select_action(AgentAction::CrashAgentStonks {
+                username: username.to_string(),
+            });
+        }
+        market.apply_agent_actions(&mut attacker, &mut agents)?;
+        assert_eq!(agents["victim"].assassination_threats().len(), 1);
+
+        // The victim pays protection, the bluffer does nothing.
+        let mut victim = agents["victim"].clone();
+        victim.select_action(AgentAction::PayProtection);
+        market.apply_agent_actions(&mut victim, &mut agents)?;
+        assert!(victim.assassination_threats().is_empty());
+        assert_eq!(
+            victim.cash(),
+            INITIAL_USER_CASH.saturating_sub(PROTECTION_COST)
+        );
+        agents.insert("victim".to_string(), victim);
+
+        market.resolve_assassination_threats(&mut agents);
+        assert_eq!(agents["bluffer"].assassination_threats().len(), 1);
+
+        market.last_tick += ASSASSINATION_WARNING_TICKS;
+        market.resolve_assassination_threats(&mut agents);
+        assert!(agents["bluffer"].assassination_threats().is_empty());
+        assert!(agents["bluffer"]
+            .past_actions()
+            .contains(&AgentAction::AssassinationVictim));
+        assert!(!agents["victim"]
+            .past_actions()
+            .contains(&AgentAction::AssassinationVictim));
+        // The past actions keep who was targeted.
+        assert!(attacker.past_actions().iter().any(|past| past.action
+            == AgentAction::CrashAgentStonks {
+                username: "bluffer".to_string()
+            }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scheduled_orders() -> AppResult<()> {
+        let mut market = Market::new();
+        let mut agent = UserAgent::new(SessionAuth::new("sleeper".to_string(), [0; 32]));
+        agent.add_stonk(0, 10)?;
+        market.stonks[0].allocate_shares_to_agent("sleeper", 10)?;
+
+        // Net worth is still above the threshold.
+        agent.set_scheduled_order(Some(ScheduledOrder::SellAllOnDrawdown {
+            reference_value: market.net_worth(&agent),
+        }));
+        assert!(market.scheduled_order_actions(&agent).is_none());
+
+        agent.set_scheduled_order(Some(ScheduledOrder::SellAllOnDrawdown {
+            reference_value: market.net_worth(&agent).saturating_mul(2),
+        }));
+        assert_eq!(
+            market.scheduled_order_actions(&agent),
+            Some(vec![AgentAction::Sell {
+                stonk_id: 0,
+                amount: 10
+            }])
+        );
+
+        // The order waits while the agent is on vacation.
+        let vacation_end = market.last_tick + DAY_LENGTH;
+        agent.add_condition(AgentCondition::Vacation, vacation_end);
+        let mut agents = HashMap::from([("sleeper".to_string(), agent)]);
+        market.execute_scheduled_orders(&mut agents);
+        assert!(agents["sleeper"].scheduled_order().is_some());
+        assert_eq!(agents["sleeper"].owned_stonks()[0], 10);
+
+        if let Some(agent) = agents.get_mut("sleeper") {
+            agent.apply_conditions(vacation_end);
+        }
+        market.execute_scheduled_orders(&mut agents);
+        assert!(agents["sleeper"].scheduled_order().is_none());
+        assert_eq!(agents["sleeper"].owned_stonks()[0], 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vacation() -> AppResult<()> {
+        let mut market = Market::new();
+        let mut agent = UserAgent::new(SessionAuth::new("tourist".to_string(), [0; 32]));
+        let mut agents = HashMap::new();
+
+        agent.select_action(AgentAction::StartVacation { days: 2 });
+        market.apply_agent_actions(&mut agent, &mut agents)?;
+        assert!(agent.has_condition(AgentCondition::Vacation));
+
+        agent.select_action(AgentAction::Buy {
+            stonk_id: 0,
+            amount: 1,
+        });
+        assert!(market.apply_agent_actions(&mut agent, &mut agents).is_err());
+        assert_eq!(agent.owned_stonks()[0], 0);
+
+        // Vacationing agents cannot be targeted.
+        agents.insert("tourist".to_string(), agent.clone());
+        let mut attacker = UserAgent::new(SessionAuth::new("attacker".to_string(), [0; 32]));
+        attacker.select_action(AgentAction::CrashAgentStonks {
+            username: "tourist".to_string(),
+        });
+        assert!(market
+            .apply_agent_actions(&mut attacker, &mut agents)
+            .is_err());
+        assert!(agents["tourist"].assassination_threats().is_empty());
+        assert_eq!(attacker.cash(), INITIAL_USER_CASH);
+
+        // After the vacation, the cooldown prevents leaving again.
+        agent.apply_conditions(market.last_tick + 2 * DAY_LENGTH);
+        assert!(!agent.has_condition(AgentCondition::Vacation));
+        agent.select_action(AgentAction::StartVacation { days: 1 });
+        assert!(market.apply_agent_actions(&mut agent, &mut agents).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_agent_conditions() -> AppResult<()> {
+        let mut market = Market::new();
+        let mut agents = HashMap::from([
+            (
+                "attacker".to_string(),
+                UserAgent::new(SessionAuth::new("attacker".to_string(), [0; 32])),
+            ),
+            (
+                "victim".to_string(),
+                UserAgent::new(SessionAuth::new("victim".to_string(), [0; 32])),
+            ),
+        ]);
+
+        // Audited agents pay a fee on every trade.
+        let mut agent = agents["victim"].clone();
+        agent.select_action(AgentAction::AcceptBribe);
+        market.apply_agent_actions(&mut agent, &mut agents)?;
+        assert!(agent.has_condition(AgentCondition::Audited));
+        let cash = agent.cash();
+        let cost = market.stonks[0].buy_price(10);
+        agent.select_action(AgentAction::Buy {
+            stonk_id: 0,
+            amount: 10,
+        });
+        market.apply_agent_actions(&mut agent, &mut agents)?;
+        assert_eq!(
+            agent.cash(),
+            cash.saturating_sub(cost.saturating_add(trade_fee(cost, AUDIT_FEE)))
+        );
+        agents.insert("victim".to_string(), agent);
+
+        // Calling the bluff right sends the attacker to prison.
+        let mut attacker = agents["attacker"].clone();
+        attacker.select_action(AgentAction::CrashAgentStonks {
+            username: "victim".to_string(),
+        });
+        market.apply_agent_actions(&mut attacker, &mut agents)?;
+        agents.insert("attacker".to_string(), attacker);
+        let (mut market, mut agents) = (0..)
+            .map(|seed| {
+                let mut market = market.clone();
+                let mut agents = agents.clone();
+                market.rng = ChaCha8Rng::seed_from_u64(seed);
+                let mut victim = agents["victim"].clone();
+                victim.select_action(AgentAction::CallBluff);
+                market
+                    .apply_agent_actions(&mut victim, &mut agents)
+                    .map(|_| (market, agents))
+            })
+            .find(|result| {
+                result.as_ref().is_ok_and(|(_, agents)| {
+                    agents["attacker"].has_condition(AgentCondition::Prison)
+                })
+            })
+            .expect("Some bluff should be called right")?;
+        let mut attacker = agents["attacker"].clone();
+        attacker.select_action(AgentAction::Buy {
+            stonk_id: 0,
+            amount: 1,
+        });
+        assert!(market
+            .apply_agent_actions(&mut attacker, &mut agents)
+            .is_err());
+        attacker.apply_conditions(market.last_tick + DAY_LENGTH);
+        assert!(!attacker.has_condition(AgentCondition::Prison));
+
+        // The richest players become famous at the close, and pay less protection money.
+        market.update_portfolios(&agents);
+        market.phase = GamePhase::Night {
+            cycle: 0,
+            counter: 0,
+        };
+        market.update_night_events(&mut agents);
+        let victim = &agents["victim"];
+        assert!(victim.has_condition(AgentCondition::Famous));
+        assert_eq!(
+            protection_cost(victim),
+            PROTECTION_COST.scale(1.0 - FAMOUS_PROTECTION_DISCOUNT)
+        );
+
+        Ok(())
+    }
+
+    fn trade_strategy() -> impl Strategy<Value = (usize, AgentAction)> {
+        (0..3usize, 0..NUMBER_OF_STONKS, 0..1_000u32, 0..4u8).prop_map(
+            |(agent_index, stonk_id, amount, kind)| {
+                let action = match kind {
+                    0 => AgentAction::Buy { stonk_id, amount },
+                    1 => AgentAction::Sell { stonk_id, amount },
+                    2 => AgentAction::DarkPoolBuy { stonk_id, amount },
+                    _ => AgentAction::DarkPoolSell { stonk_id, amount },
+                };
+                (agent_index, action)
+            },
+        )
+    }
+
+    #[test]
+    fn test_trade_limits() -> AppResult<()> {
+        let mut market = Market::new();
+        market.trading.max_trades_per_tick = 2;
+        market.trading.max_trades_per_day = 3;
+        let mut agent = UserAgent::new(SessionAuth::new("spammer".to_string(), [0; 32]));
+        let mut agents = HashMap::new();
+
+        for _ in 0..3 {
+            agent.select_action(AgentAction::Buy {
+                stonk_id: 0,
+                amount: 1,
+            });
+        }
+        assert!(market.apply_agent_actions(&mut agent, &mut agents).is_err());
+        assert_eq!(agent.owned_stonks()[0], 2);
+        assert_eq!(market.remaining_trades(&agent), (0, 1));
+
+        // Only the daily allowance is left on the next tick.
+        market.phase = GamePhase::Day {
+            cycle: 0,
+            counter: 1,
+        };
+        assert_eq!(market.remaining_trades(&agent), (2, 1));
+        for _ in 0..2 {
+            agent.select_action(AgentAction::Sell {
+                stonk_id: 0,
+                amount: 1,
+            });
+        }
+        assert!(market.apply_agent_actions(&mut agent, &mut agents).is_err());
+        assert_eq!(agent.owned_stonks()[0], 1);
+        assert_eq!(market.remaining_trades(&agent), (1, 0));
+
+        // Empty trades are rejected before using the allowance or bumping the price.
+        let mut agent = UserAgent::new(SessionAuth::new("idle".to_string(), [0; 32]));
+        let pending_bumps = market.pending_bumps;
+        agent.select_action(AgentAction::Sell {
+            stonk_id: 0,
+            amount: 0,
+        });
+        assert!(market.apply_agent_actions(&mut agent, &mut agents).is_err());
+        assert_eq!(market.remaining_trades(&agent), (2, 3));
+        assert_eq!(market.pending_bumps, pending_bumps);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dark_pool_trades() -> AppResult<()> {
+        let mut market = Market::new();
+        let mut agent = UserAgent::new(SessionAuth::new("shadow".to_string(), [0; 32]));
+        let mut agents = HashMap::new();
+
+        // Dark pool trades pay the extra fee, but leave no bump and no trace on the tape.
+        let fee_rate = market.trade_fee_rate(&agent, Venue::DarkPool);
+        let price = market.stonks[0].buy_price(10);
+        market.apply_agent_action(
+            &mut agent,
+            &AgentAction::DarkPoolBuy {
+                stonk_id: 0,
+                amount: 10,
+            },
+            &mut agents,
+        )?;
+        let fee = trade_fee(price, fee_rate);
+        assert_eq!(market.treasury.balance(), fee);
+        assert_eq!(
+            agent.cash(),
+            INITIAL_USER_CASH.saturating_sub(price.saturating_add(fee))
+        );
+        assert_eq!(agent.owned_stonks()[0], 10);
+        assert_eq!(market.pending_bumps, [0.0; NUMBER_OF_STONKS]);
+        assert!(market.trade_tape.is_empty());
+
+        // The max amount includes the rounded up fee, so it is always affordable.
+        let stonk = &market.stonks[0];
+        let price = stonk.buy_price(10);
+        let cost = price.saturating_add(trade_fee(price, fee_rate));
+        assert_eq!(max_buy_amount_with_fee(stonk, cost, fee_rate), 10);
+        assert_eq!(
+            max_buy_amount_with_fee(stonk, cost.saturating_sub(Money::from(1)), fee_rate),
+            9
+        );
+        agent.sub_cash(agent.cash())?;
+        agent.add_cash(cost)?;
+        market.apply_agent_action(
+            &mut agent,
+            &AgentAction::DarkPoolBuy {
+                stonk_id: 0,
+                amount: 10,
+            },
+            &mut agents,
+        )?;
+        assert_eq!(agent.cash(), Money::ZERO);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_after_hours_trading() -> AppResult<()> {
+        let mut market = Market::new();
+        let mut agent = UserAgent::new(SessionAuth::new("owl".to_string(), [0; 32]));
+        let mut agents = HashMap::new();
+        while matches!(market.phase, GamePhase::Day { .. }) {
+            market.tick();
+        }
+        let stonk = &market.stonks[0];
+        assert!(stonk.is_after_hours());
+        let mut day_stonk = stonk.clone();
+        day_stonk.set_after_hours(false);
+        assert!(stonk.buy_price(100) > day_stonk.buy_price(100));
+        assert!(stonk.sell_price(100) < day_stonk.sell_price(100));
+        let max_amount = stonk.max_trade_amount();
+        assert!(max_amount < stonk.number_of_shares);
+
+        agent.select_action(AgentAction::Buy {
+            stonk_id: 0,
+            amount: max_amount + 1,
+        });
+        assert!(market.apply_agent_actions(&mut agent, &mut agents).is_err());
+        assert_eq!(agent.owned_stonks()[0], 0);
+        agent.select_action(AgentAction::Buy {
+            stonk_id: 0,
+            amount: max_amount,
+        });
+        market.apply_agent_actions(&mut agent, &mut agents)?;
+        assert_eq!(agent.owned_stonks()[0], max_amount);
+
+        // The trade limit of a tick does not last the whole night.
+        let (trades_this_tick, _) = market.remaining_trades(&agent);
+        market.tick();
+        assert_eq!(market.remaining_trades(&agent).0, trades_this_tick + 1);
+
+        while matches!(market.phase, GamePhase::Night { .. }) {
+            market.tick();
+        }
+        assert!(!market.stonks[0].is_after_hours());
+        assert_eq!(market.stonks[0].max_trade_amount(), u32::MAX);
+        Ok(())
+    }
+
+    #[test]
+    fn test_opening_auction() -> AppResult<()> {
+        let mut market = Market::new();
+        let mut agents = HashMap::new();
+        let mut seller = UserAgent::new(SessionAuth::new("seller".to_string(), [0; 32]));
+        let mut buyer = UserAgent::new(SessionAuth::new("buyer".to_string(), [0; 32]));
+        market.apply_agent_action(
+            &mut seller,
+            &AgentAction::Buy {
+                stonk_id: 0,
+                amount: 10,
+            },
+            &mut agents,
+        )?;
+        let order = AgentAction::AuctionBuy {
+            stonk_id: 0,
+            amount: 6,
+        };
+        assert!(market
+            .apply_agent_action(&mut buyer, &order, &mut agents)
+            .is_err());
+
+        while !market.auction_is_open() {
+            market.tick();
+        }
+        market.apply_agent_action(&mut buyer, &order, &mut agents)?;
+        market.apply_agent_action(
+            &mut seller,
+            &AgentAction::AuctionSell {
+                stonk_id: 0,
+                amount: 4,
+            },
+            &mut agents,
+        )?;
+        assert!(market
+            .apply_agent_action(
+                &mut seller,
+                &AgentAction::AuctionSell {
+                    stonk_id: 0,
+                    amount: 7,
+                },
+                &mut agents,
+            )
+            .is_err());
+        // Buyers are left over, so the auction clears at the highest price.
+        let price = market.auction_limit_price(0, true);
+        assert_eq!(market.auction.indicative_price(0, 0), Some((price, 4)));
+
+        let seller_cash = seller.cash();
+        let buyer_cash = buyer.cash();
+        agents.insert(seller.username().to_string(), seller);
+        agents.insert(buyer.username().to_string(), buyer);
+        while matches!(market.phase, GamePhase::Night { .. }) {
+            market.apply_event(&MarketEvent::tick(), &mut agents)?;
+        }
+        let value = Money::from(price).saturating_mul(4);
+        assert_eq!(agents["buyer"].owned_stonks()[0], 4);
+        assert_eq!(agents["buyer"].cash(), buyer_cash.saturating_sub(value));
+        assert_eq!(agents["seller"].owned_stonks()[0], 6);
+        assert_eq!(agents["seller"].cash(), seller_cash.saturating_add(value));
+        assert_eq!(market.stonks[0].allocated_shares, 10);
+        let results = market
+            .auction
+            .results(market.phase.cycle())
+            .expect("The auction should have run");
+        assert_eq!(results.len(), 1);
+        assert_eq!((results[0].price, results[0].volume), (price, 4));
+        assert!(market.auction.orders().is_empty());
+
+        // A fill that cannot be allocated leaves the buyer untouched.
+        let buyer = agents.get_mut("buyer").expect("Missing buyer");
+        let buyer_cash = buyer.cash();
+        let available = market.stonks[1].available_amount();
+        market.stonks[1].allocate_shares_to_agent("whale", available)?;
+        let fill = AuctionOrder {
+            username: "buyer".to_string(),
+            stonk_id: 1,
+            amount: 1,
+            limit_price: price,
+            is_buy: true,
+        };
+        assert!(market.execute_auction_fill(buyer, &fill, price).is_err());
+        assert_eq!(buyer.cash(), buyer_cash);
+        assert_eq!(buyer.owned_stonks()[1], 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_wealth_fees() {
+        let mut market = Market::new();
+        let rich = UserAgent::new(SessionAuth::new("rich".to_string(), [0; 32]));
+        market.portfolios = Arc::new(vec![
+            ("rich".to_string(), Money::from_dollars(700)),
+            ("middle".to_string(), Money::from_dollars(200)),
+            ("poor".to_string(), Money::from_dollars(100)),
+        ]);
+
+        // 70% of the wealth is 36.7% above an even split.
+        let excess = 0.7 - 1.0 / 3.0;
+        assert!((market.excess_wealth_share("rich") - excess).abs() < 1e-9);
+        assert_eq!(market.excess_wealth_share("middle"), 0.0);
+        assert_eq!(market.excess_wealth_share("unknown"), 0.0);
+        assert!(
+            (market.trade_fee_rate(&rich, Venue::Exchange) - WEALTH_FEE_FACTOR * excess).abs()
+                < 1e-9
+        );
+        assert!(
+            (market.trade_fee_rate(&rich, Venue::DarkPool)
+                - DARK_POOL_FEE
+                - WEALTH_FEE_FACTOR * excess)
+                .abs()
+                < 1e-9
+        );
+        assert_eq!(
+            market.event_cost("rich", Money::from_dollars(1_000)),
+            Money::from_dollars(1_000).scale(1.0 + WEALTH_EVENT_COST_FACTOR * excess)
+        );
+        assert_eq!(
+            market.event_cost("poor", Money::from_dollars(1_000)),
+            Money::from_dollars(1_000)
+        );
+
+        // A single player has nobody to be richer than.
+        Arc::make_mut(&mut market.portfolios).truncate(1);
+        assert_eq!(market.wealth_fee_rate("rich"), 0.0);
+    }
+
+    #[test]
+    fn test_offshore_arbitrage() -> AppResult<()> {
+        let mut market = Market::new();
+        market.stonks[0].set_test_values(1_000, 10_000, 0.0, 0.0, 0.0, 0.0);
+        let mut agent = UserAgent::new(SessionAuth::new("arbitrageur".to_string(), [0; 32]));
+        let mut agents = HashMap::new();
+        let cash = agent.cash();
+
+        // Offshore trades are paid in the offshore currency, bought with a fee.
+        assert!(market
+            .apply_agent_action(
+                &mut agent,
+                &AgentAction::OffshoreBuy {
+                    stonk_id: 0,
+                    amount: 10,
+                },
+                &mut agents,
+            )
+            .is_err());
+        let treasury = market.treasury.balance();
+        market.apply_agent_action(
+            &mut agent,
+            &AgentAction::ExchangeCurrency {
+                to_offshore: true,
+                amount: Money::from_dollars(1_000),
+            },
+            &mut agents,
+        )?;
+        assert_eq!(
+            agent.cash(),
+            cash.saturating_sub(Money::from_dollars(1_000))
+        );
+        assert_eq!(agent.offshore_cash(), Money::from_cents(99_500));
+        assert_eq!(
+            market.treasury.balance(),
+            treasury.saturating_add(Money::from_dollars(5))
+        );
+        // Converted cash keeps counting toward the net worth, at the current rate.
+        assert_eq!(
+            market.net_worth(&agent),
+            cash.saturating_sub(Money::from_dollars(5))
+        );
+        let offshore_cash = agent.offshore_cash();
+
+        // Buying offshore takes the same shares, at the offshore price plus the fee.
+        market.apply_agent_action(
+            &mut agent,
+            &AgentAction::OffshoreBuy {
+                stonk_id: 0,
+                amount: 10,
+            },
+            &mut agents,
+        )?;
+        assert_eq!(agent.owned_stonks()[0], 10);
+        assert_eq!(market.stonks[0].allocated_shares, 10);
+        let cost = Money::from_cents(10_000);
+        assert_eq!(
+            agent.offshore_cash(),
+            offshore_cash.saturating_sub(cost.saturating_add(trade_fee(cost, OFFSHORE_FEE)))
+        );
+        // The offshore premium moved, not the main price.
+        assert!(market.offshore.premium(0) > 0.0);
+        assert!(market.pending_bumps[0] == 0.0);
+
+        let too_many = market.offshore.max_trade_amount(&market.stonks[0]) + 1;
+        assert!(market
+            .apply_agent_action(
+                &mut agent,
+                &AgentAction::OffshoreBuy {
+                    stonk_id: 0,
+                    amount: too_many,
+                },
+                &mut agents,
+            )
+            .is_err());
+
+        // The offshore exchange is closed at night.
+        market.phase = GamePhase::Night {
+            cycle: 0,
+            counter: 0,
+        };
+        assert!(market
+            .apply_agent_action(
+                &mut agent,
+                &AgentAction::OffshoreSell {
+                    stonk_id: 0,
+                    amount: 10,
+                },
+                &mut agents,
+            )
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_futures_settlement() -> AppResult<()> {
+        let mut market = Market::new();
+        let commodity = market
+            .stonks
+            .iter()
+            .find(|stonk| stonk.class == StonkClass::Commodity)
+            .map(|stonk| stonk.id)
+            .expect("A Commodity stonk should exist");
+        market.stonks[commodity].set_test_values(1_000, 10_000, 0.0, 0.0, 0.0, 0.0);
+        let mut agent = UserAgent::new(SessionAuth::new("farmer".to_string(), [0; 32]));
+        let mut agents = HashMap::new();
+        let cash = agent.cash();
+        let open = |stonk_id, amount, is_long| AgentAction::OpenFutures {
+            stonk_id,
+            amount,
+            is_long,
+        };
+
+        // Only Commodity stonks list futures, up to a stake of the shares.
+        let other = market
+            .stonks
+            .iter()
+            .find(|stonk| stonk.class != StonkClass::Commodity)
+            .map(|stonk| stonk.id)
+            .expect("A non Commodity stonk should exist");
+        assert!(market
+            .apply_agent_action(&mut agent, &open(other, 10, true), &mut agents)
+            .is_err());
+        assert!(market
+            .apply_agent_action(&mut agent, &open(commodity, 501, true), &mut agents)
+            .is_err());
+
+        market.apply_agent_action(&mut agent, &open(commodity, 100, true), &mut agents)?;
+        market.apply_agent_action(&mut agent, &open(commodity, 100, false), &mut agents)?;
+        let margin = Money::from_dollars(200);
+        let fee = trade_fee(Money::from_dollars(1_000), FUTURES_FEE);
+        assert_eq!(
+            agent.cash(),
+            cash.saturating_sub(margin.saturating_add(fee).saturating_mul(2))
+        );
+        // Open contracts count toward the net worth at their current value.
+        assert_eq!(
+            market.net_worth(&agent),
+            cash.saturating_sub(fee.saturating_mul(2))
+        );
+
+        // Contracts cannot be opened close to the end of the season.
+        market.phase = GamePhase::Day {
+            cycle: DAYS_PER_SEASON - FUTURES_CUTOFF_DAYS,
+            counter: 0,
+        };
+        assert!(market
+            .apply_agent_action(&mut agent, &open(commodity, 10, true), &mut agents)
+            .is_err());
+
+        // At the first dawn of the next season, the long contract pays its capped profit and
+        // the short one loses its margin to the treasury.
+        market.stonks[commodity].set_test_values(1_500, 10_000, 0.0, 0.0, 0.0, 0.0);
+        let cash = agent.cash();
+        agents.insert(agent.username().to_string(), agent);
+        market.settle_futures(&mut agents);
+        assert_eq!(agents["farmer"].cash(), cash);
+        market.phase = GamePhase::Day {
+            cycle: DAYS_PER_SEASON,
+            counter: 0,
+        };
+        let treasury = market.treasury.balance();
+        market.settle_futures(&mut agents);
+        assert_eq!(
+            agents["farmer"].cash(),
+            cash.saturating_add(margin.saturating_mul(2))
+        );
+        // The treasury only holds the fees, so the profit is printed.
+        assert_eq!(market.treasury.balance(), treasury.saturating_add(margin));
+        assert_eq!(market.futures.contracts_of("farmer").count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hostile_intel() -> AppResult<()> {
+        let mut market = Market::new();
+        let mut attacker = UserAgent::new(SessionAuth::new("attacker".to_string(), [0; 32]));
+        let mut insider = UserAgent::new(SessionAuth::new("insider".to_string(), [0; 32]));
+        for stonk in market
+            .stonks
+            .iter()
+            .filter(|s| s.class == StonkClass::Media)
+        {
+            let amount = (stonk.number_of_shares as f64 * INTEL_MEDIA_STAKE_PERCENT / 100.0).ceil();
+            insider.add_stonk(stonk.id, amount as u32)?;
+        }
+        assert!(market.has_media_contacts(&insider));
+        assert!(!market.has_media_contacts(&attacker));
+
+        attacker.select_action(AgentAction::CrashAll);
+        attacker.select_action(AgentAction::CrashAgentStonks {
+            username: "insider".to_string(),
+        });
+        let agents = HashMap::from([
+            ("attacker".to_string(), attacker),
+            ("insider".to_string(), insider),
+        ]);
+        market.update_hostile_intel(&agents);
+        assert!(market.hostile_intel.is_empty());
+
+        market.phase = GamePhase::Night {
+            cycle: 0,
+            counter: 1,
+        };
+        market.update_hostile_intel(&agents);
+        assert_eq!(
+            market.hostile_intel,
+            vec![
+                HostileIntel::MarketCrash,
+                HostileIntel::CharacterAssassination {
+                    target: "insider".to_string()
+                }
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_board_decisions() -> AppResult<()> {
+        let mut market = Market::new();
+        market.stonks[0].set_test_values(1_000, 1_000, 0.0, 0.0, 0.0, 0.0);
+        let mut chair = UserAgent::new(SessionAuth::new("chair".to_string(), [0; 32]));
+        let mut minor = UserAgent::new(SessionAuth::new("minor".to_string(), [0; 32]));
+        for (agent, amount) in [(&mut chair, 300), (&mut minor, 100)] {
+            market.stonks[0].allocate_shares_to_agent(agent.username(), amount)?;
+            agent.add_stonk(0, amount)?;
+        }
+        let chair_cash = chair.cash();
+        let minor_cash = minor.cash();
+        let mut agents = HashMap::from([("minor".to_string(), minor)]);
+        let decision = |decision| AgentAction::BoardDecision {
+            stonk_id: 0,
+            decision,
+        };
+
+        // Every shareholder is paid, and the price drops as much.
+        market.apply_agent_action(
+            &mut chair,
+            &decision(BoardDecision::DeclareDividend),
+            &mut agents,
+        )?;
+        assert_eq!(
+            chair.cash(),
+            chair_cash.saturating_add(Money::from_cents(6_000))
+        );
+        assert_eq!(
+            agents["minor"].cash(),
+            minor_cash.saturating_add(Money::from_cents(2_000))
+        );
+        assert_eq!(market.stonks[0].current_unit_price_cents(), 980);
+        assert_eq!(market.boards.news(market.last_tick).len(), 1);
+
+        // The board meets once per day, and only the chair can call it.
+        assert!(market
+            .apply_agent_action(&mut chair, &decision(BoardDecision::Buyback), &mut agents)
+            .is_err());
+        market.last_tick += BOARD_DECISION_COOLDOWN_TICKS;
+        let mut minor = agents.remove("minor").expect("Missing agent");
+        assert!(market
+            .apply_agent_action(&mut minor, &decision(BoardDecision::Buyback), &mut agents)
+            .is_err());
+
+        // A buyback keeps the market cap.
+        let market_cap = market.stonks[0].market_cap();
+        market.apply_agent_action(&mut chair, &decision(BoardDecision::Buyback), &mut agents)?;
+        assert_eq!(market.stonks[0].number_of_shares, 980);
+        assert_eq!(market.stonks[0].market_cap(), market_cap);
+        Ok(())
+    }
+
+    #[test]
+    fn test_class_bumps_have_diminishing_returns() -> AppResult<()> {
+        let mut market = Market::new();
+        let mut agents = HashMap::new();
+        let bump = AgentAction::BumpStonkClass {
+            class: StonkClass::War,
+        };
+        let mut amounts = vec![];
+        for index in 0..5 {
+            let mut agent = UserAgent::new(SessionAuth::new(format!("cartel{}", index), [0; 32]));
+            amounts.push(market.class_bumps.next_bump(StonkClass::War, 0));
+            let result = market.apply_agent_action(&mut agent, &bump, &mut agents);
+            assert_eq!(result.is_ok(), index < 4);
+        }
+        assert_eq!(amounts[0], CLASS_BUMP);
+        assert!((amounts[1] - CLASS_BUMP * CLASS_BUMP_DIMINISHING_FACTOR).abs() < 1e-9);
+        // The fourth bump is cut to reach the cap, and the fifth is refused.
+        assert!(amounts[3] < CLASS_BUMP * CLASS_BUMP_DIMINISHING_FACTOR.powi(3));
+        assert_eq!(amounts[4], 0.0);
+        let (_, count, total) = market.class_bumps.of_day(0)[0];
+        assert_eq!(count, 4);
+        assert!((total - MAX_CLASS_BUMP_PER_DAY).abs() < 1e-9);
+
+        // Other classes and the next day start from a full bump.
+        assert_eq!(
+            market.class_bumps.next_bump(StonkClass::Media, 0),
+            CLASS_BUMP
+        );
+        assert_eq!(market.class_bumps.next_bump(StonkClass::War, 1), CLASS_BUMP);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dividends_are_checked_when_applied() -> AppResult<()> {
+        let mut market = Market::new();
+        let mut agent = UserAgent::new(SessionAuth::new("holder".to_string(), [0; 32]));
+        let mut agents = HashMap::new();
+        market.apply_agent_action(
+            &mut agent,
+            &AgentAction::Buy {
+                stonk_id: 0,
+                amount: 10,
+            },
+            &mut agents,
+        )?;
+        let dividends = AgentAction::GetDividends { stonk_id: 0 };
+
+        // A short history is an error, not a panic.
+        let cash = agent.cash();
+        assert!(market
+            .apply_agent_action(&mut agent, &dividends, &mut agents)
+            .is_err());
+        assert_eq!(agent.cash(), cash);
+
+        // The price fell after the dividends were unlocked.
+        market.phase = GamePhase::Night {
+            cycle: 0,
+            counter: 0,
+        };
+        market.last_tick = DAY_LENGTH;
+        let mut prices = vec![200; DAY_LENGTH];
+        prices[DAY_LENGTH - 1] = 100;
+        market.stonks[0].historical_prices = PriceSeries::new(0, prices.clone());
+        assert!(market
+            .apply_agent_action(&mut agent, &dividends, &mut agents)
+            .is_err());
+        assert_eq!(agent.cash(), cash);
+
+        prices[DAY_LENGTH - 1] = 300;
+        market.stonks[0].historical_prices = PriceSeries::new(0, prices);
+        market.apply_agent_action(&mut agent, &dividends, &mut agents)?;
+        assert!(agent.cash() > cash);
+
+        // The market tick does not advance at night, so the closed day stays the same.
+        market.phase = GamePhase::Night {
+            cycle: 0,
+            counter: NIGHT_LENGTH - 1,
+        };
+        assert_eq!(market.last_closed_day_start_tick(), Some(0));
+        market.phase = GamePhase::Day {
+            cycle: 1,
+            counter: 0,
+        };
+        assert_eq!(market.last_closed_day_start_tick(), Some(0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_night_events_are_rolled_once() -> AppResult<()> {
+        let mut market = Market::new();
+        for probability in market.events.unlock_probabilities.values_mut() {
+            *probability = 1.0;
+        }
+        market.phase = GamePhase::Day {
+            cycle: 0,
+            counter: DAY_LENGTH - 1,
+        };
+        let mut agent = UserAgent::new(SessionAuth::new("gambler".to_string(), [0; 32]));
+        agent.sub_cash(agent.cash().saturating_sub(Money::from_dollars(500)))?;
+        let agents = HashMap::from([("gambler".to_string(), agent)]);
+
+        // The same tick gives the same cards, however many sessions draw them.
+        let (mut first_market, mut first_agents) = (market.clone(), agents.clone());
+        first_market.apply_event(&MarketEvent::Tick { seed: 7 }, &mut first_agents)?;
+        let (mut second_market, mut second_agents) = (market, agents);
+        second_market.apply_event(&MarketEvent::Tick { seed: 7 }, &mut second_agents)?;
+        let events = first_agents["gambler"].available_night_events().clone();
+        assert!(events.contains(&NightEvent::LuckyNight));
+        assert_eq!(second_agents["gambler"].available_night_events(), &events);
+
+        // Later night ticks keep them, the next day drops them.
+        first_market.apply_event(&MarketEvent::Tick { seed: 8 }, &mut first_agents)?;
+        assert_eq!(first_agents["gambler"].available_night_events(), &events);
+        first_market.phase = GamePhase::Night {
+            cycle: 0,
+            counter: NIGHT_LENGTH - 1,
+        };
+        first_market.apply_event(&MarketEvent::Tick { seed: 9 }, &mut first_agents)?;
+        assert!(first_agents["gambler"].available_night_events().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_night_selection_lock_in() {
+        let mut market = Market::new();
+        let mut agent = UserAgent::new(SessionAuth::new("indecisive".to_string(), [0; 32]));
+        market.phase = GamePhase::Night {
+            cycle: 0,
+            counter: 0,
+        };
+        assert!(!market.night_selection_locked());
+
+        agent.select_action(NightEvent::LuckyNight.action());
+        agent.select_action(NightEvent::Stimulus.action());
+        agent.unselect_action(&NightEvent::LuckyNight.action());
+        assert_eq!(
+            agent.selected_actions(),
+            &vec![NightEvent::Stimulus.action()]
+        );
+        agent.unselect_action(&NightEvent::Stimulus.action());
+        assert!(agent.selected_actions().is_empty());
+        assert!(agent.action_sequence().is_none());
+
+        market.phase = GamePhase::Night {
+            cycle: 0,
+            counter: NIGHT_LENGTH - market.events.selection_lock_ticks - 1,
+        };
+        assert!(!market.night_selection_locked());
+        market.phase = GamePhase::Night {
+            cycle: 0,
+            counter: NIGHT_LENGTH - market.events.selection_lock_ticks,
+        };
+        assert!(market.night_selection_locked());
+
+        market.events.selection_lock_ticks = 0;
+        assert!(!market.night_selection_locked());
+    }
+
+    #[test]
+    fn test_balance_report_at_day_close() -> AppResult<()> {
+        let mut market = Market::new();
+        market.telemetry.enabled = true;
+        for probability in market.events.unlock_probabilities.values_mut() {
+            *probability = 1.0;
+        }
+        market.phase = GamePhase::Day {
+            cycle: 0,
+            counter: DAY_LENGTH - 1,
+        };
+        let mut agent = UserAgent::new(SessionAuth::new("gambler".to_string(), [0; 32]));
+        agent.sub_cash(agent.cash().saturating_sub(Money::from_dollars(500)))?;
+        let mut agents = HashMap::from([("gambler".to_string(), agent)]);
+
+        market.apply_event(&MarketEvent::Tick { seed: 7 }, &mut agents)?;
+        let reports = market.telemetry.take_finished();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].players, 1);
+        assert_eq!(reports[0].wealth_by_tenure[0].players, 1);
+
+        // The events offered at night are counted in the report of the next day.
+        let agent = agents.get("gambler").ok_or("Missing agent")?;
+        assert_eq!(agent.days_played(), 1);
+        let event = agent
+            .available_night_events()
+            .iter()
+            .find(|event| matches!(event, NightEvent::LuckyNight | NightEvent::AGoodOffer))
+            .cloned()
+            .ok_or("No night event")?;
+        market.apply_event(
+            &MarketEvent::agent_actions("gambler".to_string(), vec![event.action()]),
+            &mut agents,
+        )?;
+        for seed in 0..(DAY_LENGTH + NIGHT_LENGTH) as u64 {
+            market.apply_event(&MarketEvent::Tick { seed }, &mut agents)?;
+        }
+        let reports = market.telemetry.take_finished();
+        assert_eq!(reports.len(), 1);
+        let stats = &reports[0].events[&event.to_string()];
+        assert_eq!((stats.offered, stats.picked), (1, 1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_bots_trade_without_real_shares() -> AppResult<()> {
+        let mut market = Market::new();
+        let mut agents = HashMap::new();
+        let allocated = market
+            .stonks
+            .iter()
+            .map(|stonk| stonk.allocated_shares)
+            .collect::<Vec<u32>>();
+        let balance = market.treasury.balance();
+
+        market.apply_event(
+            &MarketEvent::ScaleBots {
+                size: 3,
+                aggressiveness: 1.0,
+            },
+            &mut agents,
+        )?;
+        for seed in 0..10 {
+            market.apply_event(&MarketEvent::Tick { seed }, &mut agents)?;
+        }
+
+        assert!(market.trade_tape.len() >= 10);
+        // The first bot trades at random at every tick.
+        let bot = &market.bots.bots()[0];
+        assert_eq!(bot.strategy_name, "noise");
+        assert!(bot.account.owned_stonks.iter().any(|amount| *amount > 0));
+        for (stonk, allocated) in market.stonks.iter().zip(allocated) {
+            assert_eq!(stonk.allocated_shares, allocated);
+        }
+        assert_eq!(market.treasury.balance(), balance);
+
+        market.apply_event(
+            &MarketEvent::ScaleBots {
+                size: 0,
+                aggressiveness: 0.0,
+            },
+            &mut agents,
+        )?;
+        let trades = market.trade_tape.len();
+        market.apply_event(&MarketEvent::Tick { seed: 10 }, &mut agents)?;
+        assert_eq!(market.trade_tape.len(), trades);
+        Ok(())
+    }
+
+    #[test]
+    fn test_market_maker_damps_whales() -> AppResult<()> {
+        let whale_buy = |share: f64| -> AppResult<Market> {
+            let mut market = Market::new();
+            market.trading.market_maker_share = share;
+            let mut whale = UserAgent::new(SessionAuth::new("whale".to_string(), [0; 32]));
+            market.apply_agent_action(
+                &mut whale,
+                &AgentAction::Buy {
+                    stonk_id: 0,
+                    amount: 100,
+                },
+                &mut HashMap::new(),
+            )?;
+            Ok(market)
+        };
+
+        let unmade = whale_buy(0.0)?;
+        let made = whale_buy(0.5)?;
+        assert_eq!(unmade.treasury.balance(), Money::ZERO);
+        assert!(made.treasury.balance() > Money::ZERO);
+        assert!((made.pending_bumps[0] - unmade.pending_bumps[0] / 2.0).abs() < 1e-9);
+        assert_eq!(made.market_maker.inventory(0), -50);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_treasury_collects_and_pays() -> AppResult<()> {
+        let mut market = Market::new();
+        let mut agent = UserAgent::new(SessionAuth::new("broke".to_string(), [0; 32]));
+        let mut agents = HashMap::new();
+
+        // An empty treasury pays nothing.
+        assert!(market
+            .apply_agent_action(&mut agent, &AgentAction::ReceiveBailout, &mut agents)
+            .is_err());
+        assert_eq!(agent.cash(), INITIAL_USER_CASH);
+
+        market.apply_agent_action(
+            &mut agent,
+            &AgentAction::DarkPoolBuy {
+                stonk_id: 0,
+                amount: 10,
+            },
+            &mut agents,
+        )?;
+        let fees = market.treasury.balance();
+        assert!(fees > Money::ZERO);
+        assert_eq!(market.treasury.today().inflows, fees);
+
+        market.treasury.deposit(BAILOUT_AMOUNT);
+        let cash = agent.cash();
+        market.apply_agent_action(&mut agent, &AgentAction::ReceiveBailout, &mut agents)?;
+        assert_eq!(agent.cash(), cash.saturating_add(BAILOUT_AMOUNT));
+        assert_eq!(market.treasury.balance(), fees);
+        assert_eq!(market.treasury.today().outflows, BAILOUT_AMOUNT);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_trades_netting() -> AppResult<()> {
+        let mut market = Market::new();
+        let mut buyer = UserAgent::new(SessionAuth::new("buyer".to_string(), [0; 32]));
+        let mut seller = UserAgent::new(SessionAuth::new("seller".to_string(), [0; 32]));
+        let mut agents = HashMap::new();
+
+        seller.select_action(AgentAction::Buy {
+            stonk_id: 0,
+            amount: 10,
+        });
+        market.apply_agent_actions(&mut seller, &mut agents)?;
+        let bump = market.pending_bumps[0];
+        assert!(bump > 0.0);
+
+        buyer.select_action(AgentAction::Buy {
+            stonk_id: 0,
+            amount: 5,
+        });
+        seller.select_action(AgentAction::Sell {
+            stonk_id: 0,
+            amount: 5,
+        });
+        // The server applies the actions in the order they were selected.
+        assert!(buyer.action_sequence() < seller.action_sequence());
+        market.apply_agent_actions(&mut buyer, &mut agents)?;
+        market.apply_agent_actions(&mut seller, &mut agents)?;
+        assert_eq!(seller.action_sequence(), None);
+        assert!((market.pending_bumps[0] - bump).abs() < 1e-9);
+
+        // The net bump is applied once, at the next tick.
+        market.tick();
+        assert_eq!(market.pending_bumps, [0.0; NUMBER_OF_STONKS]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_liquidate_agent() -> AppResult<()> {
+        let mut market = Market::new();
+        let mut agent = UserAgent::new(SessionAuth::new("dormant".to_string(), [0; 32]));
+        agent.select_action(AgentAction::Buy {
+            stonk_id: 0,
+            amount: 10,
+        });
+        market.apply_agent_actions(&mut agent, &mut HashMap::new())?;
+        // A ghost entry, not backed by the agent holdings.
+        market.stonks[1].allocate_shares_to_agent("dormant", 3)?;
+
+        let cash = agent.cash();
+        let value = market.stonks[0].sell_price(10);
+        let proceeds = market.liquidate_agent(&mut agent)?;
+        assert!(proceeds < value && proceeds >= value.percent(80));
+        assert_eq!(agent.cash(), cash.saturating_add(proceeds));
+        assert_eq!(agent.owned_stonks(), &[0; NUMBER_OF_STONKS]);
+        for stonk in market.stonks.iter() {
+            assert!(stonk.shareholders.is_empty());
+            assert_eq!(stonk.allocated_shares, 0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_tournament_round() -> AppResult<()> {
+        let mut market = Market::new();
+        market.tournament.config.min_players = 2;
+        market.tournament.config.round_ticks = 2;
+        let mut agents = ["alice", "bob", "carol"]
+            .into_iter()
+            .map(|username| {
+                (
+                    username.to_string(),
+                    UserAgent::new(SessionAuth::new(username.to_string(), [0; 32])),
+                )
+            })
+            .collect::<HashMap<String, UserAgent>>();
+        for username in ["alice", "bob"] {
+            let mut agent = agents[username].clone();
+            agent.select_action(AgentAction::JoinTournament);
+            market.apply_agent_actions(&mut agent, &mut agents)?;
+        }
+
+        market.update_tournament(&mut agents);
+        assert!(market.tournament.current_round().is_some());
+        assert!(agents["carol"].tournament_account().is_none());
+
+        // Paper trades do not move the real stonks.
+        let mut alice = agents["alice"].clone();
+        alice.select_action(AgentAction::TournamentBuy {
+            stonk_id: 0,
+            amount: 10,
+        });
+        market.apply_agent_actions(&mut alice, &mut agents)?;
+        assert_eq!(
+            alice.tournament_account().map(|a| a.owned_stonks[0]),
+            Some(10)
+        );
+        assert_eq!(alice.owned_stonks()[0], 0);
+        assert_eq!(market.stonks[0].allocated_shares, 0);
+        agents.insert("alice".to_string(), alice);
+
+        market.tick();
+        market.tick();
+        market.update_tournament(&mut agents);
+        assert_eq!(market.tournament.rounds[0].results.len(), 2);
+        assert!(market.tournament.winner.is_some());
+        assert!(agents
+            .values()
+            .all(|agent| agent.tournament_account().is_none()));
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn test_market_invariants(trades in prop::collection::vec(trade_strategy(), 1..60)) {
+            let mut market = Market::new();
+            // Few shares, so that stonks can actually sell out.
+            for stonk in market.stonks.iter_mut() {
+                stonk.number_of_shares = 1_000;
+            }
+
+            let usernames = ["alice", "bob", "carol"];
+            let mut agents = usernames
+                .iter()
+                .map(|username| {
+                    let agent = UserAgent::new(SessionAuth::new(username.to_string(), [0; 32]));
+                    (username.to_string(), agent)
+                })
+                .collect::<HashMap<String, UserAgent>>();
+
+            for (agent_index, action) in trades {
+                let username = usernames[agent_index];
+                let mut agent = agents.remove(username).expect("Agent should exist");
+                let before = (agent.cash(), *agent.owned_stonks());
+
+                agent.select_action(action);
+                if market.apply_agent_actions(&mut agent, &mut agents).is_err() {
+                    // A rejected trade must not change the agent.
+                    prop_assert_eq!(before, (agent.cash(), *agent.owned_stonks()));
+                }
+                agents.insert(username.to_string(), agent);
+
+                for stonk in market.stonks.iter() {
+                    prop_assert!(stonk.allocated_shares <= stonk.number_of_shares);
+                    let shareholders_amount: u32 =
+                        stonk.shareholders.iter().map(|(_, amount)| amount).sum();
+                    prop_assert_eq!(shareholders_amount, stonk.allocated_shares);
+                    let owned_amount: u32 = agents
+                        .values()
+                        .map(|agent| agent.owned_stonks()[stonk.id])
+                        .sum();
+                    prop_assert_eq!(owned_amount, stonk.allocated_shares);
+                }
+            }
+        }
+
+        #[test]
+        fn test_max_buy_amount_is_affordable(stonk_id in 0..NUMBER_OF_STONKS, cash in any::<u32>()) {
+            let market = Market::new();
+            let stonk = &market.stonks[stonk_id];
+            let cash = Money::from(cash);
+            let amount = stonk.max_buy_amount(cash);
+            prop_assert!(stonk.buy_price(amount) <= cash);
         }
-        Ok(())
     }
 }
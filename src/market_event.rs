@@ -0,0 +1,289 @@
+// Every change of the market state goes through a MarketEvent, applied by Market::apply_event.
+// Events carry their own random seed, so replaying the same events from the same state
+// always gives the same result.
+
+use crate::{
+    agent::{AgentAction, DecisionAgent, UserAgent},
+    load_shedding::TickDetail,
+    market::Market,
+    utils::AppResult,
+};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::debug;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MarketEvent {
+    /// Advances the market by one tick, together with everything that depends on time.
+    Tick { seed: u64 },
+    /// Applies the actions selected by an agent.
+    AgentActions {
+        username: String,
+        actions: Vec<AgentAction>,
+        seed: u64,
+    },
+    /// Sells all the holdings of an agent back to the market.
+    LiquidateAgent { username: String },
+    /// Moves everything recorded under a username to a new one.
+    RenameAgent {
+        old_username: String,
+        username: String,
+    },
+    /// Resizes the bot population and sets how often bots trade.
+    ScaleBots { size: usize, aggressiveness: f64 },
+    /// Sets how much work is done at every tick, reduced under heavy load.
+    SetTickDetail { detail: TickDetail },
+    /// Suspends the trading of a stonk, freezing its price, or restores it.
+    SetStonkSuspended { stonk_id: usize, suspended: bool },
+}
+
+impl MarketEvent {
+    pub fn tick() -> Self {
+        Self::Tick {
+            seed: rand::random(),
+        }
+    }
+
+    pub fn agent_actions(username: String, actions: Vec<AgentAction>) -> Self {
+        Self::AgentActions {
+            username,
+            actions,
+            seed: rand::random(),
+        }
+    }
+}
+
+impl Market {
+    /// The reducer applying a single event to the market and to the agents.
+    pub fn apply_event(
+        &mut self,
+        event: &MarketEvent,
+        agents: &mut HashMap<String, UserAgent>,
+    ) -> AppResult<()> {
+        debug!("Applying market event {:?}", event);
+        self.revision = self.revision.wrapping_add(1);
+        match event {
+            MarketEvent::Tick { seed } => {
+                self.rng = ChaCha8Rng::seed_from_u64(*seed);
+                self.last_tick_seed = Some(*seed);
+                self.update_target_total_market_cap(agents.len());
+                if self
+                    .tick_detail
+                    .updates_portfolios(self.phase, self.last_tick)
+                {
+                    self.update_portfolios(agents);
+                }
+                self.update_reputations(agents);
+                self.tick();
+                // Conditions are also checked for offline agents, so expire them for everyone.
+                for agent in agents.values_mut() {
+                    agent.apply_conditions(self.last_tick);
+                }
+                self.settle_futures(agents);
+                self.run_opening_auction(agents);
+                self.resolve_assassination_threats(agents);
+                // The report of the closed day is started before the night events are counted.
+                self.update_telemetry(agents);
+                self.audit_closed_day();
+                self.update_night_events(agents);
+                self.update_hostile_intel(agents);
+                self.execute_scheduled_orders(agents);
+                self.update_tournament(agents);
+                Ok(())
+            }
+            MarketEvent::AgentActions {
+                username,
+                actions,
+                seed,
+            } => {
+                self.rng = ChaCha8Rng::seed_from_u64(*seed);
+                let mut agent = agents
+                    .get(username)
+                    .cloned()
+                    .ok_or_else(|| format!("Agent {} not found", username))?;
+                agent.clear_actions();
+                for action in actions.iter() {
+                    agent.select_action(action.clone());
+                }
+                let result = self.apply_agent_actions(&mut agent, agents);
+                agents.insert(username.clone(), agent);
+                result
+            }
+            MarketEvent::LiquidateAgent { username } => {
+                let agent = agents
+                    .get_mut(username)
+                    .ok_or_else(|| format!("Agent {} not found", username))?;
+                self.liquidate_agent(agent)?;
+                Ok(())
+            }
+            MarketEvent::RenameAgent {
+                old_username,
+                username,
+            } => {
+                self.rename_agent(old_username, username);
+                Ok(())
+            }
+            MarketEvent::ScaleBots {
+                size,
+                aggressiveness,
+            } => {
+                self.bots.resize(*size, *aggressiveness);
+                Ok(())
+            }
+            MarketEvent::SetTickDetail { detail } => {
+                self.tick_detail = *detail;
+                Ok(())
+            }
+            MarketEvent::SetStonkSuspended {
+                stonk_id,
+                suspended,
+            } => {
+                let stonk = self
+                    .stonks
+                    .get_mut(*stonk_id)
+                    .ok_or_else(|| format!("Stonk {} not found", stonk_id))?;
+                stonk.set_suspended(*suspended);
+                Ok(())
+            }
+        }
+    }
+
+    /// Applies the events in order, returning the errors of the failed ones.
+    pub fn replay(
+        &mut self,
+        events: &[MarketEvent],
+        agents: &mut HashMap<String, UserAgent>,
+    ) -> Vec<String> {
+        events
+            .iter()
+            .filter_map(|event| self.apply_event(event, agents).err())
+            .map(|e| e.to_string())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MarketEvent;
+    use crate::{
+        agent::{AgentAction, DecisionAgent, UserAgent},
+        market::Market,
+        ssh_client::SessionAuth,
+        utils::AppResult,
+    };
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_replay_is_deterministic() -> AppResult<()> {
+        let market = Market::new();
+        let agents = ["alice", "bob"]
+            .into_iter()
+            .map(|username| {
+                (
+                    username.to_string(),
+                    UserAgent::new(SessionAuth::new(username.to_string(), [0; 32])),
+                )
+            })
+            .collect::<HashMap<String, UserAgent>>();
+
+        let mut events = vec![];
+        for tick in 0..50_u64 {
+            events.push(MarketEvent::AgentActions {
+                username: if tick % 2 == 0 { "alice" } else { "bob" }.to_string(),
+                actions: vec![AgentAction::Buy {
+                    stonk_id: tick as usize % 8,
+                    amount: 1,
+                }],
+                seed: tick,
+            });
+            events.push(MarketEvent::Tick { seed: tick });
+        }
+        events.push(MarketEvent::AgentActions {
+            username: "alice".to_string(),
+            actions: vec![AgentAction::Sell {
+                stonk_id: 0,
+                amount: 5,
+            }],
+            seed: 0,
+        });
+        events.push(MarketEvent::RenameAgent {
+            old_username: "bob".to_string(),
+            username: "robert".to_string(),
+        });
+
+        let (mut first_market, mut first_agents) = (market.clone(), agents.clone());
+        assert_eq!(
+            first_market.replay(&events, &mut first_agents),
+            Vec::<String>::new()
+        );
+        let (mut second_market, mut second_agents) = (market, agents);
+        assert!(second_market.replay(&events, &mut second_agents).is_empty());
+
+        assert_eq!(
+            serde_json::to_value(&first_market)?,
+            serde_json::to_value(&second_market)?
+        );
+        assert_eq!(first_agents["alice"].owned_stonks()[0], 2);
+        assert_eq!(
+            first_market.stonks[1].shareholders,
+            vec![("robert".to_string(), 7)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_suspended_stonk() -> AppResult<()> {
+        let mut market = Market::new();
+        let mut agents = HashMap::from([(
+            "alice".to_string(),
+            UserAgent::new(SessionAuth::new("alice".to_string(), [0; 32])),
+        )]);
+        let buy = |seed| MarketEvent::AgentActions {
+            username: "alice".to_string(),
+            actions: vec![AgentAction::Buy {
+                stonk_id: 2,
+                amount: 3,
+            }],
+            seed,
+        };
+        market.apply_event(&buy(0), &mut agents)?;
+
+        market.apply_event(
+            &MarketEvent::SetStonkSuspended {
+                stonk_id: 2,
+                suspended: true,
+            },
+            &mut agents,
+        )?;
+        assert!(market.apply_event(&buy(1), &mut agents).is_err());
+        let price = market.stonks[2].current_unit_price_cents();
+        for seed in 0..20 {
+            market.apply_event(&MarketEvent::Tick { seed }, &mut agents)?;
+        }
+        assert_eq!(market.stonks[2].current_unit_price_cents(), price);
+        // The holdings survive the suspension.
+        assert_eq!(agents["alice"].owned_stonks()[2], 3);
+
+        market.apply_event(
+            &MarketEvent::SetStonkSuspended {
+                stonk_id: 2,
+                suspended: false,
+            },
+            &mut agents,
+        )?;
+        market.apply_event(&buy(2), &mut agents)?;
+        assert_eq!(agents["alice"].owned_stonks()[2], 6);
+        assert!(market
+            .apply_event(
+                &MarketEvent::SetStonkSuspended {
+                    stonk_id: 100,
+                    suspended: true,
+                },
+                &mut agents,
+            )
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+// The built-in market maker takes the other side of part of the player order flow. The
+// absorbed part of a trade does not bump the drift, so a single whale cannot send a stonk
+// vertical, and the spread paid on it goes to the treasury. The maker leans against moves
+// away from the fair value, and absorbs less as its inventory fills up.
+
+use crate::{market::NUMBER_OF_STONKS, money::Money, stonk::Stonk};
+use serde::{Deserialize, Serialize};
+
+// Largest stake of a stonk the market maker holds, long or short.
+pub const MAX_INVENTORY_STAKE: f64 = 0.05;
+// Share of the inventory unwound at every tick, outside of the market.
+pub const INVENTORY_UNWIND_PER_TICK: f64 = 0.02;
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MarketMaker {
+    // Shares held in each stonk: positive after absorbing sells, negative after absorbing buys.
+    #[serde(default)]
+    inventory: [i64; NUMBER_OF_STONKS],
+}
+
+impl MarketMaker {
+    pub fn inventory(&self, stonk_id: usize) -> i64 {
+        self.inventory[stonk_id]
+    }
+
+    /// Takes part of a trade, a positive amount for player buys and a negative one for
+    /// player sells. Returns the fraction absorbed, from 0 to 1.
+    pub fn absorb(&mut self, stonk: &Stonk, fair_value: u32, amount: i64, share: f64) -> f64 {
+        if amount == 0 || share <= 0.0 {
+            return 0.0;
+        }
+
+        // Trades pushing the price away from its fair value are absorbed more.
+        let price = stonk.current_unit_price_cents() as f64;
+        let deviation = if fair_value > 0 {
+            (price - fair_value as f64) / fair_value as f64
+        } else {
+            0.0
+        };
+        let pushes_away = (amount > 0) == (deviation > 0.0);
+        let lean = if pushes_away {
+            1.0 + deviation.abs().min(1.0)
+        } else {
+            1.0 - deviation.abs().min(1.0)
+        };
+        let mut fraction = (share * lean).clamp(0.0, 1.0);
+
+        // The maker sells to buyers and buys from sellers, up to its inventory limit.
+        let max_inventory = (stonk.number_of_shares as f64 * MAX_INVENTORY_STAKE) as i64;
+        let room = (max_inventory + self.inventory[stonk.id] * amount.signum()).max(0);
+        let absorbed = ((amount.abs() as f64 * fraction) as i64).min(room);
+        if absorbed < amount.abs() {
+            fraction = absorbed as f64 / amount.abs() as f64;
+        }
+        self.inventory[stonk.id] -= absorbed * amount.signum();
+        fraction
+    }
+
+    /// Unwinds part of the inventory, without moving the prices.
+    pub fn tick(&mut self) {
+        for position in self.inventory.iter_mut() {
+            let unwound = (*position as f64 * INVENTORY_UNWIND_PER_TICK) as i64;
+            *position -= if unwound == 0 {
+                position.signum()
+            } else {
+                unwound
+            };
+        }
+    }
+}
+
+/// The spread paid on a trade: what the trade costs or pays beyond its value at the current price.
+pub fn trade_spread(stonk: &Stonk, amount: u32, cost: Money, is_buy: bool) -> Money {
+    let value = stonk.holdings_value(amount);
+    if is_buy {
+        cost.saturating_sub(value)
+    } else {
+        value.saturating_sub(cost)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MarketMaker, MAX_INVENTORY_STAKE};
+    use crate::stonk::Stonk;
+
+    #[test]
+    fn test_market_maker_absorption() {
+        let mut stonk = Stonk::default();
+        stonk.set_test_values(1_000, 10_000, 0.0, 0.0, 0.0, 0.0);
+        let mut maker = MarketMaker::default();
+
+        // At fair value the base share is absorbed, and the maker goes short to sell.
+        assert_eq!(maker.absorb(&stonk, 1_000, 100, 0.5), 0.5);
+        assert_eq!(maker.inventory(0), -50);
+
+        // Above fair value, buys are absorbed more than sells.
+        let mut maker = MarketMaker::default();
+        let buy = maker.absorb(&stonk, 800, 100, 0.5);
+        let sell = maker.absorb(&stonk, 800, -100, 0.5);
+        assert!(buy > 0.5 && sell < 0.5);
+
+        // A full inventory absorbs nothing more on the same side.
+        let max_inventory = (10_000.0 * MAX_INVENTORY_STAKE) as i64;
+        let mut maker = MarketMaker::default();
+        maker.absorb(&stonk, 1_000, 10 * max_inventory, 1.0);
+        assert_eq!(maker.inventory(0), -max_inventory);
+        assert_eq!(maker.absorb(&stonk, 1_000, 100, 1.0), 0.0);
+        assert_eq!(maker.absorb(&stonk, 1_000, -100, 0.5), 0.5);
+
+        for _ in 0..1_000 {
+            maker.tick();
+        }
+        assert_eq!(maker.inventory(0), 0);
+    }
+}
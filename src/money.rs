@@ -0,0 +1,138 @@
+// Amounts of money, in cents. Cash, trade values and net worths all use this type, so that
+// large positions cannot silently wrap around: arithmetic is either checked, for anything
+// that moves money, or saturating, for totals that are only displayed or ranked.
+
+use crate::stonk::DollarValue;
+use serde::{Deserialize, Serialize};
+use std::iter::Sum;
+
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct Money(u64);
+
+impl Money {
+    pub const ZERO: Self = Self(0);
+    pub const MAX: Self = Self(u64::MAX);
+
+    pub const fn from_cents(cents: u64) -> Self {
+        Self(cents)
+    }
+
+    pub const fn from_dollars(dollars: u64) -> Self {
+        Self(dollars * 100)
+    }
+
+    pub const fn cents(self) -> u64 {
+        self.0
+    }
+
+    /// Whole dollars, rounding down.
+    pub const fn dollars(self) -> u64 {
+        self.0 / 100
+    }
+
+    /// Converts a computed value in cents, rounding down. Returns None for negative,
+    /// non-finite or too large values.
+    pub fn from_f64_cents(cents: f64) -> Option<Self> {
+        (cents.is_finite() && cents >= 0.0 && cents < u64::MAX as f64).then_some(Self(cents as u64))
+    }
+
+    /// Like from_f64_cents, but clamps the value to the valid range.
+    pub fn saturating_from_f64_cents(cents: f64) -> Self {
+        // Casting a float to an integer saturates, and NaN becomes 0.
+        Self(cents as u64)
+    }
+
+    pub fn as_f64_cents(self) -> f64 {
+        self.0 as f64
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Self)
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Self)
+    }
+
+    pub fn checked_mul(self, factor: u64) -> Option<Self> {
+        self.0.checked_mul(factor).map(Self)
+    }
+
+    pub fn saturating_add(self, other: Self) -> Self {
+        Self(self.0.saturating_add(other.0))
+    }
+
+    pub fn saturating_sub(self, other: Self) -> Self {
+        Self(self.0.saturating_sub(other.0))
+    }
+
+    pub fn saturating_mul(self, factor: u64) -> Self {
+        Self(self.0.saturating_mul(factor))
+    }
+
+    /// Multiplies by a fraction, rounding down.
+    pub fn percent(self, percent: u64) -> Self {
+        Self((self.0 as u128 * percent as u128 / 100).min(u64::MAX as u128) as u64)
+    }
+
+    pub fn scale(self, factor: f64) -> Self {
+        Self::saturating_from_f64_cents(self.0 as f64 * factor)
+    }
+}
+
+impl From<u32> for Money {
+    fn from(cents: u32) -> Self {
+        Self(cents as u64)
+    }
+}
+
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::ZERO, Self::saturating_add)
+    }
+}
+
+impl DollarValue for Money {
+    fn as_dollars(&self) -> f64 {
+        self.0 as f64 / 100.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Money;
+    use crate::stonk::DollarValue;
+
+    #[test]
+    fn test_money_arithmetic() {
+        let cash = Money::from_cents(u32::MAX as u64);
+        // Values beyond the old u32 cents are fine.
+        assert_eq!(
+            cash.checked_add(cash),
+            Some(Money::from_cents(2 * u32::MAX as u64))
+        );
+        assert_eq!(Money::MAX.checked_add(Money::from_cents(1)), None);
+        assert_eq!(Money::ZERO.checked_sub(Money::from_cents(1)), None);
+        assert_eq!(Money::MAX.saturating_mul(2), Money::MAX);
+        assert_eq!(Money::MAX.percent(100), Money::MAX);
+        assert_eq!(Money::from_cents(1_000).percent(80), Money::from_cents(800));
+
+        assert_eq!(Money::from_f64_cents(f64::NAN), None);
+        assert_eq!(Money::from_f64_cents(-1.0), None);
+        assert_eq!(Money::from_f64_cents(1e30), None);
+        assert_eq!(Money::from_f64_cents(12.9), Some(Money::from_cents(12)));
+        assert_eq!(Money::saturating_from_f64_cents(1e30), Money::MAX);
+
+        assert_eq!(
+            [Money::MAX, Money::from_cents(1)]
+                .into_iter()
+                .sum::<Money>(),
+            Money::MAX
+        );
+        assert_eq!(Money::from_cents(123_456).format(), "1.235k");
+        assert_eq!(Money::MAX.format(), "184,467.441T");
+    }
+}
@@ -0,0 +1,93 @@
+// Rumors about the stonks, written from their lore so that the news stays consistent with
+// the About pages: the same CEO, the same founding year, the same rivals.
+
+use crate::stonk::{Stonk, Stonks};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NewsTone {
+    Good,
+    Bad,
+}
+
+const GOOD_RUMORS: [&str; 4] = [
+    "{ceo} promises {name} will outlive {rival}",
+    "Rumors: {rival} in talks to sell itself to {name}",
+    "{ceo} spotted buying {name} shares at dawn",
+    "{name} has not missed a dividend since {founded}, says {ceo}",
+];
+
+const BAD_RUMORS: [&str; 4] = [
+    "{ceo} denies leaving {name} for {rival}",
+    "{rival} poaches {name} executives, {ceo} furious",
+    "Auditors find {name} books untouched since {founded}",
+    "{ceo} seen selling {name} shares to buy a yacht",
+];
+
+/// A rumor about the stonk. The same seed always gives the same rumor, so that it does not
+/// change at every frame.
+pub fn rumor(stonk: &Stonk, stonks: &Stonks, tone: NewsTone, seed: u64) -> String {
+    let templates = match tone {
+        NewsTone::Good => &GOOD_RUMORS,
+        NewsTone::Bad => &BAD_RUMORS,
+    };
+    let template = templates[(seed % templates.len() as u64) as usize];
+    let lore = &stonk.lore;
+    let ceo = if lore.ceo.is_empty() {
+        "the CEO"
+    } else {
+        lore.ceo.as_str()
+    };
+    let rival = if lore.rivals.is_empty() {
+        "the competition"
+    } else {
+        let rival_id =
+            lore.rivals[(seed / templates.len() as u64 % lore.rivals.len() as u64) as usize];
+        stonks
+            .get(rival_id)
+            .map_or("the competition", |rival| rival.name.as_str())
+    };
+    let founded = if lore.founded > 0 {
+        lore.founded.to_string()
+    } else {
+        "forever".to_string()
+    };
+    template
+        .replace("{ceo}", ceo)
+        .replace("{name}", &stonk.name)
+        .replace("{rival}", rival)
+        .replace("{founded}", &founded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rumor, NewsTone, BAD_RUMORS};
+    use crate::market::Market;
+
+    #[test]
+    fn test_rumors_use_the_lore() {
+        let market = Market::new();
+        let stonk = &market.stonks[0];
+        assert!(!stonk.lore.ceo.is_empty());
+        let rival = &market.stonks[stonk.lore.rivals[0]];
+
+        let rumors = (0..BAD_RUMORS.len() as u64)
+            .map(|seed| rumor(stonk, &market.stonks, NewsTone::Bad, seed))
+            .collect::<Vec<String>>();
+        assert!(rumors.iter().all(|rumor| !rumor.contains('{')));
+        assert!(rumors.iter().any(|rumor| rumor.contains(&stonk.lore.ceo)));
+        assert!(rumors.iter().any(|rumor| rumor.contains(&rival.name)));
+        assert!(rumors
+            .iter()
+            .any(|rumor| rumor.contains(&stonk.lore.founded.to_string())));
+        assert_eq!(
+            rumor(stonk, &market.stonks, NewsTone::Good, 7),
+            rumor(stonk, &market.stonks, NewsTone::Good, 7)
+        );
+
+        // Stonks without lore still get a readable rumor.
+        let mut unknown = stonk.clone();
+        unknown.lore = Default::default();
+        let text = rumor(&unknown, &market.stonks, NewsTone::Bad, 1);
+        assert!(text.contains("the competition") && text.contains("the CEO"));
+    }
+}
@@ -0,0 +1,150 @@
+// Short contextual messages for the player, shown one at a time in the status bar.
+// Each notification has a key, so that a newer message about the same thing replaces the
+// older one. The highest priority notification is shown, the most recent one on ties.
+
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NotificationPriority {
+    Low,
+    Normal,
+    High,
+    Critical,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub key: &'static str,
+    pub priority: NotificationPriority,
+    pub text: String,
+    // Notifications without expiration stay until they are cleared.
+    expires_at: Option<Instant>,
+}
+
+impl Notification {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Notifications {
+    // From the oldest to the most recent.
+    notifications: Vec<Notification>,
+}
+
+impl Notifications {
+    /// Shows a notification until it is cleared or replaced.
+    pub fn set(&mut self, key: &'static str, priority: NotificationPriority, text: String) {
+        // Refreshing the same text must not make it more recent than the others.
+        if let Some(notification) = self
+            .notifications
+            .iter_mut()
+            .find(|notification| notification.key == key && notification.expires_at.is_none())
+        {
+            if notification.priority == priority {
+                notification.text = text;
+                return;
+            }
+        }
+        self.insert(Notification {
+            key,
+            priority,
+            text,
+            expires_at: None,
+        });
+    }
+
+    /// Shows a notification for the given time.
+    pub fn push(
+        &mut self,
+        key: &'static str,
+        priority: NotificationPriority,
+        text: String,
+        duration: Duration,
+        now: Instant,
+    ) {
+        self.insert(Notification {
+            key,
+            priority,
+            text,
+            expires_at: Some(now + duration),
+        });
+    }
+
+    fn insert(&mut self, notification: Notification) {
+        self.clear(notification.key);
+        self.notifications.push(notification);
+    }
+
+    pub fn clear(&mut self, key: &str) {
+        self.notifications
+            .retain(|notification| notification.key != key);
+    }
+
+    /// The notification to show now, if any.
+    pub fn current(&self, now: Instant) -> Option<&Notification> {
+        self.notifications
+            .iter()
+            .filter(|notification| !notification.is_expired(now))
+            // On ties, max_by_key returns the last element, which is the most recent.
+            .max_by_key(|notification| notification.priority)
+    }
+
+    pub fn remove_expired(&mut self, now: Instant) {
+        self.notifications
+            .retain(|notification| !notification.is_expired(now));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NotificationPriority, Notifications};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_notification_priority() {
+        let now = Instant::now();
+        let mut notifications = Notifications::default();
+        assert!(notifications.current(now).is_none());
+
+        notifications.set("phase", NotificationPriority::Low, "Night ends".to_string());
+        notifications.push(
+            "trade",
+            NotificationPriority::High,
+            "Bought".to_string(),
+            Duration::from_secs(5),
+            now,
+        );
+        notifications.set("queued", NotificationPriority::Normal, "Queued".to_string());
+        assert_eq!(notifications.current(now).unwrap().text, "Bought");
+
+        // On ties the most recent notification wins, and refreshing a text keeps its place.
+        notifications.set("alert", NotificationPriority::High, "Alert".to_string());
+        notifications.push(
+            "trade",
+            NotificationPriority::High,
+            "Sold".to_string(),
+            Duration::from_secs(5),
+            now,
+        );
+        notifications.set("alert", NotificationPriority::High, "Alert!".to_string());
+        assert_eq!(notifications.current(now).unwrap().text, "Sold");
+        notifications.clear("trade");
+        assert_eq!(notifications.current(now).unwrap().text, "Alert!");
+        notifications.clear("alert");
+        notifications.push(
+            "trade",
+            NotificationPriority::High,
+            "Sold".to_string(),
+            Duration::from_secs(5),
+            now,
+        );
+
+        let later = now + Duration::from_secs(5);
+        assert_eq!(notifications.current(later).unwrap().text, "Queued");
+        notifications.remove_expired(later);
+        notifications.clear("queued");
+        assert_eq!(notifications.current(later).unwrap().text, "Night ends");
+    }
+}
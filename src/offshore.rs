@@ -0,0 +1,137 @@
+// The offshore exchange. A smaller venue listing the same shares, whose prices drift away from
+// the main exchange with their own noise and are slowly pulled back. Buying on one exchange
+// and selling on the other pockets the gap, if it is wider than the higher offshore fee.
+// Offshore trades move the offshore premium, not the drift, so arbitrage closes the gap.
+// Offshore prices are in the offshore currency, so the exchange rate moves them too.
+
+use crate::{
+    fx::ExchangeRate,
+    market::{DAY_LENGTH, NUMBER_OF_STONKS},
+    money::Money,
+    price_series::PriceSeries,
+    stonk::{Stonk, Stonks},
+};
+use rand::Rng;
+use rand_distr::{Distribution, Normal};
+use serde::{Deserialize, Serialize};
+
+// Fee on the value of offshore trades, on top of the other fees.
+pub const OFFSHORE_FEE: f64 = 0.02;
+// Standard deviation of the change of the premium at every tick.
+pub const OFFSHORE_PREMIUM_VOLATILITY: f64 = 0.004;
+// Share of the premium lost at every tick.
+pub const OFFSHORE_PREMIUM_REVERSION: f64 = 0.05;
+pub const MAX_OFFSHORE_PREMIUM: f64 = 0.05;
+// Offshore trades are limited to this stake of the stonk.
+pub const OFFSHORE_MAX_STAKE: f64 = 0.005;
+// Premium change of an offshore trade, per unit of stake traded.
+pub const OFFSHORE_PREMIUM_IMPACT: f64 = 2.0;
+// The offshore prices are kept for a week, the main exchange keeps the full history.
+const OFFSHORE_HISTORY_SIZE: usize = DAY_LENGTH * 7;
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OffshoreExchange {
+    // Offshore price of each stonk relative to the main exchange, minus one.
+    #[serde(default)]
+    premiums: [f64; NUMBER_OF_STONKS],
+    // Offshore prices in dollars, to compare them with the main exchange.
+    #[serde(default)]
+    pub historical_prices: [PriceSeries; NUMBER_OF_STONKS],
+    #[serde(default)]
+    pub exchange_rate: ExchangeRate,
+}
+
+impl OffshoreExchange {
+    pub fn premium(&self, stonk_id: usize) -> f64 {
+        self.premiums[stonk_id]
+    }
+
+    /// Price of a share offshore, in cents of the offshore currency. Shares are never free.
+    pub fn unit_price_cents(&self, stonk: &Stonk) -> u32 {
+        ((self.dollar_unit_price_cents(stonk) / self.exchange_rate.rate()).round() as u32).max(1)
+    }
+
+    /// Price of a share offshore, converted to dollar cents.
+    pub fn dollar_unit_price_cents(&self, stonk: &Stonk) -> f64 {
+        stonk.current_unit_price_cents() as f64 * (1.0 + self.premiums[stonk.id])
+    }
+
+    /// The cost of buying the given amount offshore, or None if it cannot be represented.
+    pub fn buy_price(&self, stonk: &Stonk, amount: u32) -> Option<Money> {
+        Money::from(self.unit_price_cents(stonk)).checked_mul(amount as u64)
+    }
+
+    pub fn sell_price(&self, stonk: &Stonk, amount: u32) -> Money {
+        Money::from(self.unit_price_cents(stonk)).saturating_mul(amount as u64)
+    }
+
+    pub fn max_trade_amount(&self, stonk: &Stonk) -> u32 {
+        ((stonk.number_of_shares as f64 * OFFSHORE_MAX_STAKE) as u32).max(1)
+    }
+
+    /// The largest amount that can be bought offshore with the given offshore cash, paying the
+    /// fee rate.
+    pub fn max_buy_amount(&self, stonk: &Stonk, cash: Money, fee_rate: f64) -> u32 {
+        let unit_cost = self.unit_price_cents(stonk) as f64 * (1.0 + fee_rate);
+        ((cash.as_f64_cents() / unit_cost) as u32)
+            .min(stonk.available_amount())
+            .min(self.max_trade_amount(stonk))
+    }
+
+    /// Moves the premium after an offshore trade: buys raise it, sells lower it.
+    pub fn record_trade(&mut self, stonk: &Stonk, amount: u32, is_buy: bool) {
+        let impact = OFFSHORE_PREMIUM_IMPACT * stonk.to_stake(amount);
+        let premium = &mut self.premiums[stonk.id];
+        *premium = if is_buy {
+            *premium + impact
+        } else {
+            *premium - impact
+        }
+        .clamp(-MAX_OFFSHORE_PREMIUM, MAX_OFFSHORE_PREMIUM);
+    }
+
+    /// Moves the premiums with their own noise, and records the offshore prices in dollars.
+    pub fn tick<R: Rng>(&mut self, stonks: &Stonks, current_tick: usize, rng: &mut R) {
+        let noise = Normal::new(0.0, OFFSHORE_PREMIUM_VOLATILITY)
+            .expect("Failed to sample offshore premium distribution");
+        for stonk in stonks.iter() {
+            let premium = &mut self.premiums[stonk.id];
+            *premium = (*premium * (1.0 - OFFSHORE_PREMIUM_REVERSION) + noise.sample(rng))
+                .clamp(-MAX_OFFSHORE_PREMIUM, MAX_OFFSHORE_PREMIUM);
+            let price = (self.dollar_unit_price_cents(stonk).round() as u32).max(1);
+            let series = &mut self.historical_prices[stonk.id];
+            series.push(current_tick, price);
+            series.keep_last(OFFSHORE_HISTORY_SIZE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OffshoreExchange, MAX_OFFSHORE_PREMIUM};
+    use crate::market::Market;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    #[test]
+    fn test_offshore_premium() {
+        let mut market = Market::new();
+        market.stonks[0].set_test_values(1_000, 10_000, 0.0, 0.0, 0.0, 0.0);
+        let mut offshore = OffshoreExchange::default();
+        assert_eq!(offshore.unit_price_cents(&market.stonks[0]), 1_000);
+
+        // Buying offshore raises the offshore price only, up to the cap.
+        offshore.record_trade(&market.stonks[0], 25, true);
+        assert_eq!(offshore.unit_price_cents(&market.stonks[0]), 1_005);
+        offshore.record_trade(&market.stonks[0], 10_000, true);
+        assert_eq!(offshore.premium(0), MAX_OFFSHORE_PREMIUM);
+        assert_eq!(market.stonks[0].current_unit_price_cents(), 1_000);
+
+        let rng = &mut ChaCha8Rng::seed_from_u64(0);
+        for tick in 0..100 {
+            offshore.tick(&market.stonks, tick, rng);
+        }
+        assert!(offshore.premium(0).abs() < MAX_OFFSHORE_PREMIUM);
+        assert_eq!(offshore.historical_prices[0].len(), 100);
+    }
+}
@@ -0,0 +1,260 @@
+// Passports carry the progress of a player to another server, or to a fresh account after a
+// season reset. Only cosmetic progress travels, achievements and titles, together with a
+// bounded multiplier of the starting cash earned with the net worth: cash and shares never do.
+// A passport is signed with the SSH host key of the issuing server, and is only accepted from
+// the issuers trusted by the config, the server itself always being one.
+
+use crate::{
+    agent::{AgentAction, DecisionAgent, UserAgent, INITIAL_USER_CASH},
+    audit::{self, AuditEvent},
+    board::BoardDecision,
+    events::NightEvent,
+    leaderboard::DAYS_PER_SEASON,
+    market::{GamePhase, Market},
+    money::Money,
+    stonk::DollarValue,
+    utils::AppResult,
+};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use strum::{Display, EnumIter, IntoEnumIterator};
+
+// Every tenfold of the initial cash in net worth adds this much to the starting cash multiplier.
+pub const CASH_MULTIPLIER_PER_TENFOLD: f64 = 0.25;
+pub const MAX_CASH_MULTIPLIER: f64 = 2.0;
+const VETERAN_DAYS: usize = 30;
+const MILLIONAIRE_NET_WORTH: Money = Money::from_dollars(1_000_000);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumIter, Serialize, Deserialize)]
+pub enum Achievement {
+    Veteran,
+    Millionaire,
+    Collector,
+    BoardChair,
+}
+
+impl Achievement {
+    pub fn description(&self) -> String {
+        match self {
+            Self::Veteran => format!("Played {} market days", VETERAN_DAYS),
+            Self::Millionaire => {
+                format!(
+                    "Reached {} of net worth",
+                    MILLIONAIRE_NET_WORTH.format_dollars()
+                )
+            }
+            Self::Collector => "Was offered every night event".to_string(),
+            Self::BoardChair => "Chaired the board of a stonk".to_string(),
+        }
+    }
+
+    fn is_earned(&self, market: &Market, agent: &UserAgent) -> bool {
+        match self {
+            Self::Veteran => agent.days_played() >= VETERAN_DAYS,
+            Self::Millionaire => market.net_worth(agent) >= MILLIONAIRE_NET_WORTH,
+            Self::Collector => NightEvent::iter()
+                .all(|event| agent.seen_night_events().contains_key(&event.to_string())),
+            // Past actions are counted whatever their payload.
+            Self::BoardChair => agent.past_actions().contains(&AgentAction::BoardDecision {
+                stonk_id: 0,
+                decision: BoardDecision::RaiseGuidance,
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Passport {
+    pub username: String,
+    // Public key of the issuing server.
+    pub issuer: String,
+    // Seconds since the Unix epoch.
+    pub issued_at: u64,
+    pub cash_multiplier: f64,
+    pub achievements: Vec<Achievement>,
+    pub titles: Vec<String>,
+}
+
+impl Passport {
+    /// The passport of the agent, with the progress earned on this server and the one it
+    /// brought from previous passports.
+    pub fn issue(market: &Market, agent: &UserAgent, issuer: &VerifyingKey) -> Self {
+        let mut achievements = agent.achievements().to_vec();
+        for achievement in Achievement::iter() {
+            if !achievements.contains(&achievement) && achievement.is_earned(market, agent) {
+                achievements.push(achievement);
+            }
+        }
+        let mut titles = agent.titles().to_vec();
+        for title in season_titles(market, agent.username()) {
+            if !titles.contains(&title) {
+                titles.push(title);
+            }
+        }
+        Self {
+            username: agent.username().to_string(),
+            issuer: Base64UrlUnpadded::encode_string(issuer.as_bytes()),
+            issued_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default(),
+            cash_multiplier: cash_multiplier(market.net_worth(agent)),
+            achievements,
+            titles,
+        }
+    }
+
+    /// Encodes the passport, followed by its signature, as a single line.
+    pub fn sign(&self, signing_key: &SigningKey) -> AppResult<String> {
+        let payload = serde_json::to_vec(self)?;
+        let signature = signing_key.sign(&payload);
+        Ok(format!(
+            "{}.{}",
+            Base64UrlUnpadded::encode_string(&payload),
+            Base64UrlUnpadded::encode_string(&signature.to_bytes())
+        ))
+    }
+
+    /// Decodes a signed passport, checking that a trusted issuer signed it.
+    pub fn verify(encoded: &str, trusted_issuers: &[VerifyingKey]) -> AppResult<Self> {
+        let (payload, signature) = encoded.trim().split_once('.').ok_or("Malformed passport")?;
+        let payload = Base64UrlUnpadded::decode_vec(payload).map_err(|_| "Malformed passport")?;
+        let signature = Base64UrlUnpadded::decode_vec(signature)
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+            .ok_or("Malformed passport signature")?;
+        let passport: Self = serde_json::from_slice(&payload)?;
+        let issuer = trusted_issuers
+            .iter()
+            .find(|key| Base64UrlUnpadded::encode_string(key.as_bytes()) == passport.issuer)
+            .ok_or("The passport was issued by an untrusted server")?;
+        issuer
+            .verify(&payload, &signature)
+            .map_err(|_| "Invalid passport signature")?;
+        if !(1.0..=MAX_CASH_MULTIPLIER).contains(&passport.cash_multiplier) {
+            return Err("Invalid passport cash multiplier".into());
+        }
+        Ok(passport)
+    }
+}
+
+/// The multiplier of the starting cash earned with the net worth.
+pub fn cash_multiplier(net_worth: Money) -> f64 {
+    let tenfolds = (net_worth.as_f64_cents() / INITIAL_USER_CASH.as_f64_cents()).log10();
+    (1.0 + CASH_MULTIPLIER_PER_TENFOLD * tenfolds).clamp(1.0, MAX_CASH_MULTIPLIER)
+}
+
+/// Titles of the closed seasons the player finished first on most days.
+fn season_titles(market: &Market, username: &str) -> Vec<String> {
+    let current_season = market.phase.cycle() / DAYS_PER_SEASON;
+    (0..current_season)
+        .filter(|season| {
+            market
+                .leaderboard_history
+                .season_winners(*season)
+                .first()
+                .is_some_and(|(winner, _)| winner == username)
+        })
+        .map(|season| {
+            let start = GamePhase::Day {
+                cycle: season * DAYS_PER_SEASON,
+                counter: 0,
+            };
+            format!("Champion of {}", start.formatted_season())
+        })
+        .collect()
+}
+
+/// Applies a passport to a fresh account with the username it was issued to, returning its
+/// new cash. Binding the passport to its username keeps it from being imported into several
+/// accounts of the same server.
+pub fn import(passport: &Passport, encoded: &str, agent: &mut UserAgent) -> AppResult<Money> {
+    if agent.username() != passport.username {
+        return Err(format!(
+            "This passport belongs to {}, it can only be imported by that username",
+            passport.username
+        )
+        .into());
+    }
+    if agent.days_played() > 0
+        || agent.cash() != INITIAL_USER_CASH
+        || agent.offshore_cash() != Money::ZERO
+        || agent.owned_stonks().iter().any(|amount| *amount > 0)
+    {
+        return Err("Passports can only be imported into new accounts".into());
+    }
+    if agent.imported_passport().is_some() {
+        return Err("A passport was already imported into this account".into());
+    }
+    let extra_cash = INITIAL_USER_CASH
+        .scale(passport.cash_multiplier)
+        .saturating_sub(INITIAL_USER_CASH);
+    agent.add_cash(extra_cash)?;
+    audit::record(AuditEvent::CashPrinted {
+        username: agent.username().to_string(),
+        amount_cents: extra_cash,
+    });
+    agent.set_imported_passport(
+        encoded.to_string(),
+        &passport.achievements,
+        &passport.titles,
+    );
+    Ok(agent.cash())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cash_multiplier, import, Achievement, Passport, MAX_CASH_MULTIPLIER};
+    use crate::{
+        agent::{DecisionAgent, UserAgent, INITIAL_USER_CASH},
+        market::Market,
+        money::Money,
+        ssh_client::SessionAuth,
+        utils::AppResult,
+    };
+    use ed25519_dalek::SigningKey;
+
+    #[test]
+    fn test_passport_roundtrip() -> AppResult<()> {
+        assert_eq!(cash_multiplier(Money::ZERO), 1.0);
+        assert_eq!(cash_multiplier(INITIAL_USER_CASH.saturating_mul(10)), 1.25);
+        assert_eq!(cash_multiplier(Money::MAX), MAX_CASH_MULTIPLIER);
+
+        let market = Market::new();
+        let issuer = SigningKey::from_bytes(&[1; 32]);
+        let stranger = SigningKey::from_bytes(&[2; 32]);
+        let mut agent = UserAgent::new(SessionAuth::new("traveller".to_string(), [0; 32]));
+        agent.add_cash(INITIAL_USER_CASH.saturating_mul(99))?;
+        let passport = Passport::issue(&market, &agent, &issuer.verifying_key());
+        assert_eq!(passport.cash_multiplier, 1.5);
+        assert_eq!(passport.achievements, vec![Achievement::Millionaire]);
+        let encoded = passport.sign(&issuer)?;
+
+        // Only trusted issuers are accepted, and the payload cannot be changed.
+        assert!(Passport::verify(&encoded, &[stranger.verifying_key()]).is_err());
+        let mut forged = passport.clone();
+        forged.cash_multiplier = MAX_CASH_MULTIPLIER;
+        let forged = format!(
+            "{}.{}",
+            forged.sign(&stranger)?.split_once('.').unwrap().0,
+            encoded.split_once('.').unwrap().1
+        );
+        assert!(Passport::verify(&forged, &[issuer.verifying_key()]).is_err());
+        let verified = Passport::verify(&encoded, &[issuer.verifying_key()])?;
+        assert_eq!(verified, passport);
+
+        // The progress goes to a new account with the same username only, once.
+        assert!(import(&verified, &encoded, &mut agent).is_err());
+        let mut newcomer = UserAgent::new(SessionAuth::new("newcomer".to_string(), [0; 32]));
+        assert!(import(&verified, &encoded, &mut newcomer).is_err());
+        assert_eq!(newcomer.cash(), INITIAL_USER_CASH);
+        let mut arrived = UserAgent::new(SessionAuth::new("traveller".to_string(), [0; 32]));
+        let cash = import(&verified, &encoded, &mut arrived)?;
+        assert_eq!(cash, INITIAL_USER_CASH.scale(1.5));
+        assert_eq!(arrived.achievements(), &[Achievement::Millionaire]);
+        assert!(import(&verified, &encoded, &mut arrived).is_err());
+        Ok(())
+    }
+}
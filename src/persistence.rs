@@ -0,0 +1,80 @@
+// Saves the state to disk on a dedicated task, so that the game loop only pays for copying it
+// instead of freezing every client while it is serialized. The loop hands the copies over
+// through a channel with room for a single save: while it is taken, the next save is delayed
+// rather than queued, and the leaderboard days and balance reports stay in the market until
+// a save can take them. Failed saves are reported in the server status.
+
+use crate::{
+    leaderboard::DailyLeaderboard,
+    market::Market,
+    server_status::{report_error, ServerStatus},
+    ssh_server::AgentsDatabase,
+    telemetry::BalanceReport,
+    utils::{
+        append_balance_reports, append_leaderboard_history, save_agents, save_archived_agents,
+        save_market, AppResult,
+    },
+};
+use std::{sync::Arc, time::Instant};
+use tokio::sync::mpsc::{self, Permit};
+use tracing::info;
+
+#[derive(Debug)]
+pub struct StoreSnapshot {
+    pub agents: AgentsDatabase,
+    pub market: Market,
+    // Only set when agents were archived since the last save.
+    pub archived_agents: Option<AgentsDatabase>,
+    pub leaderboard_days: Vec<DailyLeaderboard>,
+    pub balance_reports: Vec<BalanceReport>,
+}
+
+impl StoreSnapshot {
+    pub fn save(&self) -> AppResult<()> {
+        if let Some(archived_agents) = self.archived_agents.as_ref() {
+            save_archived_agents(archived_agents)?;
+        }
+        save_agents(&self.agents)?;
+        save_market(&self.market)?;
+        append_leaderboard_history(&self.leaderboard_days)?;
+        append_balance_reports(&self.balance_reports)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Persistence {
+    sender: mpsc::Sender<StoreSnapshot>,
+}
+
+impl Persistence {
+    /// Starts the task writing the snapshots to disk.
+    pub fn spawn(status: Arc<std::sync::Mutex<ServerStatus>>) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<StoreSnapshot>(1);
+        tokio::spawn(async move {
+            while let Some(snapshot) = receiver.recv().await {
+                let started_at = Instant::now();
+                let saved =
+                    tokio::task::spawn_blocking(move || snapshot.save().map_err(|e| e.to_string()))
+                        .await;
+                match saved {
+                    Ok(Ok(())) => {
+                        info!("Saved the state in {:?}", started_at.elapsed());
+                        if let Ok(mut status) = status.lock() {
+                            status.record_save(Instant::now());
+                        }
+                    }
+                    Ok(Err(e)) => report_error(&status, format!("Could not save the state: {}", e)),
+                    Err(e) => report_error(&status, format!("Saving the state panicked: {}", e)),
+                }
+            }
+        });
+        Self { sender }
+    }
+
+    /// Reserves the slot of the next save, or returns None while the previous one is waiting
+    /// to be written.
+    pub fn try_reserve(&self) -> Option<Permit<'_, StoreSnapshot>> {
+        self.sender.try_reserve().ok()
+    }
+}
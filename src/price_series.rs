@@ -0,0 +1,182 @@
+// The price history of a stonk, indexed by absolute market tick. The price of a tick is the
+// one at the end of that tick. Ticks without a recorded price repeat the last one, so that
+// every tick from the start of the series has a price.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "StoredPriceSeries")]
+pub struct PriceSeries {
+    // Tick of the first price.
+    start_tick: usize,
+    prices: Vec<u32>,
+    // Set for histories stored before they carried their start tick, see align_end.
+    #[serde(skip)]
+    unaligned: bool,
+}
+
+// Older stores only have the list of prices.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredPriceSeries {
+    Series { start_tick: usize, prices: Vec<u32> },
+    Legacy(Vec<u32>),
+}
+
+impl From<StoredPriceSeries> for PriceSeries {
+    fn from(stored: StoredPriceSeries) -> Self {
+        match stored {
+            StoredPriceSeries::Series { start_tick, prices } => Self {
+                start_tick,
+                prices,
+                unaligned: false,
+            },
+            StoredPriceSeries::Legacy(prices) => Self {
+                start_tick: 0,
+                unaligned: !prices.is_empty(),
+                prices,
+            },
+        }
+    }
+}
+
+impl PriceSeries {
+    pub fn new(start_tick: usize, prices: Vec<u32>) -> Self {
+        Self {
+            start_tick,
+            prices,
+            unaligned: false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.prices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prices.is_empty()
+    }
+
+    pub fn start_tick(&self) -> usize {
+        self.start_tick
+    }
+
+    /// The tick after the last recorded price.
+    pub fn end_tick(&self) -> usize {
+        self.start_tick + self.prices.len()
+    }
+
+    pub fn last(&self) -> Option<u32> {
+        self.prices.last().copied()
+    }
+
+    /// Records the price at the end of the given tick. The ticks skipped since the last
+    /// price repeat it.
+    pub fn push(&mut self, tick: usize, price: u32) {
+        if self.prices.is_empty() {
+            self.start_tick = tick;
+        } else if tick < self.end_tick() {
+            // The tick was already recorded, this can only happen if the clock went back.
+            self.prices.truncate(tick.saturating_sub(self.start_tick));
+        } else if let Some(last) = self.last() {
+            let missing = tick - self.end_tick();
+            self.prices.extend(std::iter::repeat_n(last, missing));
+        }
+        self.prices.push(price);
+    }
+
+    /// Drops the oldest prices, keeping at most the given number.
+    pub fn keep_last(&mut self, len: usize) {
+        let excess = self.prices.len().saturating_sub(len);
+        if excess > 0 {
+            self.prices.drain(..excess);
+            self.start_tick += excess;
+        }
+    }
+
+    /// Moves a history loaded from an older store so that it ends at the given tick.
+    pub fn align_end(&mut self, end_tick: usize) {
+        if self.unaligned {
+            self.start_tick = end_tick.saturating_sub(self.prices.len());
+            self.unaligned = false;
+        }
+    }
+
+    /// The price at the end of the given tick. Ticks after the last price repeat it.
+    pub fn at_tick(&self, tick: usize) -> Option<u32> {
+        if tick < self.start_tick {
+            return None;
+        }
+        self.prices
+            .get(tick - self.start_tick)
+            .or(self.prices.last())
+            .copied()
+    }
+
+    /// The recorded prices of the ticks from start_tick, included, to end_tick, excluded.
+    pub fn window(&self, start_tick: usize, end_tick: usize) -> &[u32] {
+        let start = start_tick.saturating_sub(self.start_tick).min(self.len());
+        let end = end_tick.saturating_sub(self.start_tick).min(self.len());
+        &self.prices[start..end.max(start)]
+    }
+
+    /// The price the day starting at the given tick opened at, that is the one at the end of
+    /// the previous tick. For the first day of the series, the first price is used.
+    pub fn open_of_day(&self, day_start_tick: usize) -> Option<u32> {
+        day_start_tick
+            .checked_sub(1)
+            .and_then(|tick| self.at_tick(tick))
+            .or_else(|| self.at_tick(day_start_tick))
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &u32> + ExactSizeIterator {
+        self.prices.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PriceSeries;
+
+    #[test]
+    fn test_price_series_ticks() {
+        let mut series = PriceSeries::default();
+        assert_eq!(series.at_tick(0), None);
+
+        series.push(10, 100);
+        series.push(11, 110);
+        // Ticks 12 to 14 are a night.
+        series.push(15, 150);
+        assert_eq!(series.start_tick(), 10);
+        assert_eq!(series.end_tick(), 16);
+        assert_eq!(series.at_tick(9), None);
+        assert_eq!(series.at_tick(13), Some(110));
+        assert_eq!(series.at_tick(15), Some(150));
+        assert_eq!(series.at_tick(20), Some(150));
+        assert_eq!(series.window(9, 13), &[100, 110, 110]);
+        assert_eq!(series.window(14, 30), &[110, 150]);
+        assert_eq!(series.window(30, 20), &[] as &[u32]);
+
+        assert_eq!(series.open_of_day(10), Some(100));
+        assert_eq!(series.open_of_day(15), Some(110));
+
+        series.keep_last(2);
+        assert_eq!(series.start_tick(), 14);
+        assert_eq!(series.at_tick(13), None);
+        assert_eq!(series.at_tick(14), Some(110));
+    }
+
+    #[test]
+    fn test_legacy_price_series() -> serde_json::Result<()> {
+        let mut series: PriceSeries = serde_json::from_str("[100, 110, 120]")?;
+        series.align_end(50);
+        assert_eq!(series.start_tick(), 47);
+        assert_eq!(series.at_tick(49), Some(120));
+
+        let stored = serde_json::to_string(&series)?;
+        let mut series: PriceSeries = serde_json::from_str(&stored)?;
+        series.align_end(60);
+        assert_eq!(series.start_tick(), 47);
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+// Opt-in recording of a game session, for bug reports. Every key press is written with the
+// time elapsed since the recording started, followed by the screen it led to when it changed,
+// one JSON line each. Players download the recording over SFTP and attach it to their report;
+// developers feed it to a headless client with `stonks-replay` to reproduce the bug.
+// Keys typed in the account and two-factor dialogs are masked, so passwords and codes are
+// never written.
+
+use crate::{ui::ScreenState, utils::AppResult};
+use crossterm::event::{KeyCode, KeyEvent};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, LineWriter, Write},
+    path::Path,
+    time::Instant,
+};
+
+// Name of the recording among the files exported over SFTP.
+pub const RECORDING_SFTP_FILENAME: &str = "session_recording.jsonl";
+// The recording stops when it grows over this size.
+const MAX_RECORDING_BYTES: usize = 4 * 1024 * 1024;
+// Typed in place of the characters of a password or a code.
+const MASKED_CHAR: char = '*';
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum RecordedEvent {
+    /// Written first, with what the playback needs to start from the same state.
+    Start {
+        username: String,
+        version: String,
+        width: u16,
+        height: u16,
+        text_mode: bool,
+        tick: usize,
+        screen: ScreenState,
+    },
+    Key {
+        millis: u64,
+        key: KeyEvent,
+    },
+    Resize {
+        millis: u64,
+        width: u16,
+        height: u16,
+    },
+    /// The screen shown after a key press, written when it changes.
+    Screen {
+        millis: u64,
+        screen: ScreenState,
+    },
+}
+
+#[derive(Debug)]
+pub struct SessionRecorder {
+    writer: LineWriter<File>,
+    started_at: Instant,
+    bytes: usize,
+    last_screen: ScreenState,
+}
+
+impl SessionRecorder {
+    /// Starts a new recording at the path, replacing any previous one.
+    pub fn start(path: &Path, start: RecordedEvent) -> AppResult<Self> {
+        let RecordedEvent::Start { screen, .. } = &start else {
+            return Err("A recording must begin with its start".into());
+        };
+        let mut recorder = Self {
+            writer: LineWriter::new(File::create(path)?),
+            started_at: Instant::now(),
+            bytes: 0,
+            last_screen: screen.clone(),
+        };
+        recorder.write(&start)?;
+        Ok(recorder)
+    }
+
+    fn millis(&self) -> u64 {
+        self.started_at.elapsed().as_millis() as u64
+    }
+
+    fn write(&mut self, event: &RecordedEvent) -> AppResult<()> {
+        let mut line = serde_json::to_string(event)?;
+        line.push('\n');
+        if self.bytes + line.len() > MAX_RECORDING_BYTES {
+            return Err("The recording is full".into());
+        }
+        self.writer.write_all(line.as_bytes())?;
+        self.bytes += line.len();
+        Ok(())
+    }
+
+    /// Records a key press, masking it if it is part of a secret.
+    pub fn record_key(&mut self, mut key: KeyEvent, secret: bool) -> AppResult<()> {
+        if secret {
+            if let KeyCode::Char(_) = key.code {
+                key.code = KeyCode::Char(MASKED_CHAR);
+            }
+        }
+        self.write(&RecordedEvent::Key {
+            millis: self.millis(),
+            key,
+        })
+    }
+
+    pub fn record_resize(&mut self, width: u16, height: u16) -> AppResult<()> {
+        self.write(&RecordedEvent::Resize {
+            millis: self.millis(),
+            width,
+            height,
+        })
+    }
+
+    pub fn record_screen(&mut self, screen: ScreenState) -> AppResult<()> {
+        if screen == self.last_screen {
+            return Ok(());
+        }
+        self.write(&RecordedEvent::Screen {
+            millis: self.millis(),
+            screen: screen.clone(),
+        })?;
+        self.last_screen = screen;
+        Ok(())
+    }
+}
+
+pub fn load_recording(path: &Path) -> AppResult<Vec<RecordedEvent>> {
+    let mut events = vec![];
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            events.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_recording, RecordedEvent, SessionRecorder, MASKED_CHAR};
+    use crate::{
+        ui::{ScreenState, UiDisplay},
+        utils::AppResult,
+    };
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    #[test]
+    fn test_session_recording() -> AppResult<()> {
+        let screen = |display| ScreenState {
+            display,
+            focus_on_stonk: None,
+            selected_stonk_index: 0,
+            modal: None,
+        };
+        let path = std::env::temp_dir().join(format!("stonks-recording-{}", std::process::id()));
+        let start = RecordedEvent::Start {
+            username: "player".to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            width: 160,
+            height: 48,
+            text_mode: false,
+            tick: 12,
+            screen: screen(UiDisplay::Stonks),
+        };
+        let mut recorder = SessionRecorder::start(&path, start.clone())?;
+        recorder.record_key(KeyEvent::new(KeyCode::Tab, KeyModifiers::NONE), false)?;
+        recorder.record_screen(screen(UiDisplay::Portfolio))?;
+        // The same screen is only written once.
+        recorder.record_screen(screen(UiDisplay::Portfolio))?;
+        recorder.record_key(KeyEvent::new(KeyCode::Char('x'), KeyModifiers::NONE), true)?;
+        recorder.record_resize(80, 24)?;
+        drop(recorder);
+
+        let events = load_recording(&path)?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(events.len(), 5);
+        assert_eq!(events[0], start);
+        assert!(matches!(
+            &events[2],
+            RecordedEvent::Screen { screen, .. } if screen.display == UiDisplay::Portfolio
+        ));
+        assert!(matches!(
+            &events[3],
+            RecordedEvent::Key { key, .. } if key.code == KeyCode::Char(MASKED_CHAR)
+        ));
+        assert!(matches!(
+            events[4],
+            RecordedEvent::Resize {
+                width: 80,
+                height: 24,
+                ..
+            }
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+// A practice sandbox is a private copy of the market, forked when the player enters it.
+// It keeps ticking with the same stonk math as the real market, one tick for every real
+// tick, but trades only move the copy, so nothing done here affects the real world.
+
+use crate::{
+    agent::{DecisionAgent, UserAgent},
+    market::{GamePhase, Market},
+    tournament::Tournament,
+    utils::AppResult,
+};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    pub market: Market,
+    pub agent: UserAgent,
+    // Tick of the real market the copy has caught up with.
+    synced_tick: usize,
+}
+
+impl Sandbox {
+    pub fn fork(market: &Market, agent: &UserAgent) -> Self {
+        let mut market = market.clone();
+        // The copy must not replay the real market randomness, nor play its tournament.
+        market.rng = ChaCha8Rng::from_entropy();
+        market.tournament = Tournament::default();
+        market.leaderboard_history = Default::default();
+
+        let mut agent = agent.clone();
+        agent.clear_actions();
+        agent.set_available_night_events(vec![]);
+        agent.set_scheduled_order(None);
+        agent.set_tournament_account(None);
+
+        Self {
+            synced_tick: market.last_tick,
+            market,
+            agent,
+        }
+    }
+
+    /// Applies the selected trades and advances the copy by the ticks elapsed in the real market.
+    pub fn sync(&mut self, real_tick: usize) -> AppResult<()> {
+        let mut result = Ok(());
+        if matches!(self.market.phase, GamePhase::Day { .. }) {
+            let actions = self.agent.selected_actions().clone();
+            self.agent.clear_actions();
+            // Trades are not audited, so they are applied one by one rather than
+            // through Market::apply_agent_actions.
+            for action in actions.iter() {
+                if let Err(e) =
+                    self.market
+                        .apply_agent_action(&mut self.agent, action, &mut HashMap::new())
+                {
+                    result = Err(e);
+                }
+            }
+        }
+
+        while self.synced_tick < real_tick {
+            self.synced_tick += 1;
+            let agents = HashMap::from([(self.agent.username().to_string(), self.agent.clone())]);
+            self.market.update_portfolios(&agents);
+            self.market.tick();
+            self.agent.apply_conditions(self.market.last_tick);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Sandbox;
+    use crate::{
+        agent::{AgentAction, DecisionAgent, UserAgent},
+        market::Market,
+        ssh_client::SessionAuth,
+        utils::AppResult,
+    };
+
+    #[test]
+    fn test_sandbox_does_not_touch_the_real_market() -> AppResult<()> {
+        let market = Market::new();
+        let agent = UserAgent::new(SessionAuth::new("alice".to_string(), [0; 32]));
+        let mut sandbox = Sandbox::fork(&market, &agent);
+
+        sandbox.agent.select_action(AgentAction::Buy {
+            stonk_id: 0,
+            amount: 10,
+        });
+        sandbox.sync(market.last_tick)?;
+        assert_eq!(sandbox.agent.owned_stonks()[0], 10);
+        assert!(sandbox.agent.cash() < agent.cash());
+        assert_eq!(
+            sandbox.market.stonks[0].shareholders,
+            vec![("alice".to_string(), 10)]
+        );
+        assert_eq!(agent.owned_stonks()[0], 0);
+        assert!(market.stonks[0].shareholders.is_empty());
+
+        // The copy follows the ticks of the real market.
+        sandbox.sync(market.last_tick + 3)?;
+        assert_eq!(sandbox.market.last_tick, market.last_tick + 3);
+        assert_eq!(sandbox.market.stonks[0].historical_prices.len(), 3);
+        sandbox.sync(market.last_tick + 3)?;
+        assert_eq!(sandbox.market.last_tick, market.last_tick + 3);
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+// Scenarios script events at given days of the game, for themed servers and reproducible
+// playtests. A scenario is a JSON file passed at startup: its days are counted from the day
+// it was first loaded, and its progress is stored with the market, so that a restart resumes
+// it instead of playing it again.
+
+use crate::{
+    market::{GamePhase, DAY_LENGTH, NUMBER_OF_STONKS},
+    stonk::StonkClass,
+    utils::AppResult,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ScriptedEffect {
+    // Drift bump of all the stonks of a class.
+    ClassDrift {
+        class: StonkClass,
+        amount: f64,
+        days: usize,
+    },
+    StonkDrift {
+        stonk_id: usize,
+        amount: f64,
+        days: usize,
+    },
+    // Increased shock probability, for a class or for every stonk.
+    Shocks {
+        #[serde(default)]
+        class: Option<StonkClass>,
+        days: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScriptedEvent {
+    // Day of the scenario, the first one being 0.
+    pub day: usize,
+    // Tick of the day, 0 being the open.
+    #[serde(default)]
+    pub tick: usize,
+    pub headline: String,
+    #[serde(default)]
+    pub effects: Vec<ScriptedEffect>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Scenario {
+    pub name: String,
+    #[serde(default)]
+    pub events: Vec<ScriptedEvent>,
+}
+
+impl Scenario {
+    pub fn validate(&self) -> AppResult<()> {
+        if self.name.is_empty() {
+            return Err("Scenario name must not be empty".into());
+        }
+        for event in self.events.iter() {
+            if event.tick >= DAY_LENGTH {
+                return Err(format!(
+                    "Invalid tick {} of scripted event '{}', must be lower than {}",
+                    event.tick, event.headline, DAY_LENGTH
+                )
+                .into());
+            }
+            for effect in event.effects.iter() {
+                let (amount, days) = match effect {
+                    ScriptedEffect::ClassDrift { amount, days, .. } => (*amount, *days),
+                    ScriptedEffect::StonkDrift {
+                        stonk_id,
+                        amount,
+                        days,
+                    } => {
+                        if *stonk_id >= NUMBER_OF_STONKS {
+                            return Err(format!("Invalid stonk id {} in scenario", stonk_id).into());
+                        }
+                        (*amount, *days)
+                    }
+                    ScriptedEffect::Shocks { days, .. } => (0.0, *days),
+                };
+                if !amount.is_finite() || days == 0 {
+                    return Err(format!(
+                        "Invalid effect of scripted event '{}': drift must be finite and duration positive",
+                        event.headline
+                    )
+                    .into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScenarioRunner {
+    // Set by the server from the scenario file.
+    #[serde(skip)]
+    scenario: Option<Scenario>,
+    // Name of the scenario being run, to resume it after a restart.
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    start_cycle: usize,
+    // Index of the next event to run, in the sorted events.
+    #[serde(default)]
+    next_event: usize,
+    // Headline of the last event, with the cycle it ran at.
+    #[serde(default)]
+    headline: Option<(usize, String)>,
+}
+
+impl ScenarioRunner {
+    /// Runs the scenario from the given cycle, or resumes it if it was already running.
+    pub fn load(&mut self, mut scenario: Scenario, cycle: usize) {
+        scenario.events.sort_by_key(|event| (event.day, event.tick));
+        if self.name.as_ref() != Some(&scenario.name) {
+            self.name = Some(scenario.name.clone());
+            self.start_cycle = cycle;
+            self.next_event = 0;
+            self.headline = None;
+        }
+        self.scenario = Some(scenario);
+    }
+
+    /// Takes the events due by the given phase. Events only run during the day.
+    pub fn due_events(&mut self, phase: GamePhase) -> Vec<ScriptedEvent> {
+        let Some(scenario) = self.scenario.as_ref() else {
+            return vec![];
+        };
+        let GamePhase::Day { cycle, counter } = phase else {
+            return vec![];
+        };
+        let Some(day) = cycle.checked_sub(self.start_cycle) else {
+            return vec![];
+        };
+        let due = scenario
+            .events
+            .iter()
+            .skip(self.next_event)
+            .take_while(|event| (event.day, event.tick) <= (day, counter))
+            .cloned()
+            .collect::<Vec<ScriptedEvent>>();
+        self.next_event += due.len();
+        if let Some(event) = due.last() {
+            self.headline = Some((cycle, event.headline.clone()));
+        }
+        due
+    }
+
+    /// Headline of the last event run in the given cycle.
+    pub fn headline(&self, cycle: usize) -> Option<&str> {
+        self.headline
+            .as_ref()
+            .filter(|(headline_cycle, _)| *headline_cycle == cycle)
+            .map(|(_, headline)| headline.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Scenario, ScenarioRunner, ScriptedEffect};
+    use crate::{market::GamePhase, stonk::StonkClass, utils::AppResult};
+
+    #[test]
+    fn test_scenario_runner() -> AppResult<()> {
+        let scenario: Scenario = serde_json::from_str(include_str!("../scenarios/world_war.json"))?;
+        scenario.validate()?;
+        assert!(matches!(
+            scenario.events[0].effects[0],
+            ScriptedEffect::ClassDrift {
+                class: StonkClass::War,
+                ..
+            }
+        ));
+
+        let mut runner = ScenarioRunner::default();
+        runner.load(scenario.clone(), 10);
+        let day = |cycle, counter| GamePhase::Day { cycle, counter };
+        assert!(runner.due_events(day(10, 0)).is_empty());
+        assert!(runner
+            .due_events(GamePhase::Night {
+                cycle: 20,
+                counter: 0
+            })
+            .is_empty());
+
+        let first_day = scenario.events[0].day + 10;
+        let due = runner.due_events(day(first_day, scenario.events[0].tick));
+        assert_eq!(due, vec![scenario.events[0].clone()]);
+        assert!(runner.due_events(day(first_day, 0)).is_empty());
+        assert_eq!(runner.headline(first_day), Some(due[0].headline.as_str()));
+        assert_eq!(runner.headline(first_day + 1), None);
+
+        // Reloading resumes the scenario, a new one starts over.
+        let mut restarted = runner.clone();
+        restarted.load(scenario.clone(), 50);
+        assert!(restarted.due_events(day(first_day, 0)).is_empty());
+        let mut other = scenario.clone();
+        other.name = "Other".to_string();
+        restarted.load(other, 50);
+        assert!(restarted.due_events(day(first_day, 0)).is_empty());
+        assert_eq!(
+            restarted.due_events(day(100, 0)).len(),
+            scenario.events.len()
+        );
+
+        let mut invalid = scenario;
+        invalid.events[0].tick = 1_000;
+        assert!(invalid.validate().is_err());
+        Ok(())
+    }
+}
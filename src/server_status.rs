@@ -0,0 +1,302 @@
+// Health of the running server, printed to the `status` login so that monitoring scripts
+// reaching only the SSH port can check it without opening the game.
+
+use crate::clock::GameClock;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tracing::error;
+
+pub const STATUS_USERNAME: &str = "status";
+// Older errors are dropped when no status report drains them.
+const MAX_PENDING_ERRORS: usize = 20;
+// A running clock is late when the last tick is older than this many tick intervals.
+const LATE_TICK_INTERVALS: u32 = 3;
+// Latest tick durations and lock waits kept for the debug overlay.
+const MAX_TIMING_SAMPLES: usize = 256;
+
+// Logs the error and keeps it for the next status report.
+pub fn report_error(status: &std::sync::Mutex<ServerStatus>, message: String) {
+    error!("{}", message);
+    if let Ok(mut status) = status.lock() {
+        status.record_error(message);
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Percentiles {
+    pub p50: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Percentiles {
+    fn of(samples: &VecDeque<Duration>) -> Self {
+        let mut sorted = samples.iter().copied().collect::<Vec<Duration>>();
+        sorted.sort();
+        let percentile = |p: f64| {
+            sorted
+                .get(((sorted.len().saturating_sub(1)) as f64 * p).round() as usize)
+                .copied()
+                .unwrap_or_default()
+        };
+        Self {
+            p50: percentile(0.5),
+            p99: percentile(0.99),
+            max: percentile(1.0),
+        }
+    }
+}
+
+// Live internals of the server, shown to the admins in the debug overlay.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DebugStats {
+    pub tick_duration: Percentiles,
+    // Time the game loops waited for the clients, agents and market locks.
+    pub lock_wait: Percentiles,
+    // Bytes of the last frame sent to each client, largest first.
+    pub frame_bytes: Vec<(String, usize)>,
+}
+
+fn push_sample(samples: &mut VecDeque<Duration>, sample: Duration) {
+    if samples.len() == MAX_TIMING_SAMPLES {
+        samples.pop_front();
+    }
+    samples.push_back(sample);
+}
+
+#[derive(Debug)]
+pub struct ServerStatus {
+    started_at: Instant,
+    last_tick_at: Option<Instant>,
+    last_save_at: Option<Instant>,
+    // Saves delayed because the previous one was still being written.
+    delayed_saves: usize,
+    keys_loaded: bool,
+    // Errors since the last status report, oldest first.
+    pending_errors: VecDeque<String>,
+    tick_durations: VecDeque<Duration>,
+    lock_waits: VecDeque<Duration>,
+}
+
+impl ServerStatus {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            started_at: now,
+            last_tick_at: None,
+            last_save_at: None,
+            delayed_saves: 0,
+            keys_loaded: false,
+            pending_errors: VecDeque::new(),
+            tick_durations: VecDeque::new(),
+            lock_waits: VecDeque::new(),
+        }
+    }
+
+    pub fn record_tick(&mut self, now: Instant) {
+        self.last_tick_at = Some(now);
+    }
+
+    pub fn record_tick_duration(&mut self, duration: Duration) {
+        push_sample(&mut self.tick_durations, duration);
+    }
+
+    pub fn record_lock_wait(&mut self, wait: Duration) {
+        push_sample(&mut self.lock_waits, wait);
+    }
+
+    /// The debug overlay stats, with the frame sizes of the clients.
+    pub fn debug_stats(&self, mut frame_bytes: Vec<(String, usize)>) -> DebugStats {
+        frame_bytes.sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then_with(|| a_name.cmp(b_name)));
+        DebugStats {
+            tick_duration: Percentiles::of(&self.tick_durations),
+            lock_wait: Percentiles::of(&self.lock_waits),
+            frame_bytes,
+        }
+    }
+
+    pub fn record_save(&mut self, now: Instant) {
+        self.last_save_at = Some(now);
+    }
+
+    pub fn record_save_delayed(&mut self) {
+        self.delayed_saves += 1;
+    }
+
+    pub fn record_keys_loaded(&mut self) {
+        self.keys_loaded = true;
+    }
+
+    pub fn record_error(&mut self, message: String) {
+        if self.pending_errors.len() >= MAX_PENDING_ERRORS {
+            self.pending_errors.pop_front();
+        }
+        self.pending_errors.push_back(message);
+    }
+
+    /// Whether the market ticks as often as the clock asks it to.
+    pub fn ticks_healthy(&self, clock: &GameClock, now: Instant) -> bool {
+        if clock.is_paused() {
+            return true;
+        }
+        let since = self.last_tick_at.unwrap_or(self.started_at);
+        now.saturating_duration_since(since) <= clock.tick_interval() * LATE_TICK_INTERVALS
+    }
+
+    /// Whether the server can take players: the market is loaded, which it is as soon as
+    /// the status exists, the SSH keys are loaded and the market has started ticking on time.
+    pub fn is_ready(&self, clock: &GameClock, now: Instant) -> bool {
+        self.keys_loaded
+            && (self.last_tick_at.is_some() || clock.is_paused())
+            && self.ticks_healthy(clock, now)
+    }
+
+    /// The status report, draining the pending errors. Returns it with whether the server
+    /// is healthy, meaning that the ticks are on time and there were no errors.
+    pub fn report(&mut self, clock: &GameClock, players: usize, now: Instant) -> (String, bool) {
+        let ticks_healthy = self.ticks_healthy(clock, now);
+        let last_tick = match self.last_tick_at {
+            Some(tick_at) => format!("{} ago", format_duration(now - tick_at)),
+            None => "none yet".to_string(),
+        };
+        let last_save = match self.last_save_at {
+            Some(save_at) => format!("{} ago", format_duration(now - save_at)),
+            None => "never".to_string(),
+        };
+        let memory = match memory_usage_kb() {
+            Some(kb) => format!("{} MB", kb / 1024),
+            None => "unknown".to_string(),
+        };
+
+        let mut lines = vec![
+            format!(
+                "uptime: {}",
+                format_duration(now.saturating_duration_since(self.started_at))
+            ),
+            format!(
+                "ticks: {} (last tick {}, {})",
+                if ticks_healthy { "ok" } else { "late" },
+                last_tick,
+                clock.status()
+            ),
+            format!("players: {}", players),
+            format!("memory: {}", memory),
+            format!("last save: {} ({} delayed)", last_save, self.delayed_saves),
+            format!("errors: {}", self.pending_errors.len()),
+        ];
+        let healthy = ticks_healthy && self.pending_errors.is_empty();
+        lines.extend(
+            self.pending_errors
+                .drain(..)
+                .map(|error| format!("  {}", error)),
+        );
+
+        (format!("{}\r\n", lines.join("\r\n")), healthy)
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!(
+        "{}h {:02}m {:02}s",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+}
+
+/// Resident memory of the server process, only known on Linux.
+fn memory_usage_kb() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ServerStatus, MAX_TIMING_SAMPLES};
+    use crate::{clock::GameClock, utils::AppResult};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_debug_stats() {
+        let mut status = ServerStatus::new(Instant::now());
+        let stats = status.debug_stats(vec![]);
+        assert_eq!(stats.tick_duration.max, Duration::ZERO);
+
+        for millis in 1..=100 {
+            status.record_tick_duration(Duration::from_millis(millis));
+        }
+        status.record_lock_wait(Duration::from_millis(3));
+        let stats = status.debug_stats(vec![("a".to_string(), 10), ("b".to_string(), 20)]);
+        assert_eq!(stats.tick_duration.p50, Duration::from_millis(51));
+        assert_eq!(stats.tick_duration.p99, Duration::from_millis(99));
+        assert_eq!(stats.tick_duration.max, Duration::from_millis(100));
+        assert_eq!(stats.lock_wait.p99, Duration::from_millis(3));
+        assert_eq!(stats.frame_bytes[0], ("b".to_string(), 20));
+
+        // Only the latest samples are kept.
+        for _ in 0..MAX_TIMING_SAMPLES {
+            status.record_tick_duration(Duration::from_millis(1));
+        }
+        assert_eq!(
+            status.debug_stats(vec![]).tick_duration.max,
+            Duration::from_millis(1)
+        );
+    }
+
+    #[test]
+    fn test_status_report() -> AppResult<()> {
+        let start = Instant::now();
+        let mut clock = GameClock::new(1.0, start)?;
+        let mut status = ServerStatus::new(start);
+        status.record_tick(start);
+
+        let now = start + Duration::from_secs(3_725);
+        let (report, healthy) = status.report(&clock, 3, now);
+        assert!(report.contains("uptime: 1h 02m 05s"));
+        assert!(report.contains("ticks: late"));
+        assert!(report.contains("players: 3"));
+        assert!(report.contains("last save: never"));
+        assert!(!healthy);
+
+        // A paused clock is never late.
+        clock.pause();
+        status.record_save(now);
+        status.record_save_delayed();
+        status.record_error("Could not tick market".to_string());
+        let (report, healthy) = status.report(&clock, 3, now);
+        assert!(report.contains("ticks: ok"));
+        assert!(report.contains("last save: 0h 00m 00s ago (1 delayed)"));
+        assert!(report.contains("errors: 1\r\n  Could not tick market"));
+        assert!(!healthy);
+
+        // Errors are only reported once.
+        let (report, healthy) = status.report(&clock, 3, now);
+        assert!(report.contains("errors: 0"));
+        assert!(healthy);
+        Ok(())
+    }
+
+    #[test]
+    fn test_readiness() -> AppResult<()> {
+        let start = Instant::now();
+        let mut clock = GameClock::new(1.0, start)?;
+        let mut status = ServerStatus::new(start);
+        assert!(!status.is_ready(&clock, start));
+
+        // Not ready until the market ticks.
+        status.record_keys_loaded();
+        assert!(!status.is_ready(&clock, start));
+        status.record_tick(start);
+        assert!(status.is_ready(&clock, start));
+
+        // Late ticks make the server not ready again, unless the clock is paused.
+        let now = start + Duration::from_secs(10);
+        assert!(!status.is_ready(&clock, now));
+        clock.pause();
+        assert!(status.is_ready(&clock, now));
+        Ok(())
+    }
+}
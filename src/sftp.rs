@@ -0,0 +1,545 @@
+// Read-only SFTP subsystem, so that players can copy their data out with `sftp` or `scp`
+// using the same credentials they play with. The files are virtual: they are generated
+// from the market when the subsystem starts and only live for the session.
+
+use crate::{
+    agent::{DecisionAgent, UserAgent},
+    market::Market,
+    money::Money,
+    utils::AppResult,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Only version 3 is implemented, which is what OpenSSH speaks.
+const SFTP_VERSION: u32 = 3;
+// Packets larger than this are refused, reads are well below it.
+const MAX_PACKET_LENGTH: usize = 256 * 1024;
+const MAX_READ_LENGTH: u32 = 64 * 1024;
+const MAX_OPEN_HANDLES: usize = 32;
+
+const SSH_FXP_INIT: u8 = 1;
+const SSH_FXP_VERSION: u8 = 2;
+const SSH_FXP_OPEN: u8 = 3;
+const SSH_FXP_CLOSE: u8 = 4;
+const SSH_FXP_READ: u8 = 5;
+const SSH_FXP_WRITE: u8 = 6;
+const SSH_FXP_LSTAT: u8 = 7;
+const SSH_FXP_FSTAT: u8 = 8;
+const SSH_FXP_SETSTAT: u8 = 9;
+const SSH_FXP_FSETSTAT: u8 = 10;
+const SSH_FXP_OPENDIR: u8 = 11;
+const SSH_FXP_READDIR: u8 = 12;
+const SSH_FXP_REMOVE: u8 = 13;
+const SSH_FXP_MKDIR: u8 = 14;
+const SSH_FXP_RMDIR: u8 = 15;
+const SSH_FXP_REALPATH: u8 = 16;
+const SSH_FXP_STAT: u8 = 17;
+const SSH_FXP_RENAME: u8 = 18;
+const SSH_FXP_SYMLINK: u8 = 20;
+const SSH_FXP_STATUS: u8 = 101;
+const SSH_FXP_HANDLE: u8 = 102;
+const SSH_FXP_DATA: u8 = 103;
+const SSH_FXP_NAME: u8 = 104;
+const SSH_FXP_ATTRS: u8 = 105;
+
+const SSH_FX_OK: u32 = 0;
+const SSH_FX_EOF: u32 = 1;
+const SSH_FX_NO_SUCH_FILE: u32 = 2;
+const SSH_FX_PERMISSION_DENIED: u32 = 3;
+const SSH_FX_FAILURE: u32 = 4;
+const SSH_FX_BAD_MESSAGE: u32 = 5;
+const SSH_FX_OP_UNSUPPORTED: u32 = 8;
+
+const SSH_FXF_WRITE: u32 = 0x02;
+const SSH_FXF_APPEND: u32 = 0x04;
+const SSH_FXF_CREAT: u32 = 0x08;
+const SSH_FXF_TRUNC: u32 = 0x10;
+
+const SSH_FILEXFER_ATTR_SIZE: u32 = 0x01;
+const SSH_FILEXFER_ATTR_PERMISSIONS: u32 = 0x04;
+const SSH_FILEXFER_ATTR_ACMODTIME: u32 = 0x08;
+const READ_ONLY_FILE_MODE: u32 = 0o100444;
+const READ_ONLY_DIRECTORY_MODE: u32 = 0o040555;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VirtualFile {
+    pub name: &'static str,
+    pub contents: Vec<u8>,
+}
+
+/// The files exported to the player, generated from the current market.
+pub fn export_files(market: &Market, agent: &UserAgent) -> Vec<VirtualFile> {
+    let mut portfolio = "stonk_id,name,amount,unit_price_cents,value_cents\n".to_string();
+    for (stonk, &amount) in market.stonks.iter().zip(agent.owned_stonks().iter()) {
+        let price = stonk.current_unit_price_cents();
+        portfolio.push_str(&format!(
+            "{},{},{},{},{}\n",
+            stonk.id,
+            stonk.name,
+            amount,
+            price,
+            Money::from_cents(price as u64)
+                .saturating_mul(amount as u64)
+                .cents()
+        ));
+    }
+    portfolio.push_str(&format!(",cash,,,{}\n", agent.cash().cents()));
+    portfolio.push_str(&format!(
+        ",net_worth,,,{}\n",
+        market.net_worth(agent).cents()
+    ));
+
+    let mut history = "tick".to_string();
+    for stonk in market.stonks.iter() {
+        history.push_str(&format!(",{}", stonk.name));
+    }
+    history.push('\n');
+    let start_tick = market
+        .stonks
+        .iter()
+        .map(|stonk| stonk.historical_prices.start_tick())
+        .min()
+        .unwrap_or_default();
+    let end_tick = market
+        .stonks
+        .iter()
+        .map(|stonk| stonk.historical_prices.end_tick())
+        .max()
+        .unwrap_or_default();
+    for tick in start_tick..end_tick {
+        history.push_str(&tick.to_string());
+        for stonk in market.stonks.iter() {
+            history.push(',');
+            if let Some(price) = stonk.historical_prices.at_tick(tick) {
+                history.push_str(&price.to_string());
+            }
+        }
+        history.push('\n');
+    }
+
+    let mut leaderboard = format!("Leaderboard - {}\n", market.phase.formatted_date());
+    for (rank, (username, net_worth)) in market.portfolios.iter().enumerate() {
+        leaderboard.push_str(&format!(
+            "{:>4}. {:<16} ${}.{:02}\n",
+            rank + 1,
+            username,
+            net_worth.dollars(),
+            net_worth.cents() % 100
+        ));
+    }
+
+    vec![
+        VirtualFile {
+            name: "portfolio.csv",
+            contents: portfolio.into_bytes(),
+        },
+        VirtualFile {
+            name: "market_history.csv",
+            contents: history.into_bytes(),
+        },
+        VirtualFile {
+            name: "leaderboard.txt",
+            contents: leaderboard.into_bytes(),
+        },
+    ]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OpenHandle {
+    File(usize),
+    // Whether the entries have already been listed.
+    Directory { listed: bool },
+}
+
+/// The SFTP server of one session, fed with the channel data and returning the responses.
+#[derive(Debug, Clone)]
+pub struct SftpSession {
+    files: Vec<VirtualFile>,
+    // Received data not forming a whole packet yet.
+    buffer: Vec<u8>,
+    handles: Vec<Option<OpenHandle>>,
+    modified_at: u32,
+}
+
+impl SftpSession {
+    pub fn new(files: Vec<VirtualFile>) -> Self {
+        Self {
+            files,
+            buffer: vec![],
+            handles: vec![],
+            modified_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs() as u32)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Handles the received data, returning the responses to the complete packets.
+    /// Fails on packets too large to be SFTP requests, after which the channel must be closed.
+    pub fn feed(&mut self, data: &[u8]) -> AppResult<Vec<u8>> {
+        self.buffer.extend_from_slice(data);
+        let mut output = vec![];
+        while self.buffer.len() >= 4 {
+            let length = u32::from_be_bytes([
+                self.buffer[0],
+                self.buffer[1],
+                self.buffer[2],
+                self.buffer[3],
+            ]) as usize;
+            if length == 0 || length > MAX_PACKET_LENGTH {
+                return Err(format!("Invalid SFTP packet length {}", length).into());
+            }
+            if self.buffer.len() < 4 + length {
+                break;
+            }
+            let packet = self.buffer.drain(..4 + length).skip(4).collect::<Vec<u8>>();
+            let response = self.handle_packet(&packet);
+            output.extend_from_slice(&(response.len() as u32).to_be_bytes());
+            output.extend(response);
+        }
+        Ok(output)
+    }
+
+    fn handle_packet(&mut self, packet: &[u8]) -> Vec<u8> {
+        let mut reader = Reader(&packet[1..]);
+        if packet[0] == SSH_FXP_INIT {
+            let mut response = vec![SSH_FXP_VERSION];
+            put_u32(&mut response, SFTP_VERSION);
+            return response;
+        }
+        let Some(id) = reader.u32() else {
+            return status(0, SSH_FX_BAD_MESSAGE, "Missing request id");
+        };
+        self.handle_request(packet[0], id, &mut reader)
+            .unwrap_or_else(|| status(id, SSH_FX_BAD_MESSAGE, "Malformed request"))
+    }
+
+    // Returns None for malformed requests.
+    fn handle_request(&mut self, kind: u8, id: u32, reader: &mut Reader) -> Option<Vec<u8>> {
+        let response = match kind {
+            SSH_FXP_REALPATH => {
+                let path = normalize(&reader.string()?);
+                let mut response = vec![SSH_FXP_NAME];
+                put_u32(&mut response, id);
+                put_u32(&mut response, 1);
+                let name = format!("/{}", path);
+                put_string(&mut response, name.as_bytes());
+                put_string(&mut response, name.as_bytes());
+                put_u32(&mut response, 0);
+                response
+            }
+            SSH_FXP_STAT | SSH_FXP_LSTAT => {
+                let path = normalize(&reader.string()?);
+                if path.is_empty() {
+                    self.attrs_response(id, None)
+                } else {
+                    match self.file_index(&path) {
+                        Some(index) => self.attrs_response(id, Some(index)),
+                        None => status(id, SSH_FX_NO_SUCH_FILE, "No such file"),
+                    }
+                }
+            }
+            SSH_FXP_FSTAT => match self.handle(&reader.string()?) {
+                Some(OpenHandle::File(index)) => self.attrs_response(id, Some(index)),
+                Some(OpenHandle::Directory { .. }) => self.attrs_response(id, None),
+                None => status(id, SSH_FX_NO_SUCH_FILE, "Invalid handle"),
+            },
+            SSH_FXP_OPEN => {
+                let path = normalize(&reader.string()?);
+                let flags = reader.u32()?;
+                if flags & (SSH_FXF_WRITE | SSH_FXF_APPEND | SSH_FXF_CREAT | SSH_FXF_TRUNC) != 0 {
+                    status(id, SSH_FX_PERMISSION_DENIED, "The files are read-only")
+                } else {
+                    match self.file_index(&path) {
+                        Some(index) => self.open_handle(id, OpenHandle::File(index)),
+                        None => status(id, SSH_FX_NO_SUCH_FILE, "No such file"),
+                    }
+                }
+            }
+            SSH_FXP_OPENDIR => {
+                if normalize(&reader.string()?).is_empty() {
+                    self.open_handle(id, OpenHandle::Directory { listed: false })
+                } else {
+                    status(id, SSH_FX_NO_SUCH_FILE, "No such directory")
+                }
+            }
+            SSH_FXP_READ => {
+                let handle = reader.string()?;
+                let offset = reader.u64()?;
+                let length = reader.u32()?.min(MAX_READ_LENGTH);
+                match self.handle(&handle) {
+                    Some(OpenHandle::File(index)) => {
+                        let contents = &self.files[index].contents;
+                        if offset >= contents.len() as u64 {
+                            status(id, SSH_FX_EOF, "End of file")
+                        } else {
+                            let start = offset as usize;
+                            let end = (start + length as usize).min(contents.len());
+                            let mut response = vec![SSH_FXP_DATA];
+                            put_u32(&mut response, id);
+                            put_string(&mut response, &contents[start..end]);
+                            response
+                        }
+                    }
+                    _ => status(id, SSH_FX_NO_SUCH_FILE, "Invalid handle"),
+                }
+            }
+            SSH_FXP_READDIR => {
+                let handle = reader.string()?;
+                match self.handle(&handle) {
+                    Some(OpenHandle::Directory { listed: false }) => {
+                        self.set_handle(&handle, OpenHandle::Directory { listed: true });
+                        let mut response = vec![SSH_FXP_NAME];
+                        put_u32(&mut response, id);
+                        put_u32(&mut response, self.files.len() as u32);
+                        for (index, file) in self.files.iter().enumerate() {
+                            put_string(&mut response, file.name.as_bytes());
+                            let long_name = format!(
+                                "-r--r--r--    1 stonks   stonks   {:>8} {}",
+                                file.contents.len(),
+                                file.name
+                            );
+                            put_string(&mut response, long_name.as_bytes());
+                            self.put_attrs(&mut response, Some(index));
+                        }
+                        response
+                    }
+                    Some(OpenHandle::Directory { listed: true }) => {
+                        status(id, SSH_FX_EOF, "End of directory")
+                    }
+                    _ => status(id, SSH_FX_NO_SUCH_FILE, "Invalid handle"),
+                }
+            }
+            SSH_FXP_CLOSE => {
+                let handle = reader.string()?;
+                match handle_index(&handle).and_then(|index| self.handles.get_mut(index)) {
+                    Some(open) if open.is_some() => {
+                        *open = None;
+                        status(id, SSH_FX_OK, "")
+                    }
+                    _ => status(id, SSH_FX_NO_SUCH_FILE, "Invalid handle"),
+                }
+            }
+            SSH_FXP_WRITE | SSH_FXP_SETSTAT | SSH_FXP_FSETSTAT | SSH_FXP_REMOVE | SSH_FXP_MKDIR
+            | SSH_FXP_RMDIR | SSH_FXP_RENAME | SSH_FXP_SYMLINK => {
+                status(id, SSH_FX_PERMISSION_DENIED, "The files are read-only")
+            }
+            _ => status(id, SSH_FX_OP_UNSUPPORTED, "Unsupported operation"),
+        };
+        Some(response)
+    }
+
+    fn file_index(&self, path: &str) -> Option<usize> {
+        self.files.iter().position(|file| file.name == path)
+    }
+
+    fn handle(&self, handle: &[u8]) -> Option<OpenHandle> {
+        handle_index(handle).and_then(|index| self.handles.get(index).copied().flatten())
+    }
+
+    fn set_handle(&mut self, handle: &[u8], open: OpenHandle) {
+        if let Some(slot) = handle_index(handle).and_then(|index| self.handles.get_mut(index)) {
+            *slot = Some(open);
+        }
+    }
+
+    fn open_handle(&mut self, id: u32, open: OpenHandle) -> Vec<u8> {
+        // Closed handles are reused, so that the list stays as long as the open handles.
+        let index = match self.handles.iter().position(Option::is_none) {
+            Some(index) => {
+                self.handles[index] = Some(open);
+                index
+            }
+            None if self.handles.len() >= MAX_OPEN_HANDLES => {
+                return status(id, SSH_FX_FAILURE, "Too many open handles");
+            }
+            None => {
+                self.handles.push(Some(open));
+                self.handles.len() - 1
+            }
+        };
+        let mut response = vec![SSH_FXP_HANDLE];
+        put_u32(&mut response, id);
+        put_string(&mut response, index.to_string().as_bytes());
+        response
+    }
+
+    // The attributes of a file, or of the root directory.
+    fn put_attrs(&self, output: &mut Vec<u8>, file_index: Option<usize>) {
+        let (size, mode) = match file_index {
+            Some(index) => (self.files[index].contents.len() as u64, READ_ONLY_FILE_MODE),
+            None => (0, READ_ONLY_DIRECTORY_MODE),
+        };
+        put_u32(
+            output,
+            SSH_FILEXFER_ATTR_SIZE | SSH_FILEXFER_ATTR_PERMISSIONS | SSH_FILEXFER_ATTR_ACMODTIME,
+        );
+        output.extend_from_slice(&size.to_be_bytes());
+        put_u32(output, mode);
+        put_u32(output, self.modified_at);
+        put_u32(output, self.modified_at);
+    }
+
+    fn attrs_response(&self, id: u32, file_index: Option<usize>) -> Vec<u8> {
+        let mut response = vec![SSH_FXP_ATTRS];
+        put_u32(&mut response, id);
+        self.put_attrs(&mut response, file_index);
+        response
+    }
+}
+
+// Paths are relative to the root directory, which holds all the files.
+fn normalize(path: &[u8]) -> String {
+    String::from_utf8_lossy(path)
+        .split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn handle_index(handle: &[u8]) -> Option<usize> {
+    std::str::from_utf8(handle).ok()?.parse().ok()
+}
+
+fn status(id: u32, code: u32, message: &str) -> Vec<u8> {
+    let mut response = vec![SSH_FXP_STATUS];
+    put_u32(&mut response, id);
+    put_u32(&mut response, code);
+    put_string(&mut response, message.as_bytes());
+    put_string(&mut response, b"");
+    response
+}
+
+fn put_u32(output: &mut Vec<u8>, value: u32) {
+    output.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_string(output: &mut Vec<u8>, value: &[u8]) {
+    put_u32(output, value.len() as u32);
+    output.extend_from_slice(value);
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take(&mut self, length: usize) -> Option<&[u8]> {
+        if self.0.len() < length {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn string(&mut self) -> Option<Vec<u8>> {
+        let length = self.u32()? as usize;
+        self.take(length).map(|value| value.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{agent::UserAgent, ssh_client::SessionAuth};
+
+    fn request(kind: u8, id: u32, fields: &[&[u8]]) -> Vec<u8> {
+        let mut body = vec![kind];
+        put_u32(&mut body, id);
+        for field in fields {
+            body.extend_from_slice(field);
+        }
+        let mut packet = vec![];
+        put_string(&mut packet, &body);
+        packet
+    }
+
+    fn string(value: &str) -> Vec<u8> {
+        let mut output = vec![];
+        put_string(&mut output, value.as_bytes());
+        output
+    }
+
+    // Splits the output into packets, as (type, body after the request id).
+    fn responses(output: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        let mut reader = Reader(output);
+        let mut responses = vec![];
+        while let Some(packet) = reader.string() {
+            responses.push((packet[0], packet[5..].to_vec()));
+        }
+        responses
+    }
+
+    #[test]
+    fn test_sftp_session() -> AppResult<()> {
+        let market = Market::default();
+        let agent = UserAgent::new(SessionAuth::new("saver".to_string(), [0; 32]));
+        let files = export_files(&market, &agent);
+        assert_eq!(files.len(), 3);
+        assert!(String::from_utf8_lossy(&files[0].contents).contains(",cash,,,"));
+        let mut session = SftpSession::new(files.clone());
+
+        let mut init = vec![];
+        put_string(&mut init, &[SSH_FXP_INIT, 0, 0, 0, 3]);
+        // Packets can be split over several messages.
+        assert!(session.feed(&init[..3])?.is_empty());
+        let output = session.feed(&init[3..])?;
+        assert_eq!(output, [0, 0, 0, 5, SSH_FXP_VERSION, 0, 0, 0, 3]);
+
+        let mut output = session.feed(&request(SSH_FXP_OPENDIR, 1, &[&string("/")]))?;
+        let handle = responses(&output)[0].1.clone();
+        output = session.feed(&request(SSH_FXP_READDIR, 2, &[&handle]))?;
+        output.extend(session.feed(&request(SSH_FXP_READDIR, 3, &[&handle]))?);
+        let listing = responses(&output);
+        assert_eq!(listing[0].0, SSH_FXP_NAME);
+        assert!(String::from_utf8_lossy(&listing[0].1).contains("leaderboard.txt"));
+        assert_eq!(listing[1].0, SSH_FXP_STATUS);
+        assert_eq!(listing[1].1[..4], SSH_FX_EOF.to_be_bytes());
+
+        // Files can be read but not written.
+        let flags = 0x01u32.to_be_bytes();
+        let attrs = 0u32.to_be_bytes();
+        output = session.feed(&request(
+            SSH_FXP_OPEN,
+            4,
+            &[&string("./portfolio.csv"), &flags, &attrs],
+        ))?;
+        let (kind, handle) = responses(&output)[0].clone();
+        assert_eq!(kind, SSH_FXP_HANDLE);
+        let length = 1_000u32.to_be_bytes();
+        output = session.feed(&request(
+            SSH_FXP_READ,
+            5,
+            &[&handle, &0u64.to_be_bytes(), &length],
+        ))?;
+        let (kind, data) = responses(&output)[0].clone();
+        assert_eq!(kind, SSH_FXP_DATA);
+        assert_eq!(Reader(&data).string(), Some(files[0].contents.clone()));
+
+        let flags = (SSH_FXF_WRITE | SSH_FXF_CREAT).to_be_bytes();
+        output = session.feed(&request(
+            SSH_FXP_OPEN,
+            6,
+            &[&string("portfolio.csv"), &flags, &attrs],
+        ))?;
+        assert_eq!(
+            responses(&output)[0].1[..4],
+            SSH_FX_PERMISSION_DENIED.to_be_bytes()
+        );
+        output = session.feed(&request(SSH_FXP_STAT, 7, &[&string("secrets.txt")]))?;
+        assert_eq!(
+            responses(&output)[0].1[..4],
+            SSH_FX_NO_SUCH_FILE.to_be_bytes()
+        );
+
+        assert!(session.feed(&u32::MAX.to_be_bytes()).is_err());
+        Ok(())
+    }
+}
@@ -22,7 +22,85 @@ use ratatui::{
     style::{Color, Modifier},
 };
 
-use crate::{ssh_client::TerminalHandle, utils::AppResult};
+use crate::{
+    ssh_client::TerminalHandle,
+    utils::{img_to_sixel, read_asset, read_image, resize_image, AppResult},
+};
+use base64ct::{Base64, Encoding};
+
+/// Colors the client terminal can display, detected from its `TERM` and `COLORTERM`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSupport {
+    #[default]
+    TrueColor,
+    Ansi256,
+}
+
+/// Terminals known to support truecolor even when `COLORTERM` is not forwarded.
+const TRUECOLOR_TERMS: [&str; 7] = [
+    "truecolor",
+    "24bit",
+    "direct",
+    "kitty",
+    "alacritty",
+    "wezterm",
+    "foot",
+];
+
+impl ColorSupport {
+    pub fn detect(term: &str, colorterm: Option<&str>) -> Self {
+        if let Some(colorterm) = colorterm {
+            if colorterm == "truecolor" || colorterm == "24bit" {
+                return Self::TrueColor;
+            }
+        }
+
+        let term = term.to_lowercase();
+        if TRUECOLOR_TERMS.iter().any(|t| term.contains(t)) {
+            Self::TrueColor
+        } else if term.is_empty() {
+            Self::default()
+        } else {
+            Self::Ansi256
+        }
+    }
+}
+
+/// Image protocol supported by the client terminal, detected from its `TERM`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GraphicsProtocol {
+    #[default]
+    None,
+    Kitty,
+    Sixel,
+}
+
+const KITTY_TERMS: [&str; 3] = ["kitty", "wezterm", "ghostty"];
+const SIXEL_TERMS: [&str; 5] = ["sixel", "mlterm", "foot", "yaft", "contour"];
+// Kitty graphics payloads must be sent in chunks of at most 4096 bytes.
+const KITTY_CHUNK_SIZE: usize = 4096;
+// Cell size in pixels used when the client does not report it.
+const DEFAULT_CELL_PIXEL_SIZE: (u16, u16) = (10, 20);
+
+impl GraphicsProtocol {
+    pub fn detect(term: &str) -> Self {
+        let term = term.to_lowercase();
+        if KITTY_TERMS.iter().any(|t| term.contains(t)) {
+            Self::Kitty
+        } else if SIXEL_TERMS.iter().any(|t| term.contains(t)) {
+            Self::Sixel
+        } else {
+            Self::None
+        }
+    }
+}
+
+/// An image asset to be drawn over the given area with the terminal graphics protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImagePlacement {
+    pub path: &'static str,
+    pub area: Rect,
+}
 
 /// A [`Backend`] implementation that uses [Crossterm] to render to the terminal.
 ///
@@ -83,6 +161,10 @@ pub struct SSHBackend {
     /// The writer used to send commands to the terminal.
     writer: TerminalHandle,
     pub size: (u16, u16),
+    pub color_support: ColorSupport,
+    pub graphics: GraphicsProtocol,
+    /// Size of the terminal in pixels, if reported by the client.
+    pub pixel_size: (u16, u16),
 }
 
 impl SSHBackend {
@@ -96,12 +178,88 @@ impl SSHBackend {
     /// let backend = CrosstermBackend::new(stdout());
     /// ```
     pub fn new(writer: TerminalHandle, size: (u16, u16)) -> SSHBackend {
-        SSHBackend { writer, size }
+        SSHBackend {
+            writer,
+            size,
+            color_support: ColorSupport::default(),
+            graphics: GraphicsProtocol::default(),
+            pixel_size: (0, 0),
+        }
+    }
+
+    fn cell_pixel_size(&self) -> (u16, u16) {
+        if self.pixel_size.0 == 0 || self.pixel_size.1 == 0 || self.size.0 == 0 || self.size.1 == 0
+        {
+            return DEFAULT_CELL_PIXEL_SIZE;
+        }
+        (
+            (self.pixel_size.0 / self.size.0).max(1),
+            (self.pixel_size.1 / self.size.1).max(1),
+        )
+    }
+
+    /// Draws the images with the client graphics protocol, on top of the last drawn frame.
+    pub fn draw_images(&mut self, images: &[ImagePlacement]) -> AppResult<()> {
+        for image in images.iter() {
+            queue!(self.writer, MoveTo(image.area.x, image.area.y))?;
+            match self.graphics {
+                GraphicsProtocol::None => {}
+                GraphicsProtocol::Kitty => {
+                    let encoded = Base64::encode_string(read_asset(image.path)?);
+                    let chunks = encoded
+                        .as_bytes()
+                        .chunks(KITTY_CHUNK_SIZE)
+                        .collect::<Vec<_>>();
+                    for (idx, chunk) in chunks.iter().enumerate() {
+                        let more = if idx < chunks.len() - 1 { 1 } else { 0 };
+                        let control = if idx == 0 {
+                            // Quiet mode, so that the terminal does not send responses back as input.
+                            format!(
+                                "a=T,f=100,q=2,C=1,c={},r={},m={}",
+                                image.area.width, image.area.height, more
+                            )
+                        } else {
+                            format!("m={}", more)
+                        };
+                        self.writer.write_all(b"\x1b_G")?;
+                        self.writer.write_all(control.as_bytes())?;
+                        self.writer.write_all(b";")?;
+                        self.writer.write_all(chunk)?;
+                        self.writer.write_all(b"\x1b\\")?;
+                    }
+                }
+                GraphicsProtocol::Sixel => {
+                    let (cell_width, cell_height) = self.cell_pixel_size();
+                    let resized = resize_image(
+                        &read_image(image.path)?,
+                        image.area.width as u32 * cell_width as u32,
+                        image.area.height as u32 * cell_height as u32,
+                    )?;
+                    self.writer.write_all(img_to_sixel(&resized).as_bytes())?;
+                }
+            }
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Removes the images drawn with the kitty protocol. Sixel images are part of the cells and
+    /// are removed by redrawing them.
+    pub fn delete_images(&mut self) -> AppResult<()> {
+        if self.graphics == GraphicsProtocol::Kitty {
+            self.writer.write_all(b"\x1b_Ga=d,q=2\x1b\\")?;
+            self.writer.flush()?;
+        }
+        Ok(())
     }
 
     pub async fn close(&self) -> AppResult<()> {
         self.writer.close().await
     }
+
+    pub async fn disconnect(&self, reason: &str) -> AppResult<()> {
+        self.writer.disconnect(reason).await
+    }
 }
 
 impl Write for SSHBackend {
@@ -123,8 +281,6 @@ impl Backend for SSHBackend {
     {
         let mut fg = Color::Reset;
         let mut bg = Color::Reset;
-        #[cfg(feature = "underline-color")]
-        let mut underline_color = Color::Reset;
         let mut modifier = Modifier::empty();
         let mut last_pos: Option<(u16, u16)> = None;
         for (x, y, cell) in content {
@@ -142,32 +298,25 @@ impl Backend for SSHBackend {
                 modifier = cell.modifier;
             }
             if cell.fg != fg {
-                let color = c_color_from_color(cell.fg);
+                let color = c_color_from_color(cell.fg, self.color_support);
                 queue!(self.writer, SetForegroundColor(color))?;
                 fg = cell.fg;
             }
             if cell.bg != bg {
-                let color = c_color_from_color(cell.bg);
+                let color = c_color_from_color(cell.bg, self.color_support);
                 queue!(self.writer, SetBackgroundColor(color))?;
                 bg = cell.bg;
             }
-            #[cfg(feature = "underline-color")]
-            if cell.underline_color != underline_color {
-                let color = CColor::from(cell.underline_color);
-                queue!(self.writer, SetUnderlineColor(color))?;
-                underline_color = cell.underline_color;
-            }
 
             queue!(self.writer, Print(cell.symbol()))?;
         }
 
-        #[cfg(not(feature = "underline-color"))]
-        return queue!(
+        queue!(
             self.writer,
             SetForegroundColor(CColor::Reset),
             SetBackgroundColor(CColor::Reset),
             SetAttribute(CAttribute::Reset),
-        );
+        )
     }
 
     fn hide_cursor(&mut self) -> io::Result<()> {
@@ -179,8 +328,7 @@ impl Backend for SSHBackend {
     }
 
     fn get_cursor(&mut self) -> io::Result<(u16, u16)> {
-        crossterm::cursor::position()
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+        crossterm::cursor::position().map_err(|e| io::Error::other(e.to_string()))
     }
 
     fn set_cursor(&mut self, x: u16, y: u16) -> io::Result<()> {
@@ -300,7 +448,39 @@ impl ModifierDiff {
     }
 }
 
-fn c_color_from_color(color: Color) -> CColor {
+/// Maps an RGB color to the closest entry of the xterm 256 colors palette.
+fn rgb_to_ansi256(r: u8, g: u8, b: u8) -> u8 {
+    const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let cube_index = |v: u8| match v {
+        0..=47 => 0,
+        48..=114 => 1,
+        _ => (v - 35) / 40,
+    };
+    let distance = |(r1, g1, b1): (u8, u8, u8)| {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(r, r1) + d(g, g1) + d(b, b1)
+    };
+
+    let (ri, gi, bi) = (cube_index(r), cube_index(g), cube_index(b));
+    let cube_color = (
+        CUBE_LEVELS[ri as usize],
+        CUBE_LEVELS[gi as usize],
+        CUBE_LEVELS[bi as usize],
+    );
+
+    // The grayscale ramp goes from 8 to 238 in steps of 10.
+    let average = (r as u32 + g as u32 + b as u32) / 3;
+    let gray_index = (average.saturating_sub(3) / 10).min(23) as u8;
+    let gray_level = 8 + 10 * gray_index;
+
+    if distance((gray_level, gray_level, gray_level)) < distance(cube_color) {
+        232 + gray_index
+    } else {
+        16 + 36 * ri + 6 * gi + bi
+    }
+}
+
+fn c_color_from_color(color: Color, color_support: ColorSupport) -> CColor {
     match color {
         Color::Reset => CColor::Reset,
         Color::Black => CColor::Black,
@@ -320,6 +500,45 @@ fn c_color_from_color(color: Color) -> CColor {
         Color::LightCyan => CColor::Cyan,
         Color::White => CColor::White,
         Color::Indexed(i) => CColor::AnsiValue(i),
-        Color::Rgb(r, g, b) => CColor::Rgb { r, g, b },
+        Color::Rgb(r, g, b) => match color_support {
+            ColorSupport::TrueColor => CColor::Rgb { r, g, b },
+            ColorSupport::Ansi256 => CColor::AnsiValue(rgb_to_ansi256(r, g, b)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rgb_to_ansi256, ColorSupport, GraphicsProtocol};
+
+    #[test]
+    fn test_color_support() {
+        assert_eq!(
+            ColorSupport::detect("xterm-256color", None),
+            ColorSupport::Ansi256
+        );
+        assert_eq!(
+            ColorSupport::detect("xterm-256color", Some("truecolor")),
+            ColorSupport::TrueColor
+        );
+        assert_eq!(
+            ColorSupport::detect("xterm-kitty", None),
+            ColorSupport::TrueColor
+        );
+
+        assert_eq!(rgb_to_ansi256(0, 0, 0), 16);
+        assert_eq!(rgb_to_ansi256(255, 255, 255), 231);
+        assert_eq!(rgb_to_ansi256(255, 0, 0), 196);
+        assert_eq!(rgb_to_ansi256(128, 128, 128), 244);
+
+        assert_eq!(
+            GraphicsProtocol::detect("xterm-kitty"),
+            GraphicsProtocol::Kitty
+        );
+        assert_eq!(GraphicsProtocol::detect("foot"), GraphicsProtocol::Sixel);
+        assert_eq!(
+            GraphicsProtocol::detect("xterm-256color"),
+            GraphicsProtocol::None
+        );
     }
 }
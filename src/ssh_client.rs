@@ -1,53 +1,231 @@
-use crate::agent::{AgentAction, DecisionAgent, UserAgent};
-use crate::market::{GamePhase, Market};
-use crate::ssh_backend::SSHBackend;
+use crate::agent::{
+    AgentAction, AgentCondition, DecisionAgent, ScheduledOrder, UserAgent, MAX_VACATION_DAYS,
+};
+use crate::board::{BoardDecision, Boards, BOARD_CONTROL_STAKE};
+use crate::clock::{Maintenance, MARKET_TICK_INTERVAL_MILLIS};
+use crate::events::ASSASSINATION_DEFENSES;
+use crate::futures::FuturesBook;
+use crate::input::InputDecoder;
+use crate::manual::manual_pages;
+use crate::market::{
+    max_buy_amount_with_fee, GamePhase, Market, Venue, DAY_LENGTH, NIGHT_LENGTH, NUMBER_OF_STONKS,
+};
+use crate::money::Money;
+use crate::notifications::NotificationPriority;
+use crate::recording::{RecordedEvent, SessionRecorder, RECORDING_SFTP_FILENAME};
+use crate::sandbox::Sandbox;
+use crate::server_status::DebugStats;
+use crate::ssh_backend::{ColorSupport, GraphicsProtocol, SSHBackend};
+use crate::stonk::{DollarValue, Stonk};
+use crate::totp::{self, MAX_TOTP_ATTEMPTS, TOTP_DIGITS};
 use crate::tui::Tui;
-use crate::ui::UiOptions;
+use crate::ui::{
+    whats_new, AccountDialog, ApiTokensScreen, LoginCodePrompt, MenuItem, Modal, TwoFactorScreen,
+    UiDisplay, UiOptions,
+};
 use crate::utils::*;
 use crossterm::event::*;
 use russh::{server::*, ChannelId, CryptoVec, Disconnect};
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
-use std::time::SystemTime;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use strum::IntoEnumIterator;
+use tracing::debug;
+
+// Weight of the latest sample in the connection stats moving averages.
+const CONNECTION_STATS_SMOOTHING: f64 = 0.1;
+const POOR_CONNECTION_ROUND_TRIP_MILLIS: f64 = 250.0;
+const POOR_CONNECTION_DROP_RATE: f64 = 0.1;
+const MAX_ACCOUNT_FIELD_LENGTH: usize = 32;
+const TRADE_NOTIFICATION_SECONDS: u64 = 5;
+const RECORDING_NOTIFICATION_SECONDS: u64 = 10;
+// Function key toggling the debug overlay of the admins.
+const DEBUG_OVERLAY_KEY: u8 = 12;
+// Longest time between two frames of a client, for the clocks and countdowns on screen.
+const FRAME_REFRESH_MILLIS: u64 = 500;
+// Upper bound of a typed count prefix, well above any reachable holding.
+const MAX_COUNT_PREFIX: u32 = 1_000_000_000;
+// Step of the amount of the currency exchange prompt, in the currency sold.
+const CURRENCY_EXCHANGE_STEP: Money = Money::from_dollars(100);
+// Step of the amount of shares of the futures prompt.
+const FUTURES_AMOUNT_STEP: u32 = 10;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ConnectionStats {
+    // Moving average of the time needed to flush a frame to the client.
+    pub round_trip_millis: f64,
+    // Moving average of the fraction of frames that could not be delivered.
+    pub drop_rate: f64,
+    pub frames: u64,
+    pub dropped_frames: u64,
+    // Frames can only be dropped once the SSH session is gone, e.g. after missed keepalives.
+    pub closed: bool,
+    pub last_frame_bytes: usize,
+}
+
+impl ConnectionStats {
+    fn record_flush(&mut self, elapsed: Duration, dropped: bool) {
+        let millis = elapsed.as_secs_f64() * 1000.0;
+        let dropped_sample = if dropped { 1.0 } else { 0.0 };
+        if self.frames == 0 {
+            self.round_trip_millis = millis;
+            self.drop_rate = dropped_sample;
+        } else {
+            self.round_trip_millis +=
+                CONNECTION_STATS_SMOOTHING * (millis - self.round_trip_millis);
+            self.drop_rate += CONNECTION_STATS_SMOOTHING * (dropped_sample - self.drop_rate);
+        }
+        self.frames += 1;
+        if dropped {
+            self.dropped_frames += 1;
+            self.closed = true;
+        }
+    }
+
+    pub fn is_poor(&self) -> bool {
+        self.round_trip_millis > POOR_CONNECTION_ROUND_TRIP_MILLIS
+            || self.drop_rate > POOR_CONNECTION_DROP_RATE
+    }
+}
+
+/// Decides when the render loop draws a client: after its input, when the market changed and
+/// while an animation runs, and otherwise only every FRAME_REFRESH_MILLIS to update the clocks.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FrameSchedule {
+    // Set by the input until the next frame.
+    dirty: bool,
+    // Market revision and time of the last frame.
+    drawn: Option<(u64, Instant)>,
+}
+
+impl FrameSchedule {
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn is_due(&self, revision: u64, animating: bool, now: Instant) -> bool {
+        match self.drawn {
+            Some((drawn_revision, drawn_at)) => {
+                self.dirty
+                    || animating
+                    || drawn_revision != revision
+                    || now.duration_since(drawn_at) >= Duration::from_millis(FRAME_REFRESH_MILLIS)
+            }
+            None => true,
+        }
+    }
+
+    pub fn record_frame(&mut self, revision: u64, now: Instant) {
+        self.dirty = false;
+        self.drawn = Some((revision, now));
+    }
+}
+
+/// Bytes that can be sent to a client every second. Frames over the budget are dropped,
+/// so that a client on a slow link only gets the frames it can take.
+#[derive(Debug, Clone, Copy)]
+pub struct BandwidthBudget {
+    bytes_per_second: u64,
+    // Start of the current one second window and bytes sent in it.
+    window: Option<(Instant, u64)>,
+    // Whether the budget ran out in the current or in the last window.
+    throttled: bool,
+}
+
+impl BandwidthBudget {
+    pub fn new(bytes_per_second: u64) -> Self {
+        Self {
+            bytes_per_second,
+            window: None,
+            throttled: false,
+        }
+    }
+
+    fn roll_window(&mut self, now: Instant) -> u64 {
+        match self.window {
+            Some((start, sent)) if now.duration_since(start) < Duration::from_secs(1) => sent,
+            Some((_, sent)) => {
+                self.throttled = sent >= self.bytes_per_second;
+                self.window = Some((now, 0));
+                0
+            }
+            None => {
+                self.window = Some((now, 0));
+                0
+            }
+        }
+    }
+
+    fn record_sent(&mut self, bytes: usize, now: Instant) {
+        let sent = self.roll_window(now);
+        if let Some((start, _)) = self.window {
+            self.window = Some((start, sent + bytes as u64));
+        }
+    }
+
+    /// Whether a new frame can be sent, marking the budget as throttled if not.
+    pub fn allows_frame(&mut self, now: Instant) -> bool {
+        if self.roll_window(now) < self.bytes_per_second {
+            return true;
+        }
+        self.throttled = true;
+        false
+    }
+
+    pub fn is_throttled(&self) -> bool {
+        self.throttled
+    }
+}
 
 #[derive(Clone)]
 pub struct TerminalHandle {
-    handle: Handle,
+    // Not set for headless clients, whose frames are discarded.
+    channel: Option<(Handle, ChannelId)>,
     // The sink collects the data which is finally flushed to the handle.
     sink: Vec<u8>,
-    channel_id: ChannelId,
+    stats: Arc<Mutex<ConnectionStats>>,
+    budget: Arc<Mutex<BandwidthBudget>>,
 }
 
 impl Debug for TerminalHandle {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("TerminalHandle")
             .field("sink", &self.sink)
-            .field("channel_id", &self.channel_id)
+            .field(
+                "channel_id",
+                &self.channel.as_ref().map(|(_, channel_id)| channel_id),
+            )
             .finish()
     }
 }
 
 impl TerminalHandle {
     pub async fn close(&self) -> AppResult<()> {
-        self.handle
-            .close(self.channel_id)
+        self.disconnect("Game quit").await
+    }
+
+    pub async fn disconnect(&self, reason: &str) -> AppResult<()> {
+        let Some((handle, channel_id)) = self.channel.as_ref() else {
+            return Ok(());
+        };
+        handle
+            .close(*channel_id)
             .await
             .map_err(|_| anyhow::anyhow!("Close terminal error"))?;
-        self.handle
-            .disconnect(Disconnect::ByApplication, "Game quit".into(), "".into())
+        handle
+            .disconnect(Disconnect::ByApplication, reason.into(), "".into())
             .await?;
         Ok(())
     }
 
-    async fn _flush(&self) -> std::io::Result<usize> {
-        let handle = self.handle.clone();
-        let channel_id = self.channel_id.clone();
+    // Returns whether the data was delivered to the channel.
+    async fn _flush(&self) -> std::io::Result<bool> {
+        let Some((handle, channel_id)) = self.channel.clone() else {
+            return Ok(true);
+        };
         let data: CryptoVec = self.sink.clone().into();
-        let data_length = data.len();
-        if let Err(err_data) = handle.data(channel_id, data).await {
-            return Ok(err_data.len());
-        }
-        Ok(data_length)
+        Ok(handle.data(channel_id, data).await.is_ok())
     }
 }
 
@@ -59,7 +237,15 @@ impl std::io::Write for TerminalHandle {
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
-        futures::executor::block_on(self._flush())?;
+        let start = Instant::now();
+        let delivered = futures::executor::block_on(self._flush())?;
+        if let Ok(mut stats) = self.stats.lock() {
+            stats.record_flush(start.elapsed(), !delivered);
+            stats.last_frame_bytes = self.sink.len();
+        }
+        if let Ok(mut budget) = self.budget.lock() {
+            budget.record_sent(self.sink.len(), Instant::now());
+        }
         self.sink.clear();
         Ok(())
     }
@@ -69,14 +255,60 @@ pub struct Client {
     pub tui: Tui,
     ui_options: UiOptions,
     username: String,
+    connection_stats: Arc<Mutex<ConnectionStats>>,
+    bandwidth_budget: Arc<Mutex<BandwidthBudget>>,
+    term: String,
+    colorterm: Option<String>,
+    // Colors detected for the terminal, reduced to 256 colors while the client is throttled.
+    color_support: ColorSupport,
+    // State announced last in text mode, as (is day, cash, number of threats).
+    announced_state: Option<(bool, Money, usize)>,
+    input_decoder: InputDecoder,
+    // Whether the paused copy mode screen has already been drawn.
+    copy_mode_drawn: bool,
+    // Private copy of the market used for practice, while the player is in it.
+    sandbox: Option<Sandbox>,
+    // Game clock state, as last seen by the server render loop.
+    market_halted: bool,
+    tick_interval: Duration,
+    // Cash and holdings at the last frame, to notify the trades executed since.
+    notified_holdings: Option<(Money, [u32; NUMBER_OF_STONKS])>,
+    // Count typed before a trade key, as in `25b` to buy 25 shares.
+    pending_count: Option<u32>,
+    frame_schedule: FrameSchedule,
+    // Whether the player is a server admin, who can open the debug overlay.
+    is_admin: bool,
+    // Set while the player records the session for a bug report.
+    recorder: Option<SessionRecorder>,
 }
 
 impl Client {
-    pub fn new(username: String, handle: Handle, channel_id: ChannelId) -> AppResult<Self> {
+    pub fn new(
+        username: String,
+        handle: Handle,
+        channel_id: ChannelId,
+        max_bytes_per_second: u64,
+    ) -> AppResult<Self> {
+        Self::with_channel(username, Some((handle, channel_id)), max_bytes_per_second)
+    }
+
+    /// A client without connection, used to play back recorded sessions.
+    pub fn headless(username: String) -> AppResult<Self> {
+        Self::with_channel(username, None, u64::MAX)
+    }
+
+    fn with_channel(
+        username: String,
+        channel: Option<(Handle, ChannelId)>,
+        max_bytes_per_second: u64,
+    ) -> AppResult<Self> {
+        let connection_stats = Arc::new(Mutex::new(ConnectionStats::default()));
+        let bandwidth_budget = Arc::new(Mutex::new(BandwidthBudget::new(max_bytes_per_second)));
         let terminal_handle = TerminalHandle {
-            handle,
+            channel,
             sink: Vec::new(),
-            channel_id,
+            stats: connection_stats.clone(),
+            budget: bandwidth_budget.clone(),
         };
 
         let backend = SSHBackend::new(terminal_handle, (160, 48));
@@ -87,27 +319,749 @@ impl Client {
             .clear()
             .map_err(|e| anyhow::anyhow!("Failed to clear terminal: {}", e))?;
 
+        // Players land on the menu rather than straight into the market.
+        let mut ui_options = UiOptions::new();
+        ui_options.modals.push(Modal::Menu { selected: 0 });
+
         Ok(Client {
             tui,
-            ui_options: UiOptions::new(),
+            ui_options,
             username,
+            connection_stats,
+            bandwidth_budget,
+            term: String::new(),
+            colorterm: None,
+            color_support: ColorSupport::default(),
+            announced_state: None,
+            input_decoder: InputDecoder::default(),
+            copy_mode_drawn: false,
+            sandbox: None,
+            market_halted: false,
+            tick_interval: Duration::from_millis(MARKET_TICK_INTERVAL_MILLIS),
+            notified_holdings: None,
+            pending_count: None,
+            frame_schedule: FrameSchedule::default(),
+            is_admin: false,
+            recorder: None,
         })
     }
+
+    pub fn set_idle_countdown(&mut self, seconds: Option<u64>) {
+        if seconds.is_some() {
+            // The warning must be visible, so redraws cannot stay paused.
+            self.ui_options.stop_copy_mode();
+        }
+        if self.ui_options.idle_countdown != seconds {
+            self.mark_dirty();
+        }
+        self.ui_options.idle_countdown = seconds;
+    }
+
+    pub fn set_clock_status(&mut self, paused: bool, tick_interval: Duration) {
+        if self.market_halted != paused || self.tick_interval != tick_interval {
+            self.mark_dirty();
+        }
+        self.market_halted = paused;
+        self.tick_interval = tick_interval;
+    }
+
+    pub fn set_maintenance(&mut self, maintenance: Option<&Maintenance>) {
+        if self.ui_options.maintenance.as_ref() != maintenance {
+            self.mark_dirty();
+            self.ui_options.maintenance = maintenance.cloned();
+        }
+    }
+
+    pub fn set_admin(&mut self, is_admin: bool) {
+        self.is_admin = is_admin;
+    }
+
+    pub fn is_debug_overlay_open(&self) -> bool {
+        self.ui_options.debug_overlay.is_some()
+    }
+
+    pub fn set_debug_stats(&mut self, stats: DebugStats) {
+        if self.is_debug_overlay_open() {
+            self.ui_options.debug_overlay = Some(stats);
+        }
+    }
+
+    /// Bytes of the last frame sent to the client.
+    pub fn last_frame_bytes(&self) -> usize {
+        self.connection_stats
+            .lock()
+            .map(|stats| stats.last_frame_bytes)
+            .unwrap_or_default()
+    }
+
+    /// Asks for a new frame at the next pass of the render loop.
+    pub fn mark_dirty(&mut self) {
+        self.frame_schedule.mark_dirty();
+    }
+
+    /// Decodes the raw data sent by the client terminal into events.
+    pub fn decode_input(&mut self, data: &[u8]) -> Vec<Event> {
+        self.input_decoder.feed(data)
+    }
+
     pub fn draw(
         &mut self,
         market: &Market,
         agent: &UserAgent,
         number_of_players: usize,
     ) -> AppResult<()> {
+        let now = Instant::now();
+        if !self
+            .frame_schedule
+            .is_due(market.revision, self.ui_options.is_animating(), now)
+        {
+            return Ok(());
+        }
+        if let Ok(stats) = self.connection_stats.lock() {
+            self.ui_options.connection_stats = *stats;
+        }
+        // In the sandbox, the private copy is shown instead of the real market.
+        let mut sandbox = self.sandbox.take();
+        let result = match sandbox.as_mut() {
+            Some(sandbox) => {
+                sandbox
+                    .sync(market.last_tick)
+                    .unwrap_or_else(|e| debug!("Sandbox action failed: {}", e));
+                self.draw_frame(&sandbox.market, &sandbox.agent, number_of_players)
+            }
+            None => self.draw_frame(market, agent, number_of_players),
+        };
+        self.sandbox = sandbox;
+        if result? {
+            self.frame_schedule.record_frame(market.revision, now);
+        }
+        Ok(())
+    }
+
+    /// Draws the frame, returning whether it was drawn or skipped to stay within the bandwidth.
+    fn draw_frame(
+        &mut self,
+        market: &Market,
+        agent: &UserAgent,
+        number_of_players: usize,
+    ) -> AppResult<bool> {
+        if agent.text_mode() {
+            self.update_announcement(market, agent);
+        }
+        self.update_notifications(market, agent);
+        if !self.reserve_frame() {
+            return Ok(false);
+        }
+        // Copy mode is drawn once and then left alone, so that redraws do not clear the selection.
+        if self.ui_options.is_copy_mode() {
+            if self.copy_mode_drawn {
+                return Ok(true);
+            }
+            self.copy_mode_drawn = true;
+        } else {
+            self.copy_mode_drawn = false;
+        }
         self.tui
             .draw(market, agent, &self.ui_options, number_of_players)?;
-        Ok(())
+        Ok(true)
+    }
+
+    pub fn set_term(&mut self, term: &str) {
+        self.term = term.to_string();
+        self.update_color_support();
+    }
+
+    pub fn set_colorterm(&mut self, colorterm: &str) {
+        self.colorterm = Some(colorterm.to_string());
+        self.update_color_support();
+    }
+
+    /// Checks the bandwidth budget before a frame, returning whether it can be drawn.
+    /// Throttled clients get shorter color codes until they are back under the budget.
+    fn reserve_frame(&mut self) -> bool {
+        let Ok(mut budget) = self.bandwidth_budget.lock() else {
+            return true;
+        };
+        let allowed = budget.allows_frame(Instant::now());
+        self.tui.terminal.backend_mut().color_support = if budget.is_throttled() {
+            ColorSupport::Ansi256
+        } else {
+            self.color_support
+        };
+        allowed
+    }
+
+    fn update_color_support(&mut self) {
+        let color_support = ColorSupport::detect(&self.term, self.colorterm.as_deref());
+        debug!(
+            "Client {} color support: {:?}",
+            self.username, color_support
+        );
+        self.color_support = color_support;
+        self.tui.terminal.backend_mut().color_support = color_support;
+
+        let graphics = GraphicsProtocol::detect(&self.term);
+        debug!("Client {} graphics protocol: {:?}", self.username, graphics);
+        self.tui.terminal.backend_mut().graphics = graphics;
+        self.ui_options.graphics = graphics;
+    }
+
+    // Describes the most relevant state change since the last announcement.
+    fn update_announcement(&mut self, market: &Market, agent: &UserAgent) {
+        let is_day = matches!(market.phase, GamePhase::Day { .. });
+        let state = (is_day, agent.cash(), agent.assassination_threats().len());
+        let Some((was_day, cash, threats)) = self.announced_state else {
+            self.announced_state = Some(state);
+            self.ui_options.announcement = Some("Welcome to stonks.".to_string());
+            return;
+        };
+
+        let announcement = if was_day != is_day {
+            if is_day {
+                "The market is open.".to_string()
+            } else {
+                format!(
+                    "Night has fallen. {} events available.",
+                    agent.available_night_events().len()
+                )
+            }
+        } else if threats < state.2 {
+            "Warning: a character assassination is targeting you.".to_string()
+        } else if cash != state.1 {
+            format!("Cash is now {}.", agent.cash().format_exact_dollars())
+        } else {
+            return;
+        };
+
+        self.announced_state = Some(state);
+        self.ui_options.announcement = Some(announcement);
+    }
+
+    // Feeds the status bar with the current state and the trades executed since the last frame.
+    fn update_notifications(&mut self, market: &Market, agent: &UserAgent) {
+        let now = Instant::now();
+        let notifications = &mut self.ui_options.notifications;
+        notifications.remove_expired(now);
+
+        if self.market_halted {
+            notifications.set(
+                "halted",
+                NotificationPriority::Critical,
+                "Market halted".to_string(),
+            );
+        } else {
+            notifications.clear("halted");
+        }
+
+        let holdings = (agent.cash(), *agent.owned_stonks());
+        if let Some((cash, owned_stonks)) = self.notified_holdings {
+            let traded = market
+                .stonks
+                .iter()
+                .filter(|stonk| owned_stonks[stonk.id] != holdings.1[stonk.id])
+                .collect::<Vec<_>>();
+            let text = match traded.as_slice() {
+                [] => None,
+                [stonk] => {
+                    let (verb, amount, value) = if holdings.1[stonk.id] > owned_stonks[stonk.id] {
+                        (
+                            "Bought",
+                            holdings.1[stonk.id] - owned_stonks[stonk.id],
+                            cash.saturating_sub(holdings.0),
+                        )
+                    } else {
+                        (
+                            "Sold",
+                            owned_stonks[stonk.id] - holdings.1[stonk.id],
+                            holdings.0.saturating_sub(cash),
+                        )
+                    };
+                    Some(format!(
+                        "{} {} {} at {}",
+                        verb,
+                        amount,
+                        stonk.name,
+                        Money::from_cents(value.cents() / amount as u64).format_dollars()
+                    ))
+                }
+                _ => Some(format!(
+                    "{} trades executed, cash is now {}",
+                    traded.len(),
+                    holdings.0.format_dollars()
+                )),
+            };
+            if let Some(text) = text {
+                notifications.push(
+                    "trade",
+                    NotificationPriority::High,
+                    text,
+                    Duration::from_secs(TRADE_NOTIFICATION_SECONDS),
+                    now,
+                );
+            }
+        }
+        self.notified_holdings = Some(holdings);
+
+        match agent.selected_actions().as_slice() {
+            [] => notifications.clear("queued"),
+            [action] => notifications.set(
+                "queued",
+                NotificationPriority::Normal,
+                format!("Pending action queued: {}", action),
+            ),
+            actions => notifications.set(
+                "queued",
+                NotificationPriority::Normal,
+                format!("{} pending actions queued", actions.len()),
+            ),
+        }
+
+        if market.hostile_intel.is_empty() || !market.has_media_contacts(agent) {
+            notifications.clear("intel");
+        } else {
+            notifications.set(
+                "intel",
+                NotificationPriority::High,
+                format!(
+                    "Media contacts report {} planned for dawn",
+                    market
+                        .hostile_intel
+                        .iter()
+                        .map(|intel| intel.description())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                ),
+            );
+        }
+
+        match market.scenario.headline(market.phase.cycle()) {
+            Some(headline) => notifications.set(
+                "scenario",
+                NotificationPriority::Normal,
+                headline.to_string(),
+            ),
+            None => notifications.clear("scenario"),
+        }
+
+        match market.auction.results(market.phase.cycle()) {
+            Some(results) if !results.is_empty() => notifications.set(
+                "auction",
+                NotificationPriority::Normal,
+                format!(
+                    "Opening auction: {}",
+                    results
+                        .iter()
+                        .map(|result| format!(
+                            "{} {} x{}",
+                            market.stonks[result.stonk_id].short_name,
+                            result.price.format_dollars(),
+                            result.volume
+                        ))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                ),
+            ),
+            _ => notifications.clear("auction"),
+        }
+
+        let (text, remaining_ticks) = match market.phase {
+            GamePhase::Day { counter, .. } => ("Market closes in", DAY_LENGTH - counter),
+            GamePhase::Night { counter, .. } => ("Night ends in", NIGHT_LENGTH - counter),
+        };
+        let seconds = (self.tick_interval * remaining_ticks as u32).as_secs();
+        notifications.set(
+            "phase",
+            NotificationPriority::Low,
+            format!("{} {}:{:02}", text, seconds / 60, seconds % 60),
+        );
     }
 
     pub fn username(&self) -> &str {
         &self.username
     }
 
+    /// Whether the SSH session of the client has ended without the channel being closed.
+    pub fn is_connection_closed(&self) -> bool {
+        self.connection_stats
+            .lock()
+            .map(|stats| stats.closed)
+            .unwrap_or(false)
+    }
+
+    pub fn set_username(&mut self, username: String) {
+        self.username = username;
+    }
+
+    /// Returns the username and password confirmed in the account dialog, if any.
+    /// The password is empty when changing username.
+    pub fn take_account_request(&mut self) -> Option<(String, String)> {
+        let Some(Modal::Account(dialog)) = self.ui_options.modals.top_mut() else {
+            return None;
+        };
+        if !dialog.submitted {
+            return None;
+        }
+        dialog.submitted = false;
+        Some((dialog.username.clone(), dialog.password.clone()))
+    }
+
+    /// Closes the account dialog on success, or shows why the account could not be created.
+    pub fn resolve_account_request(&mut self, result: AppResult<()>) {
+        match result {
+            Ok(()) => {
+                self.ui_options.modals.pop();
+            }
+            Err(e) => {
+                if let Some(Modal::Account(dialog)) = self.ui_options.modals.top_mut() {
+                    dialog.error = Some(e.to_string());
+                }
+            }
+        }
+    }
+
+    fn handle_account_dialog(&mut self, key_event: KeyEvent) {
+        let Some(Modal::Account(dialog)) = self.ui_options.modals.top_mut() else {
+            return;
+        };
+        let field = if dialog.editing_password {
+            &mut dialog.password
+        } else {
+            &mut dialog.username
+        };
+        match key_event.code {
+            KeyCode::Char(c) if field.chars().count() < MAX_ACCOUNT_FIELD_LENGTH => {
+                if key_event.modifiers == KeyModifiers::SHIFT {
+                    field.extend(c.to_uppercase());
+                } else {
+                    field.push(c);
+                }
+            }
+            KeyCode::Backspace if field.is_empty() => {
+                self.ui_options.modals.pop();
+            }
+            KeyCode::Backspace => {
+                field.pop();
+            }
+            KeyCode::Tab | KeyCode::BackTab | KeyCode::Up | KeyCode::Down if !dialog.rename => {
+                dialog.editing_password = !dialog.editing_password;
+            }
+            KeyCode::Enter if dialog.rename && !dialog.username.is_empty() => {
+                dialog.error = None;
+                dialog.submitted = true;
+            }
+            KeyCode::Enter if dialog.rename => {}
+            KeyCode::Enter => {
+                if !dialog.editing_password {
+                    dialog.editing_password = true;
+                } else if !dialog.username.is_empty() && !dialog.password.is_empty() {
+                    dialog.error = None;
+                    dialog.submitted = true;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_api_tokens_screen(&mut self, key_event: KeyEvent, agent: &mut UserAgent) {
+        let Some(Modal::ApiTokens(screen)) = self.ui_options.modals.top_mut() else {
+            return;
+        };
+        let number_of_tokens = agent.api_tokens().len();
+        match key_event.code {
+            KeyCode::Char('n') => match agent.generate_api_token() {
+                Ok(token) => {
+                    screen.new_token = Some(token);
+                    screen.error = None;
+                    screen.selected = number_of_tokens;
+                }
+                Err(e) => screen.error = Some(e.to_string()),
+            },
+            KeyCode::Char('d') | KeyCode::Delete => {
+                if let Some(token) = agent.api_tokens().get(screen.selected) {
+                    let id = token.id.clone();
+                    agent.revoke_api_token(&id);
+                    screen.new_token = None;
+                    screen.error = None;
+                    screen.selected = screen.selected.min(number_of_tokens.saturating_sub(2));
+                }
+            }
+            KeyCode::Up => screen.selected = screen.selected.saturating_sub(1),
+            KeyCode::Down => {
+                screen.selected = (screen.selected + 1).min(number_of_tokens.saturating_sub(1))
+            }
+            KeyCode::Backspace | KeyCode::Char('k') => {
+                self.ui_options.modals.pop();
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_two_factor_screen(&mut self, key_event: KeyEvent, agent: &mut UserAgent) {
+        let Some(Modal::TwoFactor(screen)) = self.ui_options.modals.top_mut() else {
+            return;
+        };
+        match key_event.code {
+            KeyCode::Char(c) if c.is_ascii_digit() && screen.code.len() < TOTP_DIGITS => {
+                screen.code.push(c);
+            }
+            KeyCode::Backspace if screen.code.is_empty() => {
+                self.ui_options.modals.pop();
+            }
+            KeyCode::Backspace => {
+                screen.code.pop();
+            }
+            KeyCode::Enter => {
+                // A pending secret is turned on with one of its codes, the current one is
+                // turned off with one of its codes.
+                let secret = screen
+                    .secret
+                    .as_deref()
+                    .or(agent.totp_secret())
+                    .map(|secret| secret.to_vec());
+                let Some(secret) = secret else {
+                    self.ui_options.modals.pop();
+                    return;
+                };
+                if !totp::verify(&secret, &screen.code) {
+                    screen.code.clear();
+                    screen.error = Some(
+                        "Wrong code, check that the clock of your device is right".to_string(),
+                    );
+                    return;
+                }
+                let turn_on = screen.secret.is_some();
+                self.ui_options.modals.pop();
+                agent.set_totp_secret(turn_on.then_some(secret));
+                self.ui_options.notifications.set(
+                    "two-factor",
+                    NotificationPriority::Normal,
+                    format!(
+                        "Two-factor authentication is {}",
+                        if turn_on { "on" } else { "off" }
+                    ),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    /// Asks for a code of the authenticator app before the game starts.
+    pub fn require_login_code(&mut self) {
+        self.ui_options.login_code = Some(LoginCodePrompt::default());
+    }
+
+    /// Whether the player failed to enter a valid login code too many times.
+    pub fn is_locked_out(&self) -> bool {
+        self.ui_options
+            .login_code
+            .as_ref()
+            .is_some_and(|prompt| prompt.attempts >= MAX_TOTP_ATTEMPTS)
+    }
+
+    fn handle_login_code(&mut self, key_event: KeyEvent, agent: &UserAgent) {
+        let Some(prompt) = self.ui_options.login_code.as_mut() else {
+            return;
+        };
+        match key_event.code {
+            KeyCode::Char(c) if c.is_ascii_digit() && prompt.code.len() < TOTP_DIGITS => {
+                prompt.code.push(c);
+            }
+            KeyCode::Backspace => {
+                prompt.code.pop();
+            }
+            KeyCode::Enter => {
+                if agent
+                    .totp_secret()
+                    .is_some_and(|secret| totp::verify(secret, &prompt.code))
+                {
+                    self.ui_options.login_code = None;
+                    return;
+                }
+                prompt.attempts += 1;
+                prompt.code.clear();
+                let attempts_left = MAX_TOTP_ATTEMPTS.saturating_sub(prompt.attempts);
+                prompt.error = Some(format!(
+                    "Wrong code, {} attempt{} left",
+                    attempts_left,
+                    if attempts_left == 1 { "" } else { "s" }
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether the key quits the game from the menu. Quitting is handled by the server,
+    /// like the escape key.
+    pub fn is_quit_key(&self, key_event: &KeyEvent) -> bool {
+        key_event.code == KeyCode::Enter
+            && matches!(
+                self.ui_options.modals.top(),
+                Some(Modal::Menu { selected }) if MenuItem::ALL[*selected] == MenuItem::Quit
+            )
+    }
+
+    fn handle_menu(&mut self, key_event: KeyEvent, agent: &UserAgent) {
+        let Some(Modal::Menu { selected }) = self.ui_options.modals.top_mut() else {
+            return;
+        };
+        let entries = MenuItem::ALL.len();
+        match key_event.code {
+            KeyCode::Up => *selected = (*selected + entries - 1) % entries,
+            KeyCode::Down => *selected = (*selected + 1) % entries,
+            KeyCode::Enter => {
+                let item = MenuItem::ALL[*selected];
+                self.ui_options.modals.pop();
+                match item {
+                    MenuItem::Play => self.ui_options.set_display(UiDisplay::Stonks),
+                    MenuItem::Leaderboard => self.ui_options.set_display(UiDisplay::History),
+                    MenuItem::Profile => {
+                        self.ui_options.set_display(UiDisplay::Portfolio);
+                        self.ui_options.modals.push(Modal::Account(AccountDialog {
+                            rename: !agent.is_guest(),
+                            ..Default::default()
+                        }));
+                    }
+                    MenuItem::Settings => self.ui_options.set_display(UiDisplay::Settings),
+                    MenuItem::Changelog => self.ui_options.set_display(UiDisplay::Changelog),
+                    // Handled by the server, see is_quit_key.
+                    MenuItem::Quit => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Opens the What's new page over the menu if the game changed since the agent last played,
+    /// and marks the current version as seen.
+    pub fn show_whats_new(&mut self, agent: &mut UserAgent) {
+        let version = env!("CARGO_PKG_VERSION");
+        if agent.last_seen_version() == Some(version) {
+            return;
+        }
+        let since = agent.last_seen_version().map(String::from);
+        agent.set_last_seen_version(version);
+        if !whats_new(since.as_deref()).is_empty() {
+            self.ui_options.modals.push(Modal::WhatsNew { since });
+        }
+    }
+
+    fn handle_vacation_prompt(&mut self, key_event: KeyEvent, agent: &mut UserAgent) {
+        let Some(Modal::Vacation { days }) = self.ui_options.modals.top_mut() else {
+            return;
+        };
+        match key_event.code {
+            KeyCode::Left => *days = (*days - 1).max(1),
+            KeyCode::Right => *days = (*days + 1).min(MAX_VACATION_DAYS),
+            KeyCode::Enter => {
+                agent.select_action(AgentAction::StartVacation { days: *days });
+                self.ui_options.modals.pop();
+            }
+            KeyCode::Backspace => {
+                self.ui_options.modals.pop();
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_currency_prompt(&mut self, key_event: KeyEvent, agent: &mut UserAgent) {
+        let Some(Modal::Currency {
+            to_offshore,
+            amount,
+        }) = self.ui_options.modals.top_mut()
+        else {
+            return;
+        };
+        let balance = if *to_offshore {
+            agent.cash()
+        } else {
+            agent.offshore_cash()
+        };
+        match key_event.code {
+            KeyCode::Left => *amount = amount.saturating_sub(CURRENCY_EXCHANGE_STEP),
+            KeyCode::Right => *amount = amount.saturating_add(CURRENCY_EXCHANGE_STEP).min(balance),
+            KeyCode::Up | KeyCode::Down => {
+                *to_offshore = !*to_offshore;
+                *amount = Money::ZERO;
+            }
+            KeyCode::Enter => {
+                if *amount > Money::ZERO {
+                    agent.select_action(AgentAction::ExchangeCurrency {
+                        to_offshore: *to_offshore,
+                        amount: *amount,
+                    });
+                }
+                self.ui_options.modals.pop();
+            }
+            KeyCode::Backspace => {
+                self.ui_options.modals.pop();
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_futures_prompt(
+        &mut self,
+        key_event: KeyEvent,
+        market: &Market,
+        agent: &mut UserAgent,
+    ) {
+        let Some(Modal::Futures {
+            stonk_id,
+            amount,
+            is_long,
+        }) = self.ui_options.modals.top_mut()
+        else {
+            return;
+        };
+        let max_amount = market
+            .futures
+            .max_open_amount(agent.username(), &market.stonks[*stonk_id]);
+        match key_event.code {
+            KeyCode::Left => *amount = amount.saturating_sub(FUTURES_AMOUNT_STEP).max(1),
+            KeyCode::Right => {
+                *amount = amount
+                    .saturating_add(FUTURES_AMOUNT_STEP)
+                    .min(max_amount)
+                    .max(1)
+            }
+            KeyCode::Up | KeyCode::Down => *is_long = !*is_long,
+            KeyCode::Enter => {
+                agent.select_action(AgentAction::OpenFutures {
+                    stonk_id: *stonk_id,
+                    amount: *amount,
+                    is_long: *is_long,
+                });
+                self.ui_options.modals.pop();
+            }
+            KeyCode::Backspace => {
+                self.ui_options.modals.pop();
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_board_prompt(&mut self, key_event: KeyEvent, agent: &mut UserAgent) {
+        let Some(Modal::Board { stonk_id, selected }) = self.ui_options.modals.top_mut() else {
+            return;
+        };
+        let decisions = BoardDecision::iter().collect::<Vec<_>>();
+        match key_event.code {
+            KeyCode::Up => *selected = (*selected + decisions.len() - 1) % decisions.len(),
+            KeyCode::Down => *selected = (*selected + 1) % decisions.len(),
+            KeyCode::Enter => {
+                agent.select_action(AgentAction::BoardDecision {
+                    stonk_id: *stonk_id,
+                    decision: decisions[*selected],
+                });
+                self.ui_options.modals.pop();
+            }
+            KeyCode::Backspace => {
+                self.ui_options.modals.pop();
+            }
+            _ => {}
+        }
+    }
+
     pub fn render_counter(&self) -> usize {
         self.ui_options.render_counter
     }
@@ -123,6 +1077,172 @@ impl Client {
     pub fn clear_event_card(&mut self) {
         self.ui_options.render_counter = 0;
         self.ui_options.selected_event_card_index = 0;
+        self.ui_options.start_animation();
+    }
+
+    fn buy_action(&self, market: &Market, stonk_id: usize, amount: u32) -> AgentAction {
+        if self.ui_options.tournament_mode {
+            AgentAction::TournamentBuy { stonk_id, amount }
+        } else if market.auction_is_open() {
+            AgentAction::AuctionBuy { stonk_id, amount }
+        } else if self.ui_options.venue == Venue::DarkPool {
+            AgentAction::DarkPoolBuy { stonk_id, amount }
+        } else if self.ui_options.venue == Venue::Offshore {
+            AgentAction::OffshoreBuy { stonk_id, amount }
+        } else {
+            AgentAction::Buy { stonk_id, amount }
+        }
+    }
+
+    fn sell_action(&self, market: &Market, stonk_id: usize, amount: u32) -> AgentAction {
+        if self.ui_options.tournament_mode {
+            AgentAction::TournamentSell { stonk_id, amount }
+        } else if market.auction_is_open() {
+            AgentAction::AuctionSell { stonk_id, amount }
+        } else if self.ui_options.venue == Venue::DarkPool {
+            AgentAction::DarkPoolSell { stonk_id, amount }
+        } else if self.ui_options.venue == Venue::Offshore {
+            AgentAction::OffshoreSell { stonk_id, amount }
+        } else {
+            AgentAction::Sell { stonk_id, amount }
+        }
+    }
+
+    fn max_buy_amount(&self, market: &Market, stonk: &Stonk, agent: &UserAgent) -> u32 {
+        match agent.tournament_account() {
+            Some(account) if self.ui_options.tournament_mode => stonk.max_buy_amount(account.cash),
+            _ if market.auction_is_open() => market.auction_max_buy_amount(stonk.id, agent),
+            _ if self.ui_options.venue == Venue::Offshore => market.offshore.max_buy_amount(
+                stonk,
+                agent.offshore_cash(),
+                market.trade_fee_rate(agent, Venue::Offshore),
+            ),
+            _ => max_buy_amount_with_fee(
+                stonk,
+                agent.cash(),
+                market.trade_fee_rate(agent, self.ui_options.venue),
+            )
+            .min(stonk.max_trade_amount()),
+        }
+    }
+
+    fn max_sell_amount(&self, market: &Market, stonk: &Stonk, agent: &UserAgent) -> u32 {
+        match agent.tournament_account() {
+            Some(account) if self.ui_options.tournament_mode => account.owned_stonks[stonk.id],
+            _ if market.auction_is_open() => market.auction_max_sell_amount(stonk.id, agent),
+            _ if self.ui_options.venue == Venue::Offshore => {
+                agent.owned_stonks()[stonk.id].min(market.offshore.max_trade_amount(stonk))
+            }
+            _ => stonk
+                .max_sell_amount(agent.owned_stonks()[stonk.id])
+                .min(stonk.max_trade_amount()),
+        }
+    }
+
+    fn handle_assassination_defense(
+        &mut self,
+        key_event: KeyEvent,
+        agent: &mut UserAgent,
+    ) -> AppResult<()> {
+        let num_defenses = ASSASSINATION_DEFENSES.len();
+        match key_event.code {
+            KeyCode::Up => {
+                self.ui_options.selected_defense_index =
+                    (self.ui_options.selected_defense_index + num_defenses - 1) % num_defenses;
+            }
+            KeyCode::Down => {
+                self.ui_options.selected_defense_index =
+                    (self.ui_options.selected_defense_index + 1) % num_defenses;
+            }
+            KeyCode::Enter => {
+                let already_selected = agent
+                    .selected_actions()
+                    .iter()
+                    .any(|action| ASSASSINATION_DEFENSES.contains(action));
+                if !already_selected {
+                    agent.select_action(
+                        ASSASSINATION_DEFENSES[self.ui_options.selected_defense_index].clone(),
+                    );
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Starts recording the session for a bug report, or stops the running recording.
+    fn toggle_recording(&mut self, market: &Market, agent: &UserAgent) {
+        if self.recorder.is_some() {
+            self.stop_recording(format!(
+                "Session recording saved, download it over SFTP as {}",
+                RECORDING_SFTP_FILENAME
+            ));
+            return;
+        }
+
+        let (width, height) = self.tui.terminal.backend().size;
+        let start = RecordedEvent::Start {
+            username: agent.username().to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            width,
+            height,
+            text_mode: agent.text_mode(),
+            tick: market.last_tick,
+            screen: self.ui_options.screen_state(),
+        };
+        match recording_path(&self.username).and_then(|path| SessionRecorder::start(&path, start)) {
+            Ok(recorder) => {
+                self.recorder = Some(recorder);
+                self.ui_options.recording = true;
+                self.ui_options.notifications.set(
+                    "recording",
+                    NotificationPriority::Normal,
+                    "Recording your session, press `@` to stop".to_string(),
+                );
+            }
+            Err(e) => self.ui_options.notifications.push(
+                "recording",
+                NotificationPriority::High,
+                format!("Could not record the session: {}", e),
+                Duration::from_secs(RECORDING_NOTIFICATION_SECONDS),
+                Instant::now(),
+            ),
+        }
+    }
+
+    fn stop_recording(&mut self, message: String) {
+        self.recorder = None;
+        self.ui_options.recording = false;
+        self.ui_options.notifications.push(
+            "recording",
+            NotificationPriority::High,
+            message,
+            Duration::from_secs(RECORDING_NOTIFICATION_SECONDS),
+            Instant::now(),
+        );
+    }
+
+    // Writes to the running recording, if any. The recording stops at the first error,
+    // for example once it is full.
+    fn record(&mut self, write: impl FnOnce(&mut SessionRecorder) -> AppResult<()>) {
+        let Some(recorder) = self.recorder.as_mut() else {
+            return;
+        };
+        if let Err(e) = write(recorder) {
+            self.stop_recording(format!("Session recording stopped: {}", e));
+        }
+    }
+
+    pub fn record_resize(&mut self, width: u16, height: u16) {
+        self.record(|recorder| recorder.record_resize(width, height));
+    }
+
+    pub fn ui_options(&self) -> &UiOptions {
+        &self.ui_options
+    }
+
+    pub fn ui_options_mut(&mut self) -> &mut UiOptions {
+        &mut self.ui_options
     }
 
     pub fn handle_key_events(
@@ -130,23 +1250,221 @@ impl Client {
         key_event: KeyEvent,
         market: &Market,
         agent: &mut UserAgent,
+    ) -> AppResult<()> {
+        let secret = self.ui_options.is_entering_secret();
+        self.record(|recorder| recorder.record_key(key_event, secret));
+        let result = self.apply_key_event(key_event, market, agent);
+        let screen = self.ui_options.screen_state();
+        self.record(|recorder| recorder.record_screen(screen));
+        result
+    }
+
+    fn apply_key_event(
+        &mut self,
+        key_event: KeyEvent,
+        market: &Market,
+        agent: &mut UserAgent,
+    ) -> AppResult<()> {
+        // While warned about inactivity, a key press only keeps the session alive.
+        if self.ui_options.idle_countdown.take().is_some() {
+            return Ok(());
+        }
+
+        if self.ui_options.login_code.is_some() {
+            self.handle_login_code(key_event, agent);
+            return Ok(());
+        }
+
+        // Any key leaves copy mode.
+        if self.ui_options.is_copy_mode() {
+            self.ui_options.stop_copy_mode();
+            return Ok(());
+        }
+
+        // Hidden from the players, the debug overlay can be toggled from any screen.
+        if self.is_admin && key_event.code == KeyCode::F(DEBUG_OVERLAY_KEY) {
+            self.ui_options.debug_overlay = match self.ui_options.debug_overlay {
+                Some(_) => None,
+                None => Some(DebugStats::default()),
+            };
+            return Ok(());
+        }
+
+        // The game is frozen during a maintenance, only quitting is possible.
+        if self.ui_options.maintenance.is_some() {
+            return Ok(());
+        }
+
+        // The paper account only exists while a tournament round is running.
+        if agent.tournament_account().is_none() {
+            self.ui_options.tournament_mode = false;
+        }
+
+        // A pending character assassination forces a decision before anything else.
+        if matches!(market.phase, GamePhase::Day { .. })
+            && !agent.assassination_threats().is_empty()
+        {
+            return self.handle_assassination_defense(key_event, agent);
+        }
+
+        // Only the topmost modal receives the input.
+        match self.ui_options.modals.top() {
+            Some(Modal::Menu { .. }) => {
+                self.handle_menu(key_event, agent);
+                return Ok(());
+            }
+            Some(Modal::Account(_)) => {
+                self.handle_account_dialog(key_event);
+                return Ok(());
+            }
+            Some(Modal::ApiTokens(_)) => {
+                self.handle_api_tokens_screen(key_event, agent);
+                return Ok(());
+            }
+            Some(Modal::TwoFactor(_)) => {
+                self.handle_two_factor_screen(key_event, agent);
+                return Ok(());
+            }
+            Some(Modal::Vacation { .. }) => {
+                self.handle_vacation_prompt(key_event, agent);
+                return Ok(());
+            }
+            Some(Modal::Board { .. }) => {
+                self.handle_board_prompt(key_event, agent);
+                return Ok(());
+            }
+            Some(Modal::Currency { .. }) => {
+                self.handle_currency_prompt(key_event, agent);
+                return Ok(());
+            }
+            Some(Modal::Futures { .. }) => {
+                self.handle_futures_prompt(key_event, market, agent);
+                return Ok(());
+            }
+            Some(Modal::WhatsNew { .. }) => {
+                if matches!(key_event.code, KeyCode::Enter | KeyCode::Backspace) {
+                    self.ui_options.modals.pop();
+                }
+                return Ok(());
+            }
+            None => {}
+        }
+
+        if self
+            .ui_options
+            .handle_history_keys(key_event.code, &market.leaderboard_history)
+        {
+            return Ok(());
+        }
+
+        if self
+            .ui_options
+            .handle_manual_keys(key_event.code, manual_pages(market).len())
+        {
+            return Ok(());
+        }
+
+        if self.ui_options.handle_about_keys(key_event.code) {
+            return Ok(());
+        }
+
+        // Digits build up a count for the next key, a leading zero is ignored.
+        if let KeyCode::Char(c @ '0'..='9') = key_event.code {
+            let digit = c as u32 - '0' as u32;
+            if self.pending_count.is_some() || digit > 0 {
+                let count = self
+                    .pending_count
+                    .unwrap_or(0)
+                    .saturating_mul(10)
+                    .saturating_add(digit)
+                    .min(MAX_COUNT_PREFIX);
+                self.pending_count = Some(count);
+                let hint = if focus_digit(count).is_some() {
+                    format!("{} (`b`:buy `s`:sell `return`:focus)", count)
+                } else {
+                    format!("{} (`b`:buy `s`:sell)", count)
+                };
+                self.ui_options
+                    .notifications
+                    .set("count", NotificationPriority::High, hint);
+            }
+            return Ok(());
+        }
+        // Any other key uses the count, if it is a trade, or drops it.
+        let count = self.pending_count.take();
+        self.ui_options.notifications.clear("count");
+        // A single digit not followed by a trade focuses on that stonk, as `3` always did.
+        // Return only confirms the focus, any other key is then handled as usual.
+        if let Some(digit) = count.and_then(focus_digit) {
+            if !matches!(key_event.code, KeyCode::Char('b' | 's')) {
+                self.ui_options
+                    .handle_key_events(KeyCode::Char(digit), agent)?;
+                if key_event.code == KeyCode::Enter {
+                    return Ok(());
+                }
+            }
+        }
+
+        if key_event.code == KeyCode::Char('i') {
+            self.toggle_sandbox(market, agent);
+            return Ok(());
+        }
+
+        if let Some(mut sandbox) = self.sandbox.take() {
+            // Account, vacation, tournament, board, currency, futures and scheduled order keys
+            // only make sense for the real account.
+            let result = if matches!(
+                key_event.code,
+                KeyCode::Char('a' | 'e' | 'j' | 'k' | 'n' | 'o' | 'u' | 'v' | '$' | '>' | '#')
+            ) {
+                Ok(())
+            } else {
+                self.handle_game_keys(key_event, count, &sandbox.market, &mut sandbox.agent)
+            };
+            self.sandbox = Some(sandbox);
+            return result;
+        }
+
+        self.handle_game_keys(key_event, count, market, agent)
+    }
+
+    fn toggle_sandbox(&mut self, market: &Market, agent: &UserAgent) {
+        self.sandbox = match self.sandbox.take() {
+            Some(_) => None,
+            None => {
+                self.ui_options.tournament_mode = false;
+                Some(Sandbox::fork(market, agent))
+            }
+        };
+        self.ui_options.sandbox = self.sandbox.is_some();
+        // The holdings of the sandbox and of the real account must not be compared.
+        self.notified_holdings = None;
+    }
+
+    fn handle_game_keys(
+        &mut self,
+        key_event: KeyEvent,
+        count: Option<u32>,
+        market: &Market,
+        agent: &mut UserAgent,
     ) -> AppResult<()> {
         match key_event.code {
             crossterm::event::KeyCode::Enter => match market.phase {
                 GamePhase::Day { .. } => {
-                    if let Some(_) = self.ui_options.focus_on_stonk {
+                    if self.ui_options.focus_on_stonk.is_some() {
                         self.ui_options.reset();
                     } else {
                         self.ui_options.select_stonk();
                     }
                 }
                 GamePhase::Night { .. } => {
-                    if agent.selected_action().is_none() {
-                        let idx = self.ui_options.selected_event_card_index;
-                        if idx < agent.available_night_events().len() {
-                            let event = agent.available_night_events()[idx].clone();
-                            let action = event.action();
-                            agent.select_action(action);
+                    let idx = self.ui_options.selected_event_card_index;
+                    if !market.night_selection_locked()
+                        && idx < agent.available_night_events().len()
+                    {
+                        let event = agent.available_night_events()[idx].clone();
+                        if agent.can_select_night_event(&event) {
+                            agent.select_action(event.action());
                         }
                     }
                 }
@@ -157,8 +1475,18 @@ impl Client {
                     self.ui_options.reset();
                 }
                 GamePhase::Night { .. } => {
-                    if agent.selected_action().is_some() {
-                        agent.clear_action();
+                    if market.night_selection_locked() {
+                        return Ok(());
+                    }
+                    // Unselects the focused card if selected, every card otherwise.
+                    let action = agent
+                        .available_night_events()
+                        .get(self.ui_options.selected_event_card_index)
+                        .map(|event| event.action())
+                        .filter(|action| agent.selected_actions().contains(action));
+                    match action {
+                        Some(action) => agent.unselect_action(&action),
+                        None => agent.clear_actions(),
                     }
                 }
             },
@@ -170,17 +1498,19 @@ impl Client {
                     self.ui_options.selected_stonk_index
                 };
 
-                let stonk = &market.stonks[stonk_id];
-                let max_buy_amount = stonk.max_buy_amount(agent.cash());
+                let max_buy_amount = self.max_buy_amount(market, &market.stonks[stonk_id], agent);
 
-                let amount = if key_event.modifiers == KeyModifiers::SHIFT {
-                    100
-                } else {
-                    1
-                }
-                .min(max_buy_amount);
+                let amount = count
+                    .unwrap_or(if key_event.modifiers == KeyModifiers::SHIFT {
+                        100
+                    } else {
+                        1
+                    })
+                    .min(max_buy_amount);
 
-                agent.select_action(AgentAction::Buy { stonk_id, amount })
+                if amount > 0 {
+                    agent.select_action(self.buy_action(market, stonk_id, amount))
+                }
             }
 
             KeyCode::Char('m') => {
@@ -189,12 +1519,10 @@ impl Client {
                 } else {
                     self.ui_options.selected_stonk_index
                 };
-                let stonk = &market.stonks[stonk_id];
-                let max_buy_amount = stonk.max_buy_amount(agent.cash());
-                agent.select_action(AgentAction::Buy {
-                    stonk_id,
-                    amount: max_buy_amount,
-                })
+                let max_buy_amount = self.max_buy_amount(market, &market.stonks[stonk_id], agent);
+                if max_buy_amount > 0 {
+                    agent.select_action(self.buy_action(market, stonk_id, max_buy_amount))
+                }
             }
 
             KeyCode::Char('s') => {
@@ -203,12 +1531,144 @@ impl Client {
                 } else {
                     self.ui_options.selected_stonk_index
                 };
-                let amount = if key_event.modifiers == KeyModifiers::SHIFT {
-                    100
+                let max_sell_amount = self.max_sell_amount(market, &market.stonks[stonk_id], agent);
+                let amount = count
+                    .unwrap_or(if key_event.modifiers == KeyModifiers::SHIFT {
+                        100
+                    } else {
+                        1
+                    })
+                    .min(max_sell_amount);
+                if amount > 0 {
+                    agent.select_action(self.sell_action(market, stonk_id, amount))
+                }
+            }
+
+            KeyCode::Char('r') => agent.toggle_reduced_motion(),
+
+            KeyCode::Char('g') => agent.toggle_text_mode(),
+
+            KeyCode::Char('y') => self.ui_options.start_copy_mode(),
+
+            KeyCode::Char('@') => self.toggle_recording(market, agent),
+
+            KeyCode::Char('j') => agent.select_action(AgentAction::JoinTournament),
+
+            KeyCode::Char('e') => {
+                self.ui_options.tournament_mode =
+                    !self.ui_options.tournament_mode && agent.tournament_account().is_some();
+            }
+
+            KeyCode::Char('k') => {
+                if !agent.is_guest() {
+                    self.ui_options
+                        .modals
+                        .push(Modal::ApiTokens(ApiTokensScreen::default()));
+                }
+            }
+
+            KeyCode::Char('#') => {
+                if !agent.is_guest() {
+                    let secret = agent.totp_secret().is_none().then(totp::generate_secret);
+                    self.ui_options
+                        .modals
+                        .push(Modal::TwoFactor(TwoFactorScreen {
+                            secret,
+                            ..Default::default()
+                        }));
+                }
+            }
+
+            KeyCode::Char('u') => {
+                self.ui_options.modals.push(Modal::Account(AccountDialog {
+                    rename: !agent.is_guest(),
+                    ..Default::default()
+                }));
+            }
+
+            KeyCode::Char('>') => {
+                if let Some(stonk_id) = self.ui_options.focus_on_stonk {
+                    match FuturesBook::check_open(market.phase, &market.stonks[stonk_id]) {
+                        Ok(()) => self.ui_options.modals.push(Modal::Futures {
+                            stonk_id,
+                            amount: FUTURES_AMOUNT_STEP,
+                            is_long: true,
+                        }),
+                        Err(e) => self.ui_options.notifications.set(
+                            "futures",
+                            NotificationPriority::Normal,
+                            e,
+                        ),
+                    }
+                }
+            }
+
+            KeyCode::Char('$') => {
+                self.ui_options.modals.push(Modal::Currency {
+                    to_offshore: true,
+                    amount: CURRENCY_EXCHANGE_STEP.min(agent.cash()),
+                });
+            }
+
+            KeyCode::Char('v') => {
+                if matches!(market.phase, GamePhase::Day { .. })
+                    && !agent.has_condition(AgentCondition::Vacation)
+                {
+                    self.ui_options.modals.push(Modal::Vacation { days: 1 });
+                }
+            }
+
+            KeyCode::Char('n') => {
+                if let Some(stonk_id) = self.ui_options.focus_on_stonk {
+                    let stonk = &market.stonks[stonk_id];
+                    if matches!(market.phase, GamePhase::Day { .. })
+                        && Boards::controller(stonk) == Some(agent.username())
+                    {
+                        self.ui_options.modals.push(Modal::Board {
+                            stonk_id,
+                            selected: 0,
+                        });
+                    } else {
+                        self.ui_options.notifications.set(
+                            "board",
+                            NotificationPriority::Normal,
+                            format!(
+                                "Own more than {:.0}% of {} to chair its board",
+                                BOARD_CONTROL_STAKE * 100.0,
+                                stonk.name
+                            ),
+                        );
+                    }
+                }
+            }
+
+            KeyCode::Char('o') => {
+                let order = match agent.scheduled_order() {
+                    Some(ScheduledOrder::SellAllOnDrawdown { .. }) => None,
+                    _ => Some(ScheduledOrder::SellAllOnDrawdown {
+                        reference_value: market.net_worth(agent),
+                    }),
+                };
+                agent.set_scheduled_order(order);
+            }
+
+            KeyCode::Char('a') => {
+                let stonk_id = if let Some(stonk_id) = self.ui_options.focus_on_stonk {
+                    stonk_id
                 } else {
-                    1
+                    self.ui_options.selected_stonk_index
+                };
+                let order = match agent.scheduled_order() {
+                    Some(ScheduledOrder::BuyAtOpen {
+                        stonk_id: scheduled_id,
+                        ..
+                    }) if *scheduled_id == stonk_id => None,
+                    _ => Some(ScheduledOrder::BuyAtOpen {
+                        stonk_id,
+                        amount: market.stonks[stonk_id].max_buy_amount(agent.cash()),
+                    }),
                 };
-                agent.select_action(AgentAction::Sell { stonk_id, amount })
+                agent.set_scheduled_order(order);
             }
 
             KeyCode::Char('d') => {
@@ -217,8 +1677,10 @@ impl Client {
                 } else {
                     self.ui_options.selected_stonk_index
                 };
-                let amount = agent.owned_stonks()[stonk_id];
-                agent.select_action(AgentAction::Sell { stonk_id, amount })
+                let amount = self.max_sell_amount(market, &market.stonks[stonk_id], agent);
+                if amount > 0 {
+                    agent.select_action(self.sell_action(market, stonk_id, amount))
+                }
             }
 
             key_code => {
@@ -229,22 +1691,31 @@ impl Client {
     }
 }
 
+/// The digit key focusing on a stonk, if the count is a single digit naming one.
+fn focus_digit(count: u32) -> Option<char> {
+    if (1..=NUMBER_OF_STONKS as u32).contains(&count) {
+        char::from_digit(count, 10)
+    } else {
+        None
+    }
+}
+
 pub type Password = [u8; 32];
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SessionAuth {
     pub username: String,
     pub hashed_password: Password,
+    // Persisted, so that inactive agents can be dropped after a restart.
     pub last_active_time: SystemTime,
+    // Monotonic counterpart of last_active_time, unaffected by system clock changes.
+    #[serde(skip)]
+    last_active_instant: Option<Instant>,
 }
 
 impl Default for SessionAuth {
     fn default() -> Self {
-        Self {
-            username: "".to_string(),
-            hashed_password: [0; 32],
-            last_active_time: SystemTime::now(),
-        }
+        Self::new("".to_string(), [0; 32])
     }
 }
 
@@ -254,14 +1725,177 @@ impl SessionAuth {
             username,
             hashed_password,
             last_active_time: SystemTime::now(),
+            last_active_instant: Some(Instant::now()),
         }
     }
 
     pub fn update_last_active_time(&mut self) {
         self.last_active_time = SystemTime::now();
+        self.last_active_instant = Some(Instant::now());
+    }
+
+    /// Time since the last activity. Agents loaded from the store fall back to the
+    /// persisted wall clock time, counting as just active if the clock went backwards.
+    pub fn idle_duration(&self) -> Duration {
+        match self.last_active_instant {
+            Some(instant) => instant.elapsed(),
+            None => SystemTime::now()
+                .duration_since(self.last_active_time)
+                .unwrap_or_default(),
+        }
     }
 
     pub fn check_password(&self, password: Password) -> bool {
         self.hashed_password == password
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BandwidthBudget, Client, ConnectionStats, FrameSchedule, SessionAuth, FRAME_REFRESH_MILLIS,
+    };
+    use crate::{
+        agent::{AgentAction, DecisionAgent, UserAgent},
+        market::Market,
+        utils::AppResult,
+    };
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+    use std::time::{Duration, Instant, SystemTime};
+
+    #[test]
+    fn test_count_prefix_and_focus_keys() -> AppResult<()> {
+        let market = Market::new();
+        let mut agent = UserAgent::new(SessionAuth::new("counter".to_string(), [0; 32]));
+        let mut client = Client::headless("counter".to_string())?;
+        client.ui_options.modals.pop();
+        let press = |client: &mut Client, agent: &mut UserAgent, keys: &[KeyCode]| {
+            for key in keys {
+                client.handle_key_events(
+                    KeyEvent::new(*key, KeyModifiers::NONE),
+                    &market,
+                    agent,
+                )?;
+            }
+            AppResult::Ok(())
+        };
+
+        // A single digit confirmed with return focuses on that stonk.
+        press(
+            &mut client,
+            &mut agent,
+            &[KeyCode::Char('3'), KeyCode::Enter],
+        )?;
+        assert_eq!(client.ui_options.focus_on_stonk, Some(2));
+
+        // A count followed by a trade key trades on the focused stonk and keeps the focus.
+        press(
+            &mut client,
+            &mut agent,
+            &[KeyCode::Char('2'), KeyCode::Char('b')],
+        )?;
+        assert_eq!(client.ui_options.focus_on_stonk, Some(2));
+        assert_eq!(
+            agent.selected_actions(),
+            &vec![AgentAction::Buy {
+                stonk_id: 2,
+                amount: 2
+            }]
+        );
+
+        // Any other key focuses too and is then handled as usual.
+        press(
+            &mut client,
+            &mut agent,
+            &[KeyCode::Char('1'), KeyCode::Char('z')],
+        )?;
+        assert_eq!(client.ui_options.focus_on_stonk, Some(0));
+
+        // Counts that do not name a stonk never move the focus.
+        press(
+            &mut client,
+            &mut agent,
+            &[KeyCode::Char('1'), KeyCode::Char('2'), KeyCode::Char('z')],
+        )?;
+        press(
+            &mut client,
+            &mut agent,
+            &[KeyCode::Char('9'), KeyCode::Char('z')],
+        )?;
+        assert_eq!(client.ui_options.focus_on_stonk, Some(0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_idle_duration_with_clock_changes() -> AppResult<()> {
+        let mut session_auth = SessionAuth::new("sleepy".to_string(), [0; 32]);
+        assert!(session_auth.idle_duration() < Duration::from_secs(1));
+
+        // A loaded session uses the persisted time, which can be in the future
+        // if the system clock went backwards since it was stored.
+        session_auth.last_active_time = SystemTime::now() + Duration::from_secs(3600);
+        let loaded: SessionAuth = serde_json::from_str(&serde_json::to_string(&session_auth)?)?;
+        assert_eq!(loaded.idle_duration(), Duration::ZERO);
+
+        session_auth.last_active_time = SystemTime::now() - Duration::from_secs(3600);
+        let loaded: SessionAuth = serde_json::from_str(&serde_json::to_string(&session_auth)?)?;
+        assert!(loaded.idle_duration() >= Duration::from_secs(3600));
+        Ok(())
+    }
+
+    #[test]
+    fn test_connection_stats_closed() {
+        let mut stats = ConnectionStats::default();
+        stats.record_flush(Duration::from_millis(20), false);
+        assert!(!stats.closed);
+
+        stats.record_flush(Duration::from_millis(20), true);
+        assert!(stats.closed);
+        assert_eq!(stats.dropped_frames, 1);
+    }
+
+    #[test]
+    fn test_bandwidth_budget() {
+        let start = Instant::now();
+        let mut budget = BandwidthBudget::new(1_000);
+        assert!(budget.allows_frame(start));
+        budget.record_sent(600, start);
+        assert!(budget.allows_frame(start));
+        budget.record_sent(600, start);
+
+        // Frames are dropped until the next window, and the client stays throttled
+        // for the whole window after the one that ran out.
+        assert!(!budget.allows_frame(start + Duration::from_millis(500)));
+        assert!(budget.is_throttled());
+        let next_window = start + Duration::from_secs(1);
+        assert!(budget.allows_frame(next_window));
+        assert!(budget.is_throttled());
+        budget.record_sent(100, next_window);
+        assert!(budget.allows_frame(next_window + Duration::from_secs(1)));
+        assert!(!budget.is_throttled());
+    }
+
+    #[test]
+    fn test_frame_schedule() {
+        let start = Instant::now();
+        let mut schedule = FrameSchedule::default();
+        assert!(schedule.is_due(0, false, start));
+        schedule.record_frame(0, start);
+        assert!(!schedule.is_due(0, false, start));
+
+        // Several keys in the same interval are drawn in a single frame.
+        schedule.mark_dirty();
+        schedule.mark_dirty();
+        assert!(schedule.is_due(0, false, start));
+        schedule.record_frame(0, start);
+        assert!(!schedule.is_due(0, false, start));
+
+        assert!(schedule.is_due(1, false, start));
+        assert!(schedule.is_due(0, true, start));
+        assert!(schedule.is_due(
+            0,
+            false,
+            start + Duration::from_millis(FRAME_REFRESH_MILLIS)
+        ));
+    }
+}
@@ -1,43 +1,100 @@
-use crate::agent::{AgentAction, DecisionAgent, UserAgent};
-use crate::events::NightEvent;
-use crate::market::{GamePhase, Market, HISTORICAL_SIZE, MAX_EVENTS_PER_NIGHT};
-use crate::ssh_client::{Client, SessionAuth};
+use crate::agent::{DecisionAgent, UserAgent};
+use crate::api::{self, ApiToken};
+use crate::audit::{self, AuditEvent};
+use crate::bots::bot_scaling;
+use crate::clock::GameClock;
+use crate::config::ServerConfig;
+use crate::formatting::set_number_format;
+use crate::healthcheck;
+use crate::leaderboard::LeaderboardHistory;
+use crate::load_shedding::tick_detail;
+use crate::logging;
+use crate::market::{GamePhase, Market, MarketSnapshot, HISTORICAL_SIZE};
+use crate::market_event::MarketEvent;
+use crate::money::Money;
+use crate::persistence::{Persistence, StoreSnapshot};
+use crate::recording::RECORDING_SFTP_FILENAME;
+use crate::server_status::{report_error, ServerStatus, STATUS_USERNAME};
+use crate::sftp::{self, SftpSession, VirtualFile};
+use crate::ssh_client::{Client, Password, SessionAuth};
+use crate::statistics::ServerStatistics;
 use crate::utils::*;
 use async_trait::async_trait;
 use crossterm::event::*;
-use rand::seq::SliceRandom;
 use rand::{Rng, RngCore, SeedableRng};
 use rand_chacha::ChaCha8Rng;
 use rand_distr::Alphanumeric;
-use russh::{server::*, Channel, ChannelId, Disconnect, Pty};
+use russh::{server::*, Channel, ChannelId, CryptoVec, Disconnect, Pty};
 use russh_keys::key::PublicKey;
+use serde_json::json;
 use sha2::{Digest, Sha256};
+use std::any::Any;
 use std::collections::HashMap;
-use std::sync::Arc;
-use std::time::{Duration, SystemTime};
-use strum::IntoEnumIterator;
-use tokio::sync::Mutex;
-use tracing::{debug, error, info};
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{watch, Mutex, Notify};
+use tracing::{debug, error, info, warn};
+
+const IDLE_TIMEOUT_MESSAGE: &str =
+    "You have been disconnected for inactivity. Your progress is safe, reconnect any time.";
+const RENDER_CRASH_MESSAGE: &str =
+    "Sorry, something went wrong while drawing the game. Your progress is safe, please reconnect.";
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
 
 pub type AgentsDatabase = HashMap<String, UserAgent>;
 
-const CLIENTS_DROPOUT_TIME_SECONDS: u64 = 60 * 10;
-const PERSISTED_CLIENTS_DROPOUT_TIME_SECONDS: u64 = 60 * 60 * 24;
+const GUEST_DROPOUT_TIME_SECONDS: u64 = 60 * 60 * 24;
 const STORE_TO_DISK_INTERVAL_SECONDS: u64 = 60;
-const MARKET_TICK_INTERVAL_MILLIS: u64 = 1000;
 const RENDER_INTERVAL_MILLIS: u64 = 50;
+const STATISTICS_REFRESH_CHECK_SECONDS: u64 = 60;
 const MIN_USERNAME_LENGTH: usize = 3;
 const MAX_USERNAME_LENGTH: usize = 16;
+// Number of top players whose usernames cannot be imitated by new ones.
+const PROTECTED_TOP_PLAYERS: usize = 10;
 
-static AUTH_PASSWORD_SALT: &'static str = "gbasfhgE4Fvb";
-static AUTH_PUBLIC_KEY_SALT: &'static str = "fa2RR4fq9XX9";
+static AUTH_PASSWORD_SALT: &str = "gbasfhgE4Fvb";
+static AUTH_PUBLIC_KEY_SALT: &str = "fa2RR4fq9XX9";
 
 #[derive(Clone)]
 pub struct AppServer {
     market: Arc<Mutex<Market>>,
     clients: Arc<Mutex<HashMap<String, Client>>>,
     agents: Arc<Mutex<AgentsDatabase>>,
+    // Dormant agents, restored on their next login. Always locked last.
+    archived_agents: Arc<Mutex<AgentsDatabase>>,
+    // Set when an agent is restored, so that the next save rewrites the archive.
+    archive_changed: Arc<AtomicBool>,
+    config: Arc<ServerConfig>,
+    clock: Arc<Mutex<GameClock>>,
+    // Wakes up the tick scheduler when the clock is changed.
+    clock_changed: Arc<Notify>,
+    // Published by the tick scheduler after every market tick.
+    snapshot: Arc<watch::Sender<MarketSnapshot>>,
+    // Never held across an await, so it can be locked anywhere.
+    status: Arc<std::sync::Mutex<ServerStatus>>,
     session_auth: SessionAuth,
+    // Whether the username was generated because the player logged in without one.
+    guest: bool,
+    // Set when the password is an API token, for sessions used by bots.
+    api_token: Option<String>,
+    // Channel of an authenticated session, until it starts either the game or SFTP.
+    game_channel: Option<ChannelId>,
+    // Set when the session runs the SFTP subsystem instead of the game.
+    sftp: Option<SftpSession>,
+    // Host key of the server, also signing the passports.
+    signing_key: Option<Arc<ed25519_dalek::SigningKey>>,
 }
 
 impl AppServer {
@@ -52,7 +109,243 @@ impl AppServer {
             .to_string()
     }
 
-    pub fn new(reset: bool, seed: Option<u64>) -> AppResult<Self> {
+    fn hash_password(password: &str) -> Password {
+        let mut hasher = Sha256::new();
+        let salted_password = format!("{}{}", password, AUTH_PASSWORD_SALT);
+        hasher.update(salted_password);
+        hasher.finalize().into()
+    }
+
+    /// Usernames of the top players, protected against lookalike names.
+    fn protected_usernames(market: &Market) -> Vec<String> {
+        market
+            .portfolios
+            .iter()
+            .take(PROTECTED_TOP_PLAYERS)
+            .map(|(username, _)| username.clone())
+            .collect()
+    }
+
+    /// Checks that the username is valid and not used or reserved by an agent other than `owner`.
+    fn check_username_available(
+        config: &ServerConfig,
+        agents: &AgentsDatabase,
+        archived_agents: &AgentsDatabase,
+        protected_usernames: &[String],
+        username: &str,
+        owner: &str,
+    ) -> AppResult<()> {
+        if username == STATUS_USERNAME {
+            return Err("This username is reserved.".into());
+        }
+        if username.len() < MIN_USERNAME_LENGTH || username.len() > MAX_USERNAME_LENGTH {
+            return Err(format!(
+                "The username must have between {} and {} characters.",
+                MIN_USERNAME_LENGTH, MAX_USERNAME_LENGTH
+            )
+            .into());
+        }
+        if agents.contains_key(username) || archived_agents.contains_key(username) {
+            return Err("This username is already taken.".into());
+        }
+        if agents
+            .iter()
+            .any(|(name, agent)| name != owner && agent.has_reserved_username(username))
+        {
+            return Err("This username was recently used by another player.".into());
+        }
+        config.usernames.check(
+            username,
+            protected_usernames
+                .iter()
+                .map(|name| name.as_str())
+                .filter(|name| *name != owner),
+        )
+    }
+
+    /// Applies the dormancy policy to the agents that are not online.
+    /// Guests are dropped after a day, registered agents have their holdings liquidated
+    /// and are later moved to the archive. Returns whether any agent was archived.
+    fn handle_dormant_agents(
+        config: &ServerConfig,
+        is_online: impl Fn(&str) -> bool,
+        agents: &mut AgentsDatabase,
+        market: &mut Market,
+        archived_agents: &mut AgentsDatabase,
+    ) -> bool {
+        const SECONDS_PER_DAY: u64 = 60 * 60 * 24;
+        let liquidate_after =
+            Duration::from_secs(config.dormancy.liquidate_after_days * SECONDS_PER_DAY);
+        let archive_after =
+            Duration::from_secs(config.dormancy.archive_after_days * SECONDS_PER_DAY);
+
+        let mut dormant = agents
+            .iter()
+            .filter(|(username, _)| !is_online(username))
+            .map(|(username, agent)| {
+                (
+                    username.clone(),
+                    agent.is_guest(),
+                    agent.session_auth.idle_duration(),
+                    agent.owned_stonks().iter().any(|&amount| amount > 0)
+                        || agent.offshore_cash() > Money::ZERO
+                        || market.futures.contracts_of(username).next().is_some(),
+                )
+            })
+            .collect::<Vec<_>>();
+        dormant.sort();
+
+        let mut archived = false;
+        for (username, guest, idle, has_holdings) in dormant {
+            let (liquidate, remove) = if guest {
+                let expired = idle > Duration::from_secs(GUEST_DROPOUT_TIME_SECONDS);
+                (expired, expired)
+            } else {
+                (idle > liquidate_after, idle > archive_after)
+            };
+
+            // Holdings go back to the market before the agent is dropped or archived.
+            if liquidate && has_holdings {
+                market
+                    .apply_event(
+                        &MarketEvent::LiquidateAgent {
+                            username: username.clone(),
+                        },
+                        agents,
+                    )
+                    .unwrap_or_else(|e| error!("Could not liquidate {}: {}", username, e));
+            }
+            if remove {
+                let agent = agents
+                    .remove(&username)
+                    .expect("Dormant agent should exist");
+                if !guest {
+                    info!("Archiving dormant agent {}", username);
+                    audit::record(AuditEvent::AgentArchived {
+                        username: username.clone(),
+                    });
+                    archived_agents.insert(username, agent);
+                    archived = true;
+                }
+            }
+        }
+        archived
+    }
+
+    /// Restores a dormant agent from the archive when logging in with their password.
+    /// An agent that is also in the live database is never overwritten, the stale archived
+    /// copy is dropped instead. Returns whether the archive changed and must be saved again,
+    /// together with the agents, so that the archived copy does not come back after a restart.
+    fn restore_archived_agent(
+        agents: &mut AgentsDatabase,
+        archived_agents: &mut AgentsDatabase,
+        username: &str,
+        hashed_password: Password,
+    ) -> bool {
+        if !archived_agents
+            .get(username)
+            .is_some_and(|agent| agent.session_auth.check_password(hashed_password))
+        {
+            return false;
+        }
+        let agent = archived_agents
+            .remove(username)
+            .expect("Archived agent should exist");
+        if agents.contains_key(username) {
+            warn!("Dropping stale archived copy of agent {}", username);
+            return true;
+        }
+        info!("Restoring archived agent {}", username);
+        audit::record(AuditEvent::AgentRestored {
+            username: username.to_string(),
+        });
+        agents.insert(username.to_string(), agent);
+        true
+    }
+
+    /// Changes the username of an agent everywhere it is referenced.
+    fn rename_agent(
+        config: &ServerConfig,
+        agents: &mut AgentsDatabase,
+        archived_agents: &AgentsDatabase,
+        market: &mut Market,
+        old_username: &str,
+        username: &str,
+    ) -> AppResult<()> {
+        let protected_usernames = Self::protected_usernames(market);
+        Self::check_username_available(
+            config,
+            agents,
+            archived_agents,
+            &protected_usernames,
+            username,
+            old_username,
+        )?;
+
+        let agent = agents.get_mut(old_username).ok_or("Agent not found")?;
+        agent.change_username(username.to_string())?;
+        let agent = agents
+            .remove(old_username)
+            .expect("Agent should have been found");
+        agents.insert(username.to_string(), agent);
+        market.apply_event(
+            &MarketEvent::RenameAgent {
+                old_username: old_username.to_string(),
+                username: username.to_string(),
+            },
+            agents,
+        )?;
+        Ok(())
+    }
+
+    /// Moves a guest agent to the chosen username and password, keeping its progress.
+    fn upgrade_guest(
+        config: &ServerConfig,
+        agents: &mut AgentsDatabase,
+        archived_agents: &AgentsDatabase,
+        market: &mut Market,
+        guest_username: &str,
+        username: &str,
+        password: &str,
+    ) -> AppResult<()> {
+        let protected_usernames = Self::protected_usernames(market);
+        Self::check_username_available(
+            config,
+            agents,
+            archived_agents,
+            &protected_usernames,
+            username,
+            guest_username,
+        )?;
+        let hashed_password = Self::hash_password(password);
+
+        let mut agent = agents
+            .remove(guest_username)
+            .ok_or("Guest agent not found")?;
+        agent.upgrade_account(username.to_string(), hashed_password);
+        agents.insert(username.to_string(), agent);
+        market.apply_event(
+            &MarketEvent::RenameAgent {
+                old_username: guest_username.to_string(),
+                username: username.to_string(),
+            },
+            agents,
+        )?;
+        Ok(())
+    }
+
+    pub fn new(
+        reset: bool,
+        seed: Option<u64>,
+        speed: f64,
+        scenario: Option<&Path>,
+    ) -> AppResult<Self> {
+        let config = load_config()?;
+        config.validate()?;
+        audit::init(audit_log_path()?)?;
+        set_number_format(config.formatting);
+        info!("Loaded server config {:?}", config);
+
         let agents = if reset {
             let agents = AgentsDatabase::default();
             save_agents(&agents)?;
@@ -62,7 +355,15 @@ impl AppServer {
         };
         info!("Loaded {} agents from store", agents.len());
 
-        let market = if reset {
+        let archived_agents = if reset {
+            let archived_agents = AgentsDatabase::default();
+            save_archived_agents(&archived_agents)?;
+            archived_agents
+        } else {
+            load_archived_agents()?
+        };
+
+        let mut market = if reset {
             info!("Creating new market from scratch");
             let mut m = Market::default();
             let rng = &mut ChaCha8Rng::seed_from_u64(
@@ -77,51 +378,327 @@ impl AppServer {
             save_market(&m)?;
             m
         } else {
-            let m = load_market()?;
+            let mut m = load_market()?;
             info!("Loading market. Starting back from {:#?}", m.phase);
+            m.align_price_history();
 
             m
         };
+        market.trading = config.trading.clone();
+        market.events = config.events.clone();
+        market.tournament.config = config.tournament.clone();
+        market.telemetry.enabled = config.telemetry.enabled;
+        if let Some(path) = scenario {
+            let scenario = load_scenario(path)?;
+            info!("Running scenario {}", scenario.name);
+            let cycle = market.phase.cycle();
+            market.scenario.load(scenario, cycle);
+        }
+        market.leaderboard_history = if reset {
+            clear_leaderboard_history()?;
+            LeaderboardHistory::default()
+        } else {
+            load_leaderboard_history()?
+        };
+        info!(
+            "Loaded {} days of leaderboard history",
+            market.leaderboard_history.days().len()
+        );
+        market.statistics = if reset {
+            // The audit log is kept, so the statistics start from now.
+            let statistics = ServerStatistics::starting_at(
+                SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)?
+                    .as_secs(),
+            );
+            save_statistics(&statistics)?;
+            statistics
+        } else {
+            load_statistics()?
+        };
+
+        let clock = GameClock::new(speed, Instant::now())?;
 
         Ok(Self {
+            clock: Arc::new(Mutex::new(clock)),
+            clock_changed: Arc::new(Notify::new()),
+            snapshot: Arc::new(watch::Sender::new(market.snapshot())),
+            status: Arc::new(std::sync::Mutex::new(ServerStatus::new(Instant::now()))),
             market: Arc::new(Mutex::new(market)),
             clients: Arc::new(Mutex::new(HashMap::new())),
             agents: Arc::new(Mutex::new(agents)),
+            archived_agents: Arc::new(Mutex::new(archived_agents)),
+            archive_changed: Arc::new(AtomicBool::new(false)),
+            config: Arc::new(config),
             session_auth: SessionAuth::default(),
+            guest: false,
+            api_token: None,
+            game_channel: None,
+            sftp: None,
+            signing_key: None,
         })
     }
 
-    pub async fn run(&mut self, port: u16) -> AppResult<()> {
+    /// Builds a server from the given state, without touching the store.
+    #[cfg(test)]
+    pub(crate) fn with_state(
+        config: ServerConfig,
+        agents: AgentsDatabase,
+        mut market: Market,
+    ) -> Self {
+        let archived_agents = AgentsDatabase::default();
+        market.trading = config.trading.clone();
+        market.events = config.events.clone();
+        market.tournament.config = config.tournament.clone();
+        market.telemetry.enabled = config.telemetry.enabled;
+        Self {
+            clock: Arc::new(Mutex::new(
+                GameClock::new(1.0, Instant::now()).expect("Default speed should be valid"),
+            )),
+            clock_changed: Arc::new(Notify::new()),
+            snapshot: Arc::new(watch::Sender::new(market.snapshot())),
+            status: Arc::new(std::sync::Mutex::new(ServerStatus::new(Instant::now()))),
+            market: Arc::new(Mutex::new(market)),
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            agents: Arc::new(Mutex::new(agents)),
+            archived_agents: Arc::new(Mutex::new(archived_agents)),
+            archive_changed: Arc::new(AtomicBool::new(false)),
+            config: Arc::new(config),
+            session_auth: SessionAuth::default(),
+            guest: false,
+            api_token: None,
+            game_channel: None,
+            sftp: None,
+            signing_key: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn agents(&self) -> Arc<Mutex<AgentsDatabase>> {
+        self.agents.clone()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn market(&self) -> Arc<Mutex<Market>> {
+        self.market.clone()
+    }
+
+    #[cfg(test)]
+    pub(crate) async fn number_of_clients(&self) -> usize {
+        self.clients.lock().await.len()
+    }
+
+    /// Runs the game on `port`, and the healthcheck probes on `health_port` if given.
+    pub async fn run(&mut self, port: u16, health_port: Option<u16>) -> AppResult<()> {
+        // The probes are up before the keys are loaded, so they can report not being ready.
+        if let Some(health_port) = health_port {
+            let listener = tokio::net::TcpListener::bind(("0.0.0.0", health_port)).await?;
+            info!("Serving healthcheck probes on port {}", health_port);
+            tokio::spawn(healthcheck::serve(
+                listener,
+                self.status.clone(),
+                self.clock.clone(),
+            ));
+        }
+
+        self.spawn_statistics_refresher();
+
+        let signing_key = load_keys().unwrap_or_else(|_| {
+            let key_pair =
+                russh_keys::key::KeyPair::generate_ed25519().expect("Failed to generate key pair");
+            let russh_keys::key::KeyPair::Ed25519(signing_key) = key_pair;
+            save_keys(&signing_key).expect("Failed to save SSH keys.");
+            signing_key
+        });
+
+        self.run_with_key_pair(russh_keys::key::KeyPair::Ed25519(signing_key), port)
+            .await
+    }
+
+    /// Refreshes the server statistics from the audit log once per day. The log is read
+    /// without holding the market lock.
+    fn spawn_statistics_refresher(&self) {
+        let market = self.market.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let (mut statistics, cycle) = {
+                    let market = market.lock().await;
+                    (market.statistics.clone(), market.phase.cycle())
+                };
+                if statistics.needs_refresh(cycle) {
+                    let refreshed = tokio::task::spawn_blocking(move || {
+                        refresh_statistics(&mut statistics, cycle)
+                            .map(|_| statistics)
+                            .map_err(|e| e.to_string())
+                    })
+                    .await;
+                    match refreshed {
+                        Ok(Ok(statistics)) => market.lock().await.statistics = statistics,
+                        Ok(Err(e)) => error!("Could not refresh the server statistics: {}", e),
+                        Err(e) => error!("Server statistics refresh panicked: {}", e),
+                    }
+                }
+                tokio::time::sleep(Duration::from_secs(STATISTICS_REFRESH_CHECK_SECONDS)).await;
+            }
+        });
+    }
+
+    /// Ticks the market following the game clock, independently of how long rendering takes.
+    fn spawn_tick_scheduler(&self) {
+        let clients = self.clients.clone();
+        let agents = self.agents.clone();
+        let market = self.market.clone();
+        let snapshot = self.snapshot.clone();
+        let clock = self.clock.clone();
+        let clock_changed = self.clock_changed.clone();
+        let config = self.config.clone();
+        let status = self.status.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let next_tick_at = clock.lock().await.next_tick_at();
+                match next_tick_at {
+                    Some(instant) => tokio::select! {
+                        _ = tokio::time::sleep_until(instant.into()) => {}
+                        _ = clock_changed.notified() => {}
+                    },
+                    None => clock_changed.notified().await,
+                }
+
+                let ticks = clock.lock().await.take_due_ticks(Instant::now());
+                if ticks == 0 {
+                    continue;
+                }
+
+                // The clients are locked first, as everywhere else.
+                let lock_started_at = Instant::now();
+                let clients = clients.lock().await;
+                let mut agents = agents.lock().await;
+                let mut market = market.lock().await;
+                let tick_started_at = Instant::now();
+
+                // Bots make up for the trades missing when few players are online.
+                let (size, aggressiveness) = bot_scaling(&config.bots, clients.len());
+                if size != market.bots.len() || aggressiveness != market.bots.aggressiveness() {
+                    market
+                        .apply_event(
+                            &MarketEvent::ScaleBots {
+                                size,
+                                aggressiveness,
+                            },
+                            &mut agents,
+                        )
+                        .unwrap_or_else(|e| {
+                            report_error(&status, format!("Could not scale bots: {}", e))
+                        });
+                }
+                // Under heavy load the ticks skip the work nobody would miss.
+                let detail = tick_detail(&config.load_shedding, clients.len());
+                if detail != market.tick_detail {
+                    info!(
+                        "Tick detail set to {:?} with {} players online",
+                        detail,
+                        clients.len()
+                    );
+                    match market.apply_event(&MarketEvent::SetTickDetail { detail }, &mut agents) {
+                        // Events only change the market, so the global logs are switched here.
+                        Ok(()) => logging::set_detailed_logs(market.tick_detail.is_full()),
+                        Err(e) => {
+                            report_error(&status, format!("Could not set tick detail: {}", e))
+                        }
+                    }
+                }
+                for _ in 0..ticks {
+                    market
+                        .apply_event(&MarketEvent::tick(), &mut agents)
+                        .unwrap_or_else(|e| {
+                            report_error(&status, format!("Could not tick market: {}", e))
+                        });
+                }
+                if let Ok(mut status) = status.lock() {
+                    status.record_lock_wait(tick_started_at - lock_started_at);
+                    status.record_tick_duration(tick_started_at.elapsed() / ticks as u32);
+                    status.record_tick(Instant::now());
+                }
+                snapshot.send_replace(market.snapshot());
+            }
+        });
+    }
+
+    /// Returns a receiver notified with a new snapshot after every market tick.
+    pub fn subscribe_snapshots(&self) -> watch::Receiver<MarketSnapshot> {
+        self.snapshot.subscribe()
+    }
+
+    pub(crate) async fn run_with_key_pair(
+        &mut self,
+        key_pair: russh_keys::key::KeyPair,
+        port: u16,
+    ) -> AppResult<()> {
         info!("Starting SSH server. Press Ctrl-C to exit.");
+        let russh_keys::key::KeyPair::Ed25519(signing_key) = &key_pair;
+        self.signing_key = Some(Arc::new(signing_key.clone()));
+        if let Ok(mut status) = self.status.lock() {
+            status.record_keys_loaded();
+        }
         let clients = self.clients.clone();
         let agents = self.agents.clone();
         let market = self.market.clone();
+        let archived_agents = self.archived_agents.clone();
+        let archive_changed = self.archive_changed.clone();
+        let config = self.config.clone();
+        let clock = self.clock.clone();
+        let status = self.status.clone();
+        let persistence = Persistence::spawn(self.status.clone());
+
+        self.spawn_tick_scheduler();
 
         tokio::spawn(async move {
-            let mut last_market_tick = SystemTime::now();
-            let mut last_store_to_disk = SystemTime::now();
+            let mut last_store_to_disk = Instant::now();
+            let mut save_delayed = false;
             loop {
                 tokio::time::sleep(tokio::time::Duration::from_millis(RENDER_INTERVAL_MILLIS))
                     .await;
 
+                // The clock is only read here, before taking the other locks.
+                let (paused, tick_interval, maintenance) = {
+                    let clock = clock.lock().await;
+                    (
+                        clock.is_paused(),
+                        clock.tick_interval(),
+                        clock.maintenance().cloned(),
+                    )
+                };
+                // Without a message of its own, the maintenance shows the message of the day.
+                let maintenance = maintenance.map(|mut maintenance| {
+                    if maintenance.message.is_empty() {
+                        maintenance.message = config.session.motd.clone();
+                    }
+                    maintenance
+                });
+
+                let lock_started_at = Instant::now();
                 let mut clients = clients.lock().await;
                 let mut agents = agents.lock().await;
                 let mut market = market.lock().await;
-
-                // Update market if necessary
-                if last_market_tick.elapsed().expect("Time flows backwards")
-                    > Duration::from_millis(MARKET_TICK_INTERVAL_MILLIS)
-                {
-                    market.update_target_total_market_cap(agents.len());
-                    market.update_portfolios(&agents);
-                    market.tick();
-                    last_market_tick = SystemTime::now();
+                if let Ok(mut status) = status.lock() {
+                    status.record_lock_wait(lock_started_at.elapsed());
                 }
 
-                // If the client did not do anything recently, it wil removed.
+                // If the client did not do anything recently, it will be warned and then removed.
                 let mut _to_remove = vec![];
-                for (id, client) in clients.iter() {
-                    let try_agent = agents.get(client.username());
+                let mut idle = vec![];
+                let mut dead = vec![];
+                for (id, client) in clients.iter_mut() {
+                    // Sessions dropped for missed keepalives stop rendering right away,
+                    // instead of waiting for the idle timeout.
+                    if client.is_connection_closed() {
+                        dead.push(id.clone());
+                        continue;
+                    }
+
+                    let try_agent = agents.get_mut(client.username());
 
                     if try_agent.is_none() {
                         _to_remove.push(id.clone());
@@ -129,50 +706,71 @@ impl AppServer {
                     }
                     let agent = try_agent.expect("Client agent should exist in persisted agents.");
 
-                    if agent
-                        .session_auth
-                        .last_active_time
-                        .elapsed()
-                        .expect("Time flows")
-                        > Duration::from_secs(CLIENTS_DROPOUT_TIME_SECONDS)
-                    {
-                        _to_remove.push(id.clone());
-                        continue;
+                    // Nobody can play during a maintenance, so nobody is idle either.
+                    if maintenance.is_some() {
+                        agent.session_auth.update_last_active_time();
+                    }
+
+                    let idle_seconds = agent.session_auth.idle_duration().as_secs();
+                    let remaining_seconds = config
+                        .session
+                        .idle_timeout_seconds
+                        .saturating_sub(idle_seconds);
+                    if remaining_seconds == 0 {
+                        idle.push(id.clone());
+                    } else if remaining_seconds <= config.session.idle_warning_seconds {
+                        client.set_idle_countdown(Some(remaining_seconds));
+                    } else {
+                        client.set_idle_countdown(None);
                     }
                 }
 
                 clients.retain(|_, c| !_to_remove.contains(&c.username().to_string()));
 
-                // Prepare CharacterAssassination events common to every agent
-                let mut character_assassination_events = vec![];
-                let mut usernames = vec![];
-                for stonk in market.stonks.iter() {
-                    for (username, _) in stonk.shareholders.iter().take(5) {
-                        if usernames.contains(&username) {
-                            continue;
-                        }
+                for id in dead {
+                    if let Some(client) = clients.remove(&id) {
+                        info!("Reaping dead connection of {}", id);
+                        audit::record(AuditEvent::Logout {
+                            username: client.username().to_string(),
+                        });
+                    }
+                }
 
-                        if let Some(agent) = agents.get(username) {
-                            if agent
-                                .past_selected_actions()
-                                .contains_key(&AgentAction::AcceptBribe.to_string())
-                                && !agent
-                                    .past_selected_actions()
-                                    .contains_key(&AgentAction::AssassinationVictim.to_string())
-                            {
-                                usernames.push(username);
-                                character_assassination_events.push(
-                                    NightEvent::CharacterAssassination {
-                                        username: username.clone(),
-                                    },
-                                )
-                            }
-                        }
+                for id in idle {
+                    if let Some(mut client) = clients.remove(&id) {
+                        info!("Disconnecting idle client {}", id);
+                        audit::record(AuditEvent::Logout {
+                            username: client.username().to_string(),
+                        });
+                        client
+                            .tui
+                            .exit_with_message(IDLE_TIMEOUT_MESSAGE)
+                            .await
+                            .unwrap_or_else(|e| error!("Error exiting tui: {}", e));
                     }
                 }
 
-                // Apply agent actions and set night events
-                for (id, client) in clients.iter_mut() {
+                // Apply agent actions.
+                // Actions are applied in the order they were selected, so that the outcome
+                // does not depend on the iteration order of the clients map.
+                let mut ids = clients.keys().cloned().collect::<Vec<String>>();
+                ids.sort_by_cached_key(|id| {
+                    (
+                        agents
+                            .get(id)
+                            .and_then(|agent| agent.action_sequence())
+                            .unwrap_or(u64::MAX),
+                        id.clone(),
+                    )
+                });
+                // Queued actions wait for the end of the maintenance, so that the state stays put.
+                if maintenance.is_some() {
+                    ids.clear();
+                }
+                for id in ids.iter() {
+                    let Some(client) = clients.get_mut(id) else {
+                        continue;
+                    };
                     let try_agent = agents.get(client.username());
 
                     let agent = &mut try_agent
@@ -184,59 +782,40 @@ impl AppServer {
                     match market.phase {
                         GamePhase::Day { .. } => {
                             client.clear_render_counter();
-                            agent.set_available_night_events(vec![]);
-                            if let Some(_) = agent.selected_action() {
-                                market
-                                    .apply_agent_action::<UserAgent>(agent, &mut agents)
-                                    .unwrap_or_else(|e| {
-                                        error!("Could not apply agent {} action: {}", id, e)
-                                    });
+                            if !agent.selected_actions().is_empty() {
+                                let event = MarketEvent::agent_actions(
+                                    agent.username().to_string(),
+                                    agent.selected_actions().clone(),
+                                );
+                                agents.insert(agent.username().to_string(), agent.clone());
+                                market.apply_event(&event, &mut agents).unwrap_or_else(|e| {
+                                    error!("Could not apply agent {} action: {}", id, e)
+                                });
+                                *agent = agents[id].clone();
                             }
                         }
                         GamePhase::Night { .. } => {
-                            // At the beginning of the night, set the available events.
-                            // We set them here because we need the market data.
-                            if client.render_counter() == 0
-                                && agent.available_night_events().len() == 0
-                            {
-                                let mut events = NightEvent::iter()
-                                    .filter(|e| {
-                                        match e {
-                                            NightEvent::CharacterAssassination { .. } => {
-                                                return false
-                                            }
-                                            NightEvent::ReceiveDividends { .. } => return false,
-                                            _ => {}
-                                        };
-                                        e.unlock_condition()(agent, &market)
-                                    })
-                                    .collect::<Vec<NightEvent>>();
-
-                                for event in character_assassination_events.iter() {
-                                    if event.unlock_condition()(agent, &market) == true {
-                                        events.push(event.clone());
-                                    }
-                                }
-
-                                // Add ReceiveDividends events for each stonk
-                                for stonk in market.stonks.iter() {
-                                    let event = NightEvent::ReceiveDividends { stonk_id: stonk.id };
-                                    if event.unlock_condition()(agent, &market) == true {
-                                        events.push(event.clone());
-                                    }
+                            // Trades and auction orders are executed right away, while the
+                            // selected night events wait for the morning.
+                            let orders = agent.take_selected_orders();
+                            if !orders.is_empty() {
+                                let night_actions = agent.selected_actions().clone();
+                                let event = MarketEvent::agent_actions(
+                                    agent.username().to_string(),
+                                    orders,
+                                );
+                                agents.insert(agent.username().to_string(), agent.clone());
+                                market.apply_event(&event, &mut agents).unwrap_or_else(|e| {
+                                    error!("Could not apply agent {} action: {}", id, e)
+                                });
+                                *agent = agents[id].clone();
+                                for action in night_actions {
+                                    agent.select_action(action);
                                 }
-
-                                info!("Got events {:#?}", events);
-                                events.shuffle(&mut rand::thread_rng());
-                                events = events
-                                    .iter()
-                                    .take(MAX_EVENTS_PER_NIGHT)
-                                    .map(|e| e.clone())
-                                    .collect::<Vec<NightEvent>>();
-
-                                agent.clear_action();
+                            }
+                            // The events are rolled by the market when the night starts.
+                            if client.render_counter() == 0 {
                                 client.clear_event_card();
-                                agent.set_available_night_events(events);
                             }
                             client.tick_render_counter();
                         }
@@ -247,73 +826,249 @@ impl AppServer {
 
                 // Draw to client TUI
                 let number_of_players = clients.len();
-                for (_, client) in clients.iter_mut() {
+                if clients
+                    .values()
+                    .any(|client| client.is_debug_overlay_open())
+                {
+                    let frame_bytes = clients
+                        .values()
+                        .map(|client| (client.username().to_string(), client.last_frame_bytes()))
+                        .collect();
+                    if let Ok(status) = status.lock() {
+                        let stats = status.debug_stats(frame_bytes);
+                        for client in clients.values_mut() {
+                            client.set_debug_stats(stats.clone());
+                        }
+                    }
+                }
+                let mut crashed = vec![];
+                for (id, client) in clients.iter_mut() {
                     let agent = agents
                         .get(client.username())
                         .expect("Client agent should exist in persisted agents.");
 
-                    client
-                        .draw(&market, &agent, number_of_players)
-                        .unwrap_or_else(|e| debug!("Failed to draw: {}", e));
+                    client.set_clock_status(paused, tick_interval);
+                    client.set_maintenance(maintenance.as_ref());
+                    // A rendering bug must not bring down the whole server, so only the
+                    // affected session is dropped.
+                    match std::panic::catch_unwind(AssertUnwindSafe(|| {
+                        client.draw(&market, agent, number_of_players)
+                    })) {
+                        Ok(result) => {
+                            result.unwrap_or_else(|e| debug!("Failed to draw: {}", e));
+                        }
+                        Err(payload) => {
+                            report_error(
+                                &status,
+                                format!(
+                                    "Rendering panicked for {}: {}\nMarket phase: {:?}",
+                                    client.username(),
+                                    panic_message(payload.as_ref()),
+                                    market.phase
+                                ),
+                            );
+                            crashed.push(id.clone());
+                        }
+                    }
+                }
+
+                for id in crashed {
+                    if let Some(mut client) = clients.remove(&id) {
+                        client
+                            .tui
+                            .exit_with_message(RENDER_CRASH_MESSAGE)
+                            .await
+                            .unwrap_or_else(|e| error!("Error exiting tui: {}", e));
+                    }
                 }
 
-                // Store to disk
-                if last_store_to_disk.elapsed().expect("Time flows backwards")
+                // Store to disk. Only the copy is made here, the persistence task writes it.
+                if last_store_to_disk.elapsed()
                     > Duration::from_secs(STORE_TO_DISK_INTERVAL_SECONDS)
                 {
-                    last_store_to_disk = SystemTime::now();
-                    info!("There are {} agents", agents.len());
-
-                    agents.retain(|_, agent| {
-                        let condition = agent
-                            .session_auth
-                            .last_active_time
-                            .elapsed()
-                            .expect("Time flows")
-                            <= Duration::from_secs(PERSISTED_CLIENTS_DROPOUT_TIME_SECONDS);
-
-                        if !condition {
-                            for (stonk_id, &amount) in agent.owned_stonks().iter().enumerate() {
-                                let stonk = &mut market.stonks[stonk_id];
-                                if let Err(e) = stonk.deallocate_shares_to_agent(agent.username(), amount) {
-                                    error!("Failed to deallocate share: {e}. Stonk id {stonk_id}, username {}, amount {amount}", agent.username());
-                                    continue;
-                                }
+                    let Some(permit) = persistence.try_reserve() else {
+                        if !save_delayed {
+                            warn!("The previous save is still being written, delaying this one");
+                            if let Ok(mut status) = status.lock() {
+                                status.record_save_delayed();
                             }
+                            save_delayed = true;
                         }
-                        condition
+                        continue;
+                    };
+                    last_store_to_disk = Instant::now();
+                    save_delayed = false;
+                    info!("There are {} agents", agents.len());
+
+                    let mut archived_agents = archived_agents.lock().await;
+                    let archived = Self::handle_dormant_agents(
+                        &config,
+                        |username| clients.contains_key(username),
+                        &mut agents,
+                        &mut market,
+                        &mut archived_agents,
+                    ) | archive_changed.swap(false, Ordering::Relaxed);
+                    permit.send(StoreSnapshot {
+                        agents: agents.clone(),
+                        archived_agents: archived.then(|| archived_agents.clone()),
+                        leaderboard_days: market.leaderboard_history.take_unsaved().to_vec(),
+                        balance_reports: market.telemetry.take_finished(),
+                        market: market.clone(),
                     });
-                    info!("Agents: {:#?}", agents);
-                    save_agents(&agents).expect("Failed to store agents to disk");
-                    save_market(&market).expect("Failed to store market to disk");
                 }
             }
         });
 
-        let signing_key = load_keys().unwrap_or_else(|_| {
-            let key_pair =
-                russh_keys::key::KeyPair::generate_ed25519().expect("Failed to generate key pair");
-            let signing_key = match key_pair {
-                russh_keys::key::KeyPair::Ed25519(signing_key) => signing_key,
+        // The SSH config is built for each connection, so that the login banner is up to date.
+        let listener = tokio::net::TcpListener::bind(("0.0.0.0", port)).await?;
+        let mut banners = BannerCache::default();
+        loop {
+            let (socket, address) = listener.accept().await?;
+            let config = banners.config(self.login_banner().await, |banner| Config {
+                auth_rejection_time: std::time::Duration::from_secs(2),
+                auth_rejection_time_initial: Some(std::time::Duration::from_secs(0)),
+                keys: vec![key_pair.clone()],
+                keepalive_interval: Some(std::time::Duration::from_secs(
+                    self.config.session.keepalive_interval_seconds,
+                )),
+                keepalive_max: self.config.session.keepalive_max_missed,
+                auth_banner: Some(banner),
+                ..Default::default()
+            });
+            let handler = self.new_client(Some(address));
+            tokio::spawn(async move {
+                let session = match run_stream(config, socket, handler).await {
+                    Ok(session) => session,
+                    Err(e) => {
+                        debug!("Connection setup failed: {}", e);
+                        return;
+                    }
+                };
+                if let Err(e) = session.await {
+                    debug!("Connection closed with error: {}", e);
+                }
+            });
+        }
+    }
+
+    /// The banner shown before logging in.
+    async fn login_banner(&self) -> String {
+        let max_players = self.config.session.max_players;
+        let number_of_players = self.clients.lock().await.len().min(max_players);
+        let (date, market_status) = {
+            let snapshot = self.snapshot.borrow();
+            let market_status = match snapshot.phase {
+                GamePhase::Day { .. } => "the market is open",
+                GamePhase::Night { .. } => "the market is closed for the night",
             };
-            save_keys(&signing_key).expect("Failed to save SSH keys.");
-            signing_key
-        });
+            (snapshot.phase.formatted_date(), market_status)
+        };
+        let mut banner = format!(
+            "Welcome to Stonks!\r\n{}/{} players online - {}, {}\r\n",
+            number_of_players, max_players, date, market_status
+        );
+        if !self.config.session.motd.is_empty() {
+            banner.push_str(&format!("{}\r\n", self.config.session.motd));
+        }
+        banner
+    }
 
-        let key_pair = russh_keys::key::KeyPair::Ed25519(signing_key);
+    /// The server status report, with whether the server is healthy.
+    async fn status_report(&self) -> (String, bool) {
+        // The clock is read before taking the other locks, as in the render loop.
+        let clock = self.clock.lock().await.clone();
+        let number_of_players = self.clients.lock().await.len();
+        match self.status.lock() {
+            Ok(mut status) => status.report(&clock, number_of_players, Instant::now()),
+            Err(_) => ("Server status unavailable\r\n".to_string(), false),
+        }
+    }
 
-        let config = Config {
-            auth_rejection_time: std::time::Duration::from_secs(2),
-            auth_rejection_time_initial: Some(std::time::Duration::from_secs(0)),
-            keys: vec![key_pair],
-            keepalive_interval: Some(std::time::Duration::from_secs(30)),
-            ..Default::default()
+    /// Creates the game client of the session, if it was not started yet.
+    async fn start_game(&mut self, session: &mut Session) {
+        let Some(channel) = self.game_channel.take() else {
+            return;
+        };
+        let mut client = match Client::new(
+            self.session_auth.username.clone(),
+            session.handle(),
+            channel,
+            self.config.session.max_bytes_per_second,
+        ) {
+            Ok(client) => client,
+            Err(e) => {
+                error!("Failed to create client: {}", e);
+                let error_string = "\n\rFailed to create client. sorry!\n".to_string();
+                session.disconnect(Disconnect::ByApplication, error_string.as_str(), "");
+                session.close(channel);
+                return;
+            }
         };
+        client.set_admin(!self.guest && self.config.admins.contains(&self.session_auth.username));
+        let mut clients = self.clients.lock().await;
+        let mut agents = self.agents.lock().await;
+        if let Some(agent) = agents.get_mut(&self.session_auth.username) {
+            client.show_whats_new(agent);
+            if agent.totp_secret().is_some() {
+                client.require_login_code();
+            }
+        }
+        clients.insert(self.session_auth.username.clone(), client);
+    }
+}
 
-        self.run_on_address(Arc::new(config), ("0.0.0.0", port))
-            .await?;
+fn refresh_statistics(statistics: &mut ServerStatistics, cycle: usize) -> AppResult<()> {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_secs();
+    audit::flush();
+    statistics.refresh(&audit_log_path()?, cycle, now)?;
+    save_statistics(statistics)
+}
 
-        Ok(())
+// russh only accepts a static banner in the config of a connection, so each distinct banner is
+// leaked and reused while it is current. An older banner is freed once the connections it was
+// built for have all closed, so that only the banners of open connections are kept.
+#[derive(Default)]
+struct BannerCache {
+    // Banners with the configs of the connections showing them, the current one last.
+    banners: Vec<(&'static str, Vec<Weak<Config>>)>,
+}
+
+impl BannerCache {
+    /// The config of a new connection showing the banner.
+    fn config(
+        &mut self,
+        banner: String,
+        config: impl FnOnce(&'static str) -> Config,
+    ) -> Arc<Config> {
+        let entry = match self.banners.iter().position(|(text, _)| *text == banner) {
+            Some(index) => self.banners.remove(index),
+            None => (&*Box::leak(banner.into_boxed_str()), vec![]),
+        };
+        let config = Arc::new(config(entry.0));
+        self.banners.push(entry);
+        self.evict_stale();
+        if let Some((_, configs)) = self.banners.last_mut() {
+            configs.push(Arc::downgrade(&config));
+        }
+        config
+    }
+
+    fn evict_stale(&mut self) {
+        let current = self.banners.len().saturating_sub(1);
+        let mut index = 0;
+        self.banners.retain(|(text, configs)| {
+            let is_stale =
+                index < current && configs.iter().all(|config| config.strong_count() == 0);
+            index += 1;
+            if is_stale {
+                // SAFETY: the banner was leaked from a Box above, and every config borrowing it
+                // has been dropped, so nothing can read it anymore.
+                drop(unsafe { Box::from_raw(*text as *const str as *mut str) });
+            }
+            !is_stale
+        });
     }
 }
 
@@ -338,16 +1093,65 @@ impl Handler for AppServer {
         session: &mut Session,
     ) -> Result<bool, Self::Error> {
         info!("User connected with {:?}", self.session_auth);
+        if let Some(api_token) = self.api_token.as_ref() {
+            // Bot sessions only run commands, so no client is created.
+            let valid = self
+                .agents
+                .lock()
+                .await
+                .get(&self.session_auth.username)
+                .map(|agent| agent.has_api_token(api_token))
+                .unwrap_or(false);
+            if !valid {
+                audit::record(AuditEvent::LoginRejected {
+                    username: self.session_auth.username.clone(),
+                    reason: "Invalid API token".to_string(),
+                });
+                session.disconnect(Disconnect::ByApplication, "Invalid API token", "");
+                session.close(channel.id());
+                return Ok(false);
+            }
+            return Ok(true);
+        }
+
+        if self.session_auth.username == STATUS_USERNAME {
+            // Monitoring sessions get the report and are disconnected, no client is created.
+            let (report, healthy) = self.status_report().await;
+            session.data(channel.id(), CryptoVec::from(report));
+            session.exit_status_request(channel.id(), if healthy { 0 } else { 1 });
+            session.eof(channel.id());
+            session.close(channel.id());
+            return Ok(true);
+        }
+
+        // Read before locking the agents, as the data handler locks the market first.
+        let protected_usernames = Self::protected_usernames(&*self.market.lock().await);
+        // The clients are locked before the agents, as in the game loop.
+        let clients = self.clients.lock().await;
         let mut agents = self.agents.lock().await;
+        let mut archived_agents = self.archived_agents.lock().await;
+
+        if Self::restore_archived_agent(
+            &mut agents,
+            &mut archived_agents,
+            &self.session_auth.username,
+            self.session_auth.hashed_password,
+        ) {
+            self.archive_changed.store(true, Ordering::Relaxed);
+        }
+        let is_new_agent = !agents.contains_key(&self.session_auth.username);
 
         // If session_auth.username is in the persisted agents db, we check the password
         let mut agent = if let Some(db_agent) = agents.get_mut(&self.session_auth.username) {
-            if db_agent
+            if !db_agent
                 .session_auth
                 .check_password(self.session_auth.hashed_password)
-                == false
             {
-                let error_string = format!("\n\rWrong password.\n");
+                audit::record(AuditEvent::LoginRejected {
+                    username: self.session_auth.username.clone(),
+                    reason: "Wrong password".to_string(),
+                });
+                let error_string = "\n\rWrong password.\n".to_string();
                 session.disconnect(Disconnect::ByApplication, error_string.as_str(), "");
                 session.close(channel.id());
                 return Ok(false);
@@ -357,43 +1161,59 @@ impl Handler for AppServer {
         }
         // Else, we check the username and persist it
         else {
-            if self.session_auth.username.len() < MIN_USERNAME_LENGTH
-                || self.session_auth.username.len() > MAX_USERNAME_LENGTH
-            {
-                let error_string = format!(
-                    "\n\rInvalid username. The username must have between {} and {} characters.\n",
-                    MIN_USERNAME_LENGTH, MAX_USERNAME_LENGTH
-                );
+            if let Err(e) = Self::check_username_available(
+                &self.config,
+                &agents,
+                &archived_agents,
+                &protected_usernames,
+                &self.session_auth.username,
+                &self.session_auth.username,
+            ) {
+                audit::record(AuditEvent::LoginRejected {
+                    username: self.session_auth.username.clone(),
+                    reason: e.to_string(),
+                });
+                let error_string = format!("\n\rInvalid username. {}\n", e);
                 session.disconnect(Disconnect::ByApplication, error_string.as_str(), "");
                 session.close(channel.id());
                 return Ok(false);
             }
-            let new_agent = UserAgent::new(self.session_auth.clone());
+            let mut new_agent = UserAgent::new(self.session_auth.clone());
+            new_agent.set_guest(self.guest);
             debug!("New agent created");
             new_agent
         };
 
-        let mut clients = self.clients.lock().await;
-
-        agent.session_auth.update_last_active_time();
-        let username = agent.username().to_string();
-        agents.insert(agent.username().to_string(), agent.clone());
-
-        let try_client = Client::new(username.clone(), session.handle(), channel.id());
-
-        if try_client.is_err() {
-            let error_string = format!("\n\rFailed to create client. sorry!\n",);
+        drop(archived_agents);
+
+        if clients.len() >= self.config.session.max_players
+            && !clients.contains_key(&self.session_auth.username)
+        {
+            audit::record(AuditEvent::LoginRejected {
+                username: self.session_auth.username.clone(),
+                reason: "Server full".to_string(),
+            });
+            let error_string = format!(
+                "\n\rThe server is full ({} players), please try again later.\n",
+                self.config.session.max_players
+            );
             session.disconnect(Disconnect::ByApplication, error_string.as_str(), "");
             session.close(channel.id());
             return Ok(false);
         }
 
-        debug!("Have fun {}!", username);
+        agent.session_auth.update_last_active_time();
+        let username = agent.username().to_string();
+        agents.insert(agent.username().to_string(), agent.clone());
 
-        clients.insert(
+        debug!("Have fun {}!", username);
+        audit::record(AuditEvent::Login {
             username,
-            try_client.expect("Client error has already been checked."),
-        );
+            new_agent: is_new_agent,
+        });
+
+        // The game starts with the shell, so that nothing is drawn on SFTP channels.
+        self.game_channel = Some(channel.id());
 
         Ok(true)
     }
@@ -402,24 +1222,18 @@ impl Handler for AppServer {
         let agents = self.agents.lock().await;
         let username = if let Some(agent) = agents.get(user) {
             agent.username().to_string()
-        } else if user.len() == 0 {
+        } else if user.is_empty() {
             Self::generate_user_id()
         } else {
             user.to_string()
         };
-
-        let mut hasher = Sha256::new();
-        let salted_password = format!("{}{}", password, AUTH_PASSWORD_SALT);
-        hasher.update(salted_password);
-        let hashed_password = hasher.finalize().to_vec()[..].try_into()?;
+        self.guest = user.is_empty();
+        self.api_token = ApiToken::parse(password).map(|_| password.to_string());
+        let hashed_password = Self::hash_password(password);
 
         // We defer checking username and password to channel_open_session so that it is possible
         // to send informative error messages to the user using session.write.
-        self.session_auth = SessionAuth {
-            username,
-            hashed_password,
-            last_active_time: SystemTime::now(),
-        };
+        self.session_auth = SessionAuth::new(username, hashed_password);
 
         Ok(Auth::Accept)
     }
@@ -433,11 +1247,12 @@ impl Handler for AppServer {
         let agents = self.agents.lock().await;
         let username = if let Some(agent) = agents.get(user) {
             agent.username().to_string()
-        } else if user.len() == 0 {
+        } else if user.is_empty() {
             Self::generate_user_id()
         } else {
             user.to_string()
         };
+        self.guest = user.is_empty();
 
         let mut hasher = Sha256::new();
         let salted_password = format!("{}{}", public_key.fingerprint(), AUTH_PUBLIC_KEY_SALT);
@@ -446,68 +1261,209 @@ impl Handler for AppServer {
 
         // We defer checking username and password to channel_open_session so that it is possible
         // to send informative error messages to the user using session.write.
-        self.session_auth = SessionAuth {
-            username,
-            hashed_password,
-            last_active_time: SystemTime::now(),
-        };
+        self.session_auth = SessionAuth::new(username, hashed_password);
 
         Ok(Auth::Accept)
     }
 
+    async fn exec_request(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        let command = String::from_utf8_lossy(data).to_string();
+        debug!(
+            "{} requested command {}",
+            self.session_auth.username, command
+        );
+
+        let command_name = command.split_whitespace().next();
+        let is_admin_command = command_name.is_some_and(|name| api::ADMIN_COMMANDS.contains(&name));
+        let is_passport_command =
+            command_name.is_some_and(|name| api::PASSPORT_COMMANDS.contains(&name));
+
+        let result = if let Some(api_token) = self.api_token.as_ref() {
+            // Locked in the same order as in the data handler.
+            let _clients = self.clients.lock().await;
+            let mut market = self.market.lock().await;
+            let mut agents = self.agents.lock().await;
+            match agents.get_mut(&self.session_auth.username) {
+                // Errors are not Send, so keep only the message across the clock lock.
+                Some(agent) => match agent
+                    .use_api_token(api_token, Instant::now())
+                    .map_err(|e| e.to_string())
+                {
+                    Err(e) => Err(e.into()),
+                    Ok(_) if is_passport_command => match self.signing_key.as_deref() {
+                        Some(signing_key) => api::execute_passport_command(
+                            &command,
+                            &market,
+                            &mut agents,
+                            &self.session_auth.username,
+                            signing_key,
+                            &self.config.passports,
+                        ),
+                        None => Err("Passports are not available on this server".into()),
+                    },
+                    Ok(_) if !is_admin_command => api::execute_command(&command, &market, agent),
+                    Ok(_) if self.config.admins.contains(&self.session_auth.username) => {
+                        let mut clock = self.clock.lock().await;
+                        let result = api::execute_admin_command(
+                            &command,
+                            &mut clock,
+                            &mut market,
+                            &mut agents,
+                        );
+                        info!(
+                            "{} ran admin command {}: {}",
+                            self.session_auth.username,
+                            command,
+                            clock.status()
+                        );
+                        self.clock_changed.notify_one();
+                        result
+                    }
+                    Ok(_) => Err("Admin commands are reserved to the server admins".into()),
+                },
+                None => Err("Agent not found".into()),
+            }
+        } else {
+            Err("Commands need an API token as password, create one in game with `k`.".into())
+        };
+
+        let (output, exit_status) = match result {
+            Ok(response) => (format!("{}\n", response), 0),
+            Err(e) => (format!("{}\n", json!({ "error": e.to_string() })), 1),
+        };
+        session.data(channel, CryptoVec::from(output));
+        session.exit_status_request(channel, exit_status);
+        session.eof(channel);
+        session.close(channel);
+        Ok(())
+    }
+
     async fn data(
         &mut self,
         channel: ChannelId,
         data: &[u8],
         session: &mut Session,
     ) -> Result<(), Self::Error> {
+        if self.api_token.is_some() {
+            return Ok(());
+        }
+        if let Some(sftp) = self.sftp.as_mut() {
+            match sftp.feed(data) {
+                Ok(response) if !response.is_empty() => {
+                    session.data(channel, CryptoVec::from(response))
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    debug!("Closing SFTP session: {}", e);
+                    session.close(channel);
+                }
+            }
+            return Ok(());
+        }
+
         let mut clients = self.clients.lock().await;
-        let number_of_players = clients.len();
         let mut end_session = false;
+        let mut renamed_to = None;
 
         if let Some(client) = clients.get_mut(&self.session_auth.username) {
-            let event = convert_data_to_crossterm_event(data);
-            debug!("{:?}", event);
-            match event {
-                Some(Event::Mouse(..)) => {}
-                Some(Event::Key(key_event)) => match key_event.code {
-                    KeyCode::Esc => {
-                        let mut agents = self.agents.lock().await;
-                        let agent = agents
-                            .get_mut(client.username())
-                            .expect("Agent should have been persisted");
-                        agent.clear_action();
-                        agent.session_auth.update_last_active_time();
-                        client
-                            .tui
-                            .exit()
-                            .await
-                            .unwrap_or_else(|e| error!("Error exiting tui: {}", e));
-                        end_session = true;
-                    }
-                    _ => {
-                        let market = self.market.lock().await;
-                        let mut agents = self.agents.lock().await;
-                        let agent = agents
-                            .get_mut(client.username())
-                            .expect("Agent should have been persisted");
+            for event in client.decode_input(data) {
+                debug!("{:?}", event);
+                let Event::Key(key_event) = event else {
+                    continue;
+                };
+
+                if key_event.code == KeyCode::Esc || client.is_quit_key(&key_event) {
+                    let mut agents = self.agents.lock().await;
+                    let agent = agents
+                        .get_mut(client.username())
+                        .expect("Agent should have been persisted");
+                    agent.clear_actions();
+                    agent.session_auth.update_last_active_time();
+                    audit::record(AuditEvent::Logout {
+                        username: agent.username().to_string(),
+                    });
+                    client
+                        .tui
+                        .exit()
+                        .await
+                        .unwrap_or_else(|e| error!("Error exiting tui: {}", e));
+                    end_session = true;
+                    break;
+                }
 
-                        agent.session_auth.update_last_active_time();
-                        client
-                            .handle_key_events(key_event, &market, agent)
-                            .map_err(|e| anyhow::anyhow!("Error: {}", e))?;
+                let mut market = self.market.lock().await;
+                let mut agents = self.agents.lock().await;
+                let agent = agents
+                    .get_mut(client.username())
+                    .expect("Agent should have been persisted");
+
+                agent.session_auth.update_last_active_time();
+                client
+                    .handle_key_events(key_event, &market, agent)
+                    .map_err(|e| anyhow::anyhow!("Error: {}", e))?;
+
+                if client.is_locked_out() {
+                    audit::record(AuditEvent::LoginRejected {
+                        username: agent.username().to_string(),
+                        reason: "Wrong two-factor code".to_string(),
+                    });
+                    end_session = true;
+                    break;
+                }
 
-                        client
-                            .draw(&market, &agent, number_of_players)
-                            .unwrap_or_else(|e| error!("Failed to draw: {}", e));
+                if let Some((username, password)) = client.take_account_request() {
+                    let archived_agents = self.archived_agents.lock().await;
+                    let result = if agent.is_guest() {
+                        Self::upgrade_guest(
+                            &self.config,
+                            &mut agents,
+                            &archived_agents,
+                            &mut market,
+                            client.username(),
+                            &username,
+                            &password,
+                        )
+                    } else {
+                        Self::rename_agent(
+                            &self.config,
+                            &mut agents,
+                            &archived_agents,
+                            &mut market,
+                            client.username(),
+                            &username,
+                        )
+                    };
+                    if result.is_ok() {
+                        audit::record(AuditEvent::UsernameChanged {
+                            old_username: client.username().to_string(),
+                            username: username.clone(),
+                        });
+                        client.set_username(username.clone());
+                        renamed_to = Some(username);
                     }
-                },
-                _ => {}
+                    client.resolve_account_request(result);
+                }
+                // The render loop draws the new state at its next pass, once for all the keys.
+                client.mark_dirty();
             }
         } else {
             end_session = true;
         }
 
+        // A renamed agent is now tracked under the new username.
+        if let Some(username) = renamed_to {
+            if let Some(client) = clients.remove(&self.session_auth.username) {
+                clients.insert(username.clone(), client);
+            }
+            self.session_auth.username = username;
+            self.guest = false;
+        }
+
         if end_session {
             clients.remove(&self.session_auth.username);
             session.disconnect(Disconnect::ByApplication, "Game quit", "");
@@ -525,7 +1481,10 @@ impl Handler for AppServer {
     ) -> Result<(), Self::Error> {
         info!("Handling channel_close for {}", self.session_auth.username);
         let mut clients = self.clients.lock().await;
-        clients.remove(&self.session_auth.username);
+        // Bot and SFTP sessions must not end the game session of the same player.
+        if self.api_token.is_none() && self.sftp.is_none() {
+            clients.remove(&self.session_auth.username);
+        }
         session.disconnect(Disconnect::ByApplication, "Game quit", "");
         session.close(channel);
 
@@ -540,17 +1499,75 @@ impl Handler for AppServer {
     ) -> Result<(), Self::Error> {
         info!("Handling channel_eof for {}", self.session_auth.username);
         let mut clients = self.clients.lock().await;
-        clients.remove(&self.session_auth.username);
+        if self.api_token.is_none()
+            && self.sftp.is_none()
+            && clients.remove(&self.session_auth.username).is_some()
+        {
+            audit::record(AuditEvent::Logout {
+                username: self.session_auth.username.clone(),
+            });
+        }
         session.disconnect(Disconnect::ByApplication, "Game quit", "");
         session.eof(channel);
 
         Ok(())
     }
 
+    async fn shell_request(
+        &mut self,
+        channel: ChannelId,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        self.start_game(session).await;
+        session.channel_success(channel);
+        Ok(())
+    }
+
+    async fn subsystem_request(
+        &mut self,
+        channel: ChannelId,
+        name: &str,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        if name != "sftp" || self.game_channel != Some(channel) {
+            session.channel_failure(channel);
+            return Ok(());
+        }
+        self.game_channel = None;
+
+        // Locked in the same order as in the game loop, the clients first.
+        let _clients = self.clients.lock().await;
+        let agents = self.agents.lock().await;
+        let market = self.market.lock().await;
+        // The login code cannot be asked over SFTP, so it is not available with two-factor
+        // authentication.
+        let Some(agent) = agents
+            .get(&self.session_auth.username)
+            .filter(|agent| agent.totp_secret().is_none())
+        else {
+            session.channel_failure(channel);
+            return Ok(());
+        };
+        info!("{} started an SFTP session", self.session_auth.username);
+        let mut files = sftp::export_files(&market, agent);
+        // The latest session recording, if the player made one.
+        if let Ok(contents) =
+            recording_path(&self.session_auth.username).and_then(|path| Ok(std::fs::read(path)?))
+        {
+            files.push(VirtualFile {
+                name: RECORDING_SFTP_FILENAME,
+                contents,
+            });
+        }
+        self.sftp = Some(SftpSession::new(files));
+        session.channel_success(channel);
+        Ok(())
+    }
+
     async fn pty_request(
         &mut self,
         channel: ChannelId,
-        _: &str,
+        term: &str,
         col_width: u32,
         row_height: u32,
         pix_width: u32,
@@ -558,19 +1575,42 @@ impl Handler for AppServer {
         _: &[(Pty, u32)],
         session: &mut Session,
     ) -> Result<(), Self::Error> {
+        self.start_game(session).await;
+        {
+            let mut clients = self.clients.lock().await;
+            if let Some(client) = clients.get_mut(&self.session_auth.username) {
+                client.set_term(term);
+            }
+        }
         self.window_change_request(
             channel, col_width, row_height, pix_width, pix_height, session,
         )
         .await
     }
 
+    async fn env_request(
+        &mut self,
+        _: ChannelId,
+        variable_name: &str,
+        variable_value: &str,
+        _: &mut Session,
+    ) -> Result<(), Self::Error> {
+        if variable_name == "COLORTERM" {
+            let mut clients = self.clients.lock().await;
+            if let Some(client) = clients.get_mut(&self.session_auth.username) {
+                client.set_colorterm(variable_value);
+            }
+        }
+        Ok(())
+    }
+
     async fn window_change_request(
         &mut self,
         _: ChannelId,
         col_width: u32,
         row_height: u32,
-        _: u32,
-        _: u32,
+        pix_width: u32,
+        pix_height: u32,
         _: &mut Session,
     ) -> Result<(), Self::Error> {
         debug!("Window resize request");
@@ -578,9 +1618,297 @@ impl Handler for AppServer {
         if let Some(client) = clients.get_mut(&self.session_auth.username) {
             client
                 .tui
-                .resize(col_width as u16, row_height as u16)
+                .resize(
+                    col_width as u16,
+                    row_height as u16,
+                    pix_width as u16,
+                    pix_height as u16,
+                )
                 .map_err(|e| anyhow::anyhow!("Resize error: {}", e))?;
+            client.mark_dirty();
+            client.record_resize(col_width as u16, row_height as u16);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{panic_message, AgentsDatabase, AppServer, BannerCache, STATUS_USERNAME};
+    use crate::{
+        agent::{AgentAction, DecisionAgent, UserAgent},
+        config::ServerConfig,
+        leaderboard::DAYS_PER_SEASON,
+        market::{GamePhase, Market},
+        money::Money,
+        ssh_client::SessionAuth,
+        stonk::StonkClass,
+        utils::AppResult,
+    };
+    use russh::server::Config;
+    use std::time::{Duration, SystemTime};
+
+    // An agent as loaded from the store, last active the given number of days ago.
+    fn dormant_agent(username: &str, days: u64) -> AppResult<UserAgent> {
+        let mut session_auth = SessionAuth::new(username.to_string(), [0; 32]);
+        session_auth.last_active_time =
+            SystemTime::now() - Duration::from_secs(days * 60 * 60 * 24);
+        let agent = UserAgent::new(serde_json::from_str(&serde_json::to_string(
+            &session_auth,
+        )?)?);
+        Ok(agent)
+    }
+
+    #[tokio::test]
+    async fn test_login_banner() {
+        let mut config = ServerConfig::default();
+        config.session.max_players = 42;
+        config.session.motd = "Double dividends this week".to_string();
+        let server = AppServer::with_state(config, AgentsDatabase::default(), Market::new());
+
+        let banner = server.login_banner().await;
+        assert!(banner.contains("0/42 players online - 1 Spring 2025, the market is open"));
+        assert!(banner.contains("Double dividends this week"));
+
+        // Identical banners are shared, older ones are freed once their connections closed.
+        let mut banners = BannerCache::default();
+        let config = |banner| Config {
+            auth_banner: Some(banner),
+            ..Default::default()
+        };
+        let first = banners.config(banner.clone(), config);
+        let second = banners.config(banner, config);
+        assert!(std::ptr::eq(
+            first.auth_banner.unwrap_or_default(),
+            second.auth_banner.unwrap_or_default()
+        ));
+        let next_day = banners.config("Another day".to_string(), config);
+        assert_eq!(banners.banners.len(), 2);
+        drop(first);
+        banners.config("Another day".to_string(), config);
+        assert_eq!(banners.banners.len(), 2);
+        drop(second);
+        banners.config("Another day".to_string(), config);
+        assert_eq!(banners.banners.len(), 1);
+        assert_eq!(next_day.auth_banner, Some("Another day"));
+    }
+
+    #[tokio::test]
+    async fn test_status_report() {
+        let config = ServerConfig::default();
+        let server =
+            AppServer::with_state(config.clone(), AgentsDatabase::default(), Market::new());
+
+        let (report, _) = server.status_report().await;
+        assert!(report.contains("players: 0"));
+        assert!(report.contains("last save: never"));
+
+        // Nobody can register the status login.
+        assert!(AppServer::check_username_available(
+            &config,
+            &AgentsDatabase::default(),
+            &AgentsDatabase::default(),
+            &[],
+            STATUS_USERNAME,
+            STATUS_USERNAME,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_panic_message() {
+        let payload = std::panic::catch_unwind(|| panic!("Failed rendering {}", 42))
+            .expect_err("Closure should panic");
+        assert_eq!(panic_message(payload.as_ref()), "Failed rendering 42");
+
+        let payload = std::panic::catch_unwind(|| panic!("Static message"))
+            .expect_err("Closure should panic");
+        assert_eq!(panic_message(payload.as_ref()), "Static message");
+    }
+
+    #[test]
+    fn test_dormant_agents() -> AppResult<()> {
+        let config = ServerConfig::default();
+        let mut market = Market::new();
+        let mut agents = AgentsDatabase::default();
+        let mut guest = dormant_agent("guest", 2)?;
+        guest.set_guest(true);
+        for mut agent in [
+            guest,
+            dormant_agent("resting", 2)?,
+            dormant_agent("away", 40)?,
+            dormant_agent("gone", 100)?,
+            dormant_agent("online", 100)?,
+        ] {
+            agent.select_action(AgentAction::Buy {
+                stonk_id: 0,
+                amount: 1,
+            });
+            market.apply_agent_actions(&mut agent, &mut AgentsDatabase::default())?;
+            agents.insert(agent.username().to_string(), agent);
+        }
+
+        let mut archived_agents = AgentsDatabase::default();
+        assert!(AppServer::handle_dormant_agents(
+            &config,
+            |username| username == "online",
+            &mut agents,
+            &mut market,
+            &mut archived_agents,
+        ));
+
+        let mut usernames = agents.keys().cloned().collect::<Vec<String>>();
+        usernames.sort();
+        assert_eq!(usernames, vec!["away", "online", "resting"]);
+        assert_eq!(agents["resting"].owned_stonks()[0], 1);
+        assert_eq!(agents["away"].owned_stonks()[0], 0);
+        assert_eq!(archived_agents.keys().collect::<Vec<_>>(), vec!["gone"]);
+        assert_eq!(archived_agents["gone"].owned_stonks()[0], 0);
+        let mut shareholders = market.stonks[0]
+            .shareholders
+            .iter()
+            .map(|(username, _)| username.as_str())
+            .collect::<Vec<_>>();
+        shareholders.sort();
+        assert_eq!(shareholders, vec!["online", "resting"]);
+
+        // Archived usernames cannot be registered by new players.
+        assert!(AppServer::check_username_available(
+            &config,
+            &agents,
+            &archived_agents,
+            &[],
+            "gone",
+            "newcomer",
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dormant_agent_futures_and_offshore_cash() -> AppResult<()> {
+        let config = ServerConfig::default();
+        let mut market = Market::new();
+        let commodity = market
+            .stonks
+            .iter()
+            .find(|stonk| stonk.class == StonkClass::Commodity)
+            .map(|stonk| stonk.id)
+            .expect("A Commodity stonk should exist");
+        let mut agent = dormant_agent("farmer", 100)?;
+        for action in [
+            AgentAction::OpenFutures {
+                stonk_id: commodity,
+                amount: 10,
+                is_long: true,
+            },
+            AgentAction::ExchangeCurrency {
+                to_offshore: true,
+                amount: Money::from_dollars(1_000),
+            },
+        ] {
+            market.apply_agent_action(&mut agent, &action, &mut AgentsDatabase::default())?;
         }
+        let margin = market
+            .futures
+            .contracts_of("farmer")
+            .map(|contract| contract.margin)
+            .sum::<Money>();
+        let (exchanged, _) = market
+            .offshore
+            .exchange_rate
+            .convert(agent.offshore_cash(), false);
+        let cash = agent.cash();
+        let mut agents = AgentsDatabase::from([("farmer".to_string(), agent)]);
+
+        // Without shares, the futures and the offshore cash still go back to the player
+        // before the account is archived.
+        let mut archived_agents = AgentsDatabase::default();
+        assert!(AppServer::handle_dormant_agents(
+            &config,
+            |_| false,
+            &mut agents,
+            &mut market,
+            &mut archived_agents,
+        ));
+        assert_eq!(market.futures.contracts_of("farmer").count(), 0);
+        let farmer = &archived_agents["farmer"];
+        assert_eq!(farmer.offshore_cash(), Money::ZERO);
+        assert_eq!(
+            farmer.cash(),
+            cash.saturating_add(margin).saturating_add(exchanged)
+        );
+
+        // Nothing is left for the market to keep at the end of the season.
+        let treasury = market.treasury.balance();
+        market.phase = GamePhase::Day {
+            cycle: DAYS_PER_SEASON,
+            counter: 0,
+        };
+        market.settle_futures(&mut agents);
+        assert_eq!(market.treasury.balance(), treasury);
+        Ok(())
+    }
+
+    #[test]
+    fn test_archive_survives_restart() -> AppResult<()> {
+        // The store is written and read back like on a restart of the server.
+        let restart = |agents: &AgentsDatabase| -> AppResult<AgentsDatabase> {
+            Ok(serde_json::from_str(&serde_json::to_string(agents)?)?)
+        };
+        let config = ServerConfig::default();
+        let mut market = Market::new();
+        let mut agents = AgentsDatabase::default();
+        agents.insert("gone".to_string(), dormant_agent("gone", 100)?);
+        let mut archived_agents = AgentsDatabase::default();
+        assert!(AppServer::handle_dormant_agents(
+            &config,
+            |_| false,
+            &mut agents,
+            &mut market,
+            &mut archived_agents,
+        ));
+        let (mut agents, mut archived_agents) = (restart(&agents)?, restart(&archived_agents)?);
+
+        // A wrong password leaves the archive alone.
+        assert!(!AppServer::restore_archived_agent(
+            &mut agents,
+            &mut archived_agents,
+            "gone",
+            [1; 32],
+        ));
+        assert!(AppServer::restore_archived_agent(
+            &mut agents,
+            &mut archived_agents,
+            "gone",
+            [0; 32],
+        ));
+        assert!(archived_agents.is_empty());
+        let agent = agents.get_mut("gone").expect("Agent should be restored");
+        let cash = agent.add_cash(1_000.into())?;
+
+        // The archive changed, so it is saved together with the agents.
+        let (mut agents, mut archived_agents) = (restart(&agents)?, restart(&archived_agents)?);
+        assert!(!AppServer::restore_archived_agent(
+            &mut agents,
+            &mut archived_agents,
+            "gone",
+            [0; 32],
+        ));
+        assert_eq!(agents["gone"].cash(), cash);
+
+        // A stale archived copy never overwrites the live agent.
+        let mut stale = agents["gone"].clone();
+        stale.sub_cash(1_000.into())?;
+        archived_agents.insert("gone".to_string(), stale);
+        assert!(AppServer::restore_archived_agent(
+            &mut agents,
+            &mut archived_agents,
+            "gone",
+            [0; 32],
+        ));
+        assert!(archived_agents.is_empty());
+        assert_eq!(agents["gone"].cash(), cash);
         Ok(())
     }
 }
@@ -0,0 +1,174 @@
+// All-time statistics of the server, aggregated from the audit log. Scanning the log is slow,
+// so the statistics are refreshed once per day and cached on disk, which also keeps the
+// records of the audit files dropped by the rotation.
+
+use crate::{
+    agent::INITIAL_USER_CASH,
+    audit::{self, AuditEvent},
+    money::Money,
+    utils::AppResult,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::Path};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DayCrash {
+    pub stonk: String,
+    pub cycle: usize,
+    pub day_return: f64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerStatistics {
+    pub total_trades: u64,
+    // Starting cash of the new players and cash given out of nothing.
+    pub money_printed: Money,
+    pub biggest_crash: Option<DayCrash>,
+    // Character assassinations suffered by each player.
+    assassinations: HashMap<String, usize>,
+    // Records logged before this timestamp, in seconds since the unix epoch, are counted.
+    scanned_until: u64,
+    // Day of the last refresh.
+    refreshed_cycle: Option<usize>,
+}
+
+impl ServerStatistics {
+    /// Statistics ignoring the records logged before the given timestamp, for a reset server.
+    pub fn starting_at(timestamp: u64) -> Self {
+        Self {
+            scanned_until: timestamp,
+            ..Default::default()
+        }
+    }
+
+    pub fn record(&mut self, event: &AuditEvent) {
+        match event {
+            AuditEvent::Action {
+                action,
+                error: None,
+                ..
+            } if action.is_trade() => {
+                self.total_trades += 1;
+            }
+            AuditEvent::Login {
+                new_agent: true, ..
+            } => {
+                self.money_printed = self.money_printed.saturating_add(INITIAL_USER_CASH);
+            }
+            AuditEvent::CashPrinted { amount_cents, .. } => {
+                self.money_printed = self.money_printed.saturating_add(*amount_cents);
+            }
+            AuditEvent::DayClosed {
+                cycle,
+                stonk_returns,
+            } => {
+                for (stonk, day_return) in stonk_returns.iter().filter(|(_, r)| *r < 0.0) {
+                    if self
+                        .biggest_crash
+                        .as_ref()
+                        .is_none_or(|crash| *day_return < crash.day_return)
+                    {
+                        self.biggest_crash = Some(DayCrash {
+                            stonk: stonk.clone(),
+                            cycle: *cycle,
+                            day_return: *day_return,
+                        });
+                    }
+                }
+            }
+            AuditEvent::AssassinationResolved { target, .. } => {
+                *self.assassinations.entry(target.clone()).or_default() += 1;
+            }
+            AuditEvent::UsernameChanged {
+                old_username,
+                username,
+            } => {
+                if let Some(count) = self.assassinations.remove(old_username) {
+                    *self.assassinations.entry(username.clone()).or_default() += count;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The player hit by the most character assassinations, the first by name on a tie.
+    pub fn most_assassinated(&self) -> Option<(&str, usize)> {
+        self.assassinations
+            .iter()
+            .max_by(|(a, a_count), (b, b_count)| a_count.cmp(b_count).then(b.cmp(a)))
+            .map(|(username, count)| (username.as_str(), *count))
+    }
+
+    pub fn needs_refresh(&self, cycle: usize) -> bool {
+        self.refreshed_cycle != Some(cycle)
+    }
+
+    /// Adds the records logged since the last refresh until the given timestamp.
+    pub fn refresh(&mut self, audit_path: &Path, cycle: usize, now: u64) -> AppResult<()> {
+        for event in audit::read_records(audit_path, self.scanned_until, now)? {
+            self.record(&event);
+        }
+        self.scanned_until = self.scanned_until.max(now);
+        self.refreshed_cycle = Some(cycle);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ServerStatistics;
+    use crate::{agent::AgentAction, audit::AuditEvent, money::Money};
+
+    #[test]
+    fn test_server_statistics() {
+        let mut statistics = ServerStatistics::default();
+        let buy = AgentAction::Buy {
+            stonk_id: 0,
+            amount: 1,
+        };
+        statistics.record(&AuditEvent::Action {
+            username: "alice".to_string(),
+            tick: 1,
+            action: buy.clone(),
+            error: None,
+        });
+        statistics.record(&AuditEvent::Action {
+            username: "alice".to_string(),
+            tick: 2,
+            action: buy,
+            error: Some("Not enough cash".to_string()),
+        });
+        assert_eq!(statistics.total_trades, 1);
+
+        statistics.record(&AuditEvent::CashPrinted {
+            username: "alice".to_string(),
+            amount_cents: Money::from_cents(250),
+        });
+        assert_eq!(statistics.money_printed, Money::from_cents(250));
+
+        for (cycle, day_return) in [(2, 0.1), (3, -0.2), (4, -0.5), (5, -0.1)] {
+            statistics.record(&AuditEvent::DayClosed {
+                cycle,
+                stonk_returns: vec![("Doge".to_string(), day_return)],
+            });
+        }
+        let crash = statistics.biggest_crash.clone().expect("Missing crash");
+        assert_eq!((crash.cycle, crash.day_return), (4, -0.5));
+
+        for target in ["bob", "carol", "carol"] {
+            statistics.record(&AuditEvent::AssassinationResolved {
+                attacker: "alice".to_string(),
+                target: target.to_string(),
+            });
+        }
+        statistics.record(&AuditEvent::UsernameChanged {
+            old_username: "bob".to_string(),
+            username: "robert".to_string(),
+        });
+        statistics.record(&AuditEvent::AssassinationResolved {
+            attacker: "alice".to_string(),
+            target: "robert".to_string(),
+        });
+        assert_eq!(statistics.most_assassinated(), Some(("carol", 2)));
+    }
+}
@@ -1,10 +1,36 @@
-use crate::utils::AppResult;
+use crate::{
+    conditions::{Condition, ConditionSet, Stacking},
+    events::DIVIDEND_PAYOUT,
+    formatting::{number_format, Currency},
+    logging,
+    market::{DAY_LENGTH, NUMBER_OF_STONKS},
+    money::Money,
+    price_series::PriceSeries,
+    utils::AppResult,
+};
 use rand::Rng;
 use rand_distr::{Cauchy, Distribution, Normal};
 use serde::{Deserialize, Serialize};
+use std::{
+    ops::{Index, IndexMut},
+    sync::Arc,
+};
 use tracing::{debug, info};
 
-const MAX_PRICE_DRIFT: f64 = 0.2;
+pub const MAX_PRICE_DRIFT: f64 = 0.2;
+// Shock probability while a stonk has the IncreasedShockProbability condition.
+pub const SHOCK_PROBABILITY_MULTIPLIER: f64 = 2.0;
+pub const MAX_SHOCK_PROBABILITY: f64 = 0.2;
+// Prices cannot go below the starting price divided by this.
+pub const MIN_PRICE_DIVISOR: u32 = 100;
+// Prices are pushed back when they move this many times away from the starting price.
+pub const PRICE_CONTROL_FACTOR: f64 = 8.0;
+// Percentiles of the simulated prices returned by Stonk::projection.
+pub const PROJECTION_PERCENTILES: [usize; 3] = [10, 50, 90];
+// Trades after hours, during the night, pay this many times the spread of the day.
+pub const AFTER_HOURS_SPREAD_MULTIPLIER: f64 = 5.0;
+// Largest stake of a stonk that can be traded at once after hours.
+pub const AFTER_HOURS_MAX_STAKE: f64 = 0.002;
 
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum StonkClass {
@@ -19,6 +45,34 @@ pub enum StonkClass {
 pub enum StonkCondition {
     Bump { amount: f64 },
     IncreasedShockProbability,
+    // Trades happen at the price scaled by 1 + factor: a discount when negative, a premium
+    // when positive. The price itself does not move.
+    Mispriced { factor: f64 },
+}
+
+impl Condition for StonkCondition {
+    fn stacking(&self) -> Stacking {
+        match self {
+            Self::Bump { .. } => Stacking::Stack,
+            Self::IncreasedShockProbability => Stacking::Extend,
+            // A new flash sale replaces the running one.
+            Self::Mispriced { .. } => Stacking::Refresh,
+        }
+    }
+}
+
+// Background of a stonk, shown in its About page and used by the news.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StonkLore {
+    #[serde(default)]
+    pub founded: usize,
+    #[serde(default)]
+    pub ceo: String,
+    // Ids of the competing stonks.
+    #[serde(default)]
+    pub rivals: Vec<usize>,
+    #[serde(default)]
+    pub paragraphs: Vec<String>,
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -35,10 +89,25 @@ pub struct Stonk {
     drift: f64,            // Cauchy dist mean, changes the mean price percentage variation
     drift_volatility: f64, // Influences the rate of change of drift, must be positive
     volatility: f64, // Cauchy dist variance, changes the variance of the price percentage variation, must be positive
+    // Depth of the order book, in shares: buying this many shares doubles the price of the next one.
+    // Sets the spread and the price impact of trades.
+    #[serde(default)]
+    liquidity: f64,
     pub shock_probability: f64, // probability to select the Cauchy dist rather than the Guassian one
     pub starting_price: u32,
-    pub historical_prices: Vec<u32>,
-    conditions: Vec<(usize, StonkCondition)>,
+    pub historical_prices: PriceSeries,
+    conditions: ConditionSet<StonkCondition>,
+    // Set by the market during the night, when trades pay a wider spread.
+    #[serde(default)]
+    after_hours: bool,
+    // Set by the admins while the parameters of the stonk are fixed: nobody can trade it and its
+    // price stays put, while the holdings are kept.
+    #[serde(default)]
+    suspended: bool,
+    // Read from the stonks data rather than stored with the market, so edits to it show up
+    // after a restart.
+    #[serde(default, skip_serializing)]
+    pub lore: StonkLore,
 }
 
 impl Stonk {
@@ -47,6 +116,25 @@ impl Stonk {
         self.shareholders.sort_by(|(_, a), (_, b)| b.cmp(a));
     }
 
+    fn debug_assert_invariants(&self) {
+        debug_assert!(
+            self.allocated_shares <= self.number_of_shares,
+            "Stonk {} has {} allocated shares out of {}",
+            self.id,
+            self.allocated_shares,
+            self.number_of_shares
+        );
+        debug_assert_eq!(
+            self.shareholders
+                .iter()
+                .map(|(_, amount)| *amount as u64)
+                .sum::<u64>(),
+            self.allocated_shares as u64,
+            "Stonk {} shareholders do not match allocated shares",
+            self.id
+        );
+    }
+
     #[allow(dead_code)]
     pub(crate) fn set_test_values(
         &mut self,
@@ -66,6 +154,74 @@ impl Stonk {
         self.shock_probability = shock_probability;
     }
 
+    #[cfg(test)]
+    pub(crate) fn set_test_history(&mut self, historical_prices: Vec<u32>) {
+        if let Some(&price) = historical_prices.last() {
+            self.price_per_share_in_cents = price;
+        }
+        self.historical_prices = PriceSeries::new(0, historical_prices);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_test_liquidity(&mut self, liquidity: f64) {
+        self.liquidity = liquidity;
+    }
+
+    pub fn volatility(&self) -> f64 {
+        self.volatility
+    }
+
+    /// Depth of the order book, in shares.
+    pub fn liquidity(&self) -> f64 {
+        1.0 / self.spread()
+    }
+
+    /// Relative increase of the price of each share bought after the previous one.
+    pub fn spread(&self) -> f64 {
+        self.book_spread() * self.spread_multiplier()
+    }
+
+    // Spread of the order book during the day.
+    fn book_spread(&self) -> f64 {
+        if self.liquidity > 0.0 {
+            1.0 / self.liquidity
+        } else {
+            // Stonks stored before the liquidity was introduced used the volatility.
+            self.volatility
+        }
+    }
+
+    fn spread_multiplier(&self) -> f64 {
+        if self.after_hours {
+            AFTER_HOURS_SPREAD_MULTIPLIER
+        } else {
+            1.0
+        }
+    }
+
+    pub fn is_after_hours(&self) -> bool {
+        self.after_hours
+    }
+
+    pub fn set_after_hours(&mut self, after_hours: bool) {
+        self.after_hours = after_hours;
+    }
+
+    /// The largest amount that can be traded at once: unbounded during the day, a small stake
+    /// of the stonk after hours.
+    pub fn max_trade_amount(&self) -> u32 {
+        if self.after_hours {
+            ((self.number_of_shares as f64 * AFTER_HOURS_MAX_STAKE) as u32).max(1)
+        } else {
+            u32::MAX
+        }
+    }
+
+    /// Drift bump of a trade of the given amount, in units of liquidity traded.
+    pub fn trade_impact(&self, amount: u32) -> f64 {
+        amount as f64 * self.spread()
+    }
+
     pub fn to_stake(&self, amount: u32) -> f64 {
         amount as f64 / self.number_of_shares as f64
     }
@@ -74,27 +230,27 @@ impl Stonk {
         let share = self.to_stake(amount) * 100.0;
         if share >= 5.0 {
             format!(
-                "Price ${:.02} - Drift {:.03}% - Volatility {:.03}%",
-                self.price_per_share_in_cents as f64 / 100.0,
+                "Price {} - Drift {:.03}% - Volatility {:.03}%",
+                self.price_per_share_in_cents.format_exact_dollars(),
                 self.drift * 100.0,
                 self.volatility * 100.0
             )
         } else if share >= 1.0 {
             format!(
-                "Price ${:.02} - Drift {:.03}%",
-                self.price_per_share_in_cents as f64 / 100.0,
+                "Price {} - Drift {:.03}%",
+                self.price_per_share_in_cents.format_exact_dollars(),
                 self.drift * 100.0
             )
         } else {
             format!(
-                "Price ${:.02}",
-                self.price_per_share_in_cents as f64 / 100.0
+                "Price {}",
+                self.price_per_share_in_cents.format_exact_dollars()
             )
         }
     }
 
-    pub fn market_cap_cents(&self) -> u64 {
-        self.price_per_share_in_cents as u64 * self.number_of_shares as u64
+    pub fn market_cap(&self) -> Money {
+        Money::from(self.price_per_share_in_cents).saturating_mul(self.number_of_shares as u64)
     }
 
     pub fn available_amount(&self) -> u32 {
@@ -120,15 +276,18 @@ impl Stonk {
         if let Some((_, old_amount)) = self
             .shareholders
             .iter_mut()
-            .find(|(holder, _)| *holder == username.to_string())
+            .find(|(holder, _)| holder == username)
         {
             *old_amount += amount
         } else {
             self.shareholders.push((username.to_string(), amount))
         }
         self.sort_shareholders();
+        self.debug_assert_invariants();
 
-        info!("New shareholders: {:#?}", self.shareholders);
+        if logging::detailed_logs() {
+            info!("New shareholders: {:#?}", self.shareholders);
+        }
 
         Ok(())
     }
@@ -147,26 +306,62 @@ impl Stonk {
     }
 
     pub fn deallocate_shares_to_agent(&mut self, username: &str, amount: u32) -> AppResult<()> {
-        self.deallocate_shares(amount)?;
-
-        if let Some((_, old_amount)) = self
+        // Check the shareholder before deallocating, so that a failure leaves the stonk untouched.
+        let Some(index) = self
             .shareholders
-            .iter_mut()
-            .find(|(holder, _)| *holder == username.to_string())
-        {
-            if amount > *old_amount {
-                return Err("Amount is greater than number of shares owned by agent.".into());
-            }
-            *old_amount -= amount
-        } else {
+            .iter()
+            .position(|(holder, _)| holder == username)
+        else {
             return Err("Agent is not a shareholder".into());
+        };
+        if amount > self.shareholders[index].1 {
+            return Err("Amount is greater than number of shares owned by agent.".into());
         }
+
+        self.deallocate_shares(amount)?;
+        self.shareholders[index].1 -= amount;
         self.sort_shareholders();
-        info!("New shareholders: {:#?}", self.shareholders);
+        self.debug_assert_invariants();
+        if logging::detailed_logs() {
+            info!("New shareholders: {:#?}", self.shareholders);
+        }
 
         Ok(())
     }
 
+    /// Removes the shareholder entry of the agent, returning the shares it held.
+    pub fn remove_shareholder(&mut self, username: &str) -> u32 {
+        let Some(index) = self
+            .shareholders
+            .iter()
+            .position(|(holder, _)| holder == username)
+        else {
+            return 0;
+        };
+        let (_, amount) = self.shareholders.remove(index);
+        self.allocated_shares = self.allocated_shares.saturating_sub(amount);
+        self.debug_assert_invariants();
+        amount
+    }
+
+    /// Multiplies the price by the factor, as for a dividend paid out of the company.
+    pub fn scale_price(&mut self, factor: f64) {
+        self.price_per_share_in_cents = ((self.price_per_share_in_cents as f64 * factor) as u32)
+            .max(self.starting_price / MIN_PRICE_DIVISOR);
+    }
+
+    /// Retires shares nobody holds, raising the price so that the market cap stays the same.
+    pub fn retire_shares(&mut self, amount: u32) -> AppResult<()> {
+        if amount == 0 || amount > self.available_amount() || amount >= self.number_of_shares {
+            return Err("Not enough unheld shares to retire.".into());
+        }
+        let factor = self.number_of_shares as f64 / (self.number_of_shares - amount) as f64;
+        self.number_of_shares -= amount;
+        self.scale_price(factor);
+        self.debug_assert_invariants();
+        Ok(())
+    }
+
     fn apply_conditions(&mut self, current_tick: usize) {
         for (_, condition) in self.conditions.iter() {
             match condition {
@@ -174,27 +369,44 @@ impl Stonk {
                 StonkCondition::IncreasedShockProbability => {
                     // This condition is checked during the stonk tick
                 }
+                StonkCondition::Mispriced { .. } => {
+                    // This condition is checked when trading
+                }
             }
         }
 
-        self.conditions
-            .retain(|(until_tick, _)| *until_tick > current_tick);
+        self.conditions.expire(current_tick);
     }
 
     pub fn add_condition(&mut self, condition: StonkCondition, until_tick: usize) {
-        self.conditions.push((until_tick, condition));
+        self.conditions.add(condition, until_tick);
+    }
+
+    pub fn has_condition(&self, condition: StonkCondition) -> bool {
+        self.conditions.has(&condition)
     }
 
-    pub fn tick(&mut self, current_tick: usize) {
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    pub fn set_suspended(&mut self, suspended: bool) {
+        self.suspended = suspended;
+    }
+
+    pub fn tick<R: Rng>(&mut self, current_tick: usize, rng: &mut R) {
+        // The price of a suspended stonk is frozen, but its chart keeps going.
+        if self.suspended {
+            self.historical_prices
+                .push(current_tick, self.price_per_share_in_cents);
+            self.conditions.expire(current_tick);
+            return;
+        }
+
         self.apply_conditions(current_tick);
 
-        let rng = &mut rand::thread_rng();
-        let shock_probability = if self
-            .conditions
-            .iter()
-            .any(|(_, condition)| *condition == StonkCondition::IncreasedShockProbability)
-        {
-            (2.0 * self.shock_probability).min(0.2)
+        let shock_probability = if self.has_condition(StonkCondition::IncreasedShockProbability) {
+            (SHOCK_PROBABILITY_MULTIPLIER * self.shock_probability).min(MAX_SHOCK_PROBABILITY)
         } else {
             self.shock_probability
         };
@@ -209,25 +421,27 @@ impl Stonk {
                         .expect("Failed to sample tick distribution")
                         .sample(rng)
         }
-        .min(MAX_PRICE_DRIFT)
-        .max(-MAX_PRICE_DRIFT);
+        .clamp(-MAX_PRICE_DRIFT, MAX_PRICE_DRIFT);
 
         self.price_per_share_in_cents = ((self.price_per_share_in_cents as f64
             * (1.0 + price_drift)) as u32)
-            .max(self.starting_price / 100); // Cannot go below one hundreth of starting price
-
-        self.historical_prices.push(self.price_per_share_in_cents);
-
-        debug!(
-            "{:15} μ={:+.5} σ={:.5} Δ={:+.5} shock={:.03} price={}\n{:?}",
-            self.name,
-            self.drift,
-            self.volatility,
-            price_drift,
-            self.shock_probability,
-            self.price_per_share_in_cents,
-            self.conditions,
-        );
+            .max(self.starting_price / MIN_PRICE_DIVISOR);
+
+        self.historical_prices
+            .push(current_tick, self.price_per_share_in_cents);
+
+        if logging::detailed_logs() {
+            debug!(
+                "{:15} μ={:+.5} σ={:.5} Δ={:+.5} shock={:.03} price={}\n{:?}",
+                self.name,
+                self.drift,
+                self.volatility,
+                price_drift,
+                self.shock_probability,
+                self.price_per_share_in_cents,
+                self.conditions,
+            );
+        }
 
         self.drift /= 2.0;
         self.add_condition(
@@ -238,15 +452,64 @@ impl Stonk {
         );
 
         // Add control mechanisms for extreme prices. not ideal.
-        if (self.price_per_share_in_cents as f64) < self.starting_price as f64 / 8.0 {
+        if (self.price_per_share_in_cents as f64)
+            < self.starting_price as f64 / PRICE_CONTROL_FACTOR
+        {
             self.add_condition(StonkCondition::Bump { amount: 1.0 }, current_tick + 1);
             self.add_condition(StonkCondition::IncreasedShockProbability, current_tick + 1);
-        } else if (self.price_per_share_in_cents as f64) > self.starting_price as f64 * 8.0 {
+        } else if (self.price_per_share_in_cents as f64)
+            > self.starting_price as f64 * PRICE_CONTROL_FACTOR
+        {
             self.add_condition(StonkCondition::Bump { amount: -1.0 }, current_tick + 1);
             self.add_condition(StonkCondition::IncreasedShockProbability, current_tick + 1);
         }
     }
 
+    /// Simulates the next ticks along many random paths, with the same math as Stonk::tick,
+    /// and returns for every future tick the PROJECTION_PERCENTILES of the simulated prices.
+    pub fn projection<R: Rng>(
+        &self,
+        current_tick: usize,
+        ticks: usize,
+        paths: usize,
+        rng: &mut R,
+    ) -> Vec<[u32; 3]> {
+        // Only what drives the price is copied, the history and shareholders are not needed.
+        let base = Stonk {
+            id: self.id,
+            price_per_share_in_cents: self.price_per_share_in_cents,
+            drift: self.drift,
+            drift_volatility: self.drift_volatility,
+            volatility: self.volatility,
+            shock_probability: self.shock_probability,
+            starting_price: self.starting_price,
+            conditions: self.conditions.clone(),
+            ..Default::default()
+        };
+
+        let mut prices = vec![Vec::with_capacity(paths); ticks];
+        for _ in 0..paths {
+            let mut stonk = base.clone();
+            for (tick, tick_prices) in prices.iter_mut().enumerate() {
+                stonk.tick(current_tick + tick, rng);
+                tick_prices.push(stonk.price_per_share_in_cents);
+            }
+        }
+
+        prices
+            .iter_mut()
+            .map(|tick_prices| {
+                tick_prices.sort_unstable();
+                PROJECTION_PERCENTILES.map(|percentile| {
+                    tick_prices
+                        .get(tick_prices.len().saturating_sub(1) * percentile / 100)
+                        .copied()
+                        .unwrap_or(self.price_per_share_in_cents)
+                })
+            })
+            .collect()
+    }
+
     fn base_price(&self) -> u32 {
         // let mut price = 0;
         // for l in 0..amount {
@@ -260,70 +523,162 @@ impl Stonk {
         self.price_per_share_in_cents
     }
 
-    fn buy_price(&self, amount: u32) -> u32 {
-        // The price to buy the first share is base_price * ( 1.0 + volatility ).
-        // Each subsequent share adds one unit of volatility
-        // ( 1.0 + 2.0*volatility ) , ( 1.0 + 3.0*volatility ) ....
+    pub fn conditions(&self) -> &ConditionSet<StonkCondition> {
+        &self.conditions
+    }
+
+    /// The mispricing factor of the stonk and the tick it ends at, while it is mispriced.
+    pub fn mispricing(&self) -> Option<(f64, usize)> {
+        self.conditions
+            .iter()
+            .find_map(|(until_tick, condition)| match condition {
+                StonkCondition::Mispriced { factor } => Some((*factor, *until_tick)),
+                _ => None,
+            })
+    }
+
+    // Price of a share in trades, in cents, before the spread.
+    fn mispriced_unit_price(&self) -> f64 {
+        let factor = self.mispricing().map_or(0.0, |(factor, _)| factor);
+        self.base_price() as f64 * (1.0 + factor).max(0.0)
+    }
+
+    // Price of a share in trades. A stonk crashed to the floor can show a zero price,
+    // but shares must never be free.
+    fn trade_unit_price(&self) -> u32 {
+        (self.mispriced_unit_price() as u32).max(1)
+    }
+
+    // Value of buying the given amount in cents, before rounding.
+    fn buy_value(&self, amount: u32) -> f64 {
+        // The price to buy the first share is base_price * ( 1.0 + spread ).
+        // Each subsequent share adds one unit of spread
+        // ( 1.0 + 2.0*spread ) , ( 1.0 + 3.0*spread ) ....
         // so that the total price is just the summation
-        // giving base_price * amount * ( 1.0 + (amount + 1.0) / 2.0 * volatility )
-        ((self.base_price() * amount) as f64 * (1.0 + (amount + 1) as f64 / 2.0 * self.volatility))
-            as u32
+        // giving base_price * amount * ( 1.0 + (amount + 1.0) / 2.0 * spread )
+        self.trade_unit_price() as f64
+            * amount as f64
+            * (1.0 + (amount as f64 + 1.0) / 2.0 * self.spread())
     }
 
-    fn sell_price(&self, amount: u32) -> u32 {
-        // The price to sell the first share is base_price * ( 1.0 - volatility ).
-        // Each subsequent share adds one unit of volatility
-        // ( 1.0 - 2.0*volatility ) , ( 1.0 - 3.0*volatility ) ....
+    // Value of selling the given amount in cents, before rounding.
+    fn sell_value(&self, amount: u32) -> f64 {
+        // The price to sell the first share is base_price * ( 1.0 - spread ).
+        // Each subsequent share adds one unit of spread
+        // ( 1.0 - 2.0*spread ) , ( 1.0 - 3.0*spread ) ....
         // so that the total price is just the summation
-        // giving base_price * amount * ( 1.0 - (amount + 1.0) / 2.0 * volatility )
-        // Notice that the volatility is then contrained by
-        // 1 - number_of_shares * volatility >= 0 ==> volatility <= 1/number_of_shares
-        ((self.base_price() * amount) as f64
-            * (1.0
-                - (amount + 1) as f64 / 2.0
-                    * self.volatility.min(1.0 / self.number_of_shares as f64))) as u32
+        // giving base_price * amount * ( 1.0 - (amount + 1.0) / 2.0 * spread )
+        // Notice that the spread is then contrained by
+        // 1 - number_of_shares * spread >= 0 ==> spread <= 1/number_of_shares
+        // After hours the constrained spread is widened, so the value is floored at zero.
+        let spread =
+            self.book_spread().min(1.0 / self.number_of_shares as f64) * self.spread_multiplier();
+        self.mispriced_unit_price()
+            * amount as f64
+            * (1.0 - (amount as f64 + 1.0) / 2.0 * spread).max(0.0)
     }
 
-    fn current_price(&self) -> u32 {
-        self.base_price()
+    /// The cost of buying the given amount, saturating: only use it for display.
+    pub fn buy_price(&self, amount: u32) -> Money {
+        Money::saturating_from_f64_cents(self.buy_value(amount))
     }
 
-    pub fn buy_price_cents(&self, amount: u32) -> u32 {
-        self.buy_price(amount)
+    /// The cost of buying the given amount, or None if it cannot be represented.
+    pub fn checked_buy_price(&self, amount: u32) -> Option<Money> {
+        Money::from_f64_cents(self.buy_value(amount))
     }
 
-    pub fn sell_price_cents(&self, amount: u32) -> u32 {
-        self.sell_price(amount)
+    pub fn sell_price(&self, amount: u32) -> Money {
+        Money::saturating_from_f64_cents(self.sell_value(amount))
     }
 
     pub fn current_unit_price_cents(&self) -> u32 {
-        self.current_price()
+        self.base_price()
     }
 
-    pub fn max_buy_amount(&self, cash: u32) -> u32 {
+    /// Value of the given amount of shares at the current price.
+    pub fn holdings_value(&self, amount: u32) -> Money {
+        Money::from(self.current_unit_price_cents()).saturating_mul(amount as u64)
+    }
+
+    /// The largest amount that can be bought with the given cash, at most the available shares.
+    pub fn max_buy_amount(&self, cash: Money) -> u32 {
+        let price = self.trade_unit_price() as f64;
+        let cash_cents = cash.as_f64_cents();
         // We need to solve cash == buy_price(amount) for amount
         // and then take the floor of amount
-        // cash == base_price * amount * (1.0 + (amount + 1) / 2.0 * volatility)
-        let max_amount = (-(2.0 + self.volatility)
-            + (8.0 * cash as f64 * self.volatility / self.base_price() as f64
-                + (2.0 + self.volatility).powf(2.0))
-            .powf(0.5))
-            / (2.0 * self.volatility);
-        max_amount as u32
+        // cash == base_price * amount * (1.0 + (amount + 1) / 2.0 * spread)
+        let spread = self.spread();
+        let max_amount = if spread > 0.0 {
+            (-(2.0 + spread)
+                + (8.0 * cash_cents * spread / price + (2.0 + spread).powf(2.0)).powf(0.5))
+                / (2.0 * spread)
+        } else {
+            cash_cents / price
+        };
+        // NaN becomes 0 and the cast saturates to the u32 bounds.
+        let mut max_amount = (max_amount as u32).min(self.available_amount());
+        // Rounding errors can make the last share unaffordable.
+        while max_amount > 0
+            && self
+                .checked_buy_price(max_amount)
+                .is_none_or(|cost| cost > cash)
+        {
+            max_amount -= 1;
+        }
+        max_amount
+    }
+
+    /// Relative price change over the day starting at the given tick, or None if the day is
+    /// not fully in the history or the opening price is zero.
+    pub fn day_return(&self, day_start_tick: usize) -> Option<f64> {
+        let day_end_tick = day_start_tick + DAY_LENGTH;
+        if day_start_tick < self.historical_prices.start_tick()
+            || day_end_tick > self.historical_prices.end_tick()
+        {
+            return None;
+        }
+        let opening_price = self.historical_prices.open_of_day(day_start_tick)?;
+        let closing_price = self.historical_prices.at_tick(day_end_tick - 1)?;
+        if opening_price == 0 {
+            return None;
+        }
+        Some((closing_price as f64 - opening_price as f64) / opening_price as f64)
+    }
+
+    /// Dividend paid on the given amount of shares, or None if the stonk did not gain during
+    /// the day starting at the given tick.
+    pub fn dividend(&self, amount: u32, day_start_tick: usize) -> Option<Money> {
+        let day_return = self.day_return(day_start_tick).filter(|r| *r > 0.0)?;
+        Some(
+            self.holdings_value(amount)
+                .scale(DIVIDEND_PAYOUT * day_return),
+        )
+    }
+
+    /// The largest amount that can be sold out of the owned one.
+    pub fn max_sell_amount(&self, owned: u32) -> u32 {
+        owned.min(self.allocated_shares)
     }
 }
 
 pub trait DollarValue {
     fn as_dollars(&self) -> f64;
+    /// The amount in the number format of the server, without currency symbol.
     fn format(&self) -> String {
-        let value = self.as_dollars();
-        if value > 1_000_000.0 {
-            format!("{:.03}M", value / 1_000_000.0)
-        } else if value > 1_000.0 {
-            format!("{:.03}k", value / 1_000.0)
-        } else {
-            format!("{:.02}", value)
-        }
+        number_format().amount(self.as_dollars())
+    }
+    /// The amount in the number format of the server, with the symbol of the currency.
+    fn format_in(&self, currency: Currency) -> String {
+        number_format().money(self.as_dollars(), currency)
+    }
+    fn format_dollars(&self) -> String {
+        self.format_in(Currency::Dollar)
+    }
+    /// The amount of dollars to the cent, even when the server abbreviates amounts.
+    fn format_exact_dollars(&self) -> String {
+        let format = number_format();
+        format.with_symbol(&format.decimal(self.as_dollars(), 2), Currency::Dollar)
     }
 }
 
@@ -338,3 +693,210 @@ impl DollarValue for u64 {
         *self as f64 / 100.0
     }
 }
+
+// The stonks of the market. Each stonk is shared with the copies of the market, like the
+// snapshots being saved or the practice sandboxes, and only copied when one of them changes it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Stonks([Arc<Stonk>; NUMBER_OF_STONKS]);
+
+impl Stonks {
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = &Stonk> + DoubleEndedIterator {
+        self.0.iter().map(|stonk| stonk.as_ref())
+    }
+
+    pub fn iter_mut(&mut self) -> impl ExactSizeIterator<Item = &mut Stonk> {
+        self.0.iter_mut().map(Arc::make_mut)
+    }
+
+    pub fn get(&self, stonk_id: usize) -> Option<&Stonk> {
+        self.0.get(stonk_id).map(|stonk| stonk.as_ref())
+    }
+
+    pub fn get_mut(&mut self, stonk_id: usize) -> Option<&mut Stonk> {
+        self.0.get_mut(stonk_id).map(Arc::make_mut)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether the stonk is shared with a copy of the market.
+    pub fn is_shared(&self, stonk_id: usize) -> bool {
+        Arc::strong_count(&self.0[stonk_id]) > 1
+    }
+}
+
+impl From<[Stonk; NUMBER_OF_STONKS]> for Stonks {
+    fn from(stonks: [Stonk; NUMBER_OF_STONKS]) -> Self {
+        Self(stonks.map(Arc::new))
+    }
+}
+
+impl Index<usize> for Stonks {
+    type Output = Stonk;
+
+    fn index(&self, stonk_id: usize) -> &Stonk {
+        &self.0[stonk_id]
+    }
+}
+
+// Changing a stonk copies it first if it is shared.
+impl IndexMut<usize> for Stonks {
+    fn index_mut(&mut self, stonk_id: usize) -> &mut Stonk {
+        Arc::make_mut(&mut self.0[stonk_id])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Stonk, StonkCondition, Stonks};
+    use crate::{
+        market::{DAY_LENGTH, NUMBER_OF_STONKS},
+        money::Money,
+        price_series::PriceSeries,
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    #[test]
+    fn test_projection() {
+        let mut stonk = Stonk::default();
+        stonk.set_test_values(10_000, 1_000, 0.0, 0.01, 0.02, 0.01);
+        let rng = &mut ChaCha8Rng::seed_from_u64(0);
+        let projection = stonk.projection(0, 20, 200, rng);
+
+        assert_eq!(projection.len(), 20);
+        assert!(stonk.historical_prices.is_empty());
+        for [low, median, high] in projection.iter() {
+            assert!(low <= median && median <= high);
+        }
+        // The cone widens with time.
+        let spread = |[low, _, high]: [u32; 3]| high - low;
+        assert!(spread(projection[19]) > spread(projection[0]));
+
+        let rng = &mut ChaCha8Rng::seed_from_u64(0);
+        assert_eq!(stonk.projection(0, 20, 200, rng), projection);
+    }
+
+    #[test]
+    fn test_trade_amounts_with_degenerate_prices() {
+        let mut stonk = Stonk::default();
+
+        // A crashed stonk still costs at least a cent per share.
+        stonk.set_test_values(0, 1_000, 0.0, 0.0, 0.0, 0.0);
+        assert_eq!(stonk.max_buy_amount(Money::from_cents(500)), 500);
+        assert_eq!(stonk.max_buy_amount(Money::MAX), 1_000);
+        assert_eq!(stonk.checked_buy_price(10), Some(Money::from_cents(10)));
+
+        // Large positions are priced exactly rather than wrapping around.
+        stonk.set_test_values(u32::MAX, u32::MAX, 0.0, 0.0, 0.0, 0.0);
+        assert_eq!(
+            stonk.checked_buy_price(1_000),
+            Some(Money::from_cents(u32::MAX as u64 * 1_000))
+        );
+        assert_eq!(
+            stonk.max_buy_amount(Money::from_cents(u32::MAX as u64 * 2)),
+            2
+        );
+
+        // Values that do not fit the money type are rejected rather than saturated.
+        stonk.set_test_values(u32::MAX, u32::MAX, 0.0, 0.0, 1.0, 0.0);
+        assert_eq!(stonk.checked_buy_price(u32::MAX), None);
+        assert_eq!(stonk.buy_price(u32::MAX), Money::MAX);
+
+        stonk.allocated_shares = 10;
+        assert_eq!(stonk.max_sell_amount(25), 10);
+        assert_eq!(stonk.max_sell_amount(3), 3);
+    }
+
+    #[test]
+    fn test_liquidity_sets_the_spread() {
+        // A volatile but liquid stonk trades closer to its price than a calm but illiquid one.
+        let mut meme = Stonk::default();
+        meme.set_test_values(1_000, 100_000, 0.0, 0.01, 0.05, 0.1);
+        meme.set_test_liquidity(50_000.0);
+        let mut small_cap = Stonk::default();
+        small_cap.set_test_values(1_000, 1_000, 0.0, 0.01, 0.0001, 0.0);
+        small_cap.set_test_liquidity(1_000.0);
+
+        assert!(meme.buy_price(100) < small_cap.buy_price(100));
+        assert!(meme.sell_price(100) > small_cap.sell_price(100));
+        assert!(meme.trade_impact(100) < small_cap.trade_impact(100));
+        assert_eq!(small_cap.buy_price(1_000), Money::from_cents(1_500_500));
+        assert_eq!(meme.max_buy_amount(meme.buy_price(100)), 100);
+    }
+
+    #[test]
+    fn test_mispriced_trades() {
+        let mut stonk = Stonk::default();
+        stonk.set_test_values(1_000, 10_000, 0.0, 0.0, 0.0, 0.0);
+        let (buy, sell) = (stonk.buy_price(10), stonk.sell_price(10));
+
+        stonk.add_condition(StonkCondition::Mispriced { factor: -0.1 }, 5);
+        assert_eq!(stonk.mispricing(), Some((-0.1, 5)));
+        assert_eq!(stonk.current_unit_price_cents(), 1_000);
+        assert_eq!(stonk.buy_price(10), buy.scale(0.9));
+        assert_eq!(stonk.sell_price(10), sell.scale(0.9));
+        assert!(stonk.max_buy_amount(buy) > 10);
+
+        stonk.apply_conditions(5);
+        assert_eq!(stonk.mispricing(), None);
+        assert_eq!(stonk.buy_price(10), buy);
+    }
+
+    #[test]
+    fn test_day_return() {
+        let mut stonk = Stonk::default();
+        stonk.set_test_values(1_000, 1_000, 0.0, 0.0, 0.0, 0.0);
+        let day = |open: u32, close: u32| {
+            let mut prices = vec![open; DAY_LENGTH];
+            prices[DAY_LENGTH - 1] = close;
+            PriceSeries::new(10, prices)
+        };
+
+        stonk.historical_prices = day(100, 150);
+        assert_eq!(stonk.day_return(10), Some(0.5));
+        assert!(stonk.dividend(10, 10).is_some_and(|d| d > Money::ZERO));
+
+        // Days not fully in the history have no return.
+        assert_eq!(stonk.day_return(9), None);
+        assert_eq!(stonk.day_return(11), None);
+        assert_eq!(stonk.dividend(10, 11), None);
+
+        stonk.historical_prices = day(100, 50);
+        assert_eq!(stonk.day_return(10), Some(-0.5));
+        assert_eq!(stonk.dividend(10, 10), None);
+
+        stonk.historical_prices = day(0, 50);
+        assert_eq!(stonk.day_return(10), None);
+    }
+
+    #[test]
+    fn test_stonks_copy_on_write() -> serde_json::Result<()> {
+        let mut stonks = Stonks::from(std::array::from_fn::<_, NUMBER_OF_STONKS, _>(|id| Stonk {
+            id,
+            ..Default::default()
+        }));
+        let snapshot = stonks.clone();
+        assert!(stonks.is_shared(0));
+
+        // Only the changed stonk stops being shared with the snapshot.
+        stonks[0].set_test_values(1_000, 1_000, 0.0, 0.0, 0.0, 0.0);
+        assert!(!stonks.is_shared(0));
+        assert!(stonks.is_shared(1));
+        assert_eq!(stonks[0].current_unit_price_cents(), 1_000);
+        assert_ne!(snapshot[0].current_unit_price_cents(), 1_000);
+
+        // Stonks are stored as a plain list.
+        let stored = serde_json::to_string(&stonks)?;
+        assert!(stored.starts_with("[{"));
+        let loaded: Stonks = serde_json::from_str(&stored)?;
+        assert_eq!(loaded[0].current_unit_price_cents(), 1_000);
+        Ok(())
+    }
+}
@@ -0,0 +1,283 @@
+// Trading strategies of the NPC bots. A strategy looks at a read-only view of the market and
+// of its own account, and returns the trade to make, if any. Strategies are looked up by name
+// in a registry, so new ones can be added with register_strategy, without touching the
+// market or the server loop.
+
+use crate::{
+    agent::AgentAction,
+    market::{GamePhase, Market, NUMBER_OF_STONKS},
+    money::Money,
+    stonk::{Stonk, Stonks},
+    utils::AppResult,
+};
+use once_cell::sync::Lazy;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::sync::RwLock;
+
+// Largest share of its cash a bot spends in a single buy, at full aggressiveness.
+pub const MAX_TRADE_CASH_SHARE: f64 = 0.2;
+// Variation of the day, in percentage, followed by the momentum strategy.
+pub const MOMENTUM_THRESHOLD: f64 = 1.0;
+// Distance from the fair value, as a fraction, traded against by the contrarian strategy.
+pub const CONTRARIAN_THRESHOLD: f64 = 0.02;
+
+/// Read-only view of the market given to the strategies.
+pub struct MarketView<'a> {
+    market: &'a Market,
+}
+
+impl<'a> MarketView<'a> {
+    pub fn new(market: &'a Market) -> Self {
+        Self { market }
+    }
+
+    pub fn stonks(&self) -> &Stonks {
+        &self.market.stonks
+    }
+
+    pub fn last_tick(&self) -> usize {
+        self.market.last_tick
+    }
+
+    pub fn phase(&self) -> GamePhase {
+        self.market.phase
+    }
+
+    /// Price variation since the day opened, in percentage.
+    pub fn today_variation(&self, stonk_id: usize) -> f64 {
+        self.market.today_variation(stonk_id)
+    }
+
+    /// Average price over the last day, in cents.
+    pub fn fair_value(&self, stonk_id: usize) -> u32 {
+        self.market.fair_value(stonk_id)
+    }
+}
+
+/// View of the account trading with a strategy.
+pub struct AgentView<'a> {
+    pub name: &'a str,
+    pub cash: Money,
+    pub owned_stonks: &'a [u32; NUMBER_OF_STONKS],
+    // From 0 to 1, scales the size of the trades.
+    pub aggressiveness: f64,
+    // Fresh for every decision, drawn from the market randomness so that replays match.
+    pub seed: u64,
+}
+
+impl AgentView<'_> {
+    /// Buys as many shares as the given share of the cash affords, scaled by aggressiveness.
+    pub fn buy(&self, stonk: &Stonk, cash_share: f64) -> Option<AgentAction> {
+        let budget = self.cash.scale(cash_share * self.aggressiveness);
+        let amount = stonk.max_buy_amount(budget).min(stonk.available_amount());
+        (amount > 0).then_some(AgentAction::Buy {
+            stonk_id: stonk.id,
+            amount,
+        })
+    }
+
+    /// Sells part of the holdings, scaled by aggressiveness, at least one share.
+    pub fn sell(&self, stonk: &Stonk) -> Option<AgentAction> {
+        let owned = self.owned_stonks[stonk.id];
+        let amount = ((owned as f64 * self.aggressiveness) as u32).clamp(1, owned.max(1));
+        (owned > 0).then_some(AgentAction::Sell {
+            stonk_id: stonk.id,
+            amount,
+        })
+    }
+}
+
+pub trait Strategy: Send + Sync {
+    fn decide(&mut self, view: &MarketView, me: &AgentView) -> Option<AgentAction>;
+}
+
+pub type StrategyBuilder = fn() -> Box<dyn Strategy>;
+
+/// Trades a random stonk in a random direction.
+#[derive(Debug, Default)]
+pub struct NoiseStrategy;
+
+impl Strategy for NoiseStrategy {
+    fn decide(&mut self, view: &MarketView, me: &AgentView) -> Option<AgentAction> {
+        let rng = &mut ChaCha8Rng::seed_from_u64(me.seed);
+        let stonk = &view.stonks()[rng.gen_range(0..view.stonks().len())];
+        if me.owned_stonks[stonk.id] > 0 && rng.gen_bool(0.5) {
+            me.sell(stonk)
+        } else {
+            me.buy(stonk, MAX_TRADE_CASH_SHARE)
+        }
+    }
+}
+
+/// Sells the held stonks falling the most, and buys the ones rising the most.
+#[derive(Debug, Default)]
+pub struct MomentumStrategy;
+
+impl Strategy for MomentumStrategy {
+    fn decide(&mut self, view: &MarketView, me: &AgentView) -> Option<AgentAction> {
+        let by_variation = |a: &&Stonk, b: &&Stonk| {
+            view.today_variation(a.id)
+                .total_cmp(&view.today_variation(b.id))
+        };
+        let falling = view
+            .stonks()
+            .iter()
+            .filter(|stonk| me.owned_stonks[stonk.id] > 0)
+            .filter(|stonk| view.today_variation(stonk.id) <= -MOMENTUM_THRESHOLD)
+            .min_by(by_variation);
+        if let Some(stonk) = falling {
+            return me.sell(stonk);
+        }
+
+        let rising = view
+            .stonks()
+            .iter()
+            .filter(|stonk| view.today_variation(stonk.id) >= MOMENTUM_THRESHOLD)
+            .max_by(by_variation)?;
+        me.buy(rising, MAX_TRADE_CASH_SHARE)
+    }
+}
+
+/// Buys the stonks trading below their fair value, and sells the held ones above it.
+#[derive(Debug, Default)]
+pub struct ContrarianStrategy;
+
+impl Strategy for ContrarianStrategy {
+    fn decide(&mut self, view: &MarketView, me: &AgentView) -> Option<AgentAction> {
+        let deviation = |stonk: &Stonk| {
+            let fair_value = view.fair_value(stonk.id).max(1) as f64;
+            (stonk.current_unit_price_cents() as f64 - fair_value) / fair_value
+        };
+        let (stonk, deviation) = view
+            .stonks()
+            .iter()
+            .map(|stonk| (stonk, deviation(stonk)))
+            .filter(|(stonk, deviation)| {
+                *deviation <= -CONTRARIAN_THRESHOLD
+                    || (*deviation >= CONTRARIAN_THRESHOLD && me.owned_stonks[stonk.id] > 0)
+            })
+            .max_by(|(_, a), (_, b)| a.abs().total_cmp(&b.abs()))?;
+        if deviation > 0.0 {
+            me.sell(stonk)
+        } else {
+            me.buy(stonk, MAX_TRADE_CASH_SHARE)
+        }
+    }
+}
+
+static REGISTRY: Lazy<RwLock<Vec<(&'static str, StrategyBuilder)>>> = Lazy::new(|| {
+    RwLock::new(vec![
+        ("noise", || Box::new(NoiseStrategy)),
+        ("momentum", || Box::new(MomentumStrategy)),
+        ("contrarian", || Box::new(ContrarianStrategy)),
+    ])
+});
+
+/// Makes a strategy available to the bots. New bots cycle through the registered strategies.
+pub fn register_strategy(name: &'static str, builder: StrategyBuilder) -> AppResult<()> {
+    let mut registry = REGISTRY
+        .write()
+        .map_err(|_| "Strategy registry is poisoned")?;
+    if registry.iter().any(|(registered, _)| *registered == name) {
+        return Err(format!("Strategy {} is already registered", name).into());
+    }
+    registry.push((name, builder));
+    Ok(())
+}
+
+/// Names of the registered strategies, in registration order.
+pub fn strategy_names() -> Vec<&'static str> {
+    REGISTRY
+        .read()
+        .map(|registry| registry.iter().map(|(name, _)| *name).collect())
+        .unwrap_or_default()
+}
+
+pub fn build_strategy(name: &str) -> Option<Box<dyn Strategy>> {
+    REGISTRY
+        .read()
+        .ok()?
+        .iter()
+        .find(|(registered, _)| *registered == name)
+        .map(|(_, builder)| builder())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        build_strategy, register_strategy, strategy_names, AgentView, MarketView, Strategy,
+    };
+    use crate::{
+        agent::AgentAction,
+        market::{Market, NUMBER_OF_STONKS},
+        money::Money,
+        price_series::PriceSeries,
+    };
+
+    struct HoldStrategy;
+
+    impl Strategy for HoldStrategy {
+        fn decide(&mut self, _: &MarketView, _: &AgentView) -> Option<AgentAction> {
+            None
+        }
+    }
+
+    #[test]
+    fn test_strategies() {
+        let mut market = Market::new();
+        // Stonk 0 opened the day far above its current price, stonk 1 far below.
+        for (stonk_id, open) in [(0, 4.0), (1, 0.25)] {
+            let stonk = &mut market.stonks[stonk_id];
+            let price = stonk.current_unit_price_cents();
+            stonk.historical_prices =
+                PriceSeries::new(0, vec![(price as f64 * open) as u32, price]);
+        }
+        market.last_tick = 1;
+
+        let mut owned_stonks = [0; NUMBER_OF_STONKS];
+        owned_stonks[0] = 10;
+        let me = AgentView {
+            name: "bot",
+            cash: Money::from_dollars(10_000),
+            owned_stonks: &owned_stonks,
+            aggressiveness: 0.5,
+            seed: 0,
+        };
+        let view = MarketView::new(&market);
+
+        let mut momentum = build_strategy("momentum").expect("Should be registered");
+        assert_eq!(
+            momentum.decide(&view, &me),
+            Some(AgentAction::Sell {
+                stonk_id: 0,
+                amount: 5
+            })
+        );
+        let mut contrarian = build_strategy("contrarian").expect("Should be registered");
+        assert!(matches!(
+            contrarian.decide(&view, &me),
+            Some(AgentAction::Buy { stonk_id: 0, .. })
+        ));
+
+        // Without holdings, momentum buys the best performer.
+        let me = AgentView {
+            owned_stonks: &[0; NUMBER_OF_STONKS],
+            ..me
+        };
+        assert!(matches!(
+            momentum.decide(&view, &me),
+            Some(AgentAction::Buy { stonk_id: 1, .. })
+        ));
+
+        let mut noise = build_strategy("noise").expect("Should be registered");
+        assert_eq!(noise.decide(&view, &me), noise.decide(&view, &me));
+
+        assert!(build_strategy("hold").is_none());
+        assert!(register_strategy("hold", || Box::new(HoldStrategy)).is_ok());
+        assert!(register_strategy("hold", || Box::new(HoldStrategy)).is_err());
+        assert!(strategy_names().ends_with(&["hold"]));
+        let mut hold = build_strategy("hold").expect("Should be registered");
+        assert_eq!(hold.decide(&view, &me), None);
+    }
+}
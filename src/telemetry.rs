@@ -0,0 +1,273 @@
+// Opt-in game balance reports, written at the end of every day for tuning. Counters are
+// updated as events happen, and the wealth of the players is scanned a few agents per tick
+// during the night, so that closing a day never walks the whole agent map at once.
+
+use crate::{
+    agent::INITIAL_USER_CASH,
+    market::{GamePhase, NIGHT_LENGTH},
+    money::Money,
+    stonk::StonkClass,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+
+// Players with a net worth below this are counted as bankrupt.
+pub const BANKRUPTCY_NET_WORTH: Money = Money::from_cents(INITIAL_USER_CASH.cents() / 10);
+// Least number of agents scanned per tick while a report is being completed.
+pub const MIN_AGENTS_SCANNED_PER_TICK: usize = 50;
+// Upper bounds, in days played, of the tenure buckets. The last bucket has no bound.
+pub const TENURE_BUCKETS: [usize; 3] = [1, 7, 30];
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventStats {
+    pub offered: usize,
+    pub picked: usize,
+    pub pick_rate: f64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TenureStats {
+    // Label of the tenure bucket, in days played.
+    pub tenure: String,
+    pub players: usize,
+    pub average_net_worth: Money,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BalanceReport {
+    pub cycle: usize,
+    pub date: String,
+    pub players: usize,
+    pub bankruptcies: usize,
+    pub events: BTreeMap<String, EventStats>,
+    pub wealth_by_tenure: Vec<TenureStats>,
+    // Average return over the day of the stonks of each class.
+    pub class_returns: Vec<(StonkClass, f64)>,
+}
+
+// Net worth totals of a tenure bucket.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+struct TenureTotals {
+    players: usize,
+    net_worth: Money,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BalanceTelemetry {
+    // Set by the server from its config.
+    #[serde(skip)]
+    pub enabled: bool,
+    // Event counters of the current day.
+    #[serde(default)]
+    events: BTreeMap<String, EventStats>,
+    // The report of the last closed day, while its players are being scanned.
+    #[serde(default)]
+    pending: Option<BalanceReport>,
+    #[serde(default)]
+    scan_queue: VecDeque<String>,
+    #[serde(default)]
+    scan_per_tick: usize,
+    #[serde(default)]
+    tenure_totals: Vec<TenureTotals>,
+    // Completed reports, not yet written to the report file.
+    #[serde(default)]
+    finished: Vec<BalanceReport>,
+}
+
+pub fn tenure_bucket(days_played: usize) -> usize {
+    TENURE_BUCKETS
+        .iter()
+        .position(|bound| days_played < *bound)
+        .unwrap_or(TENURE_BUCKETS.len())
+}
+
+fn tenure_label(bucket: usize) -> String {
+    let low = bucket.checked_sub(1).map_or(0, |idx| TENURE_BUCKETS[idx]);
+    match TENURE_BUCKETS.get(bucket) {
+        Some(high) if *high == low + 1 => format!("{low} days"),
+        Some(high) => format!("{low}-{} days", high - 1),
+        None => format!("{low}+ days"),
+    }
+}
+
+impl BalanceTelemetry {
+    pub fn record_offered(&mut self, event: &str) {
+        if self.enabled {
+            self.events.entry(event.to_string()).or_default().offered += 1;
+        }
+    }
+
+    pub fn record_picked(&mut self, event: &str) {
+        if self.enabled {
+            self.events.entry(event.to_string()).or_default().picked += 1;
+        }
+    }
+
+    pub fn is_scanning(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Closes the day, starting the scan of the given players. A report still being
+    /// scanned is completed with the players scanned so far.
+    pub fn close_day(
+        &mut self,
+        phase: GamePhase,
+        class_returns: Vec<(StonkClass, f64)>,
+        usernames: Vec<String>,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        self.finish_scan();
+
+        let mut events = std::mem::take(&mut self.events);
+        for stats in events.values_mut() {
+            if stats.offered > 0 {
+                stats.pick_rate = stats.picked as f64 / stats.offered as f64;
+            }
+        }
+        self.pending = Some(BalanceReport {
+            cycle: phase.cycle(),
+            date: phase.formatted_date(),
+            events,
+            class_returns,
+            ..Default::default()
+        });
+        // Spread over the night, so that the scan is complete before the next day closes.
+        self.scan_per_tick = usernames
+            .len()
+            .div_ceil(NIGHT_LENGTH)
+            .max(MIN_AGENTS_SCANNED_PER_TICK);
+        self.scan_queue = usernames.into();
+        self.tenure_totals = vec![TenureTotals::default(); TENURE_BUCKETS.len() + 1];
+    }
+
+    /// Takes the usernames of the players to scan at this tick.
+    pub fn next_scan_batch(&mut self) -> Vec<String> {
+        let amount = self.scan_per_tick.min(self.scan_queue.len());
+        self.scan_queue.drain(..amount).collect()
+    }
+
+    pub fn record_player(&mut self, days_played: usize, net_worth: Money) {
+        let Some(report) = self.pending.as_mut() else {
+            return;
+        };
+        report.players += 1;
+        if net_worth < BANKRUPTCY_NET_WORTH {
+            report.bankruptcies += 1;
+        }
+        let totals = &mut self.tenure_totals[tenure_bucket(days_played)];
+        totals.players += 1;
+        totals.net_worth = totals.net_worth.saturating_add(net_worth);
+    }
+
+    /// Completes the pending report once all its players have been scanned.
+    pub fn finish_scan(&mut self) {
+        let Some(mut report) = self.pending.take() else {
+            return;
+        };
+        report.wealth_by_tenure = self
+            .tenure_totals
+            .drain(..)
+            .enumerate()
+            .map(|(bucket, totals)| TenureStats {
+                tenure: tenure_label(bucket),
+                players: totals.players,
+                average_net_worth: Money::from_cents(
+                    totals.net_worth.cents() / totals.players.max(1) as u64,
+                ),
+            })
+            .collect();
+        self.scan_queue.clear();
+        self.finished.push(report);
+    }
+
+    pub fn scan_is_done(&self) -> bool {
+        self.scan_queue.is_empty()
+    }
+
+    /// Returns the completed reports, to be written to the report file.
+    pub fn take_finished(&mut self) -> Vec<BalanceReport> {
+        std::mem::take(&mut self.finished)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tenure_bucket, tenure_label, BalanceTelemetry, BANKRUPTCY_NET_WORTH};
+    use crate::{
+        market::{GamePhase, DAY_LENGTH},
+        money::Money,
+        stonk::StonkClass,
+    };
+
+    #[test]
+    fn test_balance_report() {
+        let phase = GamePhase::Night {
+            cycle: 3,
+            counter: 0,
+        };
+        let mut telemetry = BalanceTelemetry::default();
+        telemetry.record_offered("War");
+        telemetry.close_day(phase, vec![], vec!["a".into()]);
+        assert!(!telemetry.is_scanning());
+        assert!(telemetry.take_finished().is_empty());
+
+        telemetry.enabled = true;
+        telemetry.record_offered("War");
+        telemetry.record_offered("War");
+        telemetry.record_picked("War");
+        let usernames = (0..120).map(|idx| idx.to_string()).collect();
+        telemetry.close_day(phase, vec![(StonkClass::War, 0.1)], usernames);
+        assert!(telemetry.is_scanning());
+
+        let mut ticks = 0;
+        while !telemetry.scan_is_done() {
+            for username in telemetry.next_scan_batch() {
+                let days_played = username.parse::<usize>().expect("Should be a number");
+                let net_worth = if days_played < 20 {
+                    Money::ZERO
+                } else {
+                    Money::from_dollars(1_000)
+                };
+                telemetry.record_player(days_played, net_worth);
+            }
+            ticks += 1;
+        }
+        telemetry.finish_scan();
+        assert!(ticks <= DAY_LENGTH);
+
+        let reports = telemetry.take_finished();
+        assert_eq!(reports.len(), 1);
+        let report = &reports[0];
+        assert_eq!(report.cycle, 3);
+        assert_eq!(report.players, 120);
+        assert_eq!(report.bankruptcies, 20);
+        assert!(BANKRUPTCY_NET_WORTH > Money::ZERO);
+        assert_eq!(report.events["War"].offered, 2);
+        assert_eq!(report.events["War"].pick_rate, 0.5);
+        assert_eq!(report.class_returns, vec![(StonkClass::War, 0.1)]);
+
+        let tenures = report
+            .wealth_by_tenure
+            .iter()
+            .map(|stats| (stats.tenure.as_str(), stats.players))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tenures,
+            vec![
+                ("0 days", 1),
+                ("1-6 days", 6),
+                ("7-29 days", 23),
+                ("30+ days", 90)
+            ]
+        );
+        assert_eq!(
+            report.wealth_by_tenure[3].average_net_worth,
+            Money::from_dollars(1_000)
+        );
+        assert_eq!(tenure_bucket(30), 3);
+        assert_eq!(tenure_label(0), "0 days");
+        assert!(telemetry.take_finished().is_empty());
+    }
+}
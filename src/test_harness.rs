@@ -0,0 +1,688 @@
+// End-to-end tests driving a real AppServer through an in-process SSH client.
+
+use crate::{
+    agent::UserAgent,
+    config::ServerConfig,
+    market::{GamePhase, Market, MarketSnapshot},
+    ssh_server::{AgentsDatabase, AppServer},
+    utils::AppResult,
+};
+use async_trait::async_trait;
+use russh::{
+    client::{self, Handle},
+    ChannelMsg,
+};
+use russh_keys::key::{KeyPair, PublicKey};
+use std::{sync::Arc, time::Duration};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+const TERMINAL_WIDTH: u32 = 160;
+const TERMINAL_HEIGHT: u32 = 48;
+// Time between keystrokes, so that every key ends up in its own packet.
+const KEYSTROKE_INTERVAL_MILLIS: u64 = 20;
+const POLL_INTERVAL_MILLIS: u64 = 50;
+const POLL_TIMEOUT_MILLIS: u64 = 5_000;
+
+struct TestClientHandler;
+
+#[async_trait]
+impl client::Handler for TestClientHandler {
+    type Error = russh::Error;
+
+    async fn check_server_key(
+        &mut self,
+        _server_public_key: &PublicKey,
+    ) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+}
+
+pub struct TestServer {
+    server: AppServer,
+    port: u16,
+}
+
+impl TestServer {
+    /// Starts an AppServer with the given state on a random local port.
+    pub async fn start(
+        config: ServerConfig,
+        agents: AgentsDatabase,
+        market: Market,
+    ) -> AppResult<Self> {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")?
+            .local_addr()?
+            .port();
+        let server = AppServer::with_state(config, agents, market);
+
+        let mut running_server = server.clone();
+        tokio::spawn(async move {
+            let key_pair = KeyPair::generate_ed25519().expect("Failed to generate key pair");
+            running_server
+                .run_with_key_pair(key_pair, port)
+                .await
+                .expect("Test server failed");
+        });
+
+        Ok(Self { server, port })
+    }
+
+    async fn authenticate(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> AppResult<Handle<TestClientHandler>> {
+        let mut attempts = 0;
+        let mut session = loop {
+            match client::connect(
+                Arc::new(client::Config::default()),
+                ("127.0.0.1", self.port),
+                TestClientHandler,
+            )
+            .await
+            {
+                Ok(session) => break session,
+                // The server might not be listening yet.
+                Err(_) if attempts < POLL_TIMEOUT_MILLIS / POLL_INTERVAL_MILLIS => {
+                    attempts += 1;
+                    tokio::time::sleep(Duration::from_millis(POLL_INTERVAL_MILLIS)).await;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+
+        if !session.authenticate_password(username, password).await? {
+            return Err("Authentication failed".into());
+        }
+        Ok(session)
+    }
+
+    /// Opens a game session and leaves the menu shown after login by choosing Play.
+    pub async fn connect(&self, username: &str, password: &str) -> AppResult<TestClient> {
+        let mut client = self.connect_to_menu(username, password).await?;
+        client.send_keys("\r").await?;
+        Ok(client)
+    }
+
+    pub async fn connect_to_menu(&self, username: &str, password: &str) -> AppResult<TestClient> {
+        let session = self.authenticate(username, password).await?;
+        let channel = session.channel_open_session().await?;
+        channel
+            .request_pty(false, "xterm", TERMINAL_WIDTH, TERMINAL_HEIGHT, 0, 0, &[])
+            .await?;
+        channel.request_shell(true).await?;
+        let writer = channel.make_writer();
+
+        // The game client exists once the shell is confirmed.
+        let mut channel = channel;
+        loop {
+            match channel.wait().await {
+                Some(ChannelMsg::Success) => break,
+                Some(ChannelMsg::Failure | ChannelMsg::Close) | None => {
+                    return Err("Shell request failed".into())
+                }
+                Some(_) => {}
+            }
+        }
+
+        // The server blocks on flushing frames, so the output must be consumed.
+        tokio::spawn(async move {
+            while let Some(msg) = channel.wait().await {
+                if let ChannelMsg::Close | ChannelMsg::Eof = msg {
+                    break;
+                }
+            }
+        });
+
+        Ok(TestClient {
+            session,
+            writer: Box::pin(writer),
+        })
+    }
+
+    /// Runs a command over an exec channel and returns its output.
+    pub async fn exec(&self, username: &str, password: &str, command: &str) -> AppResult<String> {
+        let session = self.authenticate(username, password).await?;
+        let mut channel = session.channel_open_session().await?;
+        channel.exec(true, command).await?;
+        let mut output = vec![];
+        while let Some(msg) = channel.wait().await {
+            match msg {
+                ChannelMsg::Data { data } => output.extend_from_slice(&data),
+                ChannelMsg::Close | ChannelMsg::Eof => break,
+                _ => {}
+            }
+        }
+        Ok(String::from_utf8(output)?)
+    }
+
+    /// Sends the data over an SFTP channel and returns the first response.
+    pub async fn sftp(&self, username: &str, password: &str, data: &[u8]) -> AppResult<Vec<u8>> {
+        let session = self.authenticate(username, password).await?;
+        let mut channel = session.channel_open_session().await?;
+        channel.request_subsystem(true, "sftp").await?;
+        channel.data(data).await?;
+        while let Some(msg) = channel.wait().await {
+            match msg {
+                ChannelMsg::Data { data } => {
+                    channel.close().await?;
+                    return Ok(data.to_vec());
+                }
+                ChannelMsg::Failure | ChannelMsg::Close | ChannelMsg::Eof => break,
+                _ => {}
+            }
+        }
+        Err("SFTP subsystem refused".into())
+    }
+
+    pub async fn agent(&self, username: &str) -> Option<UserAgent> {
+        self.server.agents().lock().await.get(username).cloned()
+    }
+
+    pub async fn usernames(&self) -> Vec<String> {
+        self.server.agents().lock().await.keys().cloned().collect()
+    }
+
+    pub async fn update_agent(&self, username: &str, f: impl FnOnce(&mut UserAgent)) {
+        if let Some(agent) = self.server.agents().lock().await.get_mut(username) {
+            f(agent);
+        }
+    }
+
+    pub async fn set_phase(&self, phase: GamePhase) {
+        self.server.market().lock().await.phase = phase;
+    }
+
+    pub async fn market(&self) -> Market {
+        self.server.market().lock().await.clone()
+    }
+
+    pub async fn number_of_clients(&self) -> usize {
+        self.server.number_of_clients().await
+    }
+
+    /// Waits for the next market tick, failing after POLL_TIMEOUT_MILLIS.
+    pub async fn wait_for_tick(&self) -> AppResult<MarketSnapshot> {
+        let mut snapshots = self.server.subscribe_snapshots();
+        tokio::time::timeout(
+            Duration::from_millis(POLL_TIMEOUT_MILLIS),
+            snapshots.changed(),
+        )
+        .await??;
+        let snapshot = *snapshots.borrow();
+        Ok(snapshot)
+    }
+
+    /// Polls the agent until the condition holds, failing after POLL_TIMEOUT_MILLIS.
+    pub async fn wait_for_agent(
+        &self,
+        username: &str,
+        condition: impl Fn(&UserAgent) -> bool,
+    ) -> AppResult<UserAgent> {
+        for _ in 0..POLL_TIMEOUT_MILLIS / POLL_INTERVAL_MILLIS {
+            if let Some(agent) = self.agent(username).await {
+                if condition(&agent) {
+                    return Ok(agent);
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(POLL_INTERVAL_MILLIS)).await;
+        }
+        Err(format!("Timed out waiting for agent {}", username).into())
+    }
+}
+
+pub struct TestClient {
+    session: Handle<TestClientHandler>,
+    writer: std::pin::Pin<Box<dyn AsyncWrite + Send>>,
+}
+
+impl TestClient {
+    /// Sends each key separately, as a terminal would when typing.
+    pub async fn send_keys(&mut self, keys: &str) -> AppResult<()> {
+        for key in keys.as_bytes().chunks(1) {
+            self.writer.write_all(key).await?;
+            self.writer.flush().await?;
+            tokio::time::sleep(Duration::from_millis(KEYSTROKE_INTERVAL_MILLIS)).await;
+        }
+        Ok(())
+    }
+
+    /// Sends an escape sequence, such as an arrow key, in a single packet.
+    pub async fn send_sequence(&mut self, sequence: &str) -> AppResult<()> {
+        self.writer.write_all(sequence.as_bytes()).await?;
+        self.writer.flush().await?;
+        tokio::time::sleep(Duration::from_millis(KEYSTROKE_INTERVAL_MILLIS)).await;
+        Ok(())
+    }
+
+    pub async fn disconnect(self) -> AppResult<()> {
+        self.session
+            .disconnect(russh::Disconnect::ByApplication, "", "")
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TestServer;
+    use crate::{
+        agent::DecisionAgent,
+        config::ServerConfig,
+        events::EventRarity,
+        market::{GamePhase, Market, DAY_LENGTH},
+        money::Money,
+        ssh_server::AgentsDatabase,
+        utils::AppResult,
+    };
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_buy_and_sell() -> AppResult<()> {
+        let server = TestServer::start(
+            ServerConfig::default(),
+            AgentsDatabase::default(),
+            Market::new(),
+        )
+        .await?;
+        let mut client = server.connect("buyer", "password").await?;
+        server.wait_for_agent("buyer", |_| true).await?;
+
+        client.send_keys("b").await?;
+        let agent = server
+            .wait_for_agent("buyer", |agent| agent.owned_stonks()[0] == 1)
+            .await?;
+        assert!(agent.selected_actions().is_empty());
+
+        client.send_keys("s").await?;
+        server
+            .wait_for_agent("buyer", |agent| agent.owned_stonks()[0] == 0)
+            .await?;
+
+        client.disconnect().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_count_prefix() -> AppResult<()> {
+        let server = TestServer::start(
+            ServerConfig::default(),
+            AgentsDatabase::default(),
+            Market::new(),
+        )
+        .await?;
+        let mut client = server.connect("counter", "password").await?;
+        server.wait_for_agent("counter", |_| true).await?;
+
+        client.send_keys("25b").await?;
+        server
+            .wait_for_agent("counter", |agent| agent.owned_stonks()[0] == 25)
+            .await?;
+
+        // A count naming no stonk is dropped by any key other than a trade.
+        client.send_keys("10s12xs").await?;
+        server
+            .wait_for_agent("counter", |agent| agent.owned_stonks()[0] == 14)
+            .await?;
+
+        client.disconnect().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_market_ticks_without_clients() -> AppResult<()> {
+        let server = TestServer::start(
+            ServerConfig::default(),
+            AgentsDatabase::default(),
+            Market::new(),
+        )
+        .await?;
+        let first = server.wait_for_tick().await?;
+        let second = server.wait_for_tick().await?;
+        assert_eq!(second.last_tick, first.last_tick + 1);
+        assert_eq!(server.market().await.last_tick, second.last_tick);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_night_event_selection() -> AppResult<()> {
+        let mut config = ServerConfig::default();
+        for rarity in [
+            EventRarity::Common,
+            EventRarity::Uncommon,
+            EventRarity::Rare,
+        ] {
+            config.events.unlock_probabilities.insert(rarity, 1.0);
+        }
+        let server = TestServer::start(config, AgentsDatabase::default(), Market::new()).await?;
+        let mut client = server.connect("gambler", "password").await?;
+        server.wait_for_agent("gambler", |_| true).await?;
+
+        // A poor agent always unlocks the LuckyNight event.
+        server
+            .update_agent("gambler", |agent| {
+                agent
+                    .sub_cash(agent.cash().saturating_sub(Money::from_dollars(500)))
+                    .expect("Agent should have enough cash");
+            })
+            .await;
+        // The events are rolled when the night starts.
+        server
+            .set_phase(GamePhase::Day {
+                cycle: 0,
+                counter: DAY_LENGTH - 1,
+            })
+            .await;
+
+        let agent = server
+            .wait_for_agent("gambler", |agent| {
+                !agent.available_night_events().is_empty()
+            })
+            .await?;
+        let event = agent.available_night_events()[0].clone();
+
+        client.send_keys("\r").await?;
+        server
+            .wait_for_agent("gambler", |agent| {
+                agent.selected_actions().contains(&event.action())
+            })
+            .await?;
+
+        client.disconnect().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout() -> AppResult<()> {
+        let mut config = ServerConfig::default();
+        config.session.idle_timeout_seconds = 2;
+        config.session.idle_warning_seconds = 1;
+        let server = TestServer::start(config, AgentsDatabase::default(), Market::new()).await?;
+        let _client = server.connect("sleepy", "password").await?;
+        server.wait_for_agent("sleepy", |_| true).await?;
+        assert_eq!(server.number_of_clients().await, 1);
+
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        assert_eq!(server.number_of_clients().await, 0);
+        // The agent is kept, only the session is dropped.
+        assert!(server.agent("sleepy").await.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_guest_upgrade() -> AppResult<()> {
+        let server = TestServer::start(
+            ServerConfig::default(),
+            AgentsDatabase::default(),
+            Market::new(),
+        )
+        .await?;
+        let mut client = server.connect("", "").await?;
+        let guest_username = loop {
+            if let Some(username) = server.usernames().await.pop() {
+                break username;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        };
+        let guest = server.wait_for_agent(&guest_username, |_| true).await?;
+        assert!(guest.is_guest());
+
+        client.send_keys("b").await?;
+        server
+            .wait_for_agent(&guest_username, |agent| agent.owned_stonks()[0] == 1)
+            .await?;
+
+        client.send_keys("uNewcomer\tsecret\r").await?;
+        let agent = server
+            .wait_for_agent("Newcomer", |agent| !agent.is_guest())
+            .await?;
+        assert_eq!(agent.owned_stonks()[0], 1);
+        assert!(server.agent(&guest_username).await.is_none());
+
+        // The session keeps working under the new username.
+        client.send_keys("b").await?;
+        server
+            .wait_for_agent("Newcomer", |agent| agent.owned_stonks()[0] == 2)
+            .await?;
+        client.disconnect().await?;
+
+        let mut client = server.connect("Newcomer", "secret").await?;
+        assert_eq!(server.usernames().await.len(), 1);
+        client.send_keys("s").await?;
+        server
+            .wait_for_agent("Newcomer", |agent| agent.owned_stonks()[0] == 1)
+            .await?;
+        client.disconnect().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_username_change() -> AppResult<()> {
+        let server = TestServer::start(
+            ServerConfig::default(),
+            AgentsDatabase::default(),
+            Market::new(),
+        )
+        .await?;
+        let mut client = server.connect("oldname", "password").await?;
+        server.wait_for_agent("oldname", |_| true).await?;
+
+        client.send_keys("b").await?;
+        server
+            .wait_for_agent("oldname", |agent| agent.owned_stonks()[0] == 1)
+            .await?;
+
+        client.send_keys("unewname\r").await?;
+        let agent = server.wait_for_agent("newname", |_| true).await?;
+        assert_eq!(agent.owned_stonks()[0], 1);
+        assert_eq!(agent.previous_usernames()[0].0, "oldname");
+        assert!(server.agent("oldname").await.is_none());
+        let market = server.market().await;
+        assert_eq!(
+            market.stonks[0].shareholders,
+            vec![("newname".to_string(), 1)]
+        );
+
+        // A second change has to wait for the cooldown.
+        client.send_keys("unewername\r").await?;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(server.agent("newername").await.is_none());
+        assert!(server.agent("newname").await.is_some());
+        client.disconnect().await?;
+
+        // The old username is reserved for a while.
+        assert!(server.connect("oldname", "password").await.is_err());
+        assert!(server.agent("oldname").await.is_none());
+        let _client = server.connect("newname", "password").await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_api_token() -> AppResult<()> {
+        let server = TestServer::start(
+            ServerConfig::default(),
+            AgentsDatabase::default(),
+            Market::new(),
+        )
+        .await?;
+        let mut client = server.connect("botter", "password").await?;
+        server.wait_for_agent("botter", |_| true).await?;
+
+        client.send_keys("kn").await?;
+        let agent = server
+            .wait_for_agent("botter", |agent| agent.api_tokens().len() == 1)
+            .await?;
+        let id = agent.api_tokens()[0].id.clone();
+
+        // The token value is only known to the client, so create another one directly.
+        let mut token = String::new();
+        server
+            .update_agent("botter", |agent| {
+                token = agent
+                    .generate_api_token()
+                    .expect("Agent should be able to create a token");
+            })
+            .await;
+
+        let output = server.exec("botter", &token, "portfolio").await?;
+        assert!(output.contains("\"username\":\"botter\""));
+        let output = server.exec("botter", &token, "buy 0 1").await?;
+        assert!(output.contains("queued"));
+        server
+            .wait_for_agent("botter", |agent| agent.owned_stonks()[0] == 1)
+            .await?;
+        // The game session is still open.
+        assert_eq!(server.number_of_clients().await, 1);
+
+        // Revoke the selected token from the account settings.
+        client.send_keys("d").await?;
+        server
+            .wait_for_agent("botter", |agent| {
+                agent.api_tokens().iter().all(|token| token.id != id)
+            })
+            .await?;
+        assert!(!server
+            .exec("botter", &token, "portfolio")
+            .await?
+            .contains("error"));
+        client.disconnect().await?;
+
+        let output = server.exec("botter", "password", "portfolio").await?;
+        assert!(output.contains("error"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sftp_subsystem() -> AppResult<()> {
+        let server = TestServer::start(
+            ServerConfig::default(),
+            AgentsDatabase::default(),
+            Market::new(),
+        )
+        .await?;
+        let client = server.connect("saver", "password").await?;
+        server.wait_for_agent("saver", |_| true).await?;
+
+        // An SFTP init packet, answered with the protocol version.
+        let output = server
+            .sftp("saver", "password", &[0, 0, 0, 5, 1, 0, 0, 0, 3])
+            .await?;
+        assert_eq!(output, [0, 0, 0, 5, 2, 0, 0, 0, 3]);
+        // The game session is still open.
+        assert_eq!(server.number_of_clients().await, 1);
+        client.disconnect().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_menu_quit() -> AppResult<()> {
+        let server = TestServer::start(
+            ServerConfig::default(),
+            AgentsDatabase::default(),
+            Market::new(),
+        )
+        .await?;
+        let mut client = server.connect_to_menu("leaver", "password").await?;
+        assert_eq!(server.number_of_clients().await, 1);
+
+        // Quit is the last entry of the menu.
+        client.send_sequence("\x1b[A").await?;
+        client.send_keys("\r").await?;
+        for _ in 0..50 {
+            if server.number_of_clients().await == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(server.number_of_clients().await, 0);
+        assert!(server.agent("leaver").await.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_server_capacity() -> AppResult<()> {
+        let mut config = ServerConfig::default();
+        config.session.max_players = 1;
+        let server = TestServer::start(config, AgentsDatabase::default(), Market::new()).await?;
+        let _client = server.connect("first", "password").await?;
+        server.wait_for_agent("first", |_| true).await?;
+
+        assert!(server.connect("second", "password").await.is_err());
+        assert_eq!(server.number_of_clients().await, 1);
+        assert!(server.agent("second").await.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_blocked_usernames() -> AppResult<()> {
+        let mut config = ServerConfig::default();
+        config
+            .usernames
+            .blocked_patterns
+            .push("*stonk*".to_string());
+        let server = TestServer::start(config, AgentsDatabase::default(), Market::new()).await?;
+
+        assert!(server.connect("Admin", "password").await.is_err());
+        assert!(server.connect("bigstonks", "password").await.is_err());
+        assert!(server.usernames().await.is_empty());
+
+        let _client = server.connect("trader", "password").await?;
+        server.wait_for_agent("trader", |_| true).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_admin_clock_commands() -> AppResult<()> {
+        let mut config = ServerConfig::default();
+        config.admins.push("root".to_string());
+        let server = TestServer::start(config, AgentsDatabase::default(), Market::new()).await?;
+        let mut tokens = vec![];
+        for username in ["root", "player"] {
+            let client = server.connect(username, "password").await?;
+            server.wait_for_agent(username, |_| true).await?;
+            server
+                .update_agent(username, |agent| {
+                    tokens.push(
+                        agent
+                            .generate_api_token()
+                            .expect("Agent should be able to create a token"),
+                    );
+                })
+                .await;
+            client.disconnect().await?;
+        }
+
+        let output = server.exec("player", &tokens[1], "pause").await?;
+        assert!(output.contains("error"));
+        let output = server.exec("root", &tokens[0], "pause").await?;
+        assert!(output.contains("\"paused\":true"));
+
+        // No tick happens while paused, except for the fast-forwarded ones.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let last_tick = server.market().await.last_tick;
+        tokio::time::sleep(Duration::from_millis(1_500)).await;
+        assert_eq!(server.market().await.last_tick, last_tick);
+        server.exec("root", &tokens[0], "fast-forward 3").await?;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(server.market().await.last_tick, last_tick + 3);
+
+        let output = server.exec("root", &tokens[0], "speed 0").await?;
+        assert!(output.contains("error"));
+        let output = server.exec("root", &tokens[0], "speed 4").await?;
+        assert!(output.contains("\"tick_interval_millis\":250"));
+
+        let output = server
+            .exec("root", &tokens[0], "maintenance 10 Saving the market")
+            .await?;
+        assert!(output.contains("\"message\":\"Saving the market\""));
+        assert!(output.contains("\"back_in_seconds\":600"));
+        // Not even a fast-forward moves the market during the maintenance.
+        server.exec("root", &tokens[0], "fast-forward 2").await?;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(server.market().await.last_tick, last_tick + 3);
+        let output = server.exec("root", &tokens[0], "maintenance off").await?;
+        assert!(output.contains("\"maintenance\":null"));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(server.market().await.last_tick, last_tick + 5);
+        Ok(())
+    }
+}
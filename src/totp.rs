@@ -0,0 +1,160 @@
+// Time-based one-time passwords (RFC 6238), the optional second factor of the login. The secret
+// is provisioned from the account settings with an authenticator app, scanning a QR code drawn
+// with unicode blocks, and a code of the app is then asked before the game starts.
+
+use crate::utils::AppResult;
+use hmac::{Hmac, Mac};
+use qrcode::{Color, EcLevel, QrCode};
+use rand::RngCore;
+use sha1::Sha1;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const TOTP_STEP_SECONDS: u64 = 30;
+pub const TOTP_DIGITS: usize = 6;
+// Wrong codes allowed at login before the session is closed.
+pub const MAX_TOTP_ATTEMPTS: usize = 3;
+const TOTP_SECRET_BYTES: usize = 20;
+// Codes of the steps next to the current one are accepted too, for clocks slightly off.
+const TOTP_ALLOWED_DRIFT_STEPS: u64 = 1;
+// Width of the light border around the QR code, in modules.
+const QR_QUIET_ZONE: usize = 2;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+pub fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0; TOTP_SECRET_BYTES];
+    rand::thread_rng().fill_bytes(&mut secret);
+    secret
+}
+
+/// The code of the secret for the given counter (RFC 4226).
+pub fn code_at(secret: &[u8], counter: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let truncated = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    format!(
+        "{:0width$}",
+        truncated % 10u32.pow(TOTP_DIGITS as u32),
+        width = TOTP_DIGITS
+    )
+}
+
+/// Whether the code is valid for the secret at the given time, in seconds since the Unix epoch.
+pub fn verify_at(secret: &[u8], code: &str, unix_time: u64) -> bool {
+    let code = code.trim();
+    if code.len() != TOTP_DIGITS {
+        return false;
+    }
+    let step = unix_time / TOTP_STEP_SECONDS;
+    (step.saturating_sub(TOTP_ALLOWED_DRIFT_STEPS)..=step + TOTP_ALLOWED_DRIFT_STEPS)
+        .any(|counter| code_at(secret, counter) == code)
+}
+
+pub fn verify(secret: &[u8], code: &str) -> bool {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    verify_at(secret, code, now)
+}
+
+/// Encodes the bytes in base32 without padding, as authenticator apps expect the secret.
+pub fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+/// The URI scanned by authenticator apps to add the secret.
+pub fn provisioning_uri(secret: &[u8], username: &str) -> String {
+    format!(
+        "otpauth://totp/stonks:{}?secret={}&issuer=stonks&digits={}&period={}",
+        username,
+        base32_encode(secret),
+        TOTP_DIGITS,
+        TOTP_STEP_SECONDS
+    )
+}
+
+/// Draws the QR code of the data with half blocks, two rows of modules per line. Blocks are the
+/// light modules, so the code must be drawn light on dark.
+pub fn qr_lines(data: &str) -> AppResult<Vec<String>> {
+    let code = QrCode::with_error_correction_level(data, EcLevel::L)?;
+    let width = code.width();
+    let colors = code.to_colors();
+    let size = width + 2 * QR_QUIET_ZONE;
+    let is_light = |x: usize, y: usize| {
+        if x < QR_QUIET_ZONE || y < QR_QUIET_ZONE {
+            return true;
+        }
+        let (x, y) = (x - QR_QUIET_ZONE, y - QR_QUIET_ZONE);
+        x >= width || y >= width || colors[y * width + x] == Color::Light
+    };
+    Ok((0..size)
+        .step_by(2)
+        .map(|y| {
+            (0..size)
+                .map(
+                    |x| match (is_light(x, y), y + 1 >= size || is_light(x, y + 1)) {
+                        (true, true) => '█',
+                        (true, false) => '▀',
+                        (false, true) => '▄',
+                        (false, false) => ' ',
+                    },
+                )
+                .collect()
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{base32_encode, code_at, provisioning_uri, qr_lines, verify_at, TOTP_STEP_SECONDS};
+
+    #[test]
+    fn test_totp_codes() {
+        // Test vectors of RFC 6238, truncated to six digits.
+        let secret = b"12345678901234567890";
+        for (time, code) in [
+            (59, "287082"),
+            (1_111_111_109, "081804"),
+            (1_234_567_890, "005924"),
+            (2_000_000_000, "279037"),
+        ] {
+            assert_eq!(code_at(secret, time / TOTP_STEP_SECONDS), code);
+            assert!(verify_at(secret, code, time));
+        }
+        // The previous and next codes are accepted, older ones are not.
+        assert!(verify_at(secret, "287082", 59 + TOTP_STEP_SECONDS));
+        assert!(!verify_at(secret, "287082", 59 + 2 * TOTP_STEP_SECONDS));
+        assert!(!verify_at(secret, "28708", 59));
+
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_encode(secret), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        let lines = qr_lines(&provisioning_uri(secret, "trader")).unwrap();
+        assert!(lines.len() > 10);
+        assert!(lines
+            .iter()
+            .all(|line| line.chars().count() == lines[0].chars().count()));
+        // The quiet zone is light.
+        assert!(lines[0].chars().all(|c| c == '█'));
+    }
+}
@@ -0,0 +1,215 @@
+// Tournaments are played next to the persistent world: every round each remaining player
+// starts from the same cash on a paper account, trading at market prices without moving
+// them, and the bottom half is eliminated when the round ends.
+
+use crate::{
+    config::TournamentConfig,
+    market::NUMBER_OF_STONKS,
+    money::Money,
+    stonk::{Stonk, Stonks},
+    utils::AppResult,
+};
+use serde::{Deserialize, Serialize};
+
+// Trading account that does not touch the shares of the real stonks.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PaperAccount {
+    pub cash: Money,
+    pub owned_stonks: [u32; NUMBER_OF_STONKS],
+}
+
+impl PaperAccount {
+    pub fn new(cash: Money) -> Self {
+        Self {
+            cash,
+            owned_stonks: [0; NUMBER_OF_STONKS],
+        }
+    }
+
+    pub fn buy(&mut self, stonk: &Stonk, amount: u32) -> AppResult<()> {
+        let cost = stonk
+            .checked_buy_price(amount)
+            .ok_or("Trade value is too large")?;
+        self.cash = self.cash.checked_sub(cost).ok_or("Not enough cash")?;
+        self.owned_stonks[stonk.id] += amount;
+        Ok(())
+    }
+
+    pub fn sell(&mut self, stonk: &Stonk, amount: u32) -> AppResult<()> {
+        if amount > self.owned_stonks[stonk.id] {
+            return Err("Not enough shares owned".into());
+        }
+        self.owned_stonks[stonk.id] -= amount;
+        self.cash = self.cash.saturating_add(stonk.sell_price(amount));
+        Ok(())
+    }
+
+    pub fn net_worth(&self, stonks: &Stonks) -> Money {
+        stonks
+            .iter()
+            .map(|stonk| stonk.holdings_value(self.owned_stonks[stonk.id]))
+            .sum::<Money>()
+            .saturating_add(self.cash)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TournamentRound {
+    pub ends_at_tick: usize,
+    // Net worth of the players at the end of the round, from the richest.
+    pub results: Vec<(String, Money)>,
+    pub eliminated: Vec<String>,
+}
+
+impl TournamentRound {
+    pub fn is_over(&self) -> bool {
+        !self.results.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Tournament {
+    // Set by the server from its config.
+    #[serde(skip)]
+    pub config: TournamentConfig,
+    pub registered: Vec<String>,
+    pub rounds: Vec<TournamentRound>,
+    pub winner: Option<String>,
+}
+
+impl Tournament {
+    pub fn current_round(&self) -> Option<&TournamentRound> {
+        self.rounds.last().filter(|round| !round.is_over())
+    }
+
+    /// Whether the next round can start, once the day begins.
+    pub fn is_ready(&self) -> bool {
+        self.current_round().is_none()
+            && self.winner.is_none()
+            && (!self.rounds.is_empty() || self.registered.len() >= self.config.min_players)
+    }
+
+    pub fn is_alive(&self, username: &str) -> bool {
+        self.registered.iter().any(|name| name == username)
+            && !self
+                .rounds
+                .iter()
+                .any(|round| round.eliminated.iter().any(|name| name == username))
+    }
+
+    pub fn alive(&self) -> Vec<String> {
+        self.registered
+            .iter()
+            .filter(|username| self.is_alive(username))
+            .cloned()
+            .collect()
+    }
+
+    /// Registers the player for the next tournament, starting a new one if the last is over.
+    pub fn join(&mut self, username: &str) -> AppResult<()> {
+        if self.winner.is_some() {
+            self.registered.clear();
+            self.rounds.clear();
+            self.winner = None;
+        }
+        if !self.rounds.is_empty() {
+            return Err("The tournament already started, wait for the next one".into());
+        }
+        if self.registered.iter().any(|name| name == username) {
+            return Err("Already registered to the tournament".into());
+        }
+        self.registered.push(username.to_string());
+        Ok(())
+    }
+
+    pub fn start_round(&mut self, current_tick: usize) {
+        self.rounds.push(TournamentRound {
+            ends_at_tick: current_tick + self.config.round_ticks,
+            ..Default::default()
+        });
+    }
+
+    /// Records the scores of the remaining players and eliminates the bottom half.
+    pub fn end_round(&mut self, mut scores: Vec<(String, Money)>) {
+        let Some(round) = self.rounds.last_mut() else {
+            return;
+        };
+        scores.sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then_with(|| a_name.cmp(b_name)));
+        let kept = scores.len().div_ceil(2);
+        round.eliminated = scores[kept..]
+            .iter()
+            .map(|(username, _)| username.clone())
+            .collect();
+        if kept <= 1 {
+            self.winner = scores.first().map(|(username, _)| username.clone());
+        }
+        round.results = scores;
+    }
+
+    pub fn rename(&mut self, old_username: &str, new_username: &str) {
+        let names = self
+            .registered
+            .iter_mut()
+            .chain(self.rounds.iter_mut().flat_map(|round| {
+                round
+                    .results
+                    .iter_mut()
+                    .map(|(name, _)| name)
+                    .chain(round.eliminated.iter_mut())
+            }));
+        for name in names.chain(self.winner.iter_mut()) {
+            if name == old_username {
+                *name = new_username.to_string();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tournament;
+    use crate::{money::Money, utils::AppResult};
+
+    #[test]
+    fn test_tournament_rounds() -> AppResult<()> {
+        let mut tournament = Tournament::default();
+        tournament.config.min_players = 3;
+        for username in ["a", "b", "c"] {
+            assert!(!tournament.is_ready());
+            tournament.join(username)?;
+        }
+        assert!(tournament.join("a").is_err());
+        assert!(tournament.is_ready());
+
+        tournament.start_round(0);
+        assert!(tournament.join("d").is_err());
+        assert!(!tournament.is_ready());
+        tournament.end_round(vec![
+            ("a".to_string(), Money::from_cents(100)),
+            ("b".to_string(), Money::from_cents(300)),
+            ("c".to_string(), Money::from_cents(200)),
+        ]);
+        assert_eq!(tournament.rounds[0].eliminated, vec!["a".to_string()]);
+        assert_eq!(tournament.alive(), vec!["b".to_string(), "c".to_string()]);
+        assert!(tournament.winner.is_none());
+
+        assert!(tournament.is_ready());
+        tournament.start_round(10);
+        tournament.end_round(vec![
+            ("b".to_string(), Money::from_cents(10)),
+            ("c".to_string(), Money::from_cents(20)),
+        ]);
+        assert_eq!(tournament.winner.as_deref(), Some("c"));
+        assert!(!tournament.is_ready());
+
+        tournament.rename("c", "carol");
+        assert_eq!(tournament.winner.as_deref(), Some("carol"));
+        assert!(tournament.is_alive("carol"));
+
+        // Joining after the end starts a new tournament.
+        tournament.join("d")?;
+        assert!(tournament.rounds.is_empty());
+        assert_eq!(tournament.registered, vec!["d".to_string()]);
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+// The treasury collects the money leaving the players: fees, penalties, event costs and the
+// spread earned by the market maker. Some night events pay out of it, so the money taken out
+// of the economy can flow back to the players.
+
+use crate::{money::Money, utils::AppResult};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TreasuryFlows {
+    pub inflows: Money,
+    pub outflows: Money,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Treasury {
+    balance: Money,
+    #[serde(default)]
+    today: TreasuryFlows,
+    // Flows of the last closed day.
+    #[serde(default)]
+    yesterday: Option<TreasuryFlows>,
+}
+
+impl Treasury {
+    pub fn balance(&self) -> Money {
+        self.balance
+    }
+
+    pub fn today(&self) -> TreasuryFlows {
+        self.today
+    }
+
+    pub fn yesterday(&self) -> Option<TreasuryFlows> {
+        self.yesterday
+    }
+
+    pub fn deposit(&mut self, amount: Money) {
+        self.balance = self.balance.saturating_add(amount);
+        self.today.inflows = self.today.inflows.saturating_add(amount);
+    }
+
+    pub fn withdraw(&mut self, amount: Money) -> AppResult<()> {
+        self.balance = self
+            .balance
+            .checked_sub(amount)
+            .ok_or("The treasury cannot afford it")?;
+        self.today.outflows = self.today.outflows.saturating_add(amount);
+        Ok(())
+    }
+
+    /// Starts recording the flows of a new day.
+    pub fn close_day(&mut self) {
+        self.yesterday = Some(std::mem::take(&mut self.today));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Treasury, TreasuryFlows};
+    use crate::money::Money;
+
+    #[test]
+    fn test_treasury_flows() {
+        let mut treasury = Treasury::default();
+        treasury.deposit(Money::from_dollars(100));
+        assert!(treasury.withdraw(Money::from_dollars(150)).is_err());
+        assert!(treasury.withdraw(Money::from_dollars(30)).is_ok());
+        assert_eq!(treasury.balance(), Money::from_dollars(70));
+        assert_eq!(treasury.yesterday(), None);
+
+        treasury.close_day();
+        assert_eq!(
+            treasury.yesterday(),
+            Some(TreasuryFlows {
+                inflows: Money::from_dollars(100),
+                outflows: Money::from_dollars(30),
+            })
+        );
+        assert_eq!(treasury.today(), TreasuryFlows::default());
+        assert_eq!(treasury.balance(), Money::from_dollars(70));
+    }
+}
@@ -1,10 +1,11 @@
 use crate::agent::UserAgent;
 use crate::market::Market;
-use crate::ssh_backend::SSHBackend;
-use crate::ui::{render, UiOptions};
+use crate::ssh_backend::{GraphicsProtocol, ImagePlacement, SSHBackend};
+use crate::ui::{render, RenderCache, UiOptions};
 use crate::utils::AppResult;
 use crossterm::terminal::{Clear, EnterAlternateScreen, LeaveAlternateScreen};
 use ratatui::Terminal;
+use std::io::Write;
 
 /// Representation of a terminal user interface.
 ///
@@ -14,13 +15,21 @@ use ratatui::Terminal;
 pub struct Tui {
     /// Interface to the Terminal.
     pub terminal: Terminal<SSHBackend>,
+    /// Images currently drawn with the terminal graphics protocol.
+    displayed_images: Vec<ImagePlacement>,
+    /// Text of the previous frames reused by the next ones.
+    render_cache: RenderCache,
 }
 
 impl Tui {
     /// Constructs a new instance of [`Tui`].
     pub fn new(backend: SSHBackend) -> AppResult<Self> {
         let terminal = Terminal::new(backend)?;
-        let mut tui = Self { terminal };
+        let mut tui = Self {
+            terminal,
+            displayed_images: vec![],
+            render_cache: RenderCache::default(),
+        };
         tui.init()?;
 
         Ok(tui)
@@ -50,15 +59,48 @@ impl Tui {
         ui_options: &UiOptions,
         number_of_players: usize,
     ) -> AppResult<()> {
+        let mut result = Ok(vec![]);
+        let render_cache = &mut self.render_cache;
         self.terminal.draw(|frame| {
-            render(frame, market, agent, ui_options, number_of_players).expect("Failed rendering")
+            result = render(
+                frame,
+                market,
+                agent,
+                ui_options,
+                render_cache,
+                number_of_players,
+            );
         })?;
+        let images = result?;
+
+        if images != self.displayed_images {
+            let backend = self.terminal.backend_mut();
+            if backend.graphics == GraphicsProtocol::Sixel && !self.displayed_images.is_empty() {
+                // Sixel images can only be removed by repainting the screen, the new images
+                // are drawn after the next full redraw.
+                self.terminal.clear()?;
+                self.displayed_images.clear();
+                return Ok(());
+            }
+            backend.delete_images()?;
+            backend.draw_images(&images)?;
+            self.displayed_images = images;
+        }
         Ok(())
     }
 
     /// Resizes the terminal interface.
-    pub fn resize(&mut self, width: u16, height: u16) -> AppResult<()> {
+    pub fn resize(
+        &mut self,
+        width: u16,
+        height: u16,
+        pixel_width: u16,
+        pixel_height: u16,
+    ) -> AppResult<()> {
         self.terminal.backend_mut().size = (width, height);
+        self.terminal.backend_mut().pixel_size = (pixel_width, pixel_height);
+        self.terminal.backend_mut().delete_images()?;
+        self.displayed_images.clear();
         self.terminal.clear()?;
         Ok(())
     }
@@ -84,4 +126,13 @@ impl Tui {
         self.reset()?;
         self.terminal.backend().close().await
     }
+
+    /// Exits the terminal interface, showing a message to the user before disconnecting.
+    pub async fn exit_with_message(&mut self, message: &str) -> AppResult<()> {
+        self.reset()?;
+        let backend = self.terminal.backend_mut();
+        backend.write_all(format!("\n\r{}\n\r", message).as_bytes())?;
+        backend.flush()?;
+        self.terminal.backend().disconnect(message).await
+    }
 }
@@ -1,25 +1,59 @@
-use crate::agent::{AgentCondition, DecisionAgent, UserAgent};
-use crate::events::{EventRarity, NightEvent};
+use crate::agent::{
+    AgentCondition, DecisionAgent, ScheduledOrder, UserAgent, LOW_REPUTATION_THRESHOLD,
+    SCHEDULED_DRAWDOWN_PERCENT, VACATION_COOLDOWN_DAYS,
+};
+use crate::api::ApiToken;
+use crate::board::{BoardDecision, Boards};
+use crate::clock::Maintenance;
+use crate::decimation::lttb;
+use crate::events::{
+    assassination_defense_description, EventRarity, NightEvent, ASSASSINATION_DEFENSES,
+};
+use crate::formatting::{number_format, Currency};
+use crate::futures::{FuturesBook, FUTURES_FEE};
+use crate::fx::{ExchangeRate, FX_FEE, OFFSHORE_CURRENCY_SYMBOL};
+use crate::leaderboard::{LeaderboardHistory, DAYS_PER_SEASON};
+use crate::manual::manual_pages;
 use crate::market::{
-    GamePhase, Market, DAY_LENGTH, HISTORICAL_SIZE, MAX_EVENTS_PER_NIGHT, NIGHT_LENGTH,
+    max_buy_amount_with_fee, trade_fee, GamePhase, Market, Trade, Venue, DAY_LENGTH,
+    HISTORICAL_SIZE, MAX_EVENTS_PER_NIGHT, NIGHT_LENGTH, NUMBER_OF_STONKS, TICKS_PER_HOUR,
 };
-use crate::stonk::DollarValue;
+use crate::money::Money;
+use crate::news::{rumor, NewsTone};
+use crate::notifications::{NotificationPriority, Notifications};
+use crate::server_status::{DebugStats, Percentiles};
+use crate::ssh_backend::{GraphicsProtocol, ImagePlacement};
+use crate::ssh_client::ConnectionStats;
+use crate::statistics::ServerStatistics;
+use crate::stonk::{
+    DollarValue, StonkClass, AFTER_HOURS_SPREAD_MULTIPLIER, PROJECTION_PERCENTILES,
+};
+use crate::totp;
+use crate::treasury::TreasuryFlows;
 use crate::utils::*;
 use crossterm::event::KeyCode;
-use once_cell::sync::Lazy;
+use image::RgbaImage;
+use once_cell::sync::{Lazy, OnceCell};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
 use ratatui::layout::{Constraint, Margin, Rect};
 use ratatui::style::palette::tailwind;
 use ratatui::style::{Color, Modifier, Style, Stylize};
 use ratatui::symbols;
 use ratatui::text::{Line, Span, Text};
 use ratatui::widgets::{
-    Axis, Block, Borders, Cell, Chart, Dataset, GraphType, HighlightSpacing, Paragraph, Row, Table,
-    TableState, Wrap,
+    Axis, Block, Borders, Cell, Chart, Clear, Dataset, GraphType, HighlightSpacing, Paragraph, Row,
+    Table, TableState, Tabs, Wrap,
 };
 use ratatui::{layout::Layout, Frame};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
 use std::fmt::{self};
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+use strum::IntoEnumIterator;
 
-const STONKS: [&'static str; 6] = [
+const STONKS: [&str; 6] = [
     "███████╗████████╗ ██████╗ ███╗   ██╗██╗  ██╗███████╗██╗",
     "██╔════╝╚══██╔══╝██╔═══██╗████╗  ██║██║ ██╔╝██╔════╝██║",
     "███████╗   ██║   ██║   ██║██╔██╗ ██║█████╔╝ ███████╗██║",
@@ -37,93 +71,84 @@ static STONKS_LINES: Lazy<Vec<Line>> = Lazy::new(|| {
 
 const NO_ANIMATION_FRAMES: usize = 6;
 const CARD_ANIMATION_FRAMES: usize = NO_ANIMATION_FRAMES + CARD_WIDTH as usize + 1;
-const ANIMATION_RATE: usize = 2;
-
-fn image_to_cards(path: &str) -> Vec<Vec<Line>> {
-    let back_image = read_image(path).expect("Cannot load image from file");
-    let back_lines = img_to_lines(&back_image).expect("Cannot convert image to lines");
-    let front_image = read_image("images/card_front.png").expect("Cannot load image from file");
-
-    // The whole night lasts for NIGHT_LENGTH (6 * 4 = 24 at the moment) seconds.
-    // The game renders at 20 FPS, so we got 480 frames total. We want to finish the card animation
-    // in 40 frames (2 seconds).
-
-    (0..CARD_ANIMATION_FRAMES)
-        .map(|n| {
-            let mut idx = n;
-            // Initial static card back
-            if idx < NO_ANIMATION_FRAMES {
-                return back_lines.clone();
-            }
-
-            idx -= NO_ANIMATION_FRAMES;
-            // card back shrinking animation
-            if idx < CARD_WIDTH as usize / 2 {
-                let nwidth = CARD_WIDTH as u32 - 2 * idx as u32;
-                let nheight = CARD_HEIGHT as u32;
-                let resized_image =
-                    resize_image(&back_image, nwidth, nheight).expect("Cannot resize image");
-                let lines = img_to_lines(&resized_image).expect("Cannot convert image to lines");
-
-                return lines
-                    .iter()
-                    .map(|l| {
-                        let mut spans = vec![];
-
-                        spans.push(Span::raw(
-                            " ".repeat((CARD_WIDTH as usize - nwidth as usize) / 2),
-                        ));
-                        for l in l.spans.iter() {
-                            spans.push(l.clone());
-                        }
-                        spans.push(Span::raw(
-                            " ".repeat((CARD_WIDTH as usize - nwidth as usize) / 2),
-                        ));
+// Duration of each animation frame, independent of the render interval.
+const ANIMATION_FRAME_MILLIS: u128 = 100;
 
-                        Line::from(spans)
-                    })
-                    .collect();
-            }
+// Card images are shown at CARD_ANIMATION_WIDTHS widths, from the full card to an empty one, two
+// columns narrower at each step.
+const CARD_ANIMATION_WIDTHS: usize = CARD_WIDTH as usize / 2 + 1;
 
-            idx -= CARD_WIDTH as usize / 2;
-            // card front expanding animation
-            if idx < CARD_WIDTH as usize / 2 {
-                let nwidth = 2 * idx as u32;
-                let nheight = CARD_HEIGHT as u32;
-                let resized_image =
-                    resize_image(&front_image, nwidth, nheight).expect("Cannot resize image");
-                let lines = img_to_lines(&resized_image).expect("Cannot convert image to lines");
+// A card image and its frames at the widths of the flip animation. Decoding the image and
+// converting each frame is done the first time they are shown, not at startup.
+struct CardImage {
+    path: &'static str,
+    image: OnceCell<RgbaImage>,
+    // The frame narrowed by 2 * i columns, padded back to the card width, is at index i.
+    frames: [OnceCell<Vec<Line<'static>>>; CARD_ANIMATION_WIDTHS],
+}
 
-                return lines
-                    .iter()
-                    .map(|l| {
-                        let mut spans = vec![];
-
-                        spans.push(Span::raw(
-                            " ".repeat((CARD_WIDTH as usize - nwidth as usize) / 2),
-                        ));
-                        for l in l.spans.iter() {
-                            spans.push(l.clone());
-                        }
-                        spans.push(Span::raw(
-                            " ".repeat((CARD_WIDTH as usize - nwidth as usize) / 2),
-                        ));
+impl CardImage {
+    const fn new(path: &'static str) -> Self {
+        Self {
+            path,
+            image: OnceCell::new(),
+            frames: [const { OnceCell::new() }; CARD_ANIMATION_WIDTHS],
+        }
+    }
 
-                        Line::from(spans)
-                    })
-                    .collect();
+    fn frame(&self, narrowed_by: usize) -> &Vec<Line<'static>> {
+        self.frames[narrowed_by].get_or_init(|| {
+            let image = self
+                .image
+                .get_or_init(|| read_image(self.path).expect("Cannot load image from file"));
+            if narrowed_by == 0 {
+                return img_to_lines(image).expect("Cannot convert image to lines");
             }
 
-            //card front
-            img_to_lines(&front_image).expect("Cannot convert image to lines")
+            let nwidth = CARD_WIDTH as u32 - 2 * narrowed_by as u32;
+            let resized_image =
+                resize_image(image, nwidth, CARD_HEIGHT as u32).expect("Cannot resize image");
+            img_to_lines(&resized_image)
+                .expect("Cannot convert image to lines")
+                .into_iter()
+                .map(|line| {
+                    let padding = " ".repeat(narrowed_by);
+                    let mut spans = Vec::with_capacity(line.spans.len() + 2);
+                    spans.push(Span::raw(padding.clone()));
+                    spans.extend(line.spans);
+                    spans.push(Span::raw(padding));
+                    Line::from(spans)
+                })
+                .collect()
         })
-        .collect::<Vec<Vec<Line>>>()
+    }
+
+    /// The frame of the card animation: the back is shown, then it shrinks and the front expands.
+    fn animation_frame(&self, n: usize) -> &Vec<Line<'static>> {
+        let half_width = CARD_WIDTH as usize / 2;
+        let idx = n.saturating_sub(NO_ANIMATION_FRAMES);
+        if n < NO_ANIMATION_FRAMES {
+            self.frame(0)
+        } else if idx < half_width {
+            self.frame(idx)
+        } else if idx < 2 * half_width {
+            CARD_FRONT.frame(2 * half_width - idx)
+        } else {
+            CARD_FRONT.frame(0)
+        }
+    }
 }
 
-static STONKS_CARDS: Lazy<Vec<Vec<Line>>> = Lazy::new(|| image_to_cards("images/stonks.png"));
-static DOGE_CARDS: Lazy<Vec<Vec<Line>>> = Lazy::new(|| image_to_cards("images/doge.png"));
-static ELON_CARDS: Lazy<Vec<Vec<Line>>> = Lazy::new(|| image_to_cards("images/elon.png"));
-static KIM_CARDS: Lazy<Vec<Vec<Line>>> = Lazy::new(|| image_to_cards("images/kim.png"));
+const STONKS_CARD_PATH: &str = "images/stonks.png";
+const DOGE_CARD_PATH: &str = "images/doge.png";
+const ELON_CARD_PATH: &str = "images/elon.png";
+const CHANGELOG_PATH: &str = "data/changelog.md";
+
+// The front is shared by all the cards.
+static CARD_FRONT: CardImage = CardImage::new("images/card_front.png");
+static STONKS_CARD: CardImage = CardImage::new(STONKS_CARD_PATH);
+static DOGE_CARD: CardImage = CardImage::new(DOGE_CARD_PATH);
+static ELON_CARD: CardImage = CardImage::new(ELON_CARD_PATH);
 
 static UNSELECTED_CARD: Lazy<Vec<Line>> = Lazy::new(|| {
     let image = read_image("images/unselected_card.png").expect("Cannot load image from file");
@@ -131,22 +156,60 @@ static UNSELECTED_CARD: Lazy<Vec<Line>> = Lazy::new(|| {
 });
 
 trait Carded {
-    fn cards(&self) -> &Vec<Vec<Line>>;
+    fn card(&self) -> &'static CardImage;
+    fn card_back_path(&self) -> &'static str;
 }
 
 impl Carded for NightEvent {
-    fn cards(&self) -> &Vec<Vec<Line>> {
+    fn card_back_path(&self) -> &'static str {
+        match self.rarity() {
+            EventRarity::Common => STONKS_CARD_PATH,
+            EventRarity::Uncommon => DOGE_CARD_PATH,
+            EventRarity::Rare => ELON_CARD_PATH,
+        }
+    }
+
+    fn card(&self) -> &'static CardImage {
         match self.rarity() {
-            EventRarity::Common => &*STONKS_CARDS,
-            EventRarity::Uncommon => &*DOGE_CARDS,
-            EventRarity::Rare => &*ELON_CARDS,
+            EventRarity::Common => &STONKS_CARD,
+            EventRarity::Uncommon => &DOGE_CARD,
+            EventRarity::Rare => &ELON_CARD,
         }
     }
 }
 
+const SIDEBAR_WIDTH: u16 = 28;
+// The sidebar is shown only if the terminal is wide enough to fit it next to the stonks table.
+const SIDEBAR_MIN_TERMINAL_WIDTH: u16 = 150;
+const SANDBOX_WATERMARK: &str =
+    " PRACTICE SANDBOX: trades here are not real, press `i` to go back to the market ";
+const SANDBOX_STYLE: Style = Style::new()
+    .fg(Color::Black)
+    .bg(Color::Yellow)
+    .add_modifier(Modifier::BOLD);
+// Ticks and random paths simulated for the ultra vision projection.
+const PROJECTION_TICKS: usize = 60;
+const PROJECTION_PATHS: usize = 100;
+// Redraws are paused for this long in copy mode, so that the text can be selected.
+const COPY_MODE_SECONDS: u64 = 10;
+// Flash sales and board news take at most this share of the ticker when there are trades,
+// scrolling by this many characters every tick when they do not fit.
+const TICKER_NEWS_PERCENT: u16 = 50;
+const TICKER_SCROLL_PER_TICK: usize = 4;
+const TICKER_SEPARATOR: &str = " · ";
+
 const CARD_WIDTH: u16 = 30;
 const CARD_HEIGHT: u16 = 40;
 
+const DEBUG_OVERLAY_WIDTH: u16 = 56;
+const MAINTENANCE_SPLASH_WIDTH: u16 = 72;
+// Clients listed with the size of their last frame, the largest first.
+const DEBUG_OVERLAY_MAX_CLIENTS: usize = 5;
+
+// Width of the y labels of the stonk chart, with the axis line.
+const CHART_Y_LABELS_WIDTH: usize = 7;
+const BRAILLE_DOTS_PER_COLUMN: usize = 2;
+
 const PALETTES: [tailwind::Palette; 5] = [
     tailwind::BLUE,
     tailwind::EMERALD,
@@ -179,11 +242,59 @@ impl TableColors {
     }
 }
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum UiDisplay {
     #[default]
     Stonks,
     Portfolio,
+    Tape,
+    Codex,
+    History,
+    Tournament,
+    Settings,
+    Manual,
+    About,
+    Changelog,
+    Statistics,
+}
+
+impl UiDisplay {
+    // Displays reachable from the tab bar, in order.
+    const TABS: [UiDisplay; 6] = [
+        UiDisplay::Stonks,
+        UiDisplay::Portfolio,
+        UiDisplay::History,
+        UiDisplay::Tape,
+        UiDisplay::Settings,
+        UiDisplay::Manual,
+    ];
+
+    fn tab_title(&self) -> &'static str {
+        match self {
+            UiDisplay::Stonks | UiDisplay::About => "Market",
+            UiDisplay::Portfolio => "Portfolio",
+            UiDisplay::History | UiDisplay::Tournament | UiDisplay::Statistics => "Leaderboard",
+            UiDisplay::Tape => "News",
+            UiDisplay::Settings => "Settings",
+            UiDisplay::Manual | UiDisplay::Codex | UiDisplay::Changelog => "Help",
+        }
+    }
+
+    // Displays without their own tab are shown under the closest one.
+    fn tab_index(&self) -> usize {
+        Self::TABS
+            .iter()
+            .position(|tab| tab.tab_title() == self.tab_title())
+            .unwrap_or_default()
+    }
+
+    fn next_tab(&self) -> Self {
+        Self::TABS[(self.tab_index() + 1) % Self::TABS.len()]
+    }
+
+    fn previous_tab(&self) -> Self {
+        Self::TABS[(self.tab_index() + Self::TABS.len() - 1) % Self::TABS.len()]
+    }
 }
 
 #[derive(Debug, Default, Clone, Copy)]
@@ -254,6 +365,42 @@ impl Styled for f64 {
     }
 }
 
+fn reputation_style(reputation: i32) -> Style {
+    if reputation >= 5 {
+        Style::default().green()
+    } else if reputation > LOW_REPUTATION_THRESHOLD {
+        Style::default()
+    } else if reputation > -5 {
+        Style::default().yellow()
+    } else {
+        Style::default().red()
+    }
+}
+
+fn condition_style(condition: AgentCondition) -> Style {
+    match condition {
+        AgentCondition::Prison => Style::default().red(),
+        AgentCondition::UltraVision => Style::default().magenta(),
+        AgentCondition::Vacation | AgentCondition::VacationCooldown => Style::default().cyan(),
+        AgentCondition::Audited => Style::default().yellow(),
+        AgentCondition::Famous => Style::default().green(),
+    }
+}
+
+// Conditions last in trading ticks, so the remaining time is given in trading days and hours.
+fn condition_text(condition: AgentCondition, until_tick: usize, current_tick: usize) -> String {
+    let hours = until_tick
+        .saturating_sub(current_tick)
+        .div_ceil(TICKS_PER_HOUR);
+    let hours_per_day = DAY_LENGTH / TICKS_PER_HOUR;
+    let remaining = if hours >= hours_per_day {
+        format!("{}d {}h", hours / hours_per_day, hours % hours_per_day)
+    } else {
+        format!("{}h", hours)
+    };
+    format!("{} {} {}", condition.icon(), condition.label(), remaining)
+}
+
 impl Styled for u64 {
     fn style(&self) -> Style {
         (*self as f64).style()
@@ -263,6 +410,183 @@ impl Styled for u64 {
     }
 }
 
+impl Styled for Money {
+    fn style(&self) -> Style {
+        self.as_f64_cents().style()
+    }
+    fn ustyle(&self) -> Style {
+        self.as_f64_cents().ustyle()
+    }
+}
+
+// Dialog used by guests to choose their own username and password,
+// or by registered players to change their username.
+#[derive(Debug, Default, Clone)]
+pub struct AccountDialog {
+    // When set, only a new username is asked for.
+    pub rename: bool,
+    pub username: String,
+    pub password: String,
+    pub editing_password: bool,
+    pub error: Option<String>,
+    // Set when the player confirms, until the server handles the request.
+    pub submitted: bool,
+}
+
+// Account settings screen to manage the API tokens used by bots.
+#[derive(Debug, Default, Clone)]
+pub struct ApiTokensScreen {
+    pub selected: usize,
+    // Value of the last created token, which is only shown once.
+    pub new_token: Option<String>,
+    pub error: Option<String>,
+}
+
+// Account settings screen to turn two-factor authentication on and off.
+#[derive(Debug, Default, Clone)]
+pub struct TwoFactorScreen {
+    // Secret being provisioned, until a code of it is confirmed.
+    pub secret: Option<Vec<u8>>,
+    pub code: String,
+    pub error: Option<String>,
+}
+
+// Code asked at login to players with two-factor authentication, before the game starts.
+#[derive(Debug, Default, Clone)]
+pub struct LoginCodePrompt {
+    pub code: String,
+    pub attempts: usize,
+    pub error: Option<String>,
+}
+
+// Entries of the menu shown after login, in order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MenuItem {
+    Play,
+    Leaderboard,
+    Profile,
+    Settings,
+    Changelog,
+    Quit,
+}
+
+impl MenuItem {
+    pub const ALL: [MenuItem; 6] = [
+        MenuItem::Play,
+        MenuItem::Leaderboard,
+        MenuItem::Profile,
+        MenuItem::Settings,
+        MenuItem::Changelog,
+        MenuItem::Quit,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            MenuItem::Play => "Play",
+            MenuItem::Leaderboard => "Leaderboard",
+            MenuItem::Profile => "Profile",
+            MenuItem::Settings => "Settings",
+            MenuItem::Changelog => "Changelog",
+            MenuItem::Quit => "Quit",
+        }
+    }
+}
+
+// Overlay drawn over the screen, such as a dialog or a prompt.
+#[derive(Debug, Clone)]
+pub enum Modal {
+    // Index of the selected entry of the menu, see MenuItem::ALL.
+    Menu {
+        selected: usize,
+    },
+    Account(AccountDialog),
+    ApiTokens(ApiTokensScreen),
+    TwoFactor(TwoFactorScreen),
+    // Number of days being chosen for a vacation.
+    Vacation {
+        days: usize,
+    },
+    // Changes since the given version, shown on the first login after an upgrade.
+    WhatsNew {
+        since: Option<String>,
+    },
+    // Index of the selected decision of the board of the stonk, see BoardDecision::iter.
+    Board {
+        stonk_id: usize,
+        selected: usize,
+    },
+    // Amount of the currency sold being exchanged, from dollars if to_offshore.
+    Currency {
+        to_offshore: bool,
+        amount: Money,
+    },
+    // Futures being opened on the stonk, on the amount of shares.
+    Futures {
+        stonk_id: usize,
+        amount: u32,
+        is_long: bool,
+    },
+}
+
+/// The screen of a player, as written in the session recordings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScreenState {
+    pub display: UiDisplay,
+    pub focus_on_stonk: Option<usize>,
+    pub selected_stonk_index: usize,
+    // Name of the topmost modal.
+    pub modal: Option<String>,
+}
+
+impl Modal {
+    fn name(&self) -> String {
+        match self {
+            Modal::Menu { selected } => format!("Menu on {}", MenuItem::ALL[*selected].label()),
+            Modal::Account(_) => "Account".to_string(),
+            Modal::ApiTokens(_) => "API tokens".to_string(),
+            Modal::TwoFactor(_) => "Two-factor".to_string(),
+            Modal::Vacation { .. } => "Vacation".to_string(),
+            Modal::WhatsNew { .. } => "What's new".to_string(),
+            Modal::Board { stonk_id, .. } => format!("Board of stonk {}", stonk_id),
+            Modal::Currency { .. } => "Currency".to_string(),
+            Modal::Futures { stonk_id, .. } => format!("Futures on stonk {}", stonk_id),
+        }
+    }
+}
+
+// Modals are drawn from the bottom of the stack, and only the topmost one receives the input.
+#[derive(Debug, Default, Clone)]
+pub struct ModalStack {
+    modals: Vec<Modal>,
+}
+
+impl ModalStack {
+    pub fn push(&mut self, modal: Modal) {
+        self.modals.push(modal);
+    }
+
+    pub fn pop(&mut self) -> Option<Modal> {
+        self.modals.pop()
+    }
+
+    pub fn top(&self) -> Option<&Modal> {
+        self.modals.last()
+    }
+
+    pub fn top_mut(&mut self) -> Option<&mut Modal> {
+        self.modals.last_mut()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.modals.is_empty()
+    }
+
+    /// Iterates over the modals from the bottom of the stack.
+    pub fn iter(&self) -> impl Iterator<Item = &Modal> {
+        self.modals.iter()
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct UiOptions {
     pub focus_on_stonk: Option<usize>,
@@ -271,7 +595,39 @@ pub struct UiOptions {
     palette_index: usize,
     pub(crate) zoom_level: ZoomLevel,
     pub render_counter: usize,
+    // Wall-clock start of the current animation.
+    pub animation_start: Option<Instant>,
     pub selected_event_card_index: usize,
+    pub venue: Venue,
+    pub selected_defense_index: usize,
+    pub connection_stats: ConnectionStats,
+    pub graphics: GraphicsProtocol,
+    // Latest state change, shown first in text mode.
+    pub announcement: Option<String>,
+    // End of the copy mode, if it was started.
+    pub copy_mode_until: Option<Instant>,
+    // Seconds left before an idle client is disconnected, once the warning is shown.
+    pub idle_countdown: Option<u64>,
+    // Set until players with two-factor authentication enter a valid code.
+    pub login_code: Option<LoginCodePrompt>,
+    pub modals: ModalStack,
+    // Whether trades go to the tournament paper account.
+    pub tournament_mode: bool,
+    // Whether the player is in the practice sandbox rather than the real market.
+    pub sandbox: bool,
+    // Index of the day shown in the leaderboard history, the last one if not set.
+    pub history_day: Option<usize>,
+    pub manual_page: usize,
+    // Lines scrolled down in the About page of a stonk.
+    pub about_scroll: u16,
+    // Contextual messages, the most relevant of which is shown in the status bar.
+    pub notifications: Notifications,
+    // Internals of the server, refreshed by the render loop while the admin debug overlay is open.
+    pub debug_overlay: Option<DebugStats>,
+    // Set while the server is under maintenance, replacing the game with a splash.
+    pub maintenance: Option<Maintenance>,
+    // Whether the session is being recorded for a bug report.
+    pub recording: bool,
 }
 
 impl UiOptions {
@@ -279,6 +635,78 @@ impl UiOptions {
         UiOptions::default()
     }
 
+    pub fn start_animation(&mut self) {
+        self.animation_start = Some(Instant::now());
+    }
+
+    pub fn start_copy_mode(&mut self) {
+        self.copy_mode_until = Some(Instant::now() + Duration::from_secs(COPY_MODE_SECONDS));
+    }
+
+    pub fn stop_copy_mode(&mut self) {
+        self.copy_mode_until = None;
+    }
+
+    /// What is on screen, for the session recordings. What is typed in the dialogs is left out.
+    pub fn screen_state(&self) -> ScreenState {
+        ScreenState {
+            display: self.display,
+            focus_on_stonk: self.focus_on_stonk,
+            selected_stonk_index: self.selected_stonk_index,
+            modal: if self.login_code.is_some() {
+                Some("Login code".to_string())
+            } else {
+                self.modals.top().map(Modal::name)
+            },
+        }
+    }
+
+    /// Brings back a recorded screen. Only the menu can be restored among the modals, so this
+    /// returns false if the screen had another one.
+    pub fn restore_screen_state(&mut self, state: &ScreenState) -> bool {
+        self.display = state.display;
+        self.focus_on_stonk = state.focus_on_stonk;
+        self.selected_stonk_index = state.selected_stonk_index;
+        self.modals = ModalStack::default();
+        let Some(modal) = state.modal.as_ref() else {
+            return true;
+        };
+        match (0..MenuItem::ALL.len()).find(|&selected| Modal::Menu { selected }.name() == *modal) {
+            Some(selected) => {
+                self.modals.push(Modal::Menu { selected });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether the keys typed now can be part of a password or of a code.
+    pub fn is_entering_secret(&self) -> bool {
+        self.login_code.is_some()
+            || matches!(
+                self.modals.top(),
+                Some(Modal::Account(_)) | Some(Modal::TwoFactor(_))
+            )
+    }
+
+    pub fn is_copy_mode(&self) -> bool {
+        self.copy_mode_until
+            .map(|until| Instant::now() < until)
+            .unwrap_or(false)
+    }
+
+    /// Whether the animation started last is still running.
+    pub fn is_animating(&self) -> bool {
+        self.animation_frame() < CARD_ANIMATION_FRAMES
+    }
+
+    /// Current animation frame, based on the time elapsed since the animation started.
+    pub fn animation_frame(&self) -> usize {
+        self.animation_start
+            .map(|start| (start.elapsed().as_millis() / ANIMATION_FRAME_MILLIS) as usize)
+            .unwrap_or(usize::MAX)
+    }
+
     pub fn handle_key_events(&mut self, key_code: KeyCode, agent: &UserAgent) -> AppResult<()> {
         let num_night_events = agent.available_night_events().len();
         match key_code {
@@ -299,7 +727,7 @@ impl UiOptions {
             }
 
             crossterm::event::KeyCode::Left => {
-                if agent.selected_action().is_none() && num_night_events > 0 {
+                if !agent.has_completed_night_selection() && num_night_events > 0 {
                     let idx = self.selected_event_card_index;
                     self.selected_event_card_index =
                         (idx + num_night_events - 1) % num_night_events;
@@ -307,7 +735,7 @@ impl UiOptions {
             }
 
             crossterm::event::KeyCode::Right => {
-                if agent.selected_action().is_none() && num_night_events > 0 {
+                if !agent.has_completed_night_selection() && num_night_events > 0 {
                     let idx = self.selected_event_card_index;
                     self.selected_event_card_index = (idx + 1) % num_night_events
                 }
@@ -318,8 +746,20 @@ impl UiOptions {
             crossterm::event::KeyCode::Char('c') => {
                 self.palette_index = (self.palette_index + 1) % PALETTES.len();
             }
-            crossterm::event::KeyCode::Char('p') => self.display = UiDisplay::Portfolio,
-            crossterm::event::KeyCode::Char('l') => self.display = UiDisplay::Stonks,
+            crossterm::event::KeyCode::Tab => self.set_display(self.display.next_tab()),
+            crossterm::event::KeyCode::BackTab => self.set_display(self.display.previous_tab()),
+            crossterm::event::KeyCode::Char('t') => self.set_display(UiDisplay::Tape),
+            crossterm::event::KeyCode::Char('x') => self.set_display(UiDisplay::Codex),
+            crossterm::event::KeyCode::Char('f') => self.set_display(UiDisplay::Tournament),
+            crossterm::event::KeyCode::Char('?') => self.set_display(UiDisplay::Manual),
+            crossterm::event::KeyCode::Char('w') => self.set_display(UiDisplay::History),
+            crossterm::event::KeyCode::Char('p') => self.set_display(UiDisplay::Statistics),
+            crossterm::event::KeyCode::Char('l') => {
+                self.about_scroll = 0;
+                self.set_display(UiDisplay::About)
+            }
+            crossterm::event::KeyCode::Char('h') => self.venue = self.venue.next(),
+            crossterm::event::KeyCode::Char('q') => self.modals.push(Modal::Menu { selected: 0 }),
 
             _ => {
                 for idx in 1..9 {
@@ -337,6 +777,84 @@ impl UiOptions {
         Ok(())
     }
 
+    pub fn set_display(&mut self, display: UiDisplay) {
+        if display == UiDisplay::History {
+            self.history_day = None;
+        }
+        self.display = display;
+    }
+
+    /// Moves through the leaderboard history: left and right by day, up and down by season.
+    /// Returns whether the key was used.
+    pub fn handle_history_keys(&mut self, key_code: KeyCode, history: &LeaderboardHistory) -> bool {
+        if !matches!(self.display, UiDisplay::History) || history.days().is_empty() {
+            return false;
+        }
+        let days = history.days();
+        let last = days.len() - 1;
+        let index = self.history_day.unwrap_or(last).min(last);
+        let season = days[index].season();
+        let index = match key_code {
+            KeyCode::Left => index.saturating_sub(1),
+            KeyCode::Right => (index + 1).min(last),
+            // Last day of the previous season.
+            KeyCode::Up => days[..index]
+                .iter()
+                .rposition(|day| day.season() < season)
+                .unwrap_or(0),
+            // First day of the next season.
+            KeyCode::Down => days
+                .iter()
+                .position(|day| day.season() > season)
+                .unwrap_or(last),
+            _ => return false,
+        };
+        self.history_day = Some(index);
+        true
+    }
+
+    /// Moves through the manual pages with left and right. Returns whether the key was used.
+    pub fn handle_manual_keys(&mut self, key_code: KeyCode, number_of_pages: usize) -> bool {
+        if !matches!(self.display, UiDisplay::Manual) || number_of_pages == 0 {
+            return false;
+        }
+        self.manual_page = match key_code {
+            KeyCode::Left => (self.manual_page + number_of_pages - 1) % number_of_pages,
+            KeyCode::Right => (self.manual_page + 1) % number_of_pages,
+            _ => return false,
+        };
+        true
+    }
+
+    /// Scrolls the About page with up and down, and changes stonk with left and right.
+    /// Returns whether the key was used.
+    pub fn handle_about_keys(&mut self, key_code: KeyCode) -> bool {
+        if !matches!(self.display, UiDisplay::About) {
+            return false;
+        }
+        let stonk_id = self.focus_on_stonk.unwrap_or(self.selected_stonk_index);
+        let stonk_id = match key_code {
+            KeyCode::Up => {
+                self.about_scroll = self.about_scroll.saturating_sub(1);
+                return true;
+            }
+            KeyCode::Down => {
+                self.about_scroll = self.about_scroll.saturating_add(1);
+                return true;
+            }
+            KeyCode::Left => (stonk_id + NUMBER_OF_STONKS - 1) % NUMBER_OF_STONKS,
+            KeyCode::Right => (stonk_id + 1) % NUMBER_OF_STONKS,
+            _ => return false,
+        };
+        if self.focus_on_stonk.is_some() {
+            self.focus_on_stonk = Some(stonk_id);
+        } else {
+            self.selected_stonk_index = stonk_id;
+        }
+        self.about_scroll = 0;
+        true
+    }
+
     pub fn reset(&mut self) {
         self.focus_on_stonk = None;
         self.zoom_level = ZoomLevel::Short;
@@ -350,7 +868,218 @@ impl UiOptions {
     }
 }
 
-fn build_stonks_table<'a>(market: &Market, agent: &UserAgent, colors: TableColors) -> Table<'a> {
+// Formatted text of the stonks table. Formatting it takes dozens of strings per stonk, so the
+// cells are kept between frames and only formatted again when the market ticks or something
+// shown in the table changes.
+#[derive(Debug, Default)]
+struct StonksTableCache {
+    key: Option<(usize, u64)>,
+    // The stonk rows, then the total row.
+    rows: Vec<StonksTableRow>,
+    // Number of times the cells were formatted.
+    builds: usize,
+}
+
+#[derive(Debug, Default)]
+struct StonksTableRow {
+    cells: Vec<(String, Style)>,
+    // The spans of each line of the last cell.
+    top_dogs: Vec<Vec<(String, Style)>>,
+}
+
+impl StonksTableCache {
+    /// Hashes what the table shows and can change between two ticks, like the trades of the
+    /// players, without formatting it.
+    fn key(market: &Market, agent: &UserAgent, ui_options: &UiOptions) -> (usize, u64) {
+        let mut hasher = DefaultHasher::new();
+        ui_options.sandbox.hash(&mut hasher);
+        for stonk in market.stonks.iter() {
+            stonk.historical_prices.is_empty().hash(&mut hasher);
+            stonk.current_unit_price_cents().hash(&mut hasher);
+            stonk.buy_price(1).hash(&mut hasher);
+            stonk.sell_price(1).hash(&mut hasher);
+            stonk.number_of_shares.hash(&mut hasher);
+            stonk.is_suspended().hash(&mut hasher);
+            agent.owned_stonks()[stonk.id].hash(&mut hasher);
+            for (holder, amount) in stonk.shareholders.iter().take(3) {
+                holder.hash(&mut hasher);
+                amount.hash(&mut hasher);
+                market.reputation_of(holder).hash(&mut hasher);
+            }
+        }
+        for (holder, amount) in market.portfolios.iter().take(3) {
+            holder.hash(&mut hasher);
+            amount.hash(&mut hasher);
+        }
+        (market.last_tick, hasher.finish())
+    }
+
+    /// Formats the cells again, unless they are still those of the same tick and state.
+    fn update(&mut self, market: &Market, agent: &UserAgent, ui_options: &UiOptions) {
+        let key = Self::key(market, agent, ui_options);
+        if self.key == Some(key) {
+            return;
+        }
+        self.key = Some(key);
+        self.builds += 1;
+
+        let mut avg_today_variation = 0.0;
+        let mut avg_max_variation = 0.0;
+        let mut avg_agent_share = 0.0;
+        let mut total_agent_stonk_value = Money::ZERO;
+
+        self.rows = market
+            .stonks
+            .iter()
+            .filter(|stonk| !stonk.historical_prices.is_empty())
+            .map(|stonk| {
+                let today_variation = market.today_variation(stonk.id);
+
+                avg_today_variation += today_variation * stonk.number_of_shares as f64;
+
+                let today_style = today_variation.style();
+
+                let max_variation = (stonk.current_unit_price_cents() as f64
+                    - stonk.starting_price as f64)
+                    / (stonk.starting_price as f64)
+                    * 100.0;
+
+                avg_max_variation += max_variation * stonk.number_of_shares as f64;
+
+                let max_style = (max_variation / 10.0).style();
+
+                let agent_share = stonk.to_stake(agent.owned_stonks()[stonk.id]) * 100.0;
+                avg_agent_share += agent_share * stonk.number_of_shares as f64;
+                let agent_style = agent_share.ustyle();
+
+                let agent_stonk_value = stonk.holdings_value(agent.owned_stonks()[stonk.id]);
+                total_agent_stonk_value = total_agent_stonk_value.saturating_add(agent_stonk_value);
+
+                let agent_stonk_style = if agent_stonk_value > Money::ZERO {
+                    today_style
+                } else {
+                    Style::default()
+                };
+
+                let top_dogs = stonk
+                    .shareholders
+                    .iter()
+                    .take(3)
+                    .map(|(holder, amount)| {
+                        let agent_share = stonk.to_stake(*amount) * 100.0;
+                        vec![
+                            (
+                                holder.clone(),
+                                reputation_style(market.reputation_of(holder)),
+                            ),
+                            (format!(" {:.03}%", agent_share), agent_share.ustyle()),
+                        ]
+                    })
+                    .collect();
+
+                // Suspended stonks cannot be traded, so they have no prices to show.
+                let (buy_cell, sell_cell) = if stonk.is_suspended() {
+                    (
+                        ("\nSUSPENDED".to_string(), Style::default().yellow().bold()),
+                        ("\n-".to_string(), Style::default().dark_gray()),
+                    )
+                } else {
+                    (
+                        (
+                            format!("\n{}", stonk.buy_price(1).format_dollars()),
+                            Style::default(),
+                        ),
+                        (
+                            format!("\n{}", stonk.sell_price(1).format_dollars()),
+                            Style::default(),
+                        ),
+                    )
+                };
+
+                StonksTableRow {
+                    cells: vec![
+                        (format!("\n{}", stonk.name), Style::default()),
+                        buy_cell,
+                        sell_cell,
+                        (format!("\n{:+.2}%", today_variation), today_style),
+                        (format!("\n{:+.2}%", max_variation), max_style),
+                        (format!("\n{:.03}%", agent_share), agent_style),
+                        (
+                            format!("\n{}", agent_stonk_value.format_dollars()),
+                            agent_stonk_style,
+                        ),
+                        (
+                            format!("\n{}", stonk.market_cap().format_dollars()),
+                            max_style,
+                        ),
+                    ],
+                    top_dogs,
+                }
+            })
+            .collect();
+
+        let total_number_of_shares = market
+            .stonks
+            .iter()
+            .map(|stonk| stonk.number_of_shares as u64)
+            .sum::<u64>() as f64;
+
+        avg_today_variation /= total_number_of_shares;
+        avg_max_variation /= total_number_of_shares;
+        avg_agent_share /= total_number_of_shares;
+
+        let total_max_variation_style = (avg_max_variation / 10.0).style();
+
+        let top_portfolios = market
+            .portfolios
+            .iter()
+            .take(3)
+            .map(|(holder, amount)| {
+                vec![(
+                    format!("{} {}", holder, amount.format_dollars()),
+                    Style::default(),
+                )]
+            })
+            .collect();
+
+        self.rows.push(StonksTableRow {
+            cells: vec![
+                ("\nTotal".to_string(), Style::default()),
+                ("\n".to_string(), Style::default()),
+                ("\n".to_string(), Style::default()),
+                (
+                    format!("\n{:+.2}%", avg_today_variation),
+                    avg_today_variation.style(),
+                ),
+                (
+                    format!("\n{:+.2}%", avg_max_variation),
+                    total_max_variation_style,
+                ),
+                (
+                    format!("\n{:.03}%", avg_agent_share),
+                    avg_agent_share.ustyle(),
+                ),
+                (
+                    format!("\n{}", total_agent_stonk_value.format_dollars()),
+                    total_agent_stonk_value.style(),
+                ),
+                (
+                    format!("\n{}", market.total_market_cap().format_dollars()),
+                    total_max_variation_style,
+                ),
+            ],
+            top_dogs: top_portfolios,
+        });
+    }
+}
+
+// Per-client state kept between frames to draw them faster.
+#[derive(Debug, Default)]
+pub struct RenderCache {
+    stonks_table: StonksTableCache,
+}
+
+fn build_stonks_table(cache: &StonksTableCache, colors: TableColors) -> Table<'_> {
     let header_style = Style::default()
         .fg(colors.header_fg)
         .bg(colors.header_bg)
@@ -376,136 +1105,38 @@ fn build_stonks_table<'a>(market: &Market, agent: &UserAgent, colors: TableColor
     .style(header_style)
     .height(1);
 
-    let mut avg_today_variation = 0.0;
-    let mut avg_max_variation = 0.0;
-    let mut avg_agent_share = 0.0;
-    let mut total_agent_stonk_value = 0;
-
-    let mut rows = market
-        .stonks
-        .iter()
-        .filter(|stonk| stonk.historical_prices.len() > 0)
-        .enumerate()
-        .map(|(i, stonk)| {
+    // The cells borrow the cached text, only the rows are allocated.
+    let total_index = cache.rows.len().saturating_sub(1);
+    let rows = cache.rows.iter().enumerate().map(|(i, row)| {
+        let style = if i == total_index {
+            Style::new().fg(colors.header_fg).bg(colors.header_bg)
+        } else {
             let color = match i % 2 {
                 0 => colors.normal_row_color,
                 _ => colors.alt_row_color,
             };
-
-            let n = market.last_tick % DAY_LENGTH;
-            let today_initial_price = if stonk.historical_prices.len() > n {
-                stonk.historical_prices[stonk.historical_prices.len() - n - 1]
-            } else {
-                0
-            };
-
-            let today_variation = if today_initial_price > 0 {
-                (stonk.current_unit_price_cents() as f64 - today_initial_price as f64)
-                    / (today_initial_price as f64)
-                    * 100.0
-            } else {
-                0.0
-            };
-
-            avg_today_variation += today_variation * stonk.number_of_shares as f64;
-
-            let today_style = today_variation.style();
-
-            let max_variation = (stonk.current_unit_price_cents() as f64
-                - stonk.starting_price as f64)
-                / (stonk.starting_price as f64)
-                * 100.0;
-
-            avg_max_variation += max_variation * stonk.number_of_shares as f64;
-
-            let max_style = (max_variation / 10.0).style();
-
-            let agent_share = stonk.to_stake(agent.owned_stonks()[stonk.id]) * 100.0;
-            avg_agent_share += agent_share * stonk.number_of_shares as f64;
-            let agent_style = agent_share.ustyle();
-
-            let agent_stonk_value =
-                agent.owned_stonks()[stonk.id] as u64 * stonk.current_unit_price_cents() as u64;
-            total_agent_stonk_value += agent_stonk_value;
-
-            let agent_stonk_style = if agent_stonk_value > 0 {
-                today_style
-            } else {
-                Style::default()
-            };
-
-            let top_shareholders = stonk
-                .shareholders
+            Style::new().fg(colors.row_fg).bg(color)
+        };
+        let top_dogs = row
+            .top_dogs
+            .iter()
+            .map(|spans| {
+                Line::from_iter(
+                    spans
+                        .iter()
+                        .map(|(text, style)| Span::styled(text.as_str(), *style)),
+                )
+            })
+            .collect::<Vec<Line>>();
+        Row::new(
+            row.cells
                 .iter()
-                .take(3)
-                .map(|(holder, amount)| {
-                    let agent_share = stonk.to_stake(*amount) * 100.0;
-                    let agent_style = agent_share.ustyle();
-                    Line::from(format!("{} {:.03}%", holder, agent_share)).style(agent_style)
-                })
-                .collect::<Vec<Line>>();
-
-            let market_cap_text = format!("\n${}", stonk.market_cap_cents().format());
-
-            Row::new(vec![
-                Cell::new(format!("\n{}", stonk.name)),
-                Cell::new(format!("\n${}", stonk.buy_price_cents(1).format()))
-                    .style(Style::default()),
-                Cell::new(format!("\n${}", stonk.sell_price_cents(1).format()))
-                    .style(Style::default()),
-                Cell::new(format!("\n{:+.2}%", today_variation)).style(today_style),
-                Cell::new(format!("\n{:+.2}%", max_variation)).style(max_style),
-                Cell::new(format!("\n{:.03}%", agent_share)).style(agent_style),
-                Cell::new(format!("\n${}", agent_stonk_value.format())).style(agent_stonk_style),
-                Cell::new(market_cap_text).style(max_style),
-                Cell::new(top_shareholders),
-            ])
-            .style(Style::new().fg(colors.row_fg).bg(color))
-            .height(3)
-        })
-        .collect::<Vec<Row>>();
-
-    let total_number_of_shares = market
-        .stonks
-        .iter()
-        .map(|stonk| stonk.number_of_shares as u64)
-        .sum::<u64>() as f64;
-
-    avg_today_variation /= total_number_of_shares;
-    avg_max_variation /= total_number_of_shares;
-    avg_agent_share /= total_number_of_shares;
-
-    let total_market_cap_text = format!("\n${}", market.total_market_cap().format());
-
-    let total_max_variation_style = (avg_max_variation / 10.0).style();
-
-    let top_portfolios = market
-        .portfolios
-        .iter()
-        .take(3)
-        .map(|(holder, amount)| {
-            let amount_text = format!("${}", amount.format());
-
-            Line::from(format!("{} {}", holder, amount_text))
-        })
-        .collect::<Vec<Line>>();
-
-    let total_row = Row::new(vec![
-        Cell::new(format!("\nTotal")),
-        Cell::new(format!("\n")),
-        Cell::new(format!("\n")),
-        Cell::new(format!("\n{:+.2}%", avg_today_variation)).style(avg_today_variation.style()),
-        Cell::new(format!("\n{:+.2}%", avg_max_variation)).style(total_max_variation_style),
-        Cell::new(format!("\n{:.03}%", avg_agent_share)).style(avg_agent_share.ustyle()),
-        Cell::new(format!("\n${}", total_agent_stonk_value.format()))
-            .style(total_agent_stonk_value.style()),
-        Cell::new(total_market_cap_text).style(total_max_variation_style),
-        Cell::new(top_portfolios),
-    ])
-    .style(Style::new().fg(colors.header_fg).bg(colors.header_bg))
-    .height(3);
-
-    rows.push(total_row);
+                .map(|(text, style)| Cell::new(text.as_str()).style(*style))
+                .chain(std::iter::once(Cell::new(top_dogs))),
+        )
+        .style(style)
+        .height(3)
+    });
 
     let bar = " █ ";
     Table::new(
@@ -534,13 +1165,15 @@ fn render_day(
     market: &Market,
     agent: &UserAgent,
     ui_options: &UiOptions,
+    cache: &mut RenderCache,
     area: Rect,
 ) -> AppResult<()> {
     if ui_options.focus_on_stonk.is_some() {
         render_stonk(frame, market, agent, ui_options, area)?;
     } else {
         let colors = TableColors::new(&PALETTES[ui_options.palette_index]);
-        let table = build_stonks_table(market, agent, colors);
+        cache.stonks_table.update(market, agent, ui_options);
+        let table = build_stonks_table(&cache.stonks_table, colors);
         frame.render_stateful_widget(
             table,
             area,
@@ -550,6 +1183,48 @@ fn render_day(
     Ok(())
 }
 
+fn render_sidebar(frame: &mut Frame, market: &Market, area: Rect) {
+    let movers = market.top_movers();
+    let mover_line = |(stonk_id, variation): &(usize, f64)| {
+        Line::from(vec![
+            Span::raw(format!("{:<6}", market.stonks[*stonk_id].short_name)),
+            Span::styled(format!("{:+.2}%", variation), variation.style()),
+        ])
+    };
+
+    let mut lines = vec![Line::from("Top gainers").bold()];
+    lines.extend(movers.iter().take(3).map(mover_line));
+    lines.push(Line::from(""));
+    lines.push(Line::from("Top losers").bold());
+    lines.extend(movers.iter().rev().take(3).map(mover_line));
+    lines.push(Line::from(""));
+    lines.push(Line::from("Volume today").bold());
+    lines.push(Line::from(market.volume_today.format_dollars()));
+    lines.push(Line::from(""));
+    lines.push(Line::from("Regime").bold());
+    lines.push(Line::from(market.regime()).style(market.global_drift.style()));
+
+    // The effective bumps, after the diminishing returns of the night events stacked on a class.
+    let class_bumps = market.class_bumps.of_day(market.phase.cycle());
+    if !class_bumps.is_empty() {
+        lines.push(Line::from(""));
+        lines.push(Line::from("Class bumps").bold());
+        for (class, count, total) in class_bumps {
+            lines.push(Line::from(format!(
+                "{:<10} {:+.2} x{}",
+                format!("{:?}", class),
+                total,
+                count
+            )));
+        }
+    }
+
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" Market summary ")),
+        area,
+    );
+}
+
 fn render_night(
     frame: &mut Frame,
     market: &Market,
@@ -557,7 +1232,8 @@ fn render_night(
     agent: &UserAgent,
     ui_options: &UiOptions,
     area: Rect,
-) -> AppResult<()> {
+) -> AppResult<Vec<ImagePlacement>> {
+    let mut images = vec![];
     let total_width = CARD_WIDTH * 3 + 18;
     let side_length = if area.width > total_width {
         (area.width - total_width) / 2
@@ -590,83 +1266,75 @@ fn render_night(
                 .split(v_split[2]);
 
         for i in 0..num_night_events {
-            let cards = agent.available_night_events()[i].cards();
+            let card = agent.available_night_events()[i].card();
             // If there is not more than half of the time still available, skip the animation
+            // Animations are also skipped on a poor connection or if the agent asked for reduced motion.
             if counter < NIGHT_LENGTH / 2
-                && ui_options.render_counter < ANIMATION_RATE * CARD_ANIMATION_FRAMES
+                && !ui_options.connection_stats.is_poor()
+                && !agent.reduced_motion()
+                && ui_options.animation_frame() < CARD_ANIMATION_FRAMES
             {
-                frame.render_widget(
-                    Paragraph::new(
-                        cards[(ui_options.render_counter / ANIMATION_RATE) % CARD_ANIMATION_FRAMES]
-                            .clone(),
-                    ),
-                    cards_split[i].inner(&Margin {
-                        horizontal: 2,
-                        vertical: 1,
-                    }),
-                );
+                let card_area = cards_split[i].inner(&Margin {
+                    horizontal: 2,
+                    vertical: 1,
+                });
+                // Terminals with a graphics protocol show the card back as an image instead.
+                if ui_options.graphics != GraphicsProtocol::None {
+                    images.push(ImagePlacement {
+                        path: agent.available_night_events()[i].card_back_path(),
+                        area: card_area,
+                    });
+                } else {
+                    frame.render_widget(
+                        Paragraph::new(card.animation_frame(ui_options.animation_frame()).clone()),
+                        card_area,
+                    );
+                }
             } else {
                 let selected_event = agent.available_night_events()[i].clone();
-                let border_style = if agent.selected_action().is_some() {
-                    Style::default().green().on_green()
-                } else {
-                    Style::default().red().on_red()
-                };
-                if let Some(action) = agent.selected_action().cloned() {
-                    if action == selected_event.action() {
-                        frame.render_widget(
-                            Paragraph::new(cards[CARD_ANIMATION_FRAMES - 1].clone())
-                                .block(Block::bordered().border_style(border_style)),
-                            cards_split[i].inner(&Margin {
-                                horizontal: 1,
-                                vertical: 0,
-                            }),
-                        );
-                        frame.render_widget(
-                            Block::bordered()
-                                .border_style(border_style)
-                                .borders(Borders::RIGHT | Borders::LEFT),
-                            cards_split[i],
-                        );
+                let is_selected = agent.selected_actions().contains(&selected_event.action());
+                let selection_completed = agent.has_completed_night_selection();
+                if is_selected
+                    || (!selection_completed && ui_options.selected_event_card_index == i)
+                {
+                    let border_style = if is_selected {
+                        Style::default().green().on_green()
                     } else {
-                        frame.render_widget(
-                            Paragraph::new(UNSELECTED_CARD.clone()),
-                            cards_split[i].inner(&Margin {
-                                horizontal: 2,
-                                vertical: 1,
-                            }),
-                        );
-                    }
+                        Style::default().red().on_red()
+                    };
+                    frame.render_widget(
+                        Paragraph::new(card.animation_frame(CARD_ANIMATION_FRAMES - 1).clone())
+                            .block(Block::bordered().border_style(border_style)),
+                        cards_split[i].inner(&Margin {
+                            horizontal: 1,
+                            vertical: 0,
+                        }),
+                    );
+                    frame.render_widget(
+                        Block::bordered()
+                            .border_style(border_style)
+                            .borders(Borders::RIGHT | Borders::LEFT),
+                        cards_split[i],
+                    );
+                } else if selection_completed {
+                    frame.render_widget(
+                        Paragraph::new(UNSELECTED_CARD.clone()),
+                        cards_split[i].inner(&Margin {
+                            horizontal: 2,
+                            vertical: 1,
+                        }),
+                    );
                 } else {
-                    if ui_options.selected_event_card_index == i {
-                        frame.render_widget(
-                            Paragraph::new(cards[CARD_ANIMATION_FRAMES - 1].clone())
-                                .block(Block::bordered().border_style(border_style)),
-                            cards_split[i].inner(&Margin {
-                                horizontal: 1,
-                                vertical: 0,
-                            }),
-                        );
-                        frame.render_widget(
-                            Block::bordered()
-                                .border_style(border_style)
-                                .borders(Borders::RIGHT | Borders::LEFT),
-                            cards_split[i],
-                        );
-                    } else {
-                        frame.render_widget(
-                            Paragraph::new(cards[CARD_ANIMATION_FRAMES - 1].clone()),
-                            cards_split[i].inner(&Margin {
-                                horizontal: 2,
-                                vertical: 1,
-                            }),
-                        );
-                    }
+                    frame.render_widget(
+                        Paragraph::new(card.animation_frame(CARD_ANIMATION_FRAMES - 1).clone()),
+                        cards_split[i].inner(&Margin {
+                            horizontal: 2,
+                            vertical: 1,
+                        }),
+                    );
                 }
 
-                let title_style = if agent.selected_action().is_some()
-                    && ui_options.selected_event_card_index == i
-                {
+                let title_style = if is_selected {
                     Style::default().green()
                 } else {
                     Style::default().black()
@@ -715,7 +1383,7 @@ fn render_night(
     }
     frame.render_widget(Paragraph::new(STONKS_LINES.clone()).centered(), v_split[0]);
 
-    Ok(())
+    Ok(images)
 }
 
 pub(crate) fn render_stonk(
@@ -729,7 +1397,7 @@ pub(crate) fn render_stonk(
         .focus_on_stonk
         .expect("Focus_on_stonk should be some.");
     let stonk = &market.stonks[stonk_id];
-    let styles = vec![
+    let styles = [
         Style::default().cyan(),
         Style::default().magenta(),
         Style::default().green(),
@@ -749,67 +1417,82 @@ pub(crate) fn render_stonk(
         ZoomLevel::Max => HISTORICAL_SIZE / graph_width,
     };
 
-    let x_data: Vec<f64> = (0..market.last_tick)
-        .rev()
-        .take((clustering * graph_width).min(stonk.historical_prices.len()))
-        .rev()
-        .map(|t| t as f64)
-        .collect();
-
-    let y_data: Vec<f64> = stonk
-        .historical_prices
-        .iter()
-        .rev()
-        .take((clustering * graph_width).min(stonk.historical_prices.len()))
-        .rev()
-        .map(|v| *v as f64 / 100.0)
-        .collect();
+    let end_tick = stonk.historical_prices.end_tick();
+    let start_tick = end_tick - (clustering * graph_width).min(stonk.historical_prices.len());
+    let x_data: Vec<f64> = (start_tick..end_tick).map(|t| t as f64).collect();
 
-    assert!(x_data.len() == y_data.len());
+    // Braille markers draw two points per column of the plot, next to the y labels.
+    let plot_columns = (area.width as usize).saturating_sub(CHART_Y_LABELS_WIDTH + 2);
+    let max_points = plot_columns * BRAILLE_DOTS_PER_COLUMN;
 
-    let datas: Vec<(f64, f64)> = x_data
+    let prices: Vec<(f64, f64)> = x_data
         .iter()
-        .enumerate()
-        .step_by(clustering)
-        .map(|(idx, _)| {
-            let max_idx = (idx + clustering).min(x_data.len());
-            (
-                x_data[idx],
-                y_data[idx..max_idx].iter().sum::<f64>() / (max_idx - idx) as f64,
-            )
-        })
+        .zip(stonk.historical_prices.window(start_tick, end_tick))
+        .map(|(x, price)| (*x, *price as f64 / 100.0))
         .collect();
+    let datas = lttb(&prices, max_points);
 
-    let datasets = vec![Dataset::default()
-        .graph_type(GraphType::Line)
-        .marker(symbols::Marker::Braille)
-        .style(styles[stonk.id])
-        .data(&datas)];
+    // The offshore prices are drawn over the main ones while trading offshore, on the ticks
+    // they were recorded for.
+    let offshore_datas: Vec<(f64, f64)> = if ui_options.venue == Venue::Offshore {
+        let offshore_prices = &market.offshore.historical_prices[stonk.id];
+        let prices: Vec<(f64, f64)> = (start_tick..end_tick)
+            .filter_map(|tick| {
+                offshore_prices
+                    .at_tick(tick)
+                    .map(|price| (tick as f64, price as f64 / 100.0))
+            })
+            .collect();
+        lttb(&prices, max_points)
+    } else {
+        vec![]
+    };
 
-    let min_y_bound;
-    let max_y_bound;
+    // Ultra vision shows where the price could go, as a cone of simulated future prices.
+    // The simulation is seeded by the tick, so that the cone is stable between redraws.
+    let has_projection = agent.has_condition(AgentCondition::UltraVision);
+    let projection = if has_projection {
+        let rng =
+            &mut ChaCha8Rng::seed_from_u64((market.last_tick * NUMBER_OF_STONKS + stonk.id) as u64);
+        stonk.projection(market.last_tick, PROJECTION_TICKS, PROJECTION_PATHS, rng)
+    } else {
+        vec![]
+    };
+    let (last_x, last_price) = datas.last().copied().unwrap_or_default();
+    let projection_bands: [Vec<(f64, f64)>; 3] =
+        std::array::from_fn(|band| {
+            std::iter::once((last_x, last_price))
+                .chain(projection.iter().enumerate().map(|(tick, prices)| {
+                    (last_x + (tick + 1) as f64, prices[band] as f64 / 100.0)
+                }))
+                .collect()
+        });
 
     let min_price = datas
         .iter()
+        .chain(offshore_datas.iter())
+        .chain(projection_bands[0].iter())
         .map(|(_, d)| *d as usize)
         .min()
         .unwrap_or_default();
     let max_price = datas
         .iter()
+        .chain(offshore_datas.iter())
+        .chain(projection_bands[2].iter())
         .map(|(_, d)| *d as usize)
         .max()
         .unwrap_or_default();
 
-    if min_price < 20 {
-        min_y_bound = 0;
+    let min_y_bound = if min_price < 20 {
+        0
     } else {
-        min_y_bound = min_price / 20 * 20 - 20;
-    }
-    if max_price < 20 {
-        max_y_bound = 40;
+        min_price / 20 * 20 - 20
+    };
+    let max_y_bound = if max_price < 20 {
+        40
     } else {
-        max_y_bound = max_price / 20 * 20 + 20 + max_price % 20;
-    }
+        max_price / 20 * 20 + 20 + max_price % 20
+    };
 
     let n_y_labels = area.height as usize / 6;
     let y_labels: Vec<Span<'static>> = (0..=n_y_labels)
@@ -822,8 +1505,59 @@ pub(crate) fn render_stonk(
         })
         .collect();
 
+    // The cone between the lowest and highest percentile is shaded by filling it with points,
+    // one for every row of the chart.
+    let dot_height = (max_y_bound - min_y_bound) as f64 / area.height as f64;
+    let cone: Vec<(f64, f64)> = projection_bands[0]
+        .iter()
+        .zip(projection_bands[2].iter())
+        .skip(1)
+        .flat_map(|(&(x, low), &(_, high))| {
+            std::iter::successors(Some(low + dot_height / 2.0), move |y| Some(y + dot_height))
+                .take_while(move |y| *y < high)
+                .map(move |y| (x, y))
+        })
+        .collect();
+
+    let mut datasets = vec![];
+    if has_projection {
+        datasets.push(
+            Dataset::default()
+                .graph_type(GraphType::Scatter)
+                .marker(symbols::Marker::Block)
+                .style(Style::default().fg(Color::Rgb(48, 48, 48)))
+                .data(&cone),
+        );
+        for band in projection_bands.iter() {
+            datasets.push(
+                Dataset::default()
+                    .graph_type(GraphType::Line)
+                    .marker(symbols::Marker::Braille)
+                    .style(Style::default().gray())
+                    .data(band),
+            );
+        }
+    }
+    if !offshore_datas.is_empty() {
+        datasets.push(
+            Dataset::default()
+                .name("offshore")
+                .graph_type(GraphType::Line)
+                .marker(symbols::Marker::Braille)
+                .style(Style::default().gray())
+                .data(&offshore_datas),
+        );
+    }
+    datasets.push(
+        Dataset::default()
+            .graph_type(GraphType::Line)
+            .marker(symbols::Marker::Braille)
+            .style(styles[stonk.id])
+            .data(&datas),
+    );
+
     let min_x_bound = x_data[0] as usize;
-    let max_x_bound = x_data[x_data.len() - 1] as usize;
+    let max_x_bound = x_data[x_data.len() - 1] as usize + projection.len();
     let x_labels = [min_x_bound, max_x_bound]
         .iter()
         .map(|v| v.to_string().bold())
@@ -838,7 +1572,17 @@ pub(crate) fn render_stonk(
     let chart = Chart::new(datasets)
         .block(
             Block::bordered()
-                .title(format!(" Stonk Market: {} ", stonk.name))
+                .title(if has_projection {
+                    format!(
+                        " Stonk Market: {} - projected {}/{}/{}th percentiles ",
+                        stonk.name,
+                        PROJECTION_PERCENTILES[0],
+                        PROJECTION_PERCENTILES[1],
+                        PROJECTION_PERCENTILES[2]
+                    )
+                } else {
+                    format!(" Stonk Market: {} ", stonk.name)
+                })
                 .style(styles[stonk.id])
                 .bold(),
         )
@@ -873,6 +1617,14 @@ fn clear(frame: &mut Frame) {
     frame.render_widget(clear, area);
 }
 
+// The offshore balance, only once the player holds some.
+fn offshore_cash_text(agent: &UserAgent) -> String {
+    if agent.offshore_cash() == Money::ZERO {
+        return String::new();
+    }
+    format!(" {}", agent.offshore_cash().format_in(Currency::Offshore))
+}
+
 fn render_header(
     frame: &mut Frame,
     market: &Market,
@@ -887,10 +1639,10 @@ fn render_header(
                 let amount = agent.owned_stonks()[stonk_id];
                 let stonk = &market.stonks[stonk_id];
                 format!(
-                    "Owned shares {} ({:.02}%) - value ${}",
+                    "Owned shares {} ({:.02}%) - value {}",
                     amount,
                     stonk.to_stake(agent.owned_stonks()[stonk.id]) * 100.0,
-                    (stonk.current_unit_price_cents() * amount).format()
+                    (stonk.current_unit_price_cents() * amount).format_dollars()
                 )
             } else {
                 format!(
@@ -910,246 +1662,3146 @@ fn render_header(
             )
         }
     };
-    let header_text = format!(
-        "{} - Cash: ${:<6.2} - {}",
-        market.phase.formatted(),
-        agent.cash_dollars(),
-        extra_text,
-    );
+    let reputation = agent.reputation();
+    let mut header = Line::from(vec![]);
+    if ui_options.sandbox {
+        header.spans.push(Span::styled(" PRACTICE ", SANDBOX_STYLE));
+        header.spans.push(Span::raw(" "));
+    }
+    header.spans.extend([
+        Span::raw(format!(
+            "{} - Cash: {:<7}{} - ",
+            market.phase.formatted(),
+            agent.cash().format_exact_dollars(),
+            offshore_cash_text(agent),
+        )),
+        Span::styled(
+            format!("Reputation {}", reputation),
+            reputation_style(reputation),
+        ),
+        Span::raw(format!(" - {}", extra_text)),
+    ]);
+    for (until_tick, condition) in agent.conditions().iter() {
+        header.spans.push(Span::styled(
+            format!(
+                " - {}",
+                condition_text(*condition, *until_tick, market.last_tick)
+            ),
+            condition_style(*condition),
+        ));
+    }
+    if agent.is_guest() {
+        header.spans.push(Span::styled(
+            " - Guest: press `u` to create your account",
+            Style::default().yellow(),
+        ));
+    }
 
-    frame.render_widget(Paragraph::new(header_text), area);
+    frame.render_widget(Paragraph::new(header), area);
 }
 
-fn render_stonk_info(
-    frame: &mut Frame,
-    market: &Market,
-    _agent: &UserAgent,
-    ui_options: &UiOptions,
-    area: Rect,
-) {
-    let stonk_id = if let Some(stonk_id) = ui_options.focus_on_stonk {
-        stonk_id
-    } else {
-        ui_options.selected_stonk_index
+// Stamped over the body, so that sandbox trades cannot be mistaken for real ones.
+fn render_sandbox_watermark(frame: &mut Frame, area: Rect) {
+    let text = SANDBOX_WATERMARK;
+    let width = (text.chars().count() as u16).min(area.width);
+    if area.height == 0 {
+        return;
+    }
+    let watermark_area = Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + area.height - 1,
+        width,
+        1,
+    );
+    frame.render_widget(Clear, watermark_area);
+    frame.render_widget(Paragraph::new(text).style(SANDBOX_STYLE), watermark_area);
+}
+
+fn render_status_bar(frame: &mut Frame, ui_options: &UiOptions, area: Rect) {
+    let Some(notification) = ui_options.notifications.current(Instant::now()) else {
+        return;
+    };
+    let style = match notification.priority {
+        NotificationPriority::Low => Style::default().dim(),
+        NotificationPriority::Normal => Style::default().yellow(),
+        NotificationPriority::High => Style::default().green().bold(),
+        NotificationPriority::Critical => Style::default().white().on_red().bold(),
     };
-    let stonk = &market.stonks[stonk_id];
     frame.render_widget(
-        Paragraph::new(stonk.description.clone()).wrap(Wrap { trim: true }),
+        Paragraph::new(format!(" {} ", notification.text)).style(style),
         area,
     );
 }
 
-fn render_footer(
-    frame: &mut Frame,
-    market: &Market,
-    agent: &UserAgent,
-    ui_options: &UiOptions,
-    area: Rect,
-) {
-    let mut lines: Vec<Line> = vec![];
+fn render_connection_stats(frame: &mut Frame, stats: &ConnectionStats, area: Rect) {
+    if stats.frames == 0 {
+        return;
+    }
 
-    match market.phase {
-        GamePhase::Day { .. } => {
-            if let Some(_) = ui_options.focus_on_stonk {
-                lines.push(
-                    format!(
-                        "{:28} {:28} {:28}",
-                        "`↑↓`:select stonk", "`return`:main table", "`z`:zoom level",
-                    )
-                    .into(),
-                );
-            } else {
-                lines.push(
-                    format!("{:28} {:28}", "`↑↓`:select stonk", "`return`:show graph",).into(),
-                );
-            }
+    let (text, style) = if stats.is_poor() {
+        (
+            format!(
+                "Poor connection ({:.0}ms, {:.0}% dropped): animations paused, try reconnecting ",
+                stats.round_trip_millis,
+                stats.drop_rate * 100.0
+            ),
+            Style::default().black().on_yellow(),
+        )
+    } else {
+        (
+            format!(
+                "{:.0}ms {:.0}% dropped ",
+                stats.round_trip_millis,
+                stats.drop_rate * 100.0
+            ),
+            Style::default().dim(),
+        )
+    };
+    let width = (text.chars().count() as u16).min(area.width);
+    let corner = Rect::new(area.x + area.width - width, area.y, width, 1);
+    frame.render_widget(Clear, corner);
+    frame.render_widget(Paragraph::new(text).style(style), corner);
+}
 
-            let stonk_id = if let Some(stonk_id) = ui_options.focus_on_stonk {
-                stonk_id
+fn trade_spans<'a>(market: &Market, trade: &Trade) -> Vec<Span<'a>> {
+    let stonk = &market.stonks[trade.stonk_id];
+    let (symbol, style) = if trade.is_buy {
+        ("▲", Style::default().green())
+    } else {
+        ("▼", Style::default().red())
+    };
+    let mut spans = vec![
+        Span::styled(format!("{} {}", symbol, stonk.short_name), style),
+        Span::raw(format!(
+            " x{} {}",
+            trade.amount,
+            trade.value.format_dollars()
+        )),
+    ];
+    if let Some(username) = trade.username.as_ref() {
+        spans.push(Span::styled(
+            format!(" {}", username),
+            Style::default().bold(),
+        ));
+    }
+    spans
+}
+
+fn render_ticker(frame: &mut Frame, market: &Market, area: Rect) {
+    let mut news = vec![];
+    // Flash sales are announced first, they only last a few ticks.
+    for stonk in market.stonks.iter() {
+        if let Some((factor, until_tick)) = stonk.mispricing() {
+            // A discount follows bad news, a premium good news.
+            let (label, style, tone) = if factor < 0.0 {
+                ("FLASH SALE", Style::default().green().bold(), NewsTone::Bad)
             } else {
-                ui_options.selected_stonk_index
+                ("PREMIUM", Style::default().red().bold(), NewsTone::Good)
             };
-            let stonk = &market.stonks[stonk_id];
-            let max_buy_amount = stonk.max_buy_amount(agent.cash());
-
-            lines.push(
-                format!(
-                    "{:28} {:28} {:28}",
-                    format!(
-                        "`b`: buy  x{} (${})",
-                        1.min(max_buy_amount),
-                        stonk.buy_price_cents(1.min(max_buy_amount)).format()
-                    ),
-                    format!(
-                        "`B`: buy  x{} (${})",
-                        100.min(max_buy_amount),
-                        stonk.buy_price_cents(100.min(max_buy_amount)).format()
-                    ),
-                    format!(
-                        "`m`: buy  x{} (${})",
-                        max_buy_amount,
-                        stonk.buy_price_cents(max_buy_amount).format()
-                    ),
-                )
-                .into(),
-            );
-            let owned_amount = agent.owned_stonks()[stonk.id];
-            lines.push(
+            if !news.is_empty() {
+                news.push(Span::raw(TICKER_SEPARATOR));
+            }
+            news.push(Span::styled(
                 format!(
-                    "{:28} {:28} {:28}",
-                    format!(
-                        "`s`: sell x{} (${})",
-                        1.min(owned_amount),
-                        stonk.sell_price_cents(1.min(owned_amount)).format()
-                    ),
-                    format!(
-                        "`S`: sell x{} (${})",
-                        100.min(owned_amount),
-                        stonk.sell_price_cents(100.min(owned_amount)).format()
-                    ),
-                    format!(
-                        "`d`: sell x{} (${})",
-                        owned_amount,
-                        stonk.sell_price_cents(owned_amount).format()
-                    ),
-                )
-                .into(),
-            );
+                    "{} {} {:+.0}% for {} ticks",
+                    label,
+                    stonk.short_name,
+                    factor * 100.0,
+                    until_tick.saturating_sub(market.last_tick)
+                ),
+                style,
+            ));
+            news.push(Span::raw(format!(
+                ": {}",
+                rumor(stonk, &market.stonks, tone, until_tick as u64)
+            )));
         }
-        GamePhase::Night { .. } => {
-            if let Some(action) = agent.selected_action().cloned() {
-                for event in agent.available_night_events().iter() {
-                    if event.action() == action {
-                        lines.push(format!("You selected `{}`", event).into());
-                    }
-                }
-            } else {
-                lines.push(format!("{:28} {:28}", "`←→`:select event", "`return`:confirm",).into());
-            }
+    }
+    // Then the board decisions, which are public.
+    for minutes in market.boards.news(market.last_tick) {
+        if !news.is_empty() {
+            news.push(Span::raw(TICKER_SEPARATOR));
         }
+        news.push(Span::styled(
+            format!(
+                "BOARD {} {}",
+                market.stonks[minutes.stonk_id].short_name,
+                minutes.decision.headline()
+            ),
+            Style::default().yellow().bold(),
+        ));
+        news.push(Span::raw(format!(", chaired by {}", minutes.username)));
     }
 
-    frame.render_widget(Paragraph::new(lines), area);
-}
-
-pub fn render(
-    frame: &mut Frame,
-    market: &Market,
-    agent: &UserAgent,
-    ui_options: &UiOptions,
-    number_of_players: usize,
-) -> AppResult<()> {
-    clear(frame);
+    let mut trades = vec![];
+    for trade in market.trade_tape.iter().rev() {
+        if !trades.is_empty() || !news.is_empty() {
+            trades.push(Span::raw(TICKER_SEPARATOR));
+        }
+        trades.extend(trade_spans(market, trade));
+    }
+    if news.is_empty() {
+        frame.render_widget(Paragraph::new(Line::from(trades)), area);
+        return;
+    }
 
-    let area = frame.size();
-    let split = Layout::vertical([
-        Constraint::Length(1), //header
-        Constraint::Min(0),    //body
-        Constraint::Length(3), //footer
+    // The news never push every trade off a narrow terminal.
+    let news_width = Line::from(news.clone()).width();
+    let max_news_width = if trades.is_empty() {
+        area.width
+    } else {
+        area.width * TICKER_NEWS_PERCENT / 100
+    };
+    let split = Layout::horizontal([
+        Constraint::Length((news_width as u16).min(max_news_width)),
+        Constraint::Min(0),
     ])
     .split(area);
+    frame.render_widget(Paragraph::new(Line::from(trades)), split[1]);
 
-    render_header(
-        frame,
-        market,
-        agent,
-        ui_options,
-        number_of_players,
+    if news_width <= split[0].width as usize {
+        frame.render_widget(Paragraph::new(Line::from(news)), split[0]);
+        return;
+    }
+    // Longer news scroll through their share, starting over once they have all been shown.
+    let cycle_width = news_width + TICKER_SEPARATOR.chars().count();
+    let offset = (market.last_tick * TICKER_SCROLL_PER_TICK) % cycle_width;
+    let mut scrolling = news.clone();
+    scrolling.push(Span::raw(TICKER_SEPARATOR));
+    scrolling.extend(news);
+    frame.render_widget(
+        Paragraph::new(Line::from(scrolling)).scroll((0, offset as u16)),
         split[0],
     );
+}
 
-    match ui_options.display {
-        UiDisplay::Portfolio => {}
-        UiDisplay::Stonks => match market.phase {
-            GamePhase::Day { .. } => {
-                let sub_split = Layout::vertical([
-                    Constraint::Min(0),    //body
-                    Constraint::Length(3), // stonk info / newspaper
-                ])
-                .split(split[1]);
-                render_day(frame, market, agent, ui_options, sub_split[0])?;
-                render_stonk_info(frame, market, agent, ui_options, sub_split[1]);
-            }
-            GamePhase::Night { counter, .. } => {
-                render_night(frame, market, counter, agent, ui_options, split[1])?
-            }
-        },
+fn render_treasury_report(frame: &mut Frame, market: &Market, area: Rect) {
+    let treasury = &market.treasury;
+    let flows_line = |label: &str, flows: TreasuryFlows| {
+        Line::from(vec![
+            Span::raw(format!("{:<10}", label)),
+            Span::styled(
+                format!("+{}", flows.inflows.format_dollars()),
+                Style::default().green(),
+            ),
+            Span::raw(" in, "),
+            Span::styled(
+                format!("-{}", flows.outflows.format_dollars()),
+                Style::default().red(),
+            ),
+            Span::raw(" out"),
+        ])
+    };
+    let mut lines = vec![Line::from(format!(
+        "Balance   {}",
+        treasury.balance().format_dollars()
+    ))];
+    lines.push(flows_line("Today", treasury.today()));
+    if let Some(yesterday) = treasury.yesterday() {
+        lines.push(flows_line("Yesterday", yesterday));
     }
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" Treasury ")),
+        area,
+    );
+}
 
-    render_footer(frame, market, agent, ui_options, split[2]);
+fn render_trade_tape(frame: &mut Frame, market: &Market, ui_options: &UiOptions, area: Rect) {
+    let split = Layout::vertical([Constraint::Length(5), Constraint::Min(0)]).split(area);
+    render_treasury_report(frame, market, split[0]);
+    let area = split[1];
 
-    Ok(())
+    let colors = TableColors::new(&PALETTES[ui_options.palette_index]);
+    let header = ["Tick", "Stonk", "Side", "Amount", "Value", "Trader"]
+        .into_iter()
+        .map(Cell::from)
+        .collect::<Row>()
+        .style(
+            Style::default()
+                .fg(colors.header_fg)
+                .bg(colors.header_bg)
+                .bold(),
+        )
+        .height(1);
+
+    let rows = market
+        .trade_tape
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, trade)| {
+            let color = match i % 2 {
+                0 => colors.normal_row_color,
+                _ => colors.alt_row_color,
+            };
+            let (side, side_style) = if trade.is_buy {
+                ("Buy", Style::default().green())
+            } else {
+                ("Sell", Style::default().red())
+            };
+            Row::new(vec![
+                Cell::new(trade.tick.to_string()),
+                Cell::new(market.stonks[trade.stonk_id].name.clone()),
+                Cell::new(side).style(side_style),
+                Cell::new(trade.amount.to_string()),
+                Cell::new(trade.value.format_dollars()),
+                Cell::new(
+                    trade
+                        .username
+                        .clone()
+                        .unwrap_or_else(|| "anonymous".to_string()),
+                ),
+            ])
+            .style(Style::new().fg(colors.row_fg).bg(color))
+        })
+        .collect::<Vec<Row>>();
+
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(10),
+            Constraint::Length(20),
+            Constraint::Length(6),
+            Constraint::Length(10),
+            Constraint::Length(12),
+            Constraint::Length(20),
+        ],
+    )
+    .header(header)
+    .bg(colors.buffer_bg)
+    .block(Block::bordered().title(" Trade tape "));
+
+    frame.render_widget(table, area);
 }
 
-#[cfg(test)]
-mod tests {
-    use super::{ANIMATION_RATE, CARD_WIDTH, DOGE_CARDS, ELON_CARDS, KIM_CARDS, STONKS_CARDS};
-    use crate::utils::AppResult;
-    use ratatui::{
-        backend::CrosstermBackend,
-        layout::{Layout, Margin},
-        widgets::Paragraph,
-        Terminal,
-    };
-    use std::{thread, time::Duration};
+fn render_leaderboard_history(
+    frame: &mut Frame,
+    market: &Market,
+    ui_options: &UiOptions,
+    area: Rect,
+) {
+    let history = &market.leaderboard_history;
+    let days = history.days();
+    if days.is_empty() {
+        frame.render_widget(
+            Paragraph::new(
+                "The leaderboard is recorded at the end of every day, come back tonight!",
+            )
+            .block(Block::bordered().title(" Leaderboard history ")),
+            area,
+        );
+        return;
+    }
 
-    #[test]
-    fn test_card_animation() -> AppResult<()> {
-        // create crossterm terminal to stdout
-        let backend = CrosstermBackend::new(std::io::stdout());
-        let mut terminal = Terminal::new(backend).unwrap();
-
-        let mut idx = 0;
-
-        let stonks = STONKS_CARDS.clone();
-        let doge = DOGE_CARDS.clone();
-        let elon = ELON_CARDS.clone();
-        let kim = KIM_CARDS.clone();
-
-        terminal.clear()?;
-        loop {
-            if idx == stonks.len() * ANIMATION_RATE {
-                break;
-            }
+    let colors = TableColors::new(&PALETTES[ui_options.palette_index]);
+    let header_style = Style::default()
+        .fg(colors.header_fg)
+        .bg(colors.header_bg)
+        .bold();
+    let row_style = |i: usize| {
+        Style::new().fg(colors.row_fg).bg(match i % 2 {
+            0 => colors.normal_row_color,
+            _ => colors.alt_row_color,
+        })
+    };
+    let index = ui_options
+        .history_day
+        .unwrap_or(days.len() - 1)
+        .min(days.len() - 1);
+    let day = &days[index];
 
-            thread::sleep(Duration::from_millis(50));
-            terminal.draw(|frame| {
-                let area = frame.size();
+    let split = Layout::horizontal([Constraint::Min(0), Constraint::Length(48)]).split(area);
 
-                let split = Layout::horizontal([CARD_WIDTH + 2].repeat(4)).split(area);
-                frame.render_widget(
-                    Paragraph::new(stonks[idx / ANIMATION_RATE].clone()),
-                    split[0].inner(&Margin {
-                        horizontal: 1,
-                        vertical: 1,
-                    }),
-                );
-                frame.render_widget(
-                    Paragraph::new(doge[idx / ANIMATION_RATE].clone()),
-                    split[1].inner(&Margin {
-                        horizontal: 1,
-                        vertical: 1,
-                    }),
+    let rows = day
+        .top
+        .iter()
+        .enumerate()
+        .map(|(rank, (username, value))| {
+            Row::new(vec![
+                Cell::new(format!("#{}", rank + 1)),
+                Cell::new(username.clone()),
+                Cell::new(value.format_dollars()),
+            ])
+            .style(row_style(rank))
+        })
+        .collect::<Vec<Row>>();
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(6),
+            Constraint::Length(20),
+            Constraint::Length(16),
+        ],
+    )
+    .header(
+        ["Rank", "Player", "Net worth"]
+            .into_iter()
+            .map(Cell::from)
+            .collect::<Row>()
+            .style(header_style),
+    )
+    .bg(colors.buffer_bg)
+    .block(Block::bordered().title(format!(
+        " Leaderboard of {} ({}/{}) `←→`:day `↑↓`:season ",
+        day.date,
+        index + 1,
+        days.len()
+    )));
+    frame.render_widget(table, split[0]);
+
+    let rows = history
+        .season_winners(day.season())
+        .into_iter()
+        .enumerate()
+        .map(|(i, (username, wins))| {
+            let streak = history.streak(&username);
+            Row::new(vec![
+                Cell::new(username),
+                Cell::new(wins.to_string()),
+                Cell::new(if streak > 0 {
+                    streak.to_string()
+                } else {
+                    String::new()
+                }),
+            ])
+            .style(row_style(i))
+        })
+        .collect::<Vec<Row>>();
+    let season_start = GamePhase::Day {
+        cycle: day.season() * DAYS_PER_SEASON,
+        counter: 0,
+    };
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(20),
+            Constraint::Length(10),
+            Constraint::Length(10),
+        ],
+    )
+    .header(
+        ["Player", "Days #1", "Streak"]
+            .into_iter()
+            .map(Cell::from)
+            .collect::<Row>()
+            .style(header_style),
+    )
+    .bg(colors.buffer_bg)
+    .block(Block::bordered().title(format!(" Season of {} ", season_start.formatted_season())));
+    frame.render_widget(table, split[1]);
+}
+
+pub fn leaderboard_history_text(
+    history: &LeaderboardHistory,
+    ui_options: &UiOptions,
+) -> Vec<String> {
+    let days = history.days();
+    if days.is_empty() {
+        return vec!["No day has ended yet.".to_string()];
+    }
+    let index = ui_options
+        .history_day
+        .unwrap_or(days.len() - 1)
+        .min(days.len() - 1);
+    let day = &days[index];
+    let mut lines = vec![format!(
+        "Leaderboard of {}, day {} of {}. Left and right change day, up and down season.",
+        day.date,
+        index + 1,
+        days.len()
+    )];
+    for (rank, (username, value)) in day.top.iter().enumerate() {
+        lines.push(format!(
+            "#{:<3} {:<20} {:>14}",
+            rank + 1,
+            username,
+            value.format()
+        ));
+    }
+    lines.push(String::new());
+    lines.push("Days as first this season:".to_string());
+    for (username, wins) in history.season_winners(day.season()) {
+        lines.push(format!("{:<20} {:>4}", username, wins));
+    }
+    lines
+}
+
+fn tournament_status(market: &Market, agent: &UserAgent) -> String {
+    let tournament = &market.tournament;
+    let status = if let Some(winner) = tournament.winner.as_ref() {
+        format!(
+            "{} won the last tournament. Press `j` to register for the next one.",
+            winner
+        )
+    } else if let Some(round) = tournament.current_round() {
+        format!(
+            "Round {} ends in {} ticks.",
+            tournament.rounds.len(),
+            round.ends_at_tick.saturating_sub(market.last_tick)
+        )
+    } else if tournament.rounds.is_empty() {
+        format!(
+            "Registration open: {}/{} players, the tournament starts at the first day with enough players. Press `j` to register.",
+            tournament.registered.len(),
+            tournament.config.min_players
+        )
+    } else {
+        format!(
+            "Round {} starts at the beginning of the day.",
+            tournament.rounds.len() + 1
+        )
+    };
+    match agent.tournament_account() {
+        Some(account) => format!(
+            "{} Your paper account is worth {}, press `e` to trade with it.",
+            status,
+            account.net_worth(&market.stonks).format_dollars()
+        ),
+        None => status,
+    }
+}
+
+fn render_tabs(frame: &mut Frame, ui_options: &UiOptions, area: Rect) {
+    let tabs = Tabs::new(UiDisplay::TABS.iter().map(|tab| tab.tab_title()))
+        .select(ui_options.display.tab_index())
+        .highlight_style(Style::default().reversed())
+        .divider("|");
+    let split = Layout::horizontal([Constraint::Min(0), Constraint::Length(28)]).split(area);
+    frame.render_widget(tabs, split[0]);
+    frame.render_widget(
+        Paragraph::new("`tab`/`shift+tab`:switch tab")
+            .right_aligned()
+            .dim(),
+        split[1],
+    );
+}
+
+fn render_portfolio(frame: &mut Frame, market: &Market, agent: &UserAgent, area: Rect) {
+    frame.render_widget(
+        Paragraph::new(
+            portfolio_text(market, agent)
+                .into_iter()
+                .map(Line::from)
+                .collect::<Vec<Line>>(),
+        )
+        .block(Block::bordered().title(" Portfolio ")),
+        area,
+    );
+}
+
+fn settings_text(agent: &UserAgent, ui_options: &UiOptions) -> Vec<String> {
+    let on_off = |value: bool| if value { "on" } else { "off" };
+    let mut lines = vec![
+        format!("`r` Reduced motion: {}", on_off(agent.reduced_motion())),
+        format!("`g` Text mode: {}", on_off(agent.text_mode())),
+        format!(
+            "`c` Color palette: {} of {}",
+            ui_options.palette_index + 1,
+            PALETTES.len()
+        ),
+        format!("`z` Chart zoom: {}", ui_options.zoom_level),
+        format!("`h` Trading venue: {}", ui_options.venue.name()),
+        format!("`i` Practice sandbox: {}", on_off(ui_options.sandbox)),
+        "`y` Copy the screen as text".to_string(),
+        format!("`@` Record my session: {}", on_off(ui_options.recording)),
+    ];
+    if agent.is_guest() {
+        lines.push("`u` Create your account".to_string());
+    } else {
+        lines.push("`u` Change username".to_string());
+        lines.push("`k` Manage API tokens".to_string());
+        lines.push(format!(
+            "`#` Two-factor authentication: {}",
+            on_off(agent.totp_secret().is_some())
+        ));
+    }
+    lines
+}
+
+fn render_settings(frame: &mut Frame, agent: &UserAgent, ui_options: &UiOptions, area: Rect) {
+    frame.render_widget(
+        Paragraph::new(
+            settings_text(agent, ui_options)
+                .into_iter()
+                .map(Line::from)
+                .collect::<Vec<Line>>(),
+        )
+        .block(Block::bordered().title(" Settings ")),
+        area,
+    );
+}
+
+fn render_changelog(frame: &mut Frame, area: Rect) {
+    let changelog = read_asset(CHANGELOG_PATH)
+        .map(|contents| String::from_utf8_lossy(contents).to_string())
+        .unwrap_or_default();
+    frame.render_widget(
+        Paragraph::new(changelog.lines().map(Line::from).collect::<Vec<Line>>())
+            .wrap(Wrap { trim: false })
+            .block(Block::bordered().title(" Changelog ")),
+        area,
+    );
+}
+
+fn parse_version(version: &str) -> Vec<u32> {
+    version
+        .trim()
+        .split('.')
+        .filter_map(|part| part.parse().ok())
+        .collect()
+}
+
+// Changelog sections start with a `## <version>` heading, the newest first.
+fn changelog_since<'a>(changelog: &'a str, since: Option<&str>) -> Vec<&'a str> {
+    let since = since.map(parse_version);
+    let mut lines = vec![];
+    let mut sections = 0;
+    let mut include = false;
+    for line in changelog.lines() {
+        if let Some(version) = line.strip_prefix("## ") {
+            // Without a version the player never saw the changelog, so only the latest
+            // section is new to them.
+            include = match since.as_ref() {
+                Some(since) => parse_version(version) > *since,
+                None => sections == 0,
+            };
+            sections += 1;
+        }
+        if include {
+            lines.push(line);
+        }
+    }
+    while lines.last().is_some_and(|line| line.trim().is_empty()) {
+        lines.pop();
+    }
+    lines
+}
+
+/// Lines of the changelog sections newer than the given version.
+pub fn whats_new(since: Option<&str>) -> Vec<&'static str> {
+    let changelog = read_asset(CHANGELOG_PATH)
+        .ok()
+        .and_then(|contents| std::str::from_utf8(contents).ok())
+        .unwrap_or_default();
+    changelog_since(changelog, since)
+}
+
+fn render_whats_new(frame: &mut Frame, since: Option<&str>, area: Rect) {
+    let width = 120.min(area.width);
+    let inner_width = width.saturating_sub(2).max(1) as usize;
+    let mut lines = whats_new(since)
+        .into_iter()
+        .map(Line::from)
+        .collect::<Vec<Line>>();
+    lines.push(Line::from(""));
+    lines.push(Line::from("`return`:close  `?` in game:manual").dim());
+    let height = lines
+        .iter()
+        .map(|line| line.width().div_ceil(inner_width).max(1))
+        .sum::<usize>() as u16;
+
+    let popup = centered_rect(width, height + 2, area);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .block(Block::bordered().title(" What's new ")),
+        popup,
+    );
+}
+
+fn render_manual(frame: &mut Frame, market: &Market, ui_options: &UiOptions, area: Rect) {
+    let pages = manual_pages(market);
+    let index = ui_options.manual_page.min(pages.len() - 1);
+    let split = Layout::horizontal([Constraint::Length(24), Constraint::Min(0)]).split(area);
+
+    let titles = pages
+        .iter()
+        .enumerate()
+        .map(|(i, page)| {
+            if i == index {
+                Line::from(Span::styled(page.title, Style::default().reversed()))
+            } else {
+                Line::from(page.title)
+            }
+        })
+        .collect::<Vec<Line>>();
+    frame.render_widget(
+        Paragraph::new(titles).block(Block::bordered().title(" Manual ")),
+        split[0],
+    );
+
+    let page = &pages[index];
+    frame.render_widget(
+        Paragraph::new(
+            page.lines
+                .iter()
+                .map(|line| Line::from(line.as_str()))
+                .collect::<Vec<Line>>(),
+        )
+        .wrap(Wrap { trim: false })
+        .block(
+            Block::bordered()
+                .title(format!(" {} ", page.title))
+                .title_bottom(" `←→`:change page "),
+        ),
+        split[1],
+    );
+}
+
+fn about_lines(market: &Market, ui_options: &UiOptions) -> Vec<String> {
+    let stonk = &market.stonks[ui_options
+        .focus_on_stonk
+        .unwrap_or(ui_options.selected_stonk_index)];
+    let lore = &stonk.lore;
+    let rivals = lore
+        .rivals
+        .iter()
+        .filter_map(|id| market.stonks.get(*id))
+        .map(|rival| format!("{} ({})", rival.name, rival.short_name))
+        .collect::<Vec<String>>();
+    let mut lines = vec![
+        format!("{} ({}) - {:?}", stonk.name, stonk.short_name, stonk.class),
+        format!(
+            "Founded: {}",
+            if lore.founded > 0 {
+                lore.founded.to_string()
+            } else {
+                "unknown".to_string()
+            }
+        ),
+        format!(
+            "CEO: {}",
+            if lore.ceo.is_empty() {
+                "unknown"
+            } else {
+                lore.ceo.as_str()
+            }
+        ),
+        format!(
+            "Rivals: {}",
+            if rivals.is_empty() {
+                "none".to_string()
+            } else {
+                rivals.join(", ")
+            }
+        ),
+    ];
+    if !stonk.description.is_empty() {
+        lines.push(String::new());
+        lines.push(stonk.description.clone());
+    }
+    for paragraph in lore.paragraphs.iter() {
+        lines.push(String::new());
+        lines.push(paragraph.clone());
+    }
+    lines
+}
+
+fn render_about(frame: &mut Frame, market: &Market, ui_options: &UiOptions, area: Rect) {
+    let stonk = &market.stonks[ui_options
+        .focus_on_stonk
+        .unwrap_or(ui_options.selected_stonk_index)];
+    frame.render_widget(
+        Paragraph::new(
+            about_lines(market, ui_options)
+                .into_iter()
+                .map(Line::from)
+                .collect::<Vec<Line>>(),
+        )
+        .wrap(Wrap { trim: false })
+        .scroll((ui_options.about_scroll, 0))
+        .block(
+            Block::bordered()
+                .title(format!(" About {} ", stonk.name))
+                .title_bottom(" `↑↓`:scroll `←→`:change stonk "),
+        ),
+        area,
+    );
+}
+
+fn manual_text(market: &Market, ui_options: &UiOptions) -> Vec<String> {
+    let pages = manual_pages(market);
+    let index = ui_options.manual_page.min(pages.len() - 1);
+    let page = &pages[index];
+    let mut lines = vec![format!(
+        "Manual page {} of {}: {}",
+        index + 1,
+        pages.len(),
+        page.title
+    )];
+    lines.extend(page.lines.iter().cloned());
+    lines
+}
+
+fn render_tournament(
+    frame: &mut Frame,
+    market: &Market,
+    agent: &UserAgent,
+    ui_options: &UiOptions,
+    area: Rect,
+) {
+    let tournament = &market.tournament;
+    let colors = TableColors::new(&PALETTES[ui_options.palette_index]);
+    let split = Layout::vertical([Constraint::Length(3), Constraint::Min(0)]).split(area);
+    frame.render_widget(
+        Paragraph::new(tournament_status(market, agent))
+            .wrap(Wrap { trim: true })
+            .block(Block::bordered().title(" Tournament ")),
+        split[0],
+    );
+
+    // One column for the registered players, then one for each round.
+    let columns = 1 + tournament.rounds.len();
+    let column_areas =
+        Layout::horizontal(vec![Constraint::Ratio(1, columns as u32); columns]).split(split[1]);
+
+    let registered = tournament
+        .registered
+        .iter()
+        .map(|username| {
+            let style = if username == agent.username() {
+                Style::default().bold()
+            } else {
+                Style::default()
+            };
+            Line::from(Span::styled(username.clone(), style))
+        })
+        .collect::<Vec<Line>>();
+    frame.render_widget(
+        Paragraph::new(registered)
+            .bg(colors.buffer_bg)
+            .block(Block::bordered().title(" Registered ")),
+        column_areas[0],
+    );
+
+    for (index, round) in tournament.rounds.iter().enumerate() {
+        let lines = if round.is_over() {
+            round
+                .results
+                .iter()
+                .map(|(username, value)| {
+                    let style = if round.eliminated.contains(username) {
+                        Style::default().red().dim()
+                    } else {
+                        Style::default().green()
+                    };
+                    Line::from(Span::styled(
+                        format!("{:<16} {}", username, value.format_dollars()),
+                        style,
+                    ))
+                })
+                .collect::<Vec<Line>>()
+        } else {
+            tournament
+                .alive()
+                .into_iter()
+                .map(Line::from)
+                .collect::<Vec<Line>>()
+        };
+        frame.render_widget(
+            Paragraph::new(lines)
+                .bg(colors.buffer_bg)
+                .block(Block::bordered().title(format!(" Round {} ", index + 1))),
+            column_areas[index + 1],
+        );
+    }
+}
+
+pub fn statistics_text(statistics: &ServerStatistics) -> Vec<String> {
+    let mut lines = vec![
+        format!("Total trades: {}", statistics.total_trades),
+        format!(
+            "Money printed: {}",
+            statistics.money_printed.format_dollars()
+        ),
+    ];
+    lines.push(match statistics.biggest_crash.as_ref() {
+        Some(crash) => format!(
+            "Biggest single-day crash: {} {:+.2}% on {}",
+            crash.stonk,
+            crash.day_return * 100.0,
+            GamePhase::Day {
+                cycle: crash.cycle,
+                counter: 0
+            }
+            .formatted_date()
+        ),
+        None => "Biggest single-day crash: none yet".to_string(),
+    });
+    lines.push(match statistics.most_assassinated() {
+        Some((username, count)) => format!(
+            "Most assassinated player: {} ({} time{})",
+            username,
+            count,
+            if count > 1 { "s" } else { "" }
+        ),
+        None => "Most assassinated player: nobody yet".to_string(),
+    });
+    lines
+}
+
+fn render_statistics(frame: &mut Frame, market: &Market, area: Rect) {
+    let mut lines = statistics_text(&market.statistics)
+        .into_iter()
+        .map(Line::from)
+        .collect::<Vec<Line>>();
+    lines.push(Line::from(""));
+    lines.push(Line::from("All-time statistics of the server, updated every morning.").dim());
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" Statistics ")),
+        area,
+    );
+}
+
+pub fn tournament_text(market: &Market, agent: &UserAgent) -> Vec<String> {
+    let tournament = &market.tournament;
+    let mut lines = vec![tournament_status(market, agent)];
+    lines.push(format!("Registered: {}", tournament.registered.join(", ")));
+    for (index, round) in tournament.rounds.iter().enumerate() {
+        if round.is_over() {
+            lines.push(format!(
+                "Round {}: {}. Eliminated: {}.",
+                index + 1,
+                round
+                    .results
+                    .iter()
+                    .map(|(username, value)| format!("{} {}", username, value.format_dollars()))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                round.eliminated.join(", ")
+            ));
+        } else {
+            lines.push(format!(
+                "Round {}: {} playing.",
+                index + 1,
+                tournament.alive().join(", ")
+            ));
+        }
+    }
+    lines
+}
+
+fn centered_rect(width: u16, height: u16, area: Rect) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+fn render_assassination_warning(
+    frame: &mut Frame,
+    market: &Market,
+    agent: &UserAgent,
+    ui_options: &UiOptions,
+    area: Rect,
+) {
+    let threats = agent.assassination_threats();
+    let ticks_left = threats
+        .iter()
+        .map(|threat| threat.until_tick.saturating_sub(market.last_tick))
+        .min()
+        .unwrap_or_default();
+
+    let mut lines = vec![
+        Line::from(format!(
+            "Word is out: {} is about to run a smear campaign against you.",
+            threats
+                .iter()
+                .map(|threat| threat.attacker.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        )),
+        Line::from(format!(
+            "Your stonks will crash in {} ticks unless you act.",
+            ticks_left
+        )),
+        Line::from(""),
+    ];
+
+    let pending = agent
+        .selected_actions()
+        .iter()
+        .any(|action| ASSASSINATION_DEFENSES.contains(action));
+    for (idx, defense) in ASSASSINATION_DEFENSES.iter().enumerate() {
+        let style = if idx == ui_options.selected_defense_index {
+            Style::default().black().on_red()
+        } else {
+            Style::default()
+        };
+        lines.push(Line::from(Span::styled(
+            format!(
+                "{}. {}",
+                idx + 1,
+                assassination_defense_description(defense, agent)
+            ),
+            style,
+        )));
+    }
+    lines.push(Line::from(""));
+    if pending {
+        lines.push(Line::from("Decision taken, hold tight...").dim());
+    } else {
+        lines.push(Line::from("`↑↓`:select  `enter`:confirm").dim());
+    }
+
+    let popup = centered_rect(84, lines.len() as u16 + 2, area);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(
+        Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+            Block::bordered()
+                .border_style(Style::default().red())
+                .title(" Character assassination "),
+        ),
+        popup,
+    );
+}
+
+/// Linear plain text display, without box drawing or images, meant for screen readers.
+fn render_text_mode(
+    frame: &mut Frame,
+    market: &Market,
+    agent: &UserAgent,
+    ui_options: &UiOptions,
+    area: Rect,
+) {
+    let mut lines = vec![];
+    if let Some(seconds) = ui_options.idle_countdown {
+        lines.push(format!(
+            "Warning: you will be disconnected for inactivity in {} seconds. Press any key to stay.",
+            seconds
+        ));
+    }
+    if ui_options.sandbox {
+        lines.push(SANDBOX_WATERMARK.trim().to_string());
+    }
+    if let Some(announcement) = ui_options.announcement.as_ref() {
+        lines.push(format!("Announcement: {}", announcement));
+    }
+    let top_modal = ui_options.modals.top();
+    if let Some(Modal::Menu { selected }) = top_modal {
+        lines.push(menu_greeting(agent));
+        lines.push(format!(
+            "Menu. Selected: {}. Entries: {}.",
+            MenuItem::ALL[*selected].label(),
+            MenuItem::ALL
+                .iter()
+                .map(|item| item.label())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        ));
+        lines.push(
+            "Keys: up and down to select, enter to confirm, q in game to come back here."
+                .to_string(),
+        );
+    } else if let Some(Modal::Account(dialog)) = top_modal {
+        if dialog.rename {
+            lines.push(format!(
+                "Change username. New username: {}.",
+                dialog.username
+            ));
+        } else {
+            lines.push(format!(
+                "Create account. Editing {}. Username: {}. Password has {} characters.",
+                if dialog.editing_password {
+                    "password"
+                } else {
+                    "username"
+                },
+                dialog.username,
+                dialog.password.chars().count()
+            ));
+        }
+        if let Some(error) = dialog.error.as_ref() {
+            lines.push(format!("Error: {}", error));
+        }
+        lines.push(
+            "Keys: type to edit, tab to switch field, enter to confirm, backspace on an empty field to cancel."
+                .to_string(),
+        );
+    } else if let Some(Modal::ApiTokens(screen)) = top_modal {
+        lines.push(format!(
+            "API tokens. Use a token as password to send bot commands over ssh. You have {} token{}.",
+            agent.api_tokens().len(),
+            if agent.api_tokens().len() == 1 { "" } else { "s" }
+        ));
+        if let Some(token) = screen.new_token.as_ref() {
+            lines.push(format!("New token, it will not be shown again: {}", token));
+        }
+        for (index, token) in agent.api_tokens().iter().enumerate() {
+            lines.push(format!(
+                "{}{}",
+                if index == screen.selected {
+                    "Selected: "
+                } else {
+                    ""
+                },
+                api_token_description(token)
+            ));
+        }
+        if let Some(error) = screen.error.as_ref() {
+            lines.push(format!("Error: {}", error));
+        }
+        lines.push(
+            "Keys: n to create a token, d to revoke the selected one, up and down to select, backspace to close."
+                .to_string(),
+        );
+    } else if let Some(Modal::TwoFactor(screen)) = top_modal {
+        lines.push("Two-factor authentication.".to_string());
+        lines.extend(two_factor_lines(agent, screen));
+        if let Some(error) = screen.error.as_ref() {
+            lines.push(format!("Error: {}", error));
+        }
+        lines.push(
+            "Keys: digits to type the code, enter to confirm, backspace on an empty code to close."
+                .to_string(),
+        );
+    } else if agent.is_guest() {
+        lines.push("You are playing as a guest. Press u to create your account.".to_string());
+    }
+    lines.push(format!(
+        "Time: {}. Cash: {}{}. Reputation: {}.",
+        market
+            .phase
+            .formatted()
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" "),
+        agent.cash().format_exact_dollars(),
+        offshore_cash_text(agent),
+        agent.reputation()
+    ));
+    for (until_tick, condition) in agent.conditions().iter() {
+        lines.push(format!(
+            "{}.",
+            condition_text(*condition, *until_tick, market.last_tick)
+        ));
+    }
+    lines.push(String::new());
+
+    let threats = agent.assassination_threats();
+    if matches!(market.phase, GamePhase::Day { .. }) && !threats.is_empty() {
+        lines.push(format!(
+            "Warning: {} is about to run a smear campaign against you. Choose a defense.",
+            threats
+                .iter()
+                .map(|threat| threat.attacker.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        ));
+        let defense = &ASSASSINATION_DEFENSES[ui_options.selected_defense_index];
+        lines.push(format!(
+            "Option {} of {}: {}",
+            ui_options.selected_defense_index + 1,
+            ASSASSINATION_DEFENSES.len(),
+            assassination_defense_description(defense, agent)
+        ));
+        lines.push("Keys: up and down to change option, enter to confirm.".to_string());
+    } else if let Some(Modal::WhatsNew { since }) = top_modal {
+        lines.push("What's new.".to_string());
+        lines.extend(
+            whats_new(since.as_deref())
+                .into_iter()
+                .filter(|line| !line.trim().is_empty())
+                .map(String::from),
+        );
+        lines.push("Keys: enter to close.".to_string());
+    } else if let Some(Modal::Board { stonk_id, selected }) = top_modal {
+        lines.push(format!("Board of {}.", market.stonks[*stonk_id].name));
+        lines.extend(board_prompt_lines(market, *stonk_id, *selected));
+        lines.push(
+            "Keys: up and down to change decision, enter to confirm, backspace to cancel."
+                .to_string(),
+        );
+    } else if let Some(Modal::Currency {
+        to_offshore,
+        amount,
+    }) = top_modal
+    {
+        lines.push("Currency exchange.".to_string());
+        lines.extend(currency_prompt_lines(
+            &market.offshore.exchange_rate,
+            agent,
+            *to_offshore,
+            *amount,
+        ));
+        lines.push(
+            "Keys: left and right to change amount, up and down to change direction, enter to confirm, backspace to cancel."
+                .to_string(),
+        );
+    } else if let Some(Modal::Futures {
+        stonk_id,
+        amount,
+        is_long,
+    }) = top_modal
+    {
+        lines.push(format!("Futures on {}.", market.stonks[*stonk_id].name));
+        lines.extend(futures_prompt_lines(
+            market, agent, *stonk_id, *amount, *is_long,
+        ));
+        lines.push(
+            "Keys: left and right to change amount, up and down to switch long and short, enter to confirm, backspace to cancel."
+                .to_string(),
+        );
+    } else if let Some(Modal::Vacation { days }) = top_modal {
+        lines.push(format!(
+            "Vacation for {} days. Trading is disabled and nobody can target you.",
+            days
+        ));
+        lines.push(
+            "Keys: left and right to change days, enter to confirm, backspace to cancel."
+                .to_string(),
+        );
+    } else {
+        match market.phase {
+            GamePhase::Day { .. } => {
+                let stonk_id = ui_options
+                    .focus_on_stonk
+                    .unwrap_or(ui_options.selected_stonk_index);
+                let stonk = &market.stonks[stonk_id];
+                lines.push(format!(
+                    "Stonk {} of {}: {}. Price {}. Today {:+.2} percent. You own {} shares.",
+                    stonk_id + 1,
+                    market.stonks.len(),
+                    stonk.name,
+                    stonk.current_unit_price_cents().format_dollars(),
+                    market.today_variation(stonk_id),
+                    agent.owned_stonks()[stonk_id]
+                ));
+                let (buy_price, sell_price) = if ui_options.venue == Venue::Offshore {
+                    (
+                        market.offshore.buy_price(stonk, 1).unwrap_or(Money::ZERO),
+                        market.offshore.sell_price(stonk, 1),
+                    )
+                } else {
+                    (stonk.buy_price(1), stonk.sell_price(1))
+                };
+                let currency = if ui_options.venue == Venue::Offshore {
+                    Currency::Offshore
+                } else {
+                    Currency::Dollar
+                };
+                lines.push(format!(
+                    "Buy one for {}. Sell one for {}. Trading venue is {}.",
+                    buy_price.format_in(currency),
+                    sell_price.format_in(currency),
+                    ui_options.venue.name()
+                ));
+                let (trades_this_tick, trades_today) = market.remaining_trades(agent);
+                if stonk.is_suspended() {
+                    lines.push(format!(
+                        "Trading of {} is suspended, its price is frozen.",
+                        stonk.name
+                    ));
+                }
+                lines.push(format!(
+                    "You can make {} more trades now and {} more today.",
+                    trades_this_tick, trades_today
+                ));
+                if ui_options.focus_on_stonk.is_some()
+                    && Boards::controller(stonk) == Some(agent.username())
+                {
+                    lines.push("You chair the board of this stonk, press n to decide.".to_string());
+                }
+                lines.push(
+                    "Keys: up and down to change stonk, b to buy one, m to buy max, s to sell one, d to sell all, h to toggle dark pool, g to leave text mode."
+                        .to_string(),
+                );
+            }
+            GamePhase::Night { .. } => {
+                let events = agent.available_night_events();
+                if events.is_empty() {
+                    lines.push("No special events available tonight.".to_string());
+                } else {
+                    let idx = ui_options.selected_event_card_index.min(events.len() - 1);
+                    let event = &events[idx];
+                    let is_selected = agent.selected_actions().contains(&event.action());
+                    lines.push(format!(
+                        "Event {} of {}: {}. {}.",
+                        idx + 1,
+                        events.len(),
+                        event,
+                        if is_selected {
+                            "Selected"
+                        } else {
+                            "Not selected"
+                        }
+                    ));
+                    lines.extend(event.description(agent, market));
+                    lines.push(format!(
+                        "Action points used {} of {}.",
+                        agent.spent_night_action_points(),
+                        agent.night_action_points()
+                    ));
+                }
+                lines.push(
+                    "Keys: left and right to change event, enter to select, backspace to undo, g to leave text mode."
+                        .to_string(),
+                );
+            }
+        }
+    }
+
+    frame.render_widget(
+        Paragraph::new(lines.into_iter().map(Line::from).collect::<Vec<Line>>())
+            .wrap(Wrap { trim: true }),
+        area,
+    );
+}
+
+/// The stonks table as plain aligned text.
+pub fn stonks_table_text(market: &Market, agent: &UserAgent) -> Vec<String> {
+    let mut lines = vec![format!(
+        "{:<20} {:>10} {:>10} {:>10} {:>10} {:>12}",
+        "Stonk", "Buy $", "Sell $", "Today +/-", "Stake", "Value"
+    )];
+    for stonk in market.stonks.iter() {
+        let amount = agent.owned_stonks()[stonk.id];
+        lines.push(format!(
+            "{:<20} {:>10} {:>10} {:>10} {:>10} {:>12}",
+            stonk.name,
+            stonk.buy_price(1).format(),
+            stonk.sell_price(1).format(),
+            format!("{:+.2}%", market.today_variation(stonk.id)),
+            format!("{:.03}%", stonk.to_stake(amount) * 100.0),
+            stonk.holdings_value(amount).format()
+        ));
+    }
+    lines
+}
+
+/// The agent portfolio as plain aligned text.
+pub fn portfolio_text(market: &Market, agent: &UserAgent) -> Vec<String> {
+    let mut lines = vec![format!(
+        "{:<20} {:>10} {:>10} {:>12}",
+        "Stonk", "Shares", "Price $", "Value $"
+    )];
+    let mut total_value = Money::ZERO;
+    for stonk in market.stonks.iter() {
+        let amount = agent.owned_stonks()[stonk.id];
+        if amount == 0 {
+            continue;
+        }
+        let value = stonk.holdings_value(amount);
+        total_value = total_value.saturating_add(value);
+        lines.push(format!(
+            "{:<20} {:>10} {:>10} {:>12}",
+            stonk.name,
+            amount,
+            stonk.current_unit_price_cents().format(),
+            value.format()
+        ));
+    }
+    lines.push(format!(
+        "{:<20} {:>10} {:>10} {:>12}",
+        "Stonks",
+        "",
+        "",
+        total_value.format()
+    ));
+    lines.push(format!(
+        "{:<20} {:>10} {:>10} {:>12}",
+        "Cash",
+        "",
+        "",
+        agent.cash().format()
+    ));
+    lines.push(format!(
+        "{:<20} {:>10} {:>10} {:>12}",
+        "Total",
+        "",
+        "",
+        total_value.saturating_add(agent.cash()).format()
+    ));
+    lines
+}
+
+fn render_copy_mode(
+    frame: &mut Frame,
+    market: &Market,
+    agent: &UserAgent,
+    ui_options: &UiOptions,
+    area: Rect,
+) {
+    let mut lines = vec![
+        format!(
+            "Copy mode: the screen is paused for {} seconds, select the text to copy it. Press any key to go back.",
+            COPY_MODE_SECONDS
+        ),
+        String::new(),
+        format!(
+            "{} - Cash: {}",
+            market.phase.formatted(),
+            agent.cash().format_exact_dollars()
+        ),
+        String::new(),
+    ];
+    if ui_options.sandbox {
+        lines.insert(1, SANDBOX_WATERMARK.trim().to_string());
+    }
+    lines.extend(match ui_options.display {
+        UiDisplay::Portfolio => portfolio_text(market, agent),
+        UiDisplay::History => leaderboard_history_text(&market.leaderboard_history, ui_options),
+        UiDisplay::Tournament => tournament_text(market, agent),
+        UiDisplay::Statistics => statistics_text(&market.statistics),
+        UiDisplay::Manual => manual_text(market, ui_options),
+        UiDisplay::Settings => settings_text(agent, ui_options),
+        UiDisplay::About => about_lines(market, ui_options),
+        _ => stonks_table_text(market, agent),
+    });
+
+    frame.render_widget(
+        Paragraph::new(lines.into_iter().map(Line::from).collect::<Vec<Line>>()),
+        area,
+    );
+}
+
+fn render_account_dialog(frame: &mut Frame, dialog: &AccountDialog, area: Rect) {
+    let field_style = |active: bool| {
+        if active {
+            Style::default().reversed()
+        } else {
+            Style::default()
+        }
+    };
+    let (title, description) = if dialog.rename {
+        (
+            " Change username ",
+            "Choose a new username. Your old one stays reserved for a while.",
+        )
+    } else {
+        (
+            " Create account ",
+            "Choose a username and password to keep playing with this account.",
+        )
+    };
+    let mut lines = vec![
+        Line::from(description),
+        Line::from(""),
+        Line::from(vec![
+            Span::raw("Username: "),
+            Span::styled(
+                format!("{:<16}", dialog.username),
+                field_style(!dialog.editing_password),
+            ),
+        ]),
+    ];
+    if !dialog.rename {
+        lines.push(Line::from(vec![
+            Span::raw("Password: "),
+            Span::styled(
+                format!("{:<16}", "*".repeat(dialog.password.chars().count())),
+                field_style(dialog.editing_password),
+            ),
+        ]));
+    }
+    lines.push(Line::from(""));
+    if let Some(error) = dialog.error.as_ref() {
+        lines.push(Line::from(error.clone()).red());
+    }
+    lines.push(Line::from("`tab`:switch field  `return`:confirm  `backspace`:delete/cancel").dim());
+
+    let popup = centered_rect(72, lines.len() as u16 + 2, area);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(title)),
+        popup,
+    );
+}
+
+fn api_token_description(token: &ApiToken) -> String {
+    let days = token
+        .created_at
+        .elapsed()
+        .map(|elapsed| elapsed.as_secs() / (60 * 60 * 24))
+        .unwrap_or_default();
+    format!(
+        "{}  created {}  {} requests/minute",
+        token.id,
+        match days {
+            0 => "today".to_string(),
+            1 => "yesterday".to_string(),
+            days => format!("{} days ago", days),
+        },
+        token.rate_limit_per_minute
+    )
+}
+
+fn render_api_tokens_screen(
+    frame: &mut Frame,
+    agent: &UserAgent,
+    screen: &ApiTokensScreen,
+    area: Rect,
+) {
+    let mut lines = vec![
+        Line::from("Use a token as password to send commands from your bots, for example"),
+        Line::from(format!(
+            "ssh -p 3333 {}@<server> portfolio",
+            agent.username()
+        ))
+        .cyan(),
+        Line::from(
+            "Commands: market, portfolio, buy <stonk id> <amount>, sell <stonk id> <amount>",
+        ),
+        Line::from(""),
+    ];
+    if let Some(token) = screen.new_token.as_ref() {
+        lines.push(Line::from("New token, copy it now as it will not be shown again:").yellow());
+        lines.push(Line::from(token.clone()).bold());
+        lines.push(Line::from(""));
+    }
+    if agent.api_tokens().is_empty() {
+        lines.push(Line::from("No tokens yet."));
+    }
+    for (index, token) in agent.api_tokens().iter().enumerate() {
+        let line = Line::from(api_token_description(token));
+        lines.push(if index == screen.selected {
+            line.reversed()
+        } else {
+            line
+        });
+    }
+    lines.push(Line::from(""));
+    if let Some(error) = screen.error.as_ref() {
+        lines.push(Line::from(error.clone()).red());
+    }
+    lines.push(
+        Line::from("`n`:new token  `d`:revoke selected  `↑↓`:select  `backspace`:close").dim(),
+    );
+
+    let popup = centered_rect(84, lines.len() as u16 + 2, area);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" API tokens ")),
+        popup,
+    );
+}
+
+fn two_factor_lines(agent: &UserAgent, screen: &TwoFactorScreen) -> Vec<String> {
+    let mut lines = vec![];
+    match screen.secret.as_ref() {
+        Some(secret) => {
+            lines.push(format!(
+                "Add this secret to your authenticator app: {}",
+                totp::base32_encode(secret)
+            ));
+            lines.push(format!(
+                "Then enter the code it shows to turn two-factor authentication on: {}",
+                screen.code
+            ));
+        }
+        None => {
+            lines.push(format!(
+                "Two-factor authentication is on: a code of your authenticator app is asked at every login as {}.",
+                agent.username()
+            ));
+            lines.push(format!("Enter a code to turn it off: {}", screen.code));
+        }
+    }
+    lines
+}
+
+fn render_two_factor_screen(
+    frame: &mut Frame,
+    agent: &UserAgent,
+    screen: &TwoFactorScreen,
+    area: Rect,
+) {
+    let mut lines = vec![];
+    if let Some(secret) = screen.secret.as_ref() {
+        lines.push(Line::from("Scan this code with your authenticator app:"));
+        // Light on dark, as the blocks are the light modules.
+        let qr_style = Style::default().fg(Color::White).bg(Color::Black);
+        for line in
+            totp::qr_lines(&totp::provisioning_uri(secret, agent.username())).unwrap_or_default()
+        {
+            lines.push(Line::from(Span::styled(line, qr_style)));
+        }
+    }
+    lines.extend(two_factor_lines(agent, screen).into_iter().map(Line::from));
+    if let Some(error) = screen.error.as_ref() {
+        lines.push(Line::from(error.clone()).red());
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::from("`0-9`:code  `return`:confirm  `backspace`:close").dim());
+
+    let width = lines
+        .iter()
+        .map(|line| line.width())
+        .max()
+        .unwrap_or_default() as u16
+        + 2;
+    let popup = centered_rect(width, lines.len() as u16 + 2, area);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" Two-factor authentication ")),
+        popup,
+    );
+}
+
+fn render_login_code_prompt(frame: &mut Frame, prompt: &LoginCodePrompt, area: Rect) {
+    let mut lines = vec![Line::from(format!(
+        "Enter the code of your authenticator app: {}",
+        prompt.code
+    ))];
+    if let Some(error) = prompt.error.as_ref() {
+        lines.push(Line::from(error.clone()).red());
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::from("`0-9`:code  `return`:confirm  `esc`:quit").dim());
+
+    let popup = centered_rect(60, lines.len() as u16 + 2, area);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" Two-factor authentication ")),
+        popup,
+    );
+}
+
+fn debug_overlay_lines(market: &Market, stats: &DebugStats) -> Vec<Line<'static>> {
+    let timings = |name: &str, percentiles: &Percentiles| {
+        Line::from(format!(
+            "{:<10} p50 {:>6.1}ms p99 {:>6.1}ms max {:>6.1}ms",
+            name,
+            percentiles.p50.as_secs_f64() * 1_000.0,
+            percentiles.p99.as_secs_f64() * 1_000.0,
+            percentiles.max.as_secs_f64() * 1_000.0,
+        ))
+    };
+    let mut lines = vec![
+        timings("Tick", &stats.tick_duration),
+        timings("Lock wait", &stats.lock_wait),
+        Line::from(format!(
+            "RNG seed   {}",
+            market
+                .last_tick_seed
+                .map(|seed| seed.to_string())
+                .unwrap_or_else(|| "none yet".to_string())
+        )),
+        Line::from(""),
+        Line::from("Frame bytes").bold(),
+    ];
+    lines.extend(
+        stats
+            .frame_bytes
+            .iter()
+            .take(DEBUG_OVERLAY_MAX_CLIENTS)
+            .map(|(username, bytes)| Line::from(format!("{:<20} {:>8}", username, bytes))),
+    );
+    if stats.frame_bytes.len() > DEBUG_OVERLAY_MAX_CLIENTS {
+        lines.push(Line::from(format!(
+            "and {} more",
+            stats.frame_bytes.len() - DEBUG_OVERLAY_MAX_CLIENTS
+        )));
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::from("Stonk conditions").bold());
+    for stonk in market.stonks.iter() {
+        for (until_tick, condition) in stonk.conditions().iter() {
+            lines.push(Line::from(format!(
+                "{:<6} {:?} until {}",
+                stonk.short_name, condition, until_tick
+            )));
+        }
+    }
+    lines
+}
+
+// Shown to the admins over any screen, in the top right corner.
+fn render_debug_overlay(frame: &mut Frame, market: &Market, stats: &DebugStats, area: Rect) {
+    let lines = debug_overlay_lines(market, stats);
+    let width = DEBUG_OVERLAY_WIDTH.min(area.width);
+    let height = (lines.len() as u16 + 2).min(area.height);
+    let overlay_area = Rect::new(area.x + area.width - width, area.y, width, height);
+    frame.render_widget(Clear, overlay_area);
+    frame.render_widget(
+        Paragraph::new(lines).block(
+            Block::bordered()
+                .title(" Debug ")
+                .border_style(Style::default().magenta()),
+        ),
+        overlay_area,
+    );
+}
+
+fn maintenance_lines(maintenance: &Maintenance, now: Instant) -> Vec<Line<'static>> {
+    let mut lines = vec![
+        Line::from("Stonks is under maintenance").bold(),
+        Line::from(""),
+    ];
+    if !maintenance.message.is_empty() {
+        lines.push(Line::from(maintenance.message.clone()));
+        lines.push(Line::from(""));
+    }
+    lines.push(Line::from(match maintenance.back_at {
+        Some(back_at) if back_at > now => {
+            let minutes = back_at.duration_since(now).as_secs().div_ceil(60);
+            format!(
+                "Back in about {} minute{}.",
+                minutes,
+                if minutes > 1 { "s" } else { "" }
+            )
+        }
+        Some(_) => "Back any moment now.".to_string(),
+        None => "Back as soon as possible.".to_string(),
+    }));
+    lines.push(Line::from(
+        "The market is frozen: no tick passes and no trade is made.",
+    ));
+    lines.push(Line::from(""));
+    lines.push(Line::from("Your session stays open, press `esc` to quit.").dim());
+    lines
+}
+
+// Replaces the whole game, since nothing can be done until the maintenance ends.
+fn render_maintenance(frame: &mut Frame, maintenance: &Maintenance, plain: bool, area: Rect) {
+    let lines = maintenance_lines(maintenance, Instant::now());
+    if plain {
+        frame.render_widget(Paragraph::new(lines).wrap(Wrap { trim: true }), area);
+        return;
+    }
+
+    let width = MAINTENANCE_SPLASH_WIDTH.min(area.width);
+    let inner_width = width.saturating_sub(2).max(1) as usize;
+    let height = lines
+        .iter()
+        .map(|line| line.width().max(1).div_ceil(inner_width) as u16)
+        .sum::<u16>()
+        + 2;
+    frame.render_widget(
+        Paragraph::new(lines)
+            .centered()
+            .wrap(Wrap { trim: true })
+            .block(
+                Block::bordered()
+                    .title(" Maintenance ")
+                    .border_style(Style::default().yellow()),
+            ),
+        centered_rect(width, height, area),
+    );
+}
+
+fn render_idle_warning(frame: &mut Frame, seconds: u64, area: Rect) {
+    let lines = vec![
+        Line::from(format!(
+            "You will be disconnected for inactivity in {} second{}.",
+            seconds,
+            if seconds > 1 { "s" } else { "" }
+        )),
+        Line::from(""),
+        Line::from("Press any key to stay").dim(),
+    ];
+
+    let popup = centered_rect(60, lines.len() as u16 + 2, area);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" Still there? ")),
+        popup,
+    );
+}
+
+fn menu_greeting(agent: &UserAgent) -> String {
+    if agent.is_guest() {
+        "Welcome! You are playing as a guest, open your Profile to create an account.".to_string()
+    } else {
+        format!("Welcome back, {}!", agent.username())
+    }
+}
+
+// The menu is the first screen after login, so it hides the game behind it.
+fn render_menu(frame: &mut Frame, market: &Market, agent: &UserAgent, selected: usize, area: Rect) {
+    let mut lines = vec![
+        Line::from(menu_greeting(agent)),
+        Line::from(format!(
+            "{} - net worth {}",
+            market.phase.formatted_date(),
+            market.net_worth(agent).format_dollars()
+        )),
+        Line::from(""),
+    ];
+    for (index, item) in MenuItem::ALL.iter().enumerate() {
+        if index == selected {
+            lines.push(Line::from(format!("> {}", item.label())).reversed());
+        } else {
+            lines.push(Line::from(format!("  {}", item.label())));
+        }
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::from("`↑↓`:select  `return`:confirm  `q` in game:back to the menu").dim());
+
+    frame.render_widget(Clear, area);
+    let popup = centered_rect(72, lines.len() as u16 + 2, area);
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" Stonks ")),
+        popup,
+    );
+}
+
+fn board_prompt_lines(market: &Market, stonk_id: usize, selected: usize) -> Vec<String> {
+    let mut lines = vec![];
+    for (index, decision) in BoardDecision::iter().enumerate() {
+        lines.push(format!(
+            "{} {}: {}",
+            if index == selected { ">" } else { " " },
+            decision.name(),
+            decision.description()
+        ));
+    }
+    let next_tick = market.boards.next_decision_tick(stonk_id);
+    if market.last_tick < next_tick {
+        lines.push(format!(
+            "The board meets again in {} ticks.",
+            next_tick - market.last_tick
+        ));
+    }
+    lines
+}
+
+fn render_board_prompt(
+    frame: &mut Frame,
+    market: &Market,
+    stonk_id: usize,
+    selected: usize,
+    area: Rect,
+) {
+    let mut lines = vec![
+        Line::from("As the largest shareholder, you chair the board. Decisions are public."),
+        Line::from(""),
+    ];
+    for (index, line) in board_prompt_lines(market, stonk_id, selected)
+        .into_iter()
+        .enumerate()
+    {
+        if index == selected {
+            lines.push(Line::from(line).reversed());
+        } else {
+            lines.push(Line::from(line));
+        }
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::from("`↑↓`:select  `return`:confirm  `backspace`:cancel").dim());
+
+    let popup = centered_rect(120, lines.len() as u16 + 2, area);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(
+        Paragraph::new(lines)
+            .block(Block::bordered().title(format!(" Board of {} ", market.stonks[stonk_id].name))),
+        popup,
+    );
+}
+
+fn currency_prompt_lines(
+    exchange_rate: &ExchangeRate,
+    agent: &UserAgent,
+    to_offshore: bool,
+    amount: Money,
+) -> Vec<String> {
+    let (sold, bought) = if to_offshore {
+        (Currency::Dollar, Currency::Offshore)
+    } else {
+        (Currency::Offshore, Currency::Dollar)
+    };
+    let (received, _) = exchange_rate.convert(amount, to_offshore);
+    let format = number_format();
+    vec![
+        format!(
+            "Balances: {} and {}. Rate: {} = {} ({:+.2}% today).",
+            agent.cash().format_dollars(),
+            agent.offshore_cash().format_in(Currency::Offshore),
+            format.with_symbol("1", Currency::Offshore),
+            format.with_symbol(&format.decimal(exchange_rate.rate(), 4), Currency::Dollar),
+            exchange_rate.day_change()
+        ),
+        format!(
+            "Exchange {} for {}, after a {:.1}% fee.",
+            amount.format_in(sold),
+            received.format_in(bought),
+            FX_FEE * 100.0
+        ),
+    ]
+}
+
+fn render_currency_prompt(
+    frame: &mut Frame,
+    market: &Market,
+    agent: &UserAgent,
+    to_offshore: bool,
+    amount: Money,
+    area: Rect,
+) {
+    let mut lines = vec![
+        Line::from("The offshore exchange trades in its own currency."),
+        Line::from(""),
+    ];
+    lines.extend(
+        currency_prompt_lines(&market.offshore.exchange_rate, agent, to_offshore, amount)
+            .into_iter()
+            .map(Line::from),
+    );
+    lines.push(Line::from(""));
+    lines.push(
+        Line::from("`←→`:amount  `↑↓`:direction  `return`:confirm  `backspace`:cancel").dim(),
+    );
+
+    let popup = centered_rect(72, lines.len() as u16 + 2, area);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" Currency exchange ")),
+        popup,
+    );
+}
+
+fn futures_prompt_lines(
+    market: &Market,
+    agent: &UserAgent,
+    stonk_id: usize,
+    amount: u32,
+    is_long: bool,
+) -> Vec<String> {
+    let stonk = &market.stonks[stonk_id];
+    let price = stonk.current_unit_price_cents();
+    let settlement = GamePhase::Day {
+        cycle: FuturesBook::settlement_cycle(market.phase.cycle()),
+        counter: 0,
+    };
+    let mut lines = vec![
+        format!(
+            "Settled at the dawn of {}, at the closing price. Price now {}.",
+            settlement.formatted_date(),
+            price.format_dollars()
+        ),
+        format!(
+            "{} x{}: margin {}, fee {}. Gains and losses are capped at the margin.",
+            if is_long { "Long" } else { "Short" },
+            amount,
+            FuturesBook::margin(price, amount)
+                .unwrap_or(Money::MAX)
+                .format_dollars(),
+            trade_fee(
+                Money::from(price).saturating_mul(amount as u64),
+                FUTURES_FEE
+            )
+            .format_dollars()
+        ),
+    ];
+    match FuturesBook::check_open(market.phase, stonk) {
+        Ok(()) => lines.push(format!(
+            "You can open futures on {} more shares.",
+            market.futures.max_open_amount(agent.username(), stonk)
+        )),
+        Err(e) => lines.push(format!("{}.", e)),
+    }
+    for contract in market
+        .futures
+        .contracts_of(agent.username())
+        .filter(|contract| contract.stonk_id == stonk_id)
+    {
+        lines.push(format!(
+            "Open: {} x{} at {}, worth {} now.",
+            if contract.is_long { "long" } else { "short" },
+            contract.amount,
+            contract.entry_price_cents.format_dollars(),
+            contract.value(price).format_dollars()
+        ));
+    }
+    lines
+}
+
+fn render_futures_prompt(
+    frame: &mut Frame,
+    market: &Market,
+    agent: &UserAgent,
+    stonk_id: usize,
+    amount: u32,
+    is_long: bool,
+    area: Rect,
+) {
+    let mut lines = vec![
+        Line::from(
+            "Bet on the price at the end of the season: long if it rises, short if it falls.",
+        ),
+        Line::from(""),
+    ];
+    lines.extend(
+        futures_prompt_lines(market, agent, stonk_id, amount, is_long)
+            .into_iter()
+            .map(Line::from),
+    );
+    lines.push(Line::from(""));
+    lines.push(
+        Line::from("`←→`:amount  `↑↓`:long/short  `return`:confirm  `backspace`:cancel").dim(),
+    );
+
+    let popup = centered_rect(88, lines.len() as u16 + 2, area);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(
+        Paragraph::new(lines).block(
+            Block::bordered().title(format!(" Futures on {} ", market.stonks[stonk_id].name)),
+        ),
+        popup,
+    );
+}
+
+fn render_vacation_prompt(frame: &mut Frame, days: usize, area: Rect) {
+    let lines = vec![
+        Line::from(format!(
+            "Go on vacation for {} day{}?",
+            days,
+            if days > 1 { "s" } else { "" }
+        )),
+        Line::from("Trading is disabled and nobody can target you until you are back."),
+        Line::from(format!(
+            "You cannot leave again for {} days after returning.",
+            VACATION_COOLDOWN_DAYS
+        )),
+        Line::from(""),
+        Line::from("`←→`:days  `return`:confirm  `backspace`:cancel").dim(),
+    ];
+
+    let popup = centered_rect(72, lines.len() as u16 + 2, area);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" Vacation ")),
+        popup,
+    );
+}
+
+fn render_codex(frame: &mut Frame, agent: &UserAgent, ui_options: &UiOptions, area: Rect) {
+    let colors = TableColors::new(&PALETTES[ui_options.palette_index]);
+    let header = [
+        "Event",
+        "Rarity",
+        "Seen",
+        "Used",
+        "Unlock condition",
+        "Cost",
+    ]
+    .into_iter()
+    .map(Cell::from)
+    .collect::<Row>()
+    .style(
+        Style::default()
+            .fg(colors.header_fg)
+            .bg(colors.header_bg)
+            .bold(),
+    )
+    .height(1);
+
+    let rows = NightEvent::iter()
+        .enumerate()
+        .map(|(i, event)| {
+            let color = match i % 2 {
+                0 => colors.normal_row_color,
+                _ => colors.alt_row_color,
+            };
+            let seen = agent
+                .seen_night_events()
+                .get(&event.to_string())
+                .copied()
+                .unwrap_or_default();
+            let used = agent.past_actions().count(&event.action());
+            let name_style = if seen > 0 {
+                Style::default().bold()
+            } else {
+                Style::default().dim()
+            };
+            Row::new(vec![
+                Cell::new(event.to_string()).style(name_style),
+                Cell::new(event.rarity().to_string()),
+                Cell::new(seen.to_string()),
+                Cell::new(used.to_string()),
+                Cell::new(event.unlock_condition_description().join(" ")),
+                Cell::new(event.cost_description().join(" ")),
+            ])
+            .style(Style::new().fg(colors.row_fg).bg(color))
+        })
+        .collect::<Vec<Row>>();
+
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(24),
+            Constraint::Length(10),
+            Constraint::Length(6),
+            Constraint::Length(6),
+            Constraint::Min(40),
+            Constraint::Length(10),
+        ],
+    )
+    .header(header)
+    .bg(colors.buffer_bg)
+    .block(Block::bordered().title(" Codex "));
+
+    frame.render_widget(table, area);
+}
+
+fn render_stonk_info(
+    frame: &mut Frame,
+    market: &Market,
+    _agent: &UserAgent,
+    ui_options: &UiOptions,
+    area: Rect,
+) {
+    let stonk_id = if let Some(stonk_id) = ui_options.focus_on_stonk {
+        stonk_id
+    } else {
+        ui_options.selected_stonk_index
+    };
+    let stonk = &market.stonks[stonk_id];
+    frame.render_widget(
+        Paragraph::new(stonk.description.clone()).wrap(Wrap { trim: true }),
+        area,
+    );
+}
+
+fn render_footer(
+    frame: &mut Frame,
+    market: &Market,
+    agent: &UserAgent,
+    ui_options: &UiOptions,
+    area: Rect,
+) {
+    let mut lines: Vec<Line> = vec![];
+
+    match market.phase {
+        GamePhase::Day { .. } => {
+            // Trades go to the paper account while playing a tournament round.
+            let tournament_account = agent
+                .tournament_account()
+                .filter(|_| ui_options.tournament_mode);
+            let venue_text = if let Some(account) = tournament_account {
+                format!("`e`:tournament {}", account.cash.format_dollars())
+            } else {
+                format!("`h`:venue {}", ui_options.venue.name())
+            };
+            // Scheduled orders and account changes only apply to the real account.
+            let order_text = match agent.scheduled_order() {
+                _ if ui_options.sandbox => String::new(),
+                Some(ScheduledOrder::SellAllOnDrawdown { reference_value }) => format!(
+                    "Order: sell all below {}",
+                    reference_value
+                        .percent(100 - SCHEDULED_DRAWDOWN_PERCENT)
+                        .format_dollars()
+                ),
+                Some(ScheduledOrder::BuyAtOpen { stonk_id, amount }) => format!(
+                    "Order: buy {} x{} at open",
+                    market.stonks[*stonk_id].name, amount
+                ),
+                None => format!(
+                    "`o`:stop-loss -{}% `a`:buy at open `v`:vacation",
+                    SCHEDULED_DRAWDOWN_PERCENT
+                ),
+            };
+            lines.push(
+                format!(
+                    "{:28} {:28} {:28} {}",
+                    "`↑↓`:select stonk",
+                    if ui_options.focus_on_stonk.is_some() {
+                        "`return`:main table"
+                    } else {
+                        "`return`:show graph"
+                    },
+                    venue_text,
+                    order_text
+                )
+                .into(),
+            );
+
+            let stonk_id = if let Some(stonk_id) = ui_options.focus_on_stonk {
+                stonk_id
+            } else {
+                ui_options.selected_stonk_index
+            };
+            let stonk = &market.stonks[stonk_id];
+            // Tournament trades pay no fees.
+            let fee_rate = if tournament_account.is_some() {
+                0.0
+            } else {
+                market.trade_fee_rate(agent, ui_options.venue)
+            };
+            let is_offshore = tournament_account.is_none() && ui_options.venue == Venue::Offshore;
+            let max_buy_amount = if let Some(account) = tournament_account {
+                stonk.max_buy_amount(account.cash)
+            } else if is_offshore {
+                market
+                    .offshore
+                    .max_buy_amount(stonk, agent.offshore_cash(), fee_rate)
+            } else {
+                max_buy_amount_with_fee(stonk, agent.cash(), fee_rate)
+            };
+            // Offshore prices are in the offshore currency.
+            let currency = if is_offshore {
+                Currency::Offshore
+            } else {
+                Currency::Dollar
+            };
+            let buy_price = |amount| {
+                let cost = if is_offshore {
+                    market
+                        .offshore
+                        .buy_price(stonk, amount)
+                        .unwrap_or(Money::ZERO)
+                } else {
+                    stonk.buy_price(amount)
+                };
+                cost.saturating_add(trade_fee(cost, fee_rate))
+            };
+            let sell_price = |amount| {
+                let cost = if is_offshore {
+                    market.offshore.sell_price(stonk, amount)
+                } else {
+                    stonk.sell_price(amount)
+                };
+                cost.saturating_sub(trade_fee(cost, fee_rate))
+            };
+
+            let (trades_this_tick, trades_today) = market.remaining_trades(agent);
+            let mut trades_text = format!(
+                "Trades left: {} now, {} today",
+                trades_this_tick, trades_today
+            );
+            // The prices above include the fees, the wealth part is shown so it is not a surprise.
+            if is_offshore {
+                let exchange_rate = &market.offshore.exchange_rate;
+                trades_text.push_str(&format!(
+                    "  Offshore {:+.2}%  {}1=${:.4} ({:+.2}%) `$`:exchange",
+                    market.offshore.premium(stonk.id) * 100.0,
+                    OFFSHORE_CURRENCY_SYMBOL,
+                    exchange_rate.rate(),
+                    exchange_rate.day_change()
+                ));
+            }
+            let wealth_fee_rate = market.wealth_fee_rate(agent.username());
+            if tournament_account.is_none() && wealth_fee_rate > 0.0 {
+                trades_text.push_str(&format!(
+                    "  Fee {:.2}% ({:.2}% wealth)",
+                    fee_rate * 100.0,
+                    wealth_fee_rate * 100.0
+                ));
+            }
+
+            lines.push(
+                format!(
+                    "{:28} {:28} {:28} {:28} {}",
+                    format!(
+                        "`b`: buy  x{} ({})",
+                        1.min(max_buy_amount),
+                        buy_price(1.min(max_buy_amount)).format_in(currency)
+                    ),
+                    format!(
+                        "`B`: buy  x{} ({})",
+                        100.min(max_buy_amount),
+                        buy_price(100.min(max_buy_amount)).format_in(currency)
+                    ),
+                    format!(
+                        "`m`: buy  x{} ({})",
+                        max_buy_amount,
+                        buy_price(max_buy_amount).format_in(currency)
+                    ),
+                    if ui_options.sandbox {
+                        "`y`:copy `i`:leave practice"
+                    } else {
+                        "`y`:copy `i`:practice"
+                    },
+                    trades_text,
+                )
+                .into(),
+            );
+            // The keys of the chart take the place of the account keys, which are also listed
+            // in the settings, so that every hint fits in the footer.
+            let account_keys = if let Some(stonk_id) = ui_options.focus_on_stonk {
+                let stonk = &market.stonks[stonk_id];
+                let mut stonk_keys = "`z`:zoom `l`:about".to_string();
+                if !ui_options.sandbox && Boards::controller(stonk) == Some(agent.username()) {
+                    stonk_keys.push_str(" `n`:board");
+                }
+                if !ui_options.sandbox && stonk.class == StonkClass::Commodity {
+                    stonk_keys.push_str(" `>`:futures");
+                }
+                stonk_keys
+            } else if ui_options.sandbox {
+                String::new()
+            } else if agent.is_guest() {
+                "`u`:create account".to_string()
+            } else {
+                "`u`:change username `k`:API tokens".to_string()
+            };
+            let owned_amount = tournament_account
+                .map(|account| account.owned_stonks)
+                .unwrap_or(*agent.owned_stonks())[stonk.id];
+            lines.push(
+                format!(
+                    "{:28} {:28} {:28} {}",
+                    format!(
+                        "`s`: sell x{} ({})",
+                        1.min(owned_amount),
+                        sell_price(1.min(owned_amount)).format_in(currency)
+                    ),
+                    format!(
+                        "`S`: sell x{} ({})",
+                        100.min(owned_amount),
+                        sell_price(100.min(owned_amount)).format_in(currency)
+                    ),
+                    format!(
+                        "`d`: sell x{} ({})",
+                        owned_amount,
+                        sell_price(owned_amount).format_in(currency)
+                    ),
+                    account_keys,
+                )
+                .into(),
+            );
+        }
+        GamePhase::Night { .. } => {
+            let selected_events = agent
+                .available_night_events()
+                .iter()
+                .filter(|event| agent.selected_actions().contains(&event.action()))
+                .map(|event| format!("`{}`", event))
+                .collect::<Vec<String>>();
+            let stonk = &market.stonks[ui_options
+                .focus_on_stonk
+                .unwrap_or(ui_options.selected_stonk_index)];
+            let trading_text = if market.auction_is_open() {
+                let (bought, sold) = market.auction.ordered_amounts(agent.username(), stonk.id);
+                let indicative_text = match market
+                    .auction
+                    .indicative_price(stonk.id, stonk.current_unit_price_cents())
+                {
+                    Some((price, volume)) => {
+                        format!("opens at {} x{}", price.format_dollars(), volume)
+                    }
+                    None => "no match yet".to_string(),
+                };
+                format!(
+                    "Opening auction {}: `b`:bid {} `s`:offer {} (ordered +{}/-{}, {})",
+                    stonk.name,
+                    market.auction_limit_price(stonk.id, true).format_dollars(),
+                    market.auction_limit_price(stonk.id, false).format_dollars(),
+                    bought,
+                    sold,
+                    indicative_text
+                )
+            } else {
+                format!(
+                    "After hours {}: `b`:buy {} `s`:sell {} (spread x{}, up to {} shares per trade)",
+                    stonk.name,
+                    stonk.buy_price(1).format_dollars(),
+                    stonk.sell_price(1).format_dollars(),
+                    AFTER_HOURS_SPREAD_MULTIPLIER,
+                    stonk.max_trade_amount()
+                )
+            };
+            lines.push(
+                format!(
+                    "{:28} {}",
+                    format!(
+                        "Action points {}/{}",
+                        agent.spent_night_action_points(),
+                        agent.night_action_points()
+                    ),
+                    trading_text
+                )
+                .into(),
+            );
+            if !selected_events.is_empty() {
+                lines.push(format!("You selected {}", selected_events.join(", ")).into());
+            }
+            let reduced_motion_text = format!(
+                "`r`:reduced motion {}",
+                if agent.reduced_motion() { "on" } else { "off" }
+            );
+            if market.night_selection_locked() {
+                lines.push(
+                    format!(
+                        "{:28} {:28}",
+                        "Selection locked until dawn", reduced_motion_text
+                    )
+                    .into(),
                 );
-                frame.render_widget(
-                    Paragraph::new(elon[idx / ANIMATION_RATE].clone()),
-                    split[2].inner(&Margin {
-                        horizontal: 1,
-                        vertical: 1,
-                    }),
+            } else if agent.has_completed_night_selection() {
+                lines.push(
+                    format!(
+                        "{:28} {:28}",
+                        "`backspace`:undo selection", reduced_motion_text
+                    )
+                    .into(),
                 );
-                frame.render_widget(
-                    Paragraph::new(kim[idx / ANIMATION_RATE ].clone()),
-                    split[3].inner(&Margin {
-                        horizontal: 1,
-                        vertical: 1,
-                    }),
+            } else {
+                lines.push(
+                    format!(
+                        "{:28} {:28} {:28} {:28}",
+                        "`←→`:select event",
+                        "`return`:confirm",
+                        "`backspace`:undo selection",
+                        reduced_motion_text
+                    )
+                    .into(),
                 );
+            }
+        }
+    }
+
+    frame.render_widget(Paragraph::new(lines), area);
+}
+
+pub fn render(
+    frame: &mut Frame,
+    market: &Market,
+    agent: &UserAgent,
+    ui_options: &UiOptions,
+    cache: &mut RenderCache,
+    number_of_players: usize,
+) -> AppResult<Vec<ImagePlacement>> {
+    clear(frame);
+    // Nothing of the game is drawn until the login code is confirmed.
+    if let Some(prompt) = ui_options.login_code.as_ref() {
+        render_login_code_prompt(frame, prompt, frame.size());
+        return Ok(vec![]);
+    }
+    if let Some(maintenance) = ui_options.maintenance.as_ref() {
+        render_maintenance(frame, maintenance, agent.text_mode(), frame.size());
+        if let Some(stats) = ui_options.debug_overlay.as_ref() {
+            render_debug_overlay(frame, market, stats, frame.size());
+        }
+        return Ok(vec![]);
+    }
+    if agent.text_mode() {
+        render_text_mode(frame, market, agent, ui_options, frame.size());
+        return Ok(vec![]);
+    }
+    if ui_options.is_copy_mode() {
+        render_copy_mode(frame, market, agent, ui_options, frame.size());
+        return Ok(vec![]);
+    }
+    let mut images = vec![];
+
+    let area = frame.size();
+    let split = Layout::vertical([
+        Constraint::Length(1), //header
+        Constraint::Length(1), //tabs
+        Constraint::Length(1), //ticker
+        Constraint::Min(0),    //body
+        Constraint::Length(1), //status bar
+        Constraint::Length(3), //footer
+    ])
+    .split(area);
+
+    render_header(
+        frame,
+        market,
+        agent,
+        ui_options,
+        number_of_players,
+        split[0],
+    );
+    render_connection_stats(frame, &ui_options.connection_stats, split[0]);
+    render_tabs(frame, ui_options, split[1]);
+    render_ticker(frame, market, split[2]);
+
+    match ui_options.display {
+        UiDisplay::Portfolio => render_portfolio(frame, market, agent, split[3]),
+        UiDisplay::Settings => render_settings(frame, agent, ui_options, split[3]),
+        UiDisplay::Tape => render_trade_tape(frame, market, ui_options, split[3]),
+        UiDisplay::Codex => render_codex(frame, agent, ui_options, split[3]),
+        UiDisplay::History => render_leaderboard_history(frame, market, ui_options, split[3]),
+        UiDisplay::Tournament => render_tournament(frame, market, agent, ui_options, split[3]),
+        UiDisplay::Manual => render_manual(frame, market, ui_options, split[3]),
+        UiDisplay::About => render_about(frame, market, ui_options, split[3]),
+        UiDisplay::Changelog => render_changelog(frame, split[3]),
+        UiDisplay::Statistics => render_statistics(frame, market, split[3]),
+        UiDisplay::Stonks => match market.phase {
+            GamePhase::Day { .. } => {
+                let sub_split = Layout::vertical([
+                    Constraint::Min(0),    //body
+                    Constraint::Length(3), // stonk info / newspaper
+                ])
+                .split(split[3]);
+                if area.width >= SIDEBAR_MIN_TERMINAL_WIDTH {
+                    let body_split =
+                        Layout::horizontal([Constraint::Min(0), Constraint::Length(SIDEBAR_WIDTH)])
+                            .split(sub_split[0]);
+                    render_day(frame, market, agent, ui_options, cache, body_split[0])?;
+                    render_sidebar(frame, market, body_split[1]);
+                } else {
+                    render_day(frame, market, agent, ui_options, cache, sub_split[0])?;
+                }
+                render_stonk_info(frame, market, agent, ui_options, sub_split[1]);
+            }
+            GamePhase::Night { counter, .. } => {
+                images = render_night(frame, market, counter, agent, ui_options, split[3])?
+            }
+        },
+    }
+
+    if ui_options.sandbox {
+        render_sandbox_watermark(frame, split[3]);
+    }
+
+    render_status_bar(frame, ui_options, split[4]);
+    render_footer(frame, market, agent, ui_options, split[5]);
+
+    for modal in ui_options.modals.iter() {
+        match modal {
+            Modal::Menu { selected } => render_menu(frame, market, agent, *selected, area),
+            Modal::Account(dialog) => render_account_dialog(frame, dialog, area),
+            Modal::ApiTokens(screen) => render_api_tokens_screen(frame, agent, screen, area),
+            Modal::TwoFactor(screen) => render_two_factor_screen(frame, agent, screen, area),
+            Modal::Vacation { days } => render_vacation_prompt(frame, *days, area),
+            Modal::Currency {
+                to_offshore,
+                amount,
+            } => render_currency_prompt(frame, market, agent, *to_offshore, *amount, area),
+            Modal::Futures {
+                stonk_id,
+                amount,
+                is_long,
+            } => render_futures_prompt(frame, market, agent, *stonk_id, *amount, *is_long, area),
+            Modal::Board { stonk_id, selected } => {
+                render_board_prompt(frame, market, *stonk_id, *selected, area)
+            }
+            Modal::WhatsNew { since } => render_whats_new(frame, since.as_deref(), area),
+        }
+    }
+
+    // A pending character assassination takes the input before any modal.
+    if matches!(market.phase, GamePhase::Day { .. }) && !agent.assassination_threats().is_empty() {
+        render_assassination_warning(frame, market, agent, ui_options, area);
+    }
+
+    if let Some(stats) = ui_options.debug_overlay.as_ref() {
+        render_debug_overlay(frame, market, stats, area);
+    }
+
+    if let Some(seconds) = ui_options.idle_countdown {
+        render_idle_warning(frame, seconds, area);
+    }
+
+    Ok(images)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        changelog_since, render, render_ticker, whats_new, AccountDialog, Modal, RenderCache,
+        StonksTableCache, UiDisplay, UiOptions, ZoomLevel, CARD_ANIMATION_FRAMES, CARD_WIDTH,
+        DOGE_CARD, STONKS_CARD,
+    };
+    use crate::{
+        agent::{AgentCondition, DecisionAgent, UserAgent},
+        clock::Maintenance,
+        events::NightEvent,
+        formatting::{set_test_number_format, Locale, NumberFormat},
+        market::{GamePhase, Market, Trade, DAY_LENGTH},
+        money::Money,
+        notifications::NotificationPriority,
+        server_status::{DebugStats, Percentiles},
+        ssh_client::SessionAuth,
+        stonk::{StonkClass, StonkCondition},
+        tournament::PaperAccount,
+        utils::AppResult,
+    };
+    use crossterm::event::KeyCode;
+    use ratatui::{backend::TestBackend, buffer::Buffer, Terminal};
+    use std::{
+        path::PathBuf,
+        time::{Duration, Instant},
+    };
+
+    const FRAME_WIDTH: u16 = 160;
+    const FRAME_HEIGHT: u16 = 48;
+    // Set this variable to regenerate the golden frames after an intended UI change.
+    const UPDATE_GOLDEN_FRAMES_VAR: &str = "UPDATE_GOLDEN_FRAMES";
+
+    // Prices follow fixed waves, so that frames do not depend on the random market evolution.
+    fn golden_market() -> Market {
+        let mut market = Market::new();
+        for stonk in market.stonks.iter_mut() {
+            let base_price = (20 + 15 * stonk.id) as f64 * 100.0;
+            let prices = (0..4 * DAY_LENGTH)
+                .map(|t| {
+                    let phase = t as f64 / 40.0 + stonk.id as f64;
+                    (base_price * (1.0 + 0.2 * phase.sin() + 0.05 * (3.0 * phase).cos())) as u32
+                })
+                .collect();
+            stonk.set_test_history(prices);
+        }
+        market.last_tick = 4 * DAY_LENGTH;
+        market.phase = GamePhase::Day {
+            cycle: 4,
+            counter: DAY_LENGTH / 2,
+        };
+        market
+    }
+
+    fn golden_agent() -> UserAgent {
+        UserAgent::new(SessionAuth::new("golden".to_string(), [0; 32]))
+    }
+
+    fn draw(market: &Market, agent: &UserAgent, ui_options: &UiOptions) -> AppResult<Buffer> {
+        let mut terminal = Terminal::new(TestBackend::new(FRAME_WIDTH, FRAME_HEIGHT))?;
+        terminal.draw(|frame| {
+            render(
+                frame,
+                market,
+                agent,
+                ui_options,
+                &mut RenderCache::default(),
+                1,
+            )
+            .expect("Failed to render");
+        })?;
+        Ok(terminal.backend().buffer().clone())
+    }
+
+    fn buffer_to_string(buffer: &Buffer) -> String {
+        let width = buffer.area.width as usize;
+        buffer
+            .content()
+            .chunks(width)
+            .map(|row| {
+                let line = row.iter().map(|cell| cell.symbol()).collect::<String>();
+                format!("{}\n", line.trim_end())
+            })
+            .collect()
+    }
+
+    /// Compares the frame with the golden file, or overwrites it if UPDATE_GOLDEN_FRAMES is set.
+    fn assert_golden_frame(name: &str, buffer: &Buffer) -> AppResult<()> {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("golden")
+            .join(format!("{}.txt", name));
+        let frame = buffer_to_string(buffer);
+
+        if std::env::var_os(UPDATE_GOLDEN_FRAMES_VAR).is_some() {
+            std::fs::create_dir_all(path.parent().expect("Golden path should have a parent"))?;
+            std::fs::write(&path, frame)?;
+            return Ok(());
+        }
+
+        let golden = std::fs::read_to_string(&path).map_err(|e| {
+            format!(
+                "Could not read golden frame {}: {}. Run with {}=1 to create it.",
+                path.display(),
+                e,
+                UPDATE_GOLDEN_FRAMES_VAR
+            )
+        })?;
+        assert_eq!(frame, golden, "Frame differs from golden frame {}", name);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stonks_table_frame() -> AppResult<()> {
+        let market = golden_market();
+        let mut agent = golden_agent();
+        agent.add_stonk(2, 100)?;
+        let mut ui_options = UiOptions::new();
+        ui_options.selected_stonk_index = 2;
+
+        assert_golden_frame("day_stonks_table", &draw(&market, &agent, &ui_options)?)
+    }
+
+    #[test]
+    fn test_suspended_stonk_frame() -> AppResult<()> {
+        let mut market = golden_market();
+        market.stonks[4].set_suspended(true);
+        let mut agent = golden_agent();
+        agent.add_stonk(4, 100)?;
+
+        assert_golden_frame(
+            "day_stonks_table_suspended",
+            &draw(&market, &agent, &UiOptions::new())?,
+        )
+    }
+
+    #[test]
+    fn test_stonks_table_cache() -> AppResult<()> {
+        let mut market = golden_market();
+        let mut agent = golden_agent();
+        let ui_options = UiOptions::new();
+        let mut cache = StonksTableCache::default();
+
+        cache.update(&market, &agent, &ui_options);
+        cache.update(&market, &agent, &ui_options);
+        assert_eq!(cache.builds, 1);
+
+        // A trade changes the table before the next tick.
+        agent.add_stonk(2, 100)?;
+        cache.update(&market, &agent, &ui_options);
+        assert_eq!(cache.builds, 2);
+
+        market.last_tick += 1;
+        cache.update(&market, &agent, &ui_options);
+        assert_eq!(cache.builds, 3);
+
+        market.stonks[0].set_suspended(true);
+        cache.update(&market, &agent, &ui_options);
+        assert_eq!(cache.builds, 4);
+
+        // Frames drawn from the cache match the ones drawn from scratch.
+        let mut render_cache = RenderCache::default();
+        let mut terminal = Terminal::new(TestBackend::new(FRAME_WIDTH, FRAME_HEIGHT))?;
+        for _ in 0..2 {
+            terminal.draw(|frame| {
+                render(frame, &market, &agent, &ui_options, &mut render_cache, 1)
+                    .expect("Failed to render");
             })?;
+        }
+        assert_eq!(render_cache.stonks_table.builds, 1);
+        assert_eq!(
+            terminal.backend().buffer(),
+            &draw(&market, &agent, &ui_options)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_status_bar_shows_most_relevant_notification() -> AppResult<()> {
+        let market = golden_market();
+        let agent = golden_agent();
+        let mut ui_options = UiOptions::new();
+        ui_options.notifications.set(
+            "phase",
+            NotificationPriority::Low,
+            "Night ends in 0:45".to_string(),
+        );
+        ui_options.notifications.push(
+            "trade",
+            NotificationPriority::High,
+            "Bought 10 Cassius at $1.00".to_string(),
+            Duration::from_secs(60),
+            Instant::now(),
+        );
+
+        let status_bar = |ui_options: &UiOptions| -> AppResult<String> {
+            let frame = buffer_to_string(&draw(&market, &agent, ui_options)?);
+            Ok(frame
+                .lines()
+                .nth(FRAME_HEIGHT as usize - 4)
+                .unwrap_or_default()
+                .to_string())
+        };
+        assert_eq!(status_bar(&ui_options)?, " Bought 10 Cassius at $1.00");
+        ui_options.notifications.clear("trade");
+        assert_eq!(status_bar(&ui_options)?, " Night ends in 0:45");
+        Ok(())
+    }
+
+    #[test]
+    fn test_stonk_chart_frames() -> AppResult<()> {
+        let market = golden_market();
+        let agent = golden_agent();
+        let mut ui_options = UiOptions::new();
+        ui_options.focus_on_stonk = Some(0);
+
+        assert_golden_frame("day_stonk_chart", &draw(&market, &agent, &ui_options)?)?;
+
+        ui_options.zoom_level = ZoomLevel::Max;
+        assert_golden_frame(
+            "day_stonk_chart_zoom_max",
+            &draw(&market, &agent, &ui_options)?,
+        )?;
+
+        // Every hint of the footer fits, even with the most keys for the stonk.
+        ui_options.focus_on_stonk = market
+            .stonks
+            .iter()
+            .position(|stonk| stonk.class == StonkClass::Commodity);
+        assert!(ui_options.focus_on_stonk.is_some());
+        let frame = buffer_to_string(&draw(&market, &agent, &ui_options)?);
+        let footer = frame
+            .lines()
+            .skip(FRAME_HEIGHT as usize - 3)
+            .collect::<Vec<_>>();
+        assert!(footer[2].ends_with("`>`:futures"));
+        assert!(footer
+            .iter()
+            .all(|line| line.chars().count() < FRAME_WIDTH as usize));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ticker_keeps_room_for_trades() -> AppResult<()> {
+        let mut market = golden_market();
+        for stonk in market.stonks.iter_mut().take(3) {
+            stonk.add_condition(
+                StonkCondition::Mispriced { factor: -0.1 },
+                4 * DAY_LENGTH + 5,
+            );
+        }
+        market.trade_tape.push(Trade {
+            tick: market.last_tick,
+            stonk_id: 0,
+            amount: 42,
+            value: Money::from_dollars(100),
+            is_buy: true,
+            username: Some("whale".to_string()),
+        });
+
+        let ticker = |market: &Market| -> AppResult<String> {
+            let mut terminal = Terminal::new(TestBackend::new(80, 1))?;
+            terminal.draw(|frame| render_ticker(frame, market, frame.size()))?;
+            Ok(buffer_to_string(terminal.backend().buffer()))
+        };
+        // The news take half of the ticker, the trades the other half.
+        let trades = |ticker: &str| ticker.chars().skip(40).collect::<String>();
+        let first = ticker(&market)?;
+        assert_eq!(trades(&first), " · ▲ CASH x42 $100.00 whale\n");
+
+        // The news scroll through their half.
+        market.last_tick += 1;
+        let next = ticker(&market)?;
+        assert_ne!(next, first);
+        assert_eq!(trades(&next), trades(&first));
+        Ok(())
+    }
+
+    #[test]
+    fn test_card_animation_frames() {
+        for n in 0..CARD_ANIMATION_FRAMES {
+            let lines = STONKS_CARD.animation_frame(n);
+            assert!(lines.iter().all(|line| line.width() == CARD_WIDTH as usize));
+        }
+        // The front frames are converted once for all the cards.
+        assert!(std::ptr::eq(
+            STONKS_CARD.animation_frame(CARD_ANIMATION_FRAMES - 1),
+            DOGE_CARD.animation_frame(CARD_ANIMATION_FRAMES - 1)
+        ));
+        assert!(!std::ptr::eq(
+            STONKS_CARD.animation_frame(0),
+            DOGE_CARD.animation_frame(0)
+        ));
+    }
+
+    #[test]
+    fn test_night_cards_frame() -> AppResult<()> {
+        let mut market = golden_market();
+        market.phase = GamePhase::Night {
+            cycle: 4,
+            counter: 0,
+        };
+        for stonk in market.stonks.iter_mut() {
+            stonk.set_after_hours(true);
+        }
+        let mut agent = golden_agent();
+        agent.set_available_night_events(vec![
+            NightEvent::LuckyNight,
+            NightEvent::AGoodOffer,
+            NightEvent::MarketCrash,
+        ]);
+        agent.set_night_action_points(2);
+        let mut ui_options = UiOptions::new();
+        ui_options.selected_event_card_index = 1;
+
+        // Without a running animation, cards are drawn in their final frame.
+        assert_golden_frame("night_cards", &draw(&market, &agent, &ui_options)?)?;
+
+        agent.select_action(NightEvent::AGoodOffer.action());
+        assert_golden_frame("night_cards_selected", &draw(&market, &agent, &ui_options)?)?;
+
+        // Amounts on the cards follow the number format of the server.
+        agent.unselect_action(&NightEvent::AGoodOffer.action());
+        set_test_number_format(Some(NumberFormat {
+            locale: Locale::DeDe,
+            full_precision: false,
+        }));
+        let frame = draw(&market, &agent, &ui_options);
+        set_test_number_format(None);
+        assert_golden_frame("night_cards_de_de", &frame?)
+    }
+
+    #[test]
+    fn test_copy_mode_frame() -> AppResult<()> {
+        let market = golden_market();
+        let mut agent = golden_agent();
+        agent.add_stonk(2, 100)?;
+        agent.add_stonk(5, 10)?;
+        let mut ui_options = UiOptions::new();
+        ui_options.copy_mode_until = Some(Instant::now() + Duration::from_secs(60));
+
+        assert_golden_frame("copy_mode_stonks", &draw(&market, &agent, &ui_options)?)?;
+
+        ui_options.display = UiDisplay::Portfolio;
+        assert_golden_frame("copy_mode_portfolio", &draw(&market, &agent, &ui_options)?)
+    }
+
+    #[test]
+    fn test_leaderboard_history_frame() -> AppResult<()> {
+        let mut market = golden_market();
+        for (cycle, winner) in ["alice", "bob", "bob", "alice"].into_iter().enumerate() {
+            let other = if winner == "alice" { "bob" } else { "alice" };
+            market.leaderboard_history.record(
+                GamePhase::Day {
+                    cycle: cycle + 88,
+                    counter: 0,
+                },
+                &[
+                    (winner.to_string(), Money::from_cents(200_000)),
+                    (other.to_string(), Money::from_cents(100_000)),
+                ],
+            );
+        }
+        let agent = golden_agent();
+        let mut ui_options = UiOptions::new();
+        ui_options.handle_key_events(KeyCode::Char('w'), &agent)?;
 
-            idx += 1;
+        assert_golden_frame("leaderboard_history", &draw(&market, &agent, &ui_options)?)?;
+
+        // Up goes back to the last day of the previous season.
+        assert!(ui_options.handle_history_keys(KeyCode::Up, &market.leaderboard_history));
+        assert_eq!(ui_options.history_day, Some(1));
+        assert!(ui_options.handle_history_keys(KeyCode::Left, &market.leaderboard_history));
+        assert_eq!(ui_options.history_day, Some(0));
+        assert!(ui_options.handle_history_keys(KeyCode::Down, &market.leaderboard_history));
+        assert_eq!(ui_options.history_day, Some(2));
+        assert!(!ui_options.handle_history_keys(KeyCode::Enter, &market.leaderboard_history));
+        Ok(())
+    }
+
+    #[test]
+    fn test_tournament_frame() -> AppResult<()> {
+        let mut market = golden_market();
+        for username in ["alice", "bob", "carol", "golden"] {
+            market.tournament.join(username)?;
+        }
+        market.tournament.start_round(market.last_tick);
+        market.tournament.end_round(vec![
+            ("alice".to_string(), Money::from_cents(1_200_000)),
+            ("bob".to_string(), Money::from_cents(900_000)),
+            ("carol".to_string(), Money::from_cents(1_100_000)),
+            ("golden".to_string(), Money::from_cents(1_000_000)),
+        ]);
+        market.tournament.start_round(market.last_tick);
+        let mut agent = golden_agent();
+        agent.set_tournament_account(Some(PaperAccount::new(Money::from_cents(1_000_000))));
+        let mut ui_options = UiOptions::new();
+        ui_options.handle_key_events(KeyCode::Char('f'), &agent)?;
+
+        assert_golden_frame("tournament", &draw(&market, &agent, &ui_options)?)
+    }
+
+    #[test]
+    fn test_sandbox_frame() -> AppResult<()> {
+        let market = golden_market();
+        let agent = golden_agent();
+        let mut ui_options = UiOptions::new();
+        ui_options.sandbox = true;
+
+        assert_golden_frame("sandbox", &draw(&market, &agent, &ui_options)?)
+    }
+
+    #[test]
+    fn test_stonk_projection_frame() -> AppResult<()> {
+        let mut market = golden_market();
+        let stonk = &mut market.stonks[0];
+        stonk.set_test_values(
+            stonk.current_unit_price_cents(),
+            stonk.number_of_shares,
+            0.0,
+            0.01,
+            0.01,
+            0.0,
+        );
+        let mut agent = golden_agent();
+        agent.add_condition(AgentCondition::UltraVision, market.last_tick + DAY_LENGTH);
+        let mut ui_options = UiOptions::new();
+        ui_options.focus_on_stonk = Some(0);
+
+        assert_golden_frame("day_stonk_projection", &draw(&market, &agent, &ui_options)?)
+    }
+
+    #[test]
+    fn test_manual_frame() -> AppResult<()> {
+        let market = golden_market();
+        let agent = golden_agent();
+        let mut ui_options = UiOptions::new();
+        ui_options.handle_key_events(KeyCode::Char('?'), &agent)?;
+        assert!(ui_options.handle_manual_keys(KeyCode::Right, 5));
+
+        assert_golden_frame("manual_spread", &draw(&market, &agent, &ui_options)?)
+    }
+
+    #[test]
+    fn test_about_frame() -> AppResult<()> {
+        let market = golden_market();
+        let agent = golden_agent();
+        let mut ui_options = UiOptions::new();
+        assert!(!ui_options.handle_about_keys(KeyCode::Right));
+        ui_options.handle_key_events(KeyCode::Char('l'), &agent)?;
+        assert!(ui_options.handle_about_keys(KeyCode::Right));
+        assert!(ui_options.handle_about_keys(KeyCode::Down));
+        assert_eq!(
+            (ui_options.selected_stonk_index, ui_options.about_scroll),
+            (1, 1)
+        );
+
+        assert_golden_frame("about", &draw(&market, &agent, &ui_options)?)
+    }
+
+    #[test]
+    fn test_tab_navigation() -> AppResult<()> {
+        let agent = golden_agent();
+        let mut ui_options = UiOptions::new();
+        let mut titles = vec![];
+        for _ in 0..UiDisplay::TABS.len() {
+            titles.push(ui_options.display.tab_title());
+            ui_options.handle_key_events(KeyCode::Tab, &agent)?;
         }
+        assert_eq!(
+            titles,
+            vec![
+                "Market",
+                "Portfolio",
+                "Leaderboard",
+                "News",
+                "Settings",
+                "Help"
+            ]
+        );
+        assert_eq!(ui_options.display, UiDisplay::Stonks);
+
+        ui_options.handle_key_events(KeyCode::BackTab, &agent)?;
+        assert_eq!(ui_options.display, UiDisplay::Manual);
+        // Displays without a tab move from the tab they are shown under.
+        ui_options.handle_key_events(KeyCode::Char('f'), &agent)?;
+        assert_eq!(ui_options.display.tab_title(), "Leaderboard");
+        ui_options.handle_key_events(KeyCode::Tab, &agent)?;
+        assert_eq!(ui_options.display, UiDisplay::Tape);
+        Ok(())
+    }
+
+    #[test]
+    fn test_settings_frame() -> AppResult<()> {
+        let market = golden_market();
+        let agent = golden_agent();
+        let mut ui_options = UiOptions::new();
+        ui_options.set_display(UiDisplay::Settings);
+
+        assert_golden_frame("settings", &draw(&market, &agent, &ui_options)?)
+    }
+
+    #[test]
+    fn test_debug_overlay_frame() -> AppResult<()> {
+        let mut market = golden_market();
+        market.last_tick_seed = Some(42);
+        market.stonks[3].add_condition(
+            StonkCondition::IncreasedShockProbability,
+            4 * DAY_LENGTH + 5,
+        );
+        let agent = golden_agent();
+        let mut ui_options = UiOptions::new();
+        let percentiles = |p50, p99, max| Percentiles {
+            p50: Duration::from_micros(p50),
+            p99: Duration::from_micros(p99),
+            max: Duration::from_micros(max),
+        };
+        ui_options.debug_overlay = Some(DebugStats {
+            tick_duration: percentiles(1_200, 4_500, 9_000),
+            lock_wait: percentiles(50, 800, 2_000),
+            frame_bytes: vec![("golden".to_string(), 12_345), ("other".to_string(), 678)],
+        });
+
+        assert_golden_frame("debug_overlay", &draw(&market, &agent, &ui_options)?)
+    }
+
+    #[test]
+    fn test_maintenance_frame() -> AppResult<()> {
+        let market = golden_market();
+        let agent = golden_agent();
+        let mut ui_options = UiOptions::new();
+        ui_options.maintenance = Some(Maintenance {
+            message: "Moving to a bigger server, your portfolio comes along.".to_string(),
+            back_at: Some(Instant::now() + Duration::from_secs(30 * 60)),
+        });
+
+        assert_golden_frame("maintenance", &draw(&market, &agent, &ui_options)?)
+    }
+
+    #[test]
+    fn test_screen_state() -> AppResult<()> {
+        let agent = golden_agent();
+        let mut ui_options = UiOptions::new();
+        ui_options.handle_key_events(KeyCode::Char('3'), &agent)?;
+        ui_options.modals.push(Modal::Menu { selected: 3 });
+        let state = ui_options.screen_state();
+        assert_eq!(state.focus_on_stonk, Some(2));
+        assert_eq!(state.modal.as_deref(), Some("Menu on Settings"));
+
+        let mut restored = UiOptions::new();
+        assert!(restored.restore_screen_state(&state));
+        assert_eq!(restored.screen_state(), state);
+
+        // What is typed in the account dialog is a secret, and the dialog cannot be restored.
+        ui_options
+            .modals
+            .push(Modal::Account(AccountDialog::default()));
+        assert!(ui_options.is_entering_secret());
+        assert!(!restored.restore_screen_state(&ui_options.screen_state()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_changelog_since() {
+        let changelog = "# Changelog\n\n## 0.10.0\n\n- Futures.\n\n## 0.9.1\n\n- Fees.\n\n## 0.2.0\n\n- Menu.\n";
+        assert_eq!(
+            changelog_since(changelog, Some("0.2.0")),
+            vec!["## 0.10.0", "", "- Futures.", "", "## 0.9.1", "", "- Fees."]
+        );
+        assert!(changelog_since(changelog, Some("0.10.0")).is_empty());
+        assert_eq!(
+            changelog_since(changelog, None),
+            vec!["## 0.10.0", "", "- Futures."]
+        );
+
+        // The embedded changelog covers the current version.
+        assert!(whats_new(None)[0].contains(env!("CARGO_PKG_VERSION")));
+        assert!(whats_new(Some(env!("CARGO_PKG_VERSION"))).is_empty());
+    }
+
+    #[test]
+    fn test_menu_frame() -> AppResult<()> {
+        let market = golden_market();
+        let agent = golden_agent();
+        let mut ui_options = UiOptions::new();
+        ui_options.handle_key_events(KeyCode::Char('q'), &agent)?;
+        assert!(matches!(
+            ui_options.modals.top(),
+            Some(Modal::Menu { selected: 0 })
+        ));
+        ui_options.modals.pop();
+        ui_options.modals.push(Modal::Menu { selected: 4 });
+
+        assert_golden_frame("menu", &draw(&market, &agent, &ui_options)?)
+    }
+
+    #[test]
+    fn test_modal_stack_frame() -> AppResult<()> {
+        let market = golden_market();
+        let agent = golden_agent();
+        let mut ui_options = UiOptions::new();
+        ui_options.modals.push(Modal::Account(AccountDialog {
+            rename: true,
+            username: "golden".to_string(),
+            ..Default::default()
+        }));
+        ui_options.modals.push(Modal::Vacation { days: 3 });
+        assert!(matches!(
+            ui_options.modals.top(),
+            Some(Modal::Vacation { days: 3 })
+        ));
+
+        assert_golden_frame("modal_stack", &draw(&market, &agent, &ui_options)?)?;
 
+        ui_options.modals.pop();
+        assert!(matches!(ui_options.modals.top(), Some(Modal::Account(_))));
+        ui_options.modals.pop();
+        assert!(ui_options.modals.is_empty());
         Ok(())
     }
 }
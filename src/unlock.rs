@@ -0,0 +1,157 @@
+// Declarative unlock conditions. A condition is plain data, so it can be stored, compared and
+// tested on its own, and a small interpreter evaluates it against an agent and the market.
+
+use crate::{
+    agent::{AgentAction, DecisionAgent},
+    market::Market,
+    money::Money,
+    stonk::StonkClass,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum UnlockCondition {
+    // Average stake in the stonks of the class, in percent.
+    StakeInClass { class: StonkClass, percent: f64 },
+    // Stake in the stonk, in percent.
+    StakeInStonk { stonk_id: usize, percent: f64 },
+    CashAtLeast(Money),
+    CashBelow(Money),
+    NetWorthBelow(Money),
+    ReputationAtMost(i32),
+    TreasuryAtLeast(Money),
+    // The agent applied an action of this kind in the past, whatever its payload.
+    PastAction(AgentAction),
+    IsAgent(String),
+    // The agent owns shares of the stonk, and its price went up during the last closed day.
+    StonkGained { stonk_id: usize },
+    // Drawn at every evaluation, so it should come last in an And.
+    Probability(f64),
+    // Evaluated in order, stopping at the first condition deciding the result.
+    And(Vec<UnlockCondition>),
+    Or(Vec<UnlockCondition>),
+    Not(Box<UnlockCondition>),
+}
+
+/// Average stake of the agent in the stonks of the class, in percent.
+pub fn class_stake(agent: &dyn DecisionAgent, market: &Market, class: StonkClass) -> Option<f64> {
+    let stakes = market
+        .stonks
+        .iter()
+        .filter(|stonk| stonk.class == class)
+        .map(|stonk| 100.0 * stonk.to_stake(agent.owned_stonks()[stonk.id]))
+        .collect::<Vec<f64>>();
+    if stakes.is_empty() {
+        return None;
+    }
+    Some(stakes.iter().sum::<f64>() / stakes.len() as f64)
+}
+
+impl UnlockCondition {
+    pub fn evaluate<R: Rng>(
+        &self,
+        agent: &dyn DecisionAgent,
+        market: &Market,
+        rng: &mut R,
+    ) -> bool {
+        match self {
+            Self::StakeInClass { class, percent } => {
+                class_stake(agent, market, *class).is_some_and(|stake| stake >= *percent)
+            }
+            Self::StakeInStonk { stonk_id, percent } => {
+                100.0 * market.stonks[*stonk_id].to_stake(agent.owned_stonks()[*stonk_id])
+                    >= *percent
+            }
+            Self::CashAtLeast(amount) => agent.cash() >= *amount,
+            Self::CashBelow(amount) => agent.cash() < *amount,
+            Self::NetWorthBelow(amount) => market.portfolio_value(agent.username()) < *amount,
+            Self::ReputationAtMost(reputation) => agent.reputation() <= *reputation,
+            Self::TreasuryAtLeast(amount) => market.treasury.balance() >= *amount,
+            Self::PastAction(action) => agent.past_actions().contains(action),
+            Self::IsAgent(username) => agent.username() == username,
+            Self::StonkGained { stonk_id } => {
+                let stonk = &market.stonks[*stonk_id];
+                agent.owned_stonks()[*stonk_id] > 0
+                    && stonk.current_unit_price_cents() > 0
+                    && market
+                        .last_closed_day_start_tick()
+                        .and_then(|tick| stonk.day_return(tick))
+                        .is_some_and(|day_return| day_return > 0.0)
+            }
+            Self::Probability(probability) => rng.gen_bool(probability.clamp(0.0, 1.0)),
+            Self::And(conditions) => conditions
+                .iter()
+                .all(|condition| condition.evaluate(agent, market, rng)),
+            Self::Or(conditions) => conditions
+                .iter()
+                .any(|condition| condition.evaluate(agent, market, rng)),
+            Self::Not(condition) => !condition.evaluate(agent, market, rng),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UnlockCondition;
+    use crate::{
+        agent::{AgentAction, DecisionAgent, UserAgent},
+        market::Market,
+        money::Money,
+        ssh_client::SessionAuth,
+        stonk::StonkClass,
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    #[test]
+    fn test_unlock_conditions() {
+        let market = Market::new();
+        let mut agent = UserAgent::new(SessionAuth::new("agent".to_string(), [0; 32]));
+        let rng = &mut ChaCha8Rng::seed_from_u64(0);
+        let cash = agent.cash();
+
+        assert!(UnlockCondition::CashAtLeast(cash).evaluate(&agent, &market, rng));
+        assert!(!UnlockCondition::CashBelow(cash).evaluate(&agent, &market, rng));
+        assert!(UnlockCondition::IsAgent("agent".to_string()).evaluate(&agent, &market, rng));
+
+        let war_stake = UnlockCondition::StakeInClass {
+            class: StonkClass::War,
+            percent: 1.0,
+        };
+        assert!(!war_stake.evaluate(&agent, &market, rng));
+        for stonk in market.stonks.iter().filter(|s| s.class == StonkClass::War) {
+            agent
+                .add_stonk(stonk.id, stonk.number_of_shares / 50)
+                .expect("Should add shares");
+        }
+        assert!(war_stake.evaluate(&agent, &market, rng));
+
+        let bribe = UnlockCondition::PastAction(AgentAction::AcceptBribe);
+        assert!(!bribe.evaluate(&agent, &market, rng));
+        agent.record_past_action(AgentAction::AcceptBribe, 0);
+        assert!(bribe.evaluate(&agent, &market, rng));
+
+        // Combinators stop at the first deciding condition, so the draw is skipped.
+        let never = UnlockCondition::Probability(0.0);
+        let always = UnlockCondition::Probability(1.0);
+        assert!(
+            UnlockCondition::Or(vec![bribe.clone(), never.clone()]).evaluate(&agent, &market, rng)
+        );
+        assert!(!UnlockCondition::And(vec![always.clone(), never]).evaluate(&agent, &market, rng));
+        assert!(
+            !UnlockCondition::Not(Box::new(UnlockCondition::And(vec![bribe, always])))
+                .evaluate(&agent, &market, rng)
+        );
+
+        // Conditions are plain data.
+        let condition = UnlockCondition::And(vec![
+            UnlockCondition::CashBelow(Money::from_dollars(2_000)),
+            UnlockCondition::Probability(0.5),
+        ]);
+        let json = serde_json::to_string(&condition).expect("Condition should serialize");
+        let loaded: UnlockCondition =
+            serde_json::from_str(&json).expect("Condition should deserialize");
+        assert_eq!(loaded, condition);
+    }
+}
@@ -1,32 +1,46 @@
+use crate::config::ServerConfig;
+use crate::leaderboard::{DailyLeaderboard, LeaderboardHistory};
 use crate::market::{Market, NUMBER_OF_STONKS};
+use crate::scenario::Scenario;
 use crate::ssh_server::AgentsDatabase;
+use crate::statistics::ServerStatistics;
 use crate::stonk::Stonk;
-use crossterm::event::{
-    Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
-};
+use crate::telemetry::BalanceReport;
 use image::imageops::resize;
 use image::io::Reader as ImageReader;
 use image::{Pixel, RgbaImage};
 use include_dir::{include_dir, Dir};
 use ratatui::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::fs::File;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
 use std::io::{Cursor, Read, Write};
-use std::path::PathBuf;
-use tracing::debug;
+use std::path::{Path, PathBuf};
 
 pub type AppResult<T> = Result<T, Box<dyn std::error::Error>>;
 
 static ASSETS_DIR: Dir = include_dir!("$CARGO_MANIFEST_DIR/assets/");
-static AGENTS_STORE_FILENAME: &'static str = "agents.json";
-static MARKET_STORE_FILENAME: &'static str = "market.json";
-
-pub fn read_image(path: &str) -> AppResult<RgbaImage> {
+static AGENTS_STORE_FILENAME: &str = "agents.json";
+static ARCHIVED_AGENTS_STORE_FILENAME: &str = "archived_agents.json";
+static LEADERBOARD_HISTORY_FILENAME: &str = "leaderboard_history.jsonl";
+static BALANCE_REPORTS_FILENAME: &str = "balance_reports.jsonl";
+static MARKET_STORE_FILENAME: &str = "market.json";
+static CONFIG_STORE_FILENAME: &str = "config.json";
+static AUDIT_LOG_FILENAME: &str = "audit.log";
+static STATISTICS_STORE_FILENAME: &str = "statistics.json";
+static RECORDINGS_DIRECTORY: &str = "recordings";
+
+pub fn read_asset(path: &str) -> AppResult<&'static [u8]> {
     let file = ASSETS_DIR.get_file(path);
     if file.is_none() {
         return Err(format!("File {} not found", path).into());
     }
-    let img = ImageReader::new(Cursor::new(file.unwrap().contents()))
+    Ok(file.unwrap().contents())
+}
+
+pub fn read_image(path: &str) -> AppResult<RgbaImage> {
+    let img = ImageReader::new(Cursor::new(read_asset(path)?))
         .with_guessed_format()?
         .decode()?
         .into_rgba8();
@@ -43,12 +57,12 @@ pub fn resize_image(image: &RgbaImage, nwidth: u32, nheight: u32) -> AppResult<R
 }
 
 pub fn img_to_lines<'a>(image: &RgbaImage) -> AppResult<Vec<Line<'a>>> {
-    let mut lines: Vec<Line> = vec![];
     let width = image.width();
     let height = image.height();
+    let mut lines: Vec<Line> = Vec::with_capacity(height.div_ceil(2) as usize);
 
     for y in (0..height - 1).step_by(2) {
-        let mut line: Vec<Span> = vec![];
+        let mut line: Vec<Span> = Vec::with_capacity(width as usize);
 
         for x in 0..width {
             let top_pixel = image.get_pixel(x, y).to_rgba();
@@ -81,7 +95,7 @@ pub fn img_to_lines<'a>(image: &RgbaImage) -> AppResult<Vec<Line<'a>>> {
     }
     // append last line if height is odd
     if height % 2 == 1 {
-        let mut line: Vec<Span> = vec![];
+        let mut line: Vec<Span> = Vec::with_capacity(width as usize);
         for x in 0..width {
             let top_pixel = image.get_pixel(x, height - 1).to_rgba();
             if top_pixel[3] == 0 {
@@ -98,6 +112,70 @@ pub fn img_to_lines<'a>(image: &RgbaImage) -> AppResult<Vec<Line<'a>>> {
     Ok(lines)
 }
 
+/// Encodes the image as a sixel sequence, using the 6x6x6 colors cube as palette.
+/// Transparent pixels are left untouched.
+pub fn img_to_sixel(image: &RgbaImage) -> String {
+    let width = image.width();
+    let height = image.height();
+    let cube_level = |v: u8| (v as u32 * 5 + 127) / 255;
+
+    let mut sixel = format!("\x1bP0;1;0q\"1;1;{};{}", width, height);
+    for idx in 0..216 {
+        let (r, g, b) = (idx / 36, (idx / 6) % 6, idx % 6);
+        sixel.push_str(&format!(
+            "#{};2;{};{};{}",
+            idx,
+            r * 100 / 5,
+            g * 100 / 5,
+            b * 100 / 5
+        ));
+    }
+
+    for band in (0..height).step_by(6) {
+        // For each color used in the band, the bitmask of the pixels of each column.
+        let mut band_colors: BTreeMap<u32, Vec<u8>> = BTreeMap::new();
+        for x in 0..width {
+            for dy in 0..6.min(height - band) {
+                let [r, g, b, a] = image.get_pixel(x, band + dy).to_rgba().0;
+                if a == 0 {
+                    continue;
+                }
+                let idx = 36 * cube_level(r) + 6 * cube_level(g) + cube_level(b);
+                band_colors
+                    .entry(idx)
+                    .or_insert_with(|| vec![0; width as usize])[x as usize] |= 1 << dy;
+            }
+        }
+
+        for (n, (idx, columns)) in band_colors.iter().enumerate() {
+            if n > 0 {
+                // Go back to the start of the band to overlay the next color.
+                sixel.push('$');
+            }
+            sixel.push_str(&format!("#{}", idx));
+
+            let mut x = 0;
+            while x < columns.len() {
+                let run = columns[x..]
+                    .iter()
+                    .take_while(|&&mask| mask == columns[x])
+                    .count();
+                let symbol = (63 + columns[x]) as char;
+                if run > 3 {
+                    sixel.push_str(&format!("!{}{}", run, symbol));
+                } else {
+                    sixel.extend(std::iter::repeat_n(symbol, run));
+                }
+                x += run;
+            }
+        }
+        sixel.push('-');
+    }
+
+    sixel.push_str("\x1b\\");
+    sixel
+}
+
 fn store_path(filename: &str) -> AppResult<PathBuf> {
     let dirs = directories::ProjectDirs::from("org", "frittura", "stonks")
         .ok_or("Failed to get directories")?;
@@ -128,6 +206,11 @@ pub fn save_agents(agents: &AgentsDatabase) -> AppResult<()> {
     Ok(())
 }
 
+pub fn save_archived_agents(agents: &AgentsDatabase) -> AppResult<()> {
+    save_to_json(store_path(ARCHIVED_AGENTS_STORE_FILENAME)?, agents)?;
+    Ok(())
+}
+
 pub fn save_market(market: &Market) -> AppResult<()> {
     save_to_json(store_path(MARKET_STORE_FILENAME)?, market)?;
     Ok(())
@@ -137,174 +220,172 @@ pub fn load_agents() -> AppResult<AgentsDatabase> {
     load_from_json(store_path(AGENTS_STORE_FILENAME)?)
 }
 
-pub fn load_market() -> AppResult<Market> {
-    load_from_json(store_path(MARKET_STORE_FILENAME)?)
+pub fn load_archived_agents() -> AppResult<AgentsDatabase> {
+    let path = store_path(ARCHIVED_AGENTS_STORE_FILENAME)?;
+    // The archive is only created once the first agent is archived.
+    if !path.exists() {
+        return Ok(AgentsDatabase::default());
+    }
+    load_from_json(path)
 }
 
-pub fn load_stonks_data() -> AppResult<[Stonk; NUMBER_OF_STONKS]> {
-    let file = ASSETS_DIR
-        .get_file("data/stonks_data.json")
-        .expect("Failed to get stonks data file");
-    let data = file
-        .contents_utf8()
-        .expect("Failed to read stonks data file");
-    let stonks = serde_json::from_str(&data)?;
-    Ok(stonks)
+pub fn load_market() -> AppResult<Market> {
+    let mut market: Market = load_from_json(store_path(MARKET_STORE_FILENAME)?)?;
+    for (stonk, data) in market.stonks.iter_mut().zip(load_stonks_data()?) {
+        stonk.lore = data.lore;
+    }
+    Ok(market)
 }
 
-pub fn save_keys(signing_key: &ed25519_dalek::SigningKey) -> AppResult<()> {
-    let file = File::create::<&str>("./keys".into())?;
-    assert!(file.metadata()?.is_file());
-    let mut buffer = std::io::BufWriter::new(file);
-    buffer.write(&signing_key.to_bytes())?;
+/// Appends the days to the leaderboard history, one JSON line per day.
+pub fn append_leaderboard_history(days: &[DailyLeaderboard]) -> AppResult<()> {
+    if days.is_empty() {
+        return Ok(());
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(store_path(LEADERBOARD_HISTORY_FILENAME)?)?;
+    for day in days {
+        writeln!(file, "{}", serde_json::to_string(day)?)?;
+    }
     Ok(())
 }
 
-pub fn load_keys() -> AppResult<ed25519_dalek::SigningKey> {
-    let file = File::open::<&str>("./keys".into())?;
-    let mut buffer = std::io::BufReader::new(file);
-    let mut buf: [u8; 32] = [0; 32];
-    buffer.read(&mut buf)?;
-    Ok(ed25519_dalek::SigningKey::from_bytes(&buf))
+/// Appends the game balance reports, one JSON line per day.
+pub fn append_balance_reports(reports: &[BalanceReport]) -> AppResult<()> {
+    if reports.is_empty() {
+        return Ok(());
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(store_path(BALANCE_REPORTS_FILENAME)?)?;
+    for report in reports {
+        writeln!(file, "{}", serde_json::to_string(report)?)?;
+    }
+    Ok(())
 }
 
-fn convert_data_to_key_event(data: &[u8]) -> Option<KeyEvent> {
-    debug!("convert_data_to_key_event: data {:?}", data);
-    let (code, modifiers) = if data.len() == 1 {
-        match data[0] {
-            1 => (KeyCode::Home, KeyModifiers::empty()),
-            2 => (KeyCode::Insert, KeyModifiers::empty()),
-            3 => (KeyCode::Delete, KeyModifiers::empty()),
-            4 => (KeyCode::End, KeyModifiers::empty()),
-            5 => (KeyCode::PageUp, KeyModifiers::empty()),
-            6 => (KeyCode::PageDown, KeyModifiers::empty()),
-            13 => (KeyCode::Enter, KeyModifiers::empty()),
-            // x if x >= 1 && x <= 26 => (
-            //     KeyCode::Char(((x + 86) as char).to_ascii_lowercase()),
-            //     KeyModifiers::CONTROL,
-            // ),
-            27 => (KeyCode::Esc, KeyModifiers::empty()),
-            x if x >= 32 && x <= 64 => (KeyCode::Char(x as char), KeyModifiers::empty()),
-            x if x >= 65 && x <= 90 => (
-                KeyCode::Char((x as char).to_ascii_lowercase()),
-                KeyModifiers::SHIFT,
-            ),
-            x if x >= 97 && x <= 122 => (KeyCode::Char(x as char), KeyModifiers::empty()),
-            127 => (KeyCode::Backspace, KeyModifiers::empty()),
-            _ => return None,
-        }
-    } else if data.len() == 3 {
-        match data[2] {
-            65 => (KeyCode::Up, KeyModifiers::empty()),
-            66 => (KeyCode::Down, KeyModifiers::empty()),
-            67 => (KeyCode::Right, KeyModifiers::empty()),
-            68 => (KeyCode::Left, KeyModifiers::empty()),
-            _ => return None,
-        }
-    } else {
-        return None;
-    };
-
-    let event = KeyEvent::new(code, modifiers);
-    Some(event)
+pub fn clear_leaderboard_history() -> AppResult<()> {
+    let path = store_path(LEADERBOARD_HISTORY_FILENAME)?;
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
 }
 
-fn decode_sgr_mouse_input(ansi_code: Vec<u8>) -> AppResult<(u8, u16, u16)> {
-    // Convert u8 vector to a String
-    let ansi_str = String::from_utf8(ansi_code.clone()).map_err(|_| "Invalid UTF-8 sequence")?;
-
-    // Check the prefix
-    if !ansi_str.starts_with("\x1b[<") {
-        return Err("Invalid SGR ANSI mouse code".into());
+pub fn load_leaderboard_history() -> AppResult<LeaderboardHistory> {
+    let path = store_path(LEADERBOARD_HISTORY_FILENAME)?;
+    if !path.exists() {
+        return Ok(LeaderboardHistory::default());
     }
+    let days = std::fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<DailyLeaderboard>, _>>()?;
+    Ok(LeaderboardHistory::from_saved(days))
+}
 
-    let cb_mod = if ansi_str.ends_with('M') {
-        0
-    } else if ansi_str.ends_with('m') {
-        3
-    } else {
-        return Err("Invalid SGR ANSI mouse code".into());
-    };
+pub fn audit_log_path() -> AppResult<PathBuf> {
+    store_path(AUDIT_LOG_FILENAME)
+}
 
-    // Remove the prefix '\x1b[<' and trailing 'M'
-    let code_body = &ansi_str[3..ansi_str.len() - 1];
+/// Path of the session recording of a player, only the latest one being kept.
+/// Usernames can contain any character, so the file is named after their hash.
+pub fn recording_path(username: &str) -> AppResult<PathBuf> {
+    let directory = store_path(RECORDINGS_DIRECTORY)?;
+    if !directory.exists() {
+        std::fs::create_dir_all(&directory)?;
+    }
+    Ok(directory.join(format!("{:x}.jsonl", Sha256::digest(username.as_bytes()))))
+}
 
-    // Split the components
-    let components: Vec<&str> = code_body.split(';').collect();
+pub fn save_statistics(statistics: &ServerStatistics) -> AppResult<()> {
+    save_to_json(store_path(STATISTICS_STORE_FILENAME)?, statistics)?;
+    Ok(())
+}
 
-    if components.len() != 3 {
-        return Err("Invalid SGR ANSI mouse code format".into());
+pub fn load_statistics() -> AppResult<ServerStatistics> {
+    let path = store_path(STATISTICS_STORE_FILENAME)?;
+    // The statistics are only cached after the first refresh.
+    if !path.exists() {
+        return Ok(ServerStatistics::default());
     }
-
-    // Parse the components
-    let cb = cb_mod
-        + components[0]
-            .parse::<u8>()
-            .map_err(|_| "Failed to parse Cb")?;
-    let cx = components[1]
-        .parse::<u16>()
-        .map_err(|_| "Failed to parse Cx")?;
-    let cy = components[2]
-        .parse::<u16>()
-        .map_err(|_| "Failed to parse Cy")?;
-
-    Ok((cb, cx, cy))
+    load_from_json(path)
 }
 
-fn convert_data_to_mouse_event(data: &[u8]) -> Option<MouseEvent> {
-    let (cb, column, row) = decode_sgr_mouse_input(data.to_vec()).ok()?;
-    let kind = match cb {
-        0 => MouseEventKind::Down(MouseButton::Left),
-        1 => MouseEventKind::Down(MouseButton::Middle),
-        2 => MouseEventKind::Down(MouseButton::Right),
-        3 => MouseEventKind::Up(MouseButton::Left),
-        32 => MouseEventKind::Drag(MouseButton::Left),
-        33 => MouseEventKind::Drag(MouseButton::Middle),
-        34 => MouseEventKind::Drag(MouseButton::Right),
-        35 => MouseEventKind::Moved,
-        64 => MouseEventKind::ScrollUp,
-        65 => MouseEventKind::ScrollDown,
-        96..=255 => {
-            debug!("cb {}", cb);
-            return None;
-        }
-        _ => return None,
-    };
+pub fn load_config() -> AppResult<ServerConfig> {
+    let path = store_path(CONFIG_STORE_FILENAME)?;
+    // Write the default config on first run, so that operators have a file to edit.
+    if !path.exists() {
+        let config = ServerConfig::default();
+        serde_json::to_writer_pretty(File::create(path)?, &config)?;
+        return Ok(config);
+    }
+    load_from_json(path)
+}
 
-    let event = MouseEvent {
-        kind,
-        column,
-        row,
-        modifiers: KeyModifiers::empty(),
-    };
+pub fn load_scenario(path: &Path) -> AppResult<Scenario> {
+    let scenario: Scenario = load_from_json(path.to_path_buf())?;
+    scenario.validate()?;
+    Ok(scenario)
+}
 
-    Some(event)
+pub fn load_stonks_data() -> AppResult<[Stonk; NUMBER_OF_STONKS]> {
+    let file = ASSETS_DIR
+        .get_file("data/stonks_data.json")
+        .expect("Failed to get stonks data file");
+    let data = file
+        .contents_utf8()
+        .expect("Failed to read stonks data file");
+    let stonks = serde_json::from_str(data)?;
+    Ok(stonks)
 }
 
-pub fn convert_data_to_crossterm_event(data: &[u8]) -> Option<Event> {
-    if data.starts_with(&[27, 91, 60]) {
-        if let Some(event) = convert_data_to_mouse_event(data) {
-            return Some(Event::Mouse(event));
-        }
-    } else {
-        if let Some(event) = convert_data_to_key_event(data) {
-            return Some(Event::Key(event));
-        }
-    }
+pub fn save_keys(signing_key: &ed25519_dalek::SigningKey) -> AppResult<()> {
+    let file = File::create::<&str>("./keys")?;
+    assert!(file.metadata()?.is_file());
+    let mut buffer = std::io::BufWriter::new(file);
+    buffer.write_all(&signing_key.to_bytes())?;
+    Ok(())
+}
 
-    None
+pub fn load_keys() -> AppResult<ed25519_dalek::SigningKey> {
+    let file = File::open::<&str>("./keys")?;
+    let mut buffer = std::io::BufReader::new(file);
+    let mut buf: [u8; 32] = [0; 32];
+    buffer.read_exact(&mut buf)?;
+    Ok(ed25519_dalek::SigningKey::from_bytes(&buf))
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{save_agents, AppResult};
+    use super::{img_to_sixel, save_agents, AppResult};
     use crate::{
         agent::{DecisionAgent, UserAgent},
         ssh_client::SessionAuth,
     };
     use directories;
+    use image::{Rgba, RgbaImage};
     use std::{collections::HashMap, fs::File};
 
+    #[test]
+    fn test_img_to_sixel() {
+        let mut image = RgbaImage::new(5, 2);
+        for x in 0..5 {
+            image.put_pixel(x, 0, Rgba([255, 0, 0, 255]));
+        }
+        image.put_pixel(0, 1, Rgba([0, 0, 255, 255]));
+
+        let sixel = img_to_sixel(&image);
+        assert!(sixel.starts_with("\x1bP0;1;0q\"1;1;5;2"));
+        assert!(sixel.ends_with("-\x1b\\"));
+        // Blue pixel in the second row, then the red first row run-length encoded.
+        assert!(sixel.contains("#5A!4?$#180!5@"));
+    }
+
     #[test]
     fn test_path() {
         let dirs = directories::ProjectDirs::from("org", "frittura", "test");
@@ -326,7 +407,7 @@ mod tests {
 
     #[test]
     fn test_save() -> AppResult<()> {
-        let _agents = vec![
+        let _agents = [
             UserAgent::new(SessionAuth::new("username".into(), [0; 32])),
             UserAgent::new(SessionAuth::default()),
         ];